@@ -37,7 +37,7 @@
 //! # }
 //! ```
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io;
 use std::path::Path;
 
@@ -96,6 +96,7 @@ pub enum MetadataError {
 /// targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// no-source = true
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
@@ -135,6 +136,15 @@ pub struct Metadata {
     /// These cannot be a subcommand, they may only be options.
     #[serde(default)]
     cargo_args: Vec<String>,
+
+    /// Whether to leave the source pages rustdoc generates out of the documentation, see
+    /// [`Metadata::no_source`].
+    #[serde(default)]
+    no_source: bool,
+
+    /// Keys docs.rs doesn't know about, see [`Metadata::unknown_keys`].
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>,
 }
 
 /// The targets that should be built for a crate.
@@ -163,6 +173,36 @@ pub struct BuildTargets<'a> {
     pub other_targets: HashSet<&'a str>,
 }
 
+impl<'a> BuildTargets<'a> {
+    /// Remove the targets that can't be built, returning them in alphabetical order.
+    ///
+    /// `is_supported` tells whether the toolchain used for the builds can install a target. If
+    /// the `default_target` is not supported, [`HOST_TARGET`] becomes the default target.
+    ///
+    /// Targets are usually unsupported because they are misspelled, or because they are tier 3
+    /// targets that rustup can't install.
+    pub fn remove_unsupported(&mut self, is_supported: impl Fn(&str) -> bool) -> Vec<&'a str> {
+        let mut unsupported: Vec<_> = self
+            .other_targets
+            .iter()
+            .copied()
+            .filter(|target| !is_supported(target))
+            .collect();
+        for target in &unsupported {
+            self.other_targets.remove(target);
+        }
+
+        if !is_supported(self.default_target) {
+            unsupported.push(self.default_target);
+            self.default_target = HOST_TARGET;
+            self.other_targets.remove(HOST_TARGET);
+        }
+
+        unsupported.sort_unstable();
+        unsupported
+    }
+}
+
 impl Metadata {
     /// Read the `Cargo.toml` from a source directory, then parse the build metadata.
     ///
@@ -271,6 +311,23 @@ impl Metadata {
         cargo_args
     }
 
+    /// Whether the source pages generated by rustdoc (the `src/` directory of the
+    /// documentation) should be removed before the documentation is uploaded.
+    ///
+    /// This is meant for crates with huge amounts of generated code, whose source pages take
+    /// most of the space of the documentation. The sources stay available in the source browser.
+    pub fn no_source(&self) -> bool {
+        self.no_source
+    }
+
+    /// Return the keys of `[package.metadata.docs.rs]` that docs.rs doesn't know about, in
+    /// alphabetical order.
+    ///
+    /// These are ignored when building the crate, they are most likely misspelled.
+    pub fn unknown_keys(&self) -> impl Iterator<Item = &str> {
+        self.unknown.keys().map(String::as_str)
+    }
+
     /// Return the environment variables that should be set when building this crate.
     pub fn environment_variables(&self) -> HashMap<&'static str, String> {
         let mut map = HashMap::new();
@@ -338,6 +395,7 @@ mod test_parsing {
             rustc-args = [ "--example-rustc-arg" ]
             rustdoc-args = [ "--example-rustdoc-arg" ]
             cargo-args = [ "-Zbuild-std" ]
+            no-source = true
         "#;
 
         let metadata = Metadata::from_str(manifest).unwrap();
@@ -374,6 +432,30 @@ mod test_parsing {
 
         let cargo_args = metadata.cargo_args;
         assert_eq!(cargo_args.as_slice(), &["-Zbuild-std"]);
+
+        assert!(metadata.no_source);
+        assert_eq!(metadata.unknown.len(), 0);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            feature = [ "feature1" ]
+            all-features = true
+            rustdoc_args = [ "--cfg", "docsrs" ]
+        "#;
+
+        let metadata = Metadata::from_str(manifest).unwrap();
+        assert!(metadata.all_features);
+        assert!(metadata.features.is_none());
+        assert_eq!(
+            metadata.unknown_keys().collect::<Vec<_>>(),
+            vec!["feature", "rustdoc_args"]
+        );
     }
 
     #[test]
@@ -529,6 +611,33 @@ mod test_targets {
         assert_eq!(others, tier_one_targets_no_default);
     }
 
+    #[test]
+    fn unsupported_targets() {
+        let metadata = Metadata {
+            default_target: Some("wasm32-unknown-unknwon".into()),
+            targets: Some(vec![
+                "x86_64-pc-windows-msvc".into(),
+                "wasm32-wasi".into(),
+                HOST_TARGET.into(),
+            ]),
+            ..Metadata::default()
+        };
+        let mut targets = metadata.targets(true);
+        let unsupported = targets.remove_unsupported(|target| {
+            target != "wasm32-wasi" && target != "wasm32-unknown-unknwon"
+        });
+        assert_eq!(unsupported, vec!["wasm32-unknown-unknwon", "wasm32-wasi"]);
+        assert_eq!(targets.default_target, HOST_TARGET);
+        let expected: HashSet<_> = vec!["x86_64-pc-windows-msvc"].into_iter().collect();
+        assert_eq!(targets.other_targets, expected);
+
+        // nothing changes when all the targets are supported
+        let mut targets = metadata.targets(true);
+        assert!(targets.remove_unsupported(|_| true).is_empty());
+        assert_eq!(targets.default_target, "wasm32-unknown-unknwon");
+        assert_eq!(targets.other_targets.len(), 3);
+    }
+
     #[test]
     fn no_default_targets() {
         // if `targets` is unset, `other_targets` should be empty
@@ -3,12 +3,14 @@ use std::fmt::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use docs_rs::db::{self, add_path_into_database, Pool, PoolClient};
+use docs_rs::db::{self, add_path_into_database, audit::Auditor, Pool, PoolClient};
 use docs_rs::repositories::RepositoryStatsUpdater;
-use docs_rs::utils::{remove_crate_priority, set_crate_priority};
+use docs_rs::utils::advisories::sync_advisories;
+use docs_rs::utils::dataset_export::export_datasets;
+use docs_rs::utils::retention::retire_superseded_prereleases;
 use docs_rs::{
-    BuildQueue, Config, Context, DocBuilder, Index, Metrics, PackageKind, RustwideBuilder, Server,
-    Storage,
+    BuildQueue, Config, Context, DocBuilder, Index, MetadataReport, Metrics, PackageKind,
+    ReleasesCache, RustwideBuilder, Server, Storage, VersionCache,
 };
 use failure::{err_msg, Error, ResultExt};
 use once_cell::sync::OnceCell;
@@ -38,14 +40,17 @@ fn logger_init() {
     let env = env_logger::Env::default().filter_or("DOCSRS_LOG", "docs_rs=info");
     let logger = env_logger::from_env(env)
         .format(|buf, record| {
-            writeln!(
+            write!(
                 buf,
-                "{} [{}] {}: {}",
+                "{} [{}] {}: ",
                 time::now().strftime("%Y/%m/%d %H:%M:%S").unwrap(),
                 record.level(),
                 record.target(),
-                record.args()
-            )
+            )?;
+            if let Some(request_id) = docs_rs::current_request_id() {
+                write!(buf, "[request {}] ", request_id)?;
+            }
+            writeln!(buf, "{}", record.args())
         })
         .build();
 
@@ -71,14 +76,34 @@ enum CommandLine {
 
     /// Starts web server
     StartWebServer {
-        #[structopt(name = "SOCKET_ADDR", default_value = "0.0.0.0:3000")]
-        socket_addr: String,
+        /// Defaults to `DOCSRS_LISTEN_ADDRESS`, or 0.0.0.0:3000 when it's unset
+        #[structopt(name = "SOCKET_ADDR")]
+        socket_addr: Option<String>,
 
-        /// Reload templates when they're changed
+        /// Reload templates when they're changed, needs the `template-reloading` feature
         #[structopt(long = "reload-templates")]
         reload_templates: bool,
     },
 
+    /// Builds a local crate and serves its documentation the way docs.rs would show it
+    ServeLocal {
+        /// The directory of the crate
+        #[structopt(name = "PATH")]
+        path: PathBuf,
+
+        #[structopt(name = "SOCKET_ADDR", default_value = "127.0.0.1:3000")]
+        socket_addr: String,
+    },
+
+    /// Shows how docs.rs interprets the `[package.metadata.docs.rs]` table of a crate, without
+    /// building it
+    CheckMetadata {
+        /// The directory or `Cargo.toml` of a local crate, or `name@version` for a release built
+        /// by docs.rs
+        #[structopt(name = "CRATE")]
+        krate: String,
+    },
+
     /// Starts the daemon
     Daemon {
         /// Deprecated. Run the server in the foreground instead of detaching a child
@@ -94,6 +119,12 @@ enum CommandLine {
         registry_watcher: Toggle,
     },
 
+    /// Checks the configuration read from the environment
+    Config {
+        #[structopt(subcommand)]
+        subcommand: ConfigSubcommand,
+    },
+
     /// Database operations
     Database {
         #[structopt(subcommand)]
@@ -102,9 +133,24 @@ enum CommandLine {
 
     /// Interactions with the build queue
     Queue {
+        /// Why the queue is changed, recorded in the audit log
+        #[structopt(long = "reason")]
+        reason: Option<String>,
+
         #[structopt(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Queues a rebuild of every release built with an older toolchain
+    RebuildAll {
+        /// Releases last built with a nightly older than this rustc version are rebuilt
+        #[structopt(long = "since")]
+        since: String,
+
+        /// Ignore the progress of a previous run with the same `--since` and start over
+        #[structopt(long = "restart")]
+        restart: bool,
+    },
 }
 
 impl CommandLine {
@@ -118,7 +164,18 @@ impl CommandLine {
                 reload_templates,
             } => {
                 // Blocks indefinitely
-                let _ = Server::start(Some(&socket_addr), reload_templates, &ctx)?;
+                let _ = Server::start(socket_addr.as_deref(), reload_templates, &ctx)?;
+            }
+            Self::ServeLocal { path, socket_addr } => {
+                docs_rs::utils::serve_local(Config::from_env()?, &path, &socket_addr)
+                    .context("failed to serve the local crate")?;
+            }
+            Self::CheckMetadata { krate } => {
+                let report = MetadataReport::load(&ctx, &krate)?;
+                print!("{}", report);
+                if !report.unknown_keys().is_empty() {
+                    return Err(err_msg("[package.metadata.docs.rs] contains unknown keys"));
+                }
             }
             Self::Daemon {
                 foreground,
@@ -130,8 +187,23 @@ impl CommandLine {
 
                 docs_rs::utils::start_daemon(&ctx, registry_watcher == Toggle::Enabled)?;
             }
+            Self::Config { subcommand } => subcommand.handle_args()?,
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
-            Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Queue { reason, subcommand } => {
+                let auditor = Auditor::from_config(&*ctx.config()?, reason);
+                subcommand.handle_args(ctx, auditor)?
+            }
+            Self::RebuildAll { since, restart } => {
+                let enqueued = docs_rs::utils::rebuild_all(
+                    &mut *ctx.conn()?,
+                    &*ctx.build_queue()?,
+                    &*ctx.config()?,
+                    &since,
+                    restart,
+                )
+                .context("failed to queue the rebuilds")?;
+                println!("Added {} releases to the build queue", enqueued);
+            }
         }
 
         Ok(())
@@ -166,20 +238,22 @@ enum QueueSubcommand {
 }
 
 impl QueueSubcommand {
-    pub fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+    pub fn handle_args(self, ctx: BinContext, auditor: Auditor) -> Result<(), Error> {
         match self {
             Self::Add {
                 crate_name,
                 crate_version,
                 build_priority,
-            } => ctx.build_queue()?.add_crate(
+            } => auditor.queue_crate(
+                &mut *ctx.conn()?,
+                &*ctx.build_queue()?,
                 &crate_name,
                 &crate_version,
                 build_priority,
                 ctx.config()?.registry_url.as_deref(),
             )?,
 
-            Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx)?,
+            Self::DefaultPriority { subcommand } => subcommand.handle_args(ctx, auditor)?,
         }
         Ok(())
     }
@@ -205,15 +279,17 @@ enum PrioritySubcommand {
 }
 
 impl PrioritySubcommand {
-    pub fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+    pub fn handle_args(self, ctx: BinContext, auditor: Auditor) -> Result<(), Error> {
         match self {
             Self::Set { pattern, priority } => {
-                set_crate_priority(&mut *ctx.conn()?, &pattern, priority)
+                auditor
+                    .set_crate_priority(&mut *ctx.conn()?, &pattern, priority)
                     .context("Could not set pattern's priority")?;
             }
 
             Self::Remove { pattern } => {
-                if let Some(priority) = remove_crate_priority(&mut *ctx.conn()?, &pattern)
+                if let Some(priority) = auditor
+                    .remove_crate_priority(&mut *ctx.conn()?, &pattern)
                     .context("Could not remove pattern's priority")?
                 {
                     println!("Removed pattern with priority {}", priority);
@@ -287,7 +363,12 @@ enum BuildSubcommand {
 
 impl BuildSubcommand {
     pub fn handle_args(self, ctx: BinContext, skip_if_exists: bool) -> Result<(), Error> {
-        let docbuilder = DocBuilder::new(ctx.config()?, ctx.pool()?, ctx.build_queue()?);
+        let docbuilder = DocBuilder::new(
+            ctx.config()?,
+            ctx.pool()?,
+            ctx.build_queue()?,
+            ctx.version_cache()?,
+        );
 
         let rustwide_builder = || -> Result<RustwideBuilder, Error> {
             let mut builder = RustwideBuilder::init(&ctx)?;
@@ -363,6 +444,25 @@ impl BuildSubcommand {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum ConfigSubcommand {
+    /// Loads the configuration and reports the first invalid variable
+    Check,
+}
+
+impl ConfigSubcommand {
+    pub fn handle_args(self) -> Result<(), Error> {
+        match self {
+            Self::Check => {
+                Config::from_env()?;
+                println!("the configuration is valid");
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum DatabaseSubcommand {
     /// Run database migration
@@ -392,16 +492,38 @@ enum DatabaseSubcommand {
 
     /// Remove documentation from the database
     Delete {
+        /// Why the documentation is removed, recorded in the audit log
+        #[structopt(long = "reason")]
+        reason: Option<String>,
+
         #[structopt(subcommand)]
         command: DeleteSubcommand,
     },
 
     /// Blacklist operations
     Blacklist {
+        /// Why the blacklist is changed, recorded in the audit log
+        #[structopt(long = "reason")]
+        reason: Option<String>,
+
         #[structopt(subcommand)]
         command: BlacklistSubcommand,
     },
 
+    /// Archives or deletes the pre-releases superseded by a newer release, following
+    /// `DOCSRS_PRERELEASE_RETENTION`
+    RetirePrereleases {
+        /// Only list the pre-releases that would be retired
+        #[structopt(long)]
+        dry_run: bool,
+    },
+
+    /// Replaces the security advisories with the ones of `DOCSRS_ADVISORY_DB_URL`
+    SyncAdvisories,
+
+    /// Dumps the public tables to `exports/` in the storage, like the daily job
+    ExportDatasets,
+
     /// Compares the database with the index and resolves inconsistencies
     #[cfg(feature = "consistency_check")]
     Synchronize {
@@ -438,19 +560,73 @@ impl DatabaseSubcommand {
             }
 
             Self::AddDirectory { directory } => {
-                add_path_into_database(&*ctx.storage()?, &ctx.config()?.prefix, directory)
+                add_path_into_database(&*ctx.storage()?, &ctx.config()?.prefix, directory, None)
                     .context("Failed to add directory into database")?;
             }
 
             Self::Delete {
+                reason,
                 command: DeleteSubcommand::Version { name, version },
-            } => db::delete_version(&mut *ctx.conn()?, &*ctx.storage()?, &name, &version)
+            } => Auditor::from_config(&*ctx.config()?, reason)
+                .delete_version(&mut *ctx.conn()?, &*ctx.storage()?, &name, &version)
                 .context("failed to delete the crate")?,
             Self::Delete {
+                reason,
                 command: DeleteSubcommand::Crate { name },
-            } => db::delete_crate(&mut *ctx.conn()?, &*ctx.storage()?, &name)
+            } => Auditor::from_config(&*ctx.config()?, reason)
+                .delete_crate(&mut *ctx.conn()?, &*ctx.storage()?, &name)
                 .context("failed to delete the crate")?,
-            Self::Blacklist { command } => command.handle_args(ctx)?,
+            Self::Blacklist { reason, command } => {
+                let auditor = Auditor::from_config(&*ctx.config()?, reason);
+                command.handle_args(ctx, auditor)?
+            }
+
+            Self::RetirePrereleases { dry_run } => {
+                let config = ctx.config()?;
+                let policy = config
+                    .prerelease_retention
+                    .ok_or_else(|| err_msg("DOCSRS_PRERELEASE_RETENTION is not set"))?;
+                let retired = retire_superseded_prereleases(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    policy,
+                    config.prerelease_retention_days,
+                    dry_run,
+                )?;
+                for release in &retired {
+                    println!(
+                        "{} {} (superseded by {})",
+                        release.name, release.version, release.superseded_by
+                    );
+                }
+                if dry_run {
+                    println!(
+                        "{} pre-releases would be retired ({})",
+                        retired.len(),
+                        policy
+                    );
+                } else {
+                    println!("{} pre-releases retired ({})", retired.len(), policy);
+                }
+            }
+
+            Self::SyncAdvisories => {
+                let url = ctx
+                    .config()?
+                    .advisory_db_url
+                    .clone()
+                    .ok_or_else(|| err_msg("DOCSRS_ADVISORY_DB_URL is not set"))?;
+                let synced = sync_advisories(&mut *ctx.conn()?, &url)?;
+                println!("{} advisories synced", synced);
+            }
+
+            Self::ExportDatasets => {
+                let keep = ctx.config()?.dataset_exports_kept;
+                let dump = export_datasets(&mut *ctx.conn()?, &*ctx.storage()?, keep)?;
+                for file in dump.files {
+                    println!("{}: {} rows", file.name, file.rows);
+                }
+            }
 
             #[cfg(feature = "consistency_check")]
             Self::Synchronize { dry_run } => {
@@ -482,7 +658,7 @@ enum BlacklistSubcommand {
 }
 
 impl BlacklistSubcommand {
-    fn handle_args(self, ctx: BinContext) -> Result<(), Error> {
+    fn handle_args(self, ctx: BinContext, auditor: Auditor) -> Result<(), Error> {
         let mut conn = &mut *ctx.conn()?;
         match self {
             Self::List => {
@@ -492,10 +668,12 @@ impl BlacklistSubcommand {
                 println!("{}", crates.join("\n"));
             }
 
-            Self::Add { crate_name } => db::blacklist::add_crate(&mut conn, &crate_name)
+            Self::Add { crate_name } => auditor
+                .blacklist_crate(&mut conn, &crate_name)
                 .context("failed to add crate to blacklist")?,
 
-            Self::Remove { crate_name } => db::blacklist::remove_crate(&mut conn, &crate_name)
+            Self::Remove { crate_name } => auditor
+                .unblacklist_crate(&mut conn, &crate_name)
                 .context("failed to remove crate from blacklist")?,
         }
         Ok(())
@@ -530,6 +708,8 @@ struct BinContext {
     metrics: OnceCell<Arc<Metrics>>,
     index: OnceCell<Arc<Index>>,
     repository_stats_updater: OnceCell<Arc<RepositoryStatsUpdater>>,
+    version_cache: OnceCell<Arc<VersionCache>>,
+    releases_cache: OnceCell<Arc<ReleasesCache>>,
 }
 
 impl BinContext {
@@ -542,6 +722,8 @@ impl BinContext {
             metrics: OnceCell::new(),
             index: OnceCell::new(),
             repository_stats_updater: OnceCell::new(),
+            version_cache: OnceCell::new(),
+            releases_cache: OnceCell::new(),
         }
     }
 
@@ -575,20 +757,20 @@ impl Context for BinContext {
         )?;
         fn config(self) -> Config = Config::from_env()?;
         fn metrics(self) -> Metrics = Metrics::new()?;
-        fn index(self) -> Index = {
-            let config = self.config()?;
-            let path = config.registry_index_path.clone();
-            if let Some(registry_url) = config.registry_url.clone() {
-                Index::from_url(path, registry_url)
-            } else {
-                Index::new(path)
-            }?
-        };
+        fn index(self) -> Index = Index::from_config(&*self.config()?)?;
         fn repository_stats_updater(self) -> RepositoryStatsUpdater = {
             let config = self.config()?;
             let pool = self.pool()?;
             RepositoryStatsUpdater::new(&config, pool)
         };
+        fn version_cache(self) -> VersionCache = VersionCache::new(
+            self.metrics()?,
+            &*self.config()?,
+        );
+        fn releases_cache(self) -> ReleasesCache = ReleasesCache::new(
+            self.metrics()?,
+            &*self.config()?,
+        );
     }
 
     fn pool(&self) -> Result<Pool, Error> {
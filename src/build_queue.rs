@@ -1,7 +1,9 @@
 use crate::db::Pool;
 use crate::error::Result;
+use crate::utils::queue_events::{self, QueueEvent};
 use crate::{Config, Metrics};
 use log::error;
+use postgres::Client;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
@@ -37,10 +39,19 @@ impl BuildQueue {
         priority: i32,
         registry: Option<&str>,
     ) -> Result<()> {
-        self.db.get()?.execute(
+        let mut conn = self.db.get()?;
+        conn.execute(
             "INSERT INTO queue (name, version, priority, registry) VALUES ($1, $2, $3, $4);",
             &[&name, &version, &priority, &registry],
         )?;
+        publish(
+            &mut conn,
+            &QueueEvent::Queued {
+                name: name.into(),
+                version: version.into(),
+                priority,
+            },
+        );
         Ok(())
     }
 
@@ -101,11 +112,19 @@ impl BuildQueue {
             None => return Ok(()),
         };
 
+        publish(
+            &mut conn,
+            &QueueEvent::Started {
+                name: to_process.name.clone(),
+                version: to_process.version.clone(),
+            },
+        );
         let res = f(to_process);
         self.metrics.total_builds.inc();
-        match res {
+        let requeued = match res {
             Ok(()) => {
                 conn.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
+                false
             }
             Err(e) => {
                 // Increase attempt count
@@ -126,13 +145,29 @@ impl BuildQueue {
                     e,
                     e.backtrace()
                 );
+                attempt < self.max_attempts
             }
-        }
+        };
+        publish(
+            &mut conn,
+            &QueueEvent::Finished {
+                name: to_process.name.clone(),
+                version: to_process.version.clone(),
+                requeued,
+            },
+        );
 
         Ok(())
     }
 }
 
+/// The live updates are only informative, failing to send them doesn't affect the queue
+fn publish(conn: &mut Client, event: &QueueEvent) {
+    if let Err(err) = queue_events::publish(conn, event) {
+        error!("failed to publish the queue event {:?}: {}", event, err);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,410 @@
+//! Invalidation of the files cached by the CDN
+//!
+//! When the files of a release change in the storage (because the release was rebuilt or
+//! deleted), the copies cached by the CDN have to be invalidated, otherwise visitors keep seeing
+//! the old documentation until the cache expires.
+//!
+//! CloudFront invalidates paths (wildcards are allowed), while Fastly purges surrogate keys: the
+//! web server tags the responses of every crate with a `crate-<name>` key when Fastly is used.
+
+use crate::Config;
+use failure::{bail, format_err, Error};
+use iron::{headers::Headers, AfterMiddleware, IronError, IronResult, Request, Response};
+use rusoto_core::{signature::SignedRequest, HttpClient, Region, RusotoError};
+use rusoto_credential::DefaultCredentialsProvider;
+use std::convert::Infallible;
+use tokio::runtime::Runtime;
+
+/// The header containing the surrogate keys of a response, used by Fastly
+pub(crate) const SURROGATE_KEY_HEADER: &str = "Surrogate-Key";
+
+const CLOUDFRONT_API_VERSION: &str = "2020-05-31";
+#[cfg_attr(test, allow(dead_code))]
+const FASTLY_API_URL: &str = "https://api.fastly.com";
+
+#[derive(Debug, failure::Fail)]
+#[fail(display = "invalid CDN backend")]
+pub(crate) struct InvalidCdnBackendError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CdnKind {
+    Noop,
+    CloudFront,
+    Fastly,
+}
+
+impl std::str::FromStr for CdnKind {
+    type Err = InvalidCdnBackendError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "noop" => Ok(CdnKind::Noop),
+            "cloudfront" => Ok(CdnKind::CloudFront),
+            "fastly" => Ok(CdnKind::Fastly),
+            _ => Err(InvalidCdnBackendError),
+        }
+    }
+}
+
+/// The releases affected by a change of the files under a storage prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Invalidation {
+    krate: String,
+    /// `None` when all the releases of the crate changed
+    version: Option<String>,
+}
+
+impl Invalidation {
+    /// Only the documentation and the sources are served from the CDN, other prefixes (like the
+    /// build logs) don't need to be invalidated.
+    fn from_storage_prefix(prefix: &str) -> Option<Self> {
+        let mut segments = prefix.trim_matches('/').split('/');
+        match segments.next()? {
+            "rustdoc" | "sources" => {}
+            _ => return None,
+        }
+
+        let krate = segments.next().filter(|krate| !krate.is_empty())?;
+        Some(Self {
+            krate: krate.to_owned(),
+            version: segments
+                .next()
+                .filter(|version| !version.is_empty())
+                .map(String::from),
+        })
+    }
+
+    fn paths(&self) -> Vec<String> {
+        let krate = &self.krate;
+        match &self.version {
+            // `latest` might point to another version now
+            Some(version) => vec![
+                format!("/{}/{}/*", krate, version),
+                format!("/{}/latest/*", krate),
+                format!("/crate/{}/*", krate),
+            ],
+            None => vec![format!("/{}/*", krate), format!("/crate/{}/*", krate)],
+        }
+    }
+
+    fn surrogate_key(&self) -> String {
+        surrogate_key(&self.krate)
+    }
+}
+
+/// The surrogate key of all the pages of a crate
+pub(crate) fn surrogate_key(krate: &str) -> String {
+    format!("crate-{}", krate)
+}
+
+/// Tags the responses with the surrogate key of the crate they belong to, including the error
+/// pages so that a 404 cached before a release was built is purged once it's uploaded.
+pub(crate) struct SurrogateKeys;
+
+impl SurrogateKeys {
+    fn tag(req: &Request, headers: &mut Headers) {
+        let params = match req.extensions.get::<router::Router>() {
+            Some(params) => params,
+            None => return,
+        };
+        // rustdoc pages use `:crate`, while the other crate pages use `:name`
+        if let Some(krate) = params.find("crate").or_else(|| params.find("name")) {
+            headers.set_raw(
+                SURROGATE_KEY_HEADER,
+                vec![surrogate_key(krate).into_bytes()],
+            );
+        }
+    }
+}
+
+impl AfterMiddleware for SurrogateKeys {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        Self::tag(req, &mut res.headers);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        Self::tag(req, &mut err.response.headers);
+        Err(err)
+    }
+}
+
+pub(crate) enum CdnBackend {
+    Noop,
+    CloudFront(Box<CloudFront>),
+    Fastly(Fastly),
+}
+
+impl CdnBackend {
+    pub(crate) fn new(config: &Config) -> Result<Self, Error> {
+        Ok(match config.cdn_backend {
+            CdnKind::Noop => CdnBackend::Noop,
+            CdnKind::CloudFront => CdnBackend::CloudFront(Box::new(CloudFront {
+                runtime: Runtime::new()?,
+                client: rusoto_core::Client::new_with(
+                    DefaultCredentialsProvider::new()?,
+                    HttpClient::new()?,
+                ),
+                distribution_id: config
+                    .cloudfront_distribution_id
+                    .clone()
+                    .ok_or_else(|| format_err!("missing the CloudFront distribution id"))?,
+            })),
+            CdnKind::Fastly => CdnBackend::Fastly(Fastly {
+                client: reqwest::blocking::Client::new(),
+                #[cfg(not(test))]
+                api_url: FASTLY_API_URL.into(),
+                // never purge a real service from the test suite
+                #[cfg(test)]
+                api_url: mockito::server_url(),
+                service_id: config
+                    .fastly_service_id
+                    .clone()
+                    .ok_or_else(|| format_err!("missing the Fastly service id"))?,
+                api_token: config
+                    .fastly_api_token
+                    .clone()
+                    .ok_or_else(|| format_err!("missing the Fastly API token"))?,
+            }),
+        })
+    }
+
+    /// Invalidates the cached pages of the releases whose files under `prefix` changed
+    pub(crate) fn invalidate_storage_prefix(&self, prefix: &str) -> Result<(), Error> {
+        let invalidation = match Invalidation::from_storage_prefix(prefix) {
+            Some(invalidation) => invalidation,
+            None => return Ok(()),
+        };
+
+        match self {
+            CdnBackend::Noop => Ok(()),
+            CdnBackend::CloudFront(cloudfront) => cloudfront.invalidate(&invalidation.paths()),
+            CdnBackend::Fastly(fastly) => fastly.purge(&invalidation.surrogate_key()),
+        }
+    }
+}
+
+pub(crate) struct CloudFront {
+    runtime: Runtime,
+    client: rusoto_core::Client,
+    distribution_id: String,
+}
+
+impl CloudFront {
+    fn invalidate(&self, paths: &[String]) -> Result<(), Error> {
+        let mut request = SignedRequest::new(
+            "POST",
+            "cloudfront",
+            &Region::UsEast1,
+            &format!(
+                "/{}/distribution/{}/invalidation",
+                CLOUDFRONT_API_VERSION, self.distribution_id
+            ),
+        );
+        request.set_content_type("application/xml".into());
+        request.set_payload(Some(invalidation_batch(paths, &caller_reference())));
+
+        self.runtime.block_on(async {
+            let mut response = self
+                .client
+                .sign_and_dispatch(request)
+                .await
+                .map_err(RusotoError::<Infallible>::from)?;
+            if !response.status.is_success() {
+                let response = response.buffer().await?;
+                bail!(
+                    "CloudFront refused to create the invalidation: {} {}",
+                    response.status,
+                    String::from_utf8_lossy(&response.body)
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+pub(crate) struct Fastly {
+    client: reqwest::blocking::Client,
+    api_url: String,
+    service_id: String,
+    api_token: String,
+}
+
+impl Fastly {
+    fn purge(&self, surrogate_key: &str) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/service/{}/purge/{}",
+                self.api_url, self.service_id, surrogate_key
+            ))
+            .header("Fastly-Key", self.api_token.as_str())
+            .header("Accept", "application/json")
+            .send()?;
+        if !response.status().is_success() {
+            bail!(
+                "Fastly refused to purge {}: {}",
+                surrogate_key,
+                response.status()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Every invalidation needs a unique reference, used by CloudFront to detect duplicate requests
+fn caller_reference() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("failed to generate a caller reference");
+    let random: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("docs.rs-{}", random)
+}
+
+fn invalidation_batch(paths: &[String], caller_reference: &str) -> String {
+    let items: String = paths
+        .iter()
+        .map(|path| format!("<Path>{}</Path>", escape_xml(path)))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <InvalidationBatch xmlns=\"http://cloudfront.amazonaws.com/doc/{}/\">\
+         <Paths><Quantity>{}</Quantity><Items>{}</Items></Paths>\
+         <CallerReference>{}</CallerReference>\
+         </InvalidationBatch>",
+        CLOUDFRONT_API_VERSION,
+        paths.len(),
+        items,
+        escape_xml(caller_reference)
+    )
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_prefixes() {
+        let invalidation = |krate: &str, version: Option<&str>| {
+            Some(Invalidation {
+                krate: krate.into(),
+                version: version.map(String::from),
+            })
+        };
+
+        assert_eq!(
+            Invalidation::from_storage_prefix("rustdoc/foo/1.0.0/"),
+            invalidation("foo", Some("1.0.0"))
+        );
+        assert_eq!(
+            Invalidation::from_storage_prefix("sources/foo/1.0.0"),
+            invalidation("foo", Some("1.0.0"))
+        );
+        assert_eq!(
+            Invalidation::from_storage_prefix("rustdoc/foo/"),
+            invalidation("foo", None)
+        );
+        assert_eq!(Invalidation::from_storage_prefix("rustdoc/"), None);
+        assert_eq!(Invalidation::from_storage_prefix("build-logs/42/"), None);
+        assert_eq!(Invalidation::from_storage_prefix(""), None);
+    }
+
+    #[test]
+    fn cloudfront_paths() {
+        let invalidation = Invalidation::from_storage_prefix("rustdoc/foo/1.0.0").unwrap();
+        assert_eq!(
+            invalidation.paths(),
+            vec!["/foo/1.0.0/*", "/foo/latest/*", "/crate/foo/*"]
+        );
+        assert_eq!(
+            invalidation_batch(&invalidation.paths()[..1], "ref"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <InvalidationBatch xmlns=\"http://cloudfront.amazonaws.com/doc/2020-05-31/\">\
+             <Paths><Quantity>1</Quantity><Items><Path>/foo/1.0.0/*</Path></Items></Paths>\
+             <CallerReference>ref</CallerReference>\
+             </InvalidationBatch>"
+        );
+
+        let invalidation = Invalidation::from_storage_prefix("rustdoc/foo/").unwrap();
+        assert_eq!(invalidation.paths(), vec!["/foo/*", "/crate/foo/*"]);
+    }
+
+    #[test]
+    fn fastly_purge() -> Result<(), Error> {
+        let purge = mockito::mock("POST", "/service/some-service/purge/crate-foo")
+            .match_header("Fastly-Key", "secret")
+            .with_status(200)
+            .create();
+        let unknown_service = mockito::mock("POST", "/service/unknown/purge/crate-foo")
+            .with_status(401)
+            .create();
+
+        let backend = |service_id: &str| {
+            CdnBackend::Fastly(Fastly {
+                client: reqwest::blocking::Client::new(),
+                api_url: mockito::server_url(),
+                service_id: service_id.into(),
+                api_token: "secret".into(),
+            })
+        };
+
+        backend("some-service").invalidate_storage_prefix("rustdoc/foo/1.0.0/")?;
+        // nothing to purge for the build logs
+        backend("some-service").invalidate_storage_prefix("build-logs/1/")?;
+        assert!(backend("unknown")
+            .invalidate_storage_prefix("sources/foo/1.0.0/")
+            .is_err());
+
+        purge.assert();
+        unknown_service.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn fastly_surrogate_keys() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.cdn_backend = CdnKind::Fastly;
+                config.fastly_service_id = Some("docs".into());
+                config.fastly_api_token = Some("secret".into());
+            });
+            let purge = mockito::mock("POST", "/service/docs/purge/crate-dummy").create();
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            purge.assert();
+
+            let web = env.frontend();
+            for path in &[
+                "/dummy/0.1.0/dummy/",
+                "/crate/dummy/0.1.0",
+                "/crate/dummy/0.2.0",
+            ] {
+                let resp = web.get(path).send()?;
+                assert_eq!(
+                    resp.headers()[SURROGATE_KEY_HEADER],
+                    "crate-dummy",
+                    "{}",
+                    path
+                );
+            }
+            assert!(web
+                .get("/releases")
+                .send()?
+                .headers()
+                .get(SURROGATE_KEY_HEADER)
+                .is_none());
+
+            Ok(())
+        });
+    }
+}
@@ -1,31 +1,102 @@
+use crate::cdn::CdnKind;
 use crate::storage::StorageKind;
-use failure::{bail, format_err, Error, Fail, ResultExt};
+use crate::utils::retention::RetentionPolicy;
+use crate::web::{
+    admin_api::AdminTokens,
+    admin_login::{AdminScopes, GitHubTeam},
+};
+use failure::Fail;
 use rusoto_core::Region;
 use std::env::VarError;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use url::Url;
+
+/// Why the configuration couldn't be loaded from the environment
+#[derive(Debug, PartialEq, Eq, Fail)]
+pub enum ConfigError {
+    #[fail(display = "configuration variable {} is missing", _0)]
+    Missing(&'static str),
+
+    #[fail(display = "failed to parse configuration variable {}: {}", var, reason)]
+    Invalid { var: &'static str, reason: String },
+
+    #[fail(display = "configuration variable {} is not UTF-8", _0)]
+    NotUnicode(&'static str),
+
+    #[fail(
+        display = "env variable {} is no longer accepted; use {} instead",
+        old, new
+    )]
+    Renamed {
+        old: &'static str,
+        new: &'static str,
+    },
+
+    /// The variable is valid on its own, but not with the rest of the configuration
+    #[fail(display = "invalid configuration variable {}: {}", var, reason)]
+    Inconsistent {
+        var: &'static str,
+        reason: &'static str,
+    },
+}
 
 #[derive(Debug)]
 pub struct Config {
     pub prefix: PathBuf,
     pub registry_index_path: PathBuf,
     pub registry_url: Option<String>,
+    /// Watch the registry through its sparse HTTP index instead of pulling its git repository
+    pub registry_sparse_index_url: Option<Url>,
+
+    // Address the web server listens on, unless another one is passed on the command line
+    pub(crate) listen_address: SocketAddr,
+    // PEM certificate chain and private key, the web server terminates TLS and speaks HTTP/2 when
+    // both are set
+    pub(crate) tls_certificate: Option<PathBuf>,
+    pub(crate) tls_private_key: Option<PathBuf>,
 
     // Database connection params
     pub(crate) database_url: String,
+    // Read replica of the database, used by the pages that only read from it
+    pub(crate) database_replica_url: Option<String>,
     pub(crate) max_pool_size: u32,
     pub(crate) min_pool_idle: u32,
+    // Connections held longer than this many seconds are logged with the place they were
+    // acquired at, to find the code exhausting the pool
+    pub(crate) db_connection_hold_deadline: u64,
 
     // Storage params
     pub(crate) storage_backend: StorageKind,
+    // Total size of the files kept in memory by the storage, in bytes. Disabled when unset.
+    pub(crate) storage_cache_size: Option<usize>,
+    // Files larger than this are never kept in memory
+    pub(crate) storage_cache_max_file_size: usize,
+    // How long the files stay in memory, in seconds
+    pub(crate) storage_cache_ttl: u64,
 
     // S3 params
     pub(crate) s3_bucket: String,
     pub(crate) s3_region: Region,
     pub(crate) s3_endpoint: Option<String>,
+    // Bucket read when the primary one is unavailable, usually replicated to another region
+    pub(crate) s3_replica_bucket: Option<String>,
+    pub(crate) s3_replica_region: Option<Region>,
+    // How long to wait for the primary bucket before reading from the replica, in seconds
+    pub(crate) s3_replica_timeout: u64,
+    // Local directory where uploads are kept when the storage is unreachable, the web server
+    // serves them from there until they are uploaded
+    pub(crate) upload_spill_dir: Option<PathBuf>,
     #[cfg(test)]
     pub(crate) s3_bucket_is_temporary: bool,
 
+    // CDN invalidation params
+    pub(crate) cdn_backend: CdnKind,
+    pub(crate) cloudfront_distribution_id: Option<String>,
+    pub(crate) fastly_service_id: Option<String>,
+    pub(crate) fastly_api_token: Option<String>,
+
     // Github authentication
     pub(crate) github_accesstoken: Option<String>,
     pub(crate) github_updater_min_rate_limit: u32,
@@ -33,11 +104,19 @@ pub struct Config {
     // Gitlab authentication
     pub(crate) gitlab_accesstoken: Option<String>,
 
+    // sr.ht authentication, its API can't be used without a token
+    pub(crate) sourcehut_accesstoken: Option<String>,
+
     // Max size of the files served by the docs.rs frontend
     pub(crate) max_file_size: usize,
     pub(crate) max_file_size_html: usize,
     // The most memory that can be used to parse an HTML file
     pub(crate) max_parse_memory: usize,
+    // Binary files larger than this are redirected to a temporary S3 url instead of being served
+    // by the docs.rs frontend, they are uploaded uncompressed for browsers to read them
+    pub(crate) presigned_url_threshold: Option<usize>,
+    // How long the temporary S3 urls stay valid, in seconds
+    pub(crate) presigned_url_expiration: u64,
     // Time between 'git gc --auto' calls in seconds
     pub(crate) registry_gc_interval: u64,
 
@@ -50,9 +129,74 @@ pub struct Config {
     // For unit-tests the number has to be higher.
     pub(crate) random_crate_search_view_size: u32,
 
+    // Weights of the signals ranking the search results: how close the crate name is to the
+    // query, the downloads of the release and how recent the release is
+    pub(crate) search_name_weight: f64,
+    pub(crate) search_downloads_weight: f64,
+    pub(crate) search_recency_weight: f64,
+
     // Content Security Policy
     pub(crate) csp_report_only: bool,
 
+    // Secret signing the cookie storing the settings of the visitors, a random one is generated
+    // at startup when missing
+    pub(crate) cookie_secret: Option<String>,
+
+    // Add X-DocsRs-* headers describing the release to rustdoc pages
+    pub(crate) rustdoc_metadata_headers: bool,
+
+    // Ask search engines not to index the documentation of yanked and outdated releases
+    pub(crate) noindex_outdated_releases: bool,
+
+    // Log a JSON line for every request served by the web server
+    pub(crate) structured_request_logs: bool,
+
+    // Count the views of the documentation of every release by day, the counts are written to the
+    // database every `page_view_flush_interval` seconds by a background thread
+    pub(crate) page_view_stats: bool,
+    pub(crate) page_view_flush_interval: u64,
+
+    // Bearer tokens accepted by the admin API, with the operations they can perform
+    pub(crate) admin_api_tokens: AdminTokens,
+    // The system user running the command line, the administrative operations performed with it
+    // are attributed to this user in the audit log
+    pub(crate) audit_actor: String,
+
+    // GitHub OAuth application letting the members of a team (`<org>/<team>`) log in to perform
+    // the admin operations instead of using a token, disabled unless all three are set. The
+    // sessions last `admin_session_duration` seconds and allow the `github_admin_scopes`.
+    pub(crate) github_oauth_client_id: Option<String>,
+    pub(crate) github_oauth_client_secret: Option<String>,
+    pub(crate) github_admin_team: Option<GitHubTeam>,
+    pub(crate) github_admin_scopes: AdminScopes,
+    pub(crate) admin_session_duration: u64,
+
+    // TOML file replacing the default navbar and footer links
+    pub(crate) site_links: Option<PathBuf>,
+
+    // How long the crate and version lookups of the web server are cached, in seconds
+    pub(crate) version_cache_ttl: u64,
+
+    // How long the first pages of the releases lists are cached, in seconds
+    pub(crate) releases_cache_ttl: u64,
+
+    // How long the pages that are expensive to render are cached, in seconds
+    pub(crate) render_cache_ttl: u64,
+
+    // Number of releases whose files are checked every hour, 0 disables the check
+    pub(crate) consistency_check_sample_size: u32,
+
+    // Where the build events are sent when a build completes, they are signed with the secret
+    // in the `X-DocsRs-Signature` header when one is set
+    pub(crate) build_events_webhook: Option<Url>,
+    pub(crate) build_events_secret: Option<String>,
+    // The address of this docs.rs instance, used in the links of the build events
+    pub(crate) public_url: Url,
+
+    // How often the log of the running build is copied to the database to be shown before the
+    // build is over, in seconds, 0 disables it
+    pub(crate) build_progress_interval: u64,
+
     // Build params
     pub(crate) build_attempts: u16,
     pub(crate) rustwide_workspace: PathBuf,
@@ -62,10 +206,51 @@ pub struct Config {
     pub(crate) build_cpu_limit: Option<u32>,
     pub(crate) include_default_targets: bool,
     pub(crate) disable_memory_limit: bool,
+    // Pause the build queue when less than this many bytes are free in the rustwide workspace
+    pub(crate) build_min_free_disk_space: Option<u64>,
+    // Document the default target twice to check that the output is the same
+    pub(crate) verify_reproducible_builds: bool,
+    // Run rustdoc a second time with `--output-format json` for every documented target, and
+    // publish the output next to the documentation. The private items of the default target are
+    // also documented this way, to link the source browser to the definitions and to resolve the
+    // paths of the items.
+    pub(crate) rustdoc_json: bool,
+
+    // Bulk rebuild params
+    pub(crate) rebuild_batch_size: u32,
+    // Time between two batches of rebuilds in seconds
+    pub(crate) rebuild_batch_interval: u64,
+    // How many of the most downloaded crates are rebuilt when a new rustdoc is deployed, 0 to
+    // disable the automatic rebuilds
+    pub(crate) toolchain_rebuild_crates: u32,
+
+    // What happens to the documentation of the pre-releases superseded by a newer release of the
+    // same version for more than `prerelease_retention_days` days, nothing when unset
+    pub prerelease_retention: Option<RetentionPolicy>,
+    pub prerelease_retention_days: u32,
+
+    // Gzipped tarball of the RustSec advisory database synced every hour, like
+    // https://github.com/rustsec/advisory-db/archive/refs/heads/main.tar.gz, no sync when unset
+    pub advisory_db_url: Option<String>,
+
+    // Dump the public tables every day to `exports/` in the storage, keeping the most recent
+    // `dataset_exports_kept` dumps
+    pub dataset_exports: bool,
+    pub dataset_exports_kept: usize,
+
+    // GraphQL API params
+    #[cfg(feature = "graphql")]
+    pub(crate) graphql_max_depth: usize,
+    // Maximum number of fields a single query can select
+    #[cfg(feature = "graphql")]
+    pub(crate) graphql_max_complexity: usize,
+    // Directory of `<id>.graphql` files; when set only those queries can be executed
+    #[cfg(feature = "graphql")]
+    pub(crate) graphql_persisted_queries: Option<PathBuf>,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, Error> {
+    pub fn from_env() -> Result<Self, ConfigError> {
         let old_vars = [
             ("CRATESFYI_PREFIX", "DOCSRS_PREFIX"),
             ("CRATESFYI_DATABASE_URL", "DOCSRS_DATABASE_URL"),
@@ -77,55 +262,122 @@ impl Config {
             ("DOCS_RS_BULID_CPU_LIMIT", "DOCSRS_BULID_CPU_LIMIT"),
         ];
         for (old_var, new_var) in old_vars {
-            if std::env::var(old_var).is_ok() {
-                failure::bail!(
-                    "env variable {} is no longer accepted; use {} instead",
-                    old_var,
-                    new_var
-                );
+            if std::env::var_os(old_var).is_some() {
+                return Err(ConfigError::Renamed {
+                    old: old_var,
+                    new: new_var,
+                });
             }
         }
 
         let prefix: PathBuf = require_env("DOCSRS_PREFIX")?;
 
-        Ok(Self {
+        let config = Self {
             build_attempts: env("DOCSRS_BUILD_ATTEMPTS", 5)?,
 
             registry_index_path: env("REGISTRY_INDEX_PATH", prefix.join("crates.io-index"))?,
             registry_url: maybe_env("REGISTRY_URL")?,
+            registry_sparse_index_url: maybe_env("DOCSRS_REGISTRY_SPARSE_INDEX_URL")?,
             prefix,
 
+            listen_address: env("DOCSRS_LISTEN_ADDRESS", ([0, 0, 0, 0], 3000).into())?,
+            tls_certificate: maybe_env("DOCSRS_TLS_CERTIFICATE")?,
+            tls_private_key: maybe_env("DOCSRS_TLS_PRIVATE_KEY")?,
+
             database_url: require_env("DOCSRS_DATABASE_URL")?,
+            database_replica_url: maybe_env("DOCSRS_DATABASE_REPLICA_URL")?,
             max_pool_size: env("DOCSRS_MAX_POOL_SIZE", 90)?,
             min_pool_idle: env("DOCSRS_MIN_POOL_IDLE", 10)?,
+            db_connection_hold_deadline: env("DOCSRS_DB_CONNECTION_HOLD_DEADLINE", 5 * 60)?,
 
             storage_backend: env("DOCSRS_STORAGE_BACKEND", StorageKind::Database)?,
+            storage_cache_size: maybe_env("DOCSRS_STORAGE_CACHE_SIZE")?,
+            storage_cache_max_file_size: env("DOCSRS_STORAGE_CACHE_MAX_FILE_SIZE", 256 * 1024)?,
+            storage_cache_ttl: env("DOCSRS_STORAGE_CACHE_TTL", 5 * 60)?,
 
             s3_bucket: env("DOCSRS_S3_BUCKET", "rust-docs-rs".to_string())?,
             s3_region: env("S3_REGION", Region::UsWest1)?,
             s3_endpoint: maybe_env("S3_ENDPOINT")?,
+            s3_replica_bucket: maybe_env("DOCSRS_S3_REPLICA_BUCKET")?,
+            s3_replica_region: maybe_env("DOCSRS_S3_REPLICA_REGION")?,
+            s3_replica_timeout: env("DOCSRS_S3_REPLICA_TIMEOUT", 5)?,
+            upload_spill_dir: maybe_env("DOCSRS_UPLOAD_SPILL_DIR")?,
             // DO NOT CONFIGURE THIS THROUGH AN ENVIRONMENT VARIABLE!
             // Accidentally turning this on outside of the test suite might cause data loss in the
             // production environment.
             #[cfg(test)]
             s3_bucket_is_temporary: false,
 
+            cdn_backend: env("DOCSRS_CDN_BACKEND", CdnKind::Noop)?,
+            cloudfront_distribution_id: maybe_env("DOCSRS_CLOUDFRONT_DISTRIBUTION_ID")?,
+            fastly_service_id: maybe_env("DOCSRS_FASTLY_SERVICE_ID")?,
+            fastly_api_token: maybe_env("DOCSRS_FASTLY_API_TOKEN")?,
+
             github_accesstoken: maybe_env("DOCSRS_GITHUB_ACCESSTOKEN")?,
             github_updater_min_rate_limit: env("DOCSRS_GITHUB_UPDATER_MIN_RATE_LIMIT", 2500)?,
 
             gitlab_accesstoken: maybe_env("DOCSRS_GITLAB_ACCESSTOKEN")?,
 
+            sourcehut_accesstoken: maybe_env("DOCSRS_SOURCEHUT_ACCESSTOKEN")?,
+
             max_file_size: env("DOCSRS_MAX_FILE_SIZE", 50 * 1024 * 1024)?,
             max_file_size_html: env("DOCSRS_MAX_FILE_SIZE_HTML", 50 * 1024 * 1024)?,
             // LOL HTML only uses as much memory as the size of the start tag!
             // https://github.com/rust-lang/docs.rs/pull/930#issuecomment-667729380
             max_parse_memory: env("DOCSRS_MAX_PARSE_MEMORY", 5 * 1024 * 1024)?,
+            presigned_url_threshold: maybe_env("DOCSRS_PRESIGNED_URL_THRESHOLD")?,
+            presigned_url_expiration: env("DOCSRS_PRESIGNED_URL_EXPIRATION", 10 * 60)?,
             registry_gc_interval: env("DOCSRS_REGISTRY_GC_INTERVAL", 60 * 60)?,
 
             random_crate_search_view_size: env("DOCSRS_RANDOM_CRATE_SEARCH_VIEW_SIZE", 500)?,
+            search_name_weight: env("DOCSRS_SEARCH_NAME_WEIGHT", 1.0)?,
+            search_downloads_weight: env("DOCSRS_SEARCH_DOWNLOADS_WEIGHT", 0.3)?,
+            search_recency_weight: env("DOCSRS_SEARCH_RECENCY_WEIGHT", 0.1)?,
 
             csp_report_only: env("DOCSRS_CSP_REPORT_ONLY", false)?,
 
+            cookie_secret: maybe_env("DOCSRS_COOKIE_SECRET")?,
+
+            rustdoc_metadata_headers: env("DOCSRS_RUSTDOC_METADATA_HEADERS", true)?,
+
+            noindex_outdated_releases: env("DOCSRS_NOINDEX_OUTDATED_RELEASES", true)?,
+
+            structured_request_logs: env("DOCSRS_STRUCTURED_REQUEST_LOGS", false)?,
+
+            page_view_stats: env("DOCSRS_PAGE_VIEW_STATS", false)?,
+            page_view_flush_interval: env("DOCSRS_PAGE_VIEW_FLUSH_INTERVAL", 60)?,
+
+            admin_api_tokens: env("DOCSRS_ADMIN_API_TOKENS", AdminTokens::default())?,
+            audit_actor: match maybe_env("USER")? {
+                Some(user) => user,
+                None => env("LOGNAME", "unknown".to_string())?,
+            },
+
+            github_oauth_client_id: maybe_env("DOCSRS_GITHUB_OAUTH_CLIENT_ID")?,
+            github_oauth_client_secret: maybe_env("DOCSRS_GITHUB_OAUTH_CLIENT_SECRET")?,
+            github_admin_team: maybe_env("DOCSRS_GITHUB_ADMIN_TEAM")?,
+            github_admin_scopes: env("DOCSRS_GITHUB_ADMIN_SCOPES", AdminScopes::default())?,
+            admin_session_duration: env("DOCSRS_ADMIN_SESSION_DURATION", 8 * 60 * 60)?,
+
+            site_links: maybe_env("DOCSRS_SITE_LINKS")?,
+
+            version_cache_ttl: env("DOCSRS_VERSION_CACHE_TTL", 30)?,
+
+            releases_cache_ttl: env("DOCSRS_RELEASES_CACHE_TTL", 60)?,
+
+            render_cache_ttl: env("DOCSRS_RENDER_CACHE_TTL", 60)?,
+
+            consistency_check_sample_size: env("DOCSRS_CONSISTENCY_CHECK_SAMPLE_SIZE", 10)?,
+
+            build_events_webhook: maybe_env("DOCSRS_BUILD_EVENTS_WEBHOOK")?,
+            build_events_secret: maybe_env("DOCSRS_BUILD_EVENTS_SECRET")?,
+            public_url: env(
+                "DOCSRS_PUBLIC_URL",
+                Url::parse("https://docs.rs").expect("valid url"),
+            )?,
+
+            build_progress_interval: env("DOCSRS_BUILD_PROGRESS_INTERVAL", 5)?,
+
             rustwide_workspace: env("DOCSRS_RUSTWIDE_WORKSPACE", PathBuf::from(".workspace"))?,
             inside_docker: env("DOCSRS_DOCKER", false)?,
             docker_image: maybe_env("DOCSRS_LOCAL_DOCKER_IMAGE")?
@@ -134,11 +386,84 @@ impl Config {
             build_cpu_limit: maybe_env("DOCSRS_BUILD_CPU_LIMIT")?,
             include_default_targets: env("DOCSRS_INCLUDE_DEFAULT_TARGETS", true)?,
             disable_memory_limit: env("DOCSRS_DISABLE_MEMORY_LIMIT", false)?,
-        })
+            build_min_free_disk_space: maybe_env("DOCSRS_BUILD_MIN_FREE_DISK_SPACE")?,
+            verify_reproducible_builds: env("DOCSRS_VERIFY_REPRODUCIBLE_BUILDS", false)?,
+            rustdoc_json: env("DOCSRS_RUSTDOC_JSON", false)?,
+
+            rebuild_batch_size: env("DOCSRS_REBUILD_BATCH_SIZE", 100)?,
+            rebuild_batch_interval: env("DOCSRS_REBUILD_BATCH_INTERVAL", 5 * 60)?,
+            toolchain_rebuild_crates: env("DOCSRS_TOOLCHAIN_REBUILD_CRATES", 0)?,
+
+            prerelease_retention: maybe_env("DOCSRS_PRERELEASE_RETENTION")?,
+            prerelease_retention_days: env("DOCSRS_PRERELEASE_RETENTION_DAYS", 30)?,
+
+            advisory_db_url: maybe_env("DOCSRS_ADVISORY_DB_URL")?,
+            dataset_exports: env("DOCSRS_DATASET_EXPORTS", false)?,
+            dataset_exports_kept: env("DOCSRS_DATASET_EXPORTS_KEPT", 7)?,
+
+            #[cfg(feature = "graphql")]
+            graphql_max_depth: env("DOCSRS_GRAPHQL_MAX_DEPTH", 8)?,
+            #[cfg(feature = "graphql")]
+            graphql_max_complexity: env("DOCSRS_GRAPHQL_MAX_COMPLEXITY", 200)?,
+            #[cfg(feature = "graphql")]
+            graphql_persisted_queries: maybe_env("DOCSRS_GRAPHQL_PERSISTED_QUERIES")?,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks the variables that depend on each other
+    fn validate(&self) -> Result<(), ConfigError> {
+        let inconsistent = |var, reason| Err(ConfigError::Inconsistent { var, reason });
+
+        if self.max_pool_size == 0 {
+            return inconsistent(
+                "DOCSRS_MAX_POOL_SIZE",
+                "the pool needs at least one connection",
+            );
+        }
+        if self.min_pool_idle > self.max_pool_size {
+            return inconsistent(
+                "DOCSRS_MIN_POOL_IDLE",
+                "can't be larger than DOCSRS_MAX_POOL_SIZE",
+            );
+        }
+        if self.s3_replica_region.is_some() && self.s3_replica_bucket.is_none() {
+            return inconsistent(
+                "DOCSRS_S3_REPLICA_REGION",
+                "requires DOCSRS_S3_REPLICA_BUCKET to be set",
+            );
+        }
+        if self.tls_certificate.is_some() != self.tls_private_key.is_some() {
+            return inconsistent(
+                "DOCSRS_TLS_CERTIFICATE",
+                "must be set together with DOCSRS_TLS_PRIVATE_KEY",
+            );
+        }
+        match self.cdn_backend {
+            CdnKind::CloudFront if self.cloudfront_distribution_id.is_none() => {
+                return inconsistent(
+                    "DOCSRS_CDN_BACKEND",
+                    "the CloudFront backend requires DOCSRS_CLOUDFRONT_DISTRIBUTION_ID",
+                );
+            }
+            CdnKind::Fastly
+                if self.fastly_service_id.is_none() || self.fastly_api_token.is_none() =>
+            {
+                return inconsistent(
+                    "DOCSRS_CDN_BACKEND",
+                    "the Fastly backend requires DOCSRS_FASTLY_SERVICE_ID and DOCSRS_FASTLY_API_TOKEN",
+                );
+            }
+            _ => {}
+        }
+
+        Ok(())
     }
 }
 
-fn env<T>(var: &str, default: T) -> Result<T, Error>
+fn env<T>(var: &'static str, default: T) -> Result<T, ConfigError>
 where
     T: FromStr,
     T::Err: Fail,
@@ -146,28 +471,92 @@ where
     Ok(maybe_env(var)?.unwrap_or(default))
 }
 
-fn require_env<T>(var: &str) -> Result<T, Error>
+fn require_env<T>(var: &'static str) -> Result<T, ConfigError>
 where
     T: FromStr,
     T::Err: Fail,
 {
-    maybe_env(var)?.ok_or_else(|| format_err!("configuration variable {} is missing", var))
+    maybe_env(var)?.ok_or(ConfigError::Missing(var))
 }
 
-fn maybe_env<T>(var: &str) -> Result<Option<T>, Error>
+fn maybe_env<T>(var: &'static str) -> Result<Option<T>, ConfigError>
 where
     T: FromStr,
     T::Err: Fail,
 {
     match std::env::var(var) {
-        Ok(content) => Ok(content
+        Ok(content) => content
             .parse::<T>()
             .map(Some)
-            .with_context(|_| format!("failed to parse configuration variable {}", var))?),
+            .map_err(|err| ConfigError::Invalid {
+                var,
+                reason: err.to_string(),
+            }),
         Err(VarError::NotPresent) => {
             log::trace!("optional configuration variable {} is not set", var);
             Ok(None)
         }
-        Err(VarError::NotUnicode(_)) => bail!("configuration variable {} is not UTF-8", var),
+        Err(VarError::NotUnicode(_)) => Err(ConfigError::NotUnicode(var)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_variables() {
+        std::env::set_var("DOCSRS_TEST_CONFIG_INVALID", "not a number");
+        assert_eq!(
+            maybe_env::<u32>("DOCSRS_TEST_CONFIG_INVALID"),
+            Err(ConfigError::Invalid {
+                var: "DOCSRS_TEST_CONFIG_INVALID",
+                reason: "invalid digit found in string".into(),
+            })
+        );
+        assert_eq!(
+            require_env::<u32>("DOCSRS_TEST_CONFIG_MISSING"),
+            Err(ConfigError::Missing("DOCSRS_TEST_CONFIG_MISSING"))
+        );
+        assert_eq!(env("DOCSRS_TEST_CONFIG_MISSING", 42), Ok(42));
+    }
+
+    #[test]
+    fn inconsistent_variables() {
+        let mut config = Config::from_env().unwrap();
+        assert_eq!(config.validate(), Ok(()));
+
+        config.min_pool_idle = config.max_pool_size + 1;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Inconsistent {
+                var: "DOCSRS_MIN_POOL_IDLE",
+                ..
+            })
+        ));
+        config.min_pool_idle = 0;
+
+        config.tls_private_key = Some("key.pem".into());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Inconsistent {
+                var: "DOCSRS_TLS_CERTIFICATE",
+                ..
+            })
+        ));
+        config.tls_certificate = Some("cert.pem".into());
+        assert_eq!(config.validate(), Ok(()));
+
+        config.cdn_backend = CdnKind::Fastly;
+        config.fastly_service_id = Some("docs".into());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Inconsistent {
+                var: "DOCSRS_CDN_BACKEND",
+                ..
+            })
+        ));
+        config.fastly_api_token = Some("secret".into());
+        assert_eq!(config.validate(), Ok(()));
     }
 }
@@ -1,6 +1,6 @@
 use crate::db::Pool;
 use crate::repositories::RepositoryStatsUpdater;
-use crate::{BuildQueue, Config, Index, Metrics, Storage};
+use crate::{BuildQueue, Config, Index, Metrics, ReleasesCache, Storage, VersionCache};
 use failure::Error;
 use std::sync::Arc;
 
@@ -12,4 +12,6 @@ pub trait Context {
     fn metrics(&self) -> Result<Arc<Metrics>, Error>;
     fn index(&self) -> Result<Arc<Index>, Error>;
     fn repository_stats_updater(&self) -> Result<Arc<RepositoryStatsUpdater>, Error>;
+    fn version_cache(&self) -> Result<Arc<VersionCache>, Error>;
+    fn releases_cache(&self) -> Result<Arc<ReleasesCache>, Error>;
 }
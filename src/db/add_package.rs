@@ -6,12 +6,15 @@ use std::{
 };
 
 use crate::{
-    db::types::Feature,
+    db::{
+        release_activity::{count_release, uncount_releases},
+        types::Feature,
+    },
     docbuilder::{BuildResult, DocCoverage},
     error::Result,
     index::api::{CrateData, CrateOwner, ReleaseData},
     storage::CompressionAlgorithm,
-    utils::MetadataPackage,
+    utils::{citation::Citation, MetadataPackage},
 };
 use log::{debug, info};
 use postgres::Client;
@@ -47,7 +50,11 @@ pub(crate) fn add_package_into_database(
     let features = get_features(metadata_pkg);
     let is_library = metadata_pkg.is_library();
 
-    let rows = conn.query(
+    // a rebuild replaces the release, which is counted again once it's updated. The activity
+    // totals would drift if the release wasn't updated after being uncounted.
+    let mut transaction = conn.transaction()?;
+    uncount_releases(&mut transaction, crate_id, Some(&metadata_pkg.version))?;
+    let rows = transaction.query(
         "INSERT INTO releases (
             crate_id, version, release_time,
             dependencies, target_name, yanked, build_status,
@@ -56,12 +63,13 @@ pub(crate) fn add_package_into_database(
             keywords, have_examples, downloads, files,
             doc_targets, is_library, doc_rustc_version,
             documentation_url, default_target, features,
-            repository_id
+            repository_id, no_source, rust_version
          )
          VALUES (
             $1,  $2,  $3,  $4,  $5,  $6,  $7,  $8,  $9,
             $10, $11, $12, $13, $14, $15, $16, $17, $18,
-            $19, $20, $21, $22, $23, $24, $25, $26
+            $19, $20, $21, $22, $23, $24, $25, $26, $27,
+            $28
          )
          ON CONFLICT (crate_id, version) DO UPDATE
             SET release_time = $3,
@@ -87,7 +95,9 @@ pub(crate) fn add_package_into_database(
                 documentation_url = $23,
                 default_target = $24,
                 features = $25,
-                repository_id = $26
+                repository_id = $26,
+                no_source = $27,
+                rust_version = $28
          RETURNING id",
         &[
             &crate_id,
@@ -116,12 +126,17 @@ pub(crate) fn add_package_into_database(
             &default_target,
             &features,
             &repository_id,
+            &res.no_source,
+            &metadata_pkg.rust_version,
         ],
     )?;
 
     let release_id: i32 = rows[0].get(0);
+    count_release(&mut transaction, release_id)?;
+    transaction.commit()?;
 
     add_keywords_into_database(conn, metadata_pkg, release_id)?;
+    add_dependencies_into_database(conn, &dependencies, release_id)?;
     add_compression_into_database(conn, compression_algorithms.into_iter(), release_id)?;
 
     // Update the crates table with the new release
@@ -165,6 +180,19 @@ pub(crate) fn add_doc_coverage(
     Ok(rows[0].get(0))
 }
 
+/// Adds the citation found in the crate sources into database
+pub(crate) fn add_citation(conn: &mut Client, release_id: i32, citation: &Citation) -> Result<()> {
+    debug!("Adding citation into database");
+    conn.execute(
+        "INSERT INTO citations (release_id, data)
+            VALUES ($1, $2)
+            ON CONFLICT (release_id) DO UPDATE
+                SET data = $2",
+        &[&release_id, &serde_json::to_value(citation)?],
+    )?;
+    Ok(())
+}
+
 /// Adds a build into database
 pub(crate) fn add_build_into_database(
     conn: &mut Client,
@@ -173,17 +201,49 @@ pub(crate) fn add_build_into_database(
 ) -> Result<i32> {
     debug!("Adding build into database");
     let rows = conn.query(
-        "INSERT INTO builds (rid, rustc_version, docsrs_version, build_status)
-        VALUES ($1, $2, $3, $4)
+        "INSERT INTO builds (
+            rid, rustc_version, docsrs_version, build_status, peak_disk_usage, failure_category,
+            limits, warnings
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         RETURNING id",
         &[
             &release_id,
             &res.rustc_version,
             &res.docsrs_version,
             &res.successful,
+            &res.peak_disk_usage.map(|usage| usage as i64),
+            &res.failure.map(|failure| failure.as_str()),
+            &res.limits.as_ref().map(serde_json::to_value).transpose()?,
+            &res.warnings,
         ],
     )?;
-    Ok(rows[0].get(0))
+    let build_id: i32 = rows[0].get(0);
+
+    for diagnostic in &res.diagnostics {
+        conn.execute(
+            "INSERT INTO build_diagnostics (build_id, level, lint, file, line, message)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &build_id,
+                &diagnostic.level,
+                &diagnostic.lint,
+                &diagnostic.file,
+                &diagnostic.line,
+                &diagnostic.message,
+            ],
+        )?;
+    }
+
+    // the sitemap and the rustdoc pages use this to tell crawlers the documentation changed
+    if res.successful {
+        conn.execute(
+            "UPDATE releases SET last_build_time = NOW() WHERE id = $1",
+            &[&release_id],
+        )?;
+    }
+
+    Ok(build_id)
 }
 
 fn initialize_package_in_database(conn: &mut Client, pkg: &MetadataPackage) -> Result<i32> {
@@ -311,7 +371,26 @@ fn read_rust_doc(file_path: &Path) -> Result<Option<String>> {
     }
 }
 
-/// Adds keywords into database
+/// Stores the dependencies of a release in `release_dependencies`, replacing the ones of a
+/// previous build of the same release
+fn add_dependencies_into_database(
+    conn: &mut Client,
+    dependencies: &[(String, String, String)],
+    release_id: i32,
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM release_dependencies WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    let insert_dependency_query = conn.prepare(
+        "INSERT INTO release_dependencies (release_id, name, req, kind) VALUES ($1, $2, $3, $4)",
+    )?;
+    for (name, req, kind) in dependencies {
+        conn.execute(&insert_dependency_query, &[&release_id, name, req, kind])?;
+    }
+    Ok(())
+}
+
 fn add_keywords_into_database(
     conn: &mut Client,
     pkg: &MetadataPackage,
@@ -556,6 +635,46 @@ mod test {
         })
     }
 
+    #[test]
+    fn updated_dependencies() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.13.0")
+                .dependencies(&[("foo", "^1", "normal"), ("bar", "^2", "dev")])
+                .create()?;
+
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .version("0.13.0")
+                .dependencies(&[("foo", "^1.1", "normal"), ("baz", "^3", "build")])
+                .create()?;
+
+            let dependencies = env
+                .db()
+                .conn()
+                .query(
+                    "SELECT name, req, kind FROM release_dependencies
+                     WHERE release_id = $1
+                     ORDER BY name",
+                    &[&release_id],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect::<Vec<(String, String, String)>>();
+            assert_eq!(
+                dependencies,
+                vec![
+                    ("baz".into(), "^3".into(), "build".into()),
+                    ("foo".into(), "^1.1".into(), "normal".into()),
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn new_owners() {
         wrapper(|env| {
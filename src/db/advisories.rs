@@ -0,0 +1,211 @@
+//! Security advisories of the RustSec advisory database, synced by `utils::advisories` and shown
+//! on the crate details and documentation of the affected releases
+
+use chrono::NaiveDate;
+use failure::Error;
+use postgres::{Client, Row};
+use semver::{Version, VersionReq};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Advisory {
+    /// `RUSTSEC-YYYY-NNNN`
+    pub id: String,
+    pub crate_name: String,
+    pub title: String,
+    pub url: String,
+    pub date: NaiveDate,
+    /// The kind of the informational advisories, like `unmaintained`, `None` for vulnerabilities
+    pub informational: Option<String>,
+    /// The requirements matching the fixed versions
+    #[serde(skip)]
+    pub patched: Vec<String>,
+    /// The requirements matching the versions that were never affected
+    #[serde(skip)]
+    pub unaffected: Vec<String>,
+}
+
+impl Advisory {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            id: row.get("id"),
+            crate_name: row.get("crate_name"),
+            title: row.get("title"),
+            url: row.get("url"),
+            date: row.get("date"),
+            informational: row.get("informational"),
+            patched: row.get("patched"),
+            unaffected: row.get("unaffected"),
+        }
+    }
+
+    /// Whether the version isn't matched by the patched or unaffected requirements. The
+    /// requirements that can't be parsed don't match any version.
+    pub fn affects(&self, version: &Version) -> bool {
+        !self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .filter_map(|req| match VersionReq::parse(req) {
+                Ok(req) => Some(req),
+                Err(err) => {
+                    log::warn!(
+                        "invalid version requirement {:?} in {}: {}",
+                        req,
+                        self.id,
+                        err
+                    );
+                    None
+                }
+            })
+            .any(|req| req.matches(version))
+    }
+}
+
+/// How the advisories are introduced on the pages, the informational ones (about unmaintained or
+/// unsound crates) aren't security vulnerabilities
+pub fn label(advisories: &[Advisory]) -> &'static str {
+    let vulnerabilities = advisories
+        .iter()
+        .all(|advisory| advisory.informational.is_none());
+    match (advisories.len(), vulnerabilities) {
+        (1, true) => "a security advisory",
+        (_, true) => "security advisories",
+        (1, false) => "an advisory",
+        (_, false) => "advisories",
+    }
+}
+
+/// Replaces all the advisories with the ones of the latest copy of the database
+pub fn replace_all(conn: &mut Client, advisories: &[Advisory]) -> Result<(), Error> {
+    let mut transaction = conn.transaction()?;
+    transaction.execute("DELETE FROM advisories", &[])?;
+    for advisory in advisories {
+        transaction.execute(
+            "INSERT INTO advisories
+                (id, crate_name, title, url, date, informational, patched, unaffected)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (id) DO NOTHING",
+            &[
+                &advisory.id,
+                &advisory.crate_name,
+                &advisory.title,
+                &advisory.url,
+                &advisory.date,
+                &advisory.informational,
+                &advisory.patched,
+                &advisory.unaffected,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The advisories affecting a release, the most recent first
+pub fn affecting(conn: &mut Client, name: &str, version: &str) -> Result<Vec<Advisory>, Error> {
+    let version = match Version::parse(version) {
+        Ok(version) => version,
+        Err(_) => return Ok(Vec::new()),
+    };
+    Ok(conn
+        .query(
+            "SELECT * FROM advisories WHERE crate_name = $1 ORDER BY date DESC, id DESC",
+            &[&name],
+        )?
+        .iter()
+        .map(Advisory::from_row)
+        .filter(|advisory| advisory.affects(&version))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{fake_advisory, wrapper};
+
+    fn advisory(id: &str, patched: &[&str], unaffected: &[&str]) -> Advisory {
+        Advisory {
+            patched: patched.iter().map(|&req| req.into()).collect(),
+            unaffected: unaffected.iter().map(|&req| req.into()).collect(),
+            ..fake_advisory(id, "foo")
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let vulnerability = fake_advisory("RUSTSEC-2021-0001", "foo");
+        let unmaintained = Advisory {
+            informational: Some("unmaintained".into()),
+            ..fake_advisory("RUSTSEC-2021-0002", "foo")
+        };
+        assert_eq!(
+            label(std::slice::from_ref(&vulnerability)),
+            "a security advisory"
+        );
+        assert_eq!(
+            label(&[vulnerability.clone(), vulnerability.clone()]),
+            "security advisories"
+        );
+        assert_eq!(label(std::slice::from_ref(&unmaintained)), "an advisory");
+        assert_eq!(label(&[vulnerability, unmaintained]), "advisories");
+    }
+
+    #[test]
+    fn affected_versions() {
+        let advisory = advisory(
+            "RUSTSEC-2021-0001",
+            &[">= 1.2.3, < 2.0.0", ">= 2.0.1"],
+            &["< 1.0.0", "not a requirement"],
+        );
+        for version in &["1.0.0", "1.2.2", "2.0.0"] {
+            assert!(
+                advisory.affects(&Version::parse(version).unwrap()),
+                "{}",
+                version
+            );
+        }
+        for version in &["0.9.0", "1.2.3", "2.0.1"] {
+            assert!(
+                !advisory.affects(&Version::parse(version).unwrap()),
+                "{}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn replace_and_query() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            replace_all(
+                &mut conn,
+                &[
+                    advisory("RUSTSEC-2021-0001", &[">= 1.0.0"], &[]),
+                    advisory("RUSTSEC-2021-0002", &[">= 2.0.0"], &[]),
+                ],
+            )?;
+            let ids = |conn: &mut Client, version| -> Result<Vec<String>, Error> {
+                Ok(affecting(conn, "foo", version)?
+                    .into_iter()
+                    .map(|advisory| advisory.id)
+                    .collect())
+            };
+            assert_eq!(
+                ids(&mut conn, "0.1.0")?,
+                vec!["RUSTSEC-2021-0002", "RUSTSEC-2021-0001"]
+            );
+            assert_eq!(ids(&mut conn, "1.0.0")?, vec!["RUSTSEC-2021-0002"]);
+
+            // the advisories withdrawn since the last sync are removed
+            replace_all(
+                &mut conn,
+                &[advisory("RUSTSEC-2021-0002", &[">= 2.0.0"], &[])],
+            )?;
+            assert_eq!(ids(&mut conn, "0.1.0")?, vec!["RUSTSEC-2021-0002"]);
+            assert!(affecting(&mut conn, "bar", "0.1.0")?.is_empty());
+
+            Ok(())
+        });
+    }
+}
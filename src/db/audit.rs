@@ -0,0 +1,272 @@
+//! Log of the administrative operations that delete data or change how crates are built
+//!
+//! The operations go through an `Auditor`, which records who performed them, when and why after
+//! they succeeded. The log is shown on `/admin/audit`.
+
+use crate::{
+    db::{
+        self,
+        crate_redirects::{self, CrateRedirect},
+        sandbox_overrides::{self, SandboxOverride},
+    },
+    utils, BuildQueue, Config, Storage,
+};
+use chrono::{DateTime, Utc};
+use failure::Error;
+use postgres::Client;
+use serde::Serialize;
+
+/// Performs administrative operations on behalf of someone, recording them in the audit log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auditor {
+    actor: String,
+    reason: Option<String>,
+}
+
+impl Auditor {
+    /// The operations are attributed to the system user running the process, which can't be
+    /// picked by whoever performs them
+    pub fn from_config(config: &Config, reason: Option<String>) -> Self {
+        Self::new(config.audit_actor.clone(), reason)
+    }
+
+    pub(crate) fn new(actor: impl Into<String>, reason: Option<String>) -> Self {
+        Self {
+            actor: actor.into(),
+            reason,
+        }
+    }
+
+    pub fn delete_crate(
+        &self,
+        conn: &mut Client,
+        storage: &Storage,
+        name: &str,
+    ) -> Result<(), Error> {
+        db::delete_crate(conn, storage, name)?;
+        self.record(conn, "delete-crate", name)
+    }
+
+    pub fn delete_version(
+        &self,
+        conn: &mut Client,
+        storage: &Storage,
+        name: &str,
+        version: &str,
+    ) -> Result<(), Error> {
+        db::delete_version(conn, storage, name, version)?;
+        self.record(conn, "delete-version", &format!("{} {}", name, version))
+    }
+
+    /// Deletes the documentation of a release, keeping the release and its sources
+    pub fn archive_version(
+        &self,
+        conn: &mut Client,
+        storage: &Storage,
+        name: &str,
+        version: &str,
+    ) -> Result<(), Error> {
+        db::archive_version(conn, storage, name, version)?;
+        self.record(conn, "archive-version", &format!("{} {}", name, version))
+    }
+
+    pub fn blacklist_crate(&self, conn: &mut Client, name: &str) -> Result<(), Error> {
+        db::blacklist::add_crate(conn, name)?;
+        self.record(conn, "blacklist-add", name)
+    }
+
+    pub fn unblacklist_crate(&self, conn: &mut Client, name: &str) -> Result<(), Error> {
+        db::blacklist::remove_crate(conn, name)?;
+        self.record(conn, "blacklist-remove", name)
+    }
+
+    pub fn set_crate_priority(
+        &self,
+        conn: &mut Client,
+        pattern: &str,
+        priority: i32,
+    ) -> Result<(), Error> {
+        utils::set_crate_priority(conn, pattern, priority)?;
+        self.record(
+            conn,
+            "priority-set",
+            &format!("{} (priority {})", pattern, priority),
+        )
+    }
+
+    /// Returns the priority the pattern had, nothing is recorded if it didn't exist
+    pub fn remove_crate_priority(
+        &self,
+        conn: &mut Client,
+        pattern: &str,
+    ) -> Result<Option<i32>, Error> {
+        let priority = utils::remove_crate_priority(conn, pattern)?;
+        if let Some(priority) = priority {
+            self.record(
+                conn,
+                "priority-remove",
+                &format!("{} (priority {})", pattern, priority),
+            )?;
+        }
+        Ok(priority)
+    }
+
+    pub fn queue_crate(
+        &self,
+        conn: &mut Client,
+        queue: &BuildQueue,
+        name: &str,
+        version: &str,
+        priority: i32,
+        registry: Option<&str>,
+    ) -> Result<(), Error> {
+        queue.add_crate(name, version, priority, registry)?;
+        self.record(
+            conn,
+            "queue-add",
+            &format!("{} {} (priority {})", name, version, priority),
+        )
+    }
+
+    /// Creates or replaces the limits overridden for a crate
+    pub fn set_sandbox_override(
+        &self,
+        conn: &mut Client,
+        limits: &SandboxOverride,
+    ) -> Result<(), Error> {
+        sandbox_overrides::set(conn, limits)?;
+        self.record(conn, "limits-set", &limits.to_string())
+    }
+
+    /// Returns the removed override, nothing is recorded if there wasn't one
+    pub fn remove_sandbox_override(
+        &self,
+        conn: &mut Client,
+        name: &str,
+    ) -> Result<Option<SandboxOverride>, Error> {
+        let removed = sandbox_overrides::remove(conn, name)?;
+        if let Some(removed) = &removed {
+            self.record(conn, "limits-remove", &removed.to_string())?;
+        }
+        Ok(removed)
+    }
+
+    /// Creates or replaces the redirect of a crate
+    pub fn set_crate_redirect(
+        &self,
+        conn: &mut Client,
+        redirect: &CrateRedirect,
+    ) -> Result<(), Error> {
+        crate_redirects::set(conn, redirect)?;
+        self.record(conn, "redirect-set", &redirect.to_string())
+    }
+
+    /// Returns the removed redirect, nothing is recorded if there wasn't one
+    pub fn remove_crate_redirect(
+        &self,
+        conn: &mut Client,
+        name: &str,
+    ) -> Result<Option<CrateRedirect>, Error> {
+        let removed = crate_redirects::remove(conn, name)?;
+        if let Some(removed) = &removed {
+            self.record(conn, "redirect-remove", &removed.to_string())?;
+        }
+        Ok(removed)
+    }
+
+    fn record(&self, conn: &mut Client, action: &str, target: &str) -> Result<(), Error> {
+        conn.execute(
+            "INSERT INTO audit_log (actor, action, target, reason) VALUES ($1, $2, $3, $4)",
+            &[&self.actor, &action, &target, &self.reason],
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct AuditEntry {
+    pub(crate) id: i32,
+    pub(crate) actor: String,
+    pub(crate) action: String,
+    pub(crate) target: String,
+    pub(crate) reason: Option<String>,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+/// The latest `limit` entries of the log, only the ones older than the entry `before` if it's
+/// given
+pub(crate) fn audit_entries(
+    conn: &mut Client,
+    before: Option<i32>,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, Error> {
+    Ok(conn
+        .query(
+            "SELECT id, actor, action, target, reason, created_at
+             FROM audit_log
+             WHERE $1::INT IS NULL OR id < $1
+             ORDER BY id DESC
+             LIMIT $2",
+            &[&before, &limit],
+        )?
+        .into_iter()
+        .map(|row| AuditEntry {
+            id: row.get("id"),
+            actor: row.get("actor"),
+            action: row.get("action"),
+            target: row.get("target"),
+            reason: row.get("reason"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn operations_are_recorded() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            env.fake_release().name("foo").version("0.2.0").create()?;
+
+            let mut conn = env.db().conn();
+            let auditor = Auditor::new("admin", Some("spam".into()));
+            auditor.delete_version(&mut conn, &env.storage(), "foo", "0.1.0")?;
+            auditor.blacklist_crate(&mut conn, "foo")?;
+            Auditor::new("other-admin", None).set_crate_priority(&mut conn, "foo-%", 10)?;
+            assert_eq!(auditor.remove_crate_priority(&mut conn, "bar-%")?, None);
+
+            // failed operations aren't recorded
+            assert!(auditor.blacklist_crate(&mut conn, "foo").is_err());
+
+            let entries = audit_entries(&mut conn, None, 10)?;
+            let summary: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.actor.as_str(),
+                        entry.action.as_str(),
+                        entry.target.as_str(),
+                        entry.reason.as_deref(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("other-admin", "priority-set", "foo-% (priority 10)", None),
+                    ("admin", "blacklist-add", "foo", Some("spam")),
+                    ("admin", "delete-version", "foo 0.1.0", Some("spam")),
+                ]
+            );
+
+            let older = audit_entries(&mut conn, Some(entries[1].id), 10)?;
+            assert_eq!(older, entries[2..]);
+
+            Ok(())
+        });
+    }
+}
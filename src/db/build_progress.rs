@@ -0,0 +1,159 @@
+//! Logs of the builds in progress
+//!
+//! While a target is being documented, the builder copies its log to the `build_progress` table
+//! every `DOCSRS_BUILD_PROGRESS_INTERVAL` seconds, and removes it once the build is stored.
+//! `/crate/:name/:version/builds/current/log` shows it, so that the long builds can be followed
+//! instead of waiting for the final log.
+
+use chrono::{DateTime, Utc};
+use failure::Error;
+use postgres::Client;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RunningBuild {
+    /// The target being documented, the log is the one of this target only
+    pub(crate) target: String,
+    pub(crate) log: String,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+/// Replaces the log of the running build of a release
+pub(crate) fn record(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+    target: &str,
+    log: &str,
+) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO build_progress (name, version, target, log)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (name, version) DO UPDATE
+             SET target = EXCLUDED.target, log = EXCLUDED.log, updated_at = NOW()",
+        &[&name, &version, &target, &log],
+    )?;
+    Ok(())
+}
+
+/// Adds the new lines to the log of the running build of a release, returns `false` if the build
+/// isn't recorded for this target, its whole log should then be recorded instead
+pub(crate) fn append(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+    target: &str,
+    lines: &str,
+) -> Result<bool, Error> {
+    let updated = conn.execute(
+        "UPDATE build_progress
+         SET log = log || $4, updated_at = NOW()
+         WHERE name = $1 AND version = $2 AND target = $3",
+        &[&name, &version, &target, &lines],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Forgets the build of a release once it's over
+pub(crate) fn clear(conn: &mut Client, name: &str, version: &str) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM build_progress WHERE name = $1 AND version = $2",
+        &[&name, &version],
+    )?;
+    Ok(())
+}
+
+/// The running build of a release, the log being copied every `interval` seconds. The builds
+/// whose log wasn't updated for three intervals are ignored, their builder probably stopped
+/// before clearing them.
+pub(crate) fn running_build(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+    interval: u64,
+) -> Result<Option<RunningBuild>, Error> {
+    let stale_after = (interval * 3).max(30) as f64;
+    Ok(conn
+        .query_opt(
+            "SELECT target, log, started_at, updated_at
+             FROM build_progress
+             WHERE name = $1 AND version = $2
+                 AND updated_at > NOW() - make_interval(secs => $3)",
+            &[&name, &version, &stale_after],
+        )?
+        .map(|row| RunningBuild {
+            target: row.get(0),
+            log: row.get(1),
+            started_at: row.get(2),
+            updated_at: row.get(3),
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn record_and_clear() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(running_build(&mut conn, "foo", "0.1.0", 5)?, None);
+
+            record(
+                &mut conn,
+                "foo",
+                "0.1.0",
+                "x86_64-unknown-linux-gnu",
+                "[INFO] a\n",
+            )?;
+            record(
+                &mut conn,
+                "foo",
+                "0.1.0",
+                "i686-pc-windows-msvc",
+                "[INFO] b\n",
+            )?;
+            let running = running_build(&mut conn, "foo", "0.1.0", 5)?.unwrap();
+            assert_eq!(running.target, "i686-pc-windows-msvc");
+            assert_eq!(running.log, "[INFO] b\n");
+
+            assert!(append(
+                &mut conn,
+                "foo",
+                "0.1.0",
+                "i686-pc-windows-msvc",
+                "[INFO] c\n"
+            )?);
+            assert!(!append(
+                &mut conn,
+                "foo",
+                "0.1.0",
+                "x86_64-unknown-linux-gnu",
+                "[INFO] d\n"
+            )?);
+            assert!(!append(
+                &mut conn,
+                "foo",
+                "0.2.0",
+                "i686-pc-windows-msvc",
+                "[INFO] e\n"
+            )?);
+            let running = running_build(&mut conn, "foo", "0.1.0", 5)?.unwrap();
+            assert_eq!(running.log, "[INFO] b\n[INFO] c\n");
+            assert!(running_build(&mut conn, "foo", "0.2.0", 5)?.is_none());
+
+            conn.execute(
+                "UPDATE build_progress SET updated_at = NOW() - INTERVAL '10 minutes'",
+                &[],
+            )?;
+            assert_eq!(running_build(&mut conn, "foo", "0.1.0", 5)?, None);
+            assert!(running_build(&mut conn, "foo", "0.1.0", 600)?.is_some());
+
+            clear(&mut conn, "foo", "0.1.0")?;
+            assert_eq!(running_build(&mut conn, "foo", "0.1.0", 600)?, None);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,213 @@
+//! Redirects of the pages of renamed or superseded crates to their replacement, applied by
+//! the handlers of the documentation and of the crate pages
+
+use failure::{Error, Fail};
+use postgres::{Client, Row};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Fail, PartialEq, Eq)]
+#[fail(display = "invalid crate redirect: {}", _0)]
+pub struct InvalidRedirectError(String);
+
+/// `/old_name/*` redirects to `/new_name/*`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateRedirect {
+    pub old_name: String,
+    pub new_name: String,
+}
+
+impl CrateRedirect {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            old_name: row.get("old_name"),
+            new_name: row.get("new_name"),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidRedirectError> {
+        for name in &[&self.old_name, &self.new_name] {
+            let valid = !name.is_empty()
+                && name.len() <= 64
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(InvalidRedirectError(format!(
+                    "invalid crate name {:?}",
+                    name
+                )));
+            }
+        }
+        let normalized = |name: &str| name.replace('_', "-").to_lowercase();
+        if normalized(&self.old_name) == normalized(&self.new_name) {
+            return Err(InvalidRedirectError(format!(
+                "{} can't redirect to itself",
+                self.old_name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CrateRedirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.old_name, self.new_name)
+    }
+}
+
+/// Returns all the redirects, sorted by the old crate name
+pub fn list(conn: &mut Client) -> Result<Vec<CrateRedirect>, Error> {
+    Ok(conn
+        .query("SELECT * FROM crate_redirects ORDER BY old_name", &[])?
+        .iter()
+        .map(CrateRedirect::from_row)
+        .collect())
+}
+
+/// Returns the crate `name` redirects to, if it's redirected. Like on crates.io, the names only
+/// differing by case, dashes and underscores are the same crate, here and in the other functions.
+pub fn get(conn: &mut Client, name: &str) -> Result<Option<String>, Error> {
+    Ok(conn
+        .query_opt(
+            "SELECT new_name
+             FROM crate_redirects
+             WHERE normalize_crate_name(old_name) = normalize_crate_name($1)",
+            &[&name],
+        )?
+        .map(|row| row.get(0)))
+}
+
+/// Creates the redirect of a crate, or replaces its target. The crates redirecting to the old
+/// name are redirected to the new one too, so that the redirects never chain.
+pub fn set(conn: &mut Client, redirect: &CrateRedirect) -> Result<(), Error> {
+    redirect.validate()?;
+
+    let mut transaction = conn.transaction()?;
+    // the redirects set concurrently could otherwise chain, e.g. `a -> b` and `b -> c`
+    transaction.execute(
+        "LOCK TABLE crate_redirects IN SHARE ROW EXCLUSIVE MODE",
+        &[],
+    )?;
+    let redirected = transaction.query_opt(
+        "SELECT 1 FROM crate_redirects
+         WHERE normalize_crate_name(old_name) = normalize_crate_name($1)",
+        &[&redirect.new_name],
+    )?;
+    if redirected.is_some() {
+        return Err(
+            InvalidRedirectError(format!("{} is redirected itself", redirect.new_name)).into(),
+        );
+    }
+
+    transaction.execute(
+        "DELETE FROM crate_redirects
+         WHERE normalize_crate_name(old_name) = normalize_crate_name($1)",
+        &[&redirect.old_name],
+    )?;
+    transaction.execute(
+        "INSERT INTO crate_redirects (old_name, new_name) VALUES ($1, $2)",
+        &[&redirect.old_name, &redirect.new_name],
+    )?;
+    transaction.execute(
+        "UPDATE crate_redirects
+         SET new_name = $2
+         WHERE normalize_crate_name(new_name) = normalize_crate_name($1)",
+        &[&redirect.old_name, &redirect.new_name],
+    )?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Returns the removed redirect, if there was one
+pub fn remove(conn: &mut Client, name: &str) -> Result<Option<CrateRedirect>, Error> {
+    Ok(conn
+        .query_opt(
+            "DELETE FROM crate_redirects
+             WHERE normalize_crate_name(old_name) = normalize_crate_name($1)
+             RETURNING *",
+            &[&name],
+        )?
+        .as_ref()
+        .map(CrateRedirect::from_row))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn validation() {
+        let redirect = |old: &str, new: &str| CrateRedirect {
+            old_name: old.into(),
+            new_name: new.into(),
+        };
+        assert_eq!(redirect("foo", "foo-rs").validate(), Ok(()));
+        assert_eq!(redirect("foo", "foo-rs").to_string(), "foo -> foo-rs");
+
+        for invalid in &[
+            redirect("", "foo"),
+            redirect("foo", ""),
+            redirect("foo", "foo"),
+            redirect("foo", "../bar"),
+            redirect("foo bar", "foo"),
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn redirects_never_chain() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let redirect = |old: &str, new: &str| CrateRedirect {
+                old_name: old.into(),
+                new_name: new.into(),
+            };
+
+            set(&mut conn, &redirect("foo", "bar"))?;
+            set(&mut conn, &redirect("bar", "baz"))?;
+            assert_eq!(get(&mut conn, "foo")?, Some("baz".into()));
+            assert!(set(&mut conn, &redirect("qux", "foo")).is_err());
+            assert!(set(&mut conn, &redirect("qux", "Bar")).is_err());
+
+            assert_eq!(remove(&mut conn, "foo")?, Some(redirect("foo", "baz")));
+            assert_eq!(remove(&mut conn, "foo")?, None);
+            assert_eq!(list(&mut conn)?, vec![redirect("bar", "baz")]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn names_differing_by_dashes_are_the_same() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let redirect = |old: &str, new: &str| CrateRedirect {
+                old_name: old.into(),
+                new_name: new.into(),
+            };
+            assert!(redirect("foo_bar", "Foo-Bar").validate().is_err());
+
+            set(&mut conn, &redirect("foo_bar", "baz"))?;
+            set(&mut conn, &redirect("foo-bar", "qux"))?;
+            assert_eq!(list(&mut conn)?, vec![redirect("foo-bar", "qux")]);
+            assert_eq!(get(&mut conn, "Foo_Bar")?, Some("qux".into()));
+
+            // the crates redirected to the old name follow it
+            set(&mut conn, &redirect("quux", "old_name"))?;
+            set(&mut conn, &redirect("old-name", "new-name"))?;
+            assert_eq!(get(&mut conn, "quux")?, Some("new-name".into()));
+
+            assert_eq!(
+                remove(&mut conn, "foo_bar")?,
+                Some(redirect("foo-bar", "qux"))
+            );
+            assert_eq!(get(&mut conn, "foo-bar")?, None);
+
+            Ok(())
+        });
+    }
+}
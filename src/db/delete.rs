@@ -1,24 +1,38 @@
+use super::release_activity::uncount_releases;
+use crate::docbuilder::Manifest;
 use crate::Storage;
 use failure::{Error, Fail};
 use postgres::Client;
 
 /// List of directories in docs.rs's underlying storage (either the database or S3) containing a
 /// subdirectory named after the crate. Those subdirectories will be deleted.
-static STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "sources"];
+static STORAGE_PATHS_TO_DELETE: &[&str] = &[
+    "rustdoc",
+    "sources",
+    "highlighted",
+    "definitions",
+    "lockfiles",
+    "rustdoc-json",
+];
 
 #[derive(Debug, Fail)]
 enum CrateDeletionError {
     #[fail(display = "crate is missing: {}", _0)]
     MissingCrate(String),
+    #[fail(display = "version is missing: {} {}", _0, _1)]
+    MissingVersion(String, String),
 }
 
 pub fn delete_crate(conn: &mut Client, storage: &Storage, name: &str) -> Result<(), Error> {
     let crate_id = get_id(conn, name)?;
-    delete_crate_from_database(conn, name, crate_id)?;
+    let build_ids = delete_crate_from_database(conn, name, crate_id)?;
 
     for prefix in STORAGE_PATHS_TO_DELETE {
         storage.delete_prefix(&format!("{}/{}/", prefix, name))?;
     }
+    for build_id in build_ids {
+        Manifest::delete(storage, build_id)?;
+    }
 
     Ok(())
 }
@@ -29,12 +43,50 @@ pub fn delete_version(
     name: &str,
     version: &str,
 ) -> Result<(), Error> {
-    delete_version_from_database(conn, name, version)?;
+    let build_ids = delete_version_from_database(conn, name, version)?;
 
     for prefix in STORAGE_PATHS_TO_DELETE {
         storage.delete_prefix(&format!("{}/{}/{}/", prefix, name, version))?;
     }
+    for build_id in build_ids {
+        Manifest::delete(storage, build_id)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the documentation of a release, keeping the release, its sources and its builds so
+/// that it can be rebuilt later
+pub fn archive_version(
+    conn: &mut Client,
+    storage: &Storage,
+    name: &str,
+    version: &str,
+) -> Result<(), Error> {
+    let crate_id = get_id(conn, name)?;
+    let mut transaction = conn.transaction()?;
+    let release_id: i32 = match transaction.query_opt(
+        "UPDATE releases SET rustdoc_status = FALSE
+         WHERE crate_id = $1 AND version = $2
+         RETURNING id",
+        &[&crate_id, &version],
+    )? {
+        Some(row) => row.get(0),
+        None => return Err(CrateDeletionError::MissingVersion(name.into(), version.into()).into()),
+    };
+    transaction.execute(
+        "DELETE FROM doc_sizes WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    transaction.execute(
+        "UPDATE release_storage_stats
+         SET rustdoc_compressed = 0, rustdoc_uncompressed = 0, updated_at = NOW()
+         WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    transaction.commit()?;
 
+    storage.delete_prefix(&format!("rustdoc/{}/{}/", name, version))?;
     Ok(())
 }
 
@@ -54,17 +106,37 @@ const METADATA: &[(&str, &str)] = &[
     ("builds", "rid"),
     ("compression_rels", "release"),
     ("doc_coverage", "release_id"),
+    ("citations", "release_id"),
+    ("consistency_issues", "release_id"),
+    ("release_dependencies", "release_id"),
 ];
 
-fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) -> Result<(), Error> {
+/// Returns the ids of the deleted builds, whose files are stored by id
+fn delete_version_from_database(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Vec<i32>, Error> {
     let crate_id = get_id(conn, name)?;
     let mut transaction = conn.transaction()?;
+    let build_ids = transaction
+        .query(
+            "SELECT builds.id
+             FROM builds
+             INNER JOIN releases ON releases.id = builds.rid
+             WHERE releases.crate_id = $1 AND releases.version = $2",
+            &[&crate_id, &version],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
     for &(table, column) in METADATA {
         transaction.execute(
             format!("DELETE FROM {} WHERE {} IN (SELECT id FROM releases WHERE crate_id = $1 AND version = $2)", table, column).as_str(),
             &[&crate_id, &version],
         )?;
     }
+    uncount_releases(&mut transaction, crate_id, Some(version))?;
     transaction.execute(
         "DELETE FROM releases WHERE crate_id = $1 AND version = $2",
         &[&crate_id, &version],
@@ -85,16 +157,37 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
         )?;
     }
 
-    transaction.commit().map_err(Into::into)
+    transaction.commit()?;
+    Ok(build_ids)
 }
 
-fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> Result<(), Error> {
+/// Returns the ids of the deleted builds, whose files are stored by id
+fn delete_crate_from_database(
+    conn: &mut Client,
+    name: &str,
+    crate_id: i32,
+) -> Result<Vec<i32>, Error> {
     let mut transaction = conn.transaction()?;
+    let build_ids = transaction
+        .query(
+            "SELECT builds.id
+             FROM builds
+             INNER JOIN releases ON releases.id = builds.rid
+             WHERE releases.crate_id = $1",
+            &[&crate_id],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
 
     transaction.execute(
         "DELETE FROM sandbox_overrides WHERE crate_name = $1",
         &[&name],
     )?;
+    transaction.execute(
+        "DELETE FROM retired_releases WHERE crate_name = $1",
+        &[&name],
+    )?;
     for &(table, column) in METADATA {
         transaction.execute(
             format!(
@@ -106,13 +199,14 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         )?;
     }
     transaction.execute("DELETE FROM owner_rels WHERE cid = $1;", &[&crate_id])?;
+    uncount_releases(&mut transaction, crate_id, None)?;
     transaction.execute("DELETE FROM releases WHERE crate_id = $1;", &[&crate_id])?;
     transaction.execute("DELETE FROM crates WHERE id = $1;", &[&crate_id])?;
 
     // Transactions automatically rollback when not committing, so if any of the previous queries
     // fail the whole transaction will be aborted.
     transaction.commit()?;
-    Ok(())
+    Ok(build_ids)
 }
 
 #[cfg(test)]
@@ -249,4 +343,37 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn build_manifests_are_deleted() {
+        wrapper(|env| {
+            env.fake_builder().name("a").version("1.0.0").build()?;
+            env.fake_builder().name("a").version("2.0.0").build()?;
+            env.fake_builder().name("b").version("1.0.0").build()?;
+            let build_ids: Vec<i32> = env
+                .db()
+                .conn()
+                .query("SELECT id FROM builds ORDER BY id", &[])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            let exists = |build_id: i32| {
+                env.storage()
+                    .exists(&format!("build-manifests/{}.json", build_id))
+            };
+            for build_id in &build_ids {
+                assert!(exists(*build_id)?);
+            }
+
+            delete_version(&mut env.db().conn(), &env.storage(), "a", "1.0.0")?;
+            assert!(!exists(build_ids[0])?);
+            assert!(exists(build_ids[1])?);
+
+            delete_crate(&mut env.db().conn(), &env.storage(), "a")?;
+            assert!(!exists(build_ids[1])?);
+            assert!(exists(build_ids[2])?);
+
+            Ok(())
+        })
+    }
 }
@@ -0,0 +1,165 @@
+//! Versions of the dependencies linked from the documentation of a release
+//!
+//! rustdoc links the items of the dependencies to `/<name>/<version>/<target>/` (see
+//! `doc.extern-map` in `cargo_args`), with the versions locked when the release was built. Those
+//! versions may never have been built by docs.rs, so each one is resolved to the nearest version
+//! with documentation when the release is uploaded, and the mapping is recorded here. The rustdoc
+//! handler redirects the links to the unbuilt versions with it.
+
+use failure::Error;
+use postgres::Client;
+use semver::Version;
+
+/// The version with documentation closest to `version`, among the non-yanked ones compatible with
+/// it. The lowest newer version is preferred, to keep the items the link points to.
+pub(crate) fn nearest_built_version(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<String>, Error> {
+    let wanted = match Version::parse(version) {
+        Ok(version) => version,
+        Err(_) => return Ok(None),
+    };
+    let mut built: Vec<Version> = conn
+        .query(
+            "SELECT releases.version
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.rustdoc_status AND NOT releases.yanked",
+            &[&name],
+        )?
+        .into_iter()
+        .filter_map(|row| Version::parse(row.get(0)).ok())
+        .filter(|candidate| is_compatible(candidate, &wanted))
+        .collect();
+    built.sort();
+
+    let newer = built.iter().find(|candidate| **candidate >= wanted);
+    Ok(newer.or_else(|| built.last()).map(|v| v.to_string()))
+}
+
+/// Whether `a` and `b` are compatible according to the caret requirements
+fn is_compatible(a: &Version, b: &Version) -> bool {
+    if a.major != b.major {
+        false
+    } else if a.major != 0 {
+        true
+    } else if a.minor != b.minor {
+        false
+    } else {
+        a.minor != 0 || a.patch == b.patch
+    }
+}
+
+/// Resolves the locked versions of the dependencies of a release and records where they're
+/// linked, replacing the previous mapping of the release
+pub(crate) fn record(
+    conn: &mut Client,
+    release_id: i32,
+    dependencies: &[(String, String)],
+) -> Result<(), Error> {
+    let mut resolved = Vec::with_capacity(dependencies.len());
+    for (name, version) in dependencies {
+        resolved.push((name, version, nearest_built_version(conn, name, version)?));
+    }
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM dependency_doc_links WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    for (name, version, linked_version) in resolved {
+        transaction.execute(
+            "INSERT INTO dependency_doc_links (release_id, name, version, linked_version)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT DO NOTHING",
+            &[&release_id, name, version, &linked_version],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The version the links to an unbuilt version of a dependency are redirected to, `None` if no
+/// documentation links to it or it has no replacement
+pub(crate) fn replacement(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<String>, Error> {
+    Ok(conn
+        .query_opt(
+            "SELECT linked_version
+             FROM dependency_doc_links
+             WHERE name = $1 AND version = $2 AND linked_version <> version
+             ORDER BY release_id DESC
+             LIMIT 1",
+            &[&name, &version],
+        )?
+        .map(|row| row.get(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn compatible_versions() {
+        let compatible =
+            |a, b| is_compatible(&Version::parse(a).unwrap(), &Version::parse(b).unwrap());
+        assert!(compatible("1.2.3", "1.0.0"));
+        assert!(!compatible("2.0.0", "1.0.0"));
+        assert!(compatible("0.2.3", "0.2.0"));
+        assert!(!compatible("0.3.0", "0.2.0"));
+        assert!(compatible("0.0.1", "0.0.1"));
+        assert!(!compatible("0.0.2", "0.0.1"));
+    }
+
+    #[test]
+    fn resolve_and_record() {
+        wrapper(|env| {
+            for version in &["1.0.0", "1.2.0", "1.3.0", "2.0.0"] {
+                env.fake_release().name("dep").version(version).create()?;
+            }
+            env.fake_release()
+                .name("dep")
+                .version("1.1.0")
+                .build_result_failed()
+                .create()?;
+            env.fake_release()
+                .name("dep")
+                .version("1.4.0")
+                .yanked(true)
+                .create()?;
+            let release_id = env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let mut conn = env.db().conn();
+            let nearest = |conn: &mut Client, version| nearest_built_version(conn, "dep", version);
+            assert_eq!(nearest(&mut conn, "1.2.0")?.as_deref(), Some("1.2.0"));
+            assert_eq!(nearest(&mut conn, "1.1.0")?.as_deref(), Some("1.2.0"));
+            assert_eq!(nearest(&mut conn, "1.4.0")?.as_deref(), Some("1.3.0"));
+            assert_eq!(nearest(&mut conn, "3.0.0")?, None);
+            assert_eq!(nearest_built_version(&mut conn, "missing", "1.0.0")?, None);
+
+            record(
+                &mut conn,
+                release_id,
+                &[
+                    ("dep".into(), "1.1.0".into()),
+                    ("dep".into(), "1.2.0".into()),
+                ],
+            )?;
+            assert_eq!(
+                replacement(&mut conn, "dep", "1.1.0")?.as_deref(),
+                Some("1.2.0")
+            );
+            // built versions aren't redirected
+            assert_eq!(replacement(&mut conn, "dep", "1.2.0")?, None);
+            assert_eq!(replacement(&mut conn, "dep", "1.5.0")?, None);
+
+            Ok(())
+        });
+    }
+}
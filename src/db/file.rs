@@ -8,7 +8,7 @@
 //! However, postgres is still available for testing and backwards compatibility.
 
 use crate::error::Result;
-use crate::storage::{CompressionAlgorithms, Storage};
+use crate::storage::{CompressionAlgorithms, Storage, StoredSize, UploadLimit};
 
 use serde_json::Value;
 use std::path::{Path, PathBuf};
@@ -21,16 +21,19 @@ use std::path::{Path, PathBuf};
 /// The mimetype is detected using `magic`.
 ///
 /// Note that this function is used for uploading both sources
-/// and files generated by rustdoc.
+/// and files generated by rustdoc. The size they take in the storage is returned with them, and
+/// nothing is stored if they are larger than the `limit`.
 pub fn add_path_into_database<P: AsRef<Path>>(
     storage: &Storage,
     prefix: impl AsRef<Path>,
     path: P,
-) -> Result<(Value, CompressionAlgorithms)> {
-    let (file_list, algorithms) = storage.store_all(prefix.as_ref(), path.as_ref())?;
+    limit: Option<UploadLimit>,
+) -> Result<(Value, CompressionAlgorithms, StoredSize)> {
+    let (file_list, algorithms, size) = storage.store_all(prefix.as_ref(), path.as_ref(), limit)?;
     Ok((
         file_list_to_json(file_list.into_iter().collect()),
         algorithms,
+        size,
     ))
 }
 
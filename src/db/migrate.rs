@@ -749,6 +749,635 @@ pub fn migrate(version: Option<Version>, conn: &mut Client) -> crate::error::Res
             "ALTER TABLE builds RENAME COLUMN cratesfyi_version TO docsrs_version",
             "ALTER TABLE builds RENAME COLUMN docsrs_version TO cratesfyi_version",
         ),
+        migration!(
+            context,
+            // version
+            30,
+            // description
+            "Add a table tracking the progress of bulk rebuilds",
+            // upgrade query
+            "
+            CREATE TABLE rebuild_runs (
+                since VARCHAR(100) NOT NULL PRIMARY KEY,
+                last_release_id INT NOT NULL DEFAULT 0,
+                enqueued INT NOT NULL DEFAULT 0,
+                started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                finished_at TIMESTAMPTZ
+            );
+            ",
+            // downgrade query
+            "DROP TABLE rebuild_runs;",
+        ),
+        migration!(
+            context,
+            // version
+            31,
+            // description
+            "Track uploads spilled to disk while the storage was unreachable",
+            // upgrade query
+            "
+            CREATE TABLE pending_uploads (
+                prefix VARCHAR(4096) NOT NULL PRIMARY KEY,
+                spill_path VARCHAR(4096) NOT NULL,
+                spilled_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            ",
+            // downgrade query
+            "DROP TABLE pending_uploads;",
+        ),
+        migration!(
+            context,
+            // version
+            32,
+            // description
+            "Add a disk quota to the sandbox and record the disk usage of builds",
+            // upgrade query
+            "
+            ALTER TABLE sandbox_overrides ADD COLUMN max_disk_bytes BIGINT;
+            ALTER TABLE builds
+                ADD COLUMN peak_disk_usage BIGINT,
+                ADD COLUMN failure_category VARCHAR(100);
+            ",
+            // downgrade query
+            "
+            ALTER TABLE sandbox_overrides DROP COLUMN max_disk_bytes;
+            ALTER TABLE builds
+                DROP COLUMN peak_disk_usage,
+                DROP COLUMN failure_category;
+            ",
+        ),
+        migration!(
+            context,
+            // version
+            33,
+            // description
+            "Store the citations found in CITATION.cff files",
+            // upgrade query
+            "
+            CREATE TABLE citations (
+                release_id INT NOT NULL UNIQUE REFERENCES releases(id),
+                data JSONB NOT NULL
+            );
+            ",
+            // downgrade query
+            "DROP TABLE citations;",
+        ),
+        migration!(
+            context,
+            // version
+            34,
+            // description
+            "Store the hash of the content of files",
+            // upgrade query
+            "ALTER TABLE files ADD COLUMN content_hash VARCHAR(64);",
+            // downgrade query
+            "ALTER TABLE files DROP COLUMN content_hash;",
+        ),
+        migration!(
+            context,
+            // version
+            35,
+            // description
+            "Record when the documentation of releases was last built successfully",
+            // upgrade query
+            "
+            ALTER TABLE releases ADD COLUMN last_build_time TIMESTAMPTZ;
+            UPDATE releases SET last_build_time = (
+                SELECT MAX(builds.build_time)
+                FROM builds
+                WHERE builds.rid = releases.id AND builds.build_status
+            );
+            ",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN last_build_time;",
+        ),
+        migration!(
+            context,
+            // version
+            36,
+            // description
+            "Record the files of releases missing from the storage",
+            // upgrade query
+            "
+            CREATE TABLE consistency_issues (
+                release_id INT NOT NULL REFERENCES releases(id),
+                path VARCHAR(4096) NOT NULL,
+                kind VARCHAR(100) NOT NULL,
+                detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (release_id, path)
+            );
+            ",
+            // downgrade query
+            "DROP TABLE consistency_issues;",
+        ),
+        migration!(
+            context,
+            // version
+            37,
+            // description
+            "Store the dependencies of releases in their own table",
+            // upgrade query
+            "
+            CREATE TABLE release_dependencies (
+                release_id INT NOT NULL REFERENCES releases(id),
+                name VARCHAR(255) NOT NULL,
+                req VARCHAR(255) NOT NULL,
+                kind VARCHAR(32) NOT NULL DEFAULT 'normal'
+            );
+            INSERT INTO release_dependencies (release_id, name, req, kind)
+                SELECT releases.id, dep->>0, dep->>1, COALESCE(dep->>2, 'normal')
+                FROM releases, json_array_elements(releases.dependencies) AS dep
+                WHERE json_typeof(releases.dependencies) = 'array'
+                    AND json_typeof(dep) = 'array'
+                    AND dep->>0 IS NOT NULL
+                    AND dep->>1 IS NOT NULL;
+            CREATE INDEX release_dependencies_name_idx ON release_dependencies (name);
+            CREATE INDEX release_dependencies_release_id_idx ON release_dependencies (release_id);
+            ",
+            // downgrade query
+            "DROP TABLE release_dependencies;",
+        ),
+        migration!(
+            context,
+            // version
+            38,
+            // description
+            "Add a trigram index to suggest crate names close to a misspelled one",
+            // upgrade query
+            "
+            CREATE EXTENSION IF NOT EXISTS pg_trgm;
+            CREATE INDEX crates_name_trgm_idx ON crates USING GIN (name gin_trgm_ops);
+            ",
+            // downgrade query
+            "DROP INDEX crates_name_trgm_idx;",
+        ),
+        migration!(
+            context,
+            // version
+            39,
+            // description
+            "Record the runs of the jobs scheduled by the daemon",
+            // upgrade query
+            "
+            CREATE TABLE scheduled_jobs (
+                name VARCHAR(255) PRIMARY KEY,
+                schedule VARCHAR(255) NOT NULL,
+                last_started TIMESTAMP WITH TIME ZONE,
+                last_finished TIMESTAMP WITH TIME ZONE,
+                last_success TIMESTAMP WITH TIME ZONE,
+                last_error TEXT
+            );
+            ",
+            // downgrade query
+            "DROP TABLE scheduled_jobs;",
+        ),
+        migration!(
+            context,
+            // version
+            40,
+            // description
+            "Store the subscribers of the WebSub hub of the releases feed",
+            // upgrade query
+            "
+            CREATE TABLE websub_subscriptions (
+                callback TEXT PRIMARY KEY,
+                secret TEXT,
+                expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                next_delivery TIMESTAMP WITH TIME ZONE,
+                failed_deliveries INT NOT NULL DEFAULT 0
+            );
+            ",
+            // downgrade query
+            "DROP TABLE websub_subscriptions;",
+        ),
+        migration!(
+            context,
+            // version
+            41,
+            // description
+            "Store the default branch and the archived status of the repositories",
+            // upgrade query
+            "
+            ALTER TABLE repositories
+                ADD COLUMN default_branch VARCHAR,
+                ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+            ",
+            // downgrade query
+            "
+            ALTER TABLE repositories
+                DROP COLUMN default_branch,
+                DROP COLUMN archived;
+            ",
+        ),
+        migration!(
+            context,
+            // version
+            42,
+            // description
+            "Record whether the documentation of builds is reproducible",
+            // upgrade query
+            "ALTER TABLE builds ADD COLUMN reproducible BOOLEAN;",
+            // downgrade query
+            "ALTER TABLE builds DROP COLUMN reproducible;",
+        ),
+        migration!(
+            context,
+            // version
+            43,
+            // description
+            "Track how many bytes the files of each release take in the storage",
+            // upgrade query
+            "
+            CREATE TABLE release_storage_stats (
+                release_id INT PRIMARY KEY REFERENCES releases(id) ON DELETE CASCADE,
+                rustdoc_compressed BIGINT NOT NULL,
+                rustdoc_uncompressed BIGINT NOT NULL,
+                sources_compressed BIGINT NOT NULL,
+                sources_uncompressed BIGINT NOT NULL,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            );
+            ",
+            // downgrade query
+            "DROP TABLE release_storage_stats;",
+        ),
+        migration!(
+            context,
+            // version
+            44,
+            // description
+            "Allow crates to upload more documentation than the default limit",
+            // upgrade query
+            "ALTER TABLE sandbox_overrides ADD COLUMN max_upload_bytes BIGINT;",
+            // downgrade query
+            "ALTER TABLE sandbox_overrides DROP COLUMN max_upload_bytes;",
+        ),
+        migration!(
+            context,
+            // version
+            45,
+            // description
+            "Allow crates to generate more documentation files than the default limit",
+            // upgrade query
+            "ALTER TABLE sandbox_overrides ADD COLUMN max_files INT;",
+            // downgrade query
+            "ALTER TABLE sandbox_overrides DROP COLUMN max_files;",
+        ),
+        migration!(
+            context,
+            // version
+            46,
+            // description
+            "Record the batches of files uploaded to the storage to resume interrupted uploads",
+            // upgrade query
+            "
+            CREATE TABLE upload_journal (
+                prefix VARCHAR(4096) NOT NULL,
+                batch INT NOT NULL,
+                digest VARCHAR(64) NOT NULL,
+                uploaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (prefix, batch)
+            );
+            ",
+            // downgrade query
+            "DROP TABLE upload_journal;",
+        ),
+        migration!(
+            context,
+            // version
+            47,
+            // description
+            "Count the releases and build failures of every day as the releases are added",
+            // upgrade query
+            "
+            CREATE TABLE release_activity (
+                date DATE PRIMARY KEY,
+                releases BIGINT NOT NULL,
+                failures BIGINT NOT NULL
+            );
+            INSERT INTO release_activity (date, releases, failures)
+                SELECT
+                    release_time::date,
+                    COUNT(*),
+                    SUM(CAST((is_library = TRUE AND build_status = FALSE) AS INT))
+                FROM releases
+                GROUP BY release_time::date;
+            ",
+            // downgrade query
+            "DROP TABLE release_activity;",
+        ),
+        migration!(
+            context,
+            // version
+            48,
+            // description
+            "Log the administrative operations deleting crates or changing their builds",
+            // upgrade query
+            "
+            CREATE TABLE audit_log (
+                id SERIAL PRIMARY KEY,
+                actor TEXT NOT NULL,
+                action TEXT NOT NULL,
+                target TEXT NOT NULL,
+                reason TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            ",
+            // downgrade query
+            "DROP TABLE audit_log;",
+        ),
+        migration!(
+            context,
+            // version
+            49,
+            // description
+            "Record the limits of the sandbox every build ran under",
+            // upgrade query
+            "ALTER TABLE builds ADD COLUMN limits JSONB;",
+            // downgrade query
+            "ALTER TABLE builds DROP COLUMN limits;",
+        ),
+        migration!(
+            context,
+            // version
+            50,
+            // description
+            "Store the warnings about the configuration of the crates with their builds",
+            // upgrade query
+            "ALTER TABLE builds ADD COLUMN warnings TEXT[] NOT NULL DEFAULT '{}';",
+            // downgrade query
+            "ALTER TABLE builds DROP COLUMN warnings;",
+        ),
+        migration!(
+            context,
+            // version
+            51,
+            // description
+            "Store the warnings and errors rustdoc emitted during the builds",
+            // upgrade query
+            "
+            ALTER TABLE builds ADD CONSTRAINT builds_id_key UNIQUE (id);
+            CREATE TABLE build_diagnostics (
+                id SERIAL PRIMARY KEY,
+                build_id INT NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+                level TEXT NOT NULL,
+                lint TEXT,
+                file TEXT NOT NULL,
+                line INT NOT NULL,
+                message TEXT NOT NULL
+            );
+            CREATE INDEX build_diagnostics_build_id_idx ON build_diagnostics (build_id);
+            ",
+            // downgrade query
+            "
+            DROP TABLE build_diagnostics;
+            ALTER TABLE builds DROP CONSTRAINT builds_id_key;
+            ",
+        ),
+        migration!(
+            context,
+            // version
+            52,
+            // description
+            "Break down the size of the documentation of each release by kind of file",
+            // upgrade query
+            "
+            CREATE TABLE doc_sizes (
+                release_id INT PRIMARY KEY REFERENCES releases(id) ON DELETE CASCADE,
+                html BIGINT NOT NULL,
+                src BIGINT NOT NULL,
+                implementors BIGINT NOT NULL,
+                search_index BIGINT NOT NULL,
+                other BIGINT NOT NULL,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+            );
+            ",
+            // downgrade query
+            "DROP TABLE doc_sizes;",
+        ),
+        migration!(
+            context,
+            // version
+            53,
+            // description
+            "Record the releases whose documentation doesn't include the source pages",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN no_source BOOLEAN NOT NULL DEFAULT FALSE;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN no_source;",
+        ),
+        migration!(
+            context,
+            // version
+            54,
+            // description
+            "Record the pre-releases retired by the retention policy",
+            // upgrade query
+            "
+            CREATE TABLE retired_releases (
+                crate_name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                superseded_by TEXT NOT NULL,
+                policy TEXT NOT NULL,
+                retired_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (crate_name, version)
+            );
+            ",
+            // downgrade query
+            "DROP TABLE retired_releases;",
+        ),
+        migration!(
+            context,
+            // version
+            55,
+            // description
+            "Add the redirects of the renamed crates",
+            // upgrade query
+            "
+            CREATE TABLE crate_redirects (
+                old_name TEXT PRIMARY KEY,
+                new_name TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            ",
+            // downgrade query
+            "DROP TABLE crate_redirects;",
+        ),
+        migration!(
+            context,
+            // version
+            56,
+            // description
+            "Record the hashes of the shared rustdoc assets for subresource integrity",
+            // upgrade query
+            "
+            CREATE TABLE shared_asset_integrity (
+                path TEXT PRIMARY KEY,
+                integrity TEXT NOT NULL
+            );
+            ",
+            // downgrade query
+            "DROP TABLE shared_asset_integrity;",
+        ),
+        migration!(
+            context,
+            // version
+            57,
+            // description
+            "Store the security advisories of the RustSec advisory database",
+            // upgrade query
+            "
+            CREATE TABLE advisories (
+                id TEXT PRIMARY KEY,
+                crate_name TEXT NOT NULL,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL,
+                date DATE NOT NULL,
+                informational TEXT,
+                patched TEXT[] NOT NULL,
+                unaffected TEXT[] NOT NULL
+            );
+            CREATE INDEX advisories_crate_name_idx ON advisories (crate_name);
+            ",
+            // downgrade query
+            "DROP TABLE advisories;",
+        ),
+        migration!(
+            context,
+            // version
+            58,
+            // description
+            "Store the minimum supported Rust version of the releases",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN rust_version TEXT;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN rust_version;",
+        ),
+        migration!(
+            context,
+            // version
+            59,
+            // description
+            "Record the versions of the dependencies linked from the documentation",
+            // upgrade query
+            "
+            CREATE TABLE dependency_doc_links (
+                release_id INT NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                linked_version TEXT,
+                PRIMARY KEY (release_id, name, version)
+            );
+            CREATE INDEX dependency_doc_links_name_version_idx
+                ON dependency_doc_links (name, version);
+            ",
+            // downgrade query
+            "DROP TABLE dependency_doc_links;",
+        ),
+        migration!(
+            context,
+            // version
+            60,
+            // description
+            "Count the views of the documentation of every release by day",
+            // upgrade query
+            "
+            CREATE TABLE page_views (
+                release_id INT NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+                date DATE NOT NULL,
+                views INT NOT NULL,
+                PRIMARY KEY (release_id, date)
+            );
+            ",
+            // downgrade query
+            "DROP TABLE page_views;",
+        ),
+        migration!(
+            context,
+            // version
+            61,
+            // description
+            "Keep the log of the builds in progress",
+            // upgrade query
+            "
+            CREATE UNLOGGED TABLE build_progress (
+                name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                target TEXT NOT NULL,
+                log TEXT NOT NULL,
+                started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (name, version)
+            );
+            ",
+            // downgrade query
+            "DROP TABLE build_progress;",
+        ),
+        migration!(
+            context,
+            // version
+            62,
+            // description
+            "Queue the WebSub subscription requests and record the host of the callbacks",
+            // upgrade query
+            "
+            ALTER TABLE websub_subscriptions ADD COLUMN host TEXT;
+            UPDATE websub_subscriptions
+                SET host = substring(callback from '^[a-z]+://(?:[^/?#@]*@)?(\\[[^]]*\\]|[^/?#:]*)');
+            ALTER TABLE websub_subscriptions ALTER COLUMN host SET NOT NULL;
+            CREATE INDEX websub_subscriptions_host_idx ON websub_subscriptions (host);
+
+            CREATE TABLE websub_verifications (
+                callback TEXT PRIMARY KEY,
+                host TEXT NOT NULL,
+                subscribe BOOLEAN NOT NULL,
+                secret TEXT,
+                lease_seconds INT NOT NULL
+            );
+            CREATE INDEX websub_verifications_host_idx ON websub_verifications (host);
+            ",
+            // downgrade query
+            "
+            DROP TABLE websub_verifications;
+            ALTER TABLE websub_subscriptions DROP COLUMN host;
+            ",
+        ),
+        migration!(
+            context,
+            // version
+            63,
+            // description
+            "Keep the recent events of the build queue for the pages polling them",
+            // upgrade query
+            "
+            CREATE UNLOGGED TABLE queue_events (
+                id BIGSERIAL PRIMARY KEY,
+                event JSONB NOT NULL,
+                published_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            CREATE INDEX queue_events_published_at_idx ON queue_events (published_at);
+            ",
+            // downgrade query
+            "DROP TABLE queue_events;",
+        ),
+        migration!(
+            context,
+            // version
+            64,
+            // description
+            "Only allow one redirect per normalized crate name",
+            // upgrade query
+            "
+            DELETE FROM crate_redirects AS older
+                USING crate_redirects AS newer
+                WHERE normalize_crate_name(older.old_name) = normalize_crate_name(newer.old_name)
+                    AND (older.created_at, older.old_name) < (newer.created_at, newer.old_name);
+            CREATE UNIQUE INDEX crate_redirects_normalized_old_name_idx
+                ON crate_redirects (normalize_crate_name(old_name));
+            ",
+            // downgrade query
+            "DROP INDEX crate_redirects_normalized_old_name_idx;",
+        ),
     ];
 
     for migration in migrations {
@@ -2,17 +2,26 @@
 
 pub use self::add_package::update_crate_data_in_database;
 pub(crate) use self::add_package::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_build_into_database, add_citation, add_doc_coverage, add_package_into_database,
 };
-pub use self::delete::{delete_crate, delete_version};
+pub use self::delete::{archive_version, delete_crate, delete_version};
 pub use self::file::add_path_into_database;
 pub use self::migrate::migrate;
 pub use self::pool::{Pool, PoolClient, PoolError};
 
 mod add_package;
+pub mod advisories;
+pub mod audit;
 pub mod blacklist;
+pub(crate) mod build_progress;
+pub mod crate_redirects;
 mod delete;
+pub(crate) mod dependency_links;
 pub(crate) mod file;
 mod migrate;
+pub(crate) mod page_views;
 mod pool;
+pub(crate) mod queries;
+mod release_activity;
+pub mod sandbox_overrides;
 pub(crate) mod types;
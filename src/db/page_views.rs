@@ -0,0 +1,379 @@
+//! Anonymous counts of the views of the documentation
+//!
+//! When `DOCSRS_PAGE_VIEW_STATS` is set, every rustdoc page served increments the count of its
+//! release for the day. Nothing else about the request is kept, and the requests sent with
+//! `DNT: 1` aren't counted. The counts are aggregated in memory and added to the `page_views`
+//! table every `DOCSRS_PAGE_VIEW_FLUSH_INTERVAL` seconds by a background thread, so that the
+//! requests never wait for it.
+
+use super::queries::{LatestRelease, PreparedStatements};
+use super::Pool;
+use chrono::{NaiveDate, Utc};
+use dashmap::DashMap;
+use failure::Error;
+use postgres::Client;
+use serde::Serialize;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How many days the crate pages and the API show
+pub(crate) const RECENT_DAYS: i32 = 30;
+/// The views a crate needs in the last week to be trending, below it a handful of visits would be
+/// enough to rank the crates without any traffic before
+const TRENDING_MIN_VIEWS: i64 = 10;
+/// The releases and days counted in memory at most, the views of the others are dropped until the
+/// next write
+const MAX_PENDING: usize = 100_000;
+
+/// The views not written to the database yet, by release and day
+type Pending = DashMap<(i32, NaiveDate), i32>;
+
+#[derive(Debug)]
+pub(crate) struct PageViews {
+    pending: Arc<Pending>,
+    /// Dropped to stop the thread writing the views, after a last write
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PageViews {
+    pub(crate) fn new(pool: Pool, flush_interval: Duration) -> Self {
+        let pending = Arc::new(Pending::new());
+        let (stop, stopped) = channel();
+
+        let shared = pending.clone();
+        let thread = std::thread::Builder::new()
+            .name("page views".into())
+            .spawn(move || loop {
+                let stopping = !matches!(
+                    stopped.recv_timeout(flush_interval),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                let result = pool
+                    .get()
+                    .map_err(Error::from)
+                    .and_then(|mut conn| flush(&shared, &mut conn));
+                if let Err(err) = result {
+                    log::error!("failed to record the page views: {}", err);
+                }
+                if stopping {
+                    return;
+                }
+            });
+        let thread = match thread {
+            Ok(thread) => Some(thread),
+            Err(err) => {
+                log::error!("failed to start recording the page views: {}", err);
+                None
+            }
+        };
+
+        Self {
+            pending,
+            stop: Some(stop),
+            thread,
+        }
+    }
+
+    /// Counts a view of the documentation of a release, it's written to the database with the
+    /// next flush
+    pub(crate) fn record(&self, release_id: i32) {
+        if self.pending.len() < MAX_PENDING {
+            let today = Utc::now().date().naive_utc();
+            *self.pending.entry((release_id, today)).or_insert(0) += 1;
+        }
+    }
+}
+
+impl Drop for PageViews {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Adds the pending counts to the database with a single statement. They're kept for the next
+/// flush when it fails.
+fn flush(pending: &Pending, conn: &mut Client) -> Result<(), Error> {
+    let keys: Vec<_> = pending.iter().map(|entry| *entry.key()).collect();
+    let views: Vec<_> = keys.iter().filter_map(|key| pending.remove(key)).collect();
+    if views.is_empty() {
+        return Ok(());
+    }
+
+    let release_ids: Vec<i32> = views.iter().map(|((id, _), _)| *id).collect();
+    let dates: Vec<NaiveDate> = views.iter().map(|((_, date), _)| *date).collect();
+    let counts: Vec<i32> = views.iter().map(|(_, views)| *views).collect();
+    // the releases might have been deleted since they were viewed
+    let result = conn.execute(
+        "INSERT INTO page_views (release_id, date, views)
+         SELECT pending.release_id, pending.date, pending.views
+         FROM UNNEST($1::INT[], $2::DATE[], $3::INT[]) AS pending (release_id, date, views)
+         WHERE EXISTS (SELECT 1 FROM releases WHERE id = pending.release_id)
+         ON CONFLICT (release_id, date) DO UPDATE
+             SET views = page_views.views + EXCLUDED.views",
+        &[&release_ids, &dates, &counts],
+    );
+    if let Err(err) = result {
+        for (key, views) in views {
+            *pending.entry(key).or_insert(0) += views;
+        }
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct RecentViews {
+    /// The views of all the releases of the crate in the last `RECENT_DAYS` days
+    pub(crate) total: i64,
+    /// The days with views, the oldest first
+    pub(crate) days: Vec<DailyViews>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct DailyViews {
+    pub(crate) date: NaiveDate,
+    pub(crate) views: i64,
+}
+
+/// The views of the documentation of a crate in the last `RECENT_DAYS` days, today included
+pub(crate) fn recent_views(conn: &mut Client, crate_id: i32) -> Result<RecentViews, Error> {
+    let days: Vec<DailyViews> = conn
+        .query(
+            "SELECT page_views.date, SUM(page_views.views)::BIGINT
+             FROM page_views
+             INNER JOIN releases ON releases.id = page_views.release_id
+             WHERE releases.crate_id = $1 AND page_views.date > CURRENT_DATE - $2::INT
+             GROUP BY page_views.date
+             ORDER BY page_views.date",
+            &[&crate_id, &RECENT_DAYS],
+        )?
+        .into_iter()
+        .map(|row| DailyViews {
+            date: row.get(0),
+            views: row.get(1),
+        })
+        .collect();
+
+    Ok(RecentViews {
+        total: days.iter().map(|day| day.views).sum(),
+        days,
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ViewsRanking {
+    /// The most views in the last `RECENT_DAYS` days
+    Popular,
+    /// The most views in the last week compared to the three weeks before
+    Trending,
+}
+
+/// The latest releases of the `limit` crates ranked first by the views of their documentation
+pub(crate) fn most_viewed(
+    conn: &mut impl PreparedStatements,
+    ranking: ViewsRanking,
+    limit: i64,
+) -> Result<Vec<LatestRelease>, postgres::Error> {
+    macro_rules! most_viewed_query {
+        ($views:literal, $score:literal) => {
+            concat!(
+                "WITH views AS (",
+                $views,
+                ")
+                SELECT releases.id,
+                    crates.name,
+                    releases.version,
+                    releases.description,
+                    releases.target_name,
+                    releases.release_time,
+                    releases.rustdoc_status,
+                    repositories.stars
+                FROM views
+                INNER JOIN crates ON crates.id = views.crate_id
+                INNER JOIN releases ON crates.latest_version_id = releases.id
+                LEFT JOIN repositories ON releases.repository_id = repositories.id
+                ORDER BY ",
+                $score,
+                " DESC, crates.name
+                LIMIT $1"
+            )
+        };
+    }
+
+    let rows = match ranking {
+        ViewsRanking::Popular => {
+            let statement = conn.prepare_cached(most_viewed_query!(
+                "SELECT releases.crate_id, SUM(page_views.views) AS views
+                 FROM page_views
+                 INNER JOIN releases ON releases.id = page_views.release_id
+                 WHERE page_views.date > CURRENT_DATE - $2::INT
+                 GROUP BY releases.crate_id",
+                "views.views"
+            ))?;
+            conn.client().query(&statement, &[&limit, &RECENT_DAYS])?
+        }
+        // the views of the last week are divided by the weekly average of the three weeks before,
+        // smoothed so that going from 1 to 10 views isn't worth more than from 1000 to 5000
+        ViewsRanking::Trending => {
+            let statement = conn.prepare_cached(most_viewed_query!(
+                "SELECT releases.crate_id,
+                     COALESCE(SUM(page_views.views) FILTER (
+                         WHERE page_views.date > CURRENT_DATE - 7
+                     ), 0) AS recent,
+                     COALESCE(SUM(page_views.views) FILTER (
+                         WHERE page_views.date <= CURRENT_DATE - 7
+                     ), 0) AS previous
+                 FROM page_views
+                 INNER JOIN releases ON releases.id = page_views.release_id
+                 WHERE page_views.date > CURRENT_DATE - 28
+                 GROUP BY releases.crate_id
+                 HAVING COALESCE(SUM(page_views.views) FILTER (
+                     WHERE page_views.date > CURRENT_DATE - 7
+                 ), 0) >= $2",
+                "views.recent::FLOAT8 / (views.previous::FLOAT8 / 3 + 100)"
+            ))?;
+            conn.client()
+                .query(&statement, &[&limit, &TRENDING_MIN_VIEWS])?
+        }
+    };
+
+    Ok(rows
+        .into_iter()
+        .map(|row| LatestRelease {
+            id: row.get(0),
+            name: row.get(1),
+            version: row.get(2),
+            description: row.get(3),
+            target_name: row.get(4),
+            release_time: row.get(5),
+            rustdoc_status: row.get(6),
+            stars: row.get::<_, Option<i32>>(7).unwrap_or(0),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn count_views() {
+        wrapper(|env| {
+            let foo_1 = env.fake_release().name("foo").version("0.1.0").create()?;
+            let foo_2 = env.fake_release().name("foo").version("0.2.0").create()?;
+            let bar = env.fake_release().name("bar").version("0.1.0").create()?;
+
+            let mut conn = env.db().conn();
+            let crate_id = |conn: &mut Client, name: &str| -> Result<i32, Error> {
+                Ok(conn
+                    .query_one("SELECT id FROM crates WHERE name = $1", &[&name])?
+                    .get(0))
+            };
+            let foo = crate_id(&mut conn, "foo")?;
+
+            // the views are kept in memory until they're due
+            let page_views = PageViews::new(env.db().pool(), Duration::from_secs(60 * 60));
+            page_views.record(foo_1);
+            assert_eq!(recent_views(&mut conn, foo)?, RecentViews::default());
+
+            page_views.record(foo_1);
+            page_views.record(foo_2);
+            page_views.record(bar);
+            flush(&page_views.pending, &mut conn)?;
+            // old views aren't recent anymore
+            conn.execute(
+                "INSERT INTO page_views (release_id, date, views)
+                 VALUES ($1, CURRENT_DATE - 30, 100)",
+                &[&foo_1],
+            )?;
+
+            let today = Utc::now().date().naive_utc();
+            assert_eq!(
+                recent_views(&mut conn, foo)?,
+                RecentViews {
+                    total: 3,
+                    days: vec![DailyViews {
+                        date: today,
+                        views: 3
+                    }],
+                }
+            );
+
+            // the counts that couldn't be written are kept for the next flush
+            page_views.record(bar);
+            conn.execute("ALTER TABLE page_views RENAME TO page_views_moved", &[])?;
+            assert!(flush(&page_views.pending, &mut conn).is_err());
+            conn.execute("ALTER TABLE page_views_moved RENAME TO page_views", &[])?;
+
+            // the counts are added to the ones of the same day, the last ones are written when
+            // the counter is dropped
+            page_views.record(bar);
+            drop(page_views);
+            let bar = crate_id(&mut conn, "bar")?;
+            assert_eq!(recent_views(&mut conn, bar)?.total, 3);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rankings() {
+        wrapper(|env| {
+            let steady = env
+                .fake_release()
+                .name("steady")
+                .version("0.1.0")
+                .create()?;
+            let rising = env
+                .fake_release()
+                .name("rising")
+                .version("0.1.0")
+                .create()?;
+            let quiet = env.fake_release().name("quiet").version("0.1.0").create()?;
+            env.fake_release()
+                .name("unseen")
+                .version("0.1.0")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            for (release_id, days_ago, views) in &[
+                (steady, 14, 3000),
+                (steady, 1, 1000),
+                (rising, 1, 200),
+                (quiet, 1, 5),
+            ] {
+                conn.execute(
+                    "INSERT INTO page_views (release_id, date, views)
+                     VALUES ($1, CURRENT_DATE - $2::INT, $3)",
+                    &[release_id, days_ago, views],
+                )?;
+            }
+
+            let names = |conn: &mut Client, ranking| -> Result<Vec<String>, Error> {
+                Ok(most_viewed(conn, ranking, 10)?
+                    .into_iter()
+                    .map(|release| release.name)
+                    .collect())
+            };
+            assert_eq!(
+                names(&mut conn, ViewsRanking::Popular)?,
+                vec!["steady", "rising", "quiet"]
+            );
+            // too few views to be trending
+            assert_eq!(
+                names(&mut conn, ViewsRanking::Trending)?,
+                vec!["rising", "steady"]
+            );
+            assert_eq!(most_viewed(&mut conn, ViewsRanking::Popular, 1)?.len(), 1);
+
+            Ok(())
+        });
+    }
+}
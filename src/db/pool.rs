@@ -2,12 +2,70 @@ use crate::metrics::Metrics;
 use crate::Config;
 use postgres::{Client, NoTls};
 use r2d2_postgres::PostgresConnectionManager;
-use std::sync::Arc;
-
-pub type PoolClient = r2d2::PooledConnection<PostgresConnectionManager<NoTls>>;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const DEFAULT_SCHEMA: &str = "public";
 
+/// A connection taken out of the pool, which goes back to it when dropped
+pub struct PoolClient {
+    conn: r2d2::PooledConnection<PostgresConnectionManager<NoTls>>,
+    checkouts: Arc<Mutex<Checkouts>>,
+    id: u64,
+}
+
+impl PoolClient {
+    /// Data attached to the connection, kept along with it while it's in the pool
+    pub(crate) fn extensions(&mut self) -> &mut r2d2::Extensions {
+        r2d2::PooledConnection::extensions_mut(&mut self.conn)
+    }
+}
+
+impl Deref for PoolClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.conn
+    }
+}
+
+impl DerefMut for PoolClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.conn
+    }
+}
+
+impl Drop for PoolClient {
+    fn drop(&mut self) {
+        let checkout = self.checkouts.lock().unwrap().held.remove(&self.id);
+        if let Some(checkout) = checkout.filter(|checkout| checkout.reported) {
+            log::warn!(
+                "the database connection acquired at {} was released after {:?}",
+                checkout.location,
+                checkout.acquired_at.elapsed()
+            );
+        }
+    }
+}
+
+/// Where and when a connection was taken out of the pool
+#[derive(Debug)]
+struct Checkout {
+    location: &'static Location<'static>,
+    acquired_at: Instant,
+    /// Whether it was already logged as held for too long
+    reported: bool,
+}
+
+#[derive(Debug, Default)]
+struct Checkouts {
+    next_id: u64,
+    held: HashMap<u64, Checkout>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Pool {
     #[cfg(test)]
@@ -16,6 +74,10 @@ pub struct Pool {
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
     metrics: Arc<Metrics>,
     max_size: u32,
+    checkouts: Arc<Mutex<Checkouts>>,
+    hold_deadline: Duration,
+    /// The connections to the read replica, if one is configured
+    replica: Option<Arc<Pool>>,
 }
 
 impl Pool {
@@ -23,7 +85,7 @@ impl Pool {
         Self::new_inner(config, metrics, DEFAULT_SCHEMA)
     }
 
-    #[cfg(test)]
+    /// Creates a pool whose connections use the tables of `schema` before the default ones
     pub(crate) fn new_with_schema(
         config: &Config,
         metrics: Arc<Metrics>,
@@ -33,10 +95,27 @@ impl Pool {
     }
 
     fn new_inner(config: &Config, metrics: Arc<Metrics>, schema: &str) -> Result<Pool, PoolError> {
-        let url = config
-            .database_url
-            .parse()
-            .map_err(PoolError::InvalidDatabaseUrl)?;
+        let replica = match &config.database_replica_url {
+            Some(url) => Some(Arc::new(Self::connect(
+                url,
+                config,
+                metrics.clone(),
+                schema,
+                None,
+            )?)),
+            None => None,
+        };
+        Self::connect(&config.database_url, config, metrics, schema, replica)
+    }
+
+    fn connect(
+        url: &str,
+        config: &Config,
+        metrics: Arc<Metrics>,
+        schema: &str,
+        replica: Option<Arc<Pool>>,
+    ) -> Result<Pool, PoolError> {
+        let url = url.parse().map_err(PoolError::InvalidDatabaseUrl)?;
         let manager = PostgresConnectionManager::new(url, NoTls);
         let pool = r2d2::Pool::builder()
             .max_size(config.max_pool_size)
@@ -52,6 +131,9 @@ impl Pool {
             pool,
             metrics,
             max_size: config.max_pool_size,
+            checkouts: Arc::new(Mutex::new(Checkouts::default())),
+            hold_deadline: Duration::from_secs(config.db_connection_hold_deadline),
+            replica,
         })
     }
 
@@ -69,9 +151,36 @@ impl Pool {
         }
     }
 
+    /// The caller is recorded as the owner of the connection, and logged if it holds it longer
+    /// than `DOCSRS_DB_CONNECTION_HOLD_DEADLINE`.
+    #[track_caller]
     pub fn get(&self) -> Result<PoolClient, PoolError> {
-        match self.with_pool(|p| p.get()) {
-            Ok(conn) => Ok(conn),
+        let location = Location::caller();
+        let start = Instant::now();
+        let res = self.with_pool(|p| p.get());
+        self.metrics
+            .db_connection_wait_times
+            .observe(start.elapsed().as_secs_f64());
+
+        match res {
+            Ok(conn) => {
+                let mut checkouts = self.checkouts.lock().unwrap();
+                let id = checkouts.next_id;
+                checkouts.next_id += 1;
+                checkouts.held.insert(
+                    id,
+                    Checkout {
+                        location,
+                        acquired_at: Instant::now(),
+                        reported: false,
+                    },
+                );
+                Ok(PoolClient {
+                    conn,
+                    checkouts: self.checkouts.clone(),
+                    id,
+                })
+            }
             Err(err) => {
                 self.metrics.failed_db_connections.inc();
                 Err(PoolError::ClientError(err))
@@ -79,6 +188,40 @@ impl Pool {
         }
     }
 
+    /// Returns a connection to the read replica, or to the primary database when no replica is
+    /// configured. The replica can lag behind the primary, it must only be used by the pages that
+    /// don't need to see the latest writes, and never to write.
+    #[track_caller]
+    pub fn read(&self) -> Result<PoolClient, PoolError> {
+        match &self.replica {
+            Some(replica) => replica.get(),
+            None => self.get(),
+        }
+    }
+
+    /// Logs the connections held for longer than the deadline, once for each of them, and
+    /// returns how many were logged
+    pub(crate) fn report_long_held_connections(&self) -> usize {
+        let mut checkouts = self.checkouts.lock().unwrap();
+        let mut reported = 0;
+        for checkout in checkouts.held.values_mut() {
+            if checkout.reported || checkout.acquired_at.elapsed() < self.hold_deadline {
+                continue;
+            }
+            log::warn!(
+                "the database connection acquired at {} has been held for {:?}",
+                checkout.location,
+                checkout.acquired_at.elapsed()
+            );
+            checkout.reported = true;
+            reported += 1;
+        }
+        self.metrics
+            .long_held_db_connections
+            .inc_by(reported as i64);
+        reported
+    }
+
     pub(crate) fn used_connections(&self) -> u32 {
         self.with_pool(|p| p.state().connections - p.state().idle_connections)
     }
@@ -94,6 +237,9 @@ impl Pool {
     #[cfg(test)]
     pub(crate) fn shutdown(&self) {
         self.pool.lock().unwrap().take();
+        if let Some(replica) = &self.replica {
+            replica.shutdown();
+        }
     }
 }
 
@@ -133,3 +279,53 @@ pub enum PoolError {
     #[fail(display = "failed to get a database connection")]
     ClientError(#[fail(cause)] r2d2::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+
+    #[test]
+    fn long_held_connections() {
+        wrapper(|env| {
+            env.override_config(|config| config.db_connection_hold_deadline = 0);
+            let pool = env.db().pool();
+            let wait_times = env.metrics().db_connection_wait_times.get_sample_count();
+
+            let conn = pool.get()?;
+            assert_eq!(
+                env.metrics().db_connection_wait_times.get_sample_count(),
+                wait_times + 1
+            );
+            assert_eq!(pool.report_long_held_connections(), 1);
+            // every connection is only reported once
+            assert_eq!(pool.report_long_held_connections(), 0);
+            assert_eq!(env.metrics().long_held_db_connections.get(), 1);
+
+            drop(conn);
+            let _conn = pool.get()?;
+            assert_eq!(pool.report_long_held_connections(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn read_replica() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.database_replica_url = Some(config.database_url.clone())
+            });
+            let pool = env.db().pool();
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let used_connections = pool.used_connections();
+            let mut conn = pool.read()?;
+            // the connection comes from the pool of the replica
+            assert_eq!(pool.used_connections(), used_connections);
+            let count: i64 = conn.query_one("SELECT COUNT(*) FROM releases", &[])?.get(0);
+            assert_eq!(count, 1);
+
+            Ok(())
+        });
+    }
+}
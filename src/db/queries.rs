@@ -0,0 +1,589 @@
+//! Typed queries of the pages served the most.
+//!
+//! Postgres parses and plans every query sent as a string. The statements of these queries are
+//! instead prepared once per pooled connection, and reused by all the requests served with it.
+
+use crate::db::PoolClient;
+use chrono::{DateTime, TimeZone, Utc};
+use postgres::{Client, Row, Statement};
+use std::{collections::HashMap, convert::TryInto, fmt};
+
+/// A connection able to prepare statements
+pub trait PreparedStatements {
+    fn client(&mut self) -> &mut Client;
+
+    /// Prepares `query`, reusing the statement when it was already prepared on this connection
+    fn prepare_cached(&mut self, query: &'static str) -> Result<Statement, postgres::Error>;
+}
+
+/// Connections outside of the pool don't live long enough to cache their statements
+impl PreparedStatements for Client {
+    fn client(&mut self) -> &mut Client {
+        self
+    }
+
+    fn prepare_cached(&mut self, query: &'static str) -> Result<Statement, postgres::Error> {
+        self.prepare(query)
+    }
+}
+
+/// The statements prepared on a pooled connection, kept along with it when it goes back to the
+/// pool
+#[derive(Default)]
+struct StatementCache(HashMap<&'static str, Statement>);
+
+impl PreparedStatements for PoolClient {
+    fn client(&mut self) -> &mut Client {
+        self
+    }
+
+    fn prepare_cached(&mut self, query: &'static str) -> Result<Statement, postgres::Error> {
+        let cached = self
+            .extensions()
+            .get::<StatementCache>()
+            .and_then(|cache| cache.0.get(query))
+            .cloned();
+        if let Some(statement) = cached {
+            return Ok(statement);
+        }
+
+        let statement = self.prepare(query)?;
+        let extensions = self.extensions();
+        if extensions.get::<StatementCache>().is_none() {
+            extensions.insert(StatementCache::default());
+        }
+        extensions
+            .get_mut::<StatementCache>()
+            .unwrap()
+            .0
+            .insert(query, statement.clone());
+        Ok(statement)
+    }
+}
+
+/// A release of a crate whose name matches the requested one, with dashes and underscores being
+/// interchangeable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MatchingRelease {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) id: i32,
+    pub(crate) yanked: bool,
+}
+
+pub(crate) fn releases_matching_name(
+    conn: &mut impl PreparedStatements,
+    name: &str,
+) -> Result<Vec<MatchingRelease>, postgres::Error> {
+    let statement = conn.prepare_cached(
+        "SELECT name, version, releases.id, releases.yanked
+         FROM releases INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE normalize_crate_name(name) = normalize_crate_name($1)",
+    )?;
+    Ok(conn
+        .client()
+        .query(&statement, &[&name])?
+        .into_iter()
+        .map(|row| MatchingRelease {
+            name: row.get(0),
+            version: row.get(1),
+            id: row.get(2),
+            yanked: row.get(3),
+        })
+        .collect())
+}
+
+/// The details of a release shown on its pages, `None` if it doesn't exist
+pub(crate) fn release_details(
+    conn: &mut impl PreparedStatements,
+    name: &str,
+    version: &str,
+) -> Result<Option<Row>, postgres::Error> {
+    let statement = conn.prepare_cached(
+        "SELECT
+            crates.id AS crate_id,
+            releases.id AS release_id,
+            crates.name,
+            releases.version,
+            releases.description,
+            releases.dependencies,
+            releases.readme,
+            releases.description_long,
+            releases.release_time,
+            releases.last_build_time,
+            releases.build_status,
+            releases.rustdoc_status,
+            releases.repository_url,
+            releases.homepage_url,
+            releases.keywords,
+            releases.have_examples,
+            releases.target_name,
+            repositories.host as repo_host,
+            repositories.stars as repo_stars,
+            repositories.forks as repo_forks,
+            repositories.issues as repo_issues,
+            repositories.name as repo_name,
+            repositories.last_commit as repo_last_commit,
+            repositories.default_branch as repo_default_branch,
+            repositories.archived as repo_archived,
+            releases.is_library,
+            releases.yanked,
+            releases.doc_targets,
+            releases.license,
+            releases.documentation_url,
+            releases.default_target,
+            releases.no_source,
+            releases.rust_version,
+            doc_coverage.total_items,
+            doc_coverage.documented_items,
+            doc_coverage.total_items_needing_examples,
+            doc_coverage.items_with_examples,
+            citations.data AS citation,
+            doc_sizes.html AS doc_size_html,
+            doc_sizes.src AS doc_size_src,
+            doc_sizes.implementors AS doc_size_implementors,
+            doc_sizes.search_index AS doc_size_search_index,
+            doc_sizes.other AS doc_size_other
+        FROM releases
+        INNER JOIN crates ON releases.crate_id = crates.id
+        LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
+        LEFT JOIN citations ON citations.release_id = releases.id
+        LEFT JOIN doc_sizes ON doc_sizes.release_id = releases.id
+        LEFT JOIN repositories ON releases.repository_id = repositories.id
+        WHERE crates.name = $1 AND releases.version = $2;",
+    )?;
+    Ok(conn
+        .client()
+        .query(&statement, &[&name, &version])?
+        .into_iter()
+        .next())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CrateRelease {
+    pub(crate) version: String,
+    pub(crate) build_status: bool,
+    pub(crate) yanked: bool,
+    pub(crate) is_library: bool,
+}
+
+/// All the releases of a crate, in no particular order
+pub(crate) fn crate_releases(
+    conn: &mut impl PreparedStatements,
+    crate_id: i32,
+) -> Result<Vec<CrateRelease>, postgres::Error> {
+    let statement = conn.prepare_cached(
+        "SELECT version, build_status, yanked, is_library
+         FROM releases
+         WHERE releases.crate_id = $1",
+    )?;
+    Ok(conn
+        .client()
+        .query(&statement, &[&crate_id])?
+        .into_iter()
+        .map(|row| CrateRelease {
+            version: row.get("version"),
+            build_status: row.get("build_status"),
+            yanked: row.get("yanked"),
+            is_library: row.get("is_library"),
+        })
+        .collect())
+}
+
+/// The login and avatar of the owners of a crate
+pub(crate) fn crate_owners(
+    conn: &mut impl PreparedStatements,
+    crate_id: i32,
+) -> Result<Vec<(String, String)>, postgres::Error> {
+    let statement = conn.prepare_cached(
+        "SELECT login, avatar
+         FROM owners
+         INNER JOIN owner_rels ON owner_rels.oid = owners.id
+         WHERE cid = $1",
+    )?;
+    Ok(conn
+        .client()
+        .query(&statement, &[&crate_id])?
+        .into_iter()
+        .map(|row| (row.get("login"), row.get("avatar")))
+        .collect())
+}
+
+/// How the latest releases of the crates are sorted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LatestReleasesOrder {
+    ReleaseTime,
+    Stars,
+}
+
+/// The position of a release in the latest releases: the key they are sorted by, and the id of
+/// the release to break the ties
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LatestReleasesCursor {
+    ReleaseTime(DateTime<Utc>, i32),
+    Stars(i32, i32),
+}
+
+impl LatestReleasesCursor {
+    pub(crate) fn order(&self) -> LatestReleasesOrder {
+        match self {
+            Self::ReleaseTime(..) => LatestReleasesOrder::ReleaseTime,
+            Self::Stars(..) => LatestReleasesOrder::Stars,
+        }
+    }
+
+    /// Parses a cursor of the latest releases sorted by `order`, as formatted by `Display`
+    pub(crate) fn parse(order: LatestReleasesOrder, cursor: &str) -> Option<Self> {
+        let mut parts = cursor.splitn(2, '_');
+        let key: i64 = parts.next()?.parse().ok()?;
+        let id = parts.next()?.parse().ok()?;
+
+        Some(match order {
+            LatestReleasesOrder::ReleaseTime => {
+                let time = Utc.timestamp_opt(
+                    key.div_euclid(1_000_000),
+                    key.rem_euclid(1_000_000) as u32 * 1000,
+                );
+                Self::ReleaseTime(time.single()?, id)
+            }
+            LatestReleasesOrder::Stars => Self::Stars(key.try_into().ok()?, id),
+        })
+    }
+}
+
+/// Release times are formatted in microseconds, the precision of the timestamps of Postgres
+impl fmt::Display for LatestReleasesCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReleaseTime(time, id) => write!(
+                f,
+                "{}_{}",
+                time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros()),
+                id
+            ),
+            Self::Stars(stars, id) => write!(f, "{}_{}", stars, id),
+        }
+    }
+}
+
+/// Which of the latest releases are returned
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LatestReleasesPage {
+    /// Skips this many releases from the start, only meant for the first page: the releases
+    /// published in the meantime shift the offset of the next ones
+    Offset(i64),
+    /// The releases sorted after this one
+    After(LatestReleasesCursor),
+    /// The releases sorted before this one, still returned in the order of the list
+    Before(LatestReleasesCursor),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LatestRelease {
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) description: Option<String>,
+    pub(crate) target_name: Option<String>,
+    pub(crate) release_time: DateTime<Utc>,
+    pub(crate) rustdoc_status: bool,
+    pub(crate) stars: i32,
+}
+
+impl LatestRelease {
+    /// The position of this release in the latest releases sorted by `order`
+    pub(crate) fn cursor(&self, order: LatestReleasesOrder) -> LatestReleasesCursor {
+        match order {
+            LatestReleasesOrder::ReleaseTime => {
+                LatestReleasesCursor::ReleaseTime(self.release_time, self.id)
+            }
+            LatestReleasesOrder::Stars => LatestReleasesCursor::Stars(self.stars, self.id),
+        }
+    }
+}
+
+/// The latest release of every crate, optionally only the ones of the libraries that failed to
+/// build
+pub(crate) fn latest_releases(
+    conn: &mut impl PreparedStatements,
+    order: LatestReleasesOrder,
+    only_failed: bool,
+    page: LatestReleasesPage,
+    limit: i64,
+) -> Result<Vec<LatestRelease>, postgres::Error> {
+    // the ordering can't be a parameter, every one of them has its own statements. The pages
+    // after or before a cursor compare the rows with it, which the indexes on the orderings can
+    // answer without going through all the previous pages like an offset does.
+    macro_rules! latest_releases_query {
+        ($ordering:literal, [$($condition:literal),*], $direction:literal, $offset:literal) => {
+            concat!(
+                "SELECT releases.id,
+                    crates.name,
+                    releases.version,
+                    releases.description,
+                    releases.target_name,
+                    releases.release_time,
+                    releases.rustdoc_status,
+                    repositories.stars
+                FROM crates
+                INNER JOIN releases ON crates.latest_version_id = releases.id
+                LEFT JOIN repositories ON releases.repository_id = repositories.id
+                WHERE
+                    ((NOT $2) OR (releases.build_status = FALSE AND releases.is_library = TRUE))
+                    AND ",
+                $ordering,
+                " IS NOT NULL",
+                $($condition,)*
+                " ORDER BY ",
+                $ordering,
+                " ",
+                $direction,
+                ", releases.id ",
+                $direction,
+                " LIMIT $1",
+                $offset
+            )
+        };
+        ($ordering:literal) => {
+            latest_releases_query!($ordering, [], "DESC", " OFFSET $3")
+        };
+        ($ordering:literal, after) => {
+            latest_releases_query!(
+                $ordering,
+                [" AND (", $ordering, ", releases.id) < ($3, $4)"],
+                "DESC",
+                ""
+            )
+        };
+        ($ordering:literal, before) => {
+            latest_releases_query!(
+                $ordering,
+                [" AND (", $ordering, ", releases.id) > ($3, $4)"],
+                "ASC",
+                ""
+            )
+        };
+    }
+
+    let statement = conn.prepare_cached(match (order, page) {
+        (LatestReleasesOrder::ReleaseTime, LatestReleasesPage::Offset(_)) => {
+            latest_releases_query!("releases.release_time")
+        }
+        (LatestReleasesOrder::ReleaseTime, LatestReleasesPage::After(_)) => {
+            latest_releases_query!("releases.release_time", after)
+        }
+        (LatestReleasesOrder::ReleaseTime, LatestReleasesPage::Before(_)) => {
+            latest_releases_query!("releases.release_time", before)
+        }
+        (LatestReleasesOrder::Stars, LatestReleasesPage::Offset(_)) => {
+            latest_releases_query!("repositories.stars")
+        }
+        (LatestReleasesOrder::Stars, LatestReleasesPage::After(_)) => {
+            latest_releases_query!("repositories.stars", after)
+        }
+        (LatestReleasesOrder::Stars, LatestReleasesPage::Before(_)) => {
+            latest_releases_query!("repositories.stars", before)
+        }
+    })?;
+
+    let rows = match page {
+        LatestReleasesPage::Offset(offset) => conn
+            .client()
+            .query(&statement, &[&limit, &only_failed, &offset])?,
+        LatestReleasesPage::After(cursor) | LatestReleasesPage::Before(cursor) => {
+            debug_assert_eq!(cursor.order(), order);
+            let client = conn.client();
+            match cursor {
+                LatestReleasesCursor::ReleaseTime(time, id) => {
+                    client.query(&statement, &[&limit, &only_failed, &time, &id])?
+                }
+                LatestReleasesCursor::Stars(stars, id) => {
+                    client.query(&statement, &[&limit, &only_failed, &stars, &id])?
+                }
+            }
+        }
+    };
+
+    let mut releases: Vec<_> = rows
+        .into_iter()
+        .map(|row| LatestRelease {
+            id: row.get(0),
+            name: row.get(1),
+            version: row.get(2),
+            description: row.get(3),
+            target_name: row.get(4),
+            release_time: row.get(5),
+            rustdoc_status: row.get(6),
+            stars: row.get::<_, Option<i32>>(7).unwrap_or(0),
+        })
+        .collect();
+    if let LatestReleasesPage::Before(_) = page {
+        releases.reverse();
+    }
+    Ok(releases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn latest_releases_cursor_roundtrip() {
+        let cursors = [
+            (
+                LatestReleasesOrder::ReleaseTime,
+                LatestReleasesCursor::ReleaseTime(
+                    Utc.ymd(2020, 4, 16).and_hms_micro(4, 33, 50, 123_456),
+                    42,
+                ),
+            ),
+            (
+                LatestReleasesOrder::ReleaseTime,
+                LatestReleasesCursor::ReleaseTime(Utc.ymd(1969, 12, 31).and_hms(23, 59, 59), 1),
+            ),
+            (
+                LatestReleasesOrder::Stars,
+                LatestReleasesCursor::Stars(0, 10_001),
+            ),
+        ];
+        for (order, cursor) in &cursors {
+            assert_eq!(cursor.order(), *order);
+            let formatted = cursor.to_string();
+            assert_eq!(
+                LatestReleasesCursor::parse(*order, &formatted),
+                Some(*cursor)
+            );
+        }
+
+        assert_eq!(
+            LatestReleasesCursor::parse(LatestReleasesOrder::Stars, "12"),
+            None
+        );
+        assert_eq!(
+            LatestReleasesCursor::parse(LatestReleasesOrder::Stars, "99999999999_1"),
+            None
+        );
+        assert_eq!(
+            LatestReleasesCursor::parse(LatestReleasesOrder::ReleaseTime, "a_1"),
+            None
+        );
+    }
+
+    #[test]
+    fn latest_releases_pages() {
+        wrapper(|env| {
+            for (i, stars) in [10, 30, 20, 30].iter().enumerate() {
+                env.fake_release()
+                    .name(&format!("crate_{}", i))
+                    .release_time(Utc.ymd(2020, 4, 16 + i as u32).and_hms(4, 33, 50))
+                    .github_stats(format!("some/repo_{}", i), *stars, 0, 0)
+                    .create()?;
+            }
+
+            let mut conn = env.db().conn();
+            let mut names = |order, page| -> Result<Vec<String>, postgres::Error> {
+                Ok(latest_releases(&mut conn, order, false, page, 2)?
+                    .into_iter()
+                    .map(|release| release.name)
+                    .collect())
+            };
+
+            let first = latest_releases(
+                &mut env.db().conn(),
+                LatestReleasesOrder::ReleaseTime,
+                false,
+                LatestReleasesPage::Offset(0),
+                2,
+            )?;
+            assert_eq!(first[0].name, "crate_3");
+            assert_eq!(first[1].name, "crate_2");
+            let last = first[1].cursor(LatestReleasesOrder::ReleaseTime);
+            assert_eq!(
+                names(
+                    LatestReleasesOrder::ReleaseTime,
+                    LatestReleasesPage::After(last)
+                )?,
+                vec!["crate_1", "crate_0"]
+            );
+            assert_eq!(
+                names(
+                    LatestReleasesOrder::ReleaseTime,
+                    LatestReleasesPage::Before(last)
+                )?,
+                vec!["crate_3"]
+            );
+
+            // the releases with as many stars are sorted by id
+            let by_stars = latest_releases(
+                &mut env.db().conn(),
+                LatestReleasesOrder::Stars,
+                false,
+                LatestReleasesPage::Offset(1),
+                1,
+            )?;
+            assert_eq!(by_stars[0].name, "crate_1");
+            let cursor = by_stars[0].cursor(LatestReleasesOrder::Stars);
+            assert_eq!(
+                names(
+                    LatestReleasesOrder::Stars,
+                    LatestReleasesPage::After(cursor)
+                )?,
+                vec!["crate_2", "crate_0"]
+            );
+            assert_eq!(
+                names(
+                    LatestReleasesOrder::Stars,
+                    LatestReleasesPage::Before(cursor)
+                )?,
+                vec!["crate_3"]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn statements_are_cached() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo_bar")
+                .version("0.1.0")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let cached = |conn: &mut PoolClient| {
+                conn.extensions()
+                    .get::<StatementCache>()
+                    .map_or(0, |cache| cache.0.len())
+            };
+            let before = cached(&mut conn);
+
+            for _ in 0..2 {
+                let releases = releases_matching_name(&mut conn, "foo-bar")?;
+                assert_eq!(releases.len(), 1);
+                assert_eq!(releases[0].name, "foo_bar");
+                assert_eq!(releases[0].version, "0.1.0");
+            }
+            assert_eq!(cached(&mut conn), before + 1);
+
+            // the other orderings are different statements
+            let first_page = LatestReleasesPage::Offset(0);
+            latest_releases(
+                &mut conn,
+                LatestReleasesOrder::ReleaseTime,
+                false,
+                first_page,
+                10,
+            )?;
+            latest_releases(&mut conn, LatestReleasesOrder::Stars, false, first_page, 10)?;
+            assert_eq!(cached(&mut conn), before + 3);
+
+            // connections outside of the pool prepare them every time
+            let releases = releases_matching_name(&mut *conn, "foo_bar")?;
+            assert_eq!(releases.len(), 1);
+
+            Ok(())
+        });
+    }
+}
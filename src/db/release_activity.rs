@@ -0,0 +1,54 @@
+//! Daily counts of the releases and of the libraries that failed to build, shown on
+//! `/releases/activity`
+//!
+//! The counts are updated whenever a release is added, rebuilt or deleted, instead of being
+//! computed from all the releases of the month every time the page is requested.
+
+use postgres::GenericClient;
+
+/// Counts a release in the activity of the day it was published
+pub(crate) fn count_release(
+    conn: &mut impl GenericClient,
+    release_id: i32,
+) -> Result<(), postgres::Error> {
+    conn.execute(
+        "INSERT INTO release_activity (date, releases, failures)
+         SELECT
+            release_time::date,
+            1,
+            CAST((is_library = TRUE AND build_status = FALSE) AS INT)
+         FROM releases
+         WHERE id = $1
+         ON CONFLICT (date) DO UPDATE
+            SET releases = release_activity.releases + EXCLUDED.releases,
+                failures = release_activity.failures + EXCLUDED.failures",
+        &[&release_id],
+    )?;
+    Ok(())
+}
+
+/// Removes the releases of a crate from the activity, only `version` if it's given. Called
+/// before the releases are deleted or replaced by a rebuild.
+pub(crate) fn uncount_releases(
+    conn: &mut impl GenericClient,
+    crate_id: i32,
+    version: Option<&str>,
+) -> Result<(), postgres::Error> {
+    conn.execute(
+        "UPDATE release_activity
+         SET releases = release_activity.releases - removed.releases,
+             failures = release_activity.failures - removed.failures
+         FROM (
+            SELECT
+                release_time::date AS date,
+                COUNT(*) AS releases,
+                SUM(CAST((is_library = TRUE AND build_status = FALSE) AS INT)) AS failures
+            FROM releases
+            WHERE crate_id = $1 AND ($2::TEXT IS NULL OR version = $2)
+            GROUP BY release_time::date
+         ) AS removed
+         WHERE release_activity.date = removed.date",
+        &[&crate_id, &version],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Per-crate overrides of the default limits of the builds, applied by `Limits::for_crate`
+
+use failure::{Error, Fail};
+use postgres::{Client, Row};
+use serde::Serialize;
+use std::fmt;
+
+/// The values larger than these are refused, they are most likely typos
+const MAX_MEMORY_BYTES: i64 = 64 * 1024 * 1024 * 1024; // 64 GB
+const MAX_DISK_BYTES: i64 = 500 * 1024 * 1024 * 1024; // 500 GB
+const MAX_TIMEOUT_SECONDS: i32 = 24 * 60 * 60; // 1 day
+const MAX_TARGETS: i32 = 100;
+const MAX_UPLOAD_BYTES: i64 = 100 * 1024 * 1024 * 1024; // 100 GB
+const MAX_FILES: i32 = 10_000_000;
+
+#[derive(Debug, Fail, PartialEq, Eq)]
+#[fail(display = "invalid sandbox override: {}", _0)]
+pub struct InvalidOverrideError(String);
+
+/// The limits overridden for a crate, the default limits apply to the ones that are `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SandboxOverride {
+    pub crate_name: String,
+    pub max_memory_bytes: Option<i64>,
+    pub max_disk_bytes: Option<i64>,
+    pub timeout_seconds: Option<i32>,
+    /// When only the timeout is overridden, a single target is built
+    pub max_targets: Option<i32>,
+    pub max_upload_bytes: Option<i64>,
+    pub max_files: Option<i32>,
+}
+
+impl SandboxOverride {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            crate_name: row.get("crate_name"),
+            max_memory_bytes: row.get("max_memory_bytes"),
+            max_disk_bytes: row.get("max_disk_bytes"),
+            timeout_seconds: row.get("timeout_seconds"),
+            max_targets: row.get("max_targets"),
+            max_upload_bytes: row.get("max_upload_bytes"),
+            max_files: row.get("max_files"),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidOverrideError> {
+        let invalid = |reason: String| Err(InvalidOverrideError(reason));
+
+        if self.crate_name.is_empty() || self.crate_name.len() > 64 {
+            return invalid(format!("invalid crate name {:?}", self.crate_name));
+        }
+        let limits = [
+            ("max_memory_bytes", self.max_memory_bytes, MAX_MEMORY_BYTES),
+            ("max_disk_bytes", self.max_disk_bytes, MAX_DISK_BYTES),
+            (
+                "timeout_seconds",
+                self.timeout_seconds.map(i64::from),
+                MAX_TIMEOUT_SECONDS.into(),
+            ),
+            (
+                "max_targets",
+                self.max_targets.map(i64::from),
+                MAX_TARGETS.into(),
+            ),
+            ("max_upload_bytes", self.max_upload_bytes, MAX_UPLOAD_BYTES),
+            ("max_files", self.max_files.map(i64::from), MAX_FILES.into()),
+        ];
+        if limits.iter().all(|(_, value, _)| value.is_none()) {
+            return invalid("no limit is overridden".into());
+        }
+        for (name, value, max) in &limits {
+            match value {
+                Some(value) if *value <= 0 || value > max => {
+                    return invalid(format!("{} must be between 1 and {}", name, max));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the overridden limits, e.g. `foo (max_memory_bytes 1024, timeout_seconds 60)`
+impl fmt::Display for SandboxOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits = [
+            ("max_memory_bytes", self.max_memory_bytes),
+            ("max_disk_bytes", self.max_disk_bytes),
+            ("timeout_seconds", self.timeout_seconds.map(i64::from)),
+            ("max_targets", self.max_targets.map(i64::from)),
+            ("max_upload_bytes", self.max_upload_bytes),
+            ("max_files", self.max_files.map(i64::from)),
+        ];
+        let limits: Vec<_> = limits
+            .iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{} {}", name, value)))
+            .collect();
+        write!(f, "{} ({})", self.crate_name, limits.join(", "))
+    }
+}
+
+/// Returns all the overrides, sorted by crate name
+pub fn list(conn: &mut Client) -> Result<Vec<SandboxOverride>, Error> {
+    Ok(conn
+        .query("SELECT * FROM sandbox_overrides ORDER BY crate_name", &[])?
+        .iter()
+        .map(SandboxOverride::from_row)
+        .collect())
+}
+
+pub fn get(conn: &mut Client, name: &str) -> Result<Option<SandboxOverride>, Error> {
+    Ok(conn
+        .query_opt(
+            "SELECT * FROM sandbox_overrides WHERE crate_name = $1",
+            &[&name],
+        )?
+        .as_ref()
+        .map(SandboxOverride::from_row))
+}
+
+/// Creates the override of a crate, or replaces all the limits of the existing one
+pub fn set(conn: &mut Client, limits: &SandboxOverride) -> Result<(), Error> {
+    limits.validate()?;
+    conn.execute(
+        "INSERT INTO sandbox_overrides (
+            crate_name, max_memory_bytes, max_disk_bytes, timeout_seconds, max_targets,
+            max_upload_bytes, max_files
+         )
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (crate_name) DO UPDATE
+            SET max_memory_bytes = EXCLUDED.max_memory_bytes,
+                max_disk_bytes = EXCLUDED.max_disk_bytes,
+                timeout_seconds = EXCLUDED.timeout_seconds,
+                max_targets = EXCLUDED.max_targets,
+                max_upload_bytes = EXCLUDED.max_upload_bytes,
+                max_files = EXCLUDED.max_files",
+        &[
+            &limits.crate_name,
+            &limits.max_memory_bytes,
+            &limits.max_disk_bytes,
+            &limits.timeout_seconds,
+            &limits.max_targets,
+            &limits.max_upload_bytes,
+            &limits.max_files,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns the removed override, if there was one
+pub fn remove(conn: &mut Client, name: &str) -> Result<Option<SandboxOverride>, Error> {
+    Ok(conn
+        .query_opt(
+            "DELETE FROM sandbox_overrides WHERE crate_name = $1 RETURNING *",
+            &[&name],
+        )?
+        .as_ref()
+        .map(SandboxOverride::from_row))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        let valid = SandboxOverride {
+            crate_name: "foo".into(),
+            timeout_seconds: Some(30 * 60),
+            ..SandboxOverride::default()
+        };
+        assert_eq!(valid.validate(), Ok(()));
+        assert_eq!(valid.to_string(), "foo (timeout_seconds 1800)".to_string());
+
+        let invalid = [
+            SandboxOverride {
+                crate_name: String::new(),
+                ..valid.clone()
+            },
+            SandboxOverride {
+                timeout_seconds: None,
+                ..valid.clone()
+            },
+            SandboxOverride {
+                max_targets: Some(0),
+                ..valid.clone()
+            },
+            SandboxOverride {
+                max_memory_bytes: Some(-1),
+                ..valid.clone()
+            },
+            SandboxOverride {
+                max_disk_bytes: Some(MAX_DISK_BYTES + 1),
+                ..valid.clone()
+            },
+        ];
+        for limits in &invalid {
+            assert!(limits.validate().is_err(), "{:?}", limits);
+        }
+    }
+}
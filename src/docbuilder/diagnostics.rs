@@ -0,0 +1,177 @@
+//! The warnings and errors of rustdoc, parsed from the output of `cargo --message-format=json`
+
+use serde::{Deserialize, Serialize};
+
+/// A warning or an error rustdoc emitted while documenting the crate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct BuildDiagnostic {
+    /// `warning` or `error`
+    pub(crate) level: String,
+    /// The lint that emitted the diagnostic, e.g. `rustdoc::broken_intra_doc_links`
+    pub(crate) lint: Option<String>,
+    pub(crate) file: String,
+    pub(crate) line: i32,
+    pub(crate) message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum CargoMessage {
+    CompilerMessage {
+        package_id: String,
+        message: CompilerMessage,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    code: Option<DiagnosticCode>,
+    level: String,
+    spans: Vec<DiagnosticSpan>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiagnosticCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct DiagnosticSpan {
+    file_name: String,
+    line_start: i32,
+    is_primary: bool,
+}
+
+/// What to do with a line of the output of cargo
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum OutputLine {
+    /// The line isn't a JSON message, it's logged as is
+    Text,
+    /// The line is replaced by the human readable version of the diagnostic in the log, if the
+    /// diagnostic is about the documented package and has a location it's also returned
+    Diagnostic {
+        rendered: Option<String>,
+        diagnostic: Option<BuildDiagnostic>,
+    },
+    /// The line is a JSON message that isn't useful in the log, e.g. the list of the artifacts
+    Ignored,
+}
+
+/// Parses a line of the output of cargo, ignoring the diagnostics of the packages other than
+/// `package_id`
+pub(crate) fn parse_output_line(line: &str, package_id: &str) -> OutputLine {
+    if !line.starts_with('{') {
+        return OutputLine::Text;
+    }
+    let (id, message) = match serde_json::from_str(line) {
+        Ok(CargoMessage::CompilerMessage {
+            package_id,
+            message,
+        }) => (package_id, message),
+        Ok(CargoMessage::Other) => return OutputLine::Ignored,
+        Err(_) => return OutputLine::Text,
+    };
+
+    // summaries like `2 warnings emitted` don't have a location
+    let span = message.spans.iter().find(|span| span.is_primary);
+    let is_problem = matches!(message.level.as_str(), "warning" | "error");
+    let diagnostic = match span {
+        Some(span) if id == package_id && is_problem => Some(BuildDiagnostic {
+            level: message.level,
+            lint: message.code.map(|code| code.code),
+            file: span.file_name.clone(),
+            line: span.line_start,
+            message: message.message,
+        }),
+        _ => None,
+    };
+
+    OutputLine::Diagnostic {
+        rendered: message.rendered,
+        diagnostic,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGE_ID: &str = "foo 0.1.0 (path+file:///opt/rustwide/workdir)";
+
+    #[test]
+    fn broken_intra_doc_link() {
+        let line = serde_json::json!({
+            "reason": "compiler-message",
+            "package_id": PACKAGE_ID,
+            "message": {
+                "message": "unresolved link to `Bar`",
+                "code": {"code": "rustdoc::broken_intra_doc_links", "explanation": null},
+                "level": "warning",
+                "spans": [
+                    {"file_name": "src/lib.rs", "line_start": 1, "is_primary": false},
+                    {"file_name": "src/lib.rs", "line_start": 3, "is_primary": true},
+                ],
+                "children": [],
+                "rendered": "warning: unresolved link to `Bar`\n --> src/lib.rs:3:10\n",
+            },
+        })
+        .to_string();
+
+        assert_eq!(
+            parse_output_line(&line, PACKAGE_ID),
+            OutputLine::Diagnostic {
+                rendered: Some("warning: unresolved link to `Bar`\n --> src/lib.rs:3:10\n".into()),
+                diagnostic: Some(BuildDiagnostic {
+                    level: "warning".into(),
+                    lint: Some("rustdoc::broken_intra_doc_links".into()),
+                    file: "src/lib.rs".into(),
+                    line: 3,
+                    message: "unresolved link to `Bar`".into(),
+                }),
+            }
+        );
+
+        // the diagnostics of the dependencies are only logged
+        match parse_output_line(
+            &line,
+            "bar 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)",
+        ) {
+            OutputLine::Diagnostic { diagnostic, .. } => assert_eq!(diagnostic, None),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn other_lines() {
+        let summary = serde_json::json!({
+            "reason": "compiler-message",
+            "package_id": PACKAGE_ID,
+            "message": {
+                "message": "1 warning emitted",
+                "code": null,
+                "level": "warning",
+                "spans": [],
+                "rendered": "warning: 1 warning emitted\n",
+            },
+        })
+        .to_string();
+        assert_eq!(
+            parse_output_line(&summary, PACKAGE_ID),
+            OutputLine::Diagnostic {
+                rendered: Some("warning: 1 warning emitted\n".into()),
+                diagnostic: None,
+            }
+        );
+
+        let finished = r#"{"reason":"build-finished","success":true}"#;
+        assert_eq!(parse_output_line(finished, PACKAGE_ID), OutputLine::Ignored);
+
+        for line in &["   Documenting foo v0.1.0", "{ not json", ""] {
+            assert_eq!(parse_output_line(line, PACKAGE_ID), OutputLine::Text);
+        }
+    }
+}
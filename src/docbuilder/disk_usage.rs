@@ -0,0 +1,191 @@
+//! Disk usage monitoring of the build directories
+//!
+//! rustwide doesn't allow limiting the disk space used by the sandbox, so while a build runs a
+//! background thread periodically measures the size of its target directory. When the size goes
+//! over the quota in `Limits` the build is marked as failed and the directory is emptied, both to
+//! free the disk and to make the running rustdoc invocation fail.
+
+use crate::error::Result;
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use walkdir::WalkDir;
+
+/// How often the size of the directory is measured
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Returns the total size of the files in `path`, without following symlinks
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        // files can be removed by the build while we're iterating
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Returns the space available to unprivileged users on the filesystem containing `path`
+pub(crate) fn available_space(path: &Path) -> Result<u64> {
+    use systemstat::{Platform, System};
+
+    let path = path.canonicalize()?;
+    System::new()
+        .mounts()?
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.fs_mounted_on))
+        .max_by_key(|mount| mount.fs_mounted_on.len())
+        .map(|mount| mount.avail.as_u64())
+        .ok_or_else(|| failure::format_err!("no filesystem is mounted at {}", path.display()))
+}
+
+/// The disk usage recorded while a build was running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DiskUsage {
+    /// The largest size of the monitored directory, in bytes
+    pub(crate) peak: u64,
+    /// Whether the size went over the quota at some point
+    pub(crate) quota_exceeded: bool,
+}
+
+#[derive(Default)]
+struct State {
+    peak: AtomicU64,
+    quota_exceeded: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl State {
+    fn check(&self, path: &Path, quota: u64) {
+        let size = dir_size(path);
+        self.peak.fetch_max(size, Ordering::SeqCst);
+
+        if size > quota {
+            if !self.quota_exceeded.swap(true, Ordering::SeqCst) {
+                warn!(
+                    "{} uses {} bytes, more than the {} bytes allowed: aborting the build",
+                    path.display(),
+                    size,
+                    quota
+                );
+            }
+            if let Err(err) = fs::remove_dir_all(path) {
+                warn!("failed to clean up {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Measures the size of a directory in a background thread until `finish` is called
+pub(crate) struct DiskUsageMonitor {
+    state: Arc<State>,
+    path: PathBuf,
+    quota: u64,
+    thread: JoinHandle<()>,
+}
+
+impl DiskUsageMonitor {
+    pub(crate) fn start(path: PathBuf, quota: u64) -> Self {
+        Self::with_interval(path, quota, CHECK_INTERVAL)
+    }
+
+    fn with_interval(path: PathBuf, quota: u64, interval: Duration) -> Self {
+        let state = Arc::new(State::default());
+        let thread = {
+            let state = state.clone();
+            let path = path.clone();
+            thread::spawn(move || {
+                while !state.stop.load(Ordering::SeqCst) {
+                    state.check(&path, quota);
+                    thread::park_timeout(interval);
+                }
+            })
+        };
+
+        Self {
+            state,
+            path,
+            quota,
+            thread,
+        }
+    }
+
+    /// Stops the monitoring and returns the recorded disk usage
+    pub(crate) fn finish(self) -> DiskUsage {
+        self.state.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        if self.thread.join().is_err() {
+            warn!("the disk usage monitor of {} panicked", self.path.display());
+        }
+
+        // the build might have grown since the last check
+        self.state.check(&self.path, self.quota);
+        DiskUsage {
+            peak: self.state.peak.load(Ordering::SeqCst),
+            quota_exceeded: self.state.quota_exceeded.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_directories() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("nested"))?;
+        fs::write(dir.path().join("a"), vec![0; 100])?;
+        fs::write(dir.path().join("nested/b"), vec![0; 50])?;
+
+        assert_eq!(dir_size(dir.path()), 150);
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+        assert!(available_space(dir.path())? > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn under_quota() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let monitor = DiskUsageMonitor::start(dir.path().to_owned(), 1000);
+        fs::write(dir.path().join("a"), vec![0; 100])?;
+
+        let usage = monitor.finish();
+        assert_eq!(
+            usage,
+            DiskUsage {
+                peak: 100,
+                quota_exceeded: false
+            }
+        );
+        assert!(dir.path().join("a").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn over_quota() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("target");
+        fs::create_dir(&target)?;
+
+        let monitor =
+            DiskUsageMonitor::with_interval(target.clone(), 100, Duration::from_millis(10));
+        fs::write(target.join("a"), vec![0; 200])?;
+        while target.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let usage = monitor.finish();
+        assert_eq!(usage.peak, 200);
+        assert!(usage.quota_exceeded);
+
+        Ok(())
+    }
+}
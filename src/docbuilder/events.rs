@@ -0,0 +1,183 @@
+//! Notifications of the completed builds
+//!
+//! Services showing the documentation status of crates (like crates.io or IDE indexes) can be
+//! told when a build completes instead of polling docs.rs. Every build sends a JSON event to the
+//! webhook configured with `DOCSRS_BUILD_EVENTS_WEBHOOK`, which can be a fanout service or the
+//! HTTP endpoint of a message bus.
+
+use crate::error::Result;
+use crate::utils::pubsubhubbub::signature;
+use crate::{Config, Metrics};
+use log::warn;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// The header containing the HMAC-SHA256 of the body, in the same format as WebSub signatures
+const SIGNATURE_HEADER: &str = "X-DocsRs-Signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BuildStatus {
+    Success,
+    Failure,
+}
+
+/// Sent when the results of a build are stored
+#[derive(Debug, Serialize)]
+pub(crate) struct BuildEvent<'a> {
+    #[serde(rename = "crate")]
+    pub(crate) krate: &'a str,
+    pub(crate) version: &'a str,
+    pub(crate) status: BuildStatus,
+    pub(crate) build_id: i32,
+    /// The documentation if the build succeeded, the build log otherwise
+    pub(crate) url: String,
+}
+
+pub(crate) struct BuildEvents {
+    webhook: Option<Url>,
+    secret: Option<String>,
+    public_url: Url,
+    client: reqwest::blocking::Client,
+    metrics: Arc<Metrics>,
+}
+
+impl BuildEvents {
+    pub(crate) fn new(config: &Config, metrics: Arc<Metrics>) -> Result<Self> {
+        Ok(Self {
+            webhook: config.build_events_webhook.clone(),
+            secret: config.build_events_secret.clone(),
+            public_url: config.public_url.clone(),
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            metrics,
+        })
+    }
+
+    pub(crate) fn build_completed(
+        &self,
+        krate: &str,
+        version: &str,
+        build_id: i32,
+        successful: bool,
+    ) -> Result<()> {
+        let (status, path) = if successful {
+            (BuildStatus::Success, format!("{}/{}/", krate, version))
+        } else {
+            (
+                BuildStatus::Failure,
+                format!("crate/{}/{}/builds/{}", krate, version, build_id),
+            )
+        };
+
+        self.publish(&BuildEvent {
+            krate,
+            version,
+            status,
+            build_id,
+            url: self.public_url.join(&path)?.into_string(),
+        });
+        Ok(())
+    }
+
+    /// Sends an event to the webhook. Failures are only logged, the build is already stored and
+    /// shouldn't be retried because of them.
+    fn publish(&self, event: &BuildEvent<'_>) {
+        let webhook = match &self.webhook {
+            Some(webhook) => webhook,
+            None => return,
+        };
+
+        let result = serde_json::to_vec(event)
+            .map_err(failure::Error::from)
+            .and_then(|body| {
+                let mut request = self
+                    .client
+                    .post(webhook.clone())
+                    .header(CONTENT_TYPE, "application/json");
+                if let Some(secret) = &self.secret {
+                    request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+                }
+                request.body(body).send()?.error_for_status()?;
+                Ok(())
+            });
+
+        let label = match result {
+            Ok(()) => "sent",
+            Err(err) => {
+                warn!(
+                    "failed to send the build event of {} {}: {}",
+                    event.krate, event.version, err
+                );
+                "failed"
+            }
+        };
+        self.metrics.build_events.with_label_values(&[label]).inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::BuildFailure;
+    use crate::test::wrapper;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    #[test]
+    fn events_are_sent_after_builds() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build_events_webhook = Some(
+                    format!("{}/build-events", mockito::server_url())
+                        .parse()
+                        .unwrap(),
+                );
+                config.build_events_secret = Some("secret".into());
+            });
+
+            let success = mockito::mock("POST", "/build-events")
+                .match_header(
+                    SIGNATURE_HEADER,
+                    Matcher::Regex("^sha256=[0-9a-f]{64}$".into()),
+                )
+                .match_body(Matcher::PartialJson(json!({
+                    "crate": "foo",
+                    "version": "0.1.0",
+                    "status": "success",
+                    "url": "https://docs.rs/foo/0.1.0/",
+                })))
+                .create();
+            let failure = mockito::mock("POST", "/build-events")
+                .match_body(Matcher::PartialJson(json!({
+                    "crate": "foo",
+                    "version": "0.2.0",
+                    "status": "failure",
+                })))
+                .create();
+
+            env.fake_builder().name("foo").version("0.1.0").build()?;
+            env.fake_builder()
+                .name("foo")
+                .version("0.2.0")
+                .failure(BuildFailure::DiskQuotaExceeded)
+                .build()?;
+
+            success.assert();
+            failure.assert();
+            assert_eq!(
+                env.metrics()
+                    .build_events
+                    .with_label_values(&["sent"])
+                    .get(),
+                2
+            );
+
+            Ok(())
+        });
+    }
+}
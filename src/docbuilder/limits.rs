@@ -1,25 +1,40 @@
+use crate::db::sandbox_overrides;
 use crate::error::Result;
+use crate::storage::UploadLimit;
 use postgres::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// Stored with every build, the limits added later are missing from the older builds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct Limits {
     memory: usize,
+    disk_space: usize,
     targets: usize,
     timeout: Duration,
     networking: bool,
     max_log_size: usize,
+    /// The maximum size of the documentation of a release, all targets included
+    upload_size: usize,
+    /// The maximum size of a single file of the documentation
+    upload_file_size: usize,
+    /// The maximum number of files of the documentation, all targets included
+    max_files: usize,
 }
 
 impl Default for Limits {
     fn default() -> Self {
         Self {
             memory: 3 * 1024 * 1024 * 1024,        // 3 GB
+            disk_space: 20 * 1024 * 1024 * 1024,   // 20 GB
             timeout: Duration::from_secs(15 * 60), // 15 minutes
             targets: 10,
             networking: false,
-            max_log_size: 100 * 1024, // 100 KB
+            max_log_size: 100 * 1024,             // 100 KB
+            upload_size: 10 * 1024 * 1024 * 1024, // 10 GB
+            upload_file_size: 1024 * 1024 * 1024, // 1 GB
+            max_files: 500_000,
         }
     }
 }
@@ -28,22 +43,25 @@ impl Limits {
     pub(crate) fn for_crate(conn: &mut Client, name: &str) -> Result<Self> {
         let mut limits = Self::default();
 
-        let res = conn.query(
-            "SELECT * FROM sandbox_overrides WHERE crate_name = $1;",
-            &[&name],
-        )?;
-        if !res.is_empty() {
-            let row = &res[0];
-            if let Some(memory) = row.get::<_, Option<i64>>("max_memory_bytes") {
+        if let Some(overrides) = sandbox_overrides::get(conn, name)? {
+            if let Some(memory) = overrides.max_memory_bytes {
                 limits.memory = memory as usize;
             }
-            let timeout = row.get::<_, Option<i32>>("timeout_seconds");
-            if let Some(timeout) = timeout {
+            if let Some(disk_space) = overrides.max_disk_bytes {
+                limits.disk_space = disk_space as usize;
+            }
+            if let Some(timeout) = overrides.timeout_seconds {
                 limits.timeout = Duration::from_secs(timeout as u64);
             }
-            if let Some(targets) = row.get::<_, Option<i32>>("max_targets") {
+            if let Some(upload_size) = overrides.max_upload_bytes {
+                limits.upload_size = upload_size as usize;
+            }
+            if let Some(max_files) = overrides.max_files {
+                limits.max_files = max_files as usize;
+            }
+            if let Some(targets) = overrides.max_targets {
                 limits.targets = targets as usize;
-            } else if timeout.is_some() {
+            } else if overrides.timeout_seconds.is_some() {
                 limits.targets = 1;
             }
         }
@@ -55,6 +73,11 @@ impl Limits {
         self.memory
     }
 
+    /// The maximum size of the build directory
+    pub(crate) fn disk_space(&self) -> usize {
+        self.disk_space
+    }
+
     pub(crate) fn timeout(&self) -> Duration {
         self.timeout
     }
@@ -70,6 +93,17 @@ impl Limits {
     pub(crate) fn targets(&self) -> usize {
         self.targets
     }
+
+    pub(crate) fn max_files(&self) -> usize {
+        self.max_files
+    }
+
+    pub(crate) fn upload_limit(&self) -> UploadLimit {
+        UploadLimit {
+            file_size: self.upload_file_size as u64,
+            total_size: self.upload_size as u64,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -105,14 +139,17 @@ mod test {
             let krate = "regex";
             let limits = Limits {
                 memory: 100_000,
+                disk_space: 1_000_000,
                 timeout: Duration::from_secs(300),
                 targets: 1,
+                upload_size: 5_000_000,
+                max_files: 1_000,
                 ..Limits::default()
             };
             db.conn().query(
-                "INSERT INTO sandbox_overrides (crate_name, max_memory_bytes, timeout_seconds, max_targets)
-                 VALUES ($1, $2, $3, $4)",
-                &[&krate, &(limits.memory as i64), &(limits.timeout.as_secs() as i32), &(limits.targets as i32)]
+                "INSERT INTO sandbox_overrides (crate_name, max_memory_bytes, max_disk_bytes, timeout_seconds, max_targets, max_upload_bytes, max_files)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[&krate, &(limits.memory as i64), &(limits.disk_space as i64), &(limits.timeout.as_secs() as i32), &(limits.targets as i32), &(limits.upload_size as i64), &(limits.max_files as i32)]
             )?;
             assert_eq!(limits, Limits::for_crate(&mut db.conn(), krate)?);
             Ok(())
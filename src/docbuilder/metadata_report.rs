@@ -0,0 +1,253 @@
+//! How the `[package.metadata.docs.rs]` table of a crate is interpreted, without building it
+
+use super::rustwide_builder::cargo_args;
+use super::Limits;
+use crate::error::Result;
+use crate::Context;
+use docsrs_metadata::Metadata;
+use failure::{bail, format_err, ResultExt};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What docs.rs would do to build a crate
+#[derive(Debug)]
+pub struct MetadataReport {
+    name: String,
+    default_target: String,
+    /// Sorted, only the first `limits.targets()` ones are built
+    other_targets: Vec<String>,
+    cargo_args: Vec<String>,
+    environment: Vec<(&'static str, String)>,
+    limits: Limits,
+    unknown_keys: Vec<String>,
+}
+
+impl MetadataReport {
+    /// Loads the manifest of `spec`, either the path of a crate or `name@version` for a release
+    /// already built by docs.rs
+    pub fn load(context: &dyn Context, spec: &str) -> Result<Self> {
+        let path = Path::new(spec);
+        let manifest = if path.exists() {
+            let path = if path.is_dir() {
+                path.join("Cargo.toml")
+            } else {
+                path.to_path_buf()
+            };
+            std::fs::read_to_string(&path)
+                .with_context(|_| format!("failed to read {}", path.display()))?
+        } else if let Some((name, version)) = spec.split_once('@') {
+            let path = format!("sources/{}/{}/Cargo.toml", name, version);
+            let blob = context
+                .storage()?
+                .get(&path, context.config()?.max_file_size)
+                .with_context(|_| format!("{} {} was not built by docs.rs", name, version))?;
+            String::from_utf8(blob.content)?
+        } else {
+            bail!(
+                "{} is neither a path nor a release written as name@version",
+                spec
+            );
+        };
+
+        let name = crate_name(&manifest)?;
+        let limits = Limits::for_crate(&mut *context.pool()?.get()?, &name)?;
+        Self::new(context, name, &manifest, limits)
+    }
+
+    fn new(context: &dyn Context, name: String, manifest: &str, limits: Limits) -> Result<Self> {
+        let config = context.config()?;
+        let metadata = Metadata::from_str(manifest)
+            .context("invalid [package.metadata.docs.rs] table in Cargo.toml")?;
+        let targets = metadata.targets(config.include_default_targets);
+
+        let mut other_targets: Vec<_> = targets
+            .other_targets
+            .iter()
+            .map(|&target| target.to_owned())
+            .collect();
+        other_targets.sort();
+        let mut environment: Vec<_> = metadata.environment_variables().into_iter().collect();
+        environment.sort();
+
+        Ok(MetadataReport {
+            cargo_args: cargo_args(
+                &config,
+                &metadata,
+                targets.default_target,
+                Vec::new(),
+                Vec::new(),
+            ),
+            default_target: targets.default_target.to_owned(),
+            other_targets,
+            environment,
+            limits,
+            unknown_keys: metadata.unknown_keys().map(str::to_owned).collect(),
+            name,
+        })
+    }
+
+    /// The keys of the table that are ignored by docs.rs
+    pub fn unknown_keys(&self) -> &[String] {
+        &self.unknown_keys
+    }
+}
+
+fn crate_name(manifest: &str) -> Result<String> {
+    let manifest = manifest
+        .parse::<toml::Value>()
+        .context("failed to parse Cargo.toml")?;
+    manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_str())
+        .map(str::to_owned)
+        .ok_or_else(|| format_err!("Cargo.toml has no package name"))
+}
+
+impl fmt::Display for MetadataReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "crate: {}", self.name)?;
+        writeln!(f, "default target: {}", self.default_target)?;
+        if self.other_targets.is_empty() {
+            writeln!(f, "other targets: none")?;
+        } else {
+            writeln!(f, "other targets: {}", self.other_targets.join(", "))?;
+            if self.other_targets.len() > self.limits.targets() {
+                writeln!(
+                    f,
+                    "  only {} of them will be built, see the limits below",
+                    self.limits.targets()
+                )?;
+            }
+        }
+        // rustdoc also gets flags specific to each build, like `--resource-suffix`
+        writeln!(
+            f,
+            "command for the default target: cargo {}",
+            self.cargo_args.join(" ")
+        )?;
+        for (key, value) in &self.environment {
+            writeln!(f, "environment variable: {}={}", key, value)?;
+        }
+
+        writeln!(f, "limits:")?;
+        writeln!(f, "  memory: {} MiB", self.limits.memory() / 1024 / 1024)?;
+        writeln!(
+            f,
+            "  disk space: {} MiB",
+            self.limits.disk_space() / 1024 / 1024
+        )?;
+        writeln!(f, "  timeout: {} seconds", self.limits.timeout().as_secs())?;
+        writeln!(f, "  targets: {}", self.limits.targets())?;
+        writeln!(
+            f,
+            "  network access: {}",
+            if self.limits.networking() {
+                "allowed"
+            } else {
+                "blocked"
+            }
+        )?;
+        writeln!(
+            f,
+            "  build log size: {} KiB",
+            self.limits.max_log_size() / 1024
+        )?;
+
+        for key in &self.unknown_keys {
+            writeln!(
+                f,
+                "warning: unknown key `{}` in [package.metadata.docs.rs], it is ignored",
+                key
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use std::fs;
+
+    #[test]
+    fn local_crate() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            fs::write(
+                dir.path().join("Cargo.toml"),
+                r#"
+                    [package]
+                    name = "foo"
+                    version = "0.1.0"
+
+                    [package.metadata.docs.rs]
+                    features = ["serde"]
+                    targets = ["x86_64-apple-darwin", "i686-pc-windows-msvc"]
+                    rustdoc_args = ["--cfg", "docsrs"]
+                "#,
+            )?;
+
+            let report = MetadataReport::load(env, dir.path().to_str().unwrap())?;
+            assert_eq!(report.name, "foo");
+            assert_eq!(report.default_target, "x86_64-apple-darwin");
+            assert_eq!(report.other_targets, vec!["i686-pc-windows-msvc"]);
+            assert!(report
+                .cargo_args
+                .windows(2)
+                .any(|args| args == ["--features", "serde"]));
+            assert_eq!(report.limits, Limits::default());
+            assert_eq!(report.unknown_keys(), ["rustdoc_args"]);
+
+            let text = report.to_string();
+            assert!(text.contains("default target: x86_64-apple-darwin\n"));
+            assert!(text.contains("warning: unknown key `rustdoc_args`"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn built_release() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file(
+                    "Cargo.toml",
+                    br#"
+                        [package]
+                        name = "foo"
+
+                        [package.metadata.docs.rs]
+                        all-features = true
+                        targets = [
+                            "x86_64-apple-darwin",
+                            "x86_64-pc-windows-msvc",
+                            "i686-pc-windows-msvc",
+                            "i686-unknown-linux-gnu",
+                        ]
+                    "#,
+                )
+                .build()?;
+            env.db().conn().execute(
+                "INSERT INTO sandbox_overrides (crate_name, max_targets) VALUES ('foo', 2)",
+                &[],
+            )?;
+
+            let report = MetadataReport::load(env, "foo@0.1.0")?;
+            assert!(report.cargo_args.contains(&"--all-features".to_owned()));
+            assert!(report.unknown_keys().is_empty());
+            assert_eq!(report.limits.targets(), 2);
+            assert!(report.to_string().contains("only 2 of them will be built"));
+
+            assert!(MetadataReport::load(env, "foo@0.2.0").is_err());
+            assert!(MetadataReport::load(env, "foo").is_err());
+
+            Ok(())
+        });
+    }
+}
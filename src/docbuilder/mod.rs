@@ -1,15 +1,27 @@
 mod crates;
+mod diagnostics;
+mod disk_usage;
+mod events;
 mod limits;
+mod metadata_report;
+mod progress;
 mod queue;
+mod reproducibility;
 mod rustwide_builder;
+mod upload;
 
+pub(crate) use self::diagnostics::BuildDiagnostic;
 pub(crate) use self::limits::Limits;
-pub(crate) use self::rustwide_builder::{BuildResult, DocCoverage};
+pub use self::metadata_report::MetadataReport;
+pub(crate) use self::reproducibility::Manifest;
+pub(crate) use self::rustwide_builder::{BuildFailure, BuildResult, DocCoverage};
 pub use self::rustwide_builder::{PackageKind, RustwideBuilder};
+#[cfg(test)]
+pub(crate) use self::upload::{BuildOutput, BuildUploader};
 
 use crate::db::Pool;
 use crate::error::Result;
-use crate::{BuildQueue, Config};
+use crate::{BuildQueue, Config, VersionCache};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -19,14 +31,21 @@ pub struct DocBuilder {
     config: Arc<Config>,
     db: Pool,
     build_queue: Arc<BuildQueue>,
+    version_cache: Arc<VersionCache>,
 }
 
 impl DocBuilder {
-    pub fn new(config: Arc<Config>, db: Pool, build_queue: Arc<BuildQueue>) -> DocBuilder {
+    pub fn new(
+        config: Arc<Config>,
+        db: Pool,
+        build_queue: Arc<BuildQueue>,
+        version_cache: Arc<VersionCache>,
+    ) -> DocBuilder {
         DocBuilder {
             config,
             db,
             build_queue,
+            version_cache,
         }
     }
 
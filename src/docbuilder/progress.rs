@@ -0,0 +1,129 @@
+//! Copies the log of the running build to the database while the build goes on, see
+//! `db::build_progress`
+
+use crate::db::{build_progress, Pool};
+use log::warn;
+use rustwide::logging::LogStorage;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub(crate) struct LogFlusher {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LogFlusher {
+    /// Starts copying the log every `interval`, until the flusher is dropped. The storage is
+    /// shared with the build, the log is read while it's being written.
+    pub(crate) fn start(
+        db: Pool,
+        name: &str,
+        version: &str,
+        target: &str,
+        storage: LogStorage,
+        interval: Duration,
+    ) -> Self {
+        let (stop, stopped) = channel();
+        let (name, version, target) = (name.to_owned(), version.to_owned(), target.to_owned());
+        // the length of the log already copied, only the lines after it are sent
+        let mut copied: Option<usize> = None;
+        let mut flush = move || {
+            let log = storage.to_string();
+            let result = db.get().map_err(failure::Error::from).and_then(|mut conn| {
+                let appended = match copied {
+                    Some(len) if log.is_char_boundary(len) => {
+                        build_progress::append(&mut conn, &name, &version, &target, &log[len..])?
+                    }
+                    _ => false,
+                };
+                if !appended {
+                    build_progress::record(&mut conn, &name, &version, &target, &log)?;
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => copied = Some(log.len()),
+                Err(err) => {
+                    warn!(
+                        "failed to record the progress of {} {}: {}",
+                        name, version, err
+                    );
+                }
+            }
+        };
+
+        let thread = std::thread::Builder::new()
+            .name("build progress".into())
+            .spawn(move || loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => flush(),
+                    // the final log is copied too, it's shown until the next target starts or
+                    // the build is stored
+                    _ => {
+                        flush();
+                        return;
+                    }
+                }
+            });
+        let thread = match thread {
+            Ok(thread) => Some(thread),
+            Err(err) => {
+                warn!("failed to start copying the build log: {}", err);
+                None
+            }
+        };
+
+        Self {
+            stop: Some(stop),
+            thread,
+        }
+    }
+}
+
+impl Drop for LogFlusher {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use log::LevelFilter;
+    use rustwide::logging;
+
+    #[test]
+    fn log_is_copied_while_building() {
+        wrapper(|env| {
+            let storage = LogStorage::new(LevelFilter::Info);
+            let flusher = LogFlusher::start(
+                env.db().pool(),
+                "foo",
+                "0.1.0",
+                "x86_64-unknown-linux-gnu",
+                storage.clone(),
+                Duration::from_millis(50),
+            );
+            logging::capture(&storage, || log::info!("Documenting foo"));
+            std::thread::sleep(Duration::from_millis(500));
+            let running = build_progress::running_build(&mut env.db().conn(), "foo", "0.1.0", 1)?;
+            assert_eq!(running.unwrap().log, "[INFO] Documenting foo\n");
+
+            // the last lines are copied when the build is over
+            logging::capture(&storage, || log::info!("Finished"));
+            drop(flusher);
+            let running = build_progress::running_build(&mut env.db().conn(), "foo", "0.1.0", 1)?;
+            assert_eq!(
+                running.unwrap().log,
+                "[INFO] Documenting foo\n[INFO] Finished\n"
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -2,15 +2,23 @@
 
 use super::{DocBuilder, PackageKind, RustwideBuilder};
 use crate::error::Result;
+use crate::index::sparse::SparseIndex;
 use crate::utils::get_crate_priority;
 use crate::Index;
+use chrono::{DateTime, Utc};
 use crates_index_diff::ChangeKind;
-use log::{debug, error};
+use log::{debug, error, info};
+use serde_json::Value;
+use std::collections::HashMap;
 
 impl DocBuilder {
     /// Updates registry index repository and adds new crates into build queue.
     /// Returns the number of crates added
     pub fn get_new_crates(&mut self, index: &Index) -> Result<usize> {
+        if let Some(sparse) = index.sparse_index() {
+            return self.get_new_crates_sparse(index, sparse);
+        }
+
         let mut conn = self.db.get()?;
         let diff = index.diff()?;
         let (mut changes, oid) = diff.peek_changes()?;
@@ -34,7 +42,10 @@ impl DocBuilder {
                         &[&krate.name, &krate.version],
                     );
                     match res {
-                        Ok(_) => debug!("{}-{} yanked", krate.name, krate.version),
+                        Ok(_) => {
+                            debug!("{}-{} yanked", krate.name, krate.version);
+                            self.version_cache.invalidate(&krate.name);
+                        }
                         Err(err) => error!(
                             "error while setting {}-{} to yanked: {}",
                             krate.name, krate.version, err
@@ -69,6 +80,93 @@ impl DocBuilder {
         Ok(crates_added)
     }
 
+    /// Looks at the index files of the crates updated since the last check, queues their versions
+    /// which are neither built nor queued yet, and updates the ones that were yanked or unyanked.
+    fn get_new_crates_sparse(&mut self, index: &Index, sparse: &SparseIndex) -> Result<usize> {
+        let mut conn = self.db.get()?;
+        let last_seen: Option<DateTime<Utc>> = conn
+            .query_opt(
+                "SELECT value FROM config WHERE name = 'sparse_index_last_seen'",
+                &[],
+            )?
+            .and_then(|row| row.get::<_, Value>(0).as_str()?.parse().ok());
+        let last_seen = match last_seen {
+            Some(last_seen) => last_seen,
+            None => {
+                // Without a previous check every crate would be looked at
+                info!("first check of the sparse index, only the next changes will be queued");
+                set_sparse_last_seen(&mut conn, Utc::now())?;
+                return Ok(0);
+            }
+        };
+
+        let updated = index.api().get_updated_crates(last_seen)?;
+        let mut crates_added = 0;
+
+        // Oldest first, so that the queue keeps the order of the publishes
+        for krate in updated.iter().rev() {
+            let releases: HashMap<String, bool> = conn
+                .query(
+                    "SELECT releases.version, releases.yanked
+                     FROM releases
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     WHERE crates.name = $1",
+                    &[&krate.name],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get::<_, Option<bool>>(1).unwrap_or(false)))
+                .collect();
+            let queued: Vec<String> = conn
+                .query("SELECT version FROM queue WHERE name = $1", &[&krate.name])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+
+            for version in sparse.versions(&krate.name)? {
+                match releases.get(&version.vers) {
+                    Some(&yanked) if yanked != version.yanked => {
+                        conn.execute(
+                            "UPDATE releases
+                                SET yanked = $3
+                            FROM crates
+                            WHERE crates.id = releases.crate_id
+                                AND name = $1
+                                AND version = $2",
+                            &[&krate.name, &version.vers, &version.yanked],
+                        )?;
+                        debug!("{}-{} yanked: {}", krate.name, version.vers, version.yanked);
+                        self.version_cache.invalidate(&krate.name);
+                    }
+                    Some(_) => {}
+                    None if version.yanked || queued.contains(&version.vers) => {}
+                    None => {
+                        let priority = get_crate_priority(&mut conn, &krate.name)?;
+                        match self.build_queue.add_crate(
+                            &krate.name,
+                            &version.vers,
+                            priority,
+                            index.repository_url(),
+                        ) {
+                            Ok(()) => {
+                                debug!("{}-{} added into build queue", krate.name, version.vers);
+                                crates_added += 1;
+                            }
+                            Err(err) => error!(
+                                "failed adding {}-{} into build queue: {}",
+                                krate.name, version.vers, err
+                            ),
+                        }
+                    }
+                }
+            }
+
+            // saved after every crate, so that a failure doesn't queue the same releases again
+            set_sparse_last_seen(&mut conn, krate.updated_at)?;
+        }
+
+        Ok(crates_added)
+    }
+
     /// Builds the top package from the queue. Returns whether there was a package in the queue.
     ///
     /// Note that this will return `Ok(true)` even if the package failed to build.
@@ -100,3 +198,173 @@ impl DocBuilder {
         Ok(processed)
     }
 }
+
+fn set_sparse_last_seen(conn: &mut postgres::Client, last_seen: DateTime<Utc>) -> Result<()> {
+    conn.execute(
+        "INSERT INTO config (name, value) VALUES ('sparse_index_last_seen', $1)
+         ON CONFLICT (name) DO UPDATE SET value = $1",
+        &[&Value::String(last_seen.to_rfc3339())],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use mockito::Matcher;
+
+    #[test]
+    fn sparse_index_changes() {
+        wrapper(|env| {
+            let _config = mockito::mock("GET", "/sparse-watcher/config.json")
+                .with_body(format!(
+                    r#"{{"dl": "https://static.crates.io/crates", "api": "{}"}}"#,
+                    mockito::server_url()
+                ))
+                .create();
+            let _updated = mockito::mock("GET", "/api/v1/crates")
+                .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+                .with_body(
+                    r#"{"crates": [
+                        {"name": "bar", "updated_at": "2021-03-01T00:00:00Z"},
+                        {"name": "foo", "updated_at": "2021-02-01T00:00:00Z"},
+                        {"name": "old", "updated_at": "2020-12-01T00:00:00Z"}
+                    ]}"#,
+                )
+                .create();
+            let _foo = mockito::mock("GET", "/sparse-watcher/3/f/foo")
+                .with_body(concat!(
+                    r#"{"name": "foo", "vers": "0.1.0", "yanked": true}"#,
+                    "\n",
+                    r#"{"name": "foo", "vers": "0.2.0", "yanked": false}"#,
+                ))
+                .create();
+            let _bar = mockito::mock("GET", "/sparse-watcher/3/b/bar")
+                .with_body(concat!(
+                    r#"{"name": "bar", "vers": "1.0.0", "yanked": false}"#,
+                    "\n",
+                    r#"{"name": "bar", "vers": "1.1.0", "yanked": true}"#,
+                ))
+                .create();
+
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            let index = Index::sparse(
+                env.config().registry_index_path.clone(),
+                format!("{}/sparse-watcher", mockito::server_url()).parse()?,
+                None,
+            )?;
+            let mut builder = DocBuilder::new(
+                env.config(),
+                env.db().pool(),
+                env.build_queue(),
+                env.version_cache(),
+            );
+
+            // the first check only records where to start from
+            assert_eq!(builder.get_new_crates(&index)?, 0);
+            env.db().conn().execute(
+                "UPDATE config SET value = '\"2021-01-01T00:00:00Z\"'
+                 WHERE name = 'sparse_index_last_seen'",
+                &[],
+            )?;
+
+            assert_eq!(builder.get_new_crates(&index)?, 2);
+            let queued: Vec<_> = env
+                .build_queue()
+                .queued_crates()?
+                .into_iter()
+                .map(|krate| (krate.name, krate.version))
+                .collect();
+            assert_eq!(
+                queued,
+                vec![
+                    ("foo".to_owned(), "0.2.0".to_owned()),
+                    ("bar".to_owned(), "1.0.0".to_owned()),
+                ]
+            );
+            let yanked: bool = env
+                .db()
+                .conn()
+                .query_one("SELECT yanked FROM releases", &[])?
+                .get(0);
+            assert!(yanked);
+
+            // nothing changed since the previous check
+            assert_eq!(builder.get_new_crates(&index)?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn sparse_index_pages_until_last_seen() {
+        wrapper(|env| {
+            let _config = mockito::mock("GET", "/sparse-pages/config.json")
+                .with_body(format!(
+                    r#"{{"dl": "https://static.crates.io/crates", "api": "{}"}}"#,
+                    mockito::server_url()
+                ))
+                .create();
+            // a full first page, the rest of the updates are on the next one
+            let first_page: Vec<_> = (0..100)
+                .map(|i| {
+                    format!(
+                        r#"{{"name": "crate-{}", "updated_at": "2021-03-01T00:00:{:02}Z"}}"#,
+                        i,
+                        59 - i % 60
+                    )
+                })
+                .collect();
+            let _first = mockito::mock("GET", "/api/v1/crates")
+                .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+                .with_body(format!(r#"{{"crates": [{}]}}"#, first_page.join(",")))
+                .create();
+            let _second = mockito::mock("GET", "/api/v1/crates")
+                .match_query(Matcher::UrlEncoded("page".into(), "2".into()))
+                .with_body(
+                    r#"{"crates": [
+                        {"name": "baz", "updated_at": "2021-02-01T00:00:00Z"},
+                        {"name": "old", "updated_at": "2020-12-01T00:00:00Z"}
+                    ]}"#,
+                )
+                .create();
+            let _crates = mockito::mock("GET", Matcher::Regex("^/sparse-pages/cr/at/".into()))
+                .with_status(404)
+                .create();
+            let _baz = mockito::mock("GET", "/sparse-pages/3/b/baz")
+                .with_body(r#"{"name": "baz", "vers": "0.1.0", "yanked": false}"#)
+                .create();
+
+            let index = Index::sparse(
+                env.config().registry_index_path.clone(),
+                format!("{}/sparse-pages", mockito::server_url()).parse()?,
+                None,
+            )?;
+            let mut builder = DocBuilder::new(
+                env.config(),
+                env.db().pool(),
+                env.build_queue(),
+                env.version_cache(),
+            );
+            set_sparse_last_seen(&mut env.db().conn(), "2021-01-01T00:00:00Z".parse()?)?;
+
+            assert_eq!(builder.get_new_crates(&index)?, 1);
+            let queued = env.build_queue().queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].name, "baz");
+
+            let last_seen: Value = env
+                .db()
+                .conn()
+                .query_one(
+                    "SELECT value FROM config WHERE name = 'sparse_index_last_seen'",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(last_seen, "2021-03-01T00:00:59+00:00");
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,183 @@
+//! Verification that builds are reproducible
+//!
+//! The hash of every documentation file is stored after each build. When a release is built
+//! again with the same toolchain and docs.rs version, the new documentation is compared with the
+//! previous one, and when `DOCSRS_VERIFY_REPRODUCIBLE_BUILDS` is set the builder documents the
+//! default target twice to compare them right away. The files that differ are counted in the
+//! metrics by extension, to find out what makes the documentation nondeterministic.
+//!
+//! The default target is documented at the root of the documentation, and the other targets in
+//! subdirectories named after them: the second build only covers the files of the default target.
+
+use crate::error::Result;
+use crate::{Metrics, Storage};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The SHA-256 of every file of the documentation, by path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest(BTreeMap<String, String>);
+
+impl Manifest {
+    pub(crate) fn from_dir(dir: &Path) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path().strip_prefix(dir)?;
+            let hash = Sha256::digest(&std::fs::read(entry.path())?);
+            files.insert(
+                path.to_string_lossy().replace('\\', "/"),
+                format!("{:x}", hash),
+            );
+        }
+        Ok(Manifest(files))
+    }
+
+    /// The files of the default target, without the subdirectories of the `other_targets`
+    pub(crate) fn default_target(&self, other_targets: &[&str]) -> Self {
+        Manifest(
+            self.0
+                .iter()
+                .filter(|(path, _)| {
+                    !other_targets.iter().any(|target| {
+                        path.strip_prefix(target)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                    })
+                })
+                .map(|(path, hash)| (path.clone(), hash.clone()))
+                .collect(),
+        )
+    }
+
+    fn storage_path(build_id: i32) -> String {
+        format!("build-manifests/{}.json", build_id)
+    }
+
+    pub(crate) fn store(&self, storage: &Storage, build_id: i32) -> Result<()> {
+        storage.store_one(Self::storage_path(build_id), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Deletes the manifest of a build, with its release
+    pub(crate) fn delete(storage: &Storage, build_id: i32) -> Result<()> {
+        storage.delete_prefix(&Self::storage_path(build_id))
+    }
+
+    /// `None` if no manifest was stored for the build
+    pub(crate) fn load(storage: &Storage, build_id: i32) -> Result<Option<Self>> {
+        let path = Self::storage_path(build_id);
+        if !storage.exists(&path)? {
+            return Ok(None);
+        }
+        let blob = storage.get(&path, usize::MAX)?;
+        Ok(Some(serde_json::from_slice(&blob.content)?))
+    }
+
+    /// The files that are missing from one of the manifests or have different contents
+    pub(crate) fn differences<'a>(&'a self, other: &'a Manifest) -> Vec<&'a str> {
+        let mut differences: Vec<&str> = self
+            .0
+            .iter()
+            .filter(|(path, hash)| other.0.get(*path) != Some(*hash))
+            .map(|(path, _)| path.as_str())
+            .collect();
+        differences.extend(
+            other
+                .0
+                .keys()
+                .filter(|path| !self.0.contains_key(*path))
+                .map(String::as_str),
+        );
+        differences
+    }
+}
+
+/// Compares the documentation of a build with a previous one and records the outcome in the
+/// metrics, returning whether they are identical
+pub(crate) fn compare(
+    metrics: &Metrics,
+    name: &str,
+    version: &str,
+    manifest: &Manifest,
+    previous: &Manifest,
+) -> bool {
+    let differences = manifest.differences(previous);
+    if differences.is_empty() {
+        metrics
+            .reproducibility_checks
+            .with_label_values(&["reproducible"])
+            .inc();
+        return true;
+    }
+
+    info!(
+        "the documentation of {} {} isn't reproducible, {} files differ: {}",
+        name,
+        version,
+        differences.len(),
+        differences.join(", ")
+    );
+    metrics
+        .reproducibility_checks
+        .with_label_values(&["nondeterministic"])
+        .inc();
+    for path in differences {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| ["html", "js", "css", "json"].contains(extension))
+            .unwrap_or("other");
+        metrics
+            .nondeterministic_files
+            .with_label_values(&[extension])
+            .inc();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_differences() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("foo"))?;
+        std::fs::write(dir.path().join("foo/index.html"), "<html>")?;
+        std::fs::write(dir.path().join("search-index.js"), "[]")?;
+
+        let manifest = Manifest::from_dir(dir.path())?;
+        assert_eq!(manifest.0.len(), 2);
+        assert!(manifest.differences(&manifest.clone()).is_empty());
+
+        std::fs::write(dir.path().join("search-index.js"), "[1]")?;
+        std::fs::write(dir.path().join("settings.html"), "")?;
+        std::fs::remove_file(dir.path().join("foo/index.html"))?;
+        let changed = Manifest::from_dir(dir.path())?;
+        let mut differences = manifest.differences(&changed);
+        differences.sort_unstable();
+        assert_eq!(
+            differences,
+            vec!["foo/index.html", "search-index.js", "settings.html"]
+        );
+
+        std::fs::create_dir(dir.path().join("i686-pc-windows-msvc"))?;
+        std::fs::write(dir.path().join("i686-pc-windows-msvc/settings.html"), "")?;
+        std::fs::write(dir.path().join("i686-pc-windows-msvc.html"), "")?;
+        let default_target =
+            Manifest::from_dir(dir.path())?.default_target(&["i686-pc-windows-msvc"]);
+        // only the directory named after the target is another target
+        assert_eq!(
+            changed.differences(&default_target),
+            vec!["i686-pc-windows-msvc.html"]
+        );
+
+        Ok(())
+    }
+}
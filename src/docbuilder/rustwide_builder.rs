@@ -1,21 +1,29 @@
+use crate::db::blacklist::is_blacklisted;
+use crate::db::build_progress;
 use crate::db::file::add_path_into_database;
-use crate::db::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
-    update_crate_data_in_database, Pool,
+use crate::db::Pool;
+use crate::docbuilder::{
+    crates::crates_from_path,
+    diagnostics::{parse_output_line, BuildDiagnostic, OutputLine},
+    disk_usage::{available_space, DiskUsageMonitor},
+    progress::LogFlusher,
+    reproducibility::Manifest,
+    upload::{BuildOutput, BuildUploader},
+    Limits,
 };
-use crate::docbuilder::{crates::crates_from_path, Limits};
 use crate::error::Result;
-use crate::index::api::ReleaseData;
-use crate::repositories::RepositoryStatsUpdater;
-use crate::storage::CompressionAlgorithms;
-use crate::utils::{copy_dir_all, parse_rustc_version, CargoMetadata};
-use crate::{db::blacklist::is_blacklisted, utils::MetadataPackage};
-use crate::{Config, Context, Index, Metrics, Storage};
+use crate::utils::{
+    asset_integrity, copy_dir_all, definitions::Definitions, item_index::ItemIndex,
+    parse_rustc_version, rustdoc_json, CargoMetadata,
+};
+use crate::{Config, Context, Metrics, Storage};
 use docsrs_metadata::{Metadata, DEFAULT_TARGETS, HOST_TARGET};
 use failure::ResultExt;
 use log::{debug, info, warn, LevelFilter};
 use postgres::Client;
-use rustwide::cmd::{Command, CommandError, SandboxBuilder, SandboxImage};
+use rustwide::cmd::{
+    Binary, Command, CommandError, ProcessLinesActions, Runnable, SandboxBuilder, SandboxImage,
+};
 use rustwide::logging::{self, LogStorage};
 use rustwide::toolchain::ToolchainError;
 use rustwide::{Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
@@ -23,6 +31,7 @@ use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const DUMMY_CRATE_NAME: &str = "empty-library";
@@ -41,12 +50,22 @@ pub struct RustwideBuilder {
     db: Pool,
     storage: Arc<Storage>,
     metrics: Arc<Metrics>,
-    index: Arc<Index>,
     rustc_version: String,
-    repository_stats_updater: Arc<RepositoryStatsUpdater>,
+    /// The targets rustup can install for the toolchain, empty if they couldn't be listed
+    supported_targets: HashSet<String>,
+    uploader: BuildUploader,
     skip_build_if_exists: bool,
 }
 
+/// The rustup binary of the workspace, which rustwide doesn't expose
+struct Rustup;
+
+impl Runnable for Rustup {
+    fn name(&self) -> Binary {
+        Binary::ManagedByRustwide("rustup".into())
+    }
+}
+
 impl RustwideBuilder {
     pub fn init(context: &dyn Context) -> Result<Self> {
         let config = context.config()?;
@@ -77,9 +96,9 @@ impl RustwideBuilder {
             db: context.pool()?,
             storage: context.storage()?,
             metrics: context.metrics()?,
-            index: context.index()?,
             rustc_version: String::new(),
-            repository_stats_updater: context.repository_stats_updater()?,
+            supported_targets: HashSet::new(),
+            uploader: BuildUploader::new(context)?,
             skip_build_if_exists: false,
         })
     }
@@ -151,23 +170,74 @@ impl RustwideBuilder {
         if old_version.as_deref() != Some(&self.rustc_version) {
             self.add_essential_files()?;
         }
+        if old_version.as_deref() != Some(&self.rustc_version) || self.supported_targets.is_empty()
+        {
+            // the targets are still built when they can't be listed, failing if they don't exist
+            self.supported_targets = self.detect_supported_targets().unwrap_or_else(|err| {
+                warn!("failed to list the targets supported by rustup: {}", err);
+                HashSet::new()
+            });
+        }
 
         Ok(())
     }
 
+    /// The targets rustup can install for the toolchain, tier 3 targets are missing
+    fn detect_supported_targets(&self) -> Result<HashSet<String>> {
+        let res = Command::new(&self.workspace, &Rustup)
+            .args(&["target", "list", "--toolchain", &self.toolchain.to_string()])
+            .log_output(false)
+            .run_capture()?;
+        // the lines look like `x86_64-unknown-linux-gnu (installed)`
+        Ok(res
+            .stdout_lines()
+            .iter()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    /// Warnings for the targets requested by the crate that can't be built, suggesting the
+    /// closest supported target when they look misspelled
+    fn unsupported_target_warnings(&self, unsupported: &[&str]) -> Vec<String> {
+        unsupported
+            .iter()
+            .map(|target| {
+                let suggestion = self
+                    .supported_targets
+                    .iter()
+                    .map(|supported| (strsim::levenshtein(target, supported), supported))
+                    .filter(|(distance, _)| *distance <= 3)
+                    .min()
+                    .map(|(_, supported)| format!(", did you mean `{}`?", supported))
+                    .unwrap_or_default();
+                format!(
+                    "the target `{}` is not supported by {} and was not built{}",
+                    target, self.rustc_version, suggestion
+                )
+            })
+            .collect()
+    }
+
     fn detect_rustc_version(&self) -> Result<String> {
-        info!("detecting rustc's version...");
-        let res = Command::new(&self.workspace, self.toolchain.rustc())
+        self.detect_version("rustc")
+    }
+
+    /// Runs `<binary> --version` with the toolchain used for the builds
+    fn detect_version(&self, binary: &'static str) -> Result<String> {
+        info!("detecting {}'s version...", binary);
+        let res = Command::new(&self.workspace, self.toolchain.rustup_binary(binary))
             .args(&["--version"])
             .log_output(false)
             .run_capture()?;
         let mut iter = res.stdout_lines().iter();
         if let (Some(line), None) = (iter.next(), iter.next()) {
-            info!("found rustc {}", line);
+            info!("found {} {}", binary, line);
             Ok(line.clone())
         } else {
-            Err(::failure::err_msg(
-                "invalid output returned by `rustc --version`",
+            Err(::failure::format_err!(
+                "invalid output returned by `{} --version`",
+                binary
             ))
         }
     }
@@ -206,12 +276,20 @@ impl RustwideBuilder {
                     .prefix("essential-files")
                     .tempdir()?;
                 copy_dir_all(source, &dest)?;
-                add_path_into_database(&self.storage, "", &dest)?;
+                add_path_into_database(&self.storage, "", &dest, None)?;
+                asset_integrity::record_shared_assets(&mut conn, dest.path())?;
                 conn.query(
                     "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
                      ON CONFLICT (name) DO UPDATE SET value = $1;",
                     &[&Value::String(self.rustc_version.clone())],
                 )?;
+                // shown on the about page, rustdoc comes with rustc so it only changes with it
+                let rustdoc_version = self.detect_version("rustdoc")?;
+                conn.query(
+                    "INSERT INTO config (name, value) VALUES ('rustdoc_version', $1) \
+                     ON CONFLICT (name) DO UPDATE SET value = $1;",
+                    &[&Value::String(rustdoc_version)],
+                )?;
 
                 Ok(())
             })?;
@@ -269,6 +347,8 @@ impl RustwideBuilder {
         }
 
         let limits = Limits::for_crate(&mut conn, name)?;
+        // don't keep a connection of the pool for the whole build
+        drop(conn);
         #[cfg(target_os = "linux")]
         if !self.config.disable_memory_limit {
             let mem_info = procfs::Meminfo::new().context("failed to read /proc/meminfo")?;
@@ -306,16 +386,29 @@ impl RustwideBuilder {
                 use docsrs_metadata::BuildTargets;
 
                 let mut has_docs = false;
+                let mut verification_manifest = None;
                 let mut successful_targets = Vec::new();
                 let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
+                let mut targets = metadata.targets(self.config.include_default_targets);
+                let warnings = if self.supported_targets.is_empty() {
+                    Vec::new()
+                } else {
+                    let unsupported = targets
+                        .remove_unsupported(|target| self.supported_targets.contains(target));
+                    self.unsupported_target_warnings(&unsupported)
+                };
+                for warning in &warnings {
+                    warn!("{} {}: {}", name, version, warning);
+                }
                 let BuildTargets {
                     default_target,
                     other_targets,
-                } = metadata.targets(self.config.include_default_targets);
+                } = targets;
 
                 // Perform an initial build
-                let res =
+                let mut res =
                     self.execute_build(default_target, true, build, &limits, &metadata, false)?;
+                res.result.warnings = warnings;
                 if res.result.successful {
                     if let Some(name) = res.cargo_metadata.root().library_name() {
                         let host_target = build.host_target_dir();
@@ -323,18 +416,28 @@ impl RustwideBuilder {
                     }
                 }
 
-                let mut algs = HashSet::new();
                 if has_docs {
                     debug!("adding documentation for the default target to the database");
-                    self.copy_docs(&build.host_target_dir(), local_storage.path(), "", true)?;
+                    self.copy_docs(
+                        &build.host_target_dir(),
+                        local_storage.path(),
+                        "",
+                        true,
+                        &metadata,
+                    )?;
 
                     successful_targets.push(res.target.clone());
 
+                    if self.config.verify_reproducible_builds {
+                        verification_manifest =
+                            self.verify_reproducibility(build, &res.target, &limits, &metadata)?;
+                    }
+
                     // Then build the documentation for all the targets
                     // Limit the number of targets so that no one can try to build all 200000 possible targets
                     for target in other_targets.into_iter().take(limits.targets()) {
                         debug!("building package {} {} for {}", name, version, target);
-                        self.build_target(
+                        let peak_disk_usage = self.build_target(
                             target,
                             build,
                             &limits,
@@ -342,69 +445,81 @@ impl RustwideBuilder {
                             &mut successful_targets,
                             &metadata,
                         )?;
+                        res.result.peak_disk_usage =
+                            res.result.peak_disk_usage.max(peak_disk_usage);
                     }
-                    let new_algs = self.upload_docs(name, version, local_storage.path())?;
-                    algs.extend(new_algs);
                 };
 
-                // Store the sources even if the build fails
-                debug!("adding sources into database");
-                let prefix = format!("sources/{}/{}", name, version);
-                let (files_list, new_algs) =
-                    add_path_into_database(&self.storage, &prefix, build.host_source_dir())?;
-                algs.extend(new_algs);
-
-                let has_examples = build.host_source_dir().join("examples").is_dir();
-                if res.result.successful {
-                    self.metrics.successful_builds.inc();
-                } else if res.cargo_metadata.root().is_library() {
-                    self.metrics.failed_builds.inc();
-                } else {
-                    self.metrics.non_library_builds.inc();
-                }
-
-                let release_data = match self.index.api().get_release_data(name, version) {
-                    Ok(data) => data,
-                    Err(err) => {
-                        warn!("{:#?}", err);
-                        ReleaseData::default()
+                // uploading millions of files takes longer than the build itself, so the
+                // documentation is discarded before trying to upload it
+                if has_docs {
+                    let files = count_files(local_storage.path())?;
+                    if files > limits.max_files() {
+                        warn!(
+                            "not uploading the documentation of {} {}: {} files",
+                            name, version, files
+                        );
+                        res.build_log.push_str(&format!(
+                            "\n[docs.rs] the documentation has {} files, more than the limit of {}\n",
+                            files,
+                            limits.max_files()
+                        ));
+                        has_docs = false;
+                        successful_targets.clear();
+                        res.result.successful = false;
+                        res.result.failure = Some(BuildFailure::TooManyFiles);
                     }
-                };
-
-                let cargo_metadata = res.cargo_metadata.root();
-                let repository = self.get_repo(cargo_metadata)?;
-
-                let release_id = add_package_into_database(
-                    &mut conn,
-                    cargo_metadata,
-                    &build.host_source_dir(),
-                    &res.result,
-                    &res.target,
-                    files_list,
-                    successful_targets,
-                    &release_data,
-                    has_docs,
-                    has_examples,
-                    algs,
-                    repository,
-                )?;
-
-                if let Some(doc_coverage) = res.doc_coverage {
-                    add_doc_coverage(&mut conn, release_id, doc_coverage)?;
                 }
 
-                let build_id = add_build_into_database(&mut conn, release_id, &res.result)?;
-                let build_log_path = format!("build-logs/{}/{}.txt", build_id, default_target);
-                self.storage.store_one(build_log_path, res.build_log)?;
-
-                // Some crates.io crate data is mutable, so we proactively update it during a release
-                match self.index.api().get_crate_data(name) {
-                    Ok(crate_data) => update_crate_data_in_database(&mut conn, name, &crate_data)?,
-                    Err(err) => warn!("{:#?}", err),
+                let mut rustdoc_json = Vec::new();
+                if has_docs && self.config.rustdoc_json {
+                    if let Some(library_name) = res.cargo_metadata.root().library_name() {
+                        rustdoc_json = self.get_rustdoc_json(
+                            &successful_targets,
+                            build,
+                            &metadata,
+                            &limits,
+                            &library_name,
+                        );
+                    }
                 }
 
-                Ok(res.result.successful)
-            })?;
+                let successful = res.result.successful;
+                self.uploader.upload(BuildOutput {
+                    package: res.cargo_metadata.root(),
+                    source_dir: &build.host_source_dir(),
+                    docs_dir: if has_docs {
+                        Some(local_storage.path())
+                    } else {
+                        None
+                    },
+                    result: res.result,
+                    default_target: &res.target,
+                    successful_targets,
+                    doc_coverage: res.doc_coverage,
+                    definitions: res.definitions,
+                    item_index: res.item_index,
+                    rustdoc_json,
+                    build_log: res.build_log,
+                    verification_manifest,
+                })?;
+
+                Ok(successful)
+            });
+        if self.config.build_progress_interval > 0 {
+            let cleared = self
+                .db
+                .get()
+                .map_err(failure::Error::from)
+                .and_then(|mut conn| build_progress::clear(&mut conn, name, version));
+            if let Err(err) = cleared {
+                warn!(
+                    "failed to clear the progress of {} {}: {}",
+                    name, version, err
+                );
+            }
+        }
+        let successful = successful?;
 
         build_dir.purge()?;
         krate.purge_from_cache(&self.workspace)?;
@@ -412,6 +527,38 @@ impl RustwideBuilder {
         Ok(successful)
     }
 
+    /// Documents the default target a second time, returning the manifest of the documentation
+    /// to compare it with the first build. `None` if the second build failed.
+    fn verify_reproducibility(
+        &self,
+        build: &Build,
+        target: &str,
+        limits: &Limits,
+        metadata: &Metadata,
+    ) -> Result<Option<Manifest>> {
+        debug!("documenting {} again to verify reproducibility", target);
+        std::fs::remove_dir_all(build.host_target_dir().join("doc"))?;
+        let res = self.execute_build(target, true, build, limits, metadata, false)?;
+        if !res.result.successful {
+            warn!("the verification build failed, skipping the reproducibility check");
+            return Ok(None);
+        }
+
+        let verification_storage = tempfile::Builder::new()
+            .prefix("docsrs-verification")
+            .tempdir()?;
+        self.copy_docs(
+            &build.host_target_dir(),
+            verification_storage.path(),
+            "",
+            true,
+            metadata,
+        )?;
+        let manifest = Manifest::from_dir(verification_storage.path())?;
+        verification_storage.close()?;
+        Ok(Some(manifest))
+    }
+
     fn build_target(
         &self,
         target: &str,
@@ -420,7 +567,7 @@ impl RustwideBuilder {
         local_storage: &Path,
         successful_targets: &mut Vec<String>,
         metadata: &Metadata,
-    ) -> Result<()> {
+    ) -> Result<Option<u64>> {
         let target_res = self.execute_build(target, false, build, limits, metadata, false)?;
         if target_res.result.successful {
             // Cargo is not giving any error and not generating documentation of some crates
@@ -428,11 +575,17 @@ impl RustwideBuilder {
             // adding target to successfully_targets.
             if build.host_target_dir().join(target).join("doc").is_dir() {
                 debug!("adding documentation for target {} to the database", target,);
-                self.copy_docs(&build.host_target_dir(), local_storage, target, false)?;
+                self.copy_docs(
+                    &build.host_target_dir(),
+                    local_storage,
+                    target,
+                    false,
+                    metadata,
+                )?;
                 successful_targets.push(target.to_string());
             }
         }
-        Ok(())
+        Ok(target_res.result.peak_disk_usage)
     }
 
     fn get_coverage(
@@ -463,7 +616,7 @@ impl RustwideBuilder {
             items_with_examples: 0,
         };
 
-        self.prepare_command(build, target, metadata, limits, rustdoc_flags)?
+        self.prepare_command(build, target, metadata, limits, Vec::new(), rustdoc_flags)?
             .process_lines(&mut |line, _| {
                 if line.starts_with('{') && line.ends_with('}') {
                     let parsed = match serde_json::from_str::<HashMap<String, FileCoverage>>(line) {
@@ -490,6 +643,83 @@ impl RustwideBuilder {
         )
     }
 
+    /// Runs rustdoc with `--output-format json` to find where the items of the library are
+    /// defined, including the private ones
+    fn get_definitions(
+        &self,
+        target: &str,
+        build: &Build,
+        metadata: &Metadata,
+        limits: &Limits,
+        library_name: &str,
+    ) -> Result<(Option<Definitions>, Option<ItemIndex>)> {
+        let json = self.run_rustdoc_json(target, build, metadata, limits, library_name, true)?;
+
+        let definitions = Definitions::from_rustdoc_json(&json)?;
+        let item_index = ItemIndex::from_rustdoc_json(&json)?;
+        Ok((
+            Some(definitions).filter(|definitions| !definitions.is_empty()),
+            Some(item_index).filter(|item_index| !item_index.is_empty()),
+        ))
+    }
+
+    /// Runs rustdoc with `--output-format json`, returning its output
+    fn run_rustdoc_json(
+        &self,
+        target: &str,
+        build: &Build,
+        metadata: &Metadata,
+        limits: &Limits,
+        library_name: &str,
+        document_private_items: bool,
+    ) -> Result<Vec<u8>> {
+        let mut rustdoc_flags = vec!["--output-format".to_string(), "json".to_string()];
+        if document_private_items {
+            rustdoc_flags.push("--document-private-items".to_string());
+        }
+        self.prepare_command(build, target, metadata, limits, Vec::new(), rustdoc_flags)?
+            .log_output(false)
+            .run()?;
+
+        let doc_dir = if target == HOST_TARGET {
+            build.host_target_dir().join("doc")
+        } else {
+            build.host_target_dir().join(target).join("doc")
+        };
+        let json_path = doc_dir.join(format!("{}.json", library_name));
+        let json = std::fs::read(&json_path)?;
+        // the JSON isn't part of the documentation that's uploaded
+        std::fs::remove_file(&json_path)?;
+        Ok(json)
+    }
+
+    /// The compressed JSON output of the public items of every documented target. A target
+    /// without it doesn't fail the build.
+    fn get_rustdoc_json(
+        &self,
+        targets: &[String],
+        build: &Build,
+        metadata: &Metadata,
+        limits: &Limits,
+        library_name: &str,
+    ) -> Vec<(String, Vec<u8>)> {
+        let mut outputs = Vec::new();
+        for target in targets {
+            let output = self
+                .run_rustdoc_json(target, build, metadata, limits, library_name, false)
+                .and_then(|json| rustdoc_json::compress_json(&json));
+            match output {
+                Ok(compressed) => outputs.push((target.clone(), compressed)),
+                Err(err) => log::info!(
+                    "error when trying to get the rustdoc JSON of {}: {}",
+                    target,
+                    err
+                ),
+            }
+        }
+        outputs
+    }
+
     fn execute_build(
         &self,
         target: &str,
@@ -516,6 +746,9 @@ impl RustwideBuilder {
         let mut storage = LogStorage::new(LevelFilter::Info);
         storage.set_max_size(limits.max_log_size());
 
+        let disk_usage_monitor =
+            DiskUsageMonitor::start(build.host_target_dir(), limits.disk_space() as u64);
+
         // we have to run coverage before the doc-build because currently it
         // deletes the doc-target folder.
         // https://github.com/rust-lang/cargo/issues/9447
@@ -528,12 +761,88 @@ impl RustwideBuilder {
             }
         };
 
-        let successful = logging::capture(&storage, || {
-            self.prepare_command(build, target, metadata, limits, rustdoc_flags)
-                .and_then(|command| command.run().map_err(failure::Error::from))
-                .is_ok()
+        // only the default target is linked from the source browser and resolved by the API, the
+        // extra rustdoc run only happens when the JSON output is enabled
+        let library_name = cargo_metadata.root().library_name();
+        let (definitions, item_index) = match library_name {
+            Some(library_name) if is_default_target && self.config.rustdoc_json => {
+                match self.get_definitions(target, build, metadata, limits, &library_name) {
+                    Ok(definitions) => definitions,
+                    Err(err) => {
+                        log::info!("error when trying to get the definitions: {}", err);
+                        (None, None)
+                    }
+                }
+            }
+            _ => (None, None),
+        };
+
+        // the diagnostics are printed as JSON to record them, and replaced by their human
+        // readable version in the log
+        let package_id = &cargo_metadata.root().id;
+        let mut diagnostics = Vec::new();
+        let mut process_diagnostics =
+            |line: &str, actions: &mut ProcessLinesActions| match parse_output_line(
+                line, package_id,
+            ) {
+                OutputLine::Text => {}
+                OutputLine::Diagnostic {
+                    rendered,
+                    diagnostic,
+                } => {
+                    match rendered {
+                        Some(rendered) => actions.replace_with_lines(rendered.lines()),
+                        None => actions.remove_line(),
+                    }
+                    diagnostics.extend(diagnostic);
+                }
+                OutputLine::Ignored => actions.remove_line(),
+            };
+        let progress = if self.config.build_progress_interval > 0 {
+            let package = cargo_metadata.root();
+            Some(LogFlusher::start(
+                self.db.clone(),
+                &package.name,
+                &package.version,
+                target,
+                storage.clone(),
+                Duration::from_secs(self.config.build_progress_interval),
+            ))
+        } else {
+            None
+        };
+        let mut successful = logging::capture(&storage, || {
+            self.prepare_command(
+                build,
+                target,
+                metadata,
+                limits,
+                vec!["--message-format=json".into()],
+                rustdoc_flags,
+            )
+            .and_then(|command| {
+                command
+                    .process_lines(&mut process_diagnostics)
+                    .run()
+                    .map_err(failure::Error::from)
+            })
+            .is_ok()
         });
 
+        let disk_usage = disk_usage_monitor.finish();
+        let mut failure = None;
+        if disk_usage.quota_exceeded {
+            successful = false;
+            failure = Some(BuildFailure::DiskQuotaExceeded);
+            logging::capture(&storage, || {
+                log::error!(
+                    "the build was aborted because it used more than {} bytes of disk space",
+                    limits.disk_space()
+                )
+            });
+        }
+        drop(progress);
+
         // If we're passed a default_target which requires a cross-compile,
         // cargo will put the output in `target/<target>/doc`.
         // However, if this is the default build, we don't want it there,
@@ -553,8 +862,16 @@ impl RustwideBuilder {
                 rustc_version: self.rustc_version.clone(),
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
+                peak_disk_usage: Some(disk_usage.peak),
+                failure,
+                limits: Some(limits.clone()),
+                warnings: Vec::new(),
+                diagnostics,
+                no_source: metadata.no_source(),
             },
             doc_coverage,
+            definitions,
+            item_index,
             cargo_metadata,
             build_log: storage.to_string(),
             target: target.to_string(),
@@ -567,7 +884,8 @@ impl RustwideBuilder {
         target: &str,
         metadata: &Metadata,
         limits: &Limits,
-        mut rustdoc_flags_extras: Vec<String>,
+        cargo_flags_extras: Vec<String>,
+        rustdoc_flags_extras: Vec<String>,
     ) -> Result<Command<'ws, 'pl>> {
         // If the explicit target is not a tier one target, we need to install it.
         if !docsrs_metadata::DEFAULT_TARGETS.contains(&target) {
@@ -575,40 +893,13 @@ impl RustwideBuilder {
             self.toolchain.add_target(&self.workspace, target)?;
         }
 
-        // Add docs.rs specific arguments
-        let mut cargo_args = vec![
-            // We know that `metadata` unconditionally passes `-Z rustdoc-map`.
-            // Don't copy paste this, since that fact is not stable and may change in the future.
-            "-Zunstable-options".into(),
-            // Add `target` so that if a dependency has target-specific docs, this links to them properly.
-            //
-            // Note that this includes the target even if this is the default, since the dependency
-            // may have a different default (and the web backend will take care of redirecting if
-            // necessary).
-            //
-            // FIXME: host-only crates like proc-macros should probably not have this passed? but #1417 should make it OK
-            format!(
-                r#"--config=doc.extern-map.registries.crates-io="https://docs.rs/{{pkg_name}}/{{version}}/{}""#,
-                target
-            ),
-        ];
-        if let Some(cpu_limit) = self.config.build_cpu_limit {
-            cargo_args.push(format!("-j{}", cpu_limit));
-        }
-        if target != HOST_TARGET {
-            cargo_args.push("--target".into());
-            cargo_args.push(target.into());
-        };
-
-        #[rustfmt::skip]
-        const UNCONDITIONAL_ARGS: &[&str] = &[
-            "--static-root-path", "/",
-            "--cap-lints", "warn",
-            "--disable-per-crate-search",
-        ];
-
-        rustdoc_flags_extras.extend(UNCONDITIONAL_ARGS.iter().map(|&s| s.to_owned()));
-        let cargo_args = metadata.cargo_args(&cargo_args, &rustdoc_flags_extras);
+        let cargo_args = cargo_args(
+            &self.config,
+            metadata,
+            target,
+            cargo_flags_extras,
+            rustdoc_flags_extras,
+        );
 
         let mut command = build
             .cargo()
@@ -628,6 +919,7 @@ impl RustwideBuilder {
         local_storage: &Path,
         target: &str,
         is_default_target: bool,
+        metadata: &Metadata,
     ) -> Result<()> {
         let source = target_dir.join(target).join("doc");
 
@@ -642,22 +934,33 @@ impl RustwideBuilder {
         }
 
         info!("{} {}", source.display(), dest.display());
-        copy_dir_all(source, dest).map_err(Into::into)
+        copy_dir_all(source, &dest)?;
+
+        let source_pages = dest.join("src");
+        if metadata.no_source() && source_pages.is_dir() {
+            debug!("removing the source pages in {}", source_pages.display());
+            std::fs::remove_dir_all(source_pages)?;
+        }
+        Ok(())
     }
 
-    fn upload_docs(
-        &self,
-        name: &str,
-        version: &str,
-        local_storage: &Path,
-    ) -> Result<CompressionAlgorithms> {
-        debug!("Adding documentation into database");
-        add_path_into_database(
-            &self.storage,
-            &format!("rustdoc/{}/{}", name, version),
-            local_storage,
-        )
-        .map(|t| t.1)
+    /// Records the disk space available in the workspace, and checks whether it's above
+    /// `DOCSRS_BUILD_MIN_FREE_DISK_SPACE`.
+    pub fn has_enough_disk_space(&self) -> Result<bool> {
+        let available = available_space(&self.config.rustwide_workspace)?;
+        self.metrics.builder_free_disk_space.set(available as i64);
+
+        Ok(match self.config.build_min_free_disk_space {
+            Some(min) if available < min => {
+                warn!(
+                    "only {} MiB of disk space left in the workspace, {} MiB are needed",
+                    available / 1024 / 1024,
+                    min / 1024 / 1024
+                );
+                false
+            }
+            _ => true,
+        })
     }
 
     fn should_build(&self, conn: &mut Client, name: &str, version: &str) -> Result<bool> {
@@ -676,10 +979,51 @@ impl RustwideBuilder {
             Ok(true)
         }
     }
+}
 
-    fn get_repo(&self, metadata: &MetadataPackage) -> Result<Option<i32>> {
-        self.repository_stats_updater.load_repository(metadata)
+/// The arguments passed to `cargo` to document a crate for `target`
+pub(crate) fn cargo_args(
+    config: &Config,
+    metadata: &Metadata,
+    target: &str,
+    cargo_flags_extras: Vec<String>,
+    mut rustdoc_flags_extras: Vec<String>,
+) -> Vec<String> {
+    // Add docs.rs specific arguments
+    let mut cargo_args = vec![
+        // We know that `metadata` unconditionally passes `-Z rustdoc-map`.
+        // Don't copy paste this, since that fact is not stable and may change in the future.
+        "-Zunstable-options".into(),
+        // Add `target` so that if a dependency has target-specific docs, this links to them properly.
+        //
+        // Note that this includes the target even if this is the default, since the dependency
+        // may have a different default (and the web backend will take care of redirecting if
+        // necessary).
+        //
+        // FIXME: host-only crates like proc-macros should probably not have this passed? but #1417 should make it OK
+        format!(
+            r#"--config=doc.extern-map.registries.crates-io="https://docs.rs/{{pkg_name}}/{{version}}/{}""#,
+            target
+        ),
+    ];
+    if let Some(cpu_limit) = config.build_cpu_limit {
+        cargo_args.push(format!("-j{}", cpu_limit));
     }
+    if target != HOST_TARGET {
+        cargo_args.push("--target".into());
+        cargo_args.push(target.into());
+    };
+    cargo_args.extend(cargo_flags_extras);
+
+    #[rustfmt::skip]
+    const UNCONDITIONAL_ARGS: &[&str] = &[
+        "--static-root-path", "/",
+        "--cap-lints", "warn",
+        "--disable-per-crate-search",
+    ];
+
+    rustdoc_flags_extras.extend(UNCONDITIONAL_ARGS.iter().map(|&s| s.to_owned()));
+    metadata.cargo_args(&cargo_args, &rustdoc_flags_extras)
 }
 
 struct FullBuildResult {
@@ -687,9 +1031,21 @@ struct FullBuildResult {
     target: String,
     cargo_metadata: CargoMetadata,
     doc_coverage: Option<DocCoverage>,
+    definitions: Option<Definitions>,
+    item_index: Option<ItemIndex>,
     build_log: String,
 }
 
+fn count_files(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        if entry?.file_type().is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 #[derive(Clone, Copy)]
 pub(crate) struct DocCoverage {
     /// The total items that could be documented in the current crate, used to calculate
@@ -708,6 +1064,40 @@ pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) successful: bool,
+    /// The largest size of the target directory during the build, in bytes
+    pub(crate) peak_disk_usage: Option<u64>,
+    /// Why the build failed, if docs.rs aborted it
+    pub(crate) failure: Option<BuildFailure>,
+    /// The limits of the sandbox the build ran in
+    pub(crate) limits: Option<Limits>,
+    /// Problems with the configuration of the crate that didn't prevent the build
+    pub(crate) warnings: Vec<String>,
+    /// The warnings and errors rustdoc emitted while documenting the crate
+    pub(crate) diagnostics: Vec<BuildDiagnostic>,
+    /// The source pages were removed from the documentation, as asked by `no-source` in the
+    /// metadata of the crate
+    pub(crate) no_source: bool,
+}
+
+/// The reasons docs.rs can abort a build for, stored in the `failure_category` of the build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BuildFailure {
+    /// The build directory grew larger than the disk quota of the crate
+    DiskQuotaExceeded,
+    /// The documentation was larger than the upload limits of the crate
+    UploadSizeExceeded,
+    /// The documentation had more files than the limit of the crate
+    TooManyFiles,
+}
+
+impl BuildFailure {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            BuildFailure::DiskQuotaExceeded => "disk-quota-exceeded",
+            BuildFailure::UploadSizeExceeded => "upload-size-exceeded",
+            BuildFailure::TooManyFiles => "too-many-files",
+        }
+    }
 }
 
 #[cfg(test)]
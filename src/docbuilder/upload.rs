@@ -0,0 +1,719 @@
+//! Storage of the build results
+//!
+//! Once rustwide is done, the documentation and the sources are uploaded and the release is
+//! recorded in the database. This doesn't depend on rustwide, so the test suite runs the same
+//! code on fake builds (see `test::fakes::FakeBuilder`).
+
+use super::events::BuildEvents;
+use super::reproducibility::{self, Manifest};
+use super::{BuildFailure, BuildResult, DocCoverage, Limits};
+use crate::db::file::add_path_into_database;
+use crate::db::{
+    add_build_into_database, add_citation, add_doc_coverage, add_package_into_database,
+    dependency_links, update_crate_data_in_database, Pool,
+};
+use crate::error::Result;
+use crate::index::api::ReleaseData;
+use crate::repositories::RepositoryStatsUpdater;
+use crate::storage::{StoredSize, UploadSizeExceeded};
+use crate::utils::{
+    citation::Citation,
+    definitions::Definitions,
+    item_index::ItemIndex,
+    pubsubhubbub, rustdoc_json,
+    storage_stats::{self, DocSizes},
+    MetadataPackage,
+};
+use crate::{Context, Index, Metrics, ReleasesCache, Storage, VersionCache};
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Everything a build of a crate produced
+pub(crate) struct BuildOutput<'a> {
+    pub(crate) package: &'a MetadataPackage,
+    /// The crate sources, stored even if the build failed
+    pub(crate) source_dir: &'a Path,
+    /// The documentation of every target, with the default target at the root. `None` if the
+    /// default target didn't produce any documentation.
+    pub(crate) docs_dir: Option<&'a Path>,
+    pub(crate) result: BuildResult,
+    pub(crate) default_target: &'a str,
+    pub(crate) successful_targets: Vec<String>,
+    pub(crate) doc_coverage: Option<DocCoverage>,
+    /// Where the items of the library are defined, linked from the source browser
+    pub(crate) definitions: Option<Definitions>,
+    /// The documented items of the library, resolved by `/api/v1/resolve`
+    pub(crate) item_index: Option<ItemIndex>,
+    /// The compressed output of `rustdoc --output-format json` of the successful targets
+    pub(crate) rustdoc_json: Vec<(String, Vec<u8>)>,
+    pub(crate) build_log: String,
+    /// The documentation of a second build of the default target, when the builder verifies
+    /// that builds are reproducible
+    pub(crate) verification_manifest: Option<Manifest>,
+}
+
+pub(crate) struct BuildUploader {
+    db: Pool,
+    storage: Arc<Storage>,
+    metrics: Arc<Metrics>,
+    index: Arc<Index>,
+    repository_stats_updater: Arc<RepositoryStatsUpdater>,
+    version_cache: Arc<VersionCache>,
+    releases_cache: Arc<ReleasesCache>,
+    events: BuildEvents,
+}
+
+impl BuildUploader {
+    pub(crate) fn new(context: &dyn Context) -> Result<Self> {
+        Ok(Self {
+            db: context.pool()?,
+            storage: context.storage()?,
+            metrics: context.metrics()?,
+            index: context.index()?,
+            repository_stats_updater: context.repository_stats_updater()?,
+            version_cache: context.version_cache()?,
+            releases_cache: context.releases_cache()?,
+            events: BuildEvents::new(&*context.config()?, context.metrics()?)?,
+        })
+    }
+
+    /// Uploads the output of a build and records the release, returning its id
+    pub(crate) fn upload(&self, mut output: BuildOutput<'_>) -> Result<i32> {
+        let mut conn = self.db.get()?;
+        let name = &output.package.name;
+        let version = &output.package.version;
+
+        let mut algs = HashSet::new();
+        let mut rustdoc_size = StoredSize::default();
+        if let Some(docs_dir) = output.docs_dir {
+            debug!("Adding documentation into database");
+            let prefix = format!("rustdoc/{}/{}", name, version);
+            let limit = Limits::for_crate(&mut conn, name)?.upload_limit();
+            match add_path_into_database(&self.storage, &prefix, docs_dir, Some(limit)) {
+                Ok((_, new_algs, size)) => {
+                    algs.extend(new_algs);
+                    rustdoc_size = size;
+                }
+                Err(err) if err.downcast_ref::<UploadSizeExceeded>().is_some() => {
+                    warn!(
+                        "not uploading the documentation of {} {}: {}",
+                        name, version, err
+                    );
+                    output.build_log.push_str(&format!(
+                        "\n[docs.rs] documentation exceeds size limit: {}\n",
+                        err
+                    ));
+                    output.docs_dir = None;
+                    output.successful_targets.clear();
+                    output.rustdoc_json.clear();
+                    output.result.successful = false;
+                    output.result.failure = Some(BuildFailure::UploadSizeExceeded);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // the size of the sources is already limited by the registry
+        debug!("adding sources into database");
+        let prefix = format!("sources/{}/{}", name, version);
+        let (files_list, new_algs, sources_size) =
+            add_path_into_database(&self.storage, &prefix, output.source_dir, None)?;
+        algs.extend(new_algs);
+        for (kind, size) in &[("rustdoc", rustdoc_size), ("sources", sources_size)] {
+            self.metrics
+                .stored_bytes
+                .with_label_values(&[kind])
+                .inc_by(size.compressed as i64);
+        }
+        // cargo generates the lockfile in the sources when the crate doesn't ship one, it's kept
+        // outside of them to be downloaded by anyone reproducing the build
+        let lockfile = output.source_dir.join("Cargo.lock");
+        if lockfile.is_file() {
+            self.storage.store_one(
+                format!("lockfiles/{}/{}/Cargo.lock", name, version),
+                std::fs::read(&lockfile)?,
+            )?;
+        }
+        if let Some(definitions) = &output.definitions {
+            definitions.store(&self.storage, name, version)?;
+        }
+        if let Some(item_index) = &output.item_index {
+            item_index.store(&self.storage, name, version)?;
+        }
+        for (target, compressed) in output.rustdoc_json.drain(..) {
+            rustdoc_json::store(&self.storage, name, version, &target, compressed)?;
+        }
+
+        let doc_sizes = output
+            .docs_dir
+            .map(|docs_dir| DocSizes::from_dir(docs_dir, &output.successful_targets))
+            .transpose()?;
+
+        let has_examples = output.source_dir.join("examples").is_dir();
+        if output.result.failure == Some(BuildFailure::DiskQuotaExceeded) {
+            self.metrics.disk_quota_exceeded_builds.inc();
+        }
+        if output.result.successful {
+            self.metrics.successful_builds.inc();
+        } else if output.package.is_library() {
+            self.metrics.failed_builds.inc();
+        } else {
+            self.metrics.non_library_builds.inc();
+        }
+
+        let release_data = match self.index.api().get_release_data(name, version) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("{:#?}", err);
+                ReleaseData::default()
+            }
+        };
+
+        let repository = self
+            .repository_stats_updater
+            .load_repository(output.package)?;
+
+        let release_id = add_package_into_database(
+            &mut conn,
+            output.package,
+            output.source_dir,
+            &output.result,
+            output.default_target,
+            files_list,
+            output.successful_targets.clone(),
+            &release_data,
+            output.docs_dir.is_some(),
+            has_examples,
+            algs,
+            repository,
+        )?;
+
+        storage_stats::record(&mut conn, release_id, rustdoc_size, sources_size)?;
+        storage_stats::record_doc_sizes(&mut conn, release_id, doc_sizes)?;
+        if let Some(doc_coverage) = output.doc_coverage {
+            add_doc_coverage(&mut conn, release_id, doc_coverage)?;
+        }
+        if let Some(citation) = Citation::from_source_dir(output.source_dir) {
+            add_citation(&mut conn, release_id, &citation)?;
+        }
+        if output.docs_dir.is_some() && lockfile.is_file() {
+            match locked_dependencies(&std::fs::read_to_string(&lockfile)?, output.package) {
+                Ok(dependencies) => dependency_links::record(&mut conn, release_id, &dependencies)?,
+                Err(err) => warn!("invalid lockfile of {} {}: {}", name, version, err),
+            }
+        }
+
+        let build_id = add_build_into_database(&mut conn, release_id, &output.result)?;
+        let build_log_path = format!("build-logs/{}/{}.txt", build_id, output.default_target);
+        self.storage.store_one(build_log_path, output.build_log)?;
+        if let Some(docs_dir) = output.docs_dir {
+            let default_target = output.default_target;
+            let other_targets: Vec<&str> = output
+                .successful_targets
+                .iter()
+                .map(String::as_str)
+                .filter(|target| *target != default_target)
+                .collect();
+            self.check_reproducibility(
+                &mut conn,
+                output.package,
+                docs_dir,
+                &other_targets,
+                build_id,
+                &output.result,
+                output.verification_manifest,
+            )?;
+        }
+
+        // Some crates.io crate data is mutable, so we proactively update it during a release
+        match self.index.api().get_crate_data(name) {
+            Ok(crate_data) => update_crate_data_in_database(&mut conn, name, &crate_data)?,
+            Err(err) => warn!("{:#?}", err),
+        }
+
+        self.version_cache.invalidate(name);
+        self.releases_cache.invalidate();
+        pubsubhubbub::feed_updated(&mut conn)?;
+        self.events
+            .build_completed(name, version, build_id, output.result.successful)?;
+
+        Ok(release_id)
+    }
+
+    /// Stores the manifest of the documentation and compares it with the verification build, which
+    /// only documented the default target, or the previous build of the release done with the same
+    /// versions of rustc and docs.rs
+    #[allow(clippy::too_many_arguments)]
+    fn check_reproducibility(
+        &self,
+        conn: &mut postgres::Client,
+        package: &MetadataPackage,
+        docs_dir: &Path,
+        other_targets: &[&str],
+        build_id: i32,
+        result: &BuildResult,
+        verification_manifest: Option<Manifest>,
+    ) -> Result<()> {
+        let mut manifest = Manifest::from_dir(docs_dir)?;
+        manifest.store(&self.storage, build_id)?;
+
+        let previous = match verification_manifest {
+            Some(verification_manifest) => {
+                manifest = manifest.default_target(other_targets);
+                Some(verification_manifest)
+            }
+            None => {
+                let previous_build = conn.query_opt(
+                    "SELECT previous.id
+                     FROM builds
+                     INNER JOIN builds AS previous ON previous.rid = builds.rid
+                     WHERE builds.id = $1 AND previous.id < $1 AND previous.build_status AND
+                           previous.rustc_version = $2 AND previous.docsrs_version = $3
+                     ORDER BY previous.id DESC
+                     LIMIT 1",
+                    &[&build_id, &result.rustc_version, &result.docsrs_version],
+                )?;
+                match previous_build {
+                    Some(row) => Manifest::load(&self.storage, row.get("id"))?,
+                    None => None,
+                }
+            }
+        };
+
+        if let Some(previous) = previous {
+            let reproducible = reproducibility::compare(
+                &self.metrics,
+                &package.name,
+                &package.version,
+                &manifest,
+                &previous,
+            );
+            conn.execute(
+                "UPDATE builds SET reproducible = $2 WHERE id = $1",
+                &[&build_id, &reproducible],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// The versions of the dependencies from a registry locked for the package, the ones rustdoc links
+/// to docs.rs
+fn locked_dependencies(lockfile: &str, package: &MetadataPackage) -> Result<Vec<(String, String)>> {
+    let lockfile: Lockfile = toml::from_str(lockfile)?;
+    let root = match lockfile
+        .package
+        .iter()
+        .find(|locked| locked.name == package.name && locked.version == package.version)
+    {
+        Some(root) => root,
+        None => return Ok(Vec::new()),
+    };
+
+    // the dependencies are `name`, `name version` or `name version (source)`, the version is
+    // only there when several versions of the crate are locked
+    Ok(root
+        .dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let mut parts = dependency.split(' ');
+            let name = parts.next()?;
+            let version = parts.next();
+            lockfile.package.iter().find(|locked| {
+                locked.name == name && (version.is_none() || version == Some(&locked.version))
+            })
+        })
+        .filter(|locked| matches!(&locked.source, Some(source) if source.starts_with("registry+")))
+        .map(|locked| (locked.name.clone(), locked.version.clone()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docbuilder::{BuildFailure, DocCoverage};
+    use crate::test::{assert_redirect, assert_success, wrapper};
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn successful_build() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo-bar")
+                .version("0.1.0")
+                .doc_coverage(DocCoverage {
+                    total_items: 4,
+                    documented_items: 3,
+                    total_items_needing_examples: 0,
+                    items_with_examples: 0,
+                })
+                .build_log("Documenting foo-bar v0.1.0")
+                .build()?;
+
+            assert_eq!(env.build_queue().pending_count()?, 0);
+            assert_eq!(env.metrics().successful_builds.get(), 1);
+            assert_eq!(env.metrics().total_builds.get(), 1);
+
+            let web = env.frontend();
+            assert_success("/foo-bar/0.1.0/foo_bar/", web)?;
+            assert_success("/foo-bar/0.1.0/foo_bar/all.html", web)?;
+            assert_success("/foo-bar/0.1.0/src/foo_bar/lib.rs.html", web)?;
+            assert_redirect("/foo-bar", "/foo-bar/0.1.0/foo_bar/", web)?;
+
+            let source = web.get("/crate/foo-bar/0.1.0/source/src/lib.rs").send()?;
+            assert!(source.status().is_success());
+            assert!(source.text()?.contains("Fake crate"));
+
+            let details =
+                kuchiki::parse_html().one(web.get("/crate/foo-bar/0.1.0").send()?.text()?);
+            assert!(details.text_contents().contains("75%"));
+
+            let builds = web.get("/crate/foo-bar/0.1.0/builds").send()?.text()?;
+            let build_id = kuchiki::parse_html()
+                .one(builds)
+                .select_first("ul > li a.release")
+                .expect("missing build")
+                .attributes
+                .borrow()
+                .get("href")
+                .unwrap()
+                .to_owned();
+            let log = web.get(&build_id).send()?.text()?;
+            assert!(log.contains("Documenting foo-bar v0.1.0"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_build() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .failure(BuildFailure::DiskQuotaExceeded)
+                .build()?;
+
+            assert_eq!(env.metrics().failed_builds.get(), 1);
+            assert_eq!(env.metrics().disk_quota_exceeded_builds.get(), 1);
+
+            let web = env.frontend();
+            assert_redirect("/foo/0.1.0", "/crate/foo/0.1.0", web)?;
+            // the sources are stored even when the build fails
+            assert_success("/crate/foo/0.1.0/source/Cargo.toml", web)?;
+            assert!(!env.storage().exists("rustdoc/foo/0.1.0/foo/index.html")?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn upload_size_exceeded() {
+        wrapper(|env| {
+            env.db().conn().execute(
+                "INSERT INTO sandbox_overrides (crate_name, max_upload_bytes) VALUES ('foo', 10)",
+                &[],
+            )?;
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file(
+                    "foo/index.html",
+                    b"<html>this is more than ten bytes</html>",
+                )
+                .build()?;
+
+            assert_eq!(env.metrics().failed_builds.get(), 1);
+            assert!(!env.storage().exists("rustdoc/foo/0.1.0/foo/index.html")?);
+            let row = env.db().conn().query_one(
+                "SELECT builds.id, builds.build_status, builds.failure_category
+                 FROM builds
+                 INNER JOIN releases ON releases.id = builds.rid
+                 WHERE releases.version = '0.1.0'",
+                &[],
+            )?;
+            assert!(!row.get::<_, bool>("build_status"));
+            assert_eq!(
+                row.get::<_, Option<String>>("failure_category").as_deref(),
+                Some("upload-size-exceeded")
+            );
+            let log = env.storage().get(
+                &format!(
+                    "build-logs/{}/x86_64-unknown-linux-gnu.txt",
+                    row.get::<_, i32>("id")
+                ),
+                usize::MAX,
+            )?;
+            assert!(String::from_utf8(log.content)?.contains("documentation exceeds size limit"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn lockfile() {
+        wrapper(|env| {
+            let lockfile = b"# This file is automatically @generated by Cargo.\nversion = 3\n";
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file(
+                    "Cargo.toml",
+                    b"[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+                )
+                .source_file("Cargo.lock", lockfile)
+                .build()?;
+            env.fake_builder().name("bar").version("0.1.0").build()?;
+
+            let web = env.frontend();
+            let resp = web.get("/crate/foo/0.1.0/Cargo.lock").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()["Content-Type"], "text/plain; charset=utf-8");
+            assert_eq!(resp.bytes()?.as_ref(), &lockfile[..]);
+            assert_redirect(
+                "/crate/foo/latest/Cargo.lock",
+                "/crate/foo/0.1.0/Cargo.lock",
+                web,
+            )?;
+
+            let resp = web.get("/crate/bar/0.1.0/Cargo.lock").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn locked_dependencies() {
+        let lockfile = r#"
+version = 3
+
+[[package]]
+name = "bar"
+version = "0.1.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "bar"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "baz"
+version = "0.1.0"
+
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "bar 1.0.0",
+ "baz",
+]
+"#;
+        let package = crate::utils::MetadataPackage {
+            name: "foo".into(),
+            version: "0.1.0".into(),
+            ..Default::default()
+        };
+        // the path dependencies don't link to docs.rs
+        assert_eq!(
+            super::locked_dependencies(lockfile, &package).unwrap(),
+            vec![("bar".to_string(), "1.0.0".to_string())]
+        );
+
+        let package = crate::utils::MetadataPackage {
+            name: "qux".into(),
+            ..package
+        };
+        assert!(super::locked_dependencies(lockfile, &package)
+            .unwrap()
+            .is_empty());
+        assert!(super::locked_dependencies("[[package]]", &package).is_err());
+    }
+
+    #[test]
+    fn dependency_links() {
+        wrapper(|env| {
+            env.fake_release().name("bar").version("1.1.0").create()?;
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file(
+                    "Cargo.lock",
+                    br#"
+[[package]]
+name = "bar"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = ["bar"]
+"#,
+                )
+                .build()?;
+
+            assert_eq!(
+                crate::db::dependency_links::replacement(&mut env.db().conn(), "bar", "1.0.0")?
+                    .as_deref(),
+                Some("1.1.0")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reproducibility() {
+        wrapper(|env| {
+            let reproducible = || -> Result<Vec<Option<bool>>, failure::Error> {
+                Ok(env
+                    .db()
+                    .conn()
+                    .query("SELECT reproducible FROM builds ORDER BY id", &[])?
+                    .iter()
+                    .map(|row| row.get(0))
+                    .collect())
+            };
+
+            env.fake_builder().name("foo").version("0.1.0").build()?;
+            assert_eq!(reproducible()?, vec![None]);
+
+            env.fake_builder().name("foo").version("0.1.0").build()?;
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file("foo/index.html", b"<p>built at 12:00</p>")
+                .build()?;
+            assert_eq!(reproducible()?, vec![None, Some(true), Some(false)]);
+
+            let metrics = env.metrics();
+            for result in &["reproducible", "nondeterministic"] {
+                assert_eq!(
+                    metrics
+                        .reproducibility_checks
+                        .with_label_values(&[result])
+                        .get(),
+                    1
+                );
+            }
+            assert!(
+                metrics
+                    .nondeterministic_files
+                    .with_label_values(&["html"])
+                    .get()
+                    > 0
+            );
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+            assert!(page.select_first(".nondeterministic").is_ok());
+            assert!(page.select_first(".reproducible").is_ok());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn verification_builds() {
+        wrapper(|env| {
+            let reproducible = |release_id: i32| -> Result<Option<bool>, failure::Error> {
+                Ok(env
+                    .db()
+                    .conn()
+                    .query_one(
+                        "SELECT reproducible FROM builds WHERE rid = $1",
+                        &[&release_id],
+                    )?
+                    .get(0))
+            };
+
+            // the verification build only documents the default target
+            let release_id = env
+                .fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .add_target("i686-pc-windows-msvc")
+                .verified()
+                .build()?;
+            assert_eq!(reproducible(release_id)?, Some(true));
+
+            let release_id = env
+                .fake_builder()
+                .name("bar")
+                .version("0.1.0")
+                .add_target("i686-pc-windows-msvc")
+                .verification_file("bar/index.html", b"<p>built at 12:00</p>")
+                .build()?;
+            assert_eq!(reproducible(release_id)?, Some(false));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn multiple_targets() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .add_target("x86_64-pc-windows-msvc")
+                .build()?;
+
+            let web = env.frontend();
+            assert_success("/foo/0.1.0/foo/", web)?;
+            assert_success("/foo/0.1.0/x86_64-pc-windows-msvc/foo/", web)?;
+            assert_redirect(
+                "/foo/0.1.0/x86_64-unknown-linux-gnu/foo/",
+                "/foo/0.1.0/foo/",
+                web,
+            )?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn unusual_file_names() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("Cargo.toml", b"[package]\nname = \"foo\"\n")
+                .source_file("src/some file.rs", b"// with a space\n")
+                .source_file("src/100%.rs", b"// with a percent\n")
+                .rustdoc_file("foo/index.html", b"<html><body>index</body></html>")
+                .rustdoc_file("foo/some file.html", b"<html><body>space</body></html>")
+                .build()?;
+
+            let web = env.frontend();
+            assert_success("/foo/0.1.0/foo/some%20file.html", web)?;
+            assert_success("/crate/foo/0.1.0/source/src/some%20file.rs", web)?;
+            assert_success("/crate/foo/0.1.0/source/src/100%25.rs", web)?;
+
+            Ok(())
+        });
+    }
+}
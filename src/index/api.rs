@@ -7,7 +7,7 @@ use url::Url;
 
 use crate::error::Result;
 
-const APP_USER_AGENT: &str = concat!(
+pub(super) const APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
     " ",
     include_str!(concat!(env!("OUT_DIR"), "/git_version"))
@@ -41,6 +41,15 @@ impl Default for ReleaseData {
     }
 }
 
+/// A crate listed by [`Api::get_updated_crates`]
+#[derive(Debug)]
+pub(crate) struct UpdatedCrate {
+    pub(crate) name: String,
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+const UPDATED_CRATES_PER_PAGE: usize = 100;
+
 #[derive(Debug, Clone)]
 pub struct CrateOwner {
     pub(crate) avatar: String,
@@ -133,6 +142,52 @@ impl Api {
         Ok((version.created_at, version.yanked, version.downloads))
     }
 
+    /// The crates which were published, yanked or updated after `since`, the most recent first.
+    ///
+    /// The pages are fetched until one reaches `since`, so that none of the updates are missed
+    /// when the caller saves the most recent one as the next `since`.
+    pub(crate) fn get_updated_crates(&self, since: DateTime<Utc>) -> Result<Vec<UpdatedCrate>> {
+        #[derive(Deserialize)]
+        struct Response {
+            crates: Vec<CrateData>,
+        }
+
+        #[derive(Deserialize)]
+        struct CrateData {
+            name: String,
+            updated_at: DateTime<Utc>,
+        }
+
+        let mut updated = Vec::new();
+        for page in 1.. {
+            let mut url = self.api_base()?;
+            url.path_segments_mut()
+                .map_err(|()| err_msg("Invalid API url"))?
+                .extend(&["api", "v1", "crates"]);
+            url.query_pairs_mut()
+                .append_pair("sort", "recent-updates")
+                .append_pair("per_page", &UPDATED_CRATES_PER_PAGE.to_string())
+                .append_pair("page", &page.to_string());
+
+            let response: Response = self.client.get(url).send()?.error_for_status()?.json()?;
+            let last_page = response.crates.len() < UPDATED_CRATES_PER_PAGE;
+            for krate in response.crates {
+                if krate.updated_at <= since {
+                    return Ok(updated);
+                }
+                updated.push(UpdatedCrate {
+                    name: krate.name,
+                    updated_at: krate.updated_at,
+                });
+            }
+            if last_page {
+                break;
+            }
+        }
+
+        Ok(updated)
+    }
+
     /// Fetch owners from the registry's API
     fn get_owners(&self, name: &str) -> Result<Vec<CrateOwner>> {
         let url = {
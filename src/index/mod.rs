@@ -3,17 +3,21 @@ use std::{path::PathBuf, process::Command};
 use url::Url;
 
 use self::api::Api;
+use self::sparse::SparseIndex;
 use crate::error::Result;
+use crate::Config;
 use failure::ResultExt;
 
 pub(crate) mod api;
 #[cfg(feature = "consistency_check")]
 mod crates;
+pub(crate) mod sparse;
 
 pub struct Index {
     path: PathBuf,
     api: Api,
     repository_url: Option<String>,
+    sparse: Option<SparseIndex>,
 }
 
 #[derive(Debug, serde::Deserialize, Clone)]
@@ -42,6 +46,19 @@ fn load_config(repo: &git2::Repository) -> Result<IndexConfig> {
 }
 
 impl Index {
+    /// Opens the index configured with `REGISTRY_URL` or `DOCSRS_REGISTRY_SPARSE_INDEX_URL`, the
+    /// crates.io git index otherwise
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let path = config.registry_index_path.clone();
+        match (&config.registry_sparse_index_url, &config.registry_url) {
+            (Some(sparse_url), registry_url) => {
+                Index::sparse(path, sparse_url.clone(), registry_url.clone())
+            }
+            (None, Some(registry_url)) => Index::from_url(path, registry_url.clone()),
+            (None, None) => Index::new(path),
+        }
+    }
+
     pub fn from_url(path: PathBuf, repository_url: String) -> Result<Self> {
         let url = repository_url.clone();
         let diff = crates_index_diff::Index::from_path_or_cloned_with_options(
@@ -56,6 +73,7 @@ impl Index {
             path,
             api,
             repository_url: Some(url),
+            sparse: None,
         })
     }
 
@@ -70,6 +88,23 @@ impl Index {
             path,
             api,
             repository_url: None,
+            sparse: None,
+        })
+    }
+
+    /// Uses the sparse HTTP protocol of the index at `sparse_url` to find new releases, without
+    /// cloning the git repository. It is only cloned at `path` if something needs the whole index,
+    /// like the consistency check. `repository_url` is the git URL of the registry, passed to the
+    /// builds of its crates, `None` for crates.io.
+    pub fn sparse(path: PathBuf, sparse_url: Url, repository_url: Option<String>) -> Result<Self> {
+        let sparse = SparseIndex::new(sparse_url).context("initialising sparse index client")?;
+        let config = sparse.config().context("loading registry config")?;
+        let api = Api::new(config.api).context("initialising registry api client")?;
+        Ok(Self {
+            path,
+            api,
+            repository_url,
+            sparse: Some(sparse),
         })
     }
 
@@ -100,7 +135,16 @@ impl Index {
         &self.api
     }
 
+    pub(crate) fn sparse_index(&self) -> Option<&SparseIndex> {
+        self.sparse.as_ref()
+    }
+
     pub fn run_git_gc(&self) {
+        if self.sparse.is_some() {
+            // there's no checkout to clean up
+            return;
+        }
+
         let gc = Command::new("git")
             .arg("-C")
             .arg(&self.path)
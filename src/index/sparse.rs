@@ -0,0 +1,123 @@
+//! Client of the sparse HTTP protocol of registry indexes
+//!
+//! Every crate has its own file on the server, with one line of JSON per version. Unlike the git
+//! protocol there is no history of the changes, so the crates to look at are found through the
+//! API of the registry, see [`Api::get_updated_crates`](super::api::Api::get_updated_crates).
+
+use super::api::APP_USER_AGENT;
+use super::IndexConfig;
+use crate::error::Result;
+use failure::ResultExt;
+use reqwest::header::{HeaderValue, USER_AGENT};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Debug)]
+pub(crate) struct SparseIndex {
+    base: Url,
+    client: reqwest::blocking::Client,
+}
+
+/// A version of a crate, as listed in the index
+#[derive(Debug, Deserialize)]
+pub(crate) struct IndexVersion {
+    pub(crate) vers: String,
+    #[serde(default)]
+    pub(crate) yanked: bool,
+}
+
+impl SparseIndex {
+    pub(super) fn new(mut base: Url) -> Result<Self> {
+        // otherwise the last segment of the path would be replaced when joining
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        let client = reqwest::blocking::Client::builder()
+            .default_headers(
+                vec![(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT))]
+                    .into_iter()
+                    .collect(),
+            )
+            .build()?;
+
+        Ok(Self { base, client })
+    }
+
+    pub(super) fn config(&self) -> Result<IndexConfig> {
+        let url = self.base.join("config.json")?;
+        Ok(self.client.get(url).send()?.error_for_status()?.json()?)
+    }
+
+    /// The versions of a crate, empty if it isn't in the index
+    pub(crate) fn versions(&self, name: &str) -> Result<Vec<IndexVersion>> {
+        let url = self.base.join(&crate_path(name))?;
+        let response = self.client.get(url).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let body = response.error_for_status()?.text()?;
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                Ok(serde_json::from_str(line)
+                    .with_context(|_| format!("invalid index entry for {}", name))?)
+            })
+            .collect()
+    }
+}
+
+/// The path of the file of a crate, relative to the root of the index
+fn crate_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crate_paths() {
+        assert_eq!(crate_path("a"), "1/a");
+        assert_eq!(crate_path("ab"), "2/ab");
+        assert_eq!(crate_path("abc"), "3/a/abc");
+        assert_eq!(crate_path("Serde_JSON"), "se/rd/serde_json");
+    }
+
+    #[test]
+    fn versions() {
+        let _file = mockito::mock("GET", "/sparse-index/fo/o-/foo-bar")
+            .with_body(concat!(
+                r#"{"name":"foo-bar","vers":"0.1.0","deps":[],"cksum":"","features":{},"yanked":true}"#,
+                "\n",
+                r#"{"name":"foo-bar","vers":"0.2.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+                "\n",
+            ))
+            .create();
+        let _missing = mockito::mock("GET", "/sparse-index/3/b/baz")
+            .with_status(404)
+            .create();
+
+        let index = SparseIndex::new(
+            format!("{}/sparse-index", mockito::server_url())
+                .parse()
+                .unwrap(),
+        )
+        .unwrap();
+        let versions = index.versions("foo-bar").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].vers, "0.1.0");
+        assert!(versions[0].yanked);
+        assert!(!versions[1].yanked);
+
+        assert!(index.versions("baz").unwrap().is_empty());
+    }
+}
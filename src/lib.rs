@@ -3,17 +3,21 @@
 #![allow(clippy::cognitive_complexity)]
 
 pub use self::build_queue::BuildQueue;
-pub use self::config::Config;
+pub use self::config::{Config, ConfigError};
 pub use self::context::Context;
 pub use self::docbuilder::DocBuilder;
+pub use self::docbuilder::MetadataReport;
 pub use self::docbuilder::PackageKind;
 pub use self::docbuilder::RustwideBuilder;
 pub use self::index::Index;
 pub use self::metrics::Metrics;
 pub use self::storage::Storage;
+pub use self::web::current_request_id;
 pub use self::web::Server;
+pub use self::web::{ReleasesCache, VersionCache};
 
 mod build_queue;
+mod cdn;
 mod config;
 mod context;
 pub mod db;
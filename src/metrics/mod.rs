@@ -16,6 +16,13 @@ load_metric_type!(IntCounterVec as vec);
 load_metric_type!(IntGaugeVec as vec);
 load_metric_type!(HistogramVec as vec);
 
+use prometheus::Histogram;
+impl MetricFromOpts for Histogram {
+    fn from_opts(opts: prometheus::Opts) -> Result<Self, prometheus::Error> {
+        Histogram::with_opts(opts.into())
+    }
+}
+
 metrics! {
     pub struct Metrics {
         /// Number of crates in the build queue
@@ -33,6 +40,10 @@ metrics! {
         max_db_connections: IntGauge,
         /// Number of attempted and failed connections to the database
         pub(crate) failed_db_connections: IntCounter,
+        /// The time spent waiting for a connection from the pool, in seconds
+        pub(crate) db_connection_wait_times: Histogram,
+        /// Number of connections held longer than the deadline
+        pub(crate) long_held_db_connections: IntCounter,
 
         /// The number of currently opened file descriptors
         #[cfg(target_os = "linux")]
@@ -45,10 +56,20 @@ metrics! {
         pub(crate) routes_visited: IntCounterVec["route"],
         /// The response times of various docs.rs routes
         pub(crate) response_time: HistogramVec["route"],
+        /// The status codes of the responses sent by various docs.rs routes
+        pub(crate) http_responses: IntCounterVec["route", "status"],
+        /// The time between receiving a request and sending the response, including error pages
+        pub(crate) http_request_duration: HistogramVec["route"],
         /// The time it takes to render a rustdoc page
         pub(crate) rustdoc_rendering_times: HistogramVec["step"],
         /// The time it takes to render a rustdoc redirect page
         pub(crate) rustdoc_redirect_rendering_times: HistogramVec["step"],
+        /// Number of crate and version lookups answered by the version cache or the database
+        pub(crate) version_cache_lookups: IntCounterVec["result"],
+        /// Number of releases lists answered by the releases cache or the database
+        pub(crate) releases_cache_lookups: IntCounterVec["result"],
+        /// Number of pages served from the render cache or rendered
+        pub(crate) render_cache_lookups: IntCounterVec["template", "result"],
 
         /// Count of recently accessed crates
         pub(crate) recent_crates: IntGaugeVec["duration"],
@@ -65,12 +86,45 @@ metrics! {
         pub(crate) failed_builds: IntCounter,
         /// Number of builds that did not complete due to not being a library
         pub(crate) non_library_builds: IntCounter,
+        /// Number of builds aborted because they used too much disk space
+        pub(crate) disk_quota_exceeded_builds: IntCounter,
+        /// Number of build events sent to the webhook, by result
+        pub(crate) build_events: IntCounterVec["result"],
+        /// Number of deliveries of the releases feed to the WebSub subscribers, by result
+        pub(crate) websub_deliveries: IntCounterVec["result"],
+        /// Number of builds whose documentation was compared with a previous build, by result
+        pub(crate) reproducibility_checks: IntCounterVec["result"],
+        /// Number of documentation files that differed between two builds, by extension
+        pub(crate) nondeterministic_files: IntCounterVec["extension"],
+        /// Number of compressed bytes uploaded to the storage by the builds, by kind of files
+        pub(crate) stored_bytes: IntCounterVec["kind"],
+        /// The disk space available to the builder, in bytes
+        pub(crate) builder_free_disk_space: IntGauge,
 
         /// Number of files uploaded to the storage backend
         pub(crate) uploaded_files_total: IntCounter,
+        /// Number of batches of files not uploaded again when resuming an interrupted upload
+        pub(crate) skipped_upload_batches: IntCounter,
+        /// Number of files deleted from the storage backend
+        pub(crate) deleted_files_total: IntCounter,
+        /// Number of files read from the S3 replica because the primary bucket was unavailable
+        pub(crate) s3_replica_fallbacks: IntCounterVec["result"],
+        /// Number of CDN invalidations that failed after the storage changed
+        pub(crate) failed_cdn_invalidations: IntCounter,
+        /// Number of files fetched from the in-memory storage cache or from the backend
+        pub(crate) storage_cache_lookups: IntCounterVec["result"],
+        /// Total size of the files kept in the in-memory storage cache, in bytes
+        pub(crate) storage_cache_size: IntGauge,
 
         /// The number of attempted files that failed due to a memory limit
         pub(crate) html_rewrite_ooms: IntCounter,
+        /// The time spent in each pass of the HTML rewriter
+        pub(crate) html_rewrite_pass_times: HistogramVec["pass"],
+
+        /// Number of times a background thread of the daemon was restarted after failing
+        pub(crate) daemon_thread_restarts: IntCounterVec["thread"],
+        /// Number of runs of the scheduled jobs, by outcome
+        pub(crate) scheduled_job_runs: IntCounterVec["job", "result"],
 
         /// the number of "I'm feeling lucky" searches for crates
         pub(crate) im_feeling_lucky_searches: IntCounter,
@@ -156,6 +210,7 @@ impl Metrics {
         self.idle_db_connections.set(pool.idle_connections() as i64);
         self.used_db_connections.set(pool.used_connections() as i64);
         self.max_db_connections.set(pool.max_size() as i64);
+        pool.report_long_held_connections();
 
         self.queued_crates_count.set(queue.pending_count()? as i64);
         self.prioritized_crates_count
@@ -1,12 +1,13 @@
 use crate::error::Result;
 use crate::Config;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use log::{trace, warn};
 use reqwest::{
     blocking::Client as HttpClient,
     header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
 };
 use serde::Deserialize;
+use std::sync::Mutex;
 
 use crate::repositories::{
     FetchRepositoriesResult, RateLimitReached, Repository, RepositoryForge, RepositoryName,
@@ -23,10 +24,13 @@ const GRAPHQL_UPDATE: &str = "query($ids: [ID!]!) {
             stargazerCount
             forkCount
             issues(states: [OPEN]) { totalCount }
+            defaultBranchRef { name }
+            isArchived
         }
     }
     rateLimit {
         remaining
+        resetAt
     }
 }";
 
@@ -39,12 +43,17 @@ const GRAPHQL_SINGLE: &str = "query($owner: String!, $repo: String!) {
         stargazerCount
         forkCount
         issues(states: [OPEN]) { totalCount }
+        defaultBranchRef { name }
+        isArchived
     }
 }";
 
 pub struct GitHub {
     client: HttpClient,
     github_updater_min_rate_limit: u32,
+    /// Set when the remaining rate limit went under `github_updater_min_rate_limit`, no request is
+    /// made until this time.
+    rate_limit_reset: Mutex<Option<DateTime<Utc>>>,
 }
 
 impl GitHub {
@@ -70,6 +79,7 @@ impl GitHub {
         Ok(Some(GitHub {
             client,
             github_updater_min_rate_limit: config.github_updater_min_rate_limit,
+            rate_limit_reset: Mutex::new(None),
         }))
     }
 }
@@ -107,6 +117,8 @@ impl RepositoryForge for GitHub {
                 name_with_owner: repo.name_with_owner,
                 description: repo.description,
                 last_activity_at: repo.pushed_at,
+                default_branch: repo.default_branch_ref.map(|branch| branch.name),
+                archived: repo.is_archived,
                 stars: repo.stargazer_count,
                 forks: repo.fork_count,
                 issues: repo.issues.total_count,
@@ -129,6 +141,7 @@ impl RepositoryForge for GitHub {
                 data.rate_limit.remaining
             );
             if data.rate_limit.remaining < self.github_updater_min_rate_limit {
+                self.rate_limited_until(data.rate_limit.reset_at.unwrap_or_else(Utc::now));
                 return Err(RateLimitReached.into());
             }
         }
@@ -158,6 +171,8 @@ impl RepositoryForge for GitHub {
                     name_with_owner: node.name_with_owner,
                     description: node.description,
                     last_activity_at: node.pushed_at,
+                    default_branch: node.default_branch_ref.map(|branch| branch.name),
+                    archived: node.is_archived,
                     stars: node.stargazer_count,
                     forks: node.fork_count,
                     issues: node.issues.total_count,
@@ -171,11 +186,28 @@ impl RepositoryForge for GitHub {
 }
 
 impl GitHub {
+    fn rate_limited_until(&self, reset: DateTime<Utc>) {
+        warn!(
+            "GitHub rate limit reached, pausing the requests until {}",
+            reset
+        );
+        *self.rate_limit_reset.lock().unwrap() = Some(reset);
+    }
+
     fn graphql<T: serde::de::DeserializeOwned>(
         &self,
         query: &str,
         variables: impl serde::Serialize,
     ) -> Result<GraphResponse<T>> {
+        {
+            let mut reset = self.rate_limit_reset.lock().unwrap();
+            match *reset {
+                Some(time) if time > Utc::now() => return Err(RateLimitReached.into()),
+                Some(_) => *reset = None,
+                None => {}
+            }
+        }
+
         #[cfg(not(test))]
         let host = "https://api.github.com/graphql";
         #[cfg(test)]
@@ -183,16 +215,31 @@ impl GitHub {
         #[cfg(test)]
         let host = &host;
 
-        Ok(self
+        let response = self
             .client
             .post(host)
             .json(&serde_json::json!({
                 "query": query,
                 "variables": variables,
             }))
-            .send()?
-            .error_for_status()?
-            .json()?)
+            .send()?;
+
+        // Every response tells how many requests are left until the rate limit resets, which
+        // also covers the queries not asking for the `rateLimit` field.
+        let header = |name: &str| -> Option<i64> {
+            response.headers().get(name)?.to_str().ok()?.parse().ok()
+        };
+        if let (Some(remaining), Some(reset)) =
+            (header("x-ratelimit-remaining"), header("x-ratelimit-reset"))
+        {
+            trace!("GitHub rate limit remaining: {}", remaining);
+            if remaining < i64::from(self.github_updater_min_rate_limit) {
+                self.rate_limited_until(Utc.timestamp(reset, 0));
+                return Err(RateLimitReached.into());
+            }
+        }
+
+        Ok(response.error_for_status()?.json()?)
     }
 }
 
@@ -220,8 +267,11 @@ enum GraphErrorPath {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GraphRateLimit {
     remaining: u32,
+    #[serde(default)]
+    reset_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -246,6 +296,14 @@ struct GraphRepository {
     stargazer_count: i64,
     fork_count: i64,
     issues: GraphIssues,
+    default_branch_ref: Option<GraphBranch>,
+    #[serde(default)]
+    is_archived: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphBranch {
+    name: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -258,6 +316,7 @@ struct GraphIssues {
 mod tests {
     use super::GitHub;
     use crate::repositories::updater::{repository_name, RepositoryForge};
+    use chrono::Utc;
     use mockito::mock;
 
     #[test]
@@ -302,6 +361,45 @@ mod tests {
         });
     }
 
+    #[test]
+    fn rate_limit_headers() {
+        crate::test::wrapper(|env| {
+            let mut config = env.base_config();
+            config.github_accesstoken = Some("qsjdnfqdq".to_owned());
+            let updater = GitHub::new(&config).expect("GitHub::new failed").unwrap();
+            let name =
+                repository_name("https://github.com/foo/bar").expect("repository_name failed");
+
+            let reset = (Utc::now() + chrono::Duration::hours(1)).timestamp();
+            let m1 = mock("POST", "/graphql")
+                .with_header("content-type", "application/json")
+                .with_header("x-ratelimit-remaining", "10")
+                .with_header("x-ratelimit-reset", &reset.to_string())
+                .with_body(r#"{"data": {"repository": null}}"#)
+                .expect(1)
+                .create();
+
+            // no request is made until the rate limit resets
+            for _ in 0..2 {
+                match updater.fetch_repository(&name) {
+                    Err(e) if format!("{:?}", e).contains("RateLimitReached") => {}
+                    x => panic!("Expected Err(RateLimitReached), found: {:?}", x),
+                }
+            }
+            m1.assert();
+
+            *updater.rate_limit_reset.lock().unwrap() = Some(Utc::now());
+            let _m2 = mock("POST", "/graphql")
+                .with_header("content-type", "application/json")
+                .with_header("x-ratelimit-remaining", "5000")
+                .with_header("x-ratelimit-reset", &reset.to_string())
+                .with_body(r#"{"data": {"repository": null}}"#)
+                .create();
+            assert!(updater.fetch_repository(&name)?.is_none());
+            Ok(())
+        });
+    }
+
     #[test]
     fn not_found() {
         crate::test::wrapper(|env| {
@@ -340,7 +438,8 @@ mod tests {
                 .with_body(
                     r#"{"data": {"repository": {"id": "hello", "nameWithOwner": "foo/bar",
                     "description": "this is", "stargazerCount": 10, "forkCount": 11,
-                    "issues": {"totalCount": 12}}}}"#,
+                    "issues": {"totalCount": 12}, "defaultBranchRef": {"name": "main"},
+                    "isArchived": true}}}"#,
                 )
                 .create();
 
@@ -357,6 +456,8 @@ mod tests {
             assert_eq!(repo.stars, 10);
             assert_eq!(repo.forks, 11);
             assert_eq!(repo.issues, 12);
+            assert_eq!(repo.default_branch, Some("main".to_owned()));
+            assert!(repo.archived);
             Ok(())
         });
     }
@@ -24,6 +24,8 @@ const GRAPHQL_UPDATE: &str = "query($ids: [ID!]!) {
             starCount
             forksCount
             openIssuesCount
+            archived
+            repository { rootRef }
         }
     }
 }";
@@ -37,6 +39,8 @@ const GRAPHQL_SINGLE: &str = "query($fullPath: ID!) {
         starCount
         forksCount
         openIssuesCount
+        archived
+        repository { rootRef }
     }
 }";
 
@@ -97,6 +101,8 @@ impl RepositoryForge for GitLab {
                 name_with_owner: repo.full_path,
                 description: repo.description,
                 last_activity_at: repo.last_activity_at,
+                default_branch: repo.repository.and_then(|repository| repository.root_ref),
+                archived: repo.archived,
                 stars: repo.star_count,
                 forks: repo.forks_count,
                 issues: repo.open_issues_count.unwrap_or(0),
@@ -134,6 +140,8 @@ impl RepositoryForge for GitLab {
                     name_with_owner: node.full_path,
                     description: node.description,
                     last_activity_at: node.last_activity_at,
+                    default_branch: node.repository.and_then(|repository| repository.root_ref),
+                    archived: node.archived,
                     stars: node.star_count,
                     forks: node.forks_count,
                     issues: node.open_issues_count.unwrap_or(0),
@@ -242,6 +250,15 @@ struct GraphProject {
     star_count: i64,
     forks_count: i64,
     open_issues_count: Option<i64>,
+    #[serde(default)]
+    archived: bool,
+    repository: Option<GraphProjectRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphProjectRepository {
+    root_ref: Option<String>,
 }
 
 #[cfg(test)]
@@ -299,7 +316,8 @@ mod tests {
             .with_body(
                 r#"{"data": {"project": {"id": "hello", "fullPath": "foo/bar",
                 "description": "this is", "starCount": 10, "forksCount": 11,
-                "openIssuesCount": 12}}}"#,
+                "openIssuesCount": 12, "archived": false,
+                "repository": {"rootRef": "master"}}}}"#,
             )
             .create();
 
@@ -316,5 +334,7 @@ mod tests {
         assert_eq!(repo.stars, 10);
         assert_eq!(repo.forks, 11);
         assert_eq!(repo.issues, 12);
+        assert_eq!(repo.default_branch, Some("master".to_owned()));
+        assert!(!repo.archived);
     }
 }
@@ -1,5 +1,6 @@
 pub use self::github::GitHub;
 pub use self::gitlab::GitLab;
+pub use self::sourcehut::SourceHut;
 pub(crate) use self::updater::RepositoryName;
 pub use self::updater::{
     FetchRepositoriesResult, Repository, RepositoryForge, RepositoryStatsUpdater,
@@ -17,4 +18,5 @@ struct RateLimitReached;
 
 mod github;
 mod gitlab;
+mod sourcehut;
 mod updater;
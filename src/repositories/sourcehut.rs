@@ -0,0 +1,289 @@
+use crate::error::Result;
+use crate::Config;
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::{
+    blocking::Client as HttpClient,
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::repositories::{
+    FetchRepositoriesResult, Repository, RepositoryForge, RepositoryName, APP_USER_AGENT,
+};
+
+const REPOSITORY_FIELDS: &str = "id name description updated owner { canonicalName }";
+
+const GRAPHQL_SINGLE: &str = "query($owner: String!, $repo: String!) {
+    user(username: $owner) {
+        repository(name: $repo) {
+            id
+            name
+            description
+            updated
+            owner { canonicalName }
+        }
+    }
+}";
+
+/// The git repositories of sr.ht. They don't have stars, forks or issues (the bug trackers are a
+/// separate service), so only their name and activity are stored.
+pub struct SourceHut {
+    client: HttpClient,
+}
+
+impl SourceHut {
+    /// Returns `Ok(None)` if there is no access token, the API of sr.ht can't be used without one.
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        if let Some(ref token) = config.sourcehut_accesstoken {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", token))?,
+            );
+        } else {
+            warn!("did not collect `git.sr.ht` stats as no token was provided");
+            return Ok(None);
+        }
+
+        let client = HttpClient::builder().default_headers(headers).build()?;
+        Ok(Some(SourceHut { client }))
+    }
+}
+
+impl RepositoryForge for SourceHut {
+    fn host(&self) -> &'static str {
+        "git.sr.ht"
+    }
+
+    fn icon(&self) -> &'static str {
+        "git-alt"
+    }
+
+    fn has_stats(&self) -> bool {
+        false
+    }
+
+    fn chunk_size(&self) -> usize {
+        50
+    }
+
+    fn fetch_repository(&self, name: &RepositoryName) -> Result<Option<Repository>> {
+        let response: GraphResponse<GraphUser> = self.graphql(
+            GRAPHQL_SINGLE,
+            serde_json::json!({
+                // the URLs have the canonical name of the users, starting with a tilde
+                "owner": name.owner.trim_start_matches('~'),
+                "repo": name.repo,
+            }),
+        )?;
+        Ok(response
+            .data
+            .and_then(|data| data.user)
+            .and_then(|user| user.repository)
+            .map(Repository::from))
+    }
+
+    fn fetch_repositories(&self, ids: &[String]) -> Result<FetchRepositoriesResult> {
+        // There's no query returning several repositories from their ids, so one query is
+        // aliased for each of them. The ids come from the database, but they are still
+        // checked to be numbers since they are part of the query.
+        let mut ret = FetchRepositoriesResult::default();
+        let mut aliases = HashMap::new();
+        let mut query = String::from("query {");
+        for id in ids {
+            match id.parse::<i32>() {
+                Ok(numeric_id) => {
+                    let alias = format!("r{}", aliases.len());
+                    query.push_str(&format!(
+                        " {}: repository(id: {}) {{ {} }}",
+                        alias, numeric_id, REPOSITORY_FIELDS
+                    ));
+                    aliases.insert(alias, id);
+                }
+                Err(_) => ret.missing.push(id.clone()),
+            }
+        }
+        query.push_str(" }");
+        if aliases.is_empty() {
+            return Ok(ret);
+        }
+
+        let response: GraphResponse<HashMap<String, Option<GraphRepository>>> =
+            self.graphql(&query, serde_json::json!({}))?;
+        let data = match response.data {
+            Some(data) => data,
+            None => {
+                let messages: Vec<_> = response.errors.iter().map(|e| &*e.message).collect();
+                failure::bail!("error updating repositories: {}", messages.join(", "))
+            }
+        };
+        for (alias, id) in aliases {
+            match data.get(&alias).cloned().flatten() {
+                Some(repo) => {
+                    ret.present.insert(id.clone(), repo.into());
+                }
+                None => ret.missing.push(id.clone()),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl SourceHut {
+    fn graphql<T: serde::de::DeserializeOwned + std::fmt::Debug>(
+        &self,
+        query: &str,
+        variables: impl serde::Serialize,
+    ) -> Result<GraphResponse<T>> {
+        #[cfg(not(test))]
+        let host = "https://git.sr.ht/query";
+        #[cfg(test)]
+        let host = format!("{}/query", mockito::server_url());
+
+        Ok(self
+            .client
+            .post(host)
+            .json(&serde_json::json!({
+                "query": query,
+                "variables": variables,
+            }))
+            .send()?
+            .error_for_status()?
+            .json()?)
+    }
+}
+
+impl From<GraphRepository> for Repository {
+    fn from(repo: GraphRepository) -> Self {
+        Repository {
+            id: repo.id.to_string(),
+            name_with_owner: format!("{}/{}", repo.owner.canonical_name, repo.name),
+            description: repo
+                .description
+                .filter(|description| !description.is_empty()),
+            last_activity_at: Some(repo.updated),
+            default_branch: None,
+            archived: false,
+            stars: 0,
+            forks: 0,
+            issues: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphUser {
+    user: Option<GraphUserRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphUserRepository {
+    repository: Option<GraphRepository>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GraphRepository {
+    id: i32,
+    name: String,
+    description: Option<String>,
+    updated: DateTime<Utc>,
+    owner: GraphOwner,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphOwner {
+    canonical_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceHut;
+    use crate::repositories::updater::{repository_name, RepositoryForge};
+    use crate::test::{wrapper, TestEnvironment};
+    use mockito::{mock, Matcher};
+
+    fn updater(env: &TestEnvironment) -> SourceHut {
+        let mut config = env.base_config();
+        config.sourcehut_accesstoken = Some("qsjdnfqdq".to_owned());
+        SourceHut::new(&config)
+            .expect("SourceHut::new failed")
+            .unwrap()
+    }
+
+    #[test]
+    fn get_repository_info() {
+        wrapper(|env| {
+            let _m1 = mock("POST", "/query")
+                .match_body(Matcher::PartialJsonString(
+                    r#"{"variables": {"owner": "ireas", "repo": "merge-rs"}}"#.into(),
+                ))
+                .with_header("content-type", "application/json")
+                .with_body(
+                    r#"{"data": {"user": {"repository": {"id": 42, "name": "merge-rs",
+                    "description": "merge structs", "updated": "2021-05-01T10:00:00Z",
+                    "owner": {"canonicalName": "~ireas"}}}}}"#,
+                )
+                .create();
+
+            let repo = updater(env)
+                .fetch_repository(
+                    &repository_name("https://git.sr.ht/~ireas/merge-rs")
+                        .expect("repository_name failed"),
+                )
+                .expect("fetch_repository failed")
+                .unwrap();
+
+            assert_eq!(repo.id, "42");
+            assert_eq!(repo.name_with_owner, "~ireas/merge-rs");
+            assert_eq!(repo.description, Some("merge structs".to_owned()));
+            assert_eq!(repo.stars, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn update_repositories() {
+        wrapper(|env| {
+            let _m1 = mock("POST", "/query")
+                .match_body(Matcher::Regex(
+                    r"r0: repository\(id: 42\).*r1: repository\(id: 43\)".into(),
+                ))
+                .with_header("content-type", "application/json")
+                .with_body(
+                    r#"{"data": {"r0": {"id": 42, "name": "merge-rs", "description": "",
+                    "updated": "2021-05-01T10:00:00Z", "owner": {"canonicalName": "~ireas"}},
+                    "r1": null}}"#,
+                )
+                .create();
+
+            let mut res = updater(env)
+                .fetch_repositories(&["42".into(), "43".into(), "not-an-id".into()])
+                .expect("fetch_repositories failed");
+
+            res.missing.sort();
+            assert_eq!(res.missing, vec!["43", "not-an-id"]);
+            assert_eq!(res.present.len(), 1);
+            assert_eq!(res.present["42"].description, None);
+            Ok(())
+        });
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::repositories::{GitHub, GitLab, RateLimitReached};
+use crate::repositories::{GitHub, GitLab, RateLimitReached, SourceHut};
 use crate::utils::MetadataPackage;
 use crate::{db::Pool, Config};
 use chrono::{DateTime, Utc};
@@ -18,6 +18,12 @@ pub trait RepositoryForge {
     /// FontAwesome icon used in the front-end.
     fn icon(&self) -> &'static str;
 
+    /// Whether the forge has stars, forks and issues. Only the name of the repositories is shown
+    /// when it doesn't.
+    fn has_stats(&self) -> bool {
+        true
+    }
+
     /// How many items we can query in one graphql request.
     fn chunk_size(&self) -> usize;
 
@@ -38,6 +44,8 @@ pub struct Repository {
     pub name_with_owner: String,
     pub description: Option<String>,
     pub last_activity_at: Option<DateTime<Utc>>,
+    pub default_branch: Option<String>,
+    pub archived: bool,
     pub stars: i64,
     pub forks: i64,
     pub issues: i64,
@@ -76,6 +84,9 @@ impl RepositoryStatsUpdater {
         if let Ok(updater) = GitLab::new("gitlab.freedesktop.org", &None) {
             updaters.push(Box::new(updater));
         }
+        if let Ok(Some(updater)) = SourceHut::new(config) {
+            updaters.push(Box::new(updater));
+        }
         Self { updaters, pool }
     }
 
@@ -131,11 +142,15 @@ impl RepositoryStatsUpdater {
         'updaters: for updater in &self.updaters {
             info!("started updating `{}` repositories stats", updater.host());
 
+            // Every chunk is stored as soon as it's fetched, so starting with the least recently
+            // updated repositories resumes where an interrupted run (for example because of the
+            // rate limit) stopped.
             let needs_update = conn
                 .query(
                     "SELECT host_id
                      FROM repositories
-                     WHERE host = $1 AND updated_at < NOW() - INTERVAL '1 day';",
+                     WHERE host = $1 AND updated_at < NOW() - INTERVAL '1 day'
+                     ORDER BY updated_at, id;",
                     &[&updater.host()],
                 )?
                 .into_iter()
@@ -238,6 +253,13 @@ impl RepositoryStatsUpdater {
         "code-branch"
     }
 
+    pub fn has_stats(&self, host: &str) -> bool {
+        self.updaters
+            .iter()
+            .filter(|updater| updater.host() == host)
+            .all(|updater| updater.has_stats())
+    }
+
     fn store_repository(&self, conn: &mut Client, host: &str, repo: Repository) -> Result<i32> {
         trace!(
             "storing {} repository stats for {}",
@@ -246,8 +268,9 @@ impl RepositoryStatsUpdater {
         );
         let data = conn.query_one(
             "INSERT INTO repositories (
-                 host, host_id, name, description, last_commit, stars, forks, issues, updated_at
-             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
+                 host, host_id, name, description, last_commit, stars, forks, issues,
+                 default_branch, archived, updated_at
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW())
              ON CONFLICT (host, host_id) DO
              UPDATE SET
                  name = $3,
@@ -256,6 +279,8 @@ impl RepositoryStatsUpdater {
                  stars = $6,
                  forks = $7,
                  issues = $8,
+                 default_branch = $9,
+                 archived = $10,
                  updated_at = NOW()
              RETURNING id;",
             &[
@@ -267,6 +292,8 @@ impl RepositoryStatsUpdater {
                 &(repo.stars as i32),
                 &(repo.forks as i32),
                 &(repo.issues as i32),
+                &repo.default_branch,
+                &repo.archived,
             ],
         )?;
         Ok(data.get(0))
@@ -288,7 +315,7 @@ impl RepositoryStatsUpdater {
 
 pub(crate) fn repository_name(url: &str) -> Option<RepositoryName> {
     static RE: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r"https?://(?P<host>[^/]+)/(?P<owner>[\w\._/-]+)/(?P<repo>[\w\._-]+)").unwrap()
+        Regex::new(r"https?://(?P<host>[^/]+)/(?P<owner>[\w\._/~-]+)/(?P<repo>[\w\._-]+)").unwrap()
     });
 
     let cap = RE.captures(url)?;
@@ -386,8 +413,11 @@ mod test {
         assert_name("https://github.com", None);
         assert_name("https://github.com/", None);
 
-        // Unknown host
-        assert_name("https://git.sr.ht/~ireas/merge-rs", None);
+        // sr.ht checks
+        assert_name(
+            "https://git.sr.ht/~ireas/merge-rs",
+            ("~ireas", "merge-rs", "git.sr.ht"),
+        );
     }
 
     #[test]
@@ -408,6 +438,13 @@ mod test {
                 updater.get_icon_name("a.gitlab.freedesktop.org"),
                 "code-branch"
             );
+            assert_eq!(updater.get_icon_name("git.sr.ht"), "code-branch");
+
+            config.sourcehut_accesstoken = Some("qsjdnfqdq".to_owned());
+            let updater = RepositoryStatsUpdater::new(&config, env.pool()?);
+            assert_eq!(updater.get_icon_name("git.sr.ht"), "git-alt");
+            assert!(!updater.has_stats("git.sr.ht"));
+            assert!(updater.has_stats("github.com"));
             Ok(())
         });
     }
@@ -0,0 +1,284 @@
+//! In-memory cache of small files
+//!
+//! Some files, like the search index and the stylesheets of popular crates, are requested over
+//! and over, and fetching them from S3 every time adds a round trip to each page view. Small files
+//! are kept in memory for a few minutes (`DOCSRS_STORAGE_CACHE_TTL`), evicting the least recently
+//! used ones once the cache reaches `DOCSRS_STORAGE_CACHE_SIZE` bytes.
+//!
+//! The files changed by this process are dropped from the cache right away, while the changes
+//! made by other processes (like the builder) are only visible once the entries expire.
+
+use super::Blob;
+use crate::{Config, Metrics};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(super) struct BlobCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    max_file_size: usize,
+    ttl: Duration,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// The paths of the entries, least recently used first
+    recency: BTreeMap<u64, String>,
+    /// Incremented by every access, used to order `recency`
+    clock: u64,
+    /// Incremented by every invalidation, so that a fetch racing with a change of the file doesn't
+    /// store a content that's already outdated
+    generation: u64,
+    size: usize,
+}
+
+struct Entry {
+    blob: Blob,
+    inserted: Instant,
+    last_access: u64,
+}
+
+impl BlobCache {
+    /// Returns `None` when the cache is disabled
+    pub(super) fn new(metrics: Arc<Metrics>, config: &Config) -> Option<Self> {
+        let capacity = config.storage_cache_size.filter(|&size| size > 0)?;
+        Some(Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+            max_file_size: config.storage_cache_max_file_size.min(capacity),
+            ttl: Duration::from_secs(config.storage_cache_ttl),
+            metrics,
+        })
+    }
+
+    /// Returns the cached file, if it's not larger than `max_size`
+    pub(super) fn get(&self, path: &str, max_size: usize) -> Option<Blob> {
+        let mut inner = self.inner.lock().unwrap();
+        let (expired, fits) = match inner.entries.get(path) {
+            Some(entry) => (
+                entry.inserted.elapsed() >= self.ttl,
+                entry.blob.content.len() <= max_size,
+            ),
+            None => (false, false),
+        };
+        if expired {
+            inner.remove(path);
+            self.metrics.storage_cache_size.set(inner.size as i64);
+        }
+
+        let result = if fits && !expired {
+            inner.touch(path);
+            inner.entries.get(path).map(|entry| entry.blob.clone())
+        } else {
+            None
+        };
+        self.metrics
+            .storage_cache_lookups
+            .with_label_values(&[if result.is_some() { "hit" } else { "miss" }])
+            .inc();
+        result
+    }
+
+    /// Must be called before fetching a file from the backend, and passed to `insert` afterwards
+    pub(super) fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    /// Caches a decompressed file fetched from the backend, unless it's too large or it changed
+    /// since `generation` was retrieved
+    pub(super) fn insert(&self, blob: &Blob, generation: u64) {
+        let size = blob.content.len();
+        if size > self.max_file_size {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(&blob.path);
+        while inner.size + size > self.capacity {
+            let oldest = match inner.recency.values().next() {
+                Some(path) => path.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+
+        inner.clock += 1;
+        let last_access = inner.clock;
+        inner.recency.insert(last_access, blob.path.clone());
+        inner.entries.insert(
+            blob.path.clone(),
+            Entry {
+                blob: blob.clone(),
+                inserted: Instant::now(),
+                last_access,
+            },
+        );
+        inner.size += size;
+        self.metrics.storage_cache_size.set(inner.size as i64);
+    }
+
+    /// Drops the cached copies of the files, called when they change
+    pub(super) fn invalidate(&self, paths: &[String]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        for path in paths {
+            inner.remove(path);
+        }
+        self.metrics.storage_cache_size.set(inner.size as i64);
+    }
+
+    /// Drops the cached files whose path starts with `prefix`, called when they are deleted
+    pub(super) fn invalidate_prefix(&self, prefix: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        let paths: Vec<_> = inner
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(prefix))
+            .cloned()
+            .collect();
+        for path in paths {
+            inner.remove(&path);
+        }
+        self.metrics.storage_cache_size.set(inner.size as i64);
+    }
+}
+
+impl Inner {
+    fn touch(&mut self, path: &str) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.recency.remove(&entry.last_access);
+            entry.last_access = clock;
+            self.recency.insert(clock, path.to_owned());
+        }
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.recency.remove(&entry.last_access);
+            self.size -= entry.blob.content.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+    use chrono::Utc;
+
+    fn blob(path: &str, size: usize) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            content: vec![b'a'; size],
+            compression: None,
+            content_hash: None,
+        }
+    }
+
+    fn cache(env: &TestEnvironment) -> BlobCache {
+        env.override_config(|config| {
+            config.storage_cache_size = Some(100);
+            config.storage_cache_max_file_size = 40;
+        });
+        BlobCache::new(env.metrics(), &env.config()).unwrap()
+    }
+
+    fn insert(cache: &BlobCache, blob: &Blob) {
+        cache.insert(blob, cache.generation());
+    }
+
+    #[test]
+    fn disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.storage_cache_size = Some(0));
+            assert!(BlobCache::new(env.metrics(), &env.config()).is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        wrapper(|env| {
+            let cache = cache(env);
+            insert(&cache, &blob("a", 40));
+            insert(&cache, &blob("b", 40));
+            assert!(cache.get("a", 100).is_some());
+
+            // `b` is the least recently used file
+            insert(&cache, &blob("c", 40));
+            assert!(cache.get("a", 100).is_some());
+            assert!(cache.get("b", 100).is_none());
+            assert!(cache.get("c", 100).is_some());
+
+            let metrics = env.metrics();
+            assert_eq!(metrics.storage_cache_size.get(), 80);
+            let lookups = |result| metrics.storage_cache_lookups.with_label_values(&[result]);
+            assert_eq!(lookups("hit").get(), 3);
+            assert_eq!(lookups("miss").get(), 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn size_limits() {
+        wrapper(|env| {
+            let cache = cache(env);
+            insert(&cache, &blob("large", 41));
+            assert!(cache.get("large", 100).is_none());
+
+            insert(&cache, &blob("small", 20));
+            // the caller doesn't accept files that large
+            assert!(cache.get("small", 10).is_none());
+            assert!(cache.get("small", 20).is_some());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn expiration() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.storage_cache_size = Some(100);
+                config.storage_cache_ttl = 0;
+            });
+            let cache = BlobCache::new(env.metrics(), &env.config()).unwrap();
+            insert(&cache, &blob("a", 10));
+            assert!(cache.get("a", 100).is_none());
+            assert_eq!(env.metrics().storage_cache_size.get(), 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalidation() {
+        wrapper(|env| {
+            let cache = cache(env);
+            insert(&cache, &blob("rustdoc/foo/0.1.0/index.html", 10));
+            insert(&cache, &blob("rustdoc/foo/0.1.0/all.html", 10));
+            insert(&cache, &blob("rustdoc/foobar/0.1.0/index.html", 10));
+
+            cache.invalidate_prefix("rustdoc/foo/");
+            assert!(cache.get("rustdoc/foo/0.1.0/index.html", 100).is_none());
+            assert!(cache.get("rustdoc/foo/0.1.0/all.html", 100).is_none());
+            assert!(cache.get("rustdoc/foobar/0.1.0/index.html", 100).is_some());
+
+            // the file was fetched before it changed
+            let generation = cache.generation();
+            cache.invalidate(&["rustdoc/foo/0.1.0/index.html".into()]);
+            cache.insert(&blob("rustdoc/foo/0.1.0/index.html", 10), generation);
+            assert!(cache.get("rustdoc/foo/0.1.0/index.html", 100).is_none());
+            Ok(())
+        });
+    }
+}
@@ -5,6 +5,9 @@ use failure::Error;
 use postgres::Transaction;
 use std::sync::Arc;
 
+/// Number of files removed by each query of `delete_prefix`
+const DELETE_BATCH_SIZE: i64 = 1000;
+
 pub(crate) struct DatabaseBackend {
     pool: Pool,
     metrics: Arc<Metrics>,
@@ -32,7 +35,7 @@ impl DatabaseBackend {
         // the limit is exceeded.
         let rows = self.pool.get()?.query(
             "SELECT
-                 path, mime, date_updated, compression,
+                 path, mime, date_updated, compression, content_hash,
                  (CASE WHEN LENGTH(content) <= $2 THEN content ELSE NULL END) AS content,
                  (LENGTH(content) > $2) AS is_too_big
              FROM files
@@ -63,6 +66,7 @@ impl DatabaseBackend {
                 date_updated: row.get("date_updated"),
                 content: row.get("content"),
                 compression,
+                content_hash: row.get("content_hash"),
             })
         }
     }
@@ -100,12 +104,14 @@ impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<(), Error> {
         for blob in batch {
             let compression = blob.compression.map(|alg| alg as i32);
+            let content_hash = super::content_hash(&blob.content);
             self.transaction.query(
-                "INSERT INTO files (path, mime, content, compression)
-                 VALUES ($1, $2, $3, $4)
+                "INSERT INTO files (path, mime, content, compression, content_hash)
+                 VALUES ($1, $2, $3, $4, $5)
                  ON CONFLICT (path) DO UPDATE
-                    SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression",
-                &[&blob.path, &blob.mime, &blob.content, &compression],
+                    SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression,
+                        content_hash = EXCLUDED.content_hash, date_updated = NOW()",
+                &[&blob.path, &blob.mime, &blob.content, &compression, &content_hash],
             )?;
             self.metrics.uploaded_files_total.inc();
         }
@@ -113,10 +119,35 @@ impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     }
 
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
-        self.transaction.execute(
-            "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}%", prefix.replace('%', "\\%"))],
-        )?;
+        // A single DELETE of a large crate keeps the files table busy for minutes, so the files
+        // are removed in batches, resuming after the last path deleted.
+        let pattern = format!("{}%", prefix.replace('%', "\\%"));
+        let mut last_path = String::new();
+        let mut deleted = 0;
+        loop {
+            let rows = self.transaction.query(
+                "DELETE FROM files
+                 WHERE path IN (
+                    SELECT path FROM files
+                    WHERE path LIKE $1 AND path > $2
+                    ORDER BY path
+                    LIMIT $3
+                 )
+                 RETURNING path",
+                &[&pattern, &last_path, &DELETE_BATCH_SIZE],
+            )?;
+
+            deleted += rows.len();
+            self.metrics.deleted_files_total.inc_by(rows.len() as i64);
+            match rows.iter().map(|row| row.get::<_, String>(0)).max() {
+                Some(path) => last_path = path,
+                None => break,
+            }
+            if (rows.len() as i64) < DELETE_BATCH_SIZE {
+                break;
+            }
+            log::debug!("deleted {} files under {} so far", deleted, prefix);
+        }
         Ok(())
     }
 
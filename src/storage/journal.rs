@@ -0,0 +1,215 @@
+//! Journal of the batches uploaded by `store_all`.
+//!
+//! Large directories are uploaded in batches of [`MAX_CONCURRENT_UPLOADS`] files, and the
+//! builder can crash between two of them, leaving the prefix half-uploaded. Every batch is
+//! recorded in the `upload_journal` table once it's stored, with a digest of its paths and
+//! contents, so that uploading the same files again skips the batches already stored. The
+//! journal of a prefix is cleared once all of its batches are uploaded: the prefixes still in the
+//! journal long after their last batch were abandoned, and
+//! [`Storage::cleanup_partial_uploads`] deletes them, unless they belong to a release which is
+//! already served: an interrupted rebuild only overwrote some of its files, which are kept.
+//!
+//! [`MAX_CONCURRENT_UPLOADS`]: super::MAX_CONCURRENT_UPLOADS
+//! [`Storage::cleanup_partial_uploads`]: super::Storage::cleanup_partial_uploads
+
+use super::{content_hash, Blob};
+use crate::db::Pool;
+use failure::Error;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Identifies the files of a batch, to only skip it when the same files are uploaded again
+pub(super) fn batch_digest(batch: &[Blob]) -> String {
+    let mut hasher = Sha256::new();
+    for blob in batch {
+        hasher.update(blob.path.as_bytes());
+        hasher.update([0]);
+        hasher.update(content_hash(&blob.content).as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+pub(super) struct UploadJournal {
+    pool: Pool,
+}
+
+impl UploadJournal {
+    pub(super) fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    /// The digests of the batches of `prefix` already uploaded, by batch number
+    pub(super) fn uploaded_batches(&self, prefix: &str) -> Result<HashMap<i32, String>, Error> {
+        Ok(self
+            .pool
+            .get()?
+            .query(
+                "SELECT batch, digest FROM upload_journal WHERE prefix = $1",
+                &[&prefix],
+            )?
+            .into_iter()
+            .map(|row| (row.get("batch"), row.get("digest")))
+            .collect())
+    }
+
+    pub(super) fn record(&self, prefix: &str, batch: i32, digest: &str) -> Result<(), Error> {
+        self.pool.get()?.execute(
+            "INSERT INTO upload_journal (prefix, batch, digest)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (prefix, batch) DO UPDATE
+                SET digest = EXCLUDED.digest, uploaded_at = NOW()",
+            &[&prefix, &batch, &digest],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the batches of `prefix`, once it's fully uploaded or deleted
+    pub(super) fn clear(&self, prefix: &str) -> Result<(), Error> {
+        self.pool
+            .get()?
+            .execute("DELETE FROM upload_journal WHERE prefix = $1", &[&prefix])?;
+        Ok(())
+    }
+
+    /// The prefixes whose last batch was uploaded more than `older_than` ago, and whether they
+    /// belong to a release already served. The prefixes are `<kind>/<name>/<version>`, and the
+    /// documentation is only served once a build succeeded. The uploads spilled to disk are
+    /// excluded, they will be resumed once the storage is reachable again.
+    pub(super) fn abandoned(&self, older_than: Duration) -> Result<Vec<(String, bool)>, Error> {
+        Ok(self
+            .pool
+            .get()?
+            .query(
+                "SELECT
+                    journal.prefix,
+                    EXISTS (
+                        SELECT 1
+                        FROM releases
+                        INNER JOIN crates ON crates.id = releases.crate_id
+                        WHERE
+                            crates.name = split_part(journal.prefix, '/', 2) AND
+                            releases.version = split_part(journal.prefix, '/', 3) AND
+                            (releases.rustdoc_status OR
+                                split_part(journal.prefix, '/', 1) <> 'rustdoc')
+                    ) AS served
+                 FROM (
+                    SELECT prefix
+                    FROM upload_journal
+                    WHERE prefix NOT IN (SELECT prefix FROM pending_uploads)
+                    GROUP BY prefix
+                    HAVING MAX(uploaded_at) < NOW() - make_interval(secs => $1)
+                 ) AS journal
+                 ORDER BY journal.prefix",
+                &[&older_than.as_secs_f64()],
+            )?
+            .into_iter()
+            .map(|row| (row.get("prefix"), row.get("served")))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{compress, CompressionAlgorithm};
+    use crate::test::wrapper;
+    use chrono::Utc;
+    use std::path::Path;
+
+    #[test]
+    fn uploaded_batches_are_skipped() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            std::fs::write(dir.path().join("index.html"), "<html>")?;
+
+            let alg = CompressionAlgorithm::default();
+            let digest = batch_digest(&[Blob {
+                path: "rustdoc/foo/0.1.0/index.html".into(),
+                mime: "text/html".into(),
+                content: compress(&b"<html>"[..], alg)?,
+                compression: Some(alg),
+                date_updated: Utc::now(),
+                content_hash: None,
+            }]);
+            let journal = UploadJournal::new(env.db().pool());
+            journal.record("rustdoc/foo/0.1.0", 0, &digest)?;
+
+            let storage = env.storage();
+            let (files, _, _) =
+                storage.store_all(Path::new("rustdoc/foo/0.1.0"), dir.path(), None)?;
+            assert_eq!(files.len(), 1);
+            assert_eq!(env.metrics().skipped_upload_batches.get(), 1);
+            // the batch is only in the journal, it wasn't uploaded again
+            assert!(!storage.exists("rustdoc/foo/0.1.0/index.html")?);
+            assert!(journal.uploaded_batches("rustdoc/foo/0.1.0")?.is_empty());
+
+            // different files are uploaded
+            std::fs::write(dir.path().join("index.html"), "<html></html>")?;
+            journal.record("rustdoc/foo/0.1.0", 0, &digest)?;
+            storage.store_all(Path::new("rustdoc/foo/0.1.0"), dir.path(), None)?;
+            assert!(storage.exists("rustdoc/foo/0.1.0/index.html")?);
+            assert_eq!(env.metrics().skipped_upload_batches.get(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn abandoned_uploads_are_deleted() {
+        wrapper(|env| {
+            let storage = env.storage();
+            storage.store_one("rustdoc/foo/0.1.0/index.html", "<html>")?;
+            storage.store_one("rustdoc/bar/0.1.0/index.html", "<html>")?;
+
+            let journal = UploadJournal::new(env.db().pool());
+            journal.record("rustdoc/foo/0.1.0", 0, "digest")?;
+            journal.record("rustdoc/bar/0.1.0", 0, "digest")?;
+            env.db().conn().execute(
+                "UPDATE upload_journal SET uploaded_at = NOW() - INTERVAL '2 days'
+                 WHERE prefix = 'rustdoc/foo/0.1.0'",
+                &[],
+            )?;
+
+            assert_eq!(
+                storage.cleanup_partial_uploads(Duration::from_secs(24 * 60 * 60))?,
+                1
+            );
+            assert!(!storage.exists("rustdoc/foo/0.1.0/index.html")?);
+            assert!(storage.exists("rustdoc/bar/0.1.0/index.html")?);
+            assert_eq!(
+                journal.abandoned(Duration::from_secs(0))?,
+                vec![("rustdoc/bar/0.1.0".to_owned(), false)]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn interrupted_rebuilds_keep_the_documentation() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            let storage = env.storage();
+            storage.store_one("sources/foo/0.1.0/Cargo.toml", "[package]")?;
+            // the rebuild crashed after overwriting some of the files
+            let journal = UploadJournal::new(env.db().pool());
+            journal.record("rustdoc/foo/0.1.0", 0, "digest")?;
+            journal.record("sources/foo/0.1.0", 0, "digest")?;
+            env.db().conn().execute(
+                "UPDATE upload_journal SET uploaded_at = NOW() - INTERVAL '2 days'",
+                &[],
+            )?;
+
+            assert_eq!(
+                storage.cleanup_partial_uploads(Duration::from_secs(24 * 60 * 60))?,
+                0
+            );
+            assert!(storage.exists("rustdoc/foo/0.1.0/foo/index.html")?);
+            assert!(storage.exists("sources/foo/0.1.0/Cargo.toml")?);
+            assert!(journal.abandoned(Duration::from_secs(0))?.is_empty());
+
+            Ok(())
+        });
+    }
+}
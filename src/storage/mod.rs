@@ -1,11 +1,17 @@
+mod cache;
 mod compression;
 mod database;
+mod journal;
+mod quarantine;
 mod s3;
 
+use self::cache::BlobCache;
 pub use self::compression::{compress, decompress, CompressionAlgorithm, CompressionAlgorithms};
 use self::database::DatabaseBackend;
+use self::journal::{batch_digest, UploadJournal};
+use self::quarantine::Quarantine;
 use self::s3::S3Backend;
-use crate::{db::Pool, Config, Metrics};
+use crate::{cdn::CdnBackend, db::Pool, Config, Metrics};
 use chrono::{DateTime, Utc};
 use failure::{err_msg, Error};
 use path_slash::PathExt;
@@ -13,8 +19,10 @@ use std::{
     collections::{HashMap, HashSet},
     ffi::OsStr,
     fmt, fs,
+    io::Read,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 const MAX_CONCURRENT_UPLOADS: usize = 1000;
@@ -23,6 +31,72 @@ const MAX_CONCURRENT_UPLOADS: usize = 1000;
 #[fail(display = "path not found")]
 pub(crate) struct PathNotFoundError;
 
+/// How many bytes a directory takes in the storage, and how many it had before the compression
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StoredSize {
+    pub compressed: u64,
+    pub uncompressed: u64,
+}
+
+/// The maximum size of the files uploaded by [`Storage::store_all`], in bytes before compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadLimit {
+    pub file_size: u64,
+    pub total_size: u64,
+}
+
+impl UploadLimit {
+    /// Checks the files of a directory before anything is uploaded, so that a directory too
+    /// large isn't partially stored
+    fn check(&self, root_dir: &Path) -> Result<(), Error> {
+        let mut total_size = 0;
+        for file_path in get_file_list(root_dir)? {
+            let size = match fs::metadata(root_dir.join(&file_path)) {
+                Ok(metadata) => metadata.len(),
+                // skipped by the upload too
+                Err(_) => continue,
+            };
+            if size > self.file_size {
+                return Err(UploadSizeExceeded::File {
+                    path: file_path.to_slash_lossy(),
+                    size,
+                    limit: self.file_size,
+                }
+                .into());
+            }
+            total_size += size;
+        }
+        if total_size > self.total_size {
+            return Err(UploadSizeExceeded::Total {
+                size: total_size,
+                limit: self.total_size,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, failure::Fail)]
+pub(crate) enum UploadSizeExceeded {
+    #[fail(
+        display = "{} takes {} bytes, more than the {} bytes allowed for a single file",
+        path, size, limit
+    )]
+    File { path: String, size: u64, limit: u64 },
+    #[fail(
+        display = "the files take {} bytes, more than the {} bytes allowed",
+        size, limit
+    )]
+    Total { size: u64, limit: u64 },
+}
+
+type StoredFiles = (
+    HashMap<PathBuf, String>,
+    HashSet<CompressionAlgorithm>,
+    StoredSize,
+);
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Blob {
     pub(crate) path: String,
@@ -30,6 +104,15 @@ pub(crate) struct Blob {
     pub(crate) date_updated: DateTime<Utc>,
     pub(crate) content: Vec<u8>,
     pub(crate) compression: Option<CompressionAlgorithm>,
+    /// Hash of the stored (possibly compressed) content, computed by the backend when storing
+    pub(crate) content_hash: Option<String>,
+}
+
+/// Computes the hash stored alongside every blob, used to generate `ETag`s
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("{:x}", Sha256::digest(content))
 }
 
 fn get_file_list_from_dir<P: AsRef<Path>>(path: P, files: &mut Vec<PathBuf>) -> Result<(), Error> {
@@ -96,39 +179,185 @@ enum StorageBackend {
 
 pub struct Storage {
     backend: StorageBackend,
+    /// Small files kept in memory, `None` when the cache is disabled
+    cache: Option<BlobCache>,
+    quarantine: Option<Quarantine>,
+    journal: UploadJournal,
+    cdn: CdnBackend,
+    metrics: Arc<Metrics>,
+    /// Binary files larger than this are downloaded from presigned urls
+    presigned_url_threshold: Option<usize>,
 }
 
 impl Storage {
     pub fn new(pool: Pool, metrics: Arc<Metrics>, config: &Config) -> Result<Self, Error> {
         Ok(Storage {
+            quarantine: config
+                .upload_spill_dir
+                .clone()
+                .map(|dir| Quarantine::new(dir, pool.clone())),
+            journal: UploadJournal::new(pool.clone()),
+            cdn: CdnBackend::new(config)?,
+            cache: BlobCache::new(metrics.clone(), config),
             backend: match config.storage_backend {
                 StorageKind::Database => {
-                    StorageBackend::Database(DatabaseBackend::new(pool, metrics))
+                    StorageBackend::Database(DatabaseBackend::new(pool, metrics.clone()))
+                }
+                StorageKind::S3 => {
+                    StorageBackend::S3(Box::new(S3Backend::new(metrics.clone(), config)?))
                 }
-                StorageKind::S3 => StorageBackend::S3(Box::new(S3Backend::new(metrics, config)?)),
             },
+            metrics,
+            presigned_url_threshold: config.presigned_url_threshold,
         })
     }
 
     pub(crate) fn exists(&self, path: &str) -> Result<bool, Error> {
-        match &self.backend {
+        let res = match &self.backend {
             StorageBackend::Database(db) => db.exists(path),
             StorageBackend::S3(s3) => s3.exists(path),
+        };
+        let res = match res {
+            Ok(false) => Ok(self.quarantine.as_ref().is_some_and(|q| q.exists(path))),
+            res => res,
+        };
+        if let Err(err) = &res {
+            // log lines emitted while serving a request carry its id
+            log::warn!("failed to check if {} exists in the storage: {}", path, err);
         }
+        res
     }
 
     pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
-        let mut blob = match &self.backend {
+        let generation = match &self.cache {
+            Some(cache) => match cache.get(path, max_size) {
+                Some(blob) => return Ok(blob),
+                None => Some(cache.generation()),
+            },
+            None => None,
+        };
+
+        let res = match &self.backend {
             StorageBackend::Database(db) => db.get(path, max_size),
             StorageBackend::S3(s3) => s3.get(path, max_size),
-        }?;
+        };
+        self.fetched(
+            path,
+            max_size,
+            generation,
+            self.or_spilled(path, max_size, res),
+        )
+    }
+
+    /// Like `get`, without blocking the thread while the file is downloaded from S3. The database
+    /// backend still blocks, it's only allowed on the threads of a multi-threaded runtime.
+    #[cfg(feature = "hyper-server")]
+    pub(crate) async fn get_async(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        let generation = match &self.cache {
+            Some(cache) => match cache.get(path, max_size) {
+                Some(blob) => return Ok(blob),
+                None => Some(cache.generation()),
+            },
+            None => None,
+        };
+
+        let res = match &self.backend {
+            StorageBackend::Database(db) => tokio::task::block_in_place(|| db.get(path, max_size)),
+            StorageBackend::S3(s3) => s3.get_async(path, max_size).await,
+        };
+        self.fetched(
+            path,
+            max_size,
+            generation,
+            self.or_spilled(path, max_size, res),
+        )
+    }
+
+    /// Falls back to the spill directory when the file isn't in the backend, it's served from
+    /// there until its upload is replayed
+    fn or_spilled(
+        &self,
+        path: &str,
+        max_size: usize,
+        res: Result<Blob, Error>,
+    ) -> Result<Blob, Error> {
+        match (&res, &self.quarantine) {
+            (Err(err), Some(quarantine)) if err.downcast_ref::<PathNotFoundError>().is_some() => {
+                match quarantine.get(path, max_size)? {
+                    Some(blob) => Ok(blob),
+                    None => res,
+                }
+            }
+            _ => res,
+        }
+    }
+
+    /// Decompresses the file fetched from the backend and caches it
+    fn fetched(
+        &self,
+        path: &str,
+        max_size: usize,
+        generation: Option<u64>,
+        res: Result<Blob, Error>,
+    ) -> Result<Blob, Error> {
+        let mut blob = match res {
+            Ok(blob) => blob,
+            Err(err) => {
+                if err.downcast_ref::<PathNotFoundError>().is_none() {
+                    log::warn!("failed to fetch {} from the storage: {}", path, err);
+                }
+                return Err(err);
+            }
+        };
+        // files stored before hashes were recorded don't have one
+        if blob.content_hash.is_none() {
+            blob.content_hash = Some(content_hash(&blob.content));
+        }
         if let Some(alg) = blob.compression {
             blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
             blob.compression = None;
         }
+        if let (Some(cache), Some(generation)) = (&self.cache, generation) {
+            cache.insert(&blob, generation);
+        }
         Ok(blob)
     }
 
+    /// Whether the backend can create temporary urls to download files directly from it
+    pub(crate) fn supports_presigned_urls(&self) -> bool {
+        matches!(self.backend, StorageBackend::S3(_))
+    }
+
+    /// Returns a temporary url to download the file directly from the storage when it's larger
+    /// than `min_size`, or `None` if it's smaller or the backend can't serve it as-is (because
+    /// it's compressed, or stored in the database)
+    pub(crate) fn presigned_url(
+        &self,
+        path: &str,
+        min_size: usize,
+        expires_in: Duration,
+    ) -> Result<Option<String>, Error> {
+        match &self.backend {
+            StorageBackend::Database(_) => Ok(None),
+            StorageBackend::S3(s3) => s3.presigned_url(path, min_size, expires_in),
+        }
+    }
+
+    /// Browsers can't decompress the files downloaded from presigned urls, so the files served
+    /// from them are stored uncompressed
+    fn compression_for(&self, mime: &str, size: u64) -> Option<CompressionAlgorithm> {
+        match self.presigned_url_threshold {
+            Some(threshold)
+                if self.supports_presigned_urls()
+                    && !mime.starts_with("text/")
+                    && size > threshold as u64 =>
+            {
+                None
+            }
+            _ => Some(CompressionAlgorithm::default()),
+        }
+    }
+
     fn transaction<T, F>(&self, f: F) -> Result<T, Error>
     where
         F: FnOnce(&mut dyn StorageTransaction) -> Result<T, Error>,
@@ -149,16 +378,119 @@ impl Storage {
 
     // Store all files in `root_dir` into the backend under `prefix`.
     //
-    // This returns (map<filename, mime type>, set<compression algorithms>).
+    // If the storage can't be reached and a spill directory is configured, the files are kept
+    // there, and served from it, until `recover_spilled_uploads` manages to upload them.
+    //
+    // Nothing is uploaded when the files are larger than the `limit`.
+    //
+    // This returns (map<filename, mime type>, set<compression algorithms>, stored size).
     pub(crate) fn store_all(
         &self,
         prefix: &Path,
         root_dir: &Path,
-    ) -> Result<(HashMap<PathBuf, String>, HashSet<CompressionAlgorithm>), Error> {
+        limit: Option<UploadLimit>,
+    ) -> Result<StoredFiles, Error> {
+        if let Some(limit) = limit {
+            limit.check(root_dir)?;
+        }
+        let err = match self.store_all_inner(prefix, root_dir) {
+            Ok(res) => return Ok(res),
+            Err(err) => err,
+        };
+        let quarantine = match &self.quarantine {
+            Some(quarantine) if quarantine::is_unreachable(&err) => quarantine,
+            _ => return Err(err),
+        };
+
+        let prefix = prefix.to_slash().unwrap();
+        log::error!("failed to upload {}, spilling it to disk: {}", prefix, err);
+        quarantine.spill(&prefix, root_dir)?;
+
+        // The uploaded files will be the same as the spilled ones once the upload is replayed
+        let mut size = StoredSize::default();
+        let file_paths_and_mimes = get_file_list(root_dir)?
+            .into_iter()
+            .filter(|file_path| fs::File::open(root_dir.join(file_path)).is_ok())
+            .map(|file_path| {
+                size.uncompressed += fs::metadata(root_dir.join(&file_path))
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+                let mime = detect_mime(&file_path).to_string();
+                (file_path, mime)
+            })
+            .collect();
+        // the files are only compressed when the upload is replayed, the uncompressed size is
+        // used as an upper bound until then
+        size.compressed = size.uncompressed;
+        let algs = std::iter::once(CompressionAlgorithm::default()).collect();
+        Ok((file_paths_and_mimes, algs, size))
+    }
+
+    /// Uploads the files spilled to disk by `store_all`, returning how many uploads were replayed.
+    pub(crate) fn recover_spilled_uploads(&self) -> Result<usize, Error> {
+        let quarantine = match &self.quarantine {
+            Some(quarantine) => quarantine,
+            None => return Ok(0),
+        };
+
+        let mut recovered = 0;
+        for upload in quarantine.pending()? {
+            let prefix = Path::new(&upload.manifest.prefix);
+            if let Err(err) = self.store_all_inner(prefix, &upload.files_dir()) {
+                // the storage is probably still unreachable, try again later
+                log::warn!(
+                    "failed to replay the upload of {}: {}",
+                    upload.manifest.prefix,
+                    err
+                );
+                break;
+            }
+
+            log::info!("replayed the upload of {}", upload.manifest.prefix);
+            quarantine.remove(upload)?;
+            recovered += 1;
+        }
+
+        Ok(recovered)
+    }
+
+    /// Deletes the prefixes left half-uploaded by a crashed builder, once nothing was uploaded to
+    /// them for `older_than`, unless their release is already served. Returns how many prefixes
+    /// were deleted.
+    pub(crate) fn cleanup_partial_uploads(&self, older_than: Duration) -> Result<usize, Error> {
+        let mut deleted = 0;
+        for (prefix, served) in self.journal.abandoned(older_than)? {
+            if served {
+                // the files of the previous build are still served, with some of the new ones
+                log::warn!(
+                    "keeping {}, its upload was interrupted by a rebuild",
+                    prefix
+                );
+            } else {
+                log::warn!("deleting the partial upload of {}", prefix);
+                // the trailing slash avoids deleting the prefixes starting with this one
+                self.delete_prefix(&format!("{}/", prefix))?;
+                deleted += 1;
+            }
+            self.journal.clear(&prefix)?;
+        }
+        Ok(deleted)
+    }
+
+    // Every batch is stored in its own transaction and recorded in the journal, so that uploading
+    // the same files again after a crash resumes from the first batch that wasn't stored.
+    fn store_all_inner(&self, prefix: &Path, root_dir: &Path) -> Result<StoredFiles, Error> {
         let mut file_paths_and_mimes = HashMap::new();
         let mut algs = HashSet::with_capacity(1);
+        let mut size = StoredSize::default();
 
-        let blobs = get_file_list(root_dir)?
+        let journal_prefix = prefix.to_slash().unwrap();
+        let uploaded_batches = self.journal.uploaded_batches(&journal_prefix)?;
+
+        // the batches must contain the same files when the upload is resumed
+        let mut file_list = get_file_list(root_dir)?;
+        file_list.sort();
+        let mut blobs = file_list
             .into_iter()
             .filter_map(|file_path| {
                 // Some files have insufficient permissions
@@ -168,30 +500,62 @@ impl Storage {
                     .ok()
                     .map(|file| (file_path, file))
             })
-            .map(|(file_path, file)| -> Result<_, Error> {
-                let alg = CompressionAlgorithm::default();
-                let content = compress(file, alg)?;
+            .map(|(file_path, mut file)| -> Result<_, Error> {
+                let file_size = file.metadata()?.len();
+                size.uncompressed += file_size;
+                let mime = detect_mime(&file_path);
+                let compression = self.compression_for(mime, file_size);
+                let content = match compression {
+                    Some(alg) => compress(file, alg)?,
+                    None => {
+                        let mut content = Vec::with_capacity(file_size as usize);
+                        file.read_to_end(&mut content)?;
+                        content
+                    }
+                };
+                size.compressed += content.len() as u64;
                 let bucket_path = prefix.join(&file_path).to_slash().unwrap();
 
-                let mime = detect_mime(&file_path);
                 file_paths_and_mimes.insert(file_path, mime.to_string());
-                algs.insert(alg);
+                algs.extend(compression);
 
                 Ok(Blob {
                     path: bucket_path,
                     mime: mime.to_string(),
                     content,
-                    compression: Some(alg),
-                    // this field is ignored by the backend
+                    compression,
+                    // these fields are ignored by the backend
                     date_updated: Utc::now(),
+                    content_hash: None,
                 })
             });
 
-        self.store_inner(blobs)?;
-        Ok((file_paths_and_mimes, algs))
+        for batch_number in 0.. {
+            let batch: Vec<_> = blobs
+                .by_ref()
+                .take(MAX_CONCURRENT_UPLOADS)
+                .collect::<Result<_, Error>>()?;
+            if batch.is_empty() {
+                break;
+            }
+
+            let digest = batch_digest(&batch);
+            if uploaded_batches.get(&batch_number) == Some(&digest) {
+                log::debug!("skipping batch {} of {}", batch_number, journal_prefix);
+                self.metrics.skipped_upload_batches.inc();
+                continue;
+            }
+            self.store_inner(batch.into_iter().map(Ok))?;
+            self.journal
+                .record(&journal_prefix, batch_number, &digest)?;
+        }
+
+        self.journal.clear(&journal_prefix)?;
+        self.invalidate_cdn(&journal_prefix);
+        Ok((file_paths_and_mimes, algs, size))
     }
 
-    #[cfg(test)]
+    /// Stores the blobs as they are, their content isn't compressed
     pub(crate) fn store_blobs(&self, blobs: Vec<Blob>) -> Result<(), Error> {
         self.store_inner(blobs.into_iter().map(Ok))
     }
@@ -214,8 +578,9 @@ impl Storage {
             mime,
             content,
             compression: Some(alg),
-            // this field is ignored by the backend
+            // these fields are ignored by the backend
             date_updated: Utc::now(),
+            content_hash: None,
         })))?;
 
         Ok(alg)
@@ -226,7 +591,8 @@ impl Storage {
         blobs: impl IntoIterator<Item = Result<Blob, Error>>,
     ) -> Result<(), Error> {
         let mut blobs = blobs.into_iter();
-        self.transaction(|trans| {
+        let mut paths = Vec::new();
+        let res = self.transaction(|trans| {
             loop {
                 let batch: Vec<_> = blobs
                     .by_ref()
@@ -235,14 +601,38 @@ impl Storage {
                 if batch.is_empty() {
                     break;
                 }
+                if self.cache.is_some() {
+                    paths.extend(batch.iter().map(|blob| blob.path.clone()));
+                }
                 trans.store_batch(batch)?;
             }
             Ok(())
-        })
+        });
+
+        // the files might have been partially uploaded even if the transaction failed
+        if let Some(cache) = &self.cache {
+            cache.invalidate(&paths);
+        }
+        res
     }
 
     pub(crate) fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
-        self.transaction(|trans| trans.delete_prefix(prefix))
+        let res = self.transaction(|trans| trans.delete_prefix(prefix));
+        if let Some(cache) = &self.cache {
+            cache.invalidate_prefix(prefix);
+        }
+        res?;
+        self.invalidate_cdn(prefix);
+        Ok(())
+    }
+
+    // A failed invalidation only means stale pages are served until the CDN cache expires, so it
+    // doesn't fail the upload or the deletion.
+    fn invalidate_cdn(&self, prefix: &str) {
+        if let Err(err) = self.cdn.invalidate_storage_prefix(prefix) {
+            log::error!("failed to invalidate {} in the CDN: {}", prefix, err);
+            self.metrics.failed_cdn_invalidations.inc();
+        }
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -272,7 +662,7 @@ trait StorageTransaction {
     fn complete(self: Box<Self>) -> Result<(), Error>;
 }
 
-fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
+pub(crate) fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
     let mime = mime_guess::from_path(file_path.as_ref())
         .first_raw()
         .unwrap_or("text/plain");
@@ -329,6 +719,50 @@ mod test {
         let detected_mime = detect_mime(Path::new(&path));
         assert_eq!(detected_mime, expected_mime);
     }
+
+    #[test]
+    fn test_memory_cache() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.storage_cache_size = Some(1024 * 1024));
+            let storage = env.storage();
+            let lookups = |result| {
+                env.metrics()
+                    .storage_cache_lookups
+                    .with_label_values(&[result])
+                    .get()
+            };
+
+            storage.store_one("rustdoc/foo/0.1.0/search-index.js", "old")?;
+            assert_eq!(
+                storage
+                    .get("rustdoc/foo/0.1.0/search-index.js", 1024)?
+                    .content,
+                b"old"
+            );
+            assert_eq!(
+                storage
+                    .get("rustdoc/foo/0.1.0/search-index.js", 1024)?
+                    .content,
+                b"old"
+            );
+            assert_eq!((lookups("hit"), lookups("miss")), (1, 1));
+
+            // changes made by this process are visible right away
+            storage.store_one("rustdoc/foo/0.1.0/search-index.js", "new")?;
+            assert_eq!(
+                storage
+                    .get("rustdoc/foo/0.1.0/search-index.js", 1024)?
+                    .content,
+                b"new"
+            );
+            storage.delete_prefix("rustdoc/foo/")?;
+            assert!(storage
+                .get("rustdoc/foo/0.1.0/search-index.js", 1024)
+                .is_err());
+
+            Ok(())
+        });
+    }
 }
 
 /// Backend tests are a set of tests executed on all the supported storage backends. They ensure
@@ -351,6 +785,7 @@ mod backend_tests {
             date_updated: Utc::now(),
             content: "Hello world!".into(),
             compression: None,
+            content_hash: None,
         };
         storage.store_blobs(vec![blob])?;
         assert!(storage.exists("path/to/file.txt")?);
@@ -364,6 +799,7 @@ mod backend_tests {
             mime: "text/plain".into(),
             date_updated: Utc::now(),
             compression: None,
+            content_hash: None,
             content: b"test content\n".to_vec(),
         };
 
@@ -372,6 +808,10 @@ mod backend_tests {
         let found = storage.get("foo/bar.txt", std::usize::MAX)?;
         assert_eq!(blob.mime, found.mime);
         assert_eq!(blob.content, found.content);
+        assert_eq!(
+            found.content_hash.as_deref(),
+            Some(content_hash(&blob.content).as_str())
+        );
 
         for path in &["bar.txt", "baz.txt", "foo/baz.txt"] {
             assert!(storage
@@ -384,6 +824,73 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_presigned_url(storage: &Storage) -> Result<(), Error> {
+        let blob = |path: &str, compression| Blob {
+            path: path.into(),
+            mime: "application/octet-stream".into(),
+            date_updated: Utc::now(),
+            content: vec![0; 64],
+            compression,
+            content_hash: None,
+        };
+        storage.store_blobs(vec![
+            blob("raw.bin", None),
+            blob("compressed.bin", Some(CompressionAlgorithm::Zstd)),
+        ])?;
+
+        let expires_in = Duration::from_secs(60);
+        let url = storage.presigned_url("raw.bin", 32, expires_in)?;
+        if storage.supports_presigned_urls() {
+            let url = url.expect("missing presigned url");
+            assert!(url.contains("/raw.bin?"));
+            assert!(url.contains("X-Amz-Expires=60"));
+            assert!(url.contains("X-Amz-Signature="));
+        } else {
+            assert_eq!(url, None);
+        }
+        assert_eq!(storage.presigned_url("raw.bin", 64, expires_in)?, None);
+        assert_eq!(
+            storage.presigned_url("compressed.bin", 32, expires_in)?,
+            None
+        );
+
+        Ok(())
+    }
+
+    // the backend tests download the binary files larger than 64 bytes from presigned urls
+    fn test_store_all_presigned(storage: &Storage) -> Result<(), Error> {
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-upload-test")
+            .tempdir()?;
+        fs::write(dir.path().join("small.wasm"), [0; 16])?;
+        fs::write(dir.path().join("large.wasm"), [0; 128])?;
+        fs::write(dir.path().join("large.html"), [b'a'; 128])?;
+        let (_, algs, size) = storage.store_all(Path::new("prefix"), dir.path(), None)?;
+        assert_eq!(
+            algs,
+            std::iter::once(CompressionAlgorithm::default()).collect()
+        );
+
+        let expires_in = Duration::from_secs(60);
+        let url = storage.presigned_url("prefix/large.wasm", 64, expires_in)?;
+        if storage.supports_presigned_urls() {
+            // the large binary wasn't compressed
+            assert!(url
+                .expect("missing presigned url")
+                .contains("/prefix/large.wasm?"));
+            assert!(size.compressed > 128);
+        } else {
+            assert_eq!(url, None);
+        }
+        for path in &["prefix/small.wasm", "prefix/large.html"] {
+            assert_eq!(storage.presigned_url(path, 0, expires_in)?, None);
+        }
+        assert_eq!(storage.get("prefix/large.wasm", 128)?.content, [0; 128]);
+        assert_eq!(storage.get("prefix/large.html", 128)?.content, [b'a'; 128]);
+
+        Ok(())
+    }
+
     fn test_get_too_big(storage: &Storage) -> Result<(), Error> {
         const MAX_SIZE: usize = 1024;
 
@@ -393,6 +900,7 @@ mod backend_tests {
             date_updated: Utc::now(),
             content: vec![0; MAX_SIZE],
             compression: None,
+            content_hash: None,
         };
         let big_blob = Blob {
             path: "big-blob.bin".into(),
@@ -400,6 +908,7 @@ mod backend_tests {
             date_updated: Utc::now(),
             content: vec![0; MAX_SIZE * 2],
             compression: None,
+            content_hash: None,
         };
 
         storage.store_blobs(vec![small_blob.clone(), big_blob])?;
@@ -434,6 +943,7 @@ mod backend_tests {
                 mime: "text/plain".into(),
                 date_updated: Utc::now(),
                 compression: None,
+                content_hash: None,
                 content: b"Hello world!\n".to_vec(),
             })
             .collect::<Vec<_>>();
@@ -464,8 +974,28 @@ mod backend_tests {
             fs::write(path, "data")?;
         }
 
-        let (stored_files, algs) = storage.store_all(Path::new("prefix"), dir.path())?;
+        // nothing is uploaded when the files are too large
+        for (file_size, total_size) in &[(3, 100), (100, 7)] {
+            let limit = UploadLimit {
+                file_size: *file_size,
+                total_size: *total_size,
+            };
+            let err = storage
+                .store_all(Path::new("prefix"), dir.path(), Some(limit))
+                .unwrap_err();
+            assert!(err.downcast_ref::<UploadSizeExceeded>().is_some());
+            assert!(!storage.exists("prefix/Cargo.toml")?);
+        }
+
+        let limit = UploadLimit {
+            file_size: 4,
+            total_size: 8,
+        };
+        let (stored_files, algs, size) =
+            storage.store_all(Path::new("prefix"), dir.path(), Some(limit))?;
         assert_eq!(stored_files.len(), files.len());
+        assert_eq!(size.uncompressed, 8);
+        assert!(size.compressed > 0);
         for name in &files {
             let name = Path::new(name);
             assert!(stored_files.contains_key(name));
@@ -509,6 +1039,7 @@ mod backend_tests {
                     path: format!("{}.rs", i),
                     date_updated: now,
                     compression: None,
+                    content_hash: None,
                 }
             })
             .collect();
@@ -551,6 +1082,35 @@ mod backend_tests {
         )
     }
 
+    fn test_delete_many(storage: &Storage, metrics: &Metrics) -> Result<(), Error> {
+        // more than the batch size of the backends
+        const FILES: usize = 2500;
+
+        let blob = |path: String| Blob {
+            path,
+            content: b"foo\n".to_vec(),
+            compression: None,
+            content_hash: None,
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+        };
+        let mut blobs: Vec<_> = (0..FILES)
+            .map(|i| blob(format!("many/{}.txt", i)))
+            .collect();
+        blobs.push(blob("many.txt".into()));
+        storage.store_blobs(blobs)?;
+
+        storage.delete_prefix("many/")?;
+
+        assert_eq!(metrics.deleted_files_total.get() as usize, FILES);
+        for i in &[0, 999, 1000, FILES - 1] {
+            assert!(!storage.exists(&format!("many/{}.txt", i))?);
+        }
+        assert!(storage.exists("many.txt")?);
+
+        Ok(())
+    }
+
     fn test_deletion(
         storage: &Storage,
         prefix: &str,
@@ -565,6 +1125,7 @@ mod backend_tests {
                     path: (*path).to_string(),
                     content: b"foo\n".to_vec(),
                     compression: None,
+                    content_hash: None,
                     mime: "text/plain".into(),
                     date_updated: Utc::now(),
                 })
@@ -604,6 +1165,7 @@ mod backend_tests {
                     fn get_storage(env: &TestEnvironment) -> Arc<Storage> {
                         env.override_config(|config| {
                             config.storage_backend = $config;
+                            config.presigned_url_threshold = Some(64);
                         });
                         env.storage()
                     }
@@ -646,6 +1208,8 @@ mod backend_tests {
             test_exists,
             test_get_object,
             test_get_too_big,
+            test_presigned_url,
+            test_store_all_presigned,
             test_delete_prefix,
             test_delete_percent,
         }
@@ -653,6 +1217,7 @@ mod backend_tests {
         tests_with_metrics {
             test_store_blobs,
             test_store_all,
+            test_delete_many,
         }
     }
 }
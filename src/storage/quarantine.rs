@@ -0,0 +1,324 @@
+//! Local spill directory for uploads that failed because the storage was unreachable.
+//!
+//! When `DOCSRS_UPLOAD_SPILL_DIR` is set and uploading a directory fails because the storage
+//! can't be reached, its files are copied to `<spill dir>/<prefix>/files` next to a
+//! `manifest.json` describing the upload, and the prefix is recorded in the `pending_uploads`
+//! table. The release is recorded like any other, and its files are read from the spill directory
+//! until [`Storage::recover_spilled_uploads`] replays them once the storage is back.
+//!
+//! The other errors, like unreadable files, fail the upload as usual.
+//!
+//! [`Storage::recover_spilled_uploads`]: super::Storage::recover_spilled_uploads
+
+use super::{detect_mime, get_file_list, Blob};
+use crate::db::Pool;
+use crate::error::SizeLimitReached;
+use chrono::{DateTime, Utc};
+use failure::{Error, ResultExt};
+use rusoto_core::RusotoError;
+use rusoto_s3::PutObjectError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+const FILES: &str = "files";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// The storage prefix the files should have been uploaded to
+    pub(crate) prefix: String,
+    /// Paths of the spilled files, relative to the `files` directory
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) spilled_at: DateTime<Utc>,
+}
+
+/// An upload waiting in the spill directory
+#[derive(Debug)]
+pub(crate) struct PendingUpload {
+    pub(crate) manifest: Manifest,
+    path: PathBuf,
+}
+
+impl PendingUpload {
+    /// The directory containing the spilled files
+    pub(crate) fn files_dir(&self) -> PathBuf {
+        self.path.join(FILES)
+    }
+}
+
+pub(crate) struct Quarantine {
+    root: PathBuf,
+    pool: Pool,
+}
+
+impl Quarantine {
+    pub(crate) fn new(root: PathBuf, pool: Pool) -> Self {
+        Self { root, pool }
+    }
+
+    /// Copies all the readable files in `root_dir` to the spill directory and marks `prefix` as
+    /// pending upload.
+    pub(crate) fn spill(&self, prefix: &str, root_dir: &Path) -> Result<(), Error> {
+        let dest = self.root.join(prefix);
+        if dest.exists() {
+            // an older spill of the same prefix is superseded by this one
+            fs::remove_dir_all(&dest)?;
+        }
+
+        let mut files = Vec::new();
+        for file_path in get_file_list(root_dir)? {
+            let target = dest.join(FILES).join(&file_path);
+            fs::create_dir_all(target.parent().unwrap())?;
+            // Skip files with insufficient permissions, like `store_all` does
+            if fs::copy(root_dir.join(&file_path), &target).is_ok() {
+                files.push(file_path);
+            }
+        }
+
+        let manifest = Manifest {
+            prefix: prefix.into(),
+            files,
+            spilled_at: Utc::now(),
+        };
+        fs::write(dest.join(MANIFEST), serde_json::to_vec(&manifest)?)?;
+
+        self.pool.get()?.execute(
+            "INSERT INTO pending_uploads (prefix, spill_path) VALUES ($1, $2)
+             ON CONFLICT (prefix) DO UPDATE SET spill_path = $2, spilled_at = NOW()",
+            &[&prefix, &dest.to_string_lossy()],
+        )?;
+
+        log::warn!(
+            "spilled {} files for {} to {}",
+            manifest.files.len(),
+            prefix,
+            dest.display()
+        );
+        Ok(())
+    }
+
+    /// Lists the uploads that are still waiting to be replayed, oldest first
+    pub(crate) fn pending(&self) -> Result<Vec<PendingUpload>, Error> {
+        let rows = self.pool.get()?.query(
+            "SELECT spill_path FROM pending_uploads ORDER BY spilled_at",
+            &[],
+        )?;
+
+        let mut pending = Vec::with_capacity(rows.len());
+        for row in rows {
+            let path = PathBuf::from(row.get::<_, String>("spill_path"));
+            let manifest = fs::read(path.join(MANIFEST))
+                .with_context(|_| format!("failed to read the manifest in {}", path.display()))?;
+            pending.push(PendingUpload {
+                manifest: serde_json::from_slice(&manifest)?,
+                path,
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Reads a file of an upload waiting to be replayed, `None` if it isn't in one
+    pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Option<Blob>, Error> {
+        let file = match self.spilled_file(path) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let metadata = fs::metadata(&file)?;
+        if metadata.len() > max_size as u64 {
+            return Err(std::io::Error::other(SizeLimitReached).into());
+        }
+        Ok(Some(Blob {
+            path: path.into(),
+            mime: detect_mime(path).into(),
+            content: fs::read(&file)?,
+            compression: None,
+            date_updated: metadata.modified()?.into(),
+            content_hash: None,
+        }))
+    }
+
+    /// Whether the file is part of an upload waiting to be replayed
+    pub(crate) fn exists(&self, path: &str) -> bool {
+        self.spilled_file(path).is_some()
+    }
+
+    /// The spilled copy of the file stored at `path`, found by looking for the manifest of an
+    /// upload in its parent directories
+    fn spilled_file(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        // the paths come from the requests, they can't leave the spill directory
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let prefix = path
+            .ancestors()
+            .skip(1)
+            .filter(|prefix| !prefix.as_os_str().is_empty())
+            .find(|prefix| self.root.join(prefix).join(MANIFEST).is_file())?;
+        let file = self
+            .root
+            .join(prefix)
+            .join(FILES)
+            .join(path.strip_prefix(prefix).ok()?);
+        Some(file).filter(|file| file.is_file())
+    }
+
+    /// Removes a successfully replayed upload from the spill directory
+    pub(crate) fn remove(&self, upload: PendingUpload) -> Result<(), Error> {
+        self.pool.get()?.execute(
+            "DELETE FROM pending_uploads WHERE prefix = $1",
+            &[&upload.manifest.prefix],
+        )?;
+        fs::remove_dir_all(&upload.path)?;
+        Ok(())
+    }
+}
+
+/// Whether the upload failed because the storage couldn't be reached, it's worth spilling the
+/// files to upload them later
+pub(crate) fn is_unreachable(err: &Error) -> bool {
+    if let Some(err) = err.downcast_ref::<RusotoError<PutObjectError>>() {
+        return match err {
+            RusotoError::HttpDispatch(_) => true,
+            RusotoError::Unknown(response) => response.status.is_server_error(),
+            _ => false,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<postgres::Error>() {
+        // the connection errors are io errors, or have a SQLSTATE of the "connection exception"
+        // class or of a shutdown of the server
+        return std::error::Error::source(err).is_some_and(|source| source.is::<std::io::Error>())
+            || err.code().is_some_and(|code| {
+                code.code().starts_with("08") || code.code().starts_with("57P0")
+            });
+    }
+    // the pool couldn't get a connection in time
+    err.downcast_ref::<r2d2::Error>().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_unreachable;
+    use crate::storage::StorageKind;
+    use crate::test::wrapper;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn spill_and_recover() {
+        wrapper(|env| {
+            let spill_dir = tempfile::tempdir()?;
+            let spill_path = spill_dir.path().to_owned();
+            env.override_config(|config| config.upload_spill_dir = Some(spill_path));
+
+            let docs = tempfile::tempdir()?;
+            fs::create_dir(docs.path().join("krate"))?;
+            fs::write(docs.path().join("krate/index.html"), "<html>hello</html>")?;
+            fs::write(docs.path().join("search-index.js"), "var searchIndex;")?;
+
+            let storage = env.storage();
+            let quarantine = storage.quarantine.as_ref().unwrap();
+            quarantine.spill("rustdoc/krate/1.0.0", docs.path())?;
+
+            let pending = quarantine.pending()?;
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].manifest.prefix, "rustdoc/krate/1.0.0");
+            assert_eq!(pending[0].manifest.files.len(), 2);
+            assert!(pending[0].files_dir().join("krate/index.html").is_file());
+
+            // the spilled files are served until the upload is replayed
+            assert!(storage.exists("rustdoc/krate/1.0.0/krate/index.html")?);
+            let blob = storage.get("rustdoc/krate/1.0.0/search-index.js", usize::MAX)?;
+            assert_eq!(blob.content, b"var searchIndex;");
+            assert_eq!(blob.mime, "application/javascript");
+            assert!(storage
+                .get("rustdoc/krate/1.0.0/search-index.js", 1)
+                .is_err());
+            assert!(!storage.exists("rustdoc/krate/1.0.0/krate/missing.html")?);
+            assert!(!storage.exists("rustdoc/krate/1.0.0/../1.0.0/search-index.js")?);
+            assert!(!storage.exists("rustdoc/krate/1.0.0/manifest.json")?);
+
+            assert_eq!(storage.recover_spilled_uploads()?, 1);
+            assert!(quarantine.pending()?.is_empty());
+            assert!(!spill_dir.path().join("rustdoc/krate/1.0.0").exists());
+            let blob = storage.get("rustdoc/krate/1.0.0/krate/index.html", usize::MAX)?;
+            assert_eq!(blob.content, b"<html>hello</html>");
+            assert!(storage.exists("rustdoc/krate/1.0.0/search-index.js")?);
+
+            // nothing left to replay
+            assert_eq!(storage.recover_spilled_uploads()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn only_unreachable_storage_is_spilled() {
+        wrapper(|env| {
+            let spill_dir = tempfile::tempdir()?;
+            let spill_path = spill_dir.path().to_owned();
+            env.override_config(|config| config.upload_spill_dir = Some(spill_path));
+
+            let storage = env.storage();
+            let missing = spill_dir.path().join("missing");
+            assert!(storage
+                .store_all(Path::new("rustdoc/krate/1.0.0"), &missing, None)
+                .is_err());
+            assert!(storage.quarantine.as_ref().unwrap().pending()?.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn unreachable_s3_is_spilled() {
+        wrapper(|env| {
+            let spill_dir = tempfile::tempdir()?;
+            let spill_path = spill_dir.path().to_owned();
+            env.override_config(|config| {
+                config.storage_backend = StorageKind::S3;
+                // nothing listens there
+                config.s3_endpoint = Some("http://127.0.0.1:1".into());
+                config.s3_bucket_is_temporary = false;
+                config.upload_spill_dir = Some(spill_path);
+            });
+
+            let docs = tempfile::tempdir()?;
+            fs::write(docs.path().join("index.html"), "<html>hello</html>")?;
+
+            let storage = env.storage();
+            let (files, _, _) =
+                storage.store_all(Path::new("rustdoc/krate/1.0.0"), docs.path(), None)?;
+            assert_eq!(files.len(), 1);
+            let pending = storage.quarantine.as_ref().unwrap().pending()?;
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].manifest.prefix, "rustdoc/krate/1.0.0");
+
+            // the storage is still unreachable, the upload stays pending
+            assert_eq!(storage.recover_spilled_uploads()?, 0);
+            assert_eq!(storage.quarantine.as_ref().unwrap().pending()?.len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn connection_errors_are_unreachable() {
+        // nothing listens there
+        let err = match postgres::Client::connect(
+            "postgresql://postgres@127.0.0.1:1/docsrs",
+            postgres::NoTls,
+        ) {
+            Ok(_) => panic!("connected to a closed port"),
+            Err(err) => err,
+        };
+        assert!(is_unreachable(&err.into()));
+        assert!(!is_unreachable(&failure::err_msg("invalid file")));
+        let err = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert!(!is_unreachable(&err.into()));
+    }
+}
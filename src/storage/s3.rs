@@ -7,41 +7,70 @@ use futures_util::{
     stream::{FuturesUnordered, StreamExt},
 };
 use rusoto_core::{region::Region, RusotoError};
-use rusoto_credential::DefaultCredentialsProvider;
+use rusoto_credential::{DefaultCredentialsProvider, ProvideAwsCredentials};
 use rusoto_s3::{
-    DeleteObjectsRequest, GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest,
-    ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3Client, S3,
+    util::{PreSignedRequest, PreSignedRequestOption},
+    DeleteObjectsRequest, GetObjectError, GetObjectOutput, GetObjectRequest, HeadObjectError,
+    HeadObjectRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3Client, S3,
 };
-use std::{convert::TryInto, io::Write, sync::Arc};
+use std::{convert::TryInto, io::Write, sync::Arc, time::Duration};
 use tokio::runtime::Runtime;
 
+/// Name of the user-defined object metadata storing the hash of the content
+const CONTENT_HASH_METADATA: &str = "content-hash";
+
 pub(super) struct S3Backend {
     client: S3Client,
+    credentials: DefaultCredentialsProvider,
+    region: Region,
     runtime: Runtime,
     bucket: String,
+    replica: Option<Replica>,
     metrics: Arc<Metrics>,
     #[cfg(test)]
     temporary: bool,
 }
 
+/// A copy of the bucket, only used to read files when the primary bucket is unavailable
+struct Replica {
+    client: S3Client,
+    bucket: String,
+    /// How long to wait for the primary bucket before giving up on it
+    timeout: Duration,
+}
+
 impl S3Backend {
     pub(super) fn new(metrics: Arc<Metrics>, config: &Config) -> Result<Self, Error> {
         let runtime = Runtime::new()?;
 
         // Connect to S3
+        let credentials = DefaultCredentialsProvider::new()?;
+        let region = custom_region(config, &config.s3_region);
         let client = S3Client::new_with(
             rusoto_core::request::HttpClient::new()?,
-            DefaultCredentialsProvider::new()?,
-            config
-                .s3_endpoint
-                .as_deref()
-                .map(|endpoint| Region::Custom {
-                    name: config.s3_region.name().to_string(),
-                    endpoint: endpoint.to_string(),
-                })
-                .unwrap_or_else(|| config.s3_region.clone()),
+            credentials.clone(),
+            region.clone(),
         );
 
+        let replica = match &config.s3_replica_bucket {
+            Some(bucket) => Some(Replica {
+                client: S3Client::new_with(
+                    rusoto_core::request::HttpClient::new()?,
+                    credentials.clone(),
+                    custom_region(
+                        config,
+                        config
+                            .s3_replica_region
+                            .as_ref()
+                            .unwrap_or(&config.s3_region),
+                    ),
+                ),
+                bucket: bucket.clone(),
+                timeout: Duration::from_secs(config.s3_replica_timeout),
+            }),
+            None => None,
+        };
+
         #[cfg(test)]
         {
             // Create the temporary S3 bucket during tests.
@@ -59,9 +88,12 @@ impl S3Backend {
 
         Ok(Self {
             client,
+            credentials,
+            region,
             runtime,
             metrics,
             bucket: config.s3_bucket.clone(),
+            replica,
             #[cfg(test)]
             temporary: config.s3_bucket_is_temporary,
         })
@@ -85,17 +117,109 @@ impl S3Backend {
     }
 
     pub(super) fn get(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        self.runtime.block_on(self.get_async(path, max_size))
+    }
+
+    pub(super) async fn get_async(&self, path: &str, max_size: usize) -> Result<Blob, Error> {
+        let res = match &self.replica {
+            Some(replica) => self.get_object_with_failover(replica, path).await?,
+            None => get_object(&self.client, &self.bucket, path).await?,
+        };
+
+        // don't download files we would discard anyway
+        if res.content_length.unwrap_or(0) as u64 > max_size as u64 {
+            return Err(std::io::Error::other(crate::error::SizeLimitReached).into());
+        }
+
+        let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
+        content.reserve(
+            res.content_length
+                .and_then(|l| l.try_into().ok())
+                .unwrap_or(0),
+        );
+
+        let mut body = res
+            .body
+            .ok_or_else(|| failure::err_msg("Received a response from S3 with no body"))?;
+
+        while let Some(data) = body.next().await.transpose()? {
+            content.write_all(data.as_ref())?;
+        }
+
+        let date_updated = res
+            .last_modified
+            // This is a bug from AWS, it should always have a modified date of when it was created if nothing else.
+            // Workaround it by passing now as the modification time, since the exact time doesn't really matter.
+            .map_or(Ok(Utc::now()), |lm| parse_timespec(&lm))?;
+
+        let compression = res.content_encoding.and_then(|s| s.parse().ok());
+        let content_hash = res
+            .metadata
+            .and_then(|mut metadata| metadata.remove(CONTENT_HASH_METADATA));
+
+        Ok(Blob {
+            path: path.into(),
+            mime: res.content_type.unwrap(),
+            date_updated,
+            content: content.into_inner(),
+            compression,
+            content_hash,
+        })
+    }
+
+    /// Reads the file from the replica when the primary bucket fails or is too slow to answer
+    async fn get_object_with_failover(
+        &self,
+        replica: &Replica,
+        path: &str,
+    ) -> Result<GetObjectOutput, Error> {
+        let primary = tokio::time::timeout(
+            replica.timeout,
+            get_object(&self.client, &self.bucket, path),
+        )
+        .await
+        .map_err(Error::from)
+        .and_then(|res| res);
+
+        match primary {
+            Err(err) if should_fail_over(&err) => {
+                log::warn!(
+                    "failed to fetch {} from the primary bucket, reading it from the replica: {}",
+                    path,
+                    err
+                );
+                let res = get_object(&replica.client, &replica.bucket, path).await;
+                let answered = match &res {
+                    Ok(_) => true,
+                    Err(err) => err.downcast_ref::<super::PathNotFoundError>().is_some(),
+                };
+                self.metrics
+                    .s3_replica_fallbacks
+                    .with_label_values(&[if answered { "success" } else { "failure" }])
+                    .inc();
+                res
+            }
+            res => res,
+        }
+    }
+
+    pub(super) fn presigned_url(
+        &self,
+        path: &str,
+        min_size: usize,
+        expires_in: Duration,
+    ) -> Result<Option<String>, Error> {
         self.runtime.block_on(async {
-            let res = self
+            let head = self
                 .client
-                .get_object(GetObjectRequest {
-                    bucket: self.bucket.to_string(),
+                .head_object(HeadObjectRequest {
+                    bucket: self.bucket.clone(),
                     key: path.into(),
                     ..Default::default()
                 })
                 .await
                 .map_err(|err| match err {
-                    RusotoError::Service(GetObjectError::NoSuchKey(_)) => {
+                    RusotoError::Service(HeadObjectError::NoSuchKey(_)) => {
                         super::PathNotFoundError.into()
                     }
                     RusotoError::Unknown(http) if http.status == 404 => {
@@ -104,36 +228,24 @@ impl S3Backend {
                     err => Error::from(err),
                 })?;
 
-            let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
-            content.reserve(
-                res.content_length
-                    .and_then(|l| l.try_into().ok())
-                    .unwrap_or(0),
-            );
-
-            let mut body = res
-                .body
-                .ok_or_else(|| failure::err_msg("Received a response from S3 with no body"))?;
-
-            while let Some(data) = body.next().await.transpose()? {
-                content.write_all(data.as_ref())?;
+            // browsers can't decompress the files, they have to go through `get`
+            if head.content_encoding.is_some()
+                || head.content_length.unwrap_or(0) as u64 <= min_size as u64
+            {
+                return Ok(None);
             }
 
-            let date_updated = res
-                .last_modified
-                // This is a bug from AWS, it should always have a modified date of when it was created if nothing else.
-                // Workaround it by passing now as the modification time, since the exact time doesn't really matter.
-                .map_or(Ok(Utc::now()), |lm| parse_timespec(&lm))?;
-
-            let compression = res.content_encoding.and_then(|s| s.parse().ok());
-
-            Ok(Blob {
-                path: path.into(),
-                mime: res.content_type.unwrap(),
-                date_updated,
-                content: content.into_inner(),
-                compression,
-            })
+            let credentials = self.credentials.credentials().await?;
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: path.into(),
+                ..Default::default()
+            };
+            Ok(Some(request.get_presigned_url(
+                &self.region,
+                &credentials,
+                &PreSignedRequestOption { expires_in },
+            )))
         })
     }
 
@@ -173,6 +285,7 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
     fn store_batch(&mut self, mut batch: Vec<Blob>) -> Result<(), Error> {
         self.s3.runtime.block_on(async {
             // Attempt to upload the batch 3 times
+            let mut last_err = None;
             for _ in 0..3 {
                 let mut futures = FuturesUnordered::new();
                 for blob in batch.drain(..) {
@@ -188,6 +301,13 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
                                     .compression
                                     .as_ref()
                                     .map(|alg| alg.to_string()),
+                                metadata: Some(
+                                    std::iter::once((
+                                        CONTENT_HASH_METADATA.to_string(),
+                                        super::content_hash(&blob.content),
+                                    ))
+                                    .collect(),
+                                ),
                                 ..Default::default()
                             })
                             .map_ok(|_| {
@@ -196,15 +316,16 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
                             .map_err(|err| {
                                 log::error!("Failed to upload blob to S3: {:?}", err);
                                 // Reintroduce failed blobs for a retry
-                                blob
+                                (blob, err)
                             }),
                     );
                 }
 
                 while let Some(result) = futures.next().await {
                     // Push each failed blob back into the batch
-                    if let Err(blob) = result {
+                    if let Err((blob, err)) = result {
                         batch.push(blob);
+                        last_err = Some(err);
                     }
                 }
 
@@ -214,7 +335,8 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
                 }
             }
 
-            panic!("failed to upload 3 times, exiting");
+            // the caller decides whether the files are spilled to disk or the build fails
+            Err(last_err.expect("the batch failed without an error").into())
         })
     }
 
@@ -264,6 +386,10 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
 
                     failure::bail!("deleting from s3 failed");
                 }
+                self.s3
+                    .metrics
+                    .deleted_files_total
+                    .inc_by(resp.deleted.map_or(0, |deleted| deleted.len() as i64));
 
                 continuation_token = list.next_continuation_token;
                 if continuation_token.is_none() {
@@ -278,6 +404,45 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
     }
 }
 
+/// The same region as `region`, but using the custom endpoint if one is configured
+fn custom_region(config: &Config, region: &Region) -> Region {
+    match &config.s3_endpoint {
+        Some(endpoint) => Region::Custom {
+            name: region.name().to_string(),
+            endpoint: endpoint.to_string(),
+        },
+        None => region.clone(),
+    }
+}
+
+async fn get_object(client: &S3Client, bucket: &str, path: &str) -> Result<GetObjectOutput, Error> {
+    client
+        .get_object(GetObjectRequest {
+            bucket: bucket.to_string(),
+            key: path.into(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|err| match err {
+            RusotoError::Service(GetObjectError::NoSuchKey(_)) => super::PathNotFoundError.into(),
+            RusotoError::Unknown(http) if http.status == 404 => super::PathNotFoundError.into(),
+            err => Error::from(err),
+        })
+}
+
+/// Only the errors caused by an outage of the bucket are retried on the replica, a missing file
+/// is missing from the replica too
+fn should_fail_over(err: &Error) -> bool {
+    if err.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        return true;
+    }
+    match err.downcast_ref::<RusotoError<GetObjectError>>() {
+        Some(RusotoError::HttpDispatch(_)) => true,
+        Some(RusotoError::Unknown(http)) => http.status.is_server_error(),
+        _ => false,
+    }
+}
+
 fn parse_timespec(mut raw: &str) -> Result<DateTime<Utc>, Error> {
     raw = raw.trim_end_matches(" GMT");
 
@@ -308,6 +473,44 @@ mod tests {
         assert!(parse_timespec("foo").is_err());
     }
 
+    #[test]
+    fn test_should_fail_over() {
+        use rusoto_core::request::{BufferedHttpResponse, HttpDispatchError};
+
+        let unknown = |status: u16| -> Error {
+            RusotoError::<GetObjectError>::Unknown(BufferedHttpResponse {
+                status: status.try_into().unwrap(),
+                body: Default::default(),
+                headers: Default::default(),
+            })
+            .into()
+        };
+        assert!(should_fail_over(&unknown(500)));
+        assert!(should_fail_over(&unknown(503)));
+        assert!(!should_fail_over(&unknown(403)));
+
+        let dispatch: Error = RusotoError::<GetObjectError>::HttpDispatch(HttpDispatchError::new(
+            "connection refused".into(),
+        ))
+        .into();
+        assert!(should_fail_over(&dispatch));
+
+        let runtime = Runtime::new().unwrap();
+        let timeout = runtime
+            .block_on(async {
+                tokio::time::timeout(
+                    Duration::from_millis(1),
+                    futures_util::future::pending::<()>(),
+                )
+                .await
+            })
+            .unwrap_err();
+        assert!(should_fail_over(&timeout.into()));
+
+        assert!(!should_fail_over(&super::super::PathNotFoundError.into()));
+        assert!(!should_fail_over(&crate::error::SizeLimitReached.into()));
+    }
+
     // The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please
     // add any test checking the public interface there.
 
@@ -1,18 +1,29 @@
-use super::TestDatabase;
-use crate::docbuilder::{BuildResult, DocCoverage};
+use super::{TestDatabase, TestEnvironment};
+use crate::docbuilder::{
+    BuildDiagnostic, BuildFailure, BuildOutput, BuildResult, BuildUploader, DocCoverage, Limits,
+    Manifest,
+};
 use crate::index::api::{CrateData, CrateOwner, ReleaseData};
 use crate::storage::Storage;
-use crate::utils::{Dependency, MetadataPackage, Target};
+use crate::utils::{
+    citation::Citation, definitions::Definitions, item_index::ItemIndex, rustdoc_json, Dependency,
+    MetadataPackage, Target,
+};
+use crate::{ReleasesCache, VersionCache};
 use chrono::{DateTime, Utc};
 use failure::{Error, ResultExt};
 use postgres::Client;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 
 #[must_use = "FakeRelease does nothing until you call .create()"]
 pub(crate) struct FakeRelease<'a> {
     db: &'a TestDatabase,
     storage: Arc<Storage>,
+    version_cache: Arc<VersionCache>,
+    releases_cache: Arc<ReleasesCache>,
     package: MetadataPackage,
     builds: Vec<FakeBuild>,
     /// name, content
@@ -29,6 +40,7 @@ pub(crate) struct FakeRelease<'a> {
     readme: Option<&'a str>,
     github_stats: Option<FakeGithubStats>,
     doc_coverage: Option<DocCoverage>,
+    citation: Option<Citation>,
 }
 
 pub(crate) struct FakeBuild {
@@ -40,40 +52,53 @@ pub(crate) struct FakeBuild {
 const DEFAULT_CONTENT: &[u8] =
     b"<html><head></head><body>default content for test/fakes</body></html>";
 
+/// The metadata of the fake crates, as cargo would report it
+fn fake_package() -> MetadataPackage {
+    MetadataPackage {
+        id: "fake-package-id".into(),
+        name: "fake-package".into(),
+        version: "1.0.0".into(),
+        license: Some("MIT".into()),
+        repository: Some("https://git.example.com".into()),
+        homepage: Some("https://www.example.com".into()),
+        description: Some("Fake package".into()),
+        documentation: Some("https://docs.example.com".into()),
+        dependencies: vec![Dependency {
+            name: "fake-dependency".into(),
+            req: "^1.0.0".into(),
+            kind: None,
+            rename: None,
+            optional: false,
+        }],
+        targets: vec![Target::dummy_lib("fake_package".into(), None)],
+        readme: None,
+        keywords: vec!["fake".into(), "package".into()],
+        features: [
+            ("default".into(), vec!["feature1".into(), "feature3".into()]),
+            ("feature1".into(), Vec::new()),
+            ("feature2".into(), vec!["feature1".into()]),
+            ("feature3".into(), Vec::new()),
+        ]
+        .iter()
+        .cloned()
+        .collect::<HashMap<String, Vec<String>>>(),
+        rust_version: None,
+    }
+}
+
 impl<'a> FakeRelease<'a> {
-    pub(super) fn new(db: &'a TestDatabase, storage: Arc<Storage>) -> Self {
+    pub(super) fn new(
+        db: &'a TestDatabase,
+        storage: Arc<Storage>,
+        version_cache: Arc<VersionCache>,
+        releases_cache: Arc<ReleasesCache>,
+    ) -> Self {
         FakeRelease {
             db,
             storage,
-            package: MetadataPackage {
-                id: "fake-package-id".into(),
-                name: "fake-package".into(),
-                version: "1.0.0".into(),
-                license: Some("MIT".into()),
-                repository: Some("https://git.example.com".into()),
-                homepage: Some("https://www.example.com".into()),
-                description: Some("Fake package".into()),
-                documentation: Some("https://docs.example.com".into()),
-                dependencies: vec![Dependency {
-                    name: "fake-dependency".into(),
-                    req: "^1.0.0".into(),
-                    kind: None,
-                    rename: None,
-                    optional: false,
-                }],
-                targets: vec![Target::dummy_lib("fake_package".into(), None)],
-                readme: None,
-                keywords: vec!["fake".into(), "package".into()],
-                features: [
-                    ("default".into(), vec!["feature1".into(), "feature3".into()]),
-                    ("feature1".into(), Vec::new()),
-                    ("feature2".into(), vec!["feature1".into()]),
-                    ("feature3".into(), Vec::new()),
-                ]
-                .iter()
-                .cloned()
-                .collect::<HashMap<String, Vec<String>>>(),
-            },
+            version_cache,
+            releases_cache,
+            package: fake_package(),
             builds: vec![],
             source_files: Vec::new(),
             rustdoc_files: Vec::new(),
@@ -90,6 +115,7 @@ impl<'a> FakeRelease<'a> {
             readme: None,
             github_stats: None,
             doc_coverage: None,
+            citation: None,
         }
     }
 
@@ -125,6 +151,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn license(mut self, license: impl Into<String>) -> Self {
+        self.package.license = Some(license.into());
+        self
+    }
+
     /// Shortcut to add a single unsuccessful build with default data
     // TODO: How should `has_docs` actually be handled?
     pub(crate) fn build_result_failed(self) -> Self {
@@ -220,11 +251,40 @@ impl<'a> FakeRelease<'a> {
         }
     }
 
+    pub(crate) fn citation(self, citation: Citation) -> Self {
+        Self {
+            citation: Some(citation),
+            ..self
+        }
+    }
+
+    pub(crate) fn rust_version(mut self, rust_version: impl Into<String>) -> Self {
+        self.package.rust_version = Some(rust_version.into());
+        self
+    }
+
     pub(crate) fn features(mut self, features: HashMap<String, Vec<String>>) -> Self {
         self.package.features = features;
         self
     }
 
+    /// Replaces the default dependency, each one is a `(name, req, kind)` triple
+    pub(crate) fn dependencies(mut self, dependencies: &[(&str, &str, &str)]) -> Self {
+        self.package.dependencies = dependencies
+            .iter()
+            .map(|&(name, req, kind)| Dependency {
+                name: name.into(),
+                req: req.into(),
+                kind: Some(kind)
+                    .filter(|&kind| kind != "normal")
+                    .map(String::from),
+                rename: None,
+                optional: false,
+            })
+            .collect();
+        self
+    }
+
     pub(crate) fn github_stats(
         mut self,
         repo: impl Into<String>,
@@ -243,9 +303,6 @@ impl<'a> FakeRelease<'a> {
 
     /// Returns the release_id
     pub(crate) fn create(mut self) -> Result<i32, Error> {
-        use std::fs;
-        use std::path::Path;
-
         let tempdir = tempfile::Builder::new().prefix("docs.rs-fake").tempdir()?;
         let package = self.package;
         let db = self.db;
@@ -299,10 +356,10 @@ impl<'a> FakeRelease<'a> {
                 target.unwrap_or("")
             );
             log::debug!("adding directory {} from {}", prefix, path_prefix.display());
-            crate::db::add_path_into_database(&storage, &prefix, path_prefix)
+            crate::db::add_path_into_database(&storage, &prefix, path_prefix, None)
         };
 
-        let (source_meta, mut algs) = upload_files("source", &self.source_files, None)?;
+        let (source_meta, mut algs, _) = upload_files("source", &self.source_files, None)?;
         log::debug!("added source files {}", source_meta);
 
         // If the test didn't add custom builds, inject a default one
@@ -317,7 +374,7 @@ impl<'a> FakeRelease<'a> {
                 rustdoc_files.push((&index, DEFAULT_CONTENT));
             }
 
-            let (rustdoc_meta, new_algs) = upload_files("rustdoc", &rustdoc_files, None)?;
+            let (rustdoc_meta, new_algs, _) = upload_files("rustdoc", &rustdoc_files, None)?;
             algs.extend(new_algs);
             log::debug!("added rustdoc files {}", rustdoc_meta);
 
@@ -367,6 +424,11 @@ impl<'a> FakeRelease<'a> {
         if let Some(coverage) = self.doc_coverage {
             crate::db::add_doc_coverage(&mut db.conn(), release_id, coverage)?;
         }
+        if let Some(citation) = &self.citation {
+            crate::db::add_citation(&mut db.conn(), release_id, citation)?;
+        }
+        self.version_cache.invalidate(&package.name);
+        self.releases_cache.invalidate();
 
         Ok(release_id)
     }
@@ -439,6 +501,67 @@ impl FakeBuild {
         }
     }
 
+    pub(crate) fn failure(self, failure: BuildFailure) -> Self {
+        Self {
+            result: BuildResult {
+                successful: false,
+                failure: Some(failure),
+                ..self.result
+            },
+            ..self
+        }
+    }
+
+    pub(crate) fn peak_disk_usage(self, peak_disk_usage: u64) -> Self {
+        Self {
+            result: BuildResult {
+                peak_disk_usage: Some(peak_disk_usage),
+                ..self.result
+            },
+            ..self
+        }
+    }
+
+    pub(crate) fn limits(self, limits: Limits) -> Self {
+        Self {
+            result: BuildResult {
+                limits: Some(limits),
+                ..self.result
+            },
+            ..self
+        }
+    }
+
+    pub(crate) fn warnings(self, warnings: Vec<String>) -> Self {
+        Self {
+            result: BuildResult {
+                warnings,
+                ..self.result
+            },
+            ..self
+        }
+    }
+
+    pub(crate) fn diagnostics(self, diagnostics: Vec<BuildDiagnostic>) -> Self {
+        Self {
+            result: BuildResult {
+                diagnostics,
+                ..self.result
+            },
+            ..self
+        }
+    }
+
+    pub(crate) fn no_source(self) -> Self {
+        Self {
+            result: BuildResult {
+                no_source: true,
+                ..self.result
+            },
+            ..self
+        }
+    }
+
     pub(crate) fn successful(self, successful: bool) -> Self {
         Self {
             result: BuildResult {
@@ -483,7 +606,277 @@ impl Default for FakeBuild {
                 rustc_version: "rustc 2.0.0-nightly (000000000 1970-01-01)".into(),
                 docsrs_version: "docs.rs 1.0.0 (000000000 1970-01-01)".into(),
                 successful: true,
+                peak_disk_usage: None,
+                failure: None,
+                limits: None,
+                warnings: Vec::new(),
+                diagnostics: Vec::new(),
+                no_source: false,
             },
         }
     }
 }
+
+/// Simulates what `RustwideBuilder` produces for a crate, without running rustdoc, and sends it
+/// through the build queue and the upload code used by real builds.
+///
+/// Unlike `FakeRelease`, the files end up exactly where a real build would put them, which makes
+/// it possible to test the web server against realistic releases.
+#[must_use = "FakeBuilder does nothing until you call .build()"]
+pub(crate) struct FakeBuilder<'a> {
+    env: &'a TestEnvironment,
+    package: MetadataPackage,
+    /// path relative to the crate root, content
+    source_files: Vec<(String, Vec<u8>)>,
+    /// path relative to the documentation of a target, content
+    rustdoc_files: Vec<(String, Vec<u8>)>,
+    other_targets: Vec<String>,
+    result: BuildResult,
+    doc_coverage: Option<DocCoverage>,
+    definitions: Option<Definitions>,
+    item_index: Option<ItemIndex>,
+    /// target, uncompressed JSON
+    rustdoc_json: Vec<(String, Vec<u8>)>,
+    build_log: String,
+    /// path relative to the documentation, content, of the files changed by the verification build
+    verification_files: Option<Vec<(String, Vec<u8>)>>,
+}
+
+impl<'a> FakeBuilder<'a> {
+    pub(super) fn new(env: &'a TestEnvironment) -> Self {
+        FakeBuilder {
+            env,
+            package: fake_package(),
+            source_files: Vec::new(),
+            rustdoc_files: Vec::new(),
+            other_targets: Vec::new(),
+            result: FakeBuild::default().result,
+            doc_coverage: None,
+            definitions: None,
+            item_index: None,
+            rustdoc_json: Vec::new(),
+            build_log: "Documenting fake-package v1.0.0\nFinished".into(),
+            verification_files: None,
+        }
+    }
+
+    pub(crate) fn name(mut self, new: &str) -> Self {
+        self.package.name = new.into();
+        self.package.id = format!("{}-id", new);
+        self.package.targets[0].name = new.into();
+        self
+    }
+
+    pub(crate) fn version(mut self, new: &str) -> Self {
+        self.package.version = new.into();
+        self
+    }
+
+    /// Replaces the default `Cargo.toml` and `src/lib.rs` sources
+    pub(crate) fn source_file(mut self, path: &str, content: &[u8]) -> Self {
+        self.source_files.push((path.into(), content.into()));
+        self
+    }
+
+    /// Replaces the default documentation of the library, the same files are generated for all
+    /// the targets
+    pub(crate) fn rustdoc_file(mut self, path: &str, content: &[u8]) -> Self {
+        self.rustdoc_files.push((path.into(), content.into()));
+        self
+    }
+
+    pub(crate) fn add_target(mut self, target: &str) -> Self {
+        self.other_targets.push(target.into());
+        self
+    }
+
+    pub(crate) fn failure(mut self, failure: BuildFailure) -> Self {
+        self.result.successful = false;
+        self.result.failure = Some(failure);
+        self
+    }
+
+    pub(crate) fn doc_coverage(mut self, doc_coverage: DocCoverage) -> Self {
+        self.doc_coverage = Some(doc_coverage);
+        self
+    }
+
+    /// Records the definition of an item, like rustdoc's JSON output would
+    pub(crate) fn definition(mut self, name: &str, file: &str, line: u32, kind: &str) -> Self {
+        self.definitions
+            .get_or_insert_with(Definitions::default)
+            .add(name, file, line, kind);
+        self
+    }
+
+    /// Records a documented item of the library, like rustdoc's JSON output would
+    pub(crate) fn item(mut self, path: &str, kind: &str) -> Self {
+        self.item_index
+            .get_or_insert_with(ItemIndex::default)
+            .add(path, kind);
+        self
+    }
+
+    /// Records a re-export of a documented item of the library, like rustdoc's JSON output would
+    pub(crate) fn reexport(mut self, path: &str, documented_path: &str) -> Self {
+        self.item_index
+            .get_or_insert_with(ItemIndex::default)
+            .add_reexport(path, documented_path);
+        self
+    }
+
+    /// Records the output of `rustdoc --output-format json` for a target
+    pub(crate) fn rustdoc_json(mut self, target: &str, json: &[u8]) -> Self {
+        self.rustdoc_json.push((target.into(), json.into()));
+        self
+    }
+
+    /// Documents the default target a second time, like `DOCSRS_VERIFY_REPRODUCIBLE_BUILDS` does
+    pub(crate) fn verified(mut self) -> Self {
+        self.verification_files.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Replaces a file of the default target in the verification build
+    pub(crate) fn verification_file(mut self, path: &str, content: &[u8]) -> Self {
+        self.verification_files
+            .get_or_insert_with(Vec::new)
+            .push((path.into(), content.into()));
+        self
+    }
+
+    pub(crate) fn build_log(mut self, build_log: impl Into<String>) -> Self {
+        self.build_log = build_log.into();
+        self
+    }
+
+    /// Queues the crate, then builds it like the daemon would, returning the id of the release
+    pub(crate) fn build(self) -> Result<i32, Error> {
+        let package = self.package;
+        let default_target = "x86_64-unknown-linux-gnu";
+
+        let source_dir = tempfile::Builder::new()
+            .prefix("docs.rs-fake-source")
+            .tempdir()?;
+        let source_files = if self.source_files.is_empty() {
+            vec![
+                (
+                    "Cargo.toml".to_string(),
+                    format!(
+                        "[package]\nname = {:?}\nversion = {:?}\n",
+                        package.name, package.version
+                    )
+                    .into_bytes(),
+                ),
+                ("src/lib.rs".into(), b"//! Fake crate\n".to_vec()),
+            ]
+        } else {
+            self.source_files
+        };
+        write_files(source_dir.path(), &source_files)?;
+
+        // rustdoc puts the documentation of the library in a directory named after it, and the
+        // other targets are in subdirectories named after the target
+        let docs_dir = tempfile::Builder::new()
+            .prefix("docs.rs-fake-docs")
+            .tempdir()?;
+        let mut successful_targets = Vec::new();
+        let mut verification_manifest = None;
+        if self.result.successful {
+            let library = package
+                .library_name()
+                .ok_or_else(|| failure::err_msg("fake builds only support libraries"))?;
+            let rustdoc_files = if self.rustdoc_files.is_empty() {
+                vec![
+                    (format!("{}/index.html", library), DEFAULT_CONTENT.to_vec()),
+                    (format!("{}/all.html", library), DEFAULT_CONTENT.to_vec()),
+                    (
+                        format!("src/{}/lib.rs.html", library),
+                        DEFAULT_CONTENT.to_vec(),
+                    ),
+                ]
+            } else {
+                self.rustdoc_files
+            };
+
+            write_files(docs_dir.path(), &rustdoc_files)?;
+            successful_targets.push(default_target.to_string());
+            if let Some(verification_files) = &self.verification_files {
+                let verification_dir = tempfile::Builder::new()
+                    .prefix("docs.rs-fake-verification")
+                    .tempdir()?;
+                write_files(verification_dir.path(), &rustdoc_files)?;
+                write_files(verification_dir.path(), verification_files)?;
+                verification_manifest = Some(Manifest::from_dir(verification_dir.path())?);
+            }
+            for target in &self.other_targets {
+                write_files(&docs_dir.path().join(target), &rustdoc_files)?;
+                successful_targets.push(target.clone());
+            }
+        }
+
+        let build_queue = self.env.build_queue();
+        build_queue.add_crate(&package.name, &package.version, 0, None)?;
+
+        let uploader = BuildUploader::new(self.env)?;
+        let mut release_id = None;
+        let rustdoc_json = self
+            .rustdoc_json
+            .iter()
+            .map(|(target, json)| Ok((target.clone(), rustdoc_json::compress_json(json)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (result, doc_coverage, definitions, item_index, build_log) = (
+            self.result,
+            self.doc_coverage,
+            self.definitions,
+            self.item_index,
+            self.build_log,
+        );
+        build_queue.process_next_crate(|krate| {
+            assert_eq!(
+                (krate.name.as_str(), krate.version.as_str()),
+                (package.name.as_str(), package.version.as_str()),
+                "another crate is waiting in the queue"
+            );
+            let has_docs = result.successful;
+            release_id = Some(uploader.upload(BuildOutput {
+                package: &package,
+                source_dir: source_dir.path(),
+                docs_dir: if has_docs {
+                    Some(docs_dir.path())
+                } else {
+                    None
+                },
+                result,
+                default_target,
+                successful_targets,
+                doc_coverage,
+                definitions,
+                item_index,
+                rustdoc_json,
+                build_log,
+                verification_manifest,
+            })?);
+            Ok(())
+        })?;
+
+        release_id.ok_or_else(|| {
+            failure::format_err!(
+                "the fake build of {} {} failed, check the logs",
+                package.name,
+                package.version
+            )
+        })
+    }
+}
+
+fn write_files(root: &Path, files: &[(String, Vec<u8>)]) -> Result<(), Error> {
+    for (path, content) in files {
+        let path = root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
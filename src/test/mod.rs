@@ -1,11 +1,11 @@
 mod fakes;
 
 pub(crate) use self::fakes::FakeBuild;
-use crate::db::{Pool, PoolClient};
+use crate::db::{advisories::Advisory, Pool, PoolClient};
 use crate::repositories::RepositoryStatsUpdater;
 use crate::storage::{Storage, StorageKind};
 use crate::web::Server;
-use crate::{BuildQueue, Config, Context, Index, Metrics};
+use crate::{BuildQueue, Config, Context, Index, Metrics, ReleasesCache, VersionCache};
 use failure::Error;
 use log::error;
 use once_cell::unsync::OnceCell;
@@ -15,6 +15,7 @@ use reqwest::{
     Method,
 };
 use std::fs;
+use std::path::Path;
 use std::{panic, sync::Arc};
 
 pub(crate) fn wrapper(f: impl FnOnce(&TestEnvironment) -> Result<(), Error>) {
@@ -101,15 +102,63 @@ pub(crate) fn assert_redirect(
     Ok(())
 }
 
+/// A vulnerability of every version of `crate_name`, the tests change the other fields they need
+/// with the struct update syntax
+pub(crate) fn fake_advisory(id: &str, crate_name: &str) -> Advisory {
+    Advisory {
+        id: id.into(),
+        crate_name: crate_name.into(),
+        title: "Use after free".into(),
+        url: format!("https://rustsec.org/advisories/{}.html", id),
+        date: chrono::NaiveDate::from_ymd(2021, 1, 2),
+        informational: None,
+        patched: Vec::new(),
+        unaffected: Vec::new(),
+    }
+}
+
+fn init_registry(dir: &Path) -> Result<Index, Error> {
+    let origin = dir.join("origin");
+    let repo = git2::Repository::init_opts(
+        &origin,
+        git2::RepositoryInitOptions::new().initial_head("master"),
+    )?;
+    fs::write(
+        origin.join("config.json"),
+        r#"{"dl": "https://static.crates.io/crates"}"#,
+    )?;
+
+    let mut git_index = repo.index()?;
+    git_index.add_path(Path::new("config.json"))?;
+    let tree = repo.find_tree(git_index.write_tree()?)?;
+    let signature = git2::Signature::now("docs.rs", "docs.rs@example.com")?;
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        "initial commit",
+        &tree,
+        &[],
+    )?;
+
+    let path = dir.join("index");
+    fs::create_dir_all(&path)?;
+    Index::from_url(path, format!("file://{}", origin.display()))
+}
+
 pub(crate) struct TestEnvironment {
     build_queue: OnceCell<Arc<BuildQueue>>,
     config: OnceCell<Arc<Config>>,
     db: OnceCell<TestDatabase>,
     storage: OnceCell<Arc<Storage>>,
     index: OnceCell<Arc<Index>>,
+    /// Contains the registry behind `index`
+    registry_dir: OnceCell<tempfile::TempDir>,
     metrics: OnceCell<Arc<Metrics>>,
     frontend: OnceCell<TestFrontend>,
     repository_stats_updater: OnceCell<Arc<RepositoryStatsUpdater>>,
+    version_cache: OnceCell<Arc<VersionCache>>,
+    releases_cache: OnceCell<Arc<ReleasesCache>>,
 }
 
 pub(crate) fn init_logger() {
@@ -130,9 +179,12 @@ impl TestEnvironment {
             db: OnceCell::new(),
             storage: OnceCell::new(),
             index: OnceCell::new(),
+            registry_dir: OnceCell::new(),
             metrics: OnceCell::new(),
             frontend: OnceCell::new(),
             repository_stats_updater: OnceCell::new(),
+            version_cache: OnceCell::new(),
+            releases_cache: OnceCell::new(),
         }
     }
 
@@ -211,13 +263,15 @@ impl TestEnvironment {
             .clone()
     }
 
+    /// An empty registry, so that the tests don't need to clone the crates.io index. It has no
+    /// API, so the requests to it fail and the builds use the default registry data.
     pub(crate) fn index(&self) -> Arc<Index> {
         self.index
             .get_or_init(|| {
-                Arc::new(
-                    Index::new(self.config().registry_index_path.clone())
-                        .expect("failed to initialize the index"),
-                )
+                let dir = self
+                    .registry_dir
+                    .get_or_init(|| tempfile::tempdir().expect("failed to create a temp dir"));
+                Arc::new(init_registry(dir.path()).expect("failed to initialize the index"))
             })
             .clone()
     }
@@ -233,6 +287,18 @@ impl TestEnvironment {
             .clone()
     }
 
+    pub(crate) fn version_cache(&self) -> Arc<VersionCache> {
+        self.version_cache
+            .get_or_init(|| Arc::new(VersionCache::new(self.metrics(), &self.config())))
+            .clone()
+    }
+
+    pub(crate) fn releases_cache(&self) -> Arc<ReleasesCache> {
+        self.releases_cache
+            .get_or_init(|| Arc::new(ReleasesCache::new(self.metrics(), &self.config())))
+            .clone()
+    }
+
     pub(crate) fn db(&self) -> &TestDatabase {
         self.db.get_or_init(|| {
             TestDatabase::new(&self.config(), self.metrics()).expect("failed to initialize the db")
@@ -243,8 +309,17 @@ impl TestEnvironment {
         self.frontend.get_or_init(|| TestFrontend::new(&*self))
     }
 
+    pub(crate) fn fake_builder(&self) -> fakes::FakeBuilder<'_> {
+        fakes::FakeBuilder::new(self)
+    }
+
     pub(crate) fn fake_release(&self) -> fakes::FakeRelease {
-        fakes::FakeRelease::new(self.db(), self.storage())
+        fakes::FakeRelease::new(
+            self.db(),
+            self.storage(),
+            self.version_cache(),
+            self.releases_cache(),
+        )
     }
 }
 
@@ -276,6 +351,14 @@ impl Context for TestEnvironment {
     fn repository_stats_updater(&self) -> Result<Arc<RepositoryStatsUpdater>, Error> {
         Ok(self.repository_stats_updater())
     }
+
+    fn version_cache(&self) -> Result<Arc<VersionCache>, Error> {
+        Ok(self.version_cache())
+    }
+
+    fn releases_cache(&self) -> Result<Arc<ReleasesCache>, Error> {
+        Ok(self.releases_cache())
+    }
 }
 
 pub(crate) struct TestDatabase {
@@ -377,12 +460,29 @@ impl TestFrontend {
         }
     }
 
-    fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
+    pub(crate) fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
         self.client
-            .request(method, &format!("http://{}{}", self.server.addr(), url))
+            .request(method, format!("http://{}{}", self.server.addr(), url))
     }
 
     pub(crate) fn get(&self, url: &str) -> RequestBuilder {
         self.build_request(Method::GET, url)
     }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.build_request(Method::POST, url)
+    }
+
+    pub(crate) fn server_addr(&self) -> std::net::SocketAddr {
+        self.server.addr()
+    }
+
+    /// Builds a request whose redirects are returned instead of being followed
+    pub(crate) fn request_without_redirects(&self, method: Method, url: &str) -> RequestBuilder {
+        Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build the client")
+            .request(method, &format!("http://{}{}", self.server.addr(), url))
+    }
 }
@@ -0,0 +1,240 @@
+//! Sync of the RustSec advisory database
+//!
+//! When `DOCSRS_ADVISORY_DB_URL` points to a tarball of the [advisory database], e.g. the archive
+//! of its main branch on GitHub, an hourly job downloads it and replaces the `advisories` table
+//! with the advisories about crates it contains. The withdrawn advisories are left out, and the
+//! ones that can't be parsed are logged and skipped.
+//!
+//! [advisory database]: https://github.com/rustsec/advisory-db
+
+use crate::db::advisories::{self, Advisory};
+use crate::error::Result;
+use chrono::NaiveDate;
+use failure::{format_err, ResultExt};
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use postgres::Client;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::{Component, Path};
+
+#[derive(Debug, Deserialize)]
+struct AdvisoryFile {
+    advisory: AdvisoryMetadata,
+    #[serde(default)]
+    versions: AdvisoryVersions,
+}
+
+#[derive(Debug, Deserialize)]
+struct AdvisoryMetadata {
+    id: String,
+    package: String,
+    date: String,
+    url: Option<String>,
+    /// Only in the advisories written in the TOML format, the title is the heading of the Markdown
+    /// otherwise
+    title: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AdvisoryVersions {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+/// Downloads the advisory database and replaces the stored advisories, returns how many there are
+pub fn sync_advisories(conn: &mut Client, url: &str) -> Result<usize> {
+    let response = reqwest::blocking::Client::builder()
+        .user_agent(crate::repositories::APP_USER_AGENT)
+        .build()?
+        .get(url)
+        .send()?
+        .error_for_status()?;
+    let advisories = parse_archive(response)
+        .with_context(|_| format!("invalid advisory database at {}", url))?;
+
+    advisories::replace_all(conn, &advisories)?;
+    info!("synced {} security advisories", advisories.len());
+    Ok(advisories.len())
+}
+
+/// Reads the advisories about crates from a gzipped tarball of the database, they're stored in
+/// `crates/<name>/RUSTSEC-YYYY-NNNN.md` under the root directory of the archive
+fn parse_archive(archive: impl Read) -> Result<Vec<Advisory>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(archive));
+    let mut advisories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !is_crate_advisory(&path) {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        // one malformed advisory doesn't keep the others from being synced
+        let advisory = String::from_utf8(content)
+            .map_err(failure::Error::from)
+            .and_then(|content| parse_advisory(&content));
+        match advisory {
+            Ok(advisory) => advisories.extend(advisory),
+            Err(err) => warn!("skipping the invalid advisory {}: {}", path.display(), err),
+        }
+    }
+    Ok(advisories)
+}
+
+fn is_crate_advisory(path: &Path) -> bool {
+    let components: Vec<_> = path.components().collect();
+    matches!(
+        components.as_slice(),
+        [Component::Normal(_), Component::Normal(dir), Component::Normal(_), Component::Normal(_)]
+            if *dir == "crates"
+    ) && path.extension() == Some("md".as_ref())
+}
+
+/// Parses an advisory, a Markdown file starting with its metadata in a TOML code block. Returns
+/// `None` for the withdrawn advisories.
+fn parse_advisory(content: &str) -> Result<Option<Advisory>> {
+    let content = content
+        .trim_start()
+        .strip_prefix("```toml")
+        .ok_or_else(|| format_err!("missing the metadata of the advisory"))?;
+    let end = content
+        .find("\n```")
+        .ok_or_else(|| format_err!("unterminated metadata"))?;
+    let file: AdvisoryFile = toml::from_str(&content[..end])?;
+    let markdown = &content[end + "\n```".len()..];
+
+    let AdvisoryMetadata {
+        id,
+        package,
+        date,
+        url,
+        title,
+        informational,
+        withdrawn,
+    } = file.advisory;
+    if withdrawn.is_some() {
+        return Ok(None);
+    }
+    let title = markdown
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+        .or(title)
+        .ok_or_else(|| format_err!("missing the title of {}", id))?;
+
+    Ok(Some(Advisory {
+        url: url.unwrap_or_else(|| format!("https://rustsec.org/advisories/{}.html", id)),
+        date: NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+        id,
+        crate_name: package,
+        title,
+        informational,
+        patched: file.versions.patched,
+        unaffected: file.versions.unaffected,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use flate2::{write::GzEncoder, Compression};
+
+    const ADVISORY: &str = r#"```toml
+[advisory]
+id = "RUSTSEC-2021-0001"
+package = "foo"
+date = "2021-01-02"
+informational = "unsound"
+
+[versions]
+patched = [">= 1.2.3"]
+```
+
+# Use after free in `Foo::bar`
+
+The details.
+"#;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let advisory = parse_advisory(ADVISORY).unwrap().unwrap();
+        assert_eq!(
+            advisory,
+            Advisory {
+                id: "RUSTSEC-2021-0001".into(),
+                crate_name: "foo".into(),
+                title: "Use after free in `Foo::bar`".into(),
+                url: "https://rustsec.org/advisories/RUSTSEC-2021-0001.html".into(),
+                date: NaiveDate::from_ymd(2021, 1, 2),
+                informational: Some("unsound".into()),
+                patched: vec![">= 1.2.3".into()],
+                unaffected: Vec::new(),
+            }
+        );
+
+        let withdrawn = ADVISORY.replace("[versions]", "withdrawn = \"2021-02-01\"\n[versions]");
+        assert_eq!(parse_advisory(&withdrawn).unwrap(), None);
+        assert!(parse_advisory("# Use after free").is_err());
+    }
+
+    #[test]
+    fn only_crate_advisories() {
+        let advisories = parse_archive(
+            &archive(&[
+                ("advisory-db-main/README.md", "# RustSec Advisory Database"),
+                ("advisory-db-main/crates/foo/RUSTSEC-2021-0001.md", ADVISORY),
+                ("advisory-db-main/crates/foo/README.md.orig", ""),
+                // skipped without failing the sync
+                (
+                    "advisory-db-main/crates/bar/RUSTSEC-2021-0003.md",
+                    "# Invalid",
+                ),
+                ("advisory-db-main/rust/std/RUSTSEC-2021-0002.md", ""),
+            ])[..],
+        )
+        .unwrap();
+        assert_eq!(advisories.len(), 1);
+        assert_eq!(advisories[0].id, "RUSTSEC-2021-0001");
+    }
+
+    #[test]
+    fn sync() {
+        wrapper(|env| {
+            let _download = mockito::mock("GET", "/advisory-db.tar.gz")
+                .with_body(archive(&[(
+                    "advisory-db-main/crates/foo/RUSTSEC-2021-0001.md",
+                    ADVISORY,
+                )]))
+                .create();
+            let url = format!("{}/advisory-db.tar.gz", mockito::server_url());
+
+            let mut conn = env.db().conn();
+            assert_eq!(sync_advisories(&mut conn, &url)?, 1);
+            assert_eq!(advisories::affecting(&mut conn, "foo", "1.0.0")?.len(), 1);
+
+            Ok(())
+        });
+    }
+}
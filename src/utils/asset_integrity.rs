@@ -0,0 +1,124 @@
+//! Subresource integrity of the shared rustdoc assets
+//!
+//! The CSS and JavaScript files shared by all the crates documented with a toolchain are uploaded
+//! by `add_essential_files`, which also records their hashes in `shared_asset_integrity`. The
+//! pages link to them with an `integrity` attribute, so browsers refuse the files if they were
+//! tampered with on the way, e.g. by the CDN.
+
+use crate::error::Result;
+use log::error;
+use postgres::Client;
+use sha2::{Digest, Sha384};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// New toolchains are rare, the hashes of their assets are picked up by the web server after this
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The value of the `integrity` attribute of a file with this content
+pub(crate) fn integrity(content: &[u8]) -> String {
+    format!("sha384-{}", base64::encode(Sha384::digest(content)))
+}
+
+/// Records the hashes of the stylesheets and scripts at the root of `dir`, replacing the ones of
+/// the files with the same name
+pub(crate) fn record_shared_assets(conn: &mut Client, dir: &Path) -> Result<()> {
+    let mut transaction = conn.transaction()?;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_asset = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("js") | Some("css")
+        );
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if is_asset && path.is_file() => name.to_string(),
+            _ => continue,
+        };
+
+        transaction.execute(
+            "INSERT INTO shared_asset_integrity (path, integrity) VALUES ($1, $2)
+             ON CONFLICT (path) DO UPDATE SET integrity = EXCLUDED.integrity",
+            &[&name, &integrity(&fs::read(&path)?)],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The `integrity` attributes by file name
+pub(crate) type AssetHashes = Arc<HashMap<String, String>>;
+
+/// In-process copy of `shared_asset_integrity`, reloaded every few minutes
+#[derive(Debug, Default)]
+pub(crate) struct AssetIntegrity {
+    hashes: RwLock<Option<(Instant, AssetHashes)>>,
+}
+
+impl AssetIntegrity {
+    /// The hashes of the shared assets by file name. If they can't be reloaded the previous ones
+    /// are kept, without hashes the pages are only served without the `integrity` attributes.
+    pub(crate) fn hashes(&self, conn: &mut Client) -> AssetHashes {
+        if let Some((loaded, hashes)) = &*self.hashes.read().unwrap() {
+            if loaded.elapsed() < RELOAD_INTERVAL {
+                return hashes.clone();
+            }
+        }
+
+        let mut cached = self.hashes.write().unwrap();
+        match conn.query("SELECT path, integrity FROM shared_asset_integrity", &[]) {
+            Ok(rows) => {
+                let hashes: AssetHashes = Arc::new(
+                    rows.into_iter()
+                        .map(|row| (row.get(0), row.get(1)))
+                        .collect(),
+                );
+                *cached = Some((Instant::now(), hashes.clone()));
+                hashes
+            }
+            Err(err) => {
+                error!("failed to load the hashes of the shared assets: {}", err);
+                cached
+                    .as_ref()
+                    .map(|(_, hashes)| hashes.clone())
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            integrity(b"alert(1)"),
+            "sha384-HT2E9NfWiuQ/w1PRai+hTyqW16NIoCGA/m8VQDUopfAtcz6YQjtsMmQd5uRbVDpW"
+        );
+    }
+
+    #[test]
+    fn record_and_load() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            fs::write(dir.path().join("main-1.55.0.js"), "alert(1)")?;
+            fs::write(dir.path().join("FiraSans-Regular.woff"), "font")?;
+            fs::create_dir(dir.path().join("dummy"))?;
+            fs::write(dir.path().join("dummy/index.js"), "")?;
+
+            let mut conn = env.db().conn();
+            record_shared_assets(&mut conn, dir.path())?;
+
+            let hashes = AssetIntegrity::default().hashes(&mut conn);
+            assert_eq!(hashes.len(), 1);
+            assert_eq!(hashes["main-1.55.0.js"], integrity(b"alert(1)"));
+
+            Ok(())
+        });
+    }
+}
@@ -59,6 +59,8 @@ pub(crate) struct Package {
     pub(crate) readme: Option<String>,
     pub(crate) keywords: Vec<String>,
     pub(crate) features: HashMap<String, Vec<String>>,
+    /// The minimum supported Rust version, the `rust-version` field of the manifest
+    pub(crate) rust_version: Option<String>,
 }
 
 impl Package {
@@ -0,0 +1,336 @@
+//! Parser and formatters for `CITATION.cff` files
+//!
+//! The [Citation File Format](https://citation-file-format.github.io/) is a YAML file telling
+//! people how to cite a piece of software. Only the fields needed to render a citation are parsed,
+//! everything else in the file is ignored.
+
+use crate::error::Result;
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Name of the citation file, looked up at the root of the crate sources
+pub(crate) const CITATION_FILE: &str = "CITATION.cff";
+/// Citation files larger than this are ignored
+const MAX_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Citation {
+    pub(crate) title: String,
+    pub(crate) authors: Vec<Author>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) version: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) doi: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) date_released: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) url: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) repository_code: Option<String>,
+}
+
+/// Either a person or an entity (like a company or a project team)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Author {
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) family_names: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) given_names: Option<String>,
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) name_particle: Option<String>,
+    /// The name of an entity
+    #[serde(default, deserialize_with = "optional_scalar")]
+    pub(crate) name: Option<String>,
+}
+
+/// YAML parses unquoted values like `version: 2` as numbers, but all the fields we care about
+/// are strings. Note that the original formatting of numbers is lost (`1.0` becomes `1`).
+fn optional_scalar<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error> {
+    use serde_yaml::Value;
+
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => Some(s),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+    .map(|s| s.trim().to_owned())
+    .filter(|s| !s.is_empty()))
+}
+
+impl Author {
+    fn family_name(&self) -> Option<String> {
+        let family = self.family_names.as_deref()?;
+        Some(match &self.name_particle {
+            Some(particle) => format!("{} {}", particle, family),
+            None => family.to_owned(),
+        })
+    }
+
+    /// `Family, G. N.` for persons, or the name of entities
+    fn short_name(&self) -> Option<String> {
+        match (self.family_name(), &self.given_names) {
+            (Some(family), Some(given)) => {
+                let initials: Vec<_> = given
+                    .split_whitespace()
+                    .filter_map(|name| name.chars().next())
+                    .map(|initial| format!("{}.", initial))
+                    .collect();
+                Some(format!("{}, {}", family, initials.join(" ")))
+            }
+            (Some(family), None) => Some(family),
+            (None, Some(given)) => Some(given.clone()),
+            (None, None) => self.name.clone(),
+        }
+    }
+
+    /// `Family, Given Names` for persons, or the name of entities wrapped in braces
+    fn bibtex_name(&self) -> Option<String> {
+        match (self.family_name(), &self.given_names) {
+            (Some(family), Some(given)) => Some(format!(
+                "{}, {}",
+                escape_bibtex(&family),
+                escape_bibtex(given)
+            )),
+            (Some(family), None) => Some(escape_bibtex(&family)),
+            (None, Some(given)) => Some(escape_bibtex(given)),
+            // braces prevent bibtex from splitting the name of entities
+            (None, None) => self
+                .name
+                .as_ref()
+                .map(|name| format!("{{{}}}", escape_bibtex(name))),
+        }
+    }
+}
+
+fn escape_bibtex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '{' | '}' | '\\' => {}
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Citation {
+    pub(crate) fn parse(content: &str) -> Result<Self> {
+        let citation: Citation = serde_yaml::from_str(content)?;
+        if citation.title.trim().is_empty() {
+            failure::bail!("the citation has no title");
+        }
+        if !citation
+            .authors
+            .iter()
+            .any(|author| author.short_name().is_some())
+        {
+            failure::bail!("the citation has no authors");
+        }
+        Ok(citation)
+    }
+
+    /// Looks for a citation file in the crate sources, ignoring it if it's invalid
+    pub(crate) fn from_source_dir(source_dir: &Path) -> Option<Self> {
+        let path = source_dir.join(CITATION_FILE);
+        let metadata = fs::metadata(&path).ok()?;
+        if !metadata.is_file() || metadata.len() > MAX_SIZE {
+            return None;
+        }
+
+        match fs::read_to_string(&path)
+            .map_err(failure::Error::from)
+            .and_then(|content| Citation::parse(&content))
+        {
+            Ok(citation) => Some(citation),
+            Err(err) => {
+                info!("ignoring invalid {}: {}", CITATION_FILE, err);
+                None
+            }
+        }
+    }
+
+    fn year(&self) -> Option<&str> {
+        self.date_released
+            .as_deref()
+            .filter(|date| date.len() >= 4 && date[..4].chars().all(|c| c.is_ascii_digit()))
+            .map(|date| &date[..4])
+    }
+
+    fn link(&self) -> Option<String> {
+        match &self.doi {
+            Some(doi) => Some(format!("https://doi.org/{}", doi)),
+            None => self.url.clone().or_else(|| self.repository_code.clone()),
+        }
+    }
+
+    /// A human readable citation, in APA style
+    pub(crate) fn formatted(&self) -> String {
+        let authors: Vec<_> = self.authors.iter().filter_map(Author::short_name).collect();
+        let authors = match authors.as_slice() {
+            [] => String::new(),
+            [single] => single.clone(),
+            [rest @ .., last] => format!("{}, & {}", rest.join(", "), last),
+        };
+
+        let mut formatted = format!(
+            "{} ({}). {}",
+            authors,
+            self.year().unwrap_or("n.d."),
+            self.title.trim()
+        );
+        if let Some(version) = &self.version {
+            formatted.push_str(&format!(" (Version {})", version));
+        }
+        formatted.push_str(" [Computer software].");
+        if let Some(link) = self.link() {
+            formatted.push(' ');
+            formatted.push_str(&link);
+        }
+        formatted
+    }
+
+    /// A `@software` BibTeX entry for the citation
+    pub(crate) fn bibtex(&self, key: &str) -> String {
+        let key: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let authors: Vec<_> = self
+            .authors
+            .iter()
+            .filter_map(Author::bibtex_name)
+            .collect();
+
+        let mut fields = vec![
+            ("author", authors.join(" and ")),
+            ("title", escape_bibtex(self.title.trim())),
+        ];
+        if let Some(year) = self.year() {
+            fields.push(("year", year.to_owned()));
+        }
+        if let Some(version) = &self.version {
+            fields.push(("version", escape_bibtex(version)));
+        }
+        if let Some(doi) = &self.doi {
+            fields.push(("doi", escape_bibtex(doi)));
+        }
+        if let Some(url) = self.url.as_ref().or(self.repository_code.as_ref()) {
+            fields.push(("url", url.replace(['{', '}'], "")));
+        }
+
+        let mut bibtex = format!("@software{{{}", key);
+        for (name, value) in fields {
+            bibtex.push_str(&format!(",\n  {} = {{{}}}", name, value));
+        }
+        bibtex.push_str("\n}\n");
+        bibtex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFF: &str = r#"
+cff-version: 1.2.0
+message: "If you use this software, please cite it as below."
+authors:
+  - family-names: Druyts
+    given-names: Jean Marie
+    orcid: https://orcid.org/0000-0000-0000-0000
+  - family-names: Rossum
+    name-particle: van
+    given-names: Guido
+  - name: "The Rust & Friends Team"
+title: "My_Research Crate"
+version: 1.0.0
+doi: 10.5281/zenodo.1234
+date-released: 2021-08-11
+"#;
+
+    #[test]
+    fn parse() {
+        let citation = Citation::parse(CFF).unwrap();
+        assert_eq!(citation.title, "My_Research Crate");
+        assert_eq!(citation.version.as_deref(), Some("1.0.0"));
+        assert_eq!(citation.date_released.as_deref(), Some("2021-08-11"));
+        assert_eq!(citation.authors.len(), 3);
+        assert_eq!(citation.authors[1].name_particle.as_deref(), Some("van"));
+        assert_eq!(
+            citation.authors[2].name.as_deref(),
+            Some("The Rust & Friends Team")
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Citation::parse("not: [valid").is_err());
+        assert!(Citation::parse("title: foo").is_err());
+        assert!(Citation::parse("title: foo\nauthors: []").is_err());
+        assert!(Citation::parse("title: ''\nauthors:\n  - name: bar").is_err());
+        assert!(Citation::parse("title: foo\nauthors:\n  - name: bar").is_ok());
+    }
+
+    #[test]
+    fn formatted() {
+        let citation = Citation::parse(CFF).unwrap();
+        assert_eq!(
+            citation.formatted(),
+            "Druyts, J. M., van Rossum, G., & The Rust & Friends Team (2021). My_Research Crate \
+             (Version 1.0.0) [Computer software]. https://doi.org/10.5281/zenodo.1234"
+        );
+
+        let citation = Citation::parse(
+            "title: foo\nauthors:\n  - family-names: Bar\nversion: 2\nrepository-code: https://example.com",
+        )
+        .unwrap();
+        assert_eq!(
+            citation.formatted(),
+            "Bar (n.d.). foo (Version 2) [Computer software]. https://example.com"
+        );
+    }
+
+    #[test]
+    fn bibtex() {
+        let citation = Citation::parse(CFF).unwrap();
+        assert_eq!(
+            citation.bibtex("my-crate"),
+            "@software{my_crate,\n  \
+               author = {Druyts, Jean Marie and van Rossum, Guido and {The Rust \\& Friends Team}},\n  \
+               title = {My\\_Research Crate},\n  \
+               year = {2021},\n  \
+               version = {1.0.0},\n  \
+               doi = {10.5281/zenodo.1234}\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn from_source_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(Citation::from_source_dir(dir.path()), None);
+
+        fs::write(dir.path().join(CITATION_FILE), "invalid: [")?;
+        assert_eq!(Citation::from_source_dir(dir.path()), None);
+
+        fs::write(dir.path().join(CITATION_FILE), CFF)?;
+        assert_eq!(
+            Citation::from_source_dir(dir.path()),
+            Some(Citation::parse(CFF)?)
+        );
+
+        Ok(())
+    }
+}
@@ -1,12 +1,28 @@
+//! Consistency checks between the database, the index and the storage
+//!
+//! Comparing the database with the index needs the `consistency_check` feature, while the storage
+//! check runs periodically in the daemon.
+
+#[cfg(feature = "consistency_check")]
 use self::diff::{Diff, Diffable};
+#[cfg(feature = "consistency_check")]
 use crate::Index;
+#[cfg(feature = "consistency_check")]
 use failure::ResultExt;
 
+pub(crate) use self::storage::{check_storage, consistency_issues, ConsistencyIssue};
+
+#[cfg(feature = "consistency_check")]
 mod data;
+#[cfg(feature = "consistency_check")]
 mod db;
+#[cfg(feature = "consistency_check")]
 mod diff;
+#[cfg(feature = "consistency_check")]
 mod index;
+mod storage;
 
+#[cfg(feature = "consistency_check")]
 pub fn run_check(
     conn: &mut postgres::Client,
     index: &Index,
@@ -0,0 +1,185 @@
+//! Checks that the files of releases recorded in the database can be retrieved from the storage
+//!
+//! Checking every release would take days, so each run picks a random sample of releases
+//! (`DOCSRS_CONSISTENCY_CHECK_SAMPLE_SIZE`) and verifies that their source files and the root of
+//! their documentation exist. The missing files are recorded in the `consistency_issues` table,
+//! replacing the issues found by the previous checks of the same releases.
+
+use crate::error::Result;
+use crate::Storage;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use postgres::Client;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IssueKind {
+    MissingSource,
+    MissingDocs,
+}
+
+impl IssueKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            IssueKind::MissingSource => "missing-source",
+            IssueKind::MissingDocs => "missing-docs",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ConsistencyIssue {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// Path of the missing file in the storage
+    pub(crate) path: String,
+    pub(crate) kind: String,
+    pub(crate) detected_at: DateTime<Utc>,
+}
+
+/// Checks a random sample of releases, returning the number of missing files found
+pub(crate) fn check_storage(
+    conn: &mut Client,
+    storage: &Storage,
+    sample_size: u32,
+) -> Result<usize> {
+    let releases = conn.query(
+        "SELECT releases.id, crates.name, releases.version, releases.files,
+                releases.rustdoc_status, releases.target_name
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         ORDER BY RANDOM()
+         LIMIT $1",
+        &[&i64::from(sample_size)],
+    )?;
+
+    let mut total = 0;
+    for release in &releases {
+        let release_id: i32 = release.get("id");
+        let name: String = release.get("name");
+        let version: String = release.get("version");
+
+        let mut expected = Vec::new();
+        if let Some(Value::Array(files)) = release.get::<_, Option<Value>>("files") {
+            // every file is stored as a `[mime, path]` pair
+            for path in files.iter().filter_map(|file| file.get(1)?.as_str()) {
+                expected.push((
+                    format!("sources/{}/{}/{}", name, version, path),
+                    IssueKind::MissingSource,
+                ));
+            }
+        }
+        if release.get("rustdoc_status") {
+            if let Some(target_name) = release.get::<_, Option<String>>("target_name") {
+                expected.push((
+                    format!("rustdoc/{}/{}/{}/index.html", name, version, target_name),
+                    IssueKind::MissingDocs,
+                ));
+            }
+        }
+
+        let mut missing = Vec::new();
+        for (path, kind) in expected {
+            if !storage.exists(&path)? {
+                warn!("{} {} is missing {} from the storage", name, version, path);
+                missing.push((path, kind));
+            }
+        }
+
+        let mut transaction = conn.transaction()?;
+        transaction.execute(
+            "DELETE FROM consistency_issues WHERE release_id = $1",
+            &[&release_id],
+        )?;
+        for (path, kind) in &missing {
+            transaction.execute(
+                "INSERT INTO consistency_issues (release_id, path, kind) VALUES ($1, $2, $3)",
+                &[&release_id, path, &kind.as_str()],
+            )?;
+        }
+        transaction.commit()?;
+        total += missing.len();
+    }
+
+    info!(
+        "checked the storage of {} releases, {} files are missing",
+        releases.len(),
+        total
+    );
+    Ok(total)
+}
+
+/// Lists the missing files found by the previous checks, most recent first
+pub(crate) fn consistency_issues(conn: &mut Client) -> Result<Vec<ConsistencyIssue>> {
+    Ok(conn
+        .query(
+            "SELECT crates.name, releases.version, consistency_issues.path,
+                    consistency_issues.kind, consistency_issues.detected_at
+             FROM consistency_issues
+             INNER JOIN releases ON releases.id = consistency_issues.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             ORDER BY consistency_issues.detected_at DESC, crates.name, releases.version,
+                      consistency_issues.path",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| ConsistencyIssue {
+            name: row.get("name"),
+            version: row.get("version"),
+            path: row.get("path"),
+            kind: row.get("kind"),
+            detected_at: row.get("detected_at"),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn missing_files() {
+        wrapper(|env| {
+            env.fake_builder().name("foo").version("0.1.0").build()?;
+            env.fake_builder().name("bar").version("0.1.0").build()?;
+            let storage = env.storage();
+            let mut conn = env.db().conn();
+
+            assert_eq!(check_storage(&mut conn, &storage, 10)?, 0);
+            assert!(consistency_issues(&mut conn)?.is_empty());
+
+            storage.delete_prefix("sources/foo/0.1.0/src/")?;
+            storage.delete_prefix("rustdoc/foo/0.1.0/foo/")?;
+            assert_eq!(check_storage(&mut conn, &storage, 10)?, 2);
+            let issues = consistency_issues(&mut conn)?;
+            let mut found: Vec<_> = issues
+                .iter()
+                .map(|issue| {
+                    (
+                        issue.name.as_str(),
+                        issue.path.as_str(),
+                        issue.kind.as_str(),
+                    )
+                })
+                .collect();
+            found.sort();
+            assert_eq!(
+                found,
+                vec![
+                    ("foo", "rustdoc/foo/0.1.0/foo/index.html", "missing-docs"),
+                    ("foo", "sources/foo/0.1.0/src/lib.rs", "missing-source"),
+                ]
+            );
+
+            // the issues of a release are replaced when it's checked again
+            storage.store_one("sources/foo/0.1.0/src/lib.rs", "//! Fake crate")?;
+            storage.store_one("rustdoc/foo/0.1.0/foo/index.html", "<html></html>")?;
+            assert_eq!(check_storage(&mut conn, &storage, 10)?, 0);
+            assert!(consistency_issues(&mut conn)?.is_empty());
+
+            Ok(())
+        });
+    }
+}
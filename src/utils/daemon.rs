@@ -2,48 +2,60 @@
 //!
 //! This daemon will start web server, track new packages and build them
 
-use crate::{utils::queue_builder, Context, DocBuilder, RustwideBuilder};
+use crate::utils::{pubsubhubbub::Hub, queue_builder, scheduler::Scheduler};
+use crate::web::{page::TemplateData, releases::render_releases_feed};
+use crate::{Context, DocBuilder, Metrics, RustwideBuilder};
 use failure::Error;
 use log::{debug, error, info};
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+/// How long to wait before restarting a thread that failed right after starting
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// The delay doubles every time the thread fails again, up to this one. A thread that ran for
+/// longer than this before failing is restarted after `MIN_RESTART_DELAY`.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(5 * 60);
+
 fn start_registry_watcher(context: &dyn Context) -> Result<(), Error> {
     let pool = context.pool()?;
     let build_queue = context.build_queue()?;
     let config = context.config()?;
     let index = context.index()?;
+    let version_cache = context.version_cache()?;
 
-    thread::Builder::new()
-        .name("registry index reader".to_string())
-        .spawn(move || {
-            // space this out to prevent it from clashing against the queue-builder thread on launch
-            thread::sleep(Duration::from_secs(30));
-
-            let mut last_gc = Instant::now();
-            loop {
-                let mut doc_builder =
-                    DocBuilder::new(config.clone(), pool.clone(), build_queue.clone());
-
-                if doc_builder.is_locked() {
-                    debug!("Lock file exists, skipping checking new crates");
-                } else {
-                    debug!("Checking new crates");
-                    match doc_builder.get_new_crates(&index) {
-                        Ok(n) => debug!("{} crates added to queue", n),
-                        Err(e) => error!("Failed to get new crates: {}", e),
-                    }
-                }
+    supervise("registry index reader", context.metrics()?, move || {
+        // space this out to prevent it from clashing against the queue-builder thread on launch
+        thread::sleep(Duration::from_secs(30));
+
+        let mut last_gc = Instant::now();
+        loop {
+            let mut doc_builder = DocBuilder::new(
+                config.clone(),
+                pool.clone(),
+                build_queue.clone(),
+                version_cache.clone(),
+            );
 
-                if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
-                    index.run_git_gc();
-                    last_gc = Instant::now();
+            if doc_builder.is_locked() {
+                debug!("Lock file exists, skipping checking new crates");
+            } else {
+                debug!("Checking new crates");
+                match doc_builder.get_new_crates(&index) {
+                    Ok(n) => debug!("{} crates added to queue", n),
+                    Err(e) => error!("Failed to get new crates: {}", e),
                 }
-                thread::sleep(Duration::from_secs(60));
             }
-        })?;
 
-    Ok(())
+            if last_gc.elapsed().as_secs() >= config.registry_gc_interval {
+                index.run_git_gc();
+                last_gc = Instant::now();
+            }
+            thread::sleep(Duration::from_secs(60));
+        }
+    })
 }
 
 pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Result<(), Error> {
@@ -60,32 +72,170 @@ pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Res
         start_registry_watcher(context)?;
     }
 
+    let metrics = context.metrics()?;
+    let mut scheduler = Scheduler::new(context.pool()?, metrics.clone());
+
+    if config.upload_spill_dir.is_some() {
+        // retry the uploads that failed while the storage was unreachable
+        let storage = context.storage()?;
+        scheduler.job(
+            "spilled uploads recovery",
+            "*/5 * * * *",
+            Duration::from_secs(0),
+            move || {
+                storage.recover_spilled_uploads()?;
+                Ok(())
+            },
+        )?;
+    }
+
+    {
+        // delete the documentation left half-uploaded by builders that crashed
+        let storage = context.storage()?;
+        scheduler.job(
+            "partial uploads cleanup",
+            "45 * * * *",
+            Duration::from_secs(0),
+            move || {
+                storage.cleanup_partial_uploads(Duration::from_secs(24 * 60 * 60))?;
+                Ok(())
+            },
+        )?;
+    }
+
+    if config.consistency_check_sample_size > 0 {
+        // look for releases whose files went missing from the storage
+        let pool = context.pool()?;
+        let storage = context.storage()?;
+        let sample_size = config.consistency_check_sample_size;
+        scheduler.job(
+            "storage consistency check",
+            "30 * * * *",
+            Duration::from_secs(5 * 60),
+            move || {
+                crate::utils::consistency::check_storage(&mut *pool.get()?, &storage, sample_size)?;
+                Ok(())
+            },
+        )?;
+    }
+
+    {
+        // verify the requests sent to the WebSub hub, and send the feed to the subscribers when
+        // new releases were added
+        let pool = context.pool()?;
+        let hub = Hub::new(&config, metrics.clone())?;
+        let templates = TemplateData::new(&mut *pool.get()?, &config)?;
+        scheduler.job(
+            "releases feed deliveries",
+            "* * * * *",
+            Duration::from_secs(0),
+            move || {
+                let mut conn = pool.get()?;
+                hub.verify_requests(&mut conn)?;
+                hub.deliver(&mut conn, |conn| render_releases_feed(conn, &templates))?;
+                Ok(())
+            },
+        )?;
+    }
+
+    if config.toolchain_rebuild_crates > 0 {
+        let pool = context.pool()?;
+        let build_queue = context.build_queue()?;
+        let config = config.clone();
+        scheduler.job(
+            "rebuilds after toolchain updates",
+            "*/15 * * * *",
+            Duration::from_secs(0),
+            move || {
+                crate::utils::rebuild::rebuild_after_toolchain_update(
+                    &mut *pool.get()?,
+                    &build_queue,
+                    &config,
+                )?;
+                Ok(())
+            },
+        )?;
+    }
+
+    if let Some(policy) = config.prerelease_retention {
+        // archive or delete the documentation of the superseded pre-releases
+        let pool = context.pool()?;
+        let storage = context.storage()?;
+        let retention_days = config.prerelease_retention_days;
+        scheduler.job(
+            "pre-release retention",
+            "0 4 * * *",
+            Duration::from_secs(30 * 60),
+            move || {
+                crate::utils::retention::retire_superseded_prereleases(
+                    &mut *pool.get()?,
+                    &storage,
+                    policy,
+                    retention_days,
+                    false,
+                )?;
+                Ok(())
+            },
+        )?;
+    }
+
+    if let Some(url) = config.advisory_db_url.clone() {
+        // refresh the security advisories shown on the affected releases
+        let pool = context.pool()?;
+        scheduler.job(
+            "advisories sync",
+            "20 * * * *",
+            Duration::from_secs(5 * 60),
+            move || {
+                crate::utils::advisories::sync_advisories(&mut *pool.get()?, &url)?;
+                Ok(())
+            },
+        )?;
+    }
+
+    if config.dataset_exports {
+        // publish the dumps of the public tables
+        let pool = context.pool()?;
+        let storage = context.storage()?;
+        let keep = config.dataset_exports_kept;
+        scheduler.job(
+            "dataset export",
+            "0 3 * * *",
+            Duration::from_secs(30 * 60),
+            move || {
+                crate::utils::dataset_export::export_datasets(&mut *pool.get()?, &storage, keep)?;
+                Ok(())
+            },
+        )?;
+    }
+
     // build new crates every minute
     let pool = context.pool()?;
     let build_queue = context.build_queue()?;
-    let rustwide_builder = RustwideBuilder::init(context)?;
-    thread::Builder::new()
-        .name("build queue reader".to_string())
-        .spawn(move || {
-            let doc_builder = DocBuilder::new(config.clone(), pool.clone(), build_queue.clone());
-            queue_builder(doc_builder, rustwide_builder, build_queue).unwrap();
-        })
-        .unwrap();
+    let version_cache = context.version_cache()?;
+    let mut rustwide_builder = RustwideBuilder::init(context)?;
+    let mut doc_builder = DocBuilder::new(config, pool, build_queue.clone(), version_cache);
+    supervise("build queue reader", metrics.clone(), move || {
+        queue_builder(&mut doc_builder, &mut rustwide_builder, &build_queue)
+    })?;
 
     // This call will still skip github repositories updates and continue if no token is provided
     // (gitlab doesn't require to have a token). The only time this can return an error is when
     // creating a pool or if config fails, which shouldn't happen here because this is run right at
     // startup.
     let updater = context.repository_stats_updater()?;
-    cron(
+    scheduler.job(
         "repositories stats updater",
-        Duration::from_secs(60 * 60),
+        "0 * * * *",
+        Duration::from_secs(5 * 60),
         move || {
             updater.update_all_crates()?;
             Ok(())
         },
     )?;
 
+    scheduler.start()?;
+
     // Never returns; `server` blocks indefinitely when dropped
     // NOTE: if a failure occurred earlier in `start_daemon`, the server will _not_ be joined -
     // instead it will get killed when the process exits.
@@ -94,17 +244,99 @@ pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Res
         .map_err(|_| failure::err_msg("web server panicked"))
 }
 
-pub(crate) fn cron<F>(name: &'static str, interval: Duration, exec: F) -> Result<(), Error>
+/// Runs `run` in a new thread, calling it again when it panics or returns an error
+///
+/// The thread stops when `run` returns `Ok`.
+pub(crate) fn supervise<F>(
+    name: &'static str,
+    metrics: Arc<Metrics>,
+    mut run: F,
+) -> Result<(), Error>
 where
-    F: Fn() -> Result<(), Error> + Send + 'static,
+    F: FnMut() -> Result<(), Error> + Send + 'static,
 {
-    thread::Builder::new()
-        .name(name.into())
-        .spawn(move || loop {
-            thread::sleep(interval);
-            if let Err(err) = exec() {
-                error!("failed to run scheduled task '{}': {:?}", name, err);
+    thread::Builder::new().name(name.into()).spawn(move || {
+        let mut delay = MIN_RESTART_DELAY;
+        loop {
+            let started = Instant::now();
+            let failure = match catch_unwind(AssertUnwindSafe(&mut run)) {
+                Ok(Ok(())) => {
+                    info!("thread '{}' finished", name);
+                    return;
+                }
+                Ok(Err(err)) => format!("{:?}", err),
+                Err(payload) => format!("panicked: {}", panic_message(&*payload)),
+            };
+
+            if started.elapsed() > MAX_RESTART_DELAY {
+                delay = MIN_RESTART_DELAY;
             }
-        })?;
+            error!(
+                "thread '{}' failed, restarting it in {} seconds: {}",
+                name,
+                delay.as_secs(),
+                failure
+            );
+            metrics
+                .daemon_thread_restarts
+                .with_label_values(&[name])
+                .inc();
+
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+        }
+    })?;
+
     Ok(())
 }
+
+pub(super) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use std::sync::mpsc;
+
+    #[test]
+    fn failed_threads_are_restarted() {
+        wrapper(|env| {
+            let metrics = env.metrics();
+            let (sender, receiver) = mpsc::channel();
+            let mut runs = 0;
+            supervise("test thread", metrics.clone(), move || {
+                runs += 1;
+                sender.send(runs).unwrap();
+                match runs {
+                    1 => panic!("first run"),
+                    2 => Err(failure::err_msg("second run")),
+                    _ => Ok(()),
+                }
+            })?;
+
+            let timeout = Duration::from_secs(10);
+            for expected in 1..=3 {
+                assert_eq!(receiver.recv_timeout(timeout)?, expected);
+            }
+            // the sender is dropped once the thread stops
+            assert!(receiver.recv_timeout(timeout).is_err());
+            assert_eq!(
+                metrics
+                    .daemon_thread_restarts
+                    .with_label_values(&["test thread"])
+                    .get(),
+                2
+            );
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,309 @@
+//! Daily dumps of the public data of docs.rs
+//!
+//! When `DOCSRS_DATASET_EXPORTS` is set, a daily job dumps the releases, builds, documentation
+//! coverage and dependencies to gzipped CSV files in the `exports/<date>/` prefix of the storage,
+//! and records them in `exports/manifest.json`. Only the `DOCSRS_DATASET_EXPORTS_KEPT` most recent
+//! dumps are kept. The manifest and the files are served under `/api/v1/exports`, for researchers
+//! and anyone analyzing the ecosystem.
+
+use crate::error::Result;
+use crate::storage::Blob;
+use crate::Storage;
+use chrono::{NaiveDate, Utc};
+use flate2::{write::GzEncoder, Compression};
+use log::info;
+use postgres::{fallible_iterator::FallibleIterator, types::ToSql, Client};
+use serde::{Deserialize, Serialize};
+
+const MANIFEST_PATH: &str = "exports/manifest.json";
+
+struct Table {
+    name: &'static str,
+    columns: &'static [&'static str],
+    /// Selects the columns, all of them cast to `TEXT`
+    query: &'static str,
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "releases",
+        columns: &[
+            "id",
+            "name",
+            "version",
+            "release_time",
+            "yanked",
+            "is_library",
+            "build_status",
+            "rustdoc_status",
+            "license",
+            "repository_url",
+            "default_target",
+            "rust_version",
+        ],
+        query: "SELECT releases.id::TEXT, crates.name, releases.version,
+                       releases.release_time::TEXT, releases.yanked::TEXT,
+                       releases.is_library::TEXT, releases.build_status::TEXT,
+                       releases.rustdoc_status::TEXT, releases.license,
+                       releases.repository_url, releases.default_target, releases.rust_version
+                FROM releases
+                INNER JOIN crates ON crates.id = releases.crate_id
+                ORDER BY releases.id",
+    },
+    Table {
+        name: "builds",
+        columns: &[
+            "id",
+            "release_id",
+            "rustc_version",
+            "docsrs_version",
+            "build_status",
+            "build_time",
+            "failure_category",
+        ],
+        query: "SELECT id::TEXT, rid::TEXT, rustc_version, docsrs_version, build_status::TEXT,
+                       build_time::TEXT, failure_category
+                FROM builds
+                ORDER BY id",
+    },
+    Table {
+        name: "doc_coverage",
+        columns: &[
+            "release_id",
+            "total_items",
+            "documented_items",
+            "total_items_needing_examples",
+            "items_with_examples",
+        ],
+        query: "SELECT release_id::TEXT, total_items::TEXT, documented_items::TEXT,
+                       total_items_needing_examples::TEXT, items_with_examples::TEXT
+                FROM doc_coverage
+                ORDER BY release_id",
+    },
+    Table {
+        name: "dependencies",
+        columns: &["release_id", "name", "requirement", "kind"],
+        // every dependency of a release is stored as `[name, requirement, kind]`
+        query: "SELECT releases.id::TEXT, dependency->>0, dependency->>1, dependency->>2
+                FROM releases, json_array_elements(releases.dependencies) AS dependency
+                WHERE json_typeof(releases.dependencies) = 'array'
+                ORDER BY releases.id",
+    },
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The most recent first
+    pub dumps: Vec<Dump>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    pub date: NaiveDate,
+    pub files: Vec<DumpFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpFile {
+    /// The name of the file in the directory of the dump, like `releases.csv.gz`
+    pub name: String,
+    pub rows: u64,
+    /// The size of the compressed file in bytes
+    pub size: u64,
+}
+
+impl Manifest {
+    /// Returns `None` before the first export
+    pub(crate) fn load(storage: &Storage, max_size: usize) -> Result<Option<Self>> {
+        if !storage.exists(MANIFEST_PATH)? {
+            return Ok(None);
+        }
+        let blob = storage.get(MANIFEST_PATH, max_size)?;
+        Ok(Some(serde_json::from_slice(&blob.content)?))
+    }
+
+    fn store(&self, storage: &Storage) -> Result<()> {
+        storage.store_one(MANIFEST_PATH, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, date: NaiveDate, name: &str) -> bool {
+        self.dumps
+            .iter()
+            .filter(|dump| dump.date == date)
+            .flat_map(|dump| &dump.files)
+            .any(|file| file.name == name)
+    }
+}
+
+pub(crate) fn storage_path(date: NaiveDate, name: &str) -> String {
+    format!("exports/{}/{}", date, name)
+}
+
+/// Dumps the tables for today, replacing the dump of the same day if it exists, and deletes the
+/// dumps beyond the `keep` most recent ones
+pub fn export_datasets(conn: &mut Client, storage: &Storage, keep: usize) -> Result<Dump> {
+    let date = Utc::now().date().naive_utc();
+    let mut dump = Dump {
+        date,
+        files: Vec::with_capacity(TABLES.len()),
+    };
+    for table in TABLES {
+        let (content, rows) = export_table(conn, table)?;
+        let name = format!("{}.csv.gz", table.name);
+        dump.files.push(DumpFile {
+            name: name.clone(),
+            rows,
+            size: content.len() as u64,
+        });
+        // the files are already compressed, and downloaded as they are
+        storage.store_blobs(vec![Blob {
+            path: storage_path(date, &name),
+            mime: "application/gzip".into(),
+            date_updated: Utc::now(),
+            content,
+            compression: None,
+            content_hash: None,
+        }])?;
+    }
+
+    let mut manifest = Manifest::load(storage, usize::MAX)?.unwrap_or_default();
+    manifest.dumps.retain(|existing| existing.date != date);
+    manifest.dumps.push(dump.clone());
+    manifest
+        .dumps
+        .sort_by_key(|dump| std::cmp::Reverse(dump.date));
+    let expired = if manifest.dumps.len() > keep {
+        manifest.dumps.split_off(keep)
+    } else {
+        Vec::new()
+    };
+    // the expired dumps are only deleted once they're out of the manifest
+    manifest.store(storage)?;
+    for dump in expired {
+        storage.delete_prefix(&format!("exports/{}/", dump.date))?;
+    }
+
+    info!("exported the datasets of {}", date);
+    Ok(dump)
+}
+
+/// Writes the rows of a table as gzipped CSV, returns the file and the number of rows. The rows
+/// are compressed as they're received, only the compressed file is kept in memory.
+fn export_table(conn: &mut Client, table: &Table) -> Result<(Vec<u8>, u64)> {
+    let mut writer = csv::Writer::from_writer(GzEncoder::new(Vec::new(), Compression::default()));
+    writer.write_record(table.columns)?;
+    let mut rows = 0;
+    let mut iter = conn.query_raw(table.query, std::iter::empty::<&dyn ToSql>())?;
+    while let Some(row) = iter.next()? {
+        writer.write_record(
+            (0..row.len()).map(|idx| row.get::<_, Option<String>>(idx).unwrap_or_default()),
+        )?;
+        rows += 1;
+    }
+    let encoder = writer
+        .into_inner()
+        .map_err(|err| failure::format_err!("failed to write the CSV: {}", err))?;
+    Ok((encoder.finish()?, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn read_csv(storage: &Storage, date: NaiveDate, name: &str) -> String {
+        let blob = storage.get(&storage_path(date, name), usize::MAX).unwrap();
+        let mut content = String::new();
+        GzDecoder::new(&blob.content[..])
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn export() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[("bar", "^1.0", "normal")])
+                .create()?;
+
+            let storage = env.storage();
+            assert_eq!(Manifest::load(&storage, usize::MAX)?, None);
+
+            let dump = export_datasets(&mut env.db().conn(), &storage, 7)?;
+            let names: Vec<_> = dump.files.iter().map(|file| file.name.as_str()).collect();
+            assert_eq!(
+                names,
+                vec![
+                    "releases.csv.gz",
+                    "builds.csv.gz",
+                    "doc_coverage.csv.gz",
+                    "dependencies.csv.gz"
+                ]
+            );
+            assert_eq!(
+                Manifest::load(&storage, usize::MAX)?,
+                Some(Manifest {
+                    dumps: vec![dump.clone()]
+                })
+            );
+
+            let releases = read_csv(&storage, dump.date, "releases.csv.gz");
+            let mut lines = releases.lines();
+            assert_eq!(
+                lines.next(),
+                Some("id,name,version,release_time,yanked,is_library,build_status,rustdoc_status,license,repository_url,default_target,rust_version")
+            );
+            assert!(lines.next().unwrap().contains(",foo,0.1.0,"));
+            assert_eq!(lines.next(), None);
+
+            let dependencies = read_csv(&storage, dump.date, "dependencies.csv.gz");
+            assert!(
+                dependencies.contains(",bar,^1.0,normal"),
+                "{}",
+                dependencies
+            );
+
+            // the dump of the same day is replaced
+            export_datasets(&mut env.db().conn(), &storage, 7)?;
+            assert_eq!(
+                Manifest::load(&storage, usize::MAX)?.unwrap().dumps.len(),
+                1
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn expired_dumps_are_deleted() {
+        wrapper(|env| {
+            let storage = env.storage();
+            let old = NaiveDate::from_ymd(2021, 1, 1);
+            storage.store_one(storage_path(old, "releases.csv.gz"), Vec::new())?;
+            Manifest {
+                dumps: vec![Dump {
+                    date: old,
+                    files: vec![DumpFile {
+                        name: "releases.csv.gz".into(),
+                        rows: 0,
+                        size: 0,
+                    }],
+                }],
+            }
+            .store(&storage)?;
+
+            let dump = export_datasets(&mut env.db().conn(), &storage, 1)?;
+            let manifest = Manifest::load(&storage, usize::MAX)?.unwrap();
+            assert_eq!(manifest.dumps, vec![dump]);
+            assert!(!storage.exists(&storage_path(old, "releases.csv.gz"))?);
+
+            Ok(())
+        });
+    }
+}
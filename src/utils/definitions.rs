@@ -0,0 +1,175 @@
+//! Locations of the items defined by a crate, used to link the source browser
+//!
+//! During the build of the default target rustdoc is also run with `--output-format json`, which
+//! records the span of every item. Only the name, file and line of the items are kept, and stored
+//! next to the sources so that the source browser can link the names to their definitions.
+
+use crate::error::Result;
+use crate::Storage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Kinds of items that don't define a name that can be linked to
+const IGNORED_KINDS: &[&str] = &[
+    "import",
+    "impl",
+    "struct_field",
+    "extern_crate",
+    "primitive",
+    "keyword",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Definition {
+    /// Path of the file relative to the root of the crate
+    pub(crate) file: String,
+    pub(crate) line: u32,
+    pub(crate) kind: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Definitions(BTreeMap<String, Vec<Definition>>);
+
+impl Definitions {
+    /// Extracts the items of the local crate from the output of `rustdoc --output-format json`
+    pub(crate) fn from_rustdoc_json(json: &[u8]) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Crate {
+            index: BTreeMap<String, Item>,
+        }
+        #[derive(Deserialize)]
+        struct Item {
+            crate_id: u32,
+            name: Option<String>,
+            span: Option<Span>,
+            kind: String,
+        }
+        #[derive(Deserialize)]
+        struct Span {
+            filename: String,
+            begin: (u32, u32),
+        }
+
+        let krate: Crate = serde_json::from_slice(json)?;
+        let mut definitions = Definitions::default();
+        for (_, item) in krate.index {
+            if item.crate_id != 0 || IGNORED_KINDS.contains(&item.kind.as_str()) {
+                continue;
+            }
+            let (name, span) = match (item.name, item.span) {
+                (Some(name), Some(span)) => (name, span),
+                _ => continue,
+            };
+            // files outside of the crate, like the ones generated by build scripts, can't be
+            // linked to, and the other paths are used in links as-is
+            let file = span.filename.trim_start_matches("./");
+            if file.starts_with('/')
+                || !file
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+            {
+                continue;
+            }
+            definitions.add(&name, file, span.begin.0, &item.kind);
+        }
+        Ok(definitions)
+    }
+
+    pub(crate) fn add(&mut self, name: &str, file: &str, line: u32, kind: &str) {
+        let definitions = self.0.entry(name.into()).or_default();
+        let definition = Definition {
+            file: file.into(),
+            line,
+            kind: kind.into(),
+        };
+        if !definitions.contains(&definition) {
+            definitions.push(definition);
+        }
+    }
+
+    /// Returns the definition of an item, unless multiple items share the same name
+    pub(crate) fn get(&self, name: &str) -> Option<&Definition> {
+        match self.0.get(name)?.as_slice() {
+            [definition] => Some(definition),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn storage_path(name: &str, version: &str) -> String {
+        format!("definitions/{}/{}/definitions.json", name, version)
+    }
+
+    pub(crate) fn store(&self, storage: &Storage, name: &str, version: &str) -> Result<()> {
+        storage.store_one(Self::storage_path(name, version), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Returns `None` if the build of the release didn't record its definitions
+    pub(crate) fn load(
+        storage: &Storage,
+        name: &str,
+        version: &str,
+        max_size: usize,
+    ) -> Result<Option<Self>> {
+        let path = Self::storage_path(name, version);
+        if !storage.exists(&path)? {
+            return Ok(None);
+        }
+        let blob = storage.get(&path, max_size)?;
+        Ok(Some(serde_json::from_slice(&blob.content)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_rustdoc_json() {
+        let item = |crate_id, name: Option<&str>, kind, filename: &str, line| {
+            json!({
+                "crate_id": crate_id,
+                "name": name,
+                "kind": kind,
+                "span": { "filename": filename, "begin": [line, 0], "end": [line + 1, 0] },
+                "visibility": "public",
+                "inner": {},
+            })
+        };
+        let krate = json!({
+            "root": "0:0",
+            "format_version": 6,
+            "index": {
+                "0:0": item(0, Some("foo"), "module", "src/lib.rs", 1),
+                "0:1": item(0, Some("Foo"), "struct", "src/lib.rs", 3),
+                "0:2": item(0, Some("new"), "method", "src/lib.rs", 6),
+                "0:3": item(0, Some("new"), "method", "src/bar.rs", 2),
+                "0:4": item(0, None, "impl", "src/lib.rs", 5),
+                "0:5": item(0, Some("x"), "struct_field", "src/lib.rs", 4),
+                "0:6": item(0, Some("Generated"), "struct", "/target/out/gen.rs", 1),
+                "1:0": item(1, Some("Vec"), "struct", "/rustc/alloc/src/vec.rs", 300),
+            },
+        });
+
+        let definitions = Definitions::from_rustdoc_json(krate.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            definitions.get("Foo"),
+            Some(&Definition {
+                file: "src/lib.rs".into(),
+                line: 3,
+                kind: "struct".into(),
+            })
+        );
+        assert_eq!(definitions.get("foo").unwrap().line, 1);
+        // ambiguous names are not linked
+        assert!(definitions.get("new").is_none());
+        for name in &["x", "Generated", "Vec"] {
+            assert!(definitions.get(name).is_none());
+        }
+    }
+}
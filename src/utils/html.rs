@@ -1,115 +1,661 @@
+//! Rewriting of the HTML generated by rustdoc
+//!
+//! The changes are split in passes registered on a [`HtmlRewriter`]. Every pass selects the
+//! elements it modifies with CSS selectors, and all of them are applied while the page is parsed
+//! once by `lol_html`. The time spent in each pass is recorded in the `html_rewrite_pass_times`
+//! metric.
+
+use crate::db::advisories::{self, Advisory};
 use crate::web::page::TemplateData;
+use crate::Metrics;
 use lol_html::errors::RewritingError;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{ElementContentHandlers, MemorySettings, Selector, Settings};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
 use tera::Context;
 
-/// Rewrite a rustdoc page to have the docs.rs topbar
-///
-/// Given a rustdoc HTML page and a context to serialize it with,
-/// render the `rustdoc/` templates with the `html`.
-/// The output is an HTML page which has not yet been UTF-8 validated.
-/// In practice, the output should always be valid UTF-8.
-pub(crate) fn rewrite_lol(
-    html: &[u8],
+/// Modifies an element matched by the selector of a pass
+pub(crate) type ElementHandler<'h> =
+    Box<dyn FnMut(&mut Element) -> Result<(), Box<dyn Error>> + 'h>;
+
+/// A transformation of the pages applied by a [`HtmlRewriter`]
+pub(crate) trait RewritePass {
+    /// The name of the pass, used as the label of its metric
+    fn name(&self) -> &'static str;
+
+    /// The CSS selectors of the elements modified by the pass, with the handler of each one
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)>;
+}
+
+/// Applies a list of passes to HTML pages, in the order they were registered
+pub(crate) struct HtmlRewriter<'p> {
+    passes: Vec<Box<dyn RewritePass + 'p>>,
     max_allowed_memory_usage: usize,
-    ctx: Context,
-    templates: &TemplateData,
-) -> Result<Vec<u8>, RewritingError> {
-    use lol_html::html_content::{ContentType, Element};
-    use lol_html::{ElementContentHandlers, HtmlRewriter, MemorySettings, Settings};
-
-    let templates = templates.templates.load();
-    let tera_head = templates.render("rustdoc/head.html", &ctx).unwrap();
-    let tera_vendored_css = templates.render("rustdoc/vendored.html", &ctx).unwrap();
-    let tera_body = templates.render("rustdoc/body.html", &ctx).unwrap();
-    let tera_rustdoc_topbar = templates.render("rustdoc/topbar.html", &ctx).unwrap();
-
-    // Append `style.css` stylesheet after all head elements.
-    let head_handler = |head: &mut Element| {
-        head.append(&tera_head, ContentType::Html);
-
-        Ok(())
-    };
-
-    // Before: <body> ... rustdoc content ... </body>
-    // After:
-    // ```html
-    // <div id="rustdoc_body_wrapper" class="{{ rustdoc_body_class }}" tabindex="-1">
-    //      ... rustdoc content ...
-    // </div>
-    // ```
-    let body_handler = |rustdoc_body_class: &mut Element| {
-        // Add the `rustdoc` classes to the html body
-        let mut tmp;
-        let klass = if let Some(classes) = rustdoc_body_class.get_attribute("class") {
-            tmp = classes;
-            tmp.push_str(" container-rustdoc");
-            &tmp
-        } else {
-            "container-rustdoc"
-        };
-        rustdoc_body_class.set_attribute("class", klass)?;
-        rustdoc_body_class.set_attribute("id", "rustdoc_body_wrapper")?;
-        rustdoc_body_class.set_attribute("tabindex", "-1")?;
-        // Change the `body` to a `div`
-        rustdoc_body_class.set_tag_name("div")?;
-        // Prepend the tera content
-        rustdoc_body_class.prepend(&tera_body, ContentType::Html);
-        // Wrap the tranformed body and topbar into a <body> element
-        rustdoc_body_class.before(r#"<body class="rustdoc-page">"#, ContentType::Html);
-        // Insert the topbar outside of the rustdoc div
-        rustdoc_body_class.before(&tera_rustdoc_topbar, ContentType::Html);
-        // Finalize body with </body>
-        rustdoc_body_class.after("</body>", ContentType::Html);
-
-        Ok(())
-    };
-
-    // Append `vendored.css` before `rustdoc.css`, so that the duplicate copy of
-    // `normalize.css` will be overridden by the later version.
-    let first_stylesheet_handler = |head: &mut Element| {
-        head.before(&tera_vendored_css, ContentType::Html);
-
-        Ok(())
-    };
-
-    let (head_selector, body_selector, first_stylesheet_selector) = (
-        "head".parse().unwrap(),
-        "body".parse().unwrap(),
-        "link[type='text/css'][href*='rustdoc']".parse().unwrap(),
-    );
-    let element_content_handlers = vec![
-        (
-            &head_selector,
-            ElementContentHandlers::default().element(head_handler),
-        ),
-        (
-            &body_selector,
-            ElementContentHandlers::default().element(body_handler),
-        ),
-        (
-            &first_stylesheet_selector,
-            ElementContentHandlers::default().element(first_stylesheet_handler),
-        ),
-    ];
-    let settings = Settings {
-        element_content_handlers,
-        memory_settings: MemorySettings {
+}
+
+impl<'p> HtmlRewriter<'p> {
+    pub(crate) fn new(max_allowed_memory_usage: usize) -> Self {
+        Self {
+            passes: Vec::new(),
             max_allowed_memory_usage,
-            ..MemorySettings::default()
-        },
-        ..Settings::default()
-    };
-
-    // The input and output are always strings, we just use `&[u8]` so we only have to validate once.
-    let mut buffer = Vec::new();
-    // TODO: Make the rewriter persistent?
-    let mut writer = HtmlRewriter::try_new(settings, |bytes: &[u8]| {
-        buffer.extend_from_slice(bytes);
-    })
-    .expect("utf8 is a valid encoding");
-
-    writer.write(html)?;
-    writer.end()?;
-
-    Ok(buffer)
+        }
+    }
+
+    pub(crate) fn pass(mut self, pass: impl RewritePass + 'p) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Rewrites a page with all the passes. The output is an HTML page which has not yet been
+    /// UTF-8 validated, in practice it should always be valid UTF-8.
+    pub(crate) fn rewrite(
+        &self,
+        html: &[u8],
+        metrics: &Metrics,
+    ) -> Result<Vec<u8>, RewritingError> {
+        let durations = RefCell::new(vec![Duration::default(); self.passes.len()]);
+
+        let mut handlers = Vec::new();
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (selector, mut handler) in pass.handlers() {
+                let selector: Selector = selector.parse().unwrap_or_else(|err| {
+                    panic!(
+                        "invalid selector {:?} in {}: {}",
+                        selector,
+                        pass.name(),
+                        err
+                    )
+                });
+                let durations = &durations;
+                let timed_handler = move |element: &mut Element| {
+                    let start = Instant::now();
+                    let result = handler(element);
+                    durations.borrow_mut()[index] += start.elapsed();
+                    result
+                };
+                handlers.push((selector, timed_handler));
+            }
+        }
+
+        let settings = Settings {
+            element_content_handlers: handlers
+                .iter_mut()
+                .map(|(selector, handler)| {
+                    (
+                        &*selector,
+                        ElementContentHandlers::default().element(handler),
+                    )
+                })
+                .collect(),
+            memory_settings: MemorySettings {
+                max_allowed_memory_usage: self.max_allowed_memory_usage,
+                ..MemorySettings::default()
+            },
+            ..Settings::default()
+        };
+
+        // The input and output are always strings, we just use `&[u8]` so we only have to validate once.
+        let mut buffer = Vec::new();
+        // TODO: Make the rewriter persistent?
+        let mut writer = lol_html::HtmlRewriter::try_new(settings, |bytes: &[u8]| {
+            buffer.extend_from_slice(bytes);
+        })
+        .expect("utf8 is a valid encoding");
+
+        writer.write(html)?;
+        writer.end()?;
+
+        for (pass, duration) in self.passes.iter().zip(durations.borrow().iter()) {
+            metrics
+                .html_rewrite_pass_times
+                .with_label_values(&[pass.name()])
+                .observe(duration.as_secs_f64());
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// Wraps a rustdoc page in the docs.rs layout: the `rustdoc/` templates are rendered with the
+/// context of the page and inserted around the content generated by rustdoc
+pub(crate) struct DocsRsLayout {
+    head: String,
+    vendored_css: String,
+    body: String,
+    topbar: String,
+}
+
+impl DocsRsLayout {
+    pub(crate) fn render(templates: &TemplateData, ctx: &Context) -> Result<Self, tera::Error> {
+        let templates = templates.templates.load();
+        Ok(Self {
+            head: templates.render("rustdoc/head.html", ctx)?,
+            vendored_css: templates.render("rustdoc/vendored.html", ctx)?,
+            body: templates.render("rustdoc/body.html", ctx)?,
+            topbar: templates.render("rustdoc/topbar.html", ctx)?,
+        })
+    }
+}
+
+impl RewritePass for DocsRsLayout {
+    fn name(&self) -> &'static str {
+        "layout"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        // Append `style.css` stylesheet after all head elements.
+        let head_handler = move |head: &mut Element| {
+            head.append(&self.head, ContentType::Html);
+
+            Ok(())
+        };
+
+        // Before: <body> ... rustdoc content ... </body>
+        // After:
+        // ```html
+        // <div id="rustdoc_body_wrapper" class="{{ rustdoc_body_class }}" tabindex="-1">
+        //      ... rustdoc content ...
+        // </div>
+        // ```
+        let body_handler = move |rustdoc_body_class: &mut Element| {
+            // Add the `rustdoc` classes to the html body
+            let mut tmp;
+            let klass = if let Some(classes) = rustdoc_body_class.get_attribute("class") {
+                tmp = classes;
+                tmp.push_str(" container-rustdoc");
+                &tmp
+            } else {
+                "container-rustdoc"
+            };
+            rustdoc_body_class.set_attribute("class", klass)?;
+            rustdoc_body_class.set_attribute("id", "rustdoc_body_wrapper")?;
+            rustdoc_body_class.set_attribute("tabindex", "-1")?;
+            // Change the `body` to a `div`
+            rustdoc_body_class.set_tag_name("div")?;
+            // Prepend the tera content
+            rustdoc_body_class.prepend(&self.body, ContentType::Html);
+            // Wrap the tranformed body and topbar into a <body> element
+            rustdoc_body_class.before(r#"<body class="rustdoc-page">"#, ContentType::Html);
+            // Insert the topbar outside of the rustdoc div
+            rustdoc_body_class.before(&self.topbar, ContentType::Html);
+            // Finalize body with </body>
+            rustdoc_body_class.after("</body>", ContentType::Html);
+
+            Ok(())
+        };
+
+        // Append `vendored.css` before `rustdoc.css`, so that the duplicate copy of
+        // `normalize.css` will be overridden by the later version.
+        let first_stylesheet_handler = move |head: &mut Element| {
+            head.before(&self.vendored_css, ContentType::Html);
+
+            Ok(())
+        };
+
+        vec![
+            ("head", Box::new(head_handler)),
+            ("body", Box::new(body_handler)),
+            (
+                "link[type='text/css'][href*='rustdoc']",
+                Box::new(first_stylesheet_handler),
+            ),
+        ]
+    }
+}
+
+/// Removes the docs.rs layout stored in the pages of old releases
+///
+/// The navigation bar and the docs.rs assets used to be saved with the documentation of some
+/// releases. They are outdated and would be shown next to the ones added by [`DocsRsLayout`],
+/// which injects the current navigation and version switcher in every page when it's served.
+pub(crate) struct StaleLayout;
+
+impl RewritePass for StaleLayout {
+    fn name(&self) -> &'static str {
+        "stale layout"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        let remove = || -> ElementHandler<'_> {
+            Box::new(|element: &mut Element| {
+                element.remove();
+                Ok(())
+            })
+        };
+
+        vec![
+            ("body > div.nav-container", remove()),
+            ("body > div.cratesfyi-package-container", remove()),
+            ("body > div.docsrs-package-container", remove()),
+            ("link[href^='/-/static/']", remove()),
+            ("script[src^='/-/static/']", remove()),
+            (
+                "#rustdoc_body_wrapper",
+                Box::new(|wrapper: &mut Element| {
+                    wrapper.remove_and_keep_content();
+                    Ok(())
+                }),
+            ),
+        ]
+    }
+}
+
+/// Points search engines to the same page in the latest release, and keeps them from indexing
+/// the page itself if requested
+pub(crate) struct CanonicalLink<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) noindex: bool,
+}
+
+impl RewritePass for CanonicalLink<'_> {
+    fn name(&self) -> &'static str {
+        "canonical"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        let head_handler = move |head: &mut Element| {
+            let url = self.url.replace('&', "&amp;").replace('"', "&quot;");
+            head.append(
+                &format!(r#"<link rel="canonical" href="{}" />"#, url),
+                ContentType::Html,
+            );
+            if self.noindex {
+                head.append(
+                    r#"<meta name="robots" content="noindex" />"#,
+                    ContentType::Html,
+                );
+            }
+
+            Ok(())
+        };
+
+        vec![("head", Box::new(head_handler))]
+    }
+}
+
+/// Adds the hashes of the shared rustdoc assets to the links to them, so that browsers refuse
+/// them if they don't match
+pub(crate) struct SubresourceIntegrity<'a> {
+    /// The `integrity` attributes by file name, see `utils::asset_integrity`
+    pub(crate) hashes: &'a HashMap<String, String>,
+}
+
+impl SubresourceIntegrity<'_> {
+    /// The shared assets are linked relatively to the page, e.g. `../main-1.55.0.js`, and they are
+    /// served from the root of the storage whatever the directory is
+    fn hash_of(&self, url: &str) -> Option<&str> {
+        if url.starts_with('/') || url.contains(':') || url.contains('?') {
+            return None;
+        }
+        let file_name = url.rsplit('/').next()?;
+        self.hashes.get(file_name).map(String::as_str)
+    }
+}
+
+impl RewritePass for SubresourceIntegrity<'_> {
+    fn name(&self) -> &'static str {
+        "subresource integrity"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        let add_integrity = |attribute: &'static str| -> ElementHandler<'_> {
+            Box::new(move |element: &mut Element| {
+                let hash = element
+                    .get_attribute(attribute)
+                    .and_then(|url| self.hash_of(&url).map(String::from));
+                if let Some(hash) = hash {
+                    element.set_attribute("integrity", &hash)?;
+                    element.set_attribute("crossorigin", "anonymous")?;
+                }
+                Ok(())
+            })
+        };
+
+        vec![
+            ("script[src]", add_integrity("src")),
+            ("link[rel='stylesheet'][href]", add_integrity("href")),
+        ]
+    }
+}
+
+/// Warns that the release is yanked with a banner under the navigation bar, linking to the
+/// latest release that isn't. The banner is dismissed by `index.js`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct YankedBanner {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// The latest version that isn't yanked and the URL of the same page in it, if there's one
+    pub(crate) latest: Option<(String, String)>,
+}
+
+impl RewritePass for YankedBanner {
+    fn name(&self) -> &'static str {
+        "yanked banner"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        let body_handler = move |body: &mut Element| {
+            let mut banner = format!(
+                r#"<div id="yanked-banner" class="warning-banner" role="alert"><span>{}-{} has been yanked."#,
+                tera::escape_html(&self.name),
+                tera::escape_html(&self.version),
+            );
+            if let Some((version, url)) = &self.latest {
+                banner.push_str(&format!(
+                    r#" Go to the latest version, <a href="{}">{}-{}</a>."#,
+                    tera::escape_html(url),
+                    tera::escape_html(&self.name),
+                    tera::escape_html(version),
+                ));
+            }
+            banner.push_str(DISMISS_BUTTON);
+            // after the navigation bar inserted by `DocsRsLayout`
+            body.before(&banner, ContentType::Html);
+
+            Ok(())
+        };
+
+        vec![("body", Box::new(body_handler))]
+    }
+}
+
+/// Closes the `.warning-banner` it's in, see `static/index.js`
+const DISMISS_BUTTON: &str =
+    r#"</span><button type="button" title="Dismiss" aria-label="Dismiss">&times;</button></div>"#;
+
+/// Warns about the security advisories affecting the release on every page of its documentation
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AdvisoriesBanner {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) advisories: Vec<Advisory>,
+}
+
+impl RewritePass for AdvisoriesBanner {
+    fn name(&self) -> &'static str {
+        "advisories banner"
+    }
+
+    fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+        let body_handler = move |body: &mut Element| {
+            let links: Vec<String> = self
+                .advisories
+                .iter()
+                .map(|advisory| {
+                    let kind = advisory
+                        .informational
+                        .as_ref()
+                        .map(|kind| format!(" ({})", tera::escape_html(kind)))
+                        .unwrap_or_default();
+                    format!(
+                        r#"<a href="{}">{}</a>{}: {}"#,
+                        tera::escape_html(&advisory.url),
+                        tera::escape_html(&advisory.id),
+                        kind,
+                        tera::escape_html(&advisory.title),
+                    )
+                })
+                .collect();
+            let banner = format!(
+                r#"<div id="advisories-banner" class="warning-banner" role="alert"><span>{}-{} is affected by {}: {}.{}"#,
+                tera::escape_html(&self.name),
+                tera::escape_html(&self.version),
+                advisories::label(&self.advisories),
+                links.join("; "),
+                DISMISS_BUTTON,
+            );
+            // after the banner of the yanked releases
+            body.before(&banner, ContentType::Html);
+
+            Ok(())
+        };
+
+        vec![("body", Box::new(body_handler))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fake_advisory;
+
+    const PAGE: &str = "<html><head><title>a</title></head><body><h2 id=\"x\">X</h2></body></html>";
+
+    fn rewrite(rewriter: HtmlRewriter, metrics: &Metrics) -> String {
+        String::from_utf8(rewriter.rewrite(PAGE.as_bytes(), metrics).unwrap()).unwrap()
+    }
+
+    /// Adds a link to the headings having an id
+    struct Permalinks;
+
+    impl RewritePass for Permalinks {
+        fn name(&self) -> &'static str {
+            "permalinks"
+        }
+
+        fn handlers(&self) -> Vec<(&'static str, ElementHandler<'_>)> {
+            vec![(
+                "h2[id]",
+                Box::new(|heading: &mut Element| {
+                    let id = heading.get_attribute("id").unwrap();
+                    heading.prepend(&format!("<a href=\"#{}\">§</a>", id), ContentType::Html);
+                    Ok(())
+                }),
+            )]
+        }
+    }
+
+    #[test]
+    fn passes_are_applied_in_order() {
+        let metrics = Metrics::new().unwrap();
+        let rewriter = HtmlRewriter::new(1024 * 1024)
+            .pass(CanonicalLink {
+                url: "https://docs.rs/foo/latest/foo/?a=1&b=\"",
+                noindex: true,
+            })
+            .pass(Permalinks);
+
+        assert_eq!(
+            rewrite(rewriter, &metrics),
+            "<html><head><title>a</title>\
+             <link rel=\"canonical\" href=\"https://docs.rs/foo/latest/foo/?a=1&amp;b=&quot;\" />\
+             <meta name=\"robots\" content=\"noindex\" /></head>\
+             <body><h2 id=\"x\"><a href=\"#x\">§</a>X</h2></body></html>"
+        );
+
+        // every pass has its own metric
+        for pass in &["canonical", "permalinks"] {
+            assert_eq!(
+                metrics
+                    .html_rewrite_pass_times
+                    .with_label_values(&[pass])
+                    .get_sample_count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn stale_layout() {
+        let metrics = Metrics::new().unwrap();
+        let page = "<html><head><link rel=\"stylesheet\" href=\"/-/static/style.css\">\
+                    <link rel=\"stylesheet\" href=\"../rustdoc.css\"></head>\
+                    <body><div class=\"nav-container\"><a href=\"/\">Docs.rs</a></div>\
+                    <div id=\"rustdoc_body_wrapper\" class=\"rustdoc\">\
+                    <div class=\"nav-container\">user content</div></div>\
+                    <script src=\"/-/static/menu.js\"></script></body></html>";
+        let html = HtmlRewriter::new(1024 * 1024)
+            .pass(StaleLayout)
+            .rewrite(page.as_bytes(), &metrics)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            "<html><head><link rel=\"stylesheet\" href=\"../rustdoc.css\"></head>\
+             <body><div class=\"nav-container\">user content</div></body></html>"
+        );
+    }
+
+    #[test]
+    fn subresource_integrity() {
+        let metrics = Metrics::new().unwrap();
+        let hashes = vec![
+            ("main-1.55.0.js".to_string(), "sha384-main".to_string()),
+            (
+                "rustdoc-1.55.0.css".to_string(),
+                "sha384-rustdoc".to_string(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let page = "<html><head>\
+                    <link rel=\"stylesheet\" type=\"text/css\" href=\"../rustdoc-1.55.0.css\">\
+                    <link rel=\"stylesheet\" href=\"/-/static/rustdoc-1.55.0.css\">\
+                    </head><body>\
+                    <script src=\"../../main-1.55.0.js\"></script>\
+                    <script src=\"../search-index-1.55.0.js\"></script>\
+                    </body></html>";
+        let html = HtmlRewriter::new(1024 * 1024)
+            .pass(SubresourceIntegrity { hashes: &hashes })
+            .rewrite(page.as_bytes(), &metrics)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            "<html><head>\
+             <link rel=\"stylesheet\" type=\"text/css\" href=\"../rustdoc-1.55.0.css\" \
+             integrity=\"sha384-rustdoc\" crossorigin=\"anonymous\">\
+             <link rel=\"stylesheet\" href=\"/-/static/rustdoc-1.55.0.css\">\
+             </head><body>\
+             <script src=\"../../main-1.55.0.js\" integrity=\"sha384-main\" \
+             crossorigin=\"anonymous\"></script>\
+             <script src=\"../search-index-1.55.0.js\"></script>\
+             </body></html>"
+        );
+    }
+
+    #[test]
+    fn yanked_banner() {
+        let metrics = Metrics::new().unwrap();
+        let banner = |latest| YankedBanner {
+            name: "foo".into(),
+            version: "0.1.0".into(),
+            latest,
+        };
+        let rewrite = |banner| {
+            String::from_utf8(
+                HtmlRewriter::new(1024 * 1024)
+                    .pass(banner)
+                    .rewrite(b"<html><body>docs</body></html>", &metrics)
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let dismiss =
+            r#"<button type="button" title="Dismiss" aria-label="Dismiss">&times;</button>"#;
+        assert_eq!(
+            rewrite(banner(Some((
+                "0.2.0".into(),
+                "/crate/foo/0.2.0/target-redirect/x86_64-unknown-linux-gnu/foo/?a=<b>".into()
+            )))),
+            format!(
+                "<html><div id=\"yanked-banner\" class=\"warning-banner\" role=\"alert\"><span>foo-0.1.0 has been yanked. \
+                 Go to the latest version, <a href=\"&#x2F;crate&#x2F;foo&#x2F;0.2.0&#x2F;\
+                 target-redirect&#x2F;x86_64-unknown-linux-gnu&#x2F;foo&#x2F;?a=&lt;b&gt;\">\
+                 foo-0.2.0</a>.</span>{}</div><body>docs</body></html>",
+                dismiss
+            )
+        );
+        assert_eq!(
+            rewrite(banner(None)),
+            format!(
+                "<html><div id=\"yanked-banner\" class=\"warning-banner\" role=\"alert\"><span>foo-0.1.0 has been yanked.\
+                 </span>{}</div><body>docs</body></html>",
+                dismiss
+            )
+        );
+    }
+
+    #[test]
+    fn without_passes() {
+        let metrics = Metrics::new().unwrap();
+        assert_eq!(rewrite(HtmlRewriter::new(1024 * 1024), &metrics), PAGE);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let metrics = Metrics::new().unwrap();
+        let page = format!("<div class=\"{}\"></div>", "a".repeat(4096));
+        let result = HtmlRewriter::new(1024)
+            .pass(Permalinks)
+            .rewrite(page.as_bytes(), &metrics);
+        assert!(matches!(
+            result,
+            Err(RewritingError::MemoryLimitExceeded(..))
+        ));
+    }
+
+    #[test]
+    fn advisories_banner() {
+        let metrics = Metrics::new().unwrap();
+        let advisory = |id: &str, title: &str| Advisory {
+            title: title.into(),
+            ..fake_advisory(id, "foo")
+        };
+        let rewritten = String::from_utf8(
+            HtmlRewriter::new(1024 * 1024)
+                .pass(AdvisoriesBanner {
+                    name: "foo".into(),
+                    version: "0.1.0".into(),
+                    advisories: vec![
+                        advisory("RUSTSEC-2021-0002", "Data race in <Foo>"),
+                        advisory("RUSTSEC-2021-0001", "Use after free"),
+                    ],
+                })
+                .rewrite(b"<html><body>docs</body></html>", &metrics)
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert!(rewritten.starts_with(
+            "<html><div id=\"advisories-banner\" class=\"warning-banner\" role=\"alert\">\
+             <span>foo-0.1.0 is affected by security advisories: <a href=\"https:&#x2F;&#x2F;\
+             rustsec.org&#x2F;advisories&#x2F;RUSTSEC-2021-0002.html\">RUSTSEC-2021-0002</a>: \
+             Data race in &lt;Foo&gt;; <a href="
+        ));
+        assert!(rewritten.ends_with(
+            "RUSTSEC-2021-0001</a>: Use after free.</span><button type=\"button\" \
+             title=\"Dismiss\" aria-label=\"Dismiss\">&times;</button></div>\
+             <body>docs</body></html>"
+        ));
+
+        // the unmaintained and unsound crates aren't vulnerabilities
+        let rewritten = String::from_utf8(
+            HtmlRewriter::new(1024 * 1024)
+                .pass(AdvisoriesBanner {
+                    name: "foo".into(),
+                    version: "0.1.0".into(),
+                    advisories: vec![Advisory {
+                        informational: Some("unmaintained".into()),
+                        ..advisory("RUSTSEC-2021-0003", "foo is unmaintained")
+                    }],
+                })
+                .rewrite(b"<html><body>docs</body></html>", &metrics)
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(
+            rewritten.contains(
+                "foo-0.1.0 is affected by an advisory: <a href=\"https:&#x2F;&#x2F;rustsec.org\
+                 &#x2F;advisories&#x2F;RUSTSEC-2021-0003.html\">RUSTSEC-2021-0003</a> \
+                 (unmaintained): foo is unmaintained."
+            ),
+            "{}",
+            rewritten
+        );
+    }
 }
@@ -0,0 +1,376 @@
+//! Index of the documented items of a crate, used to resolve item paths to their pages
+//!
+//! Built from the same `rustdoc --output-format json` output as `utils::definitions`, by walking
+//! the public modules from the root of the crate. The items re-exported from private modules are
+//! recorded where rustdoc inlines them, the other re-exports are recorded as aliases of the items
+//! they link to. The index is stored next to the definitions and used by `/api/v1/resolve` and to
+//! compare the items of two releases.
+
+use crate::error::Result;
+use crate::Storage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ItemIndex {
+    /// The kind of the documented items, by path
+    items: BTreeMap<String, String>,
+    /// The paths of the items re-exported where they aren't documented, to their documented path
+    reexports: BTreeMap<String, String>,
+}
+
+/// The changes of the items between two releases, each list sorted by path
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ItemDiff<'a> {
+    /// The path and kind of the items only documented by the newer release
+    pub(crate) added: Vec<(&'a str, &'a str)>,
+    /// The path and kind of the items only documented by the older release
+    pub(crate) removed: Vec<(&'a str, &'a str)>,
+    /// The path, old kind and new kind of the items whose kind changed, like a struct replaced by
+    /// an enum
+    pub(crate) changed: Vec<(&'a str, &'a str, &'a str)>,
+}
+
+impl ItemIndex {
+    /// Extracts the items documented at a public path from the output of
+    /// `rustdoc --output-format json`
+    pub(crate) fn from_rustdoc_json(json: &[u8]) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Crate {
+            root: String,
+            index: HashMap<String, Item>,
+        }
+        #[derive(Deserialize)]
+        struct Item {
+            crate_id: u32,
+            name: Option<String>,
+            visibility: serde_json::Value,
+            kind: String,
+            #[serde(default)]
+            inner: Inner,
+        }
+        #[derive(Deserialize, Default)]
+        struct Inner {
+            /// The items of a module
+            #[serde(default)]
+            items: Vec<String>,
+            /// The variants of an enum
+            #[serde(default)]
+            variants: Vec<String>,
+            /// The item re-exported by an import
+            id: Option<String>,
+            #[serde(default)]
+            glob: bool,
+        }
+
+        let krate: Crate = serde_json::from_slice(json)?;
+        let root = match krate.index.get(&krate.root) {
+            Some(root) => root,
+            None => return Ok(Self::default()),
+        };
+
+        let mut index = Self::default();
+        // the documented path of the items, by id
+        let mut documented = HashMap::new();
+        let mut imports = Vec::new();
+        let mut modules = vec![(root, root.name.clone().unwrap_or_default())];
+        while let Some((module, path)) = modules.pop() {
+            for id in &module.inner.items {
+                let item = match krate.index.get(id) {
+                    Some(item) if item.crate_id == 0 && item.visibility == "public" => item,
+                    _ => continue,
+                };
+                if item.kind == "import" {
+                    imports.push((item, path.clone()));
+                    continue;
+                }
+                let name = match &item.name {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let item_path = format!("{}::{}", path, name);
+                index.add(&item_path, &item.kind);
+                documented.insert(id.as_str(), item_path.clone());
+                match item.kind.as_str() {
+                    "module" => modules.push((item, item_path)),
+                    "enum" => {
+                        for variant in item.inner.variants.iter().filter_map(|id| {
+                            krate
+                                .index
+                                .get(id)
+                                .and_then(|variant| variant.name.as_ref())
+                        }) {
+                            index.add(&format!("{}::{}", item_path, variant), "variant");
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // the items that aren't public where they're defined are documented where they're
+        // re-exported, the other re-exports only link to them
+        for (import, path) in imports {
+            let target_id = match &import.inner.id {
+                Some(id) => id,
+                None => continue,
+            };
+            let target = match krate.index.get(target_id) {
+                Some(target) if target.crate_id == 0 => target,
+                _ => continue,
+            };
+            if import.inner.glob {
+                // only the items of the public modules are known, the others aren't documented
+                for (id, documented_path) in target
+                    .inner
+                    .items
+                    .iter()
+                    .filter_map(|id| Some((id, documented.get(id.as_str())?)))
+                {
+                    if let Some(name) = krate.index.get(id).and_then(|item| item.name.as_ref()) {
+                        index.add_reexport(&format!("{}::{}", path, name), documented_path);
+                    }
+                }
+                continue;
+            }
+            let name = match &import.name {
+                Some(name) => name,
+                None => continue,
+            };
+            let reexport_path = format!("{}::{}", path, name);
+            match documented.get(target_id.as_str()) {
+                Some(documented_path) => index.add_reexport(&reexport_path, documented_path),
+                None if target.kind != "module" => index.add(&reexport_path, &target.kind),
+                None => {}
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Records an item, unless its kind has no page of its own
+    pub(crate) fn add(&mut self, path: &str, kind: &str) {
+        if page_path(path, kind).is_some() {
+            self.items.insert(path.into(), kind.into());
+        }
+    }
+
+    /// Records that the item documented at `documented_path` is also public at `path`
+    pub(crate) fn add_reexport(&mut self, path: &str, documented_path: &str) {
+        if path != documented_path {
+            self.reexports.insert(path.into(), documented_path.into());
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Compares the items of this release to the ones of a `newer` release
+    pub(crate) fn diff<'a>(&'a self, newer: &'a ItemIndex) -> ItemDiff<'a> {
+        let mut diff = ItemDiff::default();
+        for (path, kind) in &self.items {
+            match newer.items.get(path) {
+                None => diff.removed.push((path, kind)),
+                Some(new_kind) if new_kind != kind => diff.changed.push((path, kind, new_kind)),
+                Some(_) => {}
+            }
+        }
+        for (path, kind) in &newer.items {
+            if !self.items.contains_key(path) {
+                diff.added.push((path, kind));
+            }
+        }
+        diff
+    }
+
+    /// The documented path and kind of the item a path refers to, either directly or through a
+    /// re-export
+    pub(crate) fn resolve<'a>(&'a self, path: &'a str) -> Option<(&'a str, &'a str)> {
+        let path = self.reexports.get(path).map_or(path, String::as_str);
+        self.items
+            .get_key_value(path)
+            .map(|(path, kind)| (path.as_str(), kind.as_str()))
+    }
+
+    fn storage_path(name: &str, version: &str) -> String {
+        format!("definitions/{}/{}/items.json", name, version)
+    }
+
+    pub(crate) fn store(&self, storage: &Storage, name: &str, version: &str) -> Result<()> {
+        storage.store_one(Self::storage_path(name, version), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Returns `None` if the build of the release didn't record its items
+    pub(crate) fn load(
+        storage: &Storage,
+        name: &str,
+        version: &str,
+        max_size: usize,
+    ) -> Result<Option<Self>> {
+        let path = Self::storage_path(name, version);
+        if !storage.exists(&path)? {
+            return Ok(None);
+        }
+        let blob = storage.get(&path, max_size)?;
+        Ok(Some(serde_json::from_slice(&blob.content)?))
+    }
+}
+
+/// The page documenting an item, relative to the documentation of the default target
+pub(crate) fn page_path(path: &str, kind: &str) -> Option<String> {
+    let segments: Vec<&str> = path.split("::").collect();
+    let (name, parents) = segments.split_last()?;
+    let prefix = match kind {
+        "module" => return Some(format!("{}/index.html", segments.join("/"))),
+        "variant" => {
+            let (enum_name, modules) = parents.split_last()?;
+            return Some(format!(
+                "{}/enum.{}.html#variant.{}",
+                modules.join("/"),
+                enum_name,
+                name
+            ));
+        }
+        "struct" | "enum" | "union" | "trait" | "constant" | "static" | "macro" => kind,
+        "function" => "fn",
+        "typedef" => "type",
+        "proc_attribute" => "attr",
+        "proc_derive" => "derive",
+        "trait_alias" => "traitalias",
+        "foreign_type" => "foreigntype",
+        _ => return None,
+    };
+    if parents.is_empty() {
+        return None;
+    }
+    Some(format!("{}/{}.{}.html", parents.join("/"), prefix, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_rustdoc_json() {
+        let item = |name: &str, kind, visibility, inner| {
+            json!({
+                "crate_id": 0,
+                "name": name,
+                "kind": kind,
+                "visibility": visibility,
+                "inner": inner,
+            })
+        };
+        let krate = json!({
+            "root": "0:0",
+            "format_version": 9,
+            "index": {
+                "0:0": item("foo", "module", "public", json!({ "is_crate": true, "items": ["0:1", "0:2", "0:5", "0:8", "0:9", "0:10"] })),
+                "0:1": item("ser", "module", "public", json!({ "is_crate": false, "items": ["0:3", "0:4"] })),
+                "0:2": item("imp", "module", "crate", json!({ "is_crate": false, "items": ["0:6"] })),
+                "0:3": item("Serialize", "trait", "public", json!({})),
+                "0:4": item("Error", "enum", "public", json!({ "variants": ["0:7"] })),
+                "0:5": item("Serialize", "import", "public", json!({ "source": "ser::Serialize", "name": "Serialize", "id": "0:3", "glob": false })),
+                "0:6": item("Inner", "struct", "public", json!({})),
+                "0:7": item("Custom", "variant", "default", json!({})),
+                "0:8": item("Inner", "import", "public", json!({ "source": "imp::Inner", "name": "Inner", "id": "0:6", "glob": false })),
+                "0:9": item("helper", "function", "crate", json!({})),
+                "0:10": item("prelude", "module", "public", json!({ "is_crate": false, "items": ["0:11"] })),
+                "0:11": item("ser", "import", "public", json!({ "source": "crate::ser", "name": "ser", "id": "0:1", "glob": true })),
+            },
+        });
+
+        let index = ItemIndex::from_rustdoc_json(krate.to_string().as_bytes()).unwrap();
+        let mut expected = ItemIndex::default();
+        for (path, kind) in &[
+            ("foo::ser", "module"),
+            ("foo::ser::Serialize", "trait"),
+            ("foo::ser::Error", "enum"),
+            ("foo::ser::Error::Custom", "variant"),
+            ("foo::Inner", "struct"),
+            ("foo::prelude", "module"),
+        ] {
+            expected.add(path, kind);
+        }
+        for (path, documented_path) in &[
+            ("foo::Serialize", "foo::ser::Serialize"),
+            ("foo::prelude::Serialize", "foo::ser::Serialize"),
+            ("foo::prelude::Error", "foo::ser::Error"),
+        ] {
+            expected.add_reexport(path, documented_path);
+        }
+        assert_eq!(index, expected);
+
+        assert_eq!(
+            index.resolve("foo::Serialize"),
+            Some(("foo::ser::Serialize", "trait"))
+        );
+        assert_eq!(
+            index.resolve("foo::prelude::Error"),
+            Some(("foo::ser::Error", "enum"))
+        );
+        assert_eq!(index.resolve("foo::Inner"), Some(("foo::Inner", "struct")));
+        assert_eq!(index.resolve("foo::helper"), None);
+        // the items are only found where they're public
+        assert_eq!(index.resolve("foo::Error"), None);
+        assert_eq!(index.resolve("foo::missing::Serialize"), None);
+    }
+
+    #[test]
+    fn diff() {
+        let index = |items: &[(&str, &str)]| {
+            let mut index = ItemIndex::default();
+            for (path, kind) in items {
+                index.add(path, kind);
+            }
+            index
+        };
+        let old = index(&[
+            ("foo::Error", "struct"),
+            ("foo::parse", "function"),
+            ("foo::ser", "module"),
+            ("foo::ser::to_string", "function"),
+        ]);
+        let new = index(&[
+            ("foo::Error", "enum"),
+            ("foo::Error::Io", "variant"),
+            ("foo::de", "module"),
+            ("foo::parse", "function"),
+            ("foo::ser", "module"),
+        ]);
+
+        assert_eq!(
+            old.diff(&new),
+            ItemDiff {
+                added: vec![("foo::Error::Io", "variant"), ("foo::de", "module")],
+                removed: vec![("foo::ser::to_string", "function")],
+                changed: vec![("foo::Error", "struct", "enum")],
+            }
+        );
+        assert_eq!(new.diff(&new), ItemDiff::default());
+    }
+
+    #[test]
+    fn pages() {
+        assert_eq!(
+            page_path("foo::ser::Serialize", "trait").as_deref(),
+            Some("foo/ser/trait.Serialize.html")
+        );
+        assert_eq!(
+            page_path("foo::bar", "function").as_deref(),
+            Some("foo/fn.bar.html")
+        );
+        assert_eq!(
+            page_path("foo::ser", "module").as_deref(),
+            Some("foo/ser/index.html")
+        );
+        assert_eq!(
+            page_path("foo::Error::Custom", "variant").as_deref(),
+            Some("foo/enum.Error.html#variant.Custom")
+        );
+        assert_eq!(page_path("foo::Foo::new", "method"), None);
+    }
+}
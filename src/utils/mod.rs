@@ -3,22 +3,39 @@
 pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
 pub(crate) use self::copy::copy_dir_all;
 pub use self::daemon::start_daemon;
-pub(crate) use self::html::rewrite_lol;
+pub(crate) use self::html::{
+    AdvisoriesBanner, CanonicalLink, DocsRsLayout, HtmlRewriter, StaleLayout, SubresourceIntegrity,
+    YankedBanner,
+};
 pub use self::queue::{get_crate_priority, remove_crate_priority, set_crate_priority};
 pub use self::queue_builder::queue_builder;
+pub use self::rebuild::rebuild_all;
 pub(crate) use self::rustc_version::parse_rustc_version;
+pub use self::serve_local::serve_local;
 
 #[cfg(test)]
 pub(crate) use self::cargo_metadata::{Dependency, Target};
 
+pub mod advisories;
+pub(crate) mod asset_integrity;
 mod cargo_metadata;
-#[cfg(feature = "consistency_check")]
+pub(crate) mod citation;
 pub mod consistency;
 mod copy;
 pub(crate) mod daemon;
+pub mod dataset_export;
+pub(crate) mod definitions;
 mod html;
-mod pubsubhubbub;
+pub(crate) mod item_index;
+pub(crate) mod pubsubhubbub;
 mod queue;
 mod queue_builder;
+pub(crate) mod queue_events;
+pub(crate) mod rebuild;
+pub mod retention;
 mod rustc_version;
+pub(crate) mod rustdoc_json;
+pub(crate) mod scheduler;
+mod serve_local;
 pub(crate) mod sized_buffer;
+pub(crate) mod storage_stats;
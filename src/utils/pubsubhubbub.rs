@@ -1,27 +1,870 @@
-use reqwest::{
-    blocking::{Client, Response},
-    Result,
-};
-use std::collections::HashMap;
-
-fn ping_hub(url: &str) -> Result<Response> {
-    let mut params = HashMap::with_capacity(2);
-    params.insert("hub.mode", "publish");
-    params.insert("hub.url", "https://docs.rs/releases/feed");
-
-    let client = Client::new();
-    client.post(url).form(&params).send()
-}
-
-/// Ping the two predefined hubs. Return either the number of successfully
-/// pinged hubs, or the first error.
-pub fn ping_hubs() -> Result<usize> {
-    vec![
-        "https://pubsubhubbub.appspot.com",
-        "https://pubsubhubbub.superfeedr.com",
-    ]
-    .into_iter()
-    .map(ping_hub)
-    .collect::<Result<Vec<_>>>()
-    .map(|v| v.len())
+//! WebSub hub of the releases feed
+//!
+//! Feed readers can subscribe to `/releases/feed` through `/releases/feed/hub` instead of polling
+//! it. The web server only queues the requests, a job of the daemon verifies them by sending a
+//! challenge to the callback, and records the subscription once the subscriber echoed it. When
+//! releases are added, the same job sends the feed to every subscriber, and the failed deliveries
+//! are retried with an exponential backoff.
+//!
+//! The callbacks are requested by docs.rs, so only the callbacks resolving to public addresses are
+//! requested, without following redirects, and every host has a few subscriptions at most.
+//!
+//! The feed also announces external hubs, which are pinged by the builder after new releases.
+//!
+//! See the [WebSub specification](https://www.w3.org/TR/websub/).
+
+use crate::error::Result;
+use crate::{Config, Metrics};
+use chrono::Utc;
+use failure::Fail;
+use hmac::{Hmac, Mac, NewMac};
+use log::{debug, warn};
+use postgres::Client;
+use reqwest::header::{CONTENT_TYPE, LINK};
+use reqwest::StatusCode;
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Lease of the subscriptions which don't ask for one
+const DEFAULT_LEASE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+const MIN_LEASE: Duration = Duration::from_secs(60 * 60);
+const MAX_LEASE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Every callback is requested by docs.rs, so their number is limited
+const MAX_SUBSCRIPTIONS: i64 = 1000;
+/// Subscriptions and requests waiting to be verified of a single host
+const MAX_SUBSCRIPTIONS_PER_HOST: i64 = 10;
+/// Requests waiting to be verified by the daemon
+const MAX_PENDING_REQUESTS: i64 = 100;
+/// The subscription is removed once this many deliveries in a row failed, after about 8 hours
+const MAX_FAILED_DELIVERIES: i32 = 10;
+/// Hubs of other services announced by the feed, which need to be told when it changes
+const EXTERNAL_HUBS: &[&str] = &[
+    "https://pubsubhubbub.appspot.com",
+    "https://pubsubhubbub.superfeedr.com",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Subscribe,
+    Unsubscribe,
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Subscribe => "subscribe",
+            Mode::Unsubscribe => "unsubscribe",
+        }
+    }
+}
+
+/// The reasons a subscription request is rejected, all of them are errors of the subscriber
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub(crate) enum SubscriptionError {
+    #[fail(display = "invalid {}: {}", field, reason)]
+    Invalid { field: &'static str, reason: String },
+    #[fail(display = "the only topic of this hub is {}", _0)]
+    UnknownTopic(String),
+    #[fail(display = "the hub has too many subscribers")]
+    TooManySubscriptions,
+    #[fail(display = "too many subscriptions for {}", _0)]
+    TooManyHostSubscriptions(String),
+    #[fail(display = "the hub has too many requests to verify, try again later")]
+    TooManyPendingRequests,
+}
+
+/// A subscription request, as sent by the subscribers in a form
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SubscriptionRequest {
+    pub(crate) mode: Mode,
+    pub(crate) callback: Url,
+    pub(crate) topic: String,
+    pub(crate) lease: Duration,
+    pub(crate) secret: Option<String>,
+}
+
+impl SubscriptionRequest {
+    pub(crate) fn from_form(body: &[u8]) -> std::result::Result<Self, SubscriptionError> {
+        let invalid = |field, reason: &str| SubscriptionError::Invalid {
+            field,
+            reason: reason.to_owned(),
+        };
+
+        let (mut mode, mut callback, mut topic, mut lease, mut secret) =
+            (None, None, None, None, None);
+        for (key, value) in url::form_urlencoded::parse(body) {
+            match &*key {
+                "hub.mode" => mode = Some(value.into_owned()),
+                "hub.callback" => callback = Some(value.into_owned()),
+                "hub.topic" => topic = Some(value.into_owned()),
+                "hub.lease_seconds" => lease = Some(value.into_owned()),
+                "hub.secret" => secret = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        let mode = match mode.as_deref() {
+            Some("subscribe") => Mode::Subscribe,
+            Some("unsubscribe") => Mode::Unsubscribe,
+            Some(_) => return Err(invalid("hub.mode", "unknown mode")),
+            None => return Err(invalid("hub.mode", "missing")),
+        };
+        let callback: Url = callback
+            .ok_or_else(|| invalid("hub.callback", "missing"))?
+            .parse()
+            .map_err(|err: url::ParseError| invalid("hub.callback", &err.to_string()))?;
+        if callback.scheme() != "http" && callback.scheme() != "https" {
+            return Err(invalid("hub.callback", "only http and https are supported"));
+        }
+        // the domains are only resolved by the daemon, when requesting the callback
+        let public = match callback.host() {
+            Some(Host::Ipv4(ip)) => is_public(ip.into()),
+            Some(Host::Ipv6(ip)) => is_public(ip.into()),
+            Some(Host::Domain(domain)) => domain != "localhost" && !domain.ends_with(".localhost"),
+            None => false,
+        };
+        if !public {
+            return Err(invalid("hub.callback", "not a public address"));
+        }
+        let lease = match lease {
+            Some(lease) => Duration::from_secs(
+                lease
+                    .parse()
+                    .map_err(|_| invalid("hub.lease_seconds", "not a number"))?,
+            ),
+            None => DEFAULT_LEASE,
+        };
+        // the specification limits secrets to 200 bytes
+        if matches!(&secret, Some(secret) if secret.len() > 200) {
+            return Err(invalid("hub.secret", "longer than 200 bytes"));
+        }
+
+        Ok(Self {
+            mode,
+            callback,
+            topic: topic.ok_or_else(|| invalid("hub.topic", "missing"))?,
+            lease: lease.clamp(MIN_LEASE, MAX_LEASE),
+            secret,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Hub {
+    topic: Url,
+    url: Url,
+    client: reqwest::blocking::Client,
+    metrics: Arc<Metrics>,
+    /// Whether the callbacks can be on the local network, which is only the case in the tests
+    allow_private_callbacks: bool,
+}
+
+impl Hub {
+    pub(crate) fn new(config: &Config, metrics: Arc<Metrics>) -> Result<Self> {
+        Ok(Self {
+            topic: config.public_url.join("releases/feed")?,
+            url: config.public_url.join("releases/feed/hub")?,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+            metrics,
+            allow_private_callbacks: false,
+        })
+    }
+
+    /// Queues the request, which is verified later by `verify_requests`
+    pub(crate) fn handle(&self, conn: &mut Client, request: &SubscriptionRequest) -> Result<()> {
+        if request.topic != self.topic.as_str() {
+            return Err(SubscriptionError::UnknownTopic(self.topic.to_string()).into());
+        }
+        // the callbacks are http and https URLs, which always have a host
+        let host = request.callback.host_str().unwrap_or_default();
+        let callback = request.callback.as_str();
+
+        let mut transaction = conn.transaction()?;
+        if request.mode == Mode::Subscribe {
+            let subscriptions: i64 = transaction
+                .query_one("SELECT COUNT(*) FROM websub_subscriptions", &[])?
+                .get(0);
+            if subscriptions >= MAX_SUBSCRIPTIONS {
+                return Err(SubscriptionError::TooManySubscriptions.into());
+            }
+        }
+        let row = transaction.query_one(
+            "SELECT
+                (SELECT COUNT(*) FROM websub_verifications),
+                (SELECT COUNT(*) FROM websub_subscriptions WHERE host = $1 AND callback <> $2) +
+                (SELECT COUNT(*) FROM websub_verifications WHERE host = $1 AND callback <> $2)",
+            &[&host, &callback],
+        )?;
+        let (pending, host_subscriptions): (i64, i64) = (row.get(0), row.get(1));
+        if pending >= MAX_PENDING_REQUESTS {
+            return Err(SubscriptionError::TooManyPendingRequests.into());
+        }
+        if host_subscriptions >= MAX_SUBSCRIPTIONS_PER_HOST {
+            return Err(SubscriptionError::TooManyHostSubscriptions(host.to_owned()).into());
+        }
+
+        transaction.execute(
+            "INSERT INTO websub_verifications (callback, host, subscribe, secret, lease_seconds)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (callback) DO UPDATE
+                SET subscribe = $3, secret = $4, lease_seconds = $5",
+            &[
+                &callback,
+                &host,
+                &(request.mode == Mode::Subscribe),
+                &request.secret,
+                &(request.lease.as_secs() as i32),
+            ],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Confirms the queued requests with the subscribers, then records or removes their
+    /// subscriptions. Returns the number of confirmed requests.
+    pub(crate) fn verify_requests(&self, conn: &mut Client) -> Result<usize> {
+        let requests = conn.query(
+            "DELETE FROM websub_verifications
+             RETURNING callback, subscribe, secret, lease_seconds",
+            &[],
+        )?;
+
+        let mut confirmed = 0;
+        for row in requests {
+            let request = SubscriptionRequest {
+                mode: if row.get("subscribe") {
+                    Mode::Subscribe
+                } else {
+                    Mode::Unsubscribe
+                },
+                callback: row.get::<_, &str>("callback").parse()?,
+                topic: self.topic.to_string(),
+                lease: Duration::from_secs(row.get::<_, i32>("lease_seconds") as u64),
+                secret: row.get("secret"),
+            };
+            if self.confirm(&request) {
+                self.record(conn, &request)?;
+                confirmed += 1;
+            }
+        }
+
+        Ok(confirmed)
+    }
+
+    fn record(&self, conn: &mut Client, request: &SubscriptionRequest) -> Result<()> {
+        match request.mode {
+            Mode::Subscribe => {
+                let expires_at = Utc::now() + chrono::Duration::from_std(request.lease)?;
+                conn.execute(
+                    "INSERT INTO websub_subscriptions (callback, host, secret, expires_at)
+                     VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (callback) DO UPDATE
+                        SET secret = $3, expires_at = $4",
+                    &[
+                        &request.callback.as_str(),
+                        &request.callback.host_str().unwrap_or_default(),
+                        &request.secret,
+                        &expires_at,
+                    ],
+                )?;
+            }
+            Mode::Unsubscribe => {
+                conn.execute(
+                    "DELETE FROM websub_subscriptions WHERE callback = $1",
+                    &[&request.callback.as_str()],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the intent of the subscriber: the callback has to answer with the challenge
+    fn confirm(&self, request: &SubscriptionRequest) -> bool {
+        if !self.is_public(&request.callback) {
+            debug!("the callback {} isn't a public address", request.callback);
+            return false;
+        }
+
+        let mut challenge = [0; 16];
+        getrandom::getrandom(&mut challenge).expect("failed to generate the challenge");
+        let challenge: String = challenge.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut url = request.callback.clone();
+        url.query_pairs_mut()
+            .append_pair("hub.mode", request.mode.as_str())
+            .append_pair("hub.topic", self.topic.as_str())
+            .append_pair("hub.challenge", &challenge);
+        if request.mode == Mode::Subscribe {
+            url.query_pairs_mut()
+                .append_pair("hub.lease_seconds", &request.lease.as_secs().to_string());
+        }
+
+        match self
+            .client
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+        {
+            Ok(body) => body.trim() == challenge,
+            Err(err) => {
+                debug!("failed to confirm {}: {}", request.callback, err);
+                false
+            }
+        }
+    }
+
+    /// Whether all the addresses of the host of the callback are public. The host is resolved
+    /// again when requesting it, this only prevents the subscribers from naming a local service.
+    fn is_public(&self, callback: &Url) -> bool {
+        if self.allow_private_callbacks {
+            return true;
+        }
+        match callback.socket_addrs(|| None) {
+            Ok(addrs) => !addrs.is_empty() && addrs.iter().all(|addr| is_public(addr.ip())),
+            Err(_) => false,
+        }
+    }
+
+    /// Sends the feed to the subscribers waiting for it, rendering it only if there's one.
+    /// Returns the number of successful deliveries.
+    pub(crate) fn deliver(
+        &self,
+        conn: &mut Client,
+        feed: impl FnOnce(&mut Client) -> Result<String>,
+    ) -> Result<usize> {
+        conn.execute(
+            "DELETE FROM websub_subscriptions WHERE expires_at < NOW()",
+            &[],
+        )?;
+        let due = conn.query(
+            "SELECT callback, secret, failed_deliveries
+             FROM websub_subscriptions
+             WHERE next_delivery <= NOW()",
+            &[],
+        )?;
+        if due.is_empty() {
+            return Ok(0);
+        }
+
+        let feed = feed(conn)?;
+        let links = format!(r#"<{}>; rel="hub", <{}>; rel="self""#, self.url, self.topic);
+        let mut delivered = 0;
+        for row in due {
+            let callback: String = row.get("callback");
+            let secret: Option<String> = row.get("secret");
+            let failed_deliveries: i32 = row.get("failed_deliveries");
+
+            if !callback
+                .parse()
+                .is_ok_and(|callback| self.is_public(&callback))
+            {
+                warn!("the callback {} isn't a public address", callback);
+                self.delivery_failed(conn, &callback, failed_deliveries)?;
+                continue;
+            }
+
+            let mut request = self
+                .client
+                .post(&callback)
+                .header(CONTENT_TYPE, "application/atom+xml")
+                .header(LINK, &links);
+            if let Some(secret) = &secret {
+                request = request.header("X-Hub-Signature", signature(secret, feed.as_bytes()));
+            }
+
+            match request.body(feed.clone()).send() {
+                Ok(response) if response.status().is_success() => {
+                    conn.execute(
+                        "UPDATE websub_subscriptions
+                         SET next_delivery = NULL, failed_deliveries = 0
+                         WHERE callback = $1",
+                        &[&callback],
+                    )?;
+                    self.metrics
+                        .websub_deliveries
+                        .with_label_values(&["sent"])
+                        .inc();
+                    delivered += 1;
+                    continue;
+                }
+                // the subscriber doesn't want the feed anymore
+                Ok(response) if response.status() == StatusCode::GONE => {
+                    conn.execute(
+                        "DELETE FROM websub_subscriptions WHERE callback = $1",
+                        &[&callback],
+                    )?;
+                    continue;
+                }
+                Ok(response) => warn!(
+                    "failed to deliver the feed to {}: status {}",
+                    callback,
+                    response.status()
+                ),
+                Err(err) => warn!("failed to deliver the feed to {}: {}", callback, err),
+            }
+            self.delivery_failed(conn, &callback, failed_deliveries)?;
+        }
+
+        Ok(delivered)
+    }
+
+    /// Retries the delivery later, or removes the subscription after too many failures
+    fn delivery_failed(
+        &self,
+        conn: &mut Client,
+        callback: &str,
+        failed_deliveries: i32,
+    ) -> Result<()> {
+        self.metrics
+            .websub_deliveries
+            .with_label_values(&["failed"])
+            .inc();
+        if failed_deliveries + 1 >= MAX_FAILED_DELIVERIES {
+            conn.execute(
+                "DELETE FROM websub_subscriptions WHERE callback = $1",
+                &[&callback],
+            )?;
+        } else {
+            let next_delivery =
+                Utc::now() + chrono::Duration::from_std(retry_delay(failed_deliveries))?;
+            conn.execute(
+                "UPDATE websub_subscriptions
+                 SET next_delivery = $2, failed_deliveries = failed_deliveries + 1
+                 WHERE callback = $1",
+                &[&callback, &next_delivery],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the address is reachable from the internet, and not on the local network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // "this network", shared address space and reserved
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local and link-local
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Pings the external hubs announced by the feed, so that they fetch it again. Returns either the
+/// number of successfully pinged hubs, or the first error.
+pub(crate) fn ping_hubs() -> Result<usize> {
+    let client = reqwest::blocking::Client::new();
+    for hub in EXTERNAL_HUBS {
+        client
+            .post(*hub)
+            .form(&[
+                ("hub.mode", "publish"),
+                ("hub.url", "https://docs.rs/releases/feed"),
+            ])
+            .send()?;
+    }
+    Ok(EXTERNAL_HUBS.len())
+}
+
+/// Marks the feed as changed, it will be sent to all the subscribers
+pub(crate) fn feed_updated(conn: &mut Client) -> Result<()> {
+    conn.execute(
+        "UPDATE websub_subscriptions SET next_delivery = NOW() WHERE next_delivery IS NULL",
+        &[],
+    )?;
+    Ok(())
+}
+
+/// One minute after the first failure, doubling every time
+fn retry_delay(failed_deliveries: i32) -> Duration {
+    Duration::from_secs(60 << failed_deliveries.clamp(0, 9))
+}
+
+/// The `X-Hub-Signature` of a body: its HMAC-SHA256 written as `sha256=<hex digest>`
+pub(crate) fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+    use mockito::Matcher;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// The requests received by a subscriber, as `(method, signature, body)`
+    type Requests = Arc<Mutex<Vec<(String, Option<String>, String)>>>;
+
+    /// A subscriber confirming every request
+    fn subscriber() -> (Url, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).unwrap();
+                let (mut signature, mut length) = (None, 0);
+                loop {
+                    let mut header = String::new();
+                    stream.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(": ").unwrap();
+                    match &*name.to_lowercase() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "x-hub-signature" => signature = Some(value.to_owned()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+
+                let mut parts = request_line.split(' ');
+                let method = parts.next().unwrap().to_owned();
+                let url = Url::parse("http://localhost")
+                    .unwrap()
+                    .join(parts.next().unwrap())
+                    .unwrap();
+                let challenge = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "hub.challenge")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap_or_default();
+                recorded.lock().unwrap().push((
+                    method,
+                    signature,
+                    String::from_utf8(body).unwrap(),
+                ));
+
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    challenge.len(),
+                    challenge
+                )
+                .unwrap();
+            }
+        });
+
+        (url.parse().unwrap(), requests)
+    }
+
+    /// A hub accepting the subscribers of the tests, which are local
+    fn hub(env: &TestEnvironment) -> Result<Hub> {
+        let mut hub = Hub::new(&env.config(), env.metrics())?;
+        hub.allow_private_callbacks = true;
+        Ok(hub)
+    }
+
+    fn subscription(callback: Url) -> SubscriptionRequest {
+        SubscriptionRequest {
+            mode: Mode::Subscribe,
+            callback,
+            topic: "https://docs.rs/releases/feed".into(),
+            lease: DEFAULT_LEASE,
+            secret: Some("secret".into()),
+        }
+    }
+
+    fn subscriptions(conn: &mut Client) -> Vec<(String, i32)> {
+        conn.query(
+            "SELECT callback, failed_deliveries FROM websub_subscriptions ORDER BY callback",
+            &[],
+        )
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+    }
+
+    #[test]
+    fn subscribe_and_deliver() {
+        wrapper(|env| {
+            let hub = hub(env)?;
+            let mut conn = env.db().conn();
+            let (callback, requests) = subscriber();
+
+            // the request is only verified by the daemon
+            hub.handle(&mut conn, &subscription(callback.clone()))?;
+            assert!(subscriptions(&mut conn).is_empty());
+            assert!(requests.lock().unwrap().is_empty());
+
+            assert_eq!(hub.verify_requests(&mut conn)?, 1);
+            assert_eq!(subscriptions(&mut conn), vec![(callback.to_string(), 0)]);
+            assert_eq!(requests.lock().unwrap()[0].0, "GET");
+            assert_eq!(hub.verify_requests(&mut conn)?, 0);
+
+            // nothing changed yet
+            assert_eq!(hub.deliver(&mut conn, |_| panic!("rendered the feed"))?, 0);
+
+            feed_updated(&mut conn)?;
+            assert_eq!(hub.deliver(&mut conn, |_| Ok("<feed />".into()))?, 1);
+            assert_eq!(
+                requests.lock().unwrap()[1],
+                (
+                    "POST".to_owned(),
+                    Some(signature("secret", b"<feed />")),
+                    "<feed />".to_owned()
+                )
+            );
+            assert_eq!(hub.deliver(&mut conn, |_| panic!("delivered twice"))?, 0);
+
+            let unsubscribe = SubscriptionRequest {
+                mode: Mode::Unsubscribe,
+                ..subscription(callback)
+            };
+            hub.handle(&mut conn, &unsubscribe)?;
+            assert_eq!(hub.verify_requests(&mut conn)?, 1);
+            assert!(subscriptions(&mut conn).is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rejected_subscriptions() {
+        wrapper(|env| {
+            let hub = hub(env)?;
+            let mut conn = env.db().conn();
+            let _callback = mockito::mock("GET", Matcher::Regex("^/websub-callback".into()))
+                .with_body("not the challenge")
+                .create();
+            let callback: Url = format!("{}/websub-callback", mockito::server_url()).parse()?;
+
+            let error = |request| {
+                hub.handle(&mut env.db().conn(), &request)
+                    .unwrap_err()
+                    .downcast::<SubscriptionError>()
+                    .unwrap()
+            };
+            assert_eq!(
+                error(SubscriptionRequest {
+                    topic: "https://docs.rs/releases/other".into(),
+                    ..subscription(callback.clone())
+                }),
+                SubscriptionError::UnknownTopic("https://docs.rs/releases/feed".into())
+            );
+
+            // the callback didn't echo the challenge
+            hub.handle(&mut conn, &subscription(callback.clone()))?;
+            assert_eq!(hub.verify_requests(&mut conn)?, 0);
+            assert!(subscriptions(&mut conn).is_empty());
+
+            // a single host can't take all the subscriptions
+            for i in 0..MAX_SUBSCRIPTIONS_PER_HOST {
+                let callback = callback.join(&format!("websub-callback-{}", i))?;
+                hub.handle(&mut conn, &subscription(callback))?;
+            }
+            assert_eq!(
+                error(subscription(callback.clone())),
+                SubscriptionError::TooManyHostSubscriptions(callback.host_str().unwrap().into())
+            );
+            // but the subscribers can renew their requests
+            hub.handle(
+                &mut conn,
+                &subscription(callback.join("websub-callback-0")?),
+            )?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn private_callbacks_are_not_requested() {
+        wrapper(|env| {
+            let hub = Hub::new(&env.config(), env.metrics())?;
+            let mut conn = env.db().conn();
+            let (callback, requests) = subscriber();
+
+            hub.handle(&mut conn, &subscription(callback.clone()))?;
+            assert_eq!(hub.verify_requests(&mut conn)?, 0);
+            assert!(subscriptions(&mut conn).is_empty());
+
+            conn.execute(
+                "INSERT INTO websub_subscriptions (callback, host, expires_at, next_delivery)
+                 VALUES ($1, '127.0.0.1', NOW() + INTERVAL '1 day', NOW())",
+                &[&callback.as_str()],
+            )?;
+            assert_eq!(hub.deliver(&mut conn, |_| Ok("<feed />".into()))?, 0);
+            assert_eq!(subscriptions(&mut conn), vec![(callback.to_string(), 1)]);
+            assert!(requests.lock().unwrap().is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried() {
+        wrapper(|env| {
+            let hub = hub(env)?;
+            let mut conn = env.db().conn();
+            let _callback = mockito::mock("POST", "/websub-failing")
+                .with_status(500)
+                .create();
+            let callback = format!("{}/websub-failing", mockito::server_url());
+            conn.execute(
+                "INSERT INTO websub_subscriptions (callback, host, expires_at, next_delivery)
+                 VALUES ($1, '127.0.0.1', NOW() + INTERVAL '1 day', NOW())",
+                &[&callback],
+            )?;
+
+            assert_eq!(hub.deliver(&mut conn, |_| Ok("<feed />".into()))?, 0);
+            assert_eq!(subscriptions(&mut conn), vec![(callback.clone(), 1)]);
+            // the next attempt is in a minute
+            assert_eq!(hub.deliver(&mut conn, |_| panic!("retried too early"))?, 0);
+
+            conn.execute(
+                "UPDATE websub_subscriptions SET next_delivery = NOW(), failed_deliveries = $1",
+                &[&(MAX_FAILED_DELIVERIES - 1)],
+            )?;
+            assert_eq!(hub.deliver(&mut conn, |_| Ok("<feed />".into()))?, 0);
+            assert!(subscriptions(&mut conn).is_empty());
+            assert_eq!(
+                env.metrics()
+                    .websub_deliveries
+                    .with_label_values(&["failed"])
+                    .get(),
+                2
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn parse_requests() {
+        let request = SubscriptionRequest::from_form(
+            b"hub.mode=subscribe&hub.callback=https%3A%2F%2Fexample.com%2Fcb%3Fid%3D1\
+              &hub.topic=https%3A%2F%2Fdocs.rs%2Freleases%2Ffeed&hub.lease_seconds=60",
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            SubscriptionRequest {
+                mode: Mode::Subscribe,
+                callback: "https://example.com/cb?id=1".parse().unwrap(),
+                topic: "https://docs.rs/releases/feed".into(),
+                lease: MIN_LEASE,
+                secret: None,
+            }
+        );
+
+        let invalid = |body: &[u8]| match SubscriptionRequest::from_form(body) {
+            Err(SubscriptionError::Invalid { field, .. }) => field,
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(invalid(b"hub.callback=https://a&hub.topic=b"), "hub.mode");
+        assert_eq!(
+            invalid(b"hub.mode=publish&hub.callback=https://a&hub.topic=b"),
+            "hub.mode"
+        );
+        assert_eq!(
+            invalid(b"hub.mode=subscribe&hub.callback=file:///etc/passwd&hub.topic=b"),
+            "hub.callback"
+        );
+        assert_eq!(
+            invalid(b"hub.mode=subscribe&hub.callback=https://a"),
+            "hub.topic"
+        );
+        for callback in &[
+            "http://127.0.0.1:8080/",
+            "http://10.1.2.3/",
+            "http://[::1]/",
+            "http://localhost/",
+            "http://metadata.localhost/",
+        ] {
+            let body = format!(
+                "hub.mode=subscribe&hub.topic=b&hub.callback={}",
+                url::form_urlencoded::byte_serialize(callback.as_bytes()).collect::<String>()
+            );
+            assert_eq!(invalid(body.as_bytes()), "hub.callback");
+        }
+    }
+
+    #[test]
+    fn public_addresses() {
+        for public in &["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+        for private in &[
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+    }
+
+    #[test]
+    fn retry_delays() {
+        assert_eq!(retry_delay(0), Duration::from_secs(60));
+        assert_eq!(retry_delay(3), Duration::from_secs(8 * 60));
+        assert_eq!(retry_delay(20), Duration::from_secs(512 * 60));
+    }
+
+    #[test]
+    fn signature_of_body() {
+        // echo -n '{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            signature("secret", b"{}"),
+            "sha256=77325902caca812dc259733aacd046b73817372c777b8d95b402647474516e13"
+        );
+    }
 }
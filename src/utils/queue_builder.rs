@@ -2,15 +2,14 @@ use crate::{docbuilder::RustwideBuilder, utils::pubsubhubbub, BuildQueue, DocBui
 use failure::Error;
 use log::{debug, error, info, warn};
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 // TODO: change to `fn() -> Result<!, Error>` when never _finally_ stabilizes
 pub fn queue_builder(
-    mut doc_builder: DocBuilder,
-    mut builder: RustwideBuilder,
-    build_queue: Arc<BuildQueue>,
+    doc_builder: &mut DocBuilder,
+    builder: &mut RustwideBuilder,
+    build_queue: &BuildQueue,
 ) -> Result<(), Error> {
     /// Represents the current state of the builder thread.
     enum BuilderState {
@@ -20,6 +19,8 @@ pub fn queue_builder(
         EmptyQueue,
         /// The builder has just seen the lock file.
         Locked,
+        /// The builder has just seen that the disk is almost full.
+        LowDiskSpace,
         /// The builder has just finished building a crate. The enclosed count is the number of
         /// crates built since the caches have been refreshed.
         QueueInProgress(usize),
@@ -39,6 +40,16 @@ pub fn queue_builder(
             continue;
         }
 
+        match builder.has_enough_disk_space() {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Not enough disk space, skipping building new crates");
+                status = BuilderState::LowDiskSpace;
+                continue;
+            }
+            Err(e) => error!("Failed to check the available disk space: {}", e),
+        }
+
         if status.count() >= 10 {
             // periodically, ping the hubs
             debug!("10 builds in a row; pinging pubsubhubhub");
@@ -84,7 +95,7 @@ pub fn queue_builder(
         // This only panicked twice in the last 6 months but its just a better
         // idea to do this.
         let res = catch_unwind(AssertUnwindSafe(|| {
-            match doc_builder.build_next_queue_package(&mut builder) {
+            match doc_builder.build_next_queue_package(builder) {
                 Err(e) => error!("Failed to build crate from queue: {}", e),
                 Ok(crate_built) => {
                     if crate_built {
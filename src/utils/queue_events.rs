@@ -0,0 +1,141 @@
+//! Live updates of the build queue
+//!
+//! The builder publishes an event when a crate is queued, when its build starts and when it's
+//! done. The events are kept for a while in the `queue_events` table, where the pages of the queue
+//! and of the builds poll them from `/releases/queue/events`, with short requests that don't tie
+//! up the threads of the web server.
+
+use crate::error::Result;
+use postgres::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How long the events are kept, the clients lagging more than this reload their list
+const RETENTION: &str = "1 hour";
+/// The events returned at most by a poll, the client gets the next ones with the next poll
+const MAX_EVENTS: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum QueueEvent {
+    Queued {
+        name: String,
+        version: String,
+        /// The priority of the crate in the queue, the lowest ones are built first
+        priority: i32,
+    },
+    Started {
+        name: String,
+        version: String,
+    },
+    /// The build is over, the crate is still in the queue if it's going to be retried
+    Finished {
+        name: String,
+        version: String,
+        requeued: bool,
+    },
+}
+
+/// The events published after a cursor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct QueueEvents {
+    /// The cursor of the next poll
+    pub(crate) cursor: i64,
+    pub(crate) events: Vec<QueueEvent>,
+}
+
+/// Records an event for the clients polling the queue, and forgets the old ones
+pub(crate) fn publish(conn: &mut Client, event: &QueueEvent) -> Result<()> {
+    conn.execute(
+        "INSERT INTO queue_events (event) VALUES ($1)",
+        &[&serde_json::to_value(event)?],
+    )?;
+    conn.execute(
+        format!(
+            "DELETE FROM queue_events WHERE published_at < NOW() - INTERVAL '{}'",
+            RETENTION
+        )
+        .as_str(),
+        &[],
+    )?;
+    Ok(())
+}
+
+/// The events published after `cursor`, oldest first. Without a cursor, only the cursor of the
+/// next events is returned.
+pub(crate) fn since(conn: &mut Client, cursor: Option<i64>) -> Result<QueueEvents> {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => {
+            let cursor: Option<i64> = conn
+                .query_one("SELECT MAX(id) FROM queue_events", &[])?
+                .get(0);
+            return Ok(QueueEvents {
+                cursor: cursor.unwrap_or(0),
+                events: Vec::new(),
+            });
+        }
+    };
+
+    let mut events = QueueEvents {
+        cursor,
+        events: Vec::new(),
+    };
+    for row in conn.query(
+        "SELECT id, event FROM queue_events WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&cursor, &MAX_EVENTS],
+    )? {
+        events.cursor = row.get(0);
+        match serde_json::from_value(row.get::<_, Value>(1)) {
+            Ok(event) => events.events.push(event),
+            Err(err) => log::error!("invalid queue event {}: {}", events.cursor, err),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn events_are_delivered() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            let start = since(&mut conn, None)?;
+            assert!(start.events.is_empty());
+
+            let queue = env.build_queue();
+            queue.add_crate("foo", "0.1.0", 5, None)?;
+            queue.process_next_crate(|_| Ok(()))?;
+
+            let received = since(&mut conn, Some(start.cursor))?;
+            assert_eq!(
+                received.events,
+                vec![
+                    QueueEvent::Queued {
+                        name: "foo".into(),
+                        version: "0.1.0".into(),
+                        priority: 5,
+                    },
+                    QueueEvent::Started {
+                        name: "foo".into(),
+                        version: "0.1.0".into(),
+                    },
+                    QueueEvent::Finished {
+                        name: "foo".into(),
+                        version: "0.1.0".into(),
+                        requeued: false,
+                    },
+                ]
+            );
+
+            // the next poll only gets the new events
+            assert!(since(&mut conn, Some(received.cursor))?.events.is_empty());
+            assert_eq!(since(&mut conn, None)?.cursor, received.cursor);
+
+            Ok(())
+        });
+    }
+}
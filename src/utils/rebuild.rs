@@ -0,0 +1,442 @@
+//! Bulk rebuilds of releases documented with an older toolchain
+//!
+//! A rebuild run is identified by the rustc version passed to `cratesfyi rebuild-all --since`.
+//! Every release whose latest build used a nightly older than that version is added to the build
+//! queue, in batches of `DOCSRS_REBUILD_BATCH_SIZE` releases every
+//! `DOCSRS_REBUILD_BATCH_INTERVAL` seconds. The id of the last enqueued release is stored in the
+//! `rebuild_runs` table after every batch, so an interrupted run can be resumed later.
+//!
+//! Smaller rebuilds also happen automatically when a new rustdoc is deployed, for the most
+//! downloaded crates only, see [`rebuild_after_toolchain_update`].
+
+use crate::error::Result;
+use crate::{BuildQueue, Config};
+use chrono::{DateTime, NaiveDate, Utc};
+use failure::ResultExt;
+use log::info;
+use postgres::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+
+/// Priority given to the queued rebuilds, so that they never delay newly published crates
+pub(crate) const REBUILD_PRIORITY: i32 = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct RebuildRun {
+    pub(crate) since: String,
+    pub(crate) last_release_id: i32,
+    pub(crate) enqueued: i32,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) updated_at: DateTime<Utc>,
+    pub(crate) finished_at: Option<DateTime<Utc>>,
+}
+
+impl RebuildRun {
+    fn from_row(row: &postgres::Row) -> Self {
+        Self {
+            since: row.get("since"),
+            last_release_id: row.get("last_release_id"),
+            enqueued: row.get("enqueued"),
+            started_at: row.get("started_at"),
+            updated_at: row.get("updated_at"),
+            finished_at: row.get("finished_at"),
+        }
+    }
+}
+
+/// Extracts the nightly date out of a rustc version string
+fn rustc_date(version: &str) -> Result<NaiveDate> {
+    let parsed = super::parse_rustc_version(version)?;
+    Ok(NaiveDate::parse_from_str(&parsed[..8], "%Y%m%d")
+        .with_context(|_| format!("invalid date in rustc version {:?}", version))?)
+}
+
+/// Enqueues a rebuild of every release last built with a toolchain older than `since`.
+///
+/// If a run for `since` was interrupted it is resumed where it stopped, unless `restart` is set.
+/// Returns the number of releases added to the queue by this invocation.
+pub fn rebuild_all(
+    conn: &mut Client,
+    queue: &BuildQueue,
+    config: &Config,
+    since: &str,
+    restart: bool,
+) -> Result<usize> {
+    let since_date = rustc_date(since)?;
+
+    if restart {
+        conn.execute("DELETE FROM rebuild_runs WHERE since = $1", &[&since])?;
+    }
+    conn.execute(
+        "INSERT INTO rebuild_runs (since) VALUES ($1) ON CONFLICT (since) DO NOTHING",
+        &[&since],
+    )?;
+    let run = RebuildRun::from_row(
+        &conn.query_one("SELECT * FROM rebuild_runs WHERE since = $1", &[&since])?,
+    );
+
+    if run.finished_at.is_some() {
+        info!("rebuild of releases older than {} already finished", since);
+        return Ok(0);
+    }
+    if run.last_release_id > 0 {
+        info!(
+            "resuming rebuild of releases older than {} after release {} ({} already enqueued)",
+            since, run.last_release_id, run.enqueued
+        );
+    }
+
+    let batch_size = config.rebuild_batch_size as i64;
+    let mut last_release_id = run.last_release_id;
+    let mut total = 0;
+    loop {
+        let batch = conn.query(
+            "SELECT releases.id, crates.name, releases.version
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             INNER JOIN LATERAL (
+                 SELECT rustc_version
+                 FROM builds
+                 WHERE builds.rid = releases.id
+                 ORDER BY builds.id DESC
+                 LIMIT 1
+             ) AS latest_build ON TRUE
+             WHERE
+                 releases.id > $1 AND
+                 to_date(
+                     substring(latest_build.rustc_version from '([0-9]{4}-[0-9]{2}-[0-9]{2})\\)$'),
+                     'YYYY-MM-DD'
+                 ) < $2 AND
+                 NOT EXISTS (
+                     SELECT 1 FROM queue
+                     WHERE queue.name = crates.name AND queue.version = releases.version
+                 )
+             ORDER BY releases.id
+             LIMIT $3",
+            &[&last_release_id, &since_date, &batch_size],
+        )?;
+
+        if batch.is_empty() {
+            break;
+        }
+
+        for row in &batch {
+            let name: String = row.get("name");
+            let version: String = row.get("version");
+            queue
+                .add_crate(
+                    &name,
+                    &version,
+                    REBUILD_PRIORITY,
+                    config.registry_url.as_deref(),
+                )
+                .with_context(|_| format!("failed to enqueue {} {}", name, version))?;
+        }
+
+        last_release_id = batch.last().unwrap().get("id");
+        total += batch.len();
+        conn.execute(
+            "UPDATE rebuild_runs
+             SET last_release_id = $2, enqueued = enqueued + $3, updated_at = NOW()
+             WHERE since = $1",
+            &[&since, &last_release_id, &(batch.len() as i32)],
+        )?;
+        info!(
+            "enqueued {} releases for rebuild (up to release {})",
+            batch.len(),
+            last_release_id
+        );
+
+        if (batch.len() as i64) < batch_size {
+            break;
+        }
+        thread::sleep(Duration::from_secs(config.rebuild_batch_interval));
+    }
+
+    conn.execute(
+        "UPDATE rebuild_runs SET finished_at = NOW(), updated_at = NOW() WHERE since = $1",
+        &[&since],
+    )?;
+    info!(
+        "finished enqueueing rebuilds of releases older than {}",
+        since
+    );
+
+    Ok(total)
+}
+
+/// Enqueues a rebuild of the `DOCSRS_TOOLCHAIN_REBUILD_CRATES` most downloaded crates when the
+/// deployed rustdoc changed since the last call, so that the fixes of its rendering quickly reach
+/// the most visited documentation. Only the latest release of each crate is rebuilt, when it was
+/// last built with an older nightly.
+///
+/// Returns the number of releases added to the queue.
+pub(crate) fn rebuild_after_toolchain_update(
+    conn: &mut Client,
+    queue: &BuildQueue,
+    config: &Config,
+) -> Result<usize> {
+    let config_value = |conn: &mut Client, name: &str| -> Result<Option<String>> {
+        Ok(conn
+            .query_opt("SELECT value FROM config WHERE name = $1", &[&name])?
+            .and_then(|row| row.get::<_, Value>(0).as_str().map(String::from)))
+    };
+
+    let deployed = match config_value(conn, "rustdoc_version")? {
+        Some(version) => version,
+        None => return Ok(0),
+    };
+    let handled = config_value(conn, "toolchain_rebuild_version")?;
+    if handled.as_deref() == Some(&deployed) {
+        return Ok(0);
+    }
+    conn.execute(
+        "INSERT INTO config (name, value) VALUES ('toolchain_rebuild_version', $1)
+         ON CONFLICT (name) DO UPDATE SET value = $1",
+        &[&Value::String(deployed.clone())],
+    )?;
+    // the documentation built before docs.rs started tracking the deployed rustdoc can't be
+    // told apart from the one that needs a rebuild
+    if handled.is_none() || config.toolchain_rebuild_crates == 0 {
+        return Ok(0);
+    }
+
+    let releases = conn.query(
+        "SELECT crates.name, releases.version
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         INNER JOIN LATERAL (
+             SELECT rustc_version
+             FROM builds
+             WHERE builds.rid = releases.id
+             ORDER BY builds.id DESC
+             LIMIT 1
+         ) AS latest_build ON TRUE
+         WHERE
+             to_date(
+                 substring(latest_build.rustc_version from '([0-9]{4}-[0-9]{2}-[0-9]{2})\\)$'),
+                 'YYYY-MM-DD'
+             ) < $1 AND
+             NOT EXISTS (
+                 SELECT 1 FROM queue
+                 WHERE queue.name = crates.name AND queue.version = releases.version
+             )
+         ORDER BY releases.downloads DESC NULLS LAST
+         LIMIT $2",
+        &[
+            &rustc_date(&deployed)?,
+            &(config.toolchain_rebuild_crates as i64),
+        ],
+    )?;
+
+    for row in &releases {
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+        queue
+            .add_crate(
+                &name,
+                &version,
+                REBUILD_PRIORITY,
+                config.registry_url.as_deref(),
+            )
+            .with_context(|_| format!("failed to enqueue {} {}", name, version))?;
+    }
+    info!(
+        "enqueued the rebuild of {} crates after the update to {}",
+        releases.len(),
+        deployed
+    );
+
+    Ok(releases.len())
+}
+
+/// Lists all rebuild runs, most recently started first
+pub(crate) fn rebuild_runs(conn: &mut Client) -> Result<Vec<RebuildRun>> {
+    Ok(conn
+        .query("SELECT * FROM rebuild_runs ORDER BY started_at DESC", &[])?
+        .iter()
+        .map(RebuildRun::from_row)
+        .collect())
+}
+
+/// Returns the highest release id, to show how far a run has progressed
+pub(crate) fn max_release_id(conn: &mut Client) -> Result<i32> {
+    Ok(conn
+        .query_one("SELECT COALESCE(MAX(id), 0) FROM releases", &[])?
+        .get(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, FakeBuild};
+
+    const OLD: &str = "rustc 1.50.0-nightly (000000000 2020-12-01)";
+    const NEW: &str = "rustc 1.53.0-nightly (000000000 2021-04-01)";
+
+    #[test]
+    fn test_rustc_date() {
+        assert_eq!(rustc_date(OLD).unwrap(), NaiveDate::from_ymd(2020, 12, 1));
+        assert!(rustc_date("not a version").is_err());
+    }
+
+    #[test]
+    fn enqueues_only_old_releases() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.rebuild_batch_size = 2;
+                config.rebuild_batch_interval = 0;
+            });
+
+            for name in &["old-a", "old-b", "old-c"] {
+                env.fake_release()
+                    .name(name)
+                    .builds(vec![FakeBuild::default().rustc_version(OLD)])
+                    .create()?;
+            }
+            env.fake_release()
+                .name("new")
+                .builds(vec![FakeBuild::default().rustc_version(NEW)])
+                .create()?;
+            env.fake_release()
+                .name("rebuilt")
+                .builds(vec![
+                    FakeBuild::default().rustc_version(OLD),
+                    FakeBuild::default().rustc_version(NEW),
+                ])
+                .create()?;
+
+            let queue = env.build_queue();
+            let mut conn = env.db().conn();
+            let enqueued = rebuild_all(&mut conn, &queue, &env.config(), NEW, false)?;
+            assert_eq!(enqueued, 3);
+
+            let mut queued: Vec<_> = queue
+                .queued_crates()?
+                .into_iter()
+                .map(|krate| {
+                    assert_eq!(krate.priority, REBUILD_PRIORITY);
+                    krate.name
+                })
+                .collect();
+            queued.sort();
+            assert_eq!(queued, vec!["old-a", "old-b", "old-c"]);
+
+            let runs = rebuild_runs(&mut conn)?;
+            assert_eq!(runs.len(), 1);
+            assert_eq!(runs[0].enqueued, 3);
+            assert!(runs[0].finished_at.is_some());
+
+            // a finished run is not repeated
+            assert_eq!(
+                rebuild_all(&mut conn, &queue, &env.config(), NEW, false)?,
+                0
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn resumes_interrupted_run() {
+        wrapper(|env| {
+            env.override_config(|config| config.rebuild_batch_interval = 0);
+
+            let first = env
+                .fake_release()
+                .name("first")
+                .builds(vec![FakeBuild::default().rustc_version(OLD)])
+                .create()?;
+            env.fake_release()
+                .name("second")
+                .builds(vec![FakeBuild::default().rustc_version(OLD)])
+                .create()?;
+
+            // simulate a run that was killed after the first release
+            let mut conn = env.db().conn();
+            conn.execute(
+                "INSERT INTO rebuild_runs (since, last_release_id, enqueued) VALUES ($1, $2, 1)",
+                &[&NEW, &first],
+            )?;
+
+            let queue = env.build_queue();
+            assert_eq!(
+                rebuild_all(&mut conn, &queue, &env.config(), NEW, false)?,
+                1
+            );
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].name, "second");
+            assert_eq!(rebuild_runs(&mut conn)?[0].enqueued, 2);
+
+            // restarting ignores the previous progress
+            conn.execute("DELETE FROM queue", &[])?;
+            assert_eq!(rebuild_all(&mut conn, &queue, &env.config(), NEW, true)?, 2);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn rebuilds_most_downloaded_after_toolchain_update() {
+        wrapper(|env| {
+            env.override_config(|config| config.toolchain_rebuild_crates = 2);
+
+            for (name, downloads) in &[("popular", 1000), ("known", 100), ("obscure", 1)] {
+                env.fake_release()
+                    .name(name)
+                    .downloads(*downloads)
+                    .builds(vec![FakeBuild::default().rustc_version(OLD)])
+                    .create()?;
+            }
+            env.fake_release()
+                .name("recent")
+                .downloads(10_000)
+                .builds(vec![FakeBuild::default().rustc_version(NEW)])
+                .create()?;
+
+            let queue = env.build_queue();
+            let mut conn = env.db().conn();
+            let set_rustdoc = |conn: &mut Client, version: &str| {
+                conn.execute(
+                    "INSERT INTO config (name, value) VALUES ('rustdoc_version', $1)
+                     ON CONFLICT (name) DO UPDATE SET value = $1",
+                    &[&Value::String(version.replace("rustc", "rustdoc"))],
+                )
+            };
+
+            // nothing happens until the deployed version is known
+            assert_eq!(
+                rebuild_after_toolchain_update(&mut conn, &queue, &env.config())?,
+                0
+            );
+            set_rustdoc(&mut conn, OLD)?;
+            assert_eq!(
+                rebuild_after_toolchain_update(&mut conn, &queue, &env.config())?,
+                0
+            );
+
+            set_rustdoc(&mut conn, NEW)?;
+            assert_eq!(
+                rebuild_after_toolchain_update(&mut conn, &queue, &env.config())?,
+                2
+            );
+            let queued: Vec<_> = queue
+                .queued_crates()?
+                .into_iter()
+                .map(|krate| krate.name)
+                .collect();
+            assert_eq!(queued, vec!["popular", "known"]);
+
+            // every version is only handled once
+            assert_eq!(
+                rebuild_after_toolchain_update(&mut conn, &queue, &env.config())?,
+                0
+            );
+
+            Ok(())
+        })
+    }
+}
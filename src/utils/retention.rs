@@ -0,0 +1,388 @@
+//! Retention of the documentation of superseded pre-releases
+//!
+//! Crates publishing many pre-releases (`1.0.0-alpha.1`, `1.0.0-alpha.2`, ...) accumulate
+//! documentation nobody reads anymore. When `DOCSRS_PRERELEASE_RETENTION` is set, a daily job looks
+//! for the pre-releases superseded by a newer, documented release of the same version published
+//! more than `DOCSRS_PRERELEASE_RETENTION_DAYS` days ago. Depending on the policy, the
+//! documentation of those pre-releases is archived (deleted from the storage, the release and its
+//! sources are kept and can be rebuilt) or the whole pre-release is deleted.
+//!
+//! The retired pre-releases are recorded in `retired_releases`, and their documentation redirects
+//! to the release that superseded them.
+
+use crate::db::audit::Auditor;
+use crate::error::Result;
+use crate::Storage;
+use chrono::{DateTime, Duration, Utc};
+use failure::Fail;
+use log::info;
+use postgres::Client;
+use semver::Version;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Fail, PartialEq, Eq)]
+#[fail(
+    display = "invalid retention policy {:?}, expected `archive` or `delete`",
+    _0
+)]
+pub struct InvalidRetentionPolicy(String);
+
+/// What happens to the superseded pre-releases
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Delete the documentation, keeping the release and its sources
+    Archive,
+    /// Delete the release entirely
+    Delete,
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = InvalidRetentionPolicy;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "archive" => Ok(RetentionPolicy::Archive),
+            "delete" => Ok(RetentionPolicy::Delete),
+            _ => Err(InvalidRetentionPolicy(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RetentionPolicy::Archive => "archive",
+            RetentionPolicy::Delete => "delete",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SupersededRelease {
+    pub name: String,
+    pub version: String,
+    /// The newest release of the same version, which is never retired along with this one
+    pub superseded_by: String,
+}
+
+struct Release {
+    version: Version,
+    raw_version: String,
+    has_docs: bool,
+    yanked: bool,
+    release_time: DateTime<Utc>,
+}
+
+/// The documented pre-releases of `releases` superseded by a release published before `cutoff`
+fn superseded_in(
+    name: &str,
+    releases: &[Release],
+    cutoff: DateTime<Utc>,
+) -> Vec<SupersededRelease> {
+    let same_version =
+        |a: &Version, b: &Version| (a.major, a.minor, a.patch) == (b.major, b.minor, b.patch);
+
+    releases
+        .iter()
+        .filter(|release| release.has_docs && !release.version.pre.is_empty())
+        .filter_map(|prerelease| {
+            let newest = releases
+                .iter()
+                .filter(|release| {
+                    same_version(&release.version, &prerelease.version)
+                        && release.version > prerelease.version
+                        && release.has_docs
+                        && !release.yanked
+                        && release.release_time <= cutoff
+                })
+                .max_by(|a, b| a.version.cmp(&b.version))?;
+            Some(SupersededRelease {
+                name: name.into(),
+                version: prerelease.raw_version.clone(),
+                superseded_by: newest.raw_version.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Lists the documented pre-releases superseded for more than `retention_days` days
+pub fn superseded_prereleases(
+    conn: &mut Client,
+    retention_days: u32,
+) -> Result<Vec<SupersededRelease>> {
+    let cutoff = Utc::now() - Duration::days(retention_days.into());
+    let rows = conn.query(
+        "SELECT crates.name, releases.version, releases.rustdoc_status, releases.yanked,
+                releases.release_time
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE releases.crate_id IN (
+             SELECT crate_id FROM releases WHERE version LIKE '%-%' AND rustdoc_status
+         )
+         ORDER BY crates.name",
+        &[],
+    )?;
+
+    let mut superseded = Vec::new();
+    let mut current: Option<String> = None;
+    let mut releases = Vec::new();
+    for row in rows {
+        let name: String = row.get("name");
+        if current.as_ref() != Some(&name) {
+            if let Some(current) = current.replace(name) {
+                superseded.extend(superseded_in(&current, &releases, cutoff));
+            }
+            releases.clear();
+        }
+
+        let raw_version: String = row.get("version");
+        if let Ok(version) = Version::parse(&raw_version) {
+            releases.push(Release {
+                version,
+                raw_version,
+                has_docs: row.get("rustdoc_status"),
+                yanked: row.get("yanked"),
+                release_time: row.get("release_time"),
+            });
+        }
+    }
+    if let Some(current) = current {
+        superseded.extend(superseded_in(&current, &releases, cutoff));
+    }
+
+    Ok(superseded)
+}
+
+/// Applies `policy` to the pre-releases superseded for more than `retention_days` days, returning
+/// them. Nothing is changed with `dry_run`.
+pub fn retire_superseded_prereleases(
+    conn: &mut Client,
+    storage: &Storage,
+    policy: RetentionPolicy,
+    retention_days: u32,
+    dry_run: bool,
+) -> Result<Vec<SupersededRelease>> {
+    let superseded = superseded_prereleases(conn, retention_days)?;
+    if dry_run {
+        return Ok(superseded);
+    }
+
+    for release in &superseded {
+        info!(
+            "retiring {} {} ({}), superseded by {}",
+            release.name, release.version, policy, release.superseded_by
+        );
+        let auditor = Auditor::new(
+            "retention",
+            Some(format!("superseded by {}", release.superseded_by)),
+        );
+        match policy {
+            RetentionPolicy::Archive => {
+                auditor.archive_version(conn, storage, &release.name, &release.version)?
+            }
+            RetentionPolicy::Delete => {
+                auditor.delete_version(conn, storage, &release.name, &release.version)?
+            }
+        }
+
+        conn.execute(
+            "INSERT INTO retired_releases (crate_name, version, superseded_by, policy)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (crate_name, version) DO UPDATE
+                SET superseded_by = EXCLUDED.superseded_by,
+                    policy = EXCLUDED.policy,
+                    retired_at = NOW()",
+            &[
+                &release.name,
+                &release.version,
+                &release.superseded_by,
+                &policy.to_string(),
+            ],
+        )?;
+        // the releases retired earlier now redirect to the newest release too
+        conn.execute(
+            "UPDATE retired_releases SET superseded_by = $3
+             WHERE crate_name = $1 AND superseded_by = $2",
+            &[&release.name, &release.version, &release.superseded_by],
+        )?;
+    }
+
+    Ok(superseded)
+}
+
+/// The release that superseded `version`, if it was retired
+pub(crate) fn superseded_by(
+    conn: &mut Client,
+    name: &str,
+    version: &str,
+) -> Result<Option<String>> {
+    Ok(conn
+        .query_opt(
+            "SELECT superseded_by FROM retired_releases WHERE crate_name = $1 AND version = $2",
+            &[&name, &version],
+        )?
+        .map(|row| row.get(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{assert_redirect, wrapper};
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("archive".parse(), Ok(RetentionPolicy::Archive));
+        assert_eq!("delete".parse(), Ok(RetentionPolicy::Delete));
+        assert!("keep".parse::<RetentionPolicy>().is_err());
+        assert_eq!(RetentionPolicy::Archive.to_string(), "archive");
+    }
+
+    #[test]
+    fn superseded() {
+        let long_ago = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let recently = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let cutoff = Utc.ymd(2020, 6, 1).and_hms(0, 0, 0);
+        let release = |version: &str, release_time, has_docs, yanked| Release {
+            version: Version::parse(version).unwrap(),
+            raw_version: version.into(),
+            has_docs,
+            yanked,
+            release_time,
+        };
+
+        let releases = [
+            release("0.1.0-alpha.1", long_ago, true, false),
+            release("0.1.0-alpha.2", long_ago, true, false),
+            release("0.1.0-alpha.3", long_ago, false, false),
+            release("0.1.0-beta.1", long_ago, true, true),
+            release("0.1.0-rc.1", recently, true, false),
+            release("0.2.0-alpha.1", long_ago, true, false),
+            release("0.3.0", long_ago, true, false),
+        ];
+        let superseded: Vec<_> = superseded_in("foo", &releases, cutoff)
+            .into_iter()
+            .map(|release| (release.version, release.superseded_by))
+            .collect();
+        assert_eq!(
+            superseded,
+            vec![("0.1.0-alpha.1".to_string(), "0.1.0-alpha.2".to_string())]
+        );
+    }
+
+    #[test]
+    fn retire() {
+        wrapper(|env| {
+            let long_ago = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+            for version in &["1.0.0-alpha.1", "1.0.0-alpha.2", "1.0.0"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .release_time(long_ago)
+                    .rustdoc_file("foo/index.html")
+                    .create()?;
+            }
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0-alpha.1")
+                .release_time(long_ago)
+                .rustdoc_file("bar/index.html")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .rustdoc_file("bar/index.html")
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let storage = env.storage();
+            let superseded = retire_superseded_prereleases(
+                &mut conn,
+                &storage,
+                RetentionPolicy::Archive,
+                30,
+                true,
+            )?;
+            assert_eq!(
+                superseded,
+                vec![
+                    SupersededRelease {
+                        name: "foo".into(),
+                        version: "1.0.0-alpha.1".into(),
+                        superseded_by: "1.0.0".into(),
+                    },
+                    SupersededRelease {
+                        name: "foo".into(),
+                        version: "1.0.0-alpha.2".into(),
+                        superseded_by: "1.0.0".into(),
+                    },
+                ]
+            );
+            assert!(storage.exists("rustdoc/foo/1.0.0-alpha.1/foo/index.html")?);
+
+            retire_superseded_prereleases(
+                &mut conn,
+                &storage,
+                RetentionPolicy::Archive,
+                30,
+                false,
+            )?;
+            assert!(!storage.exists("rustdoc/foo/1.0.0-alpha.1/foo/index.html")?);
+            assert!(storage.exists("rustdoc/foo/1.0.0/foo/index.html")?);
+            assert_eq!(
+                superseded_by(&mut conn, "foo", "1.0.0-alpha.2")?,
+                Some("1.0.0".into())
+            );
+            // the archived releases aren't documented anymore
+            assert!(superseded_prereleases(&mut conn, 30)?.is_empty());
+
+            let web = env.frontend();
+            assert_redirect(
+                "/foo/1.0.0-alpha.1/foo/index.html",
+                "/foo/1.0.0/foo/index.html",
+                web,
+            )?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn deleted_prereleases_redirect() {
+        wrapper(|env| {
+            let long_ago = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+            for version in &["1.0.0-alpha.1", "1.0.0-alpha.2"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .release_time(long_ago)
+                    .rustdoc_file("foo/index.html")
+                    .create()?;
+            }
+
+            let mut conn = env.db().conn();
+            retire_superseded_prereleases(
+                &mut conn,
+                &env.storage(),
+                RetentionPolicy::Delete,
+                30,
+                false,
+            )?;
+            assert!(conn
+                .query_opt(
+                    "SELECT 1 FROM releases WHERE version = '1.0.0-alpha.1'",
+                    &[]
+                )?
+                .is_none());
+
+            assert_redirect(
+                "/foo/1.0.0-alpha.1/foo/index.html",
+                "/foo/1.0.0-alpha.2/foo/index.html",
+                env.frontend(),
+            )?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,41 @@
+//! The output of `rustdoc --output-format json` of the documented targets
+//!
+//! When `DOCSRS_RUSTDOC_JSON` is set, the builder runs rustdoc a second time for every target with
+//! documentation and publishes its JSON output, so that tools like semver checkers or search
+//! engines can work on it without building the crate again. The files are compressed with zstd
+//! before being stored, and served as they are by `/crate/:name/:version/json/:target`.
+
+use crate::error::Result;
+use crate::storage::{compress, Blob, CompressionAlgorithm};
+use crate::Storage;
+use chrono::Utc;
+
+pub(crate) const MIME: &str = "application/zstd";
+
+pub(crate) fn storage_path(name: &str, version: &str, target: &str) -> String {
+    format!("rustdoc-json/{}/{}/{}.json.zst", name, version, target)
+}
+
+/// Compresses the JSON as it's stored, to keep the output of every target in memory until the
+/// upload
+pub(crate) fn compress_json(json: &[u8]) -> Result<Vec<u8>> {
+    compress(json, CompressionAlgorithm::Zstd)
+}
+
+/// Stores the compressed output of a target, without letting the storage compress it again
+pub(crate) fn store(
+    storage: &Storage,
+    name: &str,
+    version: &str,
+    target: &str,
+    compressed: Vec<u8>,
+) -> Result<()> {
+    storage.store_blobs(vec![Blob {
+        path: storage_path(name, version, target),
+        mime: MIME.into(),
+        date_updated: Utc::now(),
+        content: compressed,
+        compression: None,
+        content_hash: None,
+    }])
+}
@@ -0,0 +1,437 @@
+//! Periodic jobs of the daemon
+//!
+//! Every job runs in its own thread, at the times matching a cron expression. The outcome of the
+//! last run is stored in the `scheduled_jobs` table, shown on `/about/jobs`, and an advisory lock
+//! makes sure that a job doesn't run twice at the same time when multiple daemons are running.
+
+use super::daemon::{panic_message, supervise};
+use crate::db::Pool;
+use crate::Metrics;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Timelike, Utc};
+use failure::{Error, Fail};
+use log::{debug, error, info};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, Eq, Fail)]
+#[fail(display = "invalid cron expression `{}`: {}", expression, reason)]
+pub(crate) struct InvalidSchedule {
+    expression: String,
+    reason: &'static str,
+}
+
+/// The values allowed by a field of a cron expression, one bit per value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, &'static str> {
+        let mut bits = 0;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse().map_err(|_| "invalid step")?),
+                None => (item, 1),
+            };
+            if step == 0 {
+                return Err("invalid step");
+            }
+
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                let parse = |value: &str| value.parse::<u32>().map_err(|_| "invalid value");
+                (parse(start)?, parse(end)?)
+            } else {
+                let value = range.parse().map_err(|_| "invalid value")?;
+                // `5/10` means every 10 starting at 5
+                (value, if item.contains('/') { max } else { value })
+            };
+            if start < min || end > max || start > end {
+                return Err("value out of range");
+            }
+
+            for value in (start..=end).step_by(step) {
+                bits |= 1 << value;
+            }
+        }
+
+        Ok(Field(bits))
+    }
+
+    fn contains(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// When a job runs, parsed from a cron expression like `*/5 * * * *`
+///
+/// The five fields are the minute, hour, day of the month, month and day of the week (0 or 7 for
+/// Sunday). Each of them is `*`, a value, a range like `1-5`, optionally followed by a step like
+/// `/2`, or a list of those separated by commas. All the times are in UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Schedule {
+    expression: String,
+    minutes: Field,
+    hours: Field,
+    days_of_month: Field,
+    months: Field,
+    days_of_week: Field,
+    /// Like cron, a day matches either of the day fields when both of them are restricted
+    restricted_days: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = InvalidSchedule;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| InvalidSchedule {
+            expression: expression.into(),
+            reason,
+        };
+
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(invalid("expected 5 fields"));
+        }
+        let mut days_of_week = Field::parse(fields[4], 0, 7).map_err(invalid)?;
+        if days_of_week.contains(7) {
+            days_of_week.0 |= 1;
+        }
+
+        Ok(Schedule {
+            expression: expression.into(),
+            minutes: Field::parse(fields[0], 0, 59).map_err(invalid)?,
+            hours: Field::parse(fields[1], 0, 23).map_err(invalid)?,
+            days_of_month: Field::parse(fields[2], 1, 31).map_err(invalid)?,
+            months: Field::parse(fields[3], 1, 12).map_err(invalid)?,
+            days_of_week,
+            restricted_days: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+}
+
+impl Schedule {
+    pub(crate) fn as_str(&self) -> &str {
+        &self.expression
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month.contains(time.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(time.weekday().num_days_from_sunday());
+        if self.restricted_days {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+
+    /// The first time matching the schedule strictly after `after`, `None` if there isn't any in
+    /// the next years, like for the 30th of February
+    pub(crate) fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let limit = after + ChronoDuration::days(5 * 366);
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+
+        while time < limit {
+            if !self.months.contains(time.month()) {
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = Utc.ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.matches_day(time) {
+                time = time.date().succ().and_hms(0, 0, 0);
+            } else if !self.hours.contains(time.hour()) {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+            } else if !self.minutes.contains(time.minute()) {
+                time = time + ChronoDuration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+}
+
+type JobFn = Box<dyn FnMut() -> Result<(), Error> + Send>;
+
+struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    /// Each run is delayed by a random duration up to this one, to spread the load of the jobs
+    /// scheduled at the same time
+    jitter: Duration,
+    run: JobFn,
+}
+
+/// Runs the jobs registered with [`Scheduler::job`] once started
+pub(crate) struct Scheduler {
+    pool: Pool,
+    metrics: Arc<Metrics>,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(pool: Pool, metrics: Arc<Metrics>) -> Self {
+        Self {
+            pool,
+            metrics,
+            jobs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn job<F>(
+        &mut self,
+        name: &'static str,
+        schedule: &str,
+        jitter: Duration,
+        run: F,
+    ) -> Result<&mut Self, Error>
+    where
+        F: FnMut() -> Result<(), Error> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name,
+            schedule: schedule.parse()?,
+            jitter,
+            run: Box::new(run),
+        });
+        Ok(self)
+    }
+
+    /// Spawns a thread for every job
+    pub(crate) fn start(self) -> Result<(), Error> {
+        let mut conn = self.pool.get()?;
+        for mut job in self.jobs {
+            conn.execute(
+                "INSERT INTO scheduled_jobs (name, schedule) VALUES ($1, $2)
+                 ON CONFLICT (name) DO UPDATE SET schedule = EXCLUDED.schedule",
+                &[&job.name, &job.schedule.as_str()],
+            )?;
+
+            let pool = self.pool.clone();
+            let metrics = self.metrics.clone();
+            supervise(job.name, self.metrics.clone(), move || loop {
+                let next = next_run(&pool, &job)?;
+                let wait = (next - Utc::now()).to_std().unwrap_or_default() + jitter(job.jitter);
+                debug!(
+                    "next run of job '{}' in {} seconds",
+                    job.name,
+                    wait.as_secs()
+                );
+                thread::sleep(wait);
+
+                run_job(&pool, &metrics, &mut job)?;
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The next time the job is due, in the past if a run was missed while the daemon was stopped
+fn next_run(pool: &Pool, job: &Job) -> Result<DateTime<Utc>, Error> {
+    let last_started: Option<DateTime<Utc>> = pool
+        .get()?
+        .query_one(
+            "SELECT last_started FROM scheduled_jobs WHERE name = $1",
+            &[&job.name],
+        )?
+        .get("last_started");
+
+    last_started
+        .and_then(|last_started| job.schedule.next_after(last_started))
+        .or_else(|| job.schedule.next_after(Utc::now()))
+        .ok_or_else(|| failure::format_err!("job '{}' is never scheduled", job.name))
+}
+
+fn jitter(max: Duration) -> Duration {
+    let max = max.as_millis() as u64;
+    if max == 0 {
+        return Duration::from_secs(0);
+    }
+    let mut random = [0; 8];
+    getrandom::getrandom(&mut random).expect("failed to generate the jitter");
+    Duration::from_millis(u64::from_le_bytes(random) % max)
+}
+
+fn run_job(pool: &Pool, metrics: &Metrics, job: &mut Job) -> Result<(), Error> {
+    let mut conn = pool.get()?;
+    // the lock is released when the connection is closed, even if the daemon crashes
+    let locked: bool = conn
+        .query_one("SELECT pg_try_advisory_lock(hashtext($1))", &[&job.name])?
+        .get(0);
+    if !locked {
+        info!("skipping job '{}', it's already running", job.name);
+        metrics
+            .scheduled_job_runs
+            .with_label_values(&[job.name, "skipped"])
+            .inc();
+        return Ok(());
+    }
+
+    conn.execute(
+        "UPDATE scheduled_jobs SET last_started = NOW() WHERE name = $1",
+        &[&job.name],
+    )?;
+    let error = match catch_unwind(AssertUnwindSafe(&mut job.run)) {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => Some(format!("{:?}", err)),
+        Err(payload) => Some(format!("panicked: {}", panic_message(&*payload))),
+    };
+    conn.execute(
+        "UPDATE scheduled_jobs
+         SET last_finished = NOW(),
+             last_success = CASE WHEN $2::TEXT IS NULL THEN NOW() ELSE last_success END,
+             last_error = $2
+         WHERE name = $1",
+        &[&job.name, &error],
+    )?;
+    conn.execute("SELECT pg_advisory_unlock(hashtext($1))", &[&job.name])?;
+
+    let result = if let Some(error) = &error {
+        error!("failed to run scheduled job '{}': {}", job.name, error);
+        "failure"
+    } else {
+        "success"
+    };
+    metrics
+        .scheduled_job_runs
+        .with_label_values(&[job.name, result])
+        .inc();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().into()
+    }
+
+    #[test]
+    fn parse_schedule() {
+        let schedule: Schedule = "*/15 1-3,22 * * 0".parse().unwrap();
+        assert_eq!(schedule.minutes, Field(1 | 1 << 15 | 1 << 30 | 1 << 45));
+        assert_eq!(schedule.hours, Field(1 << 1 | 1 << 2 | 1 << 3 | 1 << 22));
+        assert!(!schedule.restricted_days);
+
+        let sunday: Schedule = "0 0 * * 7".parse().unwrap();
+        assert!(sunday.days_of_week.contains(0));
+
+        for invalid in &[
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn next_run_time() {
+        let next = |expression: &str, after: &str| {
+            expression
+                .parse::<Schedule>()
+                .unwrap()
+                .next_after(time(after))
+        };
+
+        assert_eq!(
+            next("*/5 * * * *", "2021-07-01T10:02:30Z"),
+            Some(time("2021-07-01T10:05:00Z"))
+        );
+        assert_eq!(
+            next("*/5 * * * *", "2021-07-01T10:05:00Z"),
+            Some(time("2021-07-01T10:10:00Z"))
+        );
+        assert_eq!(
+            next("30 2 * * *", "2021-12-31T03:00:00Z"),
+            Some(time("2022-01-01T02:30:00Z"))
+        );
+        // the 4th of July 2021 is a Sunday
+        assert_eq!(
+            next("0 12 * * 0", "2021-07-01T00:00:00Z"),
+            Some(time("2021-07-04T12:00:00Z"))
+        );
+        // either day field matches when both are restricted
+        assert_eq!(
+            next("0 0 15 * 0", "2021-07-01T00:00:00Z"),
+            Some(time("2021-07-04T00:00:00Z"))
+        );
+        assert_eq!(next("0 0 30 2 *", "2021-07-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn job_runs_are_recorded() {
+        wrapper(|env| {
+            let metrics = env.metrics();
+            let mut job = Job {
+                name: "scheduler test job",
+                schedule: "0 * * * *".parse()?,
+                jitter: Duration::from_secs(0),
+                run: Box::new(|| Err(failure::err_msg("something went wrong"))),
+            };
+            env.db().conn().execute(
+                "INSERT INTO scheduled_jobs (name, schedule) VALUES ($1, '0 * * * *')",
+                &[&job.name],
+            )?;
+
+            run_job(&env.db().pool(), &metrics, &mut job)?;
+            let row = env.db().conn().query_one(
+                "SELECT last_started, last_success, last_error FROM scheduled_jobs WHERE name = $1",
+                &[&job.name],
+            )?;
+            assert!(row
+                .get::<_, Option<DateTime<Utc>>>("last_started")
+                .is_some());
+            assert!(row
+                .get::<_, Option<DateTime<Utc>>>("last_success")
+                .is_none());
+            assert!(row
+                .get::<_, String>("last_error")
+                .contains("something went wrong"));
+
+            // a run missed while the daemon was stopped happens right away
+            env.db().conn().execute(
+                "UPDATE scheduled_jobs SET last_started = NOW() - INTERVAL '3 hours' WHERE name = $1",
+                &[&job.name],
+            )?;
+            assert!(next_run(&env.db().pool(), &job)? < Utc::now());
+
+            job.run = Box::new(|| Ok(()));
+            run_job(&env.db().pool(), &metrics, &mut job)?;
+            let row = env.db().conn().query_one(
+                "SELECT last_success, last_error FROM scheduled_jobs WHERE name = $1",
+                &[&job.name],
+            )?;
+            assert!(row
+                .get::<_, Option<DateTime<Utc>>>("last_success")
+                .is_some());
+            assert!(row.get::<_, Option<String>>("last_error").is_none());
+
+            for result in &["failure", "success"] {
+                assert_eq!(
+                    metrics
+                        .scheduled_job_runs
+                        .with_label_values(&[job.name, result])
+                        .get(),
+                    1
+                );
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,150 @@
+//! Preview of the documentation of a local crate as it will look like on docs.rs
+//!
+//! The crate is built like any other one, but the release and its files are stored in a separate
+//! database schema, so that the preview doesn't mix with the crates already in the database.
+
+use crate::db::{self, Pool};
+use crate::repositories::RepositoryStatsUpdater;
+use crate::storage::StorageKind;
+use crate::{
+    BuildQueue, Config, Context, Index, Metrics, ReleasesCache, RustwideBuilder, Server, Storage,
+    VersionCache,
+};
+use failure::{Error, ResultExt};
+use log::info;
+use postgres::{Client, NoTls};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The schema holding the previewed crate, recreated every time a preview starts
+const SCHEMA: &str = "docs_rs_serve_local";
+
+struct LocalContext {
+    config: Arc<Config>,
+    build_queue: Arc<BuildQueue>,
+    storage: Arc<Storage>,
+    pool: Pool,
+    metrics: Arc<Metrics>,
+    index: Arc<Index>,
+    repository_stats_updater: Arc<RepositoryStatsUpdater>,
+    version_cache: Arc<VersionCache>,
+    releases_cache: Arc<ReleasesCache>,
+}
+
+impl LocalContext {
+    fn new(mut config: Config) -> Result<Self, Error> {
+        // the files are stored in the temporary schema too
+        config.storage_backend = StorageKind::Database;
+
+        let metrics = Arc::new(Metrics::new()?);
+        let pool = Pool::new_with_schema(&config, metrics.clone(), SCHEMA)?;
+        let index = Index::from_config(&config)?;
+
+        Ok(LocalContext {
+            build_queue: Arc::new(BuildQueue::new(pool.clone(), metrics.clone(), &config)),
+            storage: Arc::new(Storage::new(pool.clone(), metrics.clone(), &config)?),
+            index: Arc::new(index),
+            repository_stats_updater: Arc::new(RepositoryStatsUpdater::new(&config, pool.clone())),
+            version_cache: Arc::new(VersionCache::new(metrics.clone(), &config)),
+            releases_cache: Arc::new(ReleasesCache::new(metrics.clone(), &config)),
+            config: Arc::new(config),
+            pool,
+            metrics,
+        })
+    }
+}
+
+impl Context for LocalContext {
+    fn config(&self) -> Result<Arc<Config>, Error> {
+        Ok(self.config.clone())
+    }
+
+    fn build_queue(&self) -> Result<Arc<BuildQueue>, Error> {
+        Ok(self.build_queue.clone())
+    }
+
+    fn storage(&self) -> Result<Arc<Storage>, Error> {
+        Ok(self.storage.clone())
+    }
+
+    fn pool(&self) -> Result<Pool, Error> {
+        Ok(self.pool.clone())
+    }
+
+    fn metrics(&self) -> Result<Arc<Metrics>, Error> {
+        Ok(self.metrics.clone())
+    }
+
+    fn index(&self) -> Result<Arc<Index>, Error> {
+        Ok(self.index.clone())
+    }
+
+    fn repository_stats_updater(&self) -> Result<Arc<RepositoryStatsUpdater>, Error> {
+        Ok(self.repository_stats_updater.clone())
+    }
+
+    fn version_cache(&self) -> Result<Arc<VersionCache>, Error> {
+        Ok(self.version_cache.clone())
+    }
+
+    fn releases_cache(&self) -> Result<Arc<ReleasesCache>, Error> {
+        Ok(self.releases_cache.clone())
+    }
+}
+
+/// Drops what a previous preview left behind and creates the tables again
+fn reset_schema(config: &Config) -> Result<(), Error> {
+    let mut conn = Client::connect(&config.database_url, NoTls)?;
+    conn.batch_execute(&format!(
+        "
+            DROP SCHEMA IF EXISTS {0} CASCADE;
+            CREATE SCHEMA {0};
+            SET search_path TO {0}, public;
+        ",
+        SCHEMA
+    ))?;
+    db::migrate(None, &mut conn)?;
+
+    Ok(())
+}
+
+/// Builds the crate at `path` and serves its documentation on `socket_addr`, blocking until the
+/// server is stopped
+pub fn serve_local(config: Config, path: &Path, socket_addr: &str) -> Result<(), Error> {
+    reset_schema(&config).context("failed to create the preview database schema")?;
+    let context = LocalContext::new(config)?;
+
+    let mut builder = RustwideBuilder::init(&context)?;
+    builder
+        .update_toolchain()
+        .context("failed to update toolchain")?;
+    builder
+        .add_essential_files()
+        .context("failed to add essential files")?;
+    builder
+        .build_local_package(path)
+        .context("Building documentation failed")?;
+
+    let row = context.pool.get()?.query_one(
+        "SELECT crates.name, releases.version
+         FROM crates
+         INNER JOIN releases ON releases.crate_id = crates.id",
+        &[],
+    )?;
+    let name: String = row.get("name");
+    let version: String = row.get("version");
+
+    let server = Server::start(Some(socket_addr), false, &context)?;
+    info!(
+        "Documentation of {} {} available on http://{}/{}/{}",
+        name,
+        version,
+        server.addr(),
+        name,
+        version,
+    );
+
+    // Blocks indefinitely
+    drop(server);
+    Ok(())
+}
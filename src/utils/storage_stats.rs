@@ -0,0 +1,258 @@
+//! Accounting of the space taken by each release in the storage
+//!
+//! The sizes are recorded by the uploader after every build, the latest build replacing the
+//! previous ones, and the releases taking the most space are listed on `/releases/storage`. The
+//! size of the documentation is also broken down by kind of file, shown on the crate pages.
+
+use crate::error::Result;
+use crate::storage::StoredSize;
+use postgres::Client;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReleaseStorageStats {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) compressed: i64,
+    pub(crate) uncompressed: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct StorageTotals {
+    pub(crate) releases: i64,
+    pub(crate) compressed: i64,
+    pub(crate) uncompressed: i64,
+}
+
+/// The size of the documentation of a release before compression, by kind of file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct DocSizes {
+    /// The pages of the items
+    pub(crate) html: i64,
+    /// The pages of the source browser generated by rustdoc
+    pub(crate) src: i64,
+    /// The lists of the implementors of the traits
+    pub(crate) implementors: i64,
+    pub(crate) search_index: i64,
+    /// Everything else, e.g. the stylesheets, the scripts and the fonts
+    pub(crate) other: i64,
+}
+
+impl DocSizes {
+    /// Measures the documentation in `dir`, where the targets other than the default one are in
+    /// subdirectories named after them
+    pub(crate) fn from_dir(dir: &Path, targets: &[String]) -> Result<Self> {
+        let mut sizes = DocSizes::default();
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let path = entry.path().strip_prefix(dir)?;
+                *sizes.counter_for(path, targets) += entry.metadata()?.len() as i64;
+            }
+        }
+        Ok(sizes)
+    }
+
+    fn counter_for(&mut self, path: &Path, targets: &[String]) -> &mut i64 {
+        let path = match path.iter().next().and_then(|first| first.to_str()) {
+            Some(first) if targets.iter().any(|target| target == first) => {
+                path.strip_prefix(first).unwrap_or(path)
+            }
+            _ => path,
+        };
+        let first = path.iter().next().and_then(|first| first.to_str());
+        let file_name = path.file_name().and_then(|name| name.to_str());
+
+        if first == Some("src") {
+            &mut self.src
+        } else if first == Some("implementors") {
+            &mut self.implementors
+        } else if matches!(file_name, Some(name) if name.starts_with("search-index")) {
+            &mut self.search_index
+        } else if path.extension() == Some(OsStr::new("html")) {
+            &mut self.html
+        } else {
+            &mut self.other
+        }
+    }
+
+    pub(crate) fn total(&self) -> i64 {
+        self.html + self.src + self.implementors + self.search_index + self.other
+    }
+}
+
+pub(crate) fn record(
+    conn: &mut Client,
+    release_id: i32,
+    rustdoc: StoredSize,
+    sources: StoredSize,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO release_storage_stats (
+             release_id, rustdoc_compressed, rustdoc_uncompressed,
+             sources_compressed, sources_uncompressed
+         ) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (release_id) DO UPDATE SET
+             rustdoc_compressed = EXCLUDED.rustdoc_compressed,
+             rustdoc_uncompressed = EXCLUDED.rustdoc_uncompressed,
+             sources_compressed = EXCLUDED.sources_compressed,
+             sources_uncompressed = EXCLUDED.sources_uncompressed,
+             updated_at = NOW()",
+        &[
+            &release_id,
+            &(rustdoc.compressed as i64),
+            &(rustdoc.uncompressed as i64),
+            &(sources.compressed as i64),
+            &(sources.uncompressed as i64),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Replaces the sizes of the documentation of a release, they're removed when the latest build
+/// didn't upload any documentation
+pub(crate) fn record_doc_sizes(
+    conn: &mut Client,
+    release_id: i32,
+    sizes: Option<DocSizes>,
+) -> Result<()> {
+    if let Some(sizes) = sizes {
+        conn.execute(
+            "INSERT INTO doc_sizes (release_id, html, src, implementors, search_index, other)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (release_id) DO UPDATE SET
+                 html = EXCLUDED.html,
+                 src = EXCLUDED.src,
+                 implementors = EXCLUDED.implementors,
+                 search_index = EXCLUDED.search_index,
+                 other = EXCLUDED.other,
+                 updated_at = NOW()",
+            &[
+                &release_id,
+                &sizes.html,
+                &sizes.src,
+                &sizes.implementors,
+                &sizes.search_index,
+                &sizes.other,
+            ],
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM doc_sizes WHERE release_id = $1",
+            &[&release_id],
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn doc_sizes(conn: &mut Client, name: &str, version: &str) -> Result<Option<DocSizes>> {
+    Ok(conn
+        .query_opt(
+            "SELECT doc_sizes.html, doc_sizes.src, doc_sizes.implementors,
+                    doc_sizes.search_index, doc_sizes.other
+             FROM doc_sizes
+             INNER JOIN releases ON releases.id = doc_sizes.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version],
+        )?
+        .map(|row| DocSizes {
+            html: row.get("html"),
+            src: row.get("src"),
+            implementors: row.get("implementors"),
+            search_index: row.get("search_index"),
+            other: row.get("other"),
+        }))
+}
+
+/// The releases taking the most space in the storage once compressed, documentation and sources
+/// included
+pub(crate) fn top_consumers(conn: &mut Client, limit: i64) -> Result<Vec<ReleaseStorageStats>> {
+    Ok(conn
+        .query(
+            "SELECT
+                 crates.name,
+                 releases.version,
+                 stats.rustdoc_compressed + stats.sources_compressed AS compressed,
+                 stats.rustdoc_uncompressed + stats.sources_uncompressed AS uncompressed
+             FROM release_storage_stats AS stats
+             INNER JOIN releases ON releases.id = stats.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             ORDER BY compressed DESC, releases.id
+             LIMIT $1",
+            &[&limit],
+        )?
+        .into_iter()
+        .map(|row| ReleaseStorageStats {
+            name: row.get("name"),
+            version: row.get("version"),
+            compressed: row.get("compressed"),
+            uncompressed: row.get("uncompressed"),
+        })
+        .collect())
+}
+
+pub(crate) fn totals(conn: &mut Client) -> Result<StorageTotals> {
+    let row = conn.query_one(
+        "SELECT
+             COUNT(*) AS releases,
+             COALESCE(SUM(rustdoc_compressed + sources_compressed), 0)::BIGINT AS compressed,
+             COALESCE(SUM(rustdoc_uncompressed + sources_uncompressed), 0)::BIGINT AS uncompressed
+         FROM release_storage_stats",
+        &[],
+    )?;
+    Ok(StorageTotals {
+        releases: row.get("releases"),
+        compressed: row.get("compressed"),
+        uncompressed: row.get("uncompressed"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn doc_sizes_by_kind() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files: &[(&str, usize)] = &[
+            ("foo/index.html", 10),
+            ("foo/struct.Foo.html", 20),
+            ("src/foo/lib.rs.html", 30),
+            ("implementors/core/clone/trait.Clone.js", 40),
+            ("search-index-20210101.js", 50),
+            ("rustdoc-20210101.css", 60),
+            ("i686-pc-windows-msvc/foo/index.html", 1),
+            ("i686-pc-windows-msvc/src/foo/lib.rs.html", 2),
+            ("i686-pc-windows-msvc/search-index-20210101.js", 3),
+        ];
+        for (path, size) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, vec![b'a'; *size])?;
+        }
+
+        let sizes = DocSizes::from_dir(dir.path(), &["i686-pc-windows-msvc".into()])?;
+        assert_eq!(
+            sizes,
+            DocSizes {
+                html: 31,
+                src: 32,
+                implementors: 40,
+                search_index: 53,
+                other: 60,
+            }
+        );
+        assert_eq!(sizes.total(), 216);
+
+        // without the target, its directory is treated like the one of a module
+        let sizes = DocSizes::from_dir(dir.path(), &[])?;
+        assert_eq!(sizes.src, 30);
+        assert_eq!(sizes.html, 33);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,606 @@
+//! Pages of the administrative operations
+
+use super::{
+    admin_api::{AdminScope, ApiError},
+    admin_login::{self, AdminSession},
+    ErrorPage,
+};
+use crate::{
+    db::{
+        audit::{self, Auditor},
+        blacklist,
+        sandbox_overrides::{self, SandboxOverride},
+        Pool,
+    },
+    docbuilder::Limits,
+    impl_webpage,
+    web::page::WebPage,
+    BuildQueue, Config,
+};
+use iron::{
+    headers::{Authorization, Bearer},
+    status, IronResult, Request, Response, Url,
+};
+use serde::Serialize;
+use std::io::Read;
+
+/// Number of entries of the audit log shown per page
+const AUDIT_ENTRIES_PER_PAGE: i64 = 50;
+
+const MAX_FORM_SIZE: u64 = 4 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct AuditPage {
+    description: &'static str,
+    entries: Vec<audit::AuditEntry>,
+    /// Id of the last entry shown, if there are older ones
+    next_page: Option<i32>,
+}
+
+impl_webpage! {
+    AuditPage = "admin/audit.html",
+}
+
+/// Whether the request comes from an admin, logged in with GitHub or sending a token of the admin
+/// API in the `Authorization` header, whatever its scopes
+fn is_admin(req: &Request) -> bool {
+    let config = req.extensions.get::<Config>().unwrap();
+    let token = req.headers.get::<Authorization<Bearer>>();
+    token.is_some_and(|header| config.admin_api_tokens.contains(&header.token))
+        || AdminSession::from_request(req).is_some()
+}
+
+/// The audit log names the admins and the reasons they gave, only the admins can read it
+pub fn audit_handler(req: &mut Request) -> IronResult<Response> {
+    if !is_admin(req) {
+        let config = req.extensions.get::<Config>().unwrap();
+        let message = if admin_login::is_enabled(config) {
+            "log in with GitHub at /admin/login"
+        } else {
+            "send a token of the admin API in the Authorization header"
+        };
+        return ErrorPage {
+            title: "Only the admins can read the audit log",
+            message: Some(message.into()),
+            status: status::Unauthorized,
+        }
+        .into_response(req);
+    }
+
+    let before = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "before")
+        .and_then(|(_, before)| before.parse().ok());
+
+    let mut conn = extension!(req, Pool).get()?;
+    // one more entry than shown tells whether there's a next page
+    let mut entries = ctry!(
+        req,
+        audit::audit_entries(&mut conn, before, AUDIT_ENTRIES_PER_PAGE + 1)
+    );
+    let next_page = if entries.len() > AUDIT_ENTRIES_PER_PAGE as usize {
+        entries.truncate(AUDIT_ENTRIES_PER_PAGE as usize);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+
+    AuditPage {
+        description: "Crates deleted, blacklisted or queued by the administrators",
+        entries,
+        next_page,
+    }
+    .into_response(req)
+}
+
+/// Who can submit the forms of a page: the admins logged in with GitHub send the anti-CSRF token
+/// of their session, the others need a token of the admin API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct FormAuth {
+    /// The GitHub login of the admin
+    admin: Option<String>,
+    csrf_token: Option<String>,
+    /// Whether the admins can log in with GitHub
+    login_enabled: bool,
+}
+
+impl FormAuth {
+    fn new(req: &Request) -> Self {
+        let config = req.extensions.get::<Config>().unwrap();
+        let session = AdminSession::from_request(req);
+        Self {
+            csrf_token: session.as_ref().map(|session| session.csrf_token(config)),
+            admin: session.map(|session| session.login),
+            login_enabled: admin_login::is_enabled(config),
+        }
+    }
+}
+
+/// The fields identifying the admin and explaining the operation, which all the forms have
+#[derive(Debug, Default)]
+struct AdminForm {
+    token: Option<String>,
+    csrf_token: Option<String>,
+    reason: Option<String>,
+}
+
+impl AdminForm {
+    /// Reads the body of the form, the fields left empty are skipped
+    fn read(req: &mut Request) -> Result<(Self, Vec<(String, String)>), ApiError> {
+        let mut body = Vec::new();
+        (&mut req.body)
+            .take(MAX_FORM_SIZE)
+            .read_to_end(&mut body)
+            .map_err(|err| ApiError::new(status::BadRequest, err.to_string()))?;
+
+        let mut form = Self::default();
+        let mut fields = Vec::new();
+        for (key, value) in url::form_urlencoded::parse(&body) {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match &*key {
+                "token" => form.token = Some(value.to_owned()),
+                "csrf_token" => form.csrf_token = Some(value.to_owned()),
+                "reason" => form.reason = Some(value.to_owned()),
+                _ => fields.push((key.into_owned(), value.to_owned())),
+            }
+        }
+
+        Ok((form, fields))
+    }
+
+    /// Finds who submitted the form, the admin needs the `scope`
+    fn authenticate(self, req: &Request, scope: AdminScope) -> Result<Auditor, ApiError> {
+        let config = req.extensions.get::<Config>().unwrap();
+        if self.token.is_none() {
+            if let Some(session) = AdminSession::from_request(req) {
+                session.authorize(config, self.csrf_token.as_deref(), scope)?;
+                return Ok(session.auditor(self.reason));
+            }
+        }
+
+        Ok(config
+            .admin_api_tokens
+            .authenticate(self.token.as_deref(), scope)?
+            .auditor(self.reason))
+    }
+}
+
+/// Redirects to the page of the form once it's saved
+fn redirect_to(req: &Request, path: &str) -> IronResult<Response> {
+    let url = ctry!(
+        req,
+        Url::parse(&format!("{}{}", super::redirect_base(req), path)),
+    );
+    Ok(super::redirect(url))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct LimitsPage {
+    description: &'static str,
+    overrides: Vec<SandboxOverride>,
+    default_limits: Limits,
+    /// Why the submitted form was refused
+    error: Option<String>,
+    auth: FormAuth,
+    #[serde(skip)]
+    status: status::Status,
+}
+
+impl_webpage! {
+    LimitsPage = "admin/limits.html",
+    status = |page| page.status,
+}
+
+fn limits_page(req: &Request, error: Option<ApiError>) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let (status, error) = match error {
+        Some(error) => (error.status, Some(error.message)),
+        None => (status::Ok, None),
+    };
+
+    LimitsPage {
+        description: "Limits of the builds overridden for some crates",
+        overrides: ctry!(req, sandbox_overrides::list(&mut conn)),
+        default_limits: Limits::default(),
+        error,
+        auth: FormAuth::new(req),
+        status,
+    }
+    .into_response(req)
+}
+
+pub fn limits_handler(req: &mut Request) -> IronResult<Response> {
+    limits_page(req, None)
+}
+
+/// Creates, replaces or removes an override from the form of the limits page, which needs an
+/// admin API token or an admin logged in with GitHub, with the `limits` scope
+pub fn save_limits_handler(req: &mut Request) -> IronResult<Response> {
+    match save_limits(req) {
+        Ok(()) => redirect_to(req, "/admin/limits"),
+        Err(error) => limits_page(req, Some(error)),
+    }
+}
+
+fn save_limits(req: &mut Request) -> Result<(), ApiError> {
+    let (form, fields) = AdminForm::read(req)?;
+    let mut remove = false;
+    let mut limits = SandboxOverride::default();
+    for (key, value) in fields {
+        let value = value.as_str();
+        match &*key {
+            "action" => remove = value == "remove",
+            "crate_name" => limits.crate_name = value.to_owned(),
+            "max_memory_bytes" => limits.max_memory_bytes = Some(number(&key, value)?),
+            "max_disk_bytes" => limits.max_disk_bytes = Some(number(&key, value)?),
+            "timeout_seconds" => limits.timeout_seconds = Some(number(&key, value)?),
+            "max_targets" => limits.max_targets = Some(number(&key, value)?),
+            "max_upload_bytes" => limits.max_upload_bytes = Some(number(&key, value)?),
+            "max_files" => limits.max_files = Some(number(&key, value)?),
+            _ => {}
+        }
+    }
+
+    let auditor = form.authenticate(req, AdminScope::Limits)?;
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    if remove {
+        if auditor
+            .remove_sandbox_override(&mut conn, &limits.crate_name)?
+            .is_none()
+        {
+            return Err(ApiError::new(
+                status::NotFound,
+                format!("the limits of {} aren't overridden", limits.crate_name),
+            ));
+        }
+    } else {
+        limits
+            .validate()
+            .map_err(|err| ApiError::new(status::BadRequest, err.to_string()))?;
+        auditor.set_sandbox_override(&mut conn, &limits)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct CratesPage {
+    description: &'static str,
+    blacklist: Vec<String>,
+    /// Why the submitted form was refused
+    error: Option<String>,
+    auth: FormAuth,
+    #[serde(skip)]
+    status: status::Status,
+}
+
+impl_webpage! {
+    CratesPage = "admin/crates.html",
+    status = |page| page.status,
+}
+
+fn crates_page(req: &Request, error: Option<ApiError>) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let (status, error) = match error {
+        Some(error) => (error.status, Some(error.message)),
+        None => (status::Ok, None),
+    };
+
+    CratesPage {
+        description: "Crates queued or blacklisted by the administrators",
+        blacklist: ctry!(req, blacklist::list_crates(&mut conn)),
+        error,
+        auth: FormAuth::new(req),
+        status,
+    }
+    .into_response(req)
+}
+
+pub fn crates_handler(req: &mut Request) -> IronResult<Response> {
+    crates_page(req, None)
+}
+
+/// Queues a release or changes the blacklist from the forms of the crates page, which need the
+/// `queue` or `blacklist` scope
+pub fn save_crates_handler(req: &mut Request) -> IronResult<Response> {
+    match save_crates(req) {
+        Ok(()) => redirect_to(req, "/admin/crates"),
+        Err(error) => crates_page(req, Some(error)),
+    }
+}
+
+fn save_crates(req: &mut Request) -> Result<(), ApiError> {
+    let (form, fields) = AdminForm::read(req)?;
+    let (mut action, mut name, mut version) = (None, None, None);
+    // the same priority as `cratesfyi queue add`
+    let mut priority = 5;
+    for (key, value) in fields {
+        match &*key {
+            "action" => action = Some(value),
+            "crate_name" => name = Some(value),
+            "version" => version = Some(value),
+            "priority" => priority = number(&key, &value)?,
+            _ => {}
+        }
+    }
+    let name = name.ok_or_else(|| ApiError::new(status::BadRequest, "missing crate name"))?;
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    match action.as_deref() {
+        Some("queue") => {
+            let version =
+                version.ok_or_else(|| ApiError::new(status::BadRequest, "missing version"))?;
+            let auditor = form.authenticate(req, AdminScope::Queue)?;
+            let queue = req.extensions.get::<BuildQueue>().unwrap();
+            let registry = req.extensions.get::<Config>().unwrap().registry_url.clone();
+            auditor.queue_crate(
+                &mut conn,
+                queue,
+                &name,
+                &version,
+                priority,
+                registry.as_deref(),
+            )?;
+        }
+        Some("blacklist-add") => {
+            let auditor = form.authenticate(req, AdminScope::Blacklist)?;
+            if blacklist::is_blacklisted(&mut conn, &name)? {
+                return Err(ApiError::new(
+                    status::Conflict,
+                    format!("{} is already on the blacklist", name),
+                ));
+            }
+            auditor.blacklist_crate(&mut conn, &name)?;
+        }
+        Some("blacklist-remove") => {
+            let auditor = form.authenticate(req, AdminScope::Blacklist)?;
+            if !blacklist::is_blacklisted(&mut conn, &name)? {
+                return Err(ApiError::new(
+                    status::NotFound,
+                    format!("{} is not on the blacklist", name),
+                ));
+            }
+            auditor.unblacklist_crate(&mut conn, &name)?;
+        }
+        _ => return Err(ApiError::new(status::BadRequest, "unknown action")),
+    }
+
+    Ok(())
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ApiError> {
+    value
+        .parse()
+        .map_err(|_| ApiError::new(status::BadRequest, format!("{} must be a number", key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::Auditor;
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::StatusCode;
+
+    const TOKEN: &str = "limits-token-0123456789abcdefghijklmnopqrstuvwxyz";
+
+    #[test]
+    fn audit_log_pages() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config
+                    .admin_api_tokens
+                    .add("oncall", TOKEN, &[AdminScope::Limits])
+            });
+            let web = env.frontend();
+            let audit = |path: &str| web.get(path).bearer_auth(TOKEN).send();
+
+            Auditor::new("admin", Some("malware".into()))
+                .blacklist_crate(&mut env.db().conn(), "hidden")?;
+            for token in &[None, Some("invalid-token")] {
+                let mut req = web.get("/admin/audit");
+                if let Some(token) = token {
+                    req = req.bearer_auth(token);
+                }
+                let resp = req.send()?;
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+                assert!(!resp.text()?.contains("malware"));
+            }
+            env.db().conn().execute("DELETE FROM audit_log", &[])?;
+
+            let page = kuchiki::parse_html().one(audit("/admin/audit")?.text()?);
+            assert!(page
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("No operations")));
+
+            let auditor = Auditor::new("admin", Some("malware".into()));
+            for i in 0..=AUDIT_ENTRIES_PER_PAGE {
+                auditor.blacklist_crate(&mut env.db().conn(), &format!("crate-{}", i))?;
+            }
+
+            let page = kuchiki::parse_html().one(audit("/admin/audit")?.text()?);
+            let items: Vec<_> = page
+                .select(".audit-list > li")
+                .expect("missing list items")
+                .map(|el| el.text_contents())
+                .collect();
+            assert_eq!(items.len(), AUDIT_ENTRIES_PER_PAGE as usize);
+            assert!(items[0].contains("admin"));
+            assert!(items[0].contains("blacklist-add"));
+            assert!(items[0].contains("crate-50"));
+            assert!(items[0].contains("malware"));
+
+            let next = page
+                .select_first(".pagination a")
+                .expect("missing next page")
+                .attributes
+                .borrow()
+                .get("href")
+                .unwrap()
+                .to_string();
+            let page = kuchiki::parse_html().one(audit(&next)?.text()?);
+            let items: Vec<_> = page
+                .select(".audit-list > li")
+                .expect("missing list items")
+                .map(|el| el.text_contents())
+                .collect();
+            assert_eq!(items.len(), 1);
+            assert!(items[0].contains("crate-0"));
+            assert!(page.select_first(".pagination a").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn limits_page() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config
+                    .admin_api_tokens
+                    .add("oncall", TOKEN, &[AdminScope::Limits])
+            });
+            let web = env.frontend();
+            let submit = |form: &[(&str, &str)]| -> Result<_, failure::Error> {
+                let resp = web.post("/admin/limits").form(form).send()?;
+                Ok((resp.status(), kuchiki::parse_html().one(resp.text()?)))
+            };
+            let error = |page: &kuchiki::NodeRef| {
+                page.select_first(".limits-error")
+                    .map(|el| el.text_contents().trim().to_string())
+                    .ok()
+            };
+
+            let (status, page) = submit(&[("crate_name", "foo"), ("max_targets", "2")])?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(error(&page).as_deref(), Some("missing or invalid token"));
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("max_targets", "two"),
+                ("token", TOKEN),
+            ])?;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(
+                error(&page).as_deref(),
+                Some("max_targets must be a number")
+            );
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("max_targets", "2"),
+                ("timeout_seconds", ""),
+                ("reason", "many targets"),
+                ("token", TOKEN),
+                ("action", "save"),
+            ])?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(error(&page), None);
+            let rows: Vec<_> = page
+                .select(".limits-overrides tbody tr")
+                .expect("missing table rows")
+                .map(|el| el.text_contents())
+                .collect();
+            assert_eq!(rows.len(), 1);
+            assert!(rows[0].contains("foo"));
+            assert_eq!(Limits::for_crate(&mut env.db().conn(), "foo")?.targets(), 2);
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("token", TOKEN),
+                ("action", "remove"),
+            ])?;
+            assert_eq!(status, StatusCode::OK);
+            assert!(page.select_first(".limits-overrides").is_err());
+
+            let actions: Vec<_> = audit::audit_entries(&mut env.db().conn(), None, 10)?
+                .into_iter()
+                .map(|entry| (entry.actor, entry.action))
+                .collect();
+            assert_eq!(
+                actions,
+                vec![
+                    ("api:oncall".to_string(), "limits-remove".to_string()),
+                    ("api:oncall".to_string(), "limits-set".to_string()),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn crates_page() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config
+                    .admin_api_tokens
+                    .add("oncall", TOKEN, &[AdminScope::Blacklist])
+            });
+            let web = env.frontend();
+            let submit = |form: &[(&str, &str)]| -> Result<_, failure::Error> {
+                let resp = web.post("/admin/crates").form(form).send()?;
+                Ok((resp.status(), kuchiki::parse_html().one(resp.text()?)))
+            };
+            let error = |page: &kuchiki::NodeRef| {
+                page.select_first(".crates-error")
+                    .map(|el| el.text_contents().trim().to_string())
+                    .ok()
+            };
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("version", "0.1.0"),
+                ("token", TOKEN),
+                ("action", "queue"),
+            ])?;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert!(error(&page).unwrap().contains("queue"));
+            assert_eq!(env.build_queue().pending_count()?, 0);
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("reason", "malware"),
+                ("token", TOKEN),
+                ("action", "blacklist-add"),
+            ])?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(error(&page), None);
+            let crates: Vec<_> = page
+                .select(".blacklist li")
+                .expect("missing list items")
+                .map(|el| el.text_contents())
+                .collect();
+            assert_eq!(crates, vec!["foo"]);
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("token", TOKEN),
+                ("action", "blacklist-add"),
+            ])?;
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert_eq!(
+                error(&page).as_deref(),
+                Some("foo is already on the blacklist")
+            );
+
+            let (status, page) = submit(&[
+                ("crate_name", "foo"),
+                ("token", TOKEN),
+                ("action", "blacklist-remove"),
+            ])?;
+            assert_eq!(status, StatusCode::OK);
+            assert!(page.select_first(".blacklist li").is_err());
+            assert!(!blacklist::is_blacklisted(&mut env.db().conn(), "foo")?);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,964 @@
+//! Administrative API, under `/admin/api`
+//!
+//! Every request needs one of the bearer tokens of `DOCSRS_ADMIN_API_TOKENS`, and the token
+//! needs the scope of the endpoint. The operations are recorded in the audit log under the name
+//! of the token.
+
+use crate::{
+    db::{
+        audit::Auditor,
+        blacklist,
+        crate_redirects::{self, CrateRedirect, InvalidRedirectError},
+        sandbox_overrides::{self, SandboxOverride},
+        Pool,
+    },
+    docbuilder::Limits,
+    web::page::TemplateData,
+    BuildQueue, Config, Storage,
+};
+use failure::Fail;
+use iron::{
+    headers::{Authorization, Bearer, CacheControl, CacheDirective, ContentType},
+    middleware::Handler,
+    status::{self, Status},
+    IronResult, Request, Response,
+};
+use router::Router;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{collections::HashSet, fmt, io::Read, str::FromStr};
+
+/// Largest request body accepted by the endpoints
+const MAX_BODY_SIZE: u64 = 16 * 1024;
+
+/// Tokens shorter than this are refused, so that they can't be guessed
+const MIN_TOKEN_LENGTH: usize = 32;
+
+/// The operations a token can perform
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum AdminScope {
+    /// Adding crates to the build queue
+    Queue,
+    /// Adding and removing crates from the blacklist
+    Blacklist,
+    /// Deleting crates and releases
+    Delete,
+    /// Changing the limits of the builds
+    Limits,
+    /// Redirecting the documentation of renamed crates
+    Redirects,
+    /// Reloading the templates of the web server
+    Templates,
+}
+
+impl AdminScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            AdminScope::Queue => "queue",
+            AdminScope::Blacklist => "blacklist",
+            AdminScope::Delete => "delete",
+            AdminScope::Limits => "limits",
+            AdminScope::Redirects => "redirects",
+            AdminScope::Templates => "templates",
+        }
+    }
+}
+
+impl fmt::Display for AdminScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid admin API tokens: {}", _0)]
+pub(crate) struct InvalidAdminTokensError(String);
+
+impl FromStr for AdminScope {
+    type Err = InvalidAdminTokensError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "queue" => Ok(AdminScope::Queue),
+            "blacklist" => Ok(AdminScope::Blacklist),
+            "delete" => Ok(AdminScope::Delete),
+            "limits" => Ok(AdminScope::Limits),
+            "redirects" => Ok(AdminScope::Redirects),
+            "templates" => Ok(AdminScope::Templates),
+            _ => Err(InvalidAdminTokensError(format!("unknown scope {}", input))),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct AdminToken {
+    /// Identifies whoever uses the token in the audit log
+    name: String,
+    token: String,
+    scopes: HashSet<AdminScope>,
+}
+
+impl AdminToken {
+    /// Performs the operations in the name of the token
+    pub(super) fn auditor(&self, reason: Option<String>) -> Auditor {
+        Auditor::new(format!("api:{}", self.name), reason)
+    }
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminToken")
+            .field("name", &self.name)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+/// The tokens accepted by the admin API, parsed from a space separated list of
+/// `<name>:<scope>,<scope>:<token>`. The API is disabled without any token.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AdminTokens(Vec<AdminToken>);
+
+impl AdminTokens {
+    #[cfg(test)]
+    pub(crate) fn add(&mut self, name: &str, token: &str, scopes: &[AdminScope]) {
+        self.0.push(AdminToken {
+            name: name.into(),
+            token: token.into(),
+            scopes: scopes.iter().copied().collect(),
+        });
+    }
+
+    fn find(&self, token: &str) -> Option<&AdminToken> {
+        self.0
+            .iter()
+            .find(|candidate| constant_time_eq(candidate.token.as_bytes(), token.as_bytes()))
+    }
+
+    /// Whether the token is one of the admin tokens, whatever its scopes
+    pub(super) fn contains(&self, token: &str) -> bool {
+        self.find(token).is_some()
+    }
+
+    /// Finds the token, which needs to have the `scope`
+    pub(super) fn authenticate(
+        &self,
+        token: Option<&str>,
+        scope: AdminScope,
+    ) -> Result<&AdminToken, ApiError> {
+        let token = token
+            .and_then(|token| self.find(token))
+            .ok_or_else(|| ApiError::new(status::Unauthorized, "missing or invalid token"))?;
+
+        if token.scopes.contains(&scope) {
+            Ok(token)
+        } else {
+            Err(ApiError::new(
+                status::Forbidden,
+                format!("the token doesn't have the {} scope", scope),
+            ))
+        }
+    }
+}
+
+impl FromStr for AdminTokens {
+    type Err = InvalidAdminTokensError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens: Vec<AdminToken> = Vec::new();
+        for entry in input.split_whitespace() {
+            let mut parts = entry.splitn(3, ':');
+            let (name, scopes, token) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(scopes), Some(token)) if !name.is_empty() => {
+                    (name, scopes, token)
+                }
+                _ => {
+                    return Err(InvalidAdminTokensError(
+                        "expected <name>:<scopes>:<token>".into(),
+                    ))
+                }
+            };
+            if token.len() < MIN_TOKEN_LENGTH {
+                return Err(InvalidAdminTokensError(format!(
+                    "the token of {} is shorter than {} characters",
+                    name, MIN_TOKEN_LENGTH
+                )));
+            }
+            if tokens
+                .iter()
+                .any(|other| other.name == name || other.token == token)
+            {
+                return Err(InvalidAdminTokensError(format!(
+                    "the name or the token of {} is used twice",
+                    name
+                )));
+            }
+
+            tokens.push(AdminToken {
+                name: name.into(),
+                token: token.into(),
+                scopes: scopes
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+
+        Ok(Self(tokens))
+    }
+}
+
+/// Compares the tokens without leaking how much of them matched through the timing
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Why a request to the admin API failed, returned to the client as JSON
+#[derive(Debug)]
+pub(super) struct ApiError {
+    pub(super) status: Status,
+    pub(super) message: String,
+}
+
+impl ApiError {
+    pub(super) fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl<E: Into<failure::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        let err = err.into();
+        log::error!("admin API request failed: {:?}", err);
+        Self::new(status::InternalServerError, err.to_string())
+    }
+}
+
+pub(super) type ApiHandler = fn(&mut Request, &AdminToken) -> Result<Value, ApiError>;
+
+/// Only calls the handler of an endpoint if the request has a token with the scope of the
+/// endpoint, added to all the routes of the admin API by the router
+pub(super) struct AdminApi {
+    scope: AdminScope,
+    handler: ApiHandler,
+}
+
+impl AdminApi {
+    pub(super) fn new(scope: AdminScope, handler: ApiHandler) -> Self {
+        Self { scope, handler }
+    }
+
+    fn authenticate(&self, req: &Request) -> Result<AdminToken, ApiError> {
+        let config = req
+            .extensions
+            .get::<Config>()
+            .expect("missing the config extension");
+        let header = req.headers.get::<Authorization<Bearer>>();
+        config
+            .admin_api_tokens
+            .authenticate(header.map(|header| header.token.as_str()), self.scope)
+            .cloned()
+    }
+}
+
+impl Handler for AdminApi {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let result = self
+            .authenticate(req)
+            .and_then(|token| (self.handler)(req, &token));
+        let (status, body) = match result {
+            Ok(body) => (status::Ok, body),
+            Err(err) => (err.status, json!({ "error": err.message })),
+        };
+
+        let mut resp = Response::with((status, body.to_string()));
+        resp.headers.set(ContentType::json());
+        resp.headers
+            .set(CacheControl(vec![CacheDirective::NoStore]));
+        Ok(resp)
+    }
+}
+
+fn param<'a>(req: &'a Request, name: &str) -> &'a str {
+    req.extensions
+        .get::<Router>()
+        .and_then(|router| router.find(name))
+        .expect("missing route parameter")
+}
+
+/// The JSON body of the request, the default value when it's empty
+fn body<T: DeserializeOwned + Default>(req: &mut Request) -> Result<T, ApiError> {
+    let mut body = Vec::new();
+    (&mut req.body)
+        .take(MAX_BODY_SIZE)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::new(status::BadRequest, err.to_string()))?;
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+
+    serde_json::from_slice(&body).map_err(|err| ApiError::new(status::BadRequest, err.to_string()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Reason {
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct QueueRequest {
+    priority: i32,
+    reason: Option<String>,
+}
+
+impl Default for QueueRequest {
+    fn default() -> Self {
+        // the same priority as `cratesfyi queue add`
+        Self {
+            priority: 5,
+            reason: None,
+        }
+    }
+}
+
+/// `POST /admin/api/queue/:name/:version`
+pub(super) fn queue_handler(req: &mut Request, token: &AdminToken) -> Result<Value, ApiError> {
+    let request: QueueRequest = body(req)?;
+    let (name, version) = (param(req, "name"), param(req, "version"));
+    let queue = req.extensions.get::<BuildQueue>().unwrap();
+    let registry = req.extensions.get::<Config>().unwrap().registry_url.clone();
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    token.auditor(request.reason).queue_crate(
+        &mut conn,
+        queue,
+        name,
+        version,
+        request.priority,
+        registry.as_deref(),
+    )?;
+
+    Ok(json!({ "name": name, "version": version, "priority": request.priority }))
+}
+
+/// `GET /admin/api/blacklist`
+pub(super) fn blacklist_handler(req: &mut Request, _: &AdminToken) -> Result<Value, ApiError> {
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    Ok(json!({ "crates": blacklist::list_crates(&mut conn)? }))
+}
+
+/// `POST /admin/api/blacklist/:name`
+pub(super) fn blacklist_add_handler(
+    req: &mut Request,
+    token: &AdminToken,
+) -> Result<Value, ApiError> {
+    let Reason { reason } = body(req)?;
+    let name = param(req, "name");
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    if blacklist::is_blacklisted(&mut conn, name)? {
+        return Err(ApiError::new(
+            status::Conflict,
+            format!("{} is already on the blacklist", name),
+        ));
+    }
+    token.auditor(reason).blacklist_crate(&mut conn, name)?;
+
+    Ok(json!({ "name": name }))
+}
+
+/// `DELETE /admin/api/blacklist/:name`
+pub(super) fn blacklist_remove_handler(
+    req: &mut Request,
+    token: &AdminToken,
+) -> Result<Value, ApiError> {
+    let Reason { reason } = body(req)?;
+    let name = param(req, "name");
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    if !blacklist::is_blacklisted(&mut conn, name)? {
+        return Err(ApiError::new(
+            status::NotFound,
+            format!("{} is not on the blacklist", name),
+        ));
+    }
+    token.auditor(reason).unblacklist_crate(&mut conn, name)?;
+
+    Ok(json!({ "name": name }))
+}
+
+/// `DELETE /admin/api/crates/:name` and `DELETE /admin/api/crates/:name/:version`
+pub(super) fn delete_handler(req: &mut Request, token: &AdminToken) -> Result<Value, ApiError> {
+    let Reason { reason } = body(req)?;
+    let name = param(req, "name");
+    let version = req
+        .extensions
+        .get::<Router>()
+        .and_then(|router| router.find("version"));
+    let storage = req.extensions.get::<Storage>().unwrap();
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    let exists = conn
+        .query_opt(
+            "SELECT 1
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND ($2::TEXT IS NULL OR releases.version = $2)
+             LIMIT 1",
+            &[&name, &version],
+        )?
+        .is_some();
+    if !exists {
+        return Err(ApiError::new(status::NotFound, "no such release"));
+    }
+
+    let auditor = token.auditor(reason);
+    match version {
+        Some(version) => auditor.delete_version(&mut conn, storage, name, version)?,
+        None => auditor.delete_crate(&mut conn, storage, name)?,
+    }
+
+    Ok(json!({ "name": name, "version": version }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsRequest {
+    max_memory_bytes: Option<i64>,
+    max_disk_bytes: Option<i64>,
+    timeout_seconds: Option<i32>,
+    max_targets: Option<i32>,
+    max_upload_bytes: Option<i64>,
+    max_files: Option<i32>,
+    reason: Option<String>,
+}
+
+/// `GET /admin/api/limits`
+pub(super) fn limits_handler(req: &mut Request, _: &AdminToken) -> Result<Value, ApiError> {
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    Ok(json!({ "overrides": sandbox_overrides::list(&mut conn)? }))
+}
+
+/// `GET /admin/api/limits/:name`, the override of the crate and the limits its builds get
+pub(super) fn crate_limits_handler(req: &mut Request, _: &AdminToken) -> Result<Value, ApiError> {
+    let name = param(req, "name");
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    Ok(json!({
+        "override": sandbox_overrides::get(&mut conn, name)?,
+        "limits": Limits::for_crate(&mut conn, name)?,
+    }))
+}
+
+/// `POST /admin/api/limits/:name`, replacing all the limits overridden for the crate
+pub(super) fn set_limits_handler(req: &mut Request, token: &AdminToken) -> Result<Value, ApiError> {
+    let request: LimitsRequest = body(req)?;
+    let limits = SandboxOverride {
+        crate_name: param(req, "name").into(),
+        max_memory_bytes: request.max_memory_bytes,
+        max_disk_bytes: request.max_disk_bytes,
+        timeout_seconds: request.timeout_seconds,
+        max_targets: request.max_targets,
+        max_upload_bytes: request.max_upload_bytes,
+        max_files: request.max_files,
+    };
+    limits
+        .validate()
+        .map_err(|err| ApiError::new(status::BadRequest, err.to_string()))?;
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    token
+        .auditor(request.reason)
+        .set_sandbox_override(&mut conn, &limits)?;
+
+    Ok(json!({ "override": limits }))
+}
+
+/// `DELETE /admin/api/limits/:name`
+pub(super) fn remove_limits_handler(
+    req: &mut Request,
+    token: &AdminToken,
+) -> Result<Value, ApiError> {
+    let Reason { reason } = body(req)?;
+    let name = param(req, "name");
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    match token
+        .auditor(reason)
+        .remove_sandbox_override(&mut conn, name)?
+    {
+        Some(removed) => Ok(json!({ "override": removed })),
+        None => Err(ApiError::new(
+            status::NotFound,
+            format!("the limits of {} aren't overridden", name),
+        )),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedirectRequest {
+    to: String,
+    reason: Option<String>,
+}
+
+/// `GET /admin/api/redirects`
+pub(super) fn redirects_handler(req: &mut Request, _: &AdminToken) -> Result<Value, ApiError> {
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    Ok(json!({ "redirects": crate_redirects::list(&mut conn)? }))
+}
+
+/// `POST /admin/api/redirects/:name`, redirecting the documentation of the crate to the crate `to`
+pub(super) fn set_redirect_handler(
+    req: &mut Request,
+    token: &AdminToken,
+) -> Result<Value, ApiError> {
+    let request: RedirectRequest = body(req)?;
+    let redirect = CrateRedirect {
+        old_name: param(req, "name").into(),
+        new_name: request.to,
+    };
+    redirect
+        .validate()
+        .map_err(|err| ApiError::new(status::BadRequest, err.to_string()))?;
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    token
+        .auditor(request.reason)
+        .set_crate_redirect(&mut conn, &redirect)
+        .map_err(|err| match err.downcast::<InvalidRedirectError>() {
+            // the redirect is valid, but its target is redirected itself
+            Ok(err) => ApiError::new(status::Conflict, err.to_string()),
+            Err(err) => err.into(),
+        })?;
+
+    Ok(json!({ "redirect": redirect }))
+}
+
+/// `DELETE /admin/api/redirects/:name`
+pub(super) fn remove_redirect_handler(
+    req: &mut Request,
+    token: &AdminToken,
+) -> Result<Value, ApiError> {
+    let Reason { reason } = body(req)?;
+    let name = param(req, "name");
+
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    match token
+        .auditor(reason)
+        .remove_crate_redirect(&mut conn, name)?
+    {
+        Some(removed) => Ok(json!({ "redirect": removed })),
+        None => Err(ApiError::new(
+            status::NotFound,
+            format!("{} isn't redirected", name),
+        )),
+    }
+}
+
+/// `POST /admin/api/templates/reload`, picking up the templates changed on disk without
+/// restarting the server
+pub(super) fn reload_templates_handler(
+    req: &mut Request,
+    _: &AdminToken,
+) -> Result<Value, ApiError> {
+    let mut conn = req.extensions.get::<Pool>().unwrap().get()?;
+    req.extensions
+        .get::<TemplateData>()
+        .unwrap()
+        .reload(&mut conn)?;
+    Ok(json!({ "reloaded": true }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::audit_entries;
+    use crate::test::{wrapper, TestEnvironment};
+    use reqwest::{Method, StatusCode};
+
+    const QUEUE_TOKEN: &str = "queue-token-0123456789abcdefghijklmnopqrstuvwxyz";
+    const ADMIN_TOKEN: &str = "admin-token-0123456789abcdefghijklmnopqrstuvwxyz";
+
+    fn setup(env: &TestEnvironment) {
+        env.override_config(|config| {
+            config
+                .admin_api_tokens
+                .add("ci", QUEUE_TOKEN, &[AdminScope::Queue]);
+            config.admin_api_tokens.add(
+                "oncall",
+                ADMIN_TOKEN,
+                &[
+                    AdminScope::Blacklist,
+                    AdminScope::Delete,
+                    AdminScope::Limits,
+                    AdminScope::Redirects,
+                    AdminScope::Templates,
+                ],
+            );
+        });
+    }
+
+    fn request(
+        env: &TestEnvironment,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<(StatusCode, Value), failure::Error> {
+        let mut req = env.frontend().build_request(method, path);
+        if let Some(token) = token {
+            req = req.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        let resp = req.send()?;
+        Ok((resp.status(), resp.json()?))
+    }
+
+    #[test]
+    fn parse_tokens() {
+        let tokens: AdminTokens = format!(
+            "ci:queue:{} oncall:queue,blacklist,delete,limits,redirects,templates:{}",
+            QUEUE_TOKEN, ADMIN_TOKEN
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(tokens.find(QUEUE_TOKEN).unwrap().name, "ci");
+        assert_eq!(tokens.find(ADMIN_TOKEN).unwrap().scopes.len(), 6);
+        assert!(tokens.find("queue-token").is_none());
+        assert!(tokens.find("").is_none());
+        assert_eq!("".parse::<AdminTokens>().unwrap(), AdminTokens::default());
+
+        for invalid in &[
+            format!("ci:{}", QUEUE_TOKEN),
+            format!("ci:rebuild:{}", QUEUE_TOKEN),
+            "ci:queue:short".to_string(),
+            format!("ci:queue:{} ci:delete:{}", QUEUE_TOKEN, ADMIN_TOKEN),
+            format!("ci:queue:{} oncall:delete:{}", QUEUE_TOKEN, QUEUE_TOKEN),
+        ] {
+            assert!(invalid.parse::<AdminTokens>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn authentication() {
+        wrapper(|env| {
+            setup(env);
+
+            for token in &[None, Some("wrong"), Some(&ADMIN_TOKEN[1..])] {
+                let (status, body) =
+                    request(env, Method::GET, "/admin/api/blacklist", *token, None)?;
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(body["error"], "missing or invalid token");
+            }
+
+            let (status, body) = request(
+                env,
+                Method::GET,
+                "/admin/api/blacklist",
+                Some(QUEUE_TOKEN),
+                None,
+            )?;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(body["error"], "the token doesn't have the blacklist scope");
+
+            let (status, body) = request(
+                env,
+                Method::GET,
+                "/admin/api/blacklist",
+                Some(ADMIN_TOKEN),
+                None,
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, json!({ "crates": [] }));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn blacklist() {
+        wrapper(|env| {
+            setup(env);
+            let add = |body| {
+                request(
+                    env,
+                    Method::POST,
+                    "/admin/api/blacklist/foo",
+                    Some(ADMIN_TOKEN),
+                    body,
+                )
+            };
+
+            let (status, _) = add(Some(json!({ "reason": "spam" })))?;
+            assert_eq!(status, StatusCode::OK);
+            assert!(blacklist::is_blacklisted(&mut env.db().conn(), "foo")?);
+            assert_eq!(add(None)?.0, StatusCode::CONFLICT);
+            assert_eq!(
+                add(Some(json!({ "unknown": true })))?.0,
+                StatusCode::BAD_REQUEST
+            );
+
+            let remove = || {
+                request(
+                    env,
+                    Method::DELETE,
+                    "/admin/api/blacklist/foo",
+                    Some(ADMIN_TOKEN),
+                    None,
+                )
+            };
+            assert_eq!(remove()?.0, StatusCode::OK);
+            assert!(!blacklist::is_blacklisted(&mut env.db().conn(), "foo")?);
+            assert_eq!(remove()?.0, StatusCode::NOT_FOUND);
+
+            let entries = audit_entries(&mut env.db().conn(), None, 10)?;
+            let summary: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.actor.as_str(),
+                        entry.action.as_str(),
+                        entry.reason.as_deref(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("api:oncall", "blacklist-remove", None),
+                    ("api:oncall", "blacklist-add", Some("spam")),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_and_delete() {
+        wrapper(|env| {
+            setup(env);
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            env.fake_release().name("foo").version("0.2.0").create()?;
+
+            let (status, body) = request(
+                env,
+                Method::POST,
+                "/admin/api/queue/foo/0.2.0",
+                Some(QUEUE_TOKEN),
+                Some(json!({ "priority": -10 })),
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["priority"], -10);
+            let queued = env.build_queue().queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(
+                (queued[0].version.as_str(), queued[0].priority),
+                ("0.2.0", -10)
+            );
+
+            let delete = |path| request(env, Method::DELETE, path, Some(ADMIN_TOKEN), None);
+            assert_eq!(
+                delete("/admin/api/crates/foo/0.3.0")?.0,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(delete("/admin/api/crates/foo/0.1.0")?.0, StatusCode::OK);
+            let versions: Vec<String> = env
+                .db()
+                .conn()
+                .query("SELECT version FROM releases", &[])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(versions, vec!["0.2.0"]);
+
+            assert_eq!(delete("/admin/api/crates/foo")?.0, StatusCode::OK);
+            assert_eq!(delete("/admin/api/crates/foo")?.0, StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn limits() {
+        wrapper(|env| {
+            setup(env);
+            let limits = |method, body| {
+                request(
+                    env,
+                    method,
+                    "/admin/api/limits/foo",
+                    Some(ADMIN_TOKEN),
+                    body,
+                )
+            };
+
+            let (status, body) = limits(Method::GET, None)?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["override"], Value::Null);
+            assert_eq!(body["limits"], serde_json::to_value(Limits::default())?);
+
+            for invalid in &[
+                json!({}),
+                json!({ "max_targets": 0 }),
+                json!({ "timeout_seconds": 7 * 24 * 60 * 60 }),
+                json!({ "max_memory": 1024 }),
+            ] {
+                let (status, _) = limits(Method::POST, Some(invalid.clone()))?;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{}", invalid);
+            }
+
+            let (status, body) = limits(
+                Method::POST,
+                Some(json!({ "max_targets": 2, "timeout_seconds": 3600, "reason": "big crate" })),
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["override"]["max_targets"], 2);
+            let expected = Limits::for_crate(&mut env.db().conn(), "foo")?;
+            assert_eq!(expected.targets(), 2);
+            assert_eq!(expected.timeout().as_secs(), 3600);
+
+            // the limits that aren't given are reset
+            limits(Method::POST, Some(json!({ "max_files": 10 })))?;
+            let (_, body) = request(
+                env,
+                Method::GET,
+                "/admin/api/limits",
+                Some(ADMIN_TOKEN),
+                None,
+            )?;
+            assert_eq!(body["overrides"].as_array().unwrap().len(), 1);
+            assert_eq!(body["overrides"][0]["crate_name"], "foo");
+            assert_eq!(body["overrides"][0]["max_files"], 10);
+            assert_eq!(body["overrides"][0]["max_targets"], Value::Null);
+
+            assert_eq!(limits(Method::DELETE, None)?.0, StatusCode::OK);
+            assert_eq!(limits(Method::DELETE, None)?.0, StatusCode::NOT_FOUND);
+            assert_eq!(
+                Limits::for_crate(&mut env.db().conn(), "foo")?,
+                Limits::default()
+            );
+
+            let actions: Vec<_> = audit_entries(&mut env.db().conn(), None, 10)?
+                .into_iter()
+                .map(|entry| (entry.action, entry.target))
+                .collect();
+            assert_eq!(
+                actions,
+                vec![
+                    ("limits-remove".into(), "foo (max_files 10)".into()),
+                    ("limits-set".into(), "foo (max_files 10)".into()),
+                    (
+                        "limits-set".into(),
+                        "foo (timeout_seconds 3600, max_targets 2)".into()
+                    ),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn redirects() {
+        wrapper(|env| {
+            setup(env);
+            let redirect = |method, body| {
+                request(
+                    env,
+                    method,
+                    "/admin/api/redirects/foo",
+                    Some(ADMIN_TOKEN),
+                    body,
+                )
+            };
+
+            for invalid in &[json!({}), json!({ "to": "foo" }), json!({ "to": "../bar" })] {
+                let (status, _) = redirect(Method::POST, Some(invalid.clone()))?;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{}", invalid);
+            }
+
+            let (status, body) = redirect(
+                Method::POST,
+                Some(json!({ "to": "foo-rs", "reason": "renamed" })),
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["redirect"]["new_name"], "foo-rs");
+            let (status, _) = request(
+                env,
+                Method::POST,
+                "/admin/api/redirects/bar",
+                Some(ADMIN_TOKEN),
+                Some(json!({ "to": "foo" })),
+            )?;
+            assert_eq!(status, StatusCode::CONFLICT);
+
+            let (_, body) = request(
+                env,
+                Method::GET,
+                "/admin/api/redirects",
+                Some(ADMIN_TOKEN),
+                None,
+            )?;
+            assert_eq!(
+                body,
+                json!({ "redirects": [{ "old_name": "foo", "new_name": "foo-rs" }] })
+            );
+
+            assert_eq!(redirect(Method::DELETE, None)?.0, StatusCode::OK);
+            assert_eq!(redirect(Method::DELETE, None)?.0, StatusCode::NOT_FOUND);
+
+            let actions: Vec<_> = audit_entries(&mut env.db().conn(), None, 10)?
+                .into_iter()
+                .map(|entry| (entry.action, entry.target, entry.reason))
+                .collect();
+            assert_eq!(
+                actions,
+                vec![
+                    ("redirect-remove".into(), "foo -> foo-rs".into(), None),
+                    (
+                        "redirect-set".into(),
+                        "foo -> foo-rs".into(),
+                        Some("renamed".into())
+                    ),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reload_templates() {
+        wrapper(|env| {
+            setup(env);
+
+            let reload = |token| {
+                request(
+                    env,
+                    Method::POST,
+                    "/admin/api/templates/reload",
+                    Some(token),
+                    None,
+                )
+            };
+            assert_eq!(reload(QUEUE_TOKEN)?.0, StatusCode::FORBIDDEN);
+            assert_eq!(
+                reload(ADMIN_TOKEN)?,
+                (StatusCode::OK, json!({ "reloaded": true }))
+            );
+            // the server still renders the pages with the reloaded templates
+            assert_eq!(env.frontend().get("/").send()?.status(), 200);
+
+            Ok(())
+        });
+    }
+}
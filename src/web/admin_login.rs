@@ -0,0 +1,783 @@
+//! Login of the admins with their GitHub account
+//!
+//! When a GitHub OAuth application is configured, the members of `DOCSRS_GITHUB_ADMIN_TEAM` can
+//! log in on `/admin/login` instead of using a token of the admin API, for the deployments where
+//! sharing static tokens isn't acceptable. Their session is stored in a cookie signed with
+//! `DOCSRS_COOKIE_SECRET` and allows the operations of `DOCSRS_GITHUB_ADMIN_SCOPES`, which are
+//! recorded in the audit log under their GitHub login.
+//!
+//! The documentation of the crates is served from the same origin, so its scripts could send
+//! requests carrying the session. The session is therefore only accepted on the forms of the
+//! admin pages, never by the JSON admin API, and only for the top-level navigations of the
+//! browser. The forms need the anti-CSRF token derived from the session that these pages embed,
+//! and the pages can't be read by `fetch`, framed or opened by other pages (see
+//! `SecurityHeadersMiddleware`).
+//!
+//! The team membership is only checked when logging in, removing someone from the team takes
+//! effect once their session expires.
+
+use super::{
+    admin_api::{AdminScope, ApiError, InvalidAdminTokensError},
+    error::Nope,
+    page::WebPage,
+    ErrorPage,
+};
+use crate::{db::audit::Auditor, repositories::APP_USER_AGENT, Config};
+use chrono::Utc;
+use failure::{Error, Fail};
+use hmac::Mac;
+use iron::{
+    headers::{Cookie, SetCookie},
+    method::Method,
+    status, IronResult, Request, Response, Url,
+};
+use reqwest::{
+    blocking::Client as HttpClient,
+    header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, str::FromStr};
+
+const SESSION_COOKIE: &str = "docsrs_admin_session";
+const STATE_COOKIE: &str = "docsrs_admin_oauth_state";
+/// How long the admins have to authorize the application on GitHub
+const STATE_MAX_AGE: u64 = 10 * 60;
+/// Where the admins are sent once logged in
+const LANDING_PAGE: &str = "/admin/audit";
+
+#[cfg(not(test))]
+const GITHUB_URL: &str = "https://github.com";
+#[cfg(not(test))]
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+fn github_url() -> String {
+    #[cfg(not(test))]
+    return GITHUB_URL.into();
+    // never talk to the real GitHub from the test suite
+    #[cfg(test)]
+    return mockito::server_url();
+}
+
+fn github_api_url() -> String {
+    #[cfg(not(test))]
+    return GITHUB_API_URL.into();
+    #[cfg(test)]
+    return mockito::server_url();
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid GitHub team, expected <org>/<team>: {}", _0)]
+pub(crate) struct InvalidGitHubTeamError(String);
+
+/// The team whose members are admins, written `<org>/<team>` with the slug of the team
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GitHubTeam {
+    org: String,
+    team: String,
+}
+
+impl FromStr for GitHubTeam {
+    type Err = InvalidGitHubTeamError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim().split_once('/') {
+            Some((org, team)) if !org.is_empty() && !team.is_empty() && !team.contains('/') => {
+                Ok(Self {
+                    org: org.into(),
+                    team: team.into(),
+                })
+            }
+            _ => Err(InvalidGitHubTeamError(input.into())),
+        }
+    }
+}
+
+/// The operations the admins logged in with GitHub can perform, written like the scopes of
+/// `DOCSRS_ADMIN_API_TOKENS` (`queue,blacklist`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AdminScopes(HashSet<AdminScope>);
+
+impl Default for AdminScopes {
+    /// Requeuing crates, the blacklist and the limits of the builds
+    fn default() -> Self {
+        Self(
+            [AdminScope::Queue, AdminScope::Blacklist, AdminScope::Limits]
+                .iter()
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for AdminScopes {
+    type Err = InvalidAdminTokensError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        input
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// The OAuth application, `None` when the login isn't configured
+struct GitHubLogin<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    team: &'a GitHubTeam,
+}
+
+impl<'a> GitHubLogin<'a> {
+    fn from_config(config: &'a Config) -> Option<Self> {
+        Some(Self {
+            client_id: config.github_oauth_client_id.as_deref()?,
+            client_secret: config.github_oauth_client_secret.as_deref()?,
+            team: config.github_admin_team.as_ref()?,
+        })
+    }
+
+    /// Exchanges the code GitHub gave back for the login of the user, `None` if they aren't an
+    /// active member of the team
+    fn authenticate(&self, code: &str, redirect_uri: &str) -> Result<Option<String>, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let client = HttpClient::builder().default_headers(headers).build()?;
+
+        let response: AccessToken = client
+            .post(format!("{}/login/oauth/access_token", github_url()))
+            .form(&[
+                ("client_id", self.client_id),
+                ("client_secret", self.client_secret),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+            ])
+            .send()?
+            .error_for_status()?
+            .json()?;
+        let token = match response.access_token {
+            Some(token) => token,
+            None => failure::bail!(
+                "GitHub refused the code: {}",
+                response.error_description.unwrap_or_default()
+            ),
+        };
+
+        let User { login } = client
+            .get(format!("{}/user", github_api_url()))
+            .bearer_auth(&token)
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        let response = client
+            .get(format!(
+                "{}/orgs/{}/teams/{}/memberships/{}",
+                github_api_url(),
+                self.team.org,
+                self.team.team,
+                login
+            ))
+            .bearer_auth(&token)
+            .send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        // the invitations to the team that weren't accepted yet are `pending`
+        let Membership { state } = response.error_for_status()?.json()?;
+        Ok(if state == "active" { Some(login) } else { None })
+    }
+}
+
+/// The answers of GitHub, with only the fields used
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Membership {
+    state: String,
+}
+
+/// An admin logged in with GitHub
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AdminSession {
+    /// The GitHub login of the admin
+    pub(crate) login: String,
+    /// Unix timestamp after which the admin needs to log in again
+    expires: i64,
+}
+
+impl AdminSession {
+    fn new(config: &Config, login: String) -> Self {
+        Self {
+            login,
+            expires: Utc::now().timestamp() + config.admin_session_duration as i64,
+        }
+    }
+
+    /// The session of the request, `None` if there's none, it expired, the login is disabled or
+    /// the request isn't a top-level navigation
+    pub(crate) fn from_request(req: &Request) -> Option<Self> {
+        let config = req.extensions.get::<Config>()?;
+        GitHubLogin::from_config(config)?;
+        if !is_navigation(req) {
+            return None;
+        }
+        cookies(req, SESSION_COOKIE).find_map(|value| Self::decode(config, value))
+    }
+
+    /// The token the forms of the admin pages send back with the session, which the scripts of
+    /// other pages can't know
+    pub(super) fn csrf_token(&self, config: &Config) -> String {
+        base64::encode_config(
+            self.csrf_mac(config).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Checks that a form sent with the session carries its anti-CSRF token and that the admins
+    /// logged in with GitHub can perform the operation
+    pub(super) fn authorize(
+        &self,
+        config: &Config,
+        csrf_token: Option<&str>,
+        scope: AdminScope,
+    ) -> Result<(), ApiError> {
+        let valid = csrf_token
+            .and_then(|token| base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok())
+            .is_some_and(|token| self.csrf_mac(config).verify(&token).is_ok());
+        if !valid {
+            return Err(ApiError::new(
+                status::Forbidden,
+                "the form expired, reload the page and try again",
+            ));
+        }
+
+        if config.github_admin_scopes.0.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::new(
+                status::Forbidden,
+                format!(
+                    "the admins logged in with GitHub don't have the {} scope",
+                    scope
+                ),
+            ))
+        }
+    }
+
+    fn csrf_mac(&self, config: &Config) -> impl Mac {
+        let mut mac = super::settings::mac(config);
+        mac.update(b"admin-csrf.");
+        mac.update(format!("{}.{}", self.login, self.expires).as_bytes());
+        mac
+    }
+
+    /// Performs the operations in the name of the admin
+    pub(super) fn auditor(&self, reason: Option<String>) -> Auditor {
+        Auditor::new(format!("github:{}", self.login), reason)
+    }
+
+    fn decode(config: &Config, value: &str) -> Option<Self> {
+        let (payload, signature) = value.split_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        mac(config, payload).verify(&signature).ok()?;
+
+        let json = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        let session: Self = serde_json::from_slice(&json).ok()?;
+        if session.expires > Utc::now().timestamp() {
+            Some(session)
+        } else {
+            None
+        }
+    }
+
+    fn encode(&self, config: &Config) -> String {
+        let json = serde_json::to_vec(self).expect("failed to serialize the session");
+        let payload = base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        let signature = mac(config, &payload).finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            payload,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+/// The signature shares the secret of the settings cookie, the prefix keeps a signed settings
+/// cookie from being accepted as a session
+fn mac(config: &Config, payload: &str) -> impl Mac {
+    let mut mac = super::settings::mac(config);
+    mac.update(b"admin-session.");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Whether the browser loads the request as a page in its own tab, and for the forms, whether it
+/// was submitted from docs.rs. The browsers not sending the fetch metadata headers only get the
+/// protection of the anti-CSRF token and of the `SameSite` cookie.
+fn is_navigation(req: &Request) -> bool {
+    let header = |name: &str| {
+        req.headers
+            .get_raw(name)
+            .and_then(|values| values.first())
+            .map(|value| value.as_slice())
+    };
+    let expect = |name: &str, expected: &[&[u8]]| match header(name) {
+        Some(value) => expected.contains(&value),
+        None => true,
+    };
+
+    expect("Sec-Fetch-Mode", &[b"navigate"])
+        && expect("Sec-Fetch-Dest", &[b"document"])
+        && match req.method {
+            Method::Get | Method::Head => true,
+            // typing the address of a page is `none`, but the forms are submitted from the site
+            _ => expect("Sec-Fetch-Site", &[b"same-origin"]),
+        }
+}
+
+fn cookies<'a>(req: &'a Request, name: &'a str) -> impl Iterator<Item = &'a str> {
+    req.headers
+        .get::<Cookie>()
+        .into_iter()
+        .flat_map(|cookies| cookies.iter())
+        .filter_map(move |cookie| cookie.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+fn set_cookie(config: &Config, name: &str, value: &str, path: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; Path={}; Max-Age={}; SameSite=Lax; HttpOnly{}",
+        name,
+        value,
+        path,
+        max_age,
+        if config.public_url.scheme() == "https" {
+            "; Secure"
+        } else {
+            ""
+        },
+    )
+}
+
+/// Whether the admins can log in with GitHub
+pub(crate) fn is_enabled(config: &Config) -> bool {
+    GitHubLogin::from_config(config).is_some()
+}
+
+fn redirect_uri(config: &Config) -> String {
+    format!(
+        "{}/admin/login/callback",
+        config.public_url.as_str().trim_end_matches('/')
+    )
+}
+
+/// Sends the admin to GitHub to authorize the application, which reads the teams of their
+/// organizations
+pub fn login_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config);
+    let login = match GitHubLogin::from_config(config) {
+        Some(login) => login,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    // the state ties the callback to the browser that started the login
+    let mut state = [0u8; 32];
+    ctry!(req, getrandom::getrandom(&mut state));
+    let state = base64::encode_config(state, base64::URL_SAFE_NO_PAD);
+
+    let mut url = ctry!(
+        req,
+        iron::url::Url::parse(&format!("{}/login/oauth/authorize", github_url()))
+    );
+    url.query_pairs_mut()
+        .append_pair("client_id", login.client_id)
+        .append_pair("redirect_uri", &redirect_uri(config))
+        .append_pair("scope", "read:org")
+        .append_pair("state", &state);
+    let url = ctry!(req, Url::from_generic_url(url));
+
+    let mut resp = super::redirect(url);
+    resp.headers.set(SetCookie(vec![set_cookie(
+        config,
+        STATE_COOKIE,
+        &state,
+        "/admin/login",
+        STATE_MAX_AGE,
+    )]));
+    Ok(resp)
+}
+
+/// GitHub sends the admin back here once they authorized the application
+pub fn login_callback_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config);
+    let login = match GitHubLogin::from_config(config) {
+        Some(login) => login,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let (mut code, mut state) = (None, None);
+    for (key, value) in req.url.as_ref().query_pairs() {
+        match &*key {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    let valid_state = match &state {
+        Some(state) => cookies(req, STATE_COOKIE).any(|expected| {
+            super::admin_api::constant_time_eq(expected.as_bytes(), state.as_bytes())
+        }),
+        None => false,
+    };
+    let code = match code {
+        Some(code) if valid_state => code,
+        _ => {
+            return ErrorPage {
+                title: "The login expired",
+                message: Some("the login didn't start from this browser, try again".into()),
+                status: status::BadRequest,
+            }
+            .into_response(req)
+        }
+    };
+
+    let team = login.team;
+    let user = match ctry!(req, login.authenticate(&code, &redirect_uri(config))) {
+        Some(user) => user,
+        None => {
+            return ErrorPage {
+                title: "Access denied",
+                message: Some(
+                    format!("only the members of {}/{} are admins", team.org, team.team).into(),
+                ),
+                status: status::Forbidden,
+            }
+            .into_response(req)
+        }
+    };
+    log::info!("{} logged in as an admin", user);
+
+    let url = ctry!(
+        req,
+        Url::parse(&format!("{}{}", super::redirect_base(req), LANDING_PAGE)),
+    );
+    let session = AdminSession::new(config, user);
+    let mut resp = super::redirect(url);
+    resp.headers.set(SetCookie(vec![
+        set_cookie(
+            config,
+            SESSION_COOKIE,
+            &session.encode(config),
+            "/admin",
+            config.admin_session_duration,
+        ),
+        set_cookie(config, STATE_COOKIE, "", "/admin/login", 0),
+    ]));
+    Ok(resp)
+}
+
+pub fn logout_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config);
+    let url = ctry!(req, Url::parse(&format!("{}/", super::redirect_base(req))));
+    let mut resp = super::redirect(url);
+    resp.headers.set(SetCookie(vec![set_cookie(
+        config,
+        SESSION_COOKIE,
+        "",
+        "/admin",
+        0,
+    )]));
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::audit_entries;
+    use crate::test::{wrapper, TestEnvironment};
+    use kuchiki::traits::TendrilSink;
+    use mockito::Matcher;
+    use reqwest::{header, Method};
+
+    fn enable_login(env: &TestEnvironment, scopes: &str) {
+        env.override_config(|config| {
+            config.github_admin_scopes = scopes.parse().unwrap();
+            config.github_oauth_client_id = Some("client-id".into());
+            config.github_oauth_client_secret = Some("client-secret".into());
+            config.github_admin_team = Some("rust-lang/docs-rs".parse().unwrap());
+        });
+    }
+
+    /// The `name=value` part of the cookie set by the response
+    fn set_cookie_value(response: &reqwest::blocking::Response, name: &str) -> Option<String> {
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok()?.split(';').next())
+            .find(|cookie| cookie.starts_with(&format!("{}=", name)))
+            .map(str::to_owned)
+    }
+
+    #[test]
+    fn parse_team() {
+        assert_eq!(
+            "rust-lang/docs-rs".parse::<GitHubTeam>().unwrap(),
+            GitHubTeam {
+                org: "rust-lang".into(),
+                team: "docs-rs".into(),
+            }
+        );
+        for invalid in &["rust-lang", "/docs-rs", "rust-lang/", "a/b/c"] {
+            assert!(invalid.parse::<GitHubTeam>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_scopes() {
+        assert_eq!(
+            "queue, limits".parse::<AdminScopes>().unwrap(),
+            AdminScopes(
+                [AdminScope::Queue, AdminScope::Limits]
+                    .iter()
+                    .copied()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            "".parse::<AdminScopes>().unwrap(),
+            AdminScopes(HashSet::new())
+        );
+        assert!("queue,sudo".parse::<AdminScopes>().is_err());
+    }
+
+    #[test]
+    fn signed_session() {
+        wrapper(|env| {
+            let config = env.config();
+            let session = AdminSession::new(&config, "alice".into());
+            let value = session.encode(&config);
+            assert_eq!(AdminSession::decode(&config, &value), Some(session));
+
+            let expired = AdminSession {
+                login: "alice".into(),
+                expires: Utc::now().timestamp() - 1,
+            };
+            assert_eq!(
+                AdminSession::decode(&config, &expired.encode(&config)),
+                None
+            );
+
+            // someone else can't be impersonated without the secret
+            let (_, signature) = value.split_once('.').unwrap();
+            let forged = base64::encode_config(
+                format!(r#"{{"login":"mallory","expires":{}}}"#, i64::MAX),
+                base64::URL_SAFE_NO_PAD,
+            );
+            assert_eq!(
+                AdminSession::decode(&config, &format!("{}.{}", forged, signature)),
+                None
+            );
+            assert_eq!(AdminSession::decode(&config, "garbage"), None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn login_disabled() {
+        wrapper(|env| {
+            let web = env.frontend();
+            for path in &["/admin/login", "/admin/login/callback?code=a&state=b"] {
+                let response = web.request_without_redirects(Method::GET, path).send()?;
+                assert_eq!(response.status(), 404, "{}", path);
+            }
+
+            // the sessions created while the login was enabled are ignored
+            let session = AdminSession::new(&env.config(), "alice".into());
+            let response = web
+                .post("/admin/api/blacklist/foo")
+                .header(
+                    header::COOKIE,
+                    format!("{}={}", SESSION_COOKIE, session.encode(&env.config())),
+                )
+                .send()?;
+            assert_eq!(response.status(), 401);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn login_flow() {
+        wrapper(|env| {
+            enable_login(env, "blacklist");
+            let web = env.frontend();
+            let access_token = |code: &str, token: &str| {
+                mockito::mock("POST", "/login/oauth/access_token")
+                    .match_body(Matcher::AllOf(vec![
+                        Matcher::UrlEncoded("client_secret".into(), "client-secret".into()),
+                        Matcher::UrlEncoded("code".into(), code.into()),
+                    ]))
+                    .with_body(format!(r#"{{"access_token":"{}"}}"#, token))
+                    .create()
+            };
+            let user = |token: &str, login: &str| {
+                mockito::mock("GET", "/user")
+                    .match_header("authorization", format!("Bearer {}", token).as_str())
+                    .with_body(format!(r#"{{"login":"{}"}}"#, login))
+                    .create()
+            };
+            let _mocks = [
+                access_token("member-code", "member-token"),
+                user("member-token", "alice"),
+                mockito::mock("GET", "/orgs/rust-lang/teams/docs-rs/memberships/alice")
+                    .with_body(r#"{"state":"active"}"#)
+                    .create(),
+                access_token("outsider-code", "outsider-token"),
+                user("outsider-token", "mallory"),
+                mockito::mock("GET", "/orgs/rust-lang/teams/docs-rs/memberships/mallory")
+                    .with_status(404)
+                    .create(),
+            ];
+
+            let response = web
+                .request_without_redirects(Method::GET, "/admin/login")
+                .send()?;
+            assert_eq!(response.status(), 302);
+            let location = reqwest::Url::parse(response.headers()[header::LOCATION].to_str()?)?;
+            assert_eq!(location.path(), "/login/oauth/authorize");
+            let state = location
+                .query_pairs()
+                .find(|(key, _)| key == "state")
+                .unwrap()
+                .1
+                .into_owned();
+            let state_cookie = set_cookie_value(&response, STATE_COOKIE).unwrap();
+            let callback = |code: &str, state: &str, cookie: &str| {
+                web.request_without_redirects(
+                    Method::GET,
+                    &format!("/admin/login/callback?code={}&state={}", code, state),
+                )
+                .header(header::COOKIE, cookie)
+                .send()
+            };
+
+            // the callback has to come from the browser that started the login
+            let response = callback("member-code", &state, "")?;
+            assert_eq!(response.status(), 400);
+            let response = callback("member-code", "other", &state_cookie)?;
+            assert_eq!(response.status(), 400);
+
+            let response = callback("outsider-code", &state, &state_cookie)?;
+            assert_eq!(response.status(), 403);
+            assert_eq!(set_cookie_value(&response, SESSION_COOKIE), None);
+
+            let response = callback("member-code", &state, &state_cookie)?;
+            assert_eq!(response.status(), 302);
+            assert!(response.headers()[header::LOCATION]
+                .to_str()?
+                .ends_with(LANDING_PAGE));
+            let session = set_cookie_value(&response, SESSION_COOKIE).unwrap();
+
+            // the landing page is only shown to the admins
+            let response = web.get(LANDING_PAGE).send()?;
+            assert_eq!(response.status(), 401);
+            let response = web
+                .get(LANDING_PAGE)
+                .header(header::COOKIE, &session)
+                .send()?;
+            assert_eq!(response.status(), 200);
+
+            // the JSON admin API only accepts tokens, its requests can be sent by any script
+            let response = web
+                .post("/admin/api/blacklist/foo")
+                .header(header::COOKIE, &session)
+                .json(&serde_json::json!({ "reason": "malware" }))
+                .send()?;
+            assert_eq!(response.status(), 401);
+
+            // the token of the forms can't be read from another page
+            let csrf_token = |headers: &[(&str, &str)]| -> Result<_, Error> {
+                let mut request = web.get("/admin/crates").header(header::COOKIE, &session);
+                for (name, value) in headers {
+                    request = request.header(*name, *value);
+                }
+                let page = kuchiki::parse_html().one(request.send()?.text()?);
+                Ok(page
+                    .select_first("input[name=csrf_token]")
+                    .ok()
+                    .and_then(|input| input.attributes.borrow().get("value").map(String::from)))
+            };
+            assert_eq!(csrf_token(&[("Sec-Fetch-Mode", "cors")])?, None);
+            assert_eq!(csrf_token(&[("Sec-Fetch-Dest", "iframe")])?, None);
+            let token = csrf_token(&[
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Sec-Fetch-Site", "cross-site"),
+            ])?
+            .unwrap();
+
+            let submit = |form: &[(&str, &str)], site: &str| {
+                web.request_without_redirects(Method::POST, "/admin/crates")
+                    .header(header::COOKIE, &session)
+                    .header("Sec-Fetch-Mode", "navigate")
+                    .header("Sec-Fetch-Dest", "document")
+                    .header("Sec-Fetch-Site", site)
+                    .form(form)
+                    .send()
+            };
+            fn blacklist(csrf_token: &str) -> [(&str, &str); 4] {
+                [
+                    ("crate_name", "foo"),
+                    ("reason", "malware"),
+                    ("action", "blacklist-add"),
+                    ("csrf_token", csrf_token),
+                ]
+            }
+            assert_eq!(submit(&blacklist("forged"), "same-origin")?.status(), 403);
+            assert_eq!(submit(&blacklist(&token), "cross-site")?.status(), 401);
+            assert_eq!(submit(&blacklist(&token), "same-origin")?.status(), 302);
+            let actors: Vec<_> = audit_entries(&mut env.db().conn(), None, 10)?
+                .into_iter()
+                .map(|entry| entry.actor)
+                .collect();
+            assert_eq!(actors, vec!["github:alice".to_string()]);
+
+            // the session only allows the operations of the configured scopes
+            let queue = [
+                ("crate_name", "foo"),
+                ("version", "0.1.0"),
+                ("action", "queue"),
+                ("csrf_token", &token),
+            ];
+            assert_eq!(submit(&queue, "same-origin")?.status(), 403);
+
+            let response = web
+                .request_without_redirects(Method::GET, "/admin/logout")
+                .header(header::COOKIE, &session)
+                .send()?;
+            assert_eq!(response.status(), 302);
+            assert_eq!(
+                set_cookie_value(&response, SESSION_COOKIE).as_deref(),
+                Some("docsrs_admin_session=")
+            );
+
+            Ok(())
+        });
+    }
+}
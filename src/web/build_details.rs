@@ -1,5 +1,6 @@
 use crate::{
     db::Pool,
+    docbuilder::BuildDiagnostic,
     impl_webpage,
     web::{file::File, page::WebPage, MetaData, Nope},
     Config, Storage,
@@ -16,6 +17,10 @@ pub(crate) struct BuildDetails {
     docsrs_version: String,
     build_status: bool,
     build_time: DateTime<Utc>,
+    peak_disk_usage: Option<i64>,
+    failure_category: Option<String>,
+    warnings: Vec<String>,
+    diagnostics: Vec<BuildDiagnostic>,
     output: String,
 }
 
@@ -47,6 +52,9 @@ pub fn build_details_handler(req: &mut Request) -> IronResult<Response> {
                 builds.docsrs_version,
                 builds.build_status,
                 builds.build_time,
+                builds.peak_disk_usage,
+                builds.failure_category,
+                builds.warnings,
                 builds.output,
                 releases.default_target
              FROM builds
@@ -66,12 +74,35 @@ pub fn build_details_handler(req: &mut Request) -> IronResult<Response> {
             let file = ctry!(req, File::from_path(storage, &path, config));
             ctry!(req, String::from_utf8(file.0.content))
         };
+        let diagnostics = ctry!(
+            req,
+            conn.query(
+                "SELECT level, lint, file, line, message
+                 FROM build_diagnostics
+                 WHERE build_id = $1
+                 ORDER BY file, line, id",
+                &[&id]
+            )
+        )
+        .into_iter()
+        .map(|row| BuildDiagnostic {
+            level: row.get("level"),
+            lint: row.get("lint"),
+            file: row.get("file"),
+            line: row.get("line"),
+            message: row.get("message"),
+        })
+        .collect();
         BuildDetails {
             id,
             rustc_version: row.get("rustc_version"),
             docsrs_version: row.get("docsrs_version"),
             build_status: row.get("build_status"),
             build_time: row.get("build_time"),
+            peak_disk_usage: row.get("peak_disk_usage"),
+            failure_category: row.get("failure_category"),
+            warnings: row.get("warnings"),
+            diagnostics,
             output,
         }
     } else {
@@ -87,6 +118,7 @@ pub fn build_details_handler(req: &mut Request) -> IronResult<Response> {
 
 #[cfg(test)]
 mod tests {
+    use crate::docbuilder::{BuildDiagnostic, BuildFailure};
     use crate::test::{wrapper, FakeBuild};
     use kuchiki::traits::TendrilSink;
 
@@ -185,6 +217,146 @@ mod tests {
         });
     }
 
+    #[test]
+    fn disk_quota_exceeded() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default()
+                    .peak_disk_usage(3 * 1024 * 1024)
+                    .failure(BuildFailure::DiskQuotaExceeded)])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+
+            let log = page.select("pre").unwrap().next().unwrap().text_contents();
+
+            assert!(log.contains("# peak disk usage\n3 MB"), "{}", log);
+            assert!(log.contains("# build aborted"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn too_many_files() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default().failure(BuildFailure::TooManyFiles)
+                ])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+
+            let log = page.select("pre").unwrap().next().unwrap().text_contents();
+
+            assert!(log.contains("# documentation not uploaded"));
+            assert!(log.contains("more files than allowed"));
+            assert!(log.contains("[package.metadata.docs.rs]"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn warnings() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default().warnings(vec![
+                    "the target `wasm32-unknown-unknwon` is not supported".into(),
+                ])])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+            let node = page.select_first("ul > li a.release").unwrap();
+            assert!(node.as_node().select_first(".build-warnings").is_ok());
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+            let log = page.select("pre").unwrap().next().unwrap().text_contents();
+            assert!(
+                log.contains("# warnings\nthe target `wasm32-unknown-unknwon` is not supported"),
+                "{}",
+                log
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn diagnostics() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default().diagnostics(vec![
+                    BuildDiagnostic {
+                        level: "warning".into(),
+                        lint: Some("rustdoc::broken_intra_doc_links".into()),
+                        file: "src/lib.rs".into(),
+                        line: 3,
+                        message: "unresolved link to `Bar`".into(),
+                    },
+                ])])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+            let node = page.select_first("ul > li a.release").unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+            let diagnostics = page.select_first("details.build-diagnostics").unwrap();
+            let text = diagnostics.text_contents();
+            assert!(text.contains("rustdoc emitted 1 diagnostic"), "{}", text);
+            assert!(text.contains("src/lib.rs:3"));
+            assert!(text.contains("unresolved link to `Bar`"));
+            assert!(text.contains("rustdoc::broken_intra_doc_links"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn non_existing_build() {
         wrapper(|env| {
@@ -1,9 +1,10 @@
-use super::{match_version, redirect_base, MatchSemver};
+use super::{redirect_base, MatchSemver};
 use crate::{
-    db::Pool,
+    db::{build_progress, Pool},
     docbuilder::Limits,
     impl_webpage,
-    web::{page::WebPage, MetaData},
+    web::{error::Nope, page::WebPage, MetaData},
+    Config, VersionCache,
 };
 use chrono::{DateTime, Utc};
 use iron::{
@@ -22,13 +23,24 @@ pub(crate) struct Build {
     docsrs_version: String,
     build_status: bool,
     build_time: DateTime<Utc>,
+    /// Whether the documentation was identical to the one of a previous build with the same
+    /// toolchain, `None` if it wasn't compared
+    reproducible: Option<bool>,
+    /// The limits of the sandbox, only recorded for the recent builds
+    limits: Option<Limits>,
+    warnings: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct BuildsPage {
     metadata: MetaData,
     builds: Vec<Build>,
+    /// The limits of the latest build, or the ones the next build will get
     limits: Limits,
+    /// Whether `limits` are the ones of the latest build
+    limits_of_latest_build: bool,
+    /// Whether a build of the release is running
+    running: bool,
 }
 
 impl_webpage! {
@@ -41,7 +53,6 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
     let req_version = router.find("version");
 
     let mut conn = extension!(req, Pool).get()?;
-    let limits = ctry!(req, Limits::for_crate(&mut conn, name));
 
     let is_json = req
         .url
@@ -49,26 +60,28 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
         .last()
         .map_or(false, |segment| segment.ends_with(".json"));
 
-    let version =
-        match match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())? {
-            MatchSemver::Exact((version, _)) => version,
-
-            MatchSemver::Semver((version, _)) => {
-                let ext = if is_json { ".json" } else { "" };
-                let url = ctry!(
-                    req,
-                    Url::parse(&format!(
-                        "{}/crate/{}/{}/builds{}",
-                        redirect_base(req),
-                        name,
-                        version,
-                        ext,
-                    )),
-                );
-
-                return Ok(super::redirect(url));
-            }
-        };
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let ext = if is_json { ".json" } else { "" };
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/builds{}",
+                    redirect_base(req),
+                    name,
+                    version,
+                    ext,
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
 
     let query = ctry!(
         req,
@@ -82,7 +95,10 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
                 builds.rustc_version,
                 builds.docsrs_version,
                 builds.build_status,
-                builds.build_time
+                builds.build_time,
+                builds.reproducible,
+                builds.limits,
+                builds.warnings
              FROM builds
              INNER JOIN releases ON releases.id = builds.rid
              INNER JOIN crates ON releases.crate_id = crates.id
@@ -100,6 +116,11 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
             docsrs_version: row.get("docsrs_version"),
             build_status: row.get("build_status"),
             build_time: row.get("build_time"),
+            reproducible: row.get("reproducible"),
+            limits: row
+                .get::<_, Option<serde_json::Value>>("limits")
+                .and_then(|limits| serde_json::from_value(limits).ok()),
+            warnings: row.get("warnings"),
         })
         .collect();
 
@@ -116,17 +137,99 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
 
         Ok(resp)
     } else {
+        let (limits, limits_of_latest_build) =
+            match builds.first().and_then(|build| build.limits.clone()) {
+                Some(limits) => (limits, true),
+                None => (ctry!(req, Limits::for_crate(&mut conn, name)), false),
+            };
+
+        let running = ctry!(
+            req,
+            build_progress::running_build(
+                &mut conn,
+                name,
+                &version,
+                extension!(req, Config).build_progress_interval,
+            )
+        )
+        .is_some();
+
         BuildsPage {
             metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, &version)),
             builds,
             limits,
+            limits_of_latest_build,
+            running,
         }
         .into_response(req)
     }
 }
 
+/// The log of the running build of a release as plain text, from `?offset=` bytes if given. The
+/// `X-Log-Length` header gives the offset of the next request, and `X-Build-Target` the target
+/// being documented: the log starts over with every target. An offset in the middle of a character
+/// is answered with `416 Range Not Satisfiable`.
+pub fn running_build_log_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = cexpect!(req, router.find("version"));
+    let offset: usize = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "offset")
+        .and_then(|(_, offset)| offset.parse().ok())
+        .unwrap_or(0);
+
+    let mut conn = extension!(req, Pool).get()?;
+    let build = match ctry!(
+        req,
+        build_progress::running_build(
+            &mut conn,
+            name,
+            version,
+            extension!(req, Config).build_progress_interval,
+        )
+    ) {
+        Some(build) => build,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+    let log = if offset > build.log.len() {
+        // past the end the log of another target started, it's sent whole
+        build.log.as_str()
+    } else if let Some(log) = build.log.get(offset..) {
+        log
+    } else {
+        // in the middle of a character, the client didn't get the offset from us
+        let mut resp = Response::with(status::RangeNotSatisfiable);
+        resp.headers.set_raw(
+            "X-Log-Length",
+            vec![build.log.len().to_string().into_bytes()],
+        );
+        return Ok(resp);
+    };
+
+    let mut resp = Response::with((status::Ok, log.to_owned()));
+    resp.headers.set(ContentType::plaintext());
+    resp.headers.set(CacheControl(vec![
+        CacheDirective::NoCache,
+        CacheDirective::NoStore,
+        CacheDirective::MustRevalidate,
+    ]));
+    resp.headers.set_raw(
+        "X-Log-Length",
+        vec![build.log.len().to_string().into_bytes()],
+    );
+    resp.headers
+        .set_raw("X-Build-Target", vec![build.target.into_bytes()]);
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::db::build_progress;
+    use crate::docbuilder::Limits;
     use crate::test::{wrapper, FakeBuild};
     use chrono::{DateTime, Duration, Utc};
     use kuchiki::traits::TendrilSink;
@@ -306,6 +409,53 @@ mod tests {
         });
     }
 
+    #[test]
+    fn limits_of_the_latest_build() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default().rustc_version("rustc 1.0.0"),
+                    FakeBuild::default()
+                        .rustc_version("rustc 2.0.0")
+                        .limits(Limits::default()),
+                ])
+                .create()?;
+            // the override was added after the build
+            env.db().conn().query(
+                "INSERT INTO sandbox_overrides (crate_name, max_memory_bytes) VALUES ('foo', 3072)",
+                &[],
+            )?;
+
+            let value: serde_json::Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/builds.json")
+                .send()?
+                .json()?;
+            assert_eq!(
+                value.pointer("/0/limits"),
+                Some(&serde_json::to_value(Limits::default())?)
+            );
+            assert_eq!(value.pointer("/1/limits"), Some(&serde_json::Value::Null));
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+            let about = page.select_first(".about").unwrap().text_contents();
+            assert!(
+                about.contains("The latest build of this release ran with the following limits")
+            );
+            assert!(about.contains("3 GB"));
+            assert!(!about.contains("3 KB"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn latest_redirect() {
         wrapper(|env| {
@@ -347,6 +497,61 @@ mod tests {
         });
     }
 
+    #[test]
+    fn running_build_log() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            let web = env.frontend();
+            let log_url = "/crate/foo/0.1.0/builds/current/log";
+            let running = |web: &crate::test::TestFrontend| -> Result<bool, failure::Error> {
+                let page =
+                    kuchiki::parse_html().one(web.get("/crate/foo/0.1.0/builds").send()?.text()?);
+                Ok(page.select_first("li.running a.building").is_ok())
+            };
+
+            assert_eq!(web.get(log_url).send()?.status(), StatusCode::NOT_FOUND);
+            assert!(!running(web)?);
+
+            let log = "[INFO] Compiling foo v0.1.0\n[INFO] Documenting foo v0.1.0\n";
+            build_progress::record(
+                &mut env.db().conn(),
+                "foo",
+                "0.1.0",
+                "x86_64-unknown-linux-gnu",
+                log,
+            )?;
+            assert!(running(web)?);
+
+            let resp = web.get(log_url).send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["X-Log-Length"], log.len().to_string());
+            assert_eq!(resp.headers()["X-Build-Target"], "x86_64-unknown-linux-gnu");
+            assert_eq!(resp.text()?, log);
+
+            let offset = "[INFO] Compiling foo v0.1.0\n".len();
+            let resp = web.get(&format!("{}?offset={}", log_url, offset)).send()?;
+            assert_eq!(resp.text()?, "[INFO] Documenting foo v0.1.0\n");
+            let resp = web.get(&format!("{}?offset=1000", log_url)).send()?;
+            assert_eq!(resp.text()?, log);
+
+            build_progress::append(
+                &mut env.db().conn(),
+                "foo",
+                "0.1.0",
+                "x86_64-unknown-linux-gnu",
+                "[INFO] écrit\n",
+            )?;
+            let offset = log.len() + "[INFO] é".len() - 1;
+            let resp = web.get(&format!("{}?offset={}", log_url, offset)).send()?;
+            assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+            build_progress::clear(&mut env.db().conn(), "foo", "0.1.0")?;
+            assert_eq!(web.get(log_url).send()?.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn crate_version_not_found() {
         wrapper(|env| {
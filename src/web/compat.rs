@@ -0,0 +1,244 @@
+//! Compatibility layer between the hyper server and the Iron handlers
+//!
+//! Iron is unmaintained and blocks a thread per request, so the handlers are ported to async one
+//! route at a time, starting with the files served the most. With the `hyper-server` feature the
+//! frontend serves the routes registered with `Routes::async_resource` itself, and forwards the
+//! requests they don't answer to Iron.
+//!
+//! The ported handlers build their responses with the same code as their Iron counterparts. The
+//! responses are converted here, after getting the headers and metrics the Iron middlewares would
+//! have added.
+
+use super::csp::Csp;
+use super::metrics::duration_to_seconds;
+use super::request_log::{self, RequestLine, RequestLogger};
+use super::security_headers::SecurityHeadersMiddleware;
+use crate::{Config, Context, Metrics, Storage};
+use failure::Error;
+use hyper::{Body, HeaderMap, Method, Request, Response};
+use iron::status;
+use route_recognizer::{Params, Router};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The services available to the async handlers, like the extensions of the Iron requests
+pub(super) struct AsyncState {
+    pub(super) config: Arc<Config>,
+    pub(super) storage: Arc<Storage>,
+    metrics: Arc<Metrics>,
+    logger: RequestLogger,
+}
+
+impl AsyncState {
+    pub(super) fn new(context: &dyn Context) -> Result<Self, Error> {
+        let config = context.config()?;
+        Ok(Self {
+            logger: RequestLogger::new(config.structured_request_logs),
+            config,
+            storage: context.storage()?,
+            metrics: context.metrics()?,
+        })
+    }
+}
+
+pub(super) struct AsyncRequest {
+    pub(super) headers: HeaderMap,
+    pub(super) params: Params,
+}
+
+impl AsyncRequest {
+    /// The headers of the request, for the response builders shared with the Iron handlers
+    pub(super) fn iron_headers(&self) -> iron::headers::Headers {
+        iron_headers(&self.headers)
+    }
+}
+
+/// `None` when the request has to be answered by Iron
+pub(super) type AsyncResponse = Pin<Box<dyn Future<Output = Option<iron::Response>> + Send>>;
+
+pub(super) type AsyncHandler = fn(Arc<AsyncState>, AsyncRequest) -> AsyncResponse;
+
+/// The GET routes served by the frontend, built by `Routes::async_router`
+pub(super) struct AsyncRouter {
+    router: Router<(String, AsyncHandler)>,
+}
+
+impl AsyncRouter {
+    pub(super) fn new() -> Self {
+        Self {
+            router: Router::new(),
+        }
+    }
+
+    pub(super) fn add(&mut self, pattern: &str, route: String, handler: AsyncHandler) {
+        self.router.add(pattern, (route, handler));
+    }
+
+    /// Answers the request if it matches a ported route
+    pub(super) async fn handle(
+        &self,
+        state: &Arc<AsyncState>,
+        req: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        if req.method() != Method::GET {
+            return None;
+        }
+        let matched = self.router.recognize(req.uri().path()).ok()?;
+        let (route, handler) = matched.handler;
+
+        let start = Instant::now();
+        let request = AsyncRequest {
+            headers: req.headers().clone(),
+            params: matched.params,
+        };
+        let headers = request.iron_headers();
+        let response = handler(state.clone(), request).await?;
+        Some(finish(state, route, req, &headers, start, response))
+    }
+}
+
+/// Adds the headers and records the metrics of the Iron middlewares, then converts the response
+fn finish(
+    state: &AsyncState,
+    route: &str,
+    req: &Request<Body>,
+    headers: &iron::headers::Headers,
+    start: Instant,
+    mut response: iron::Response,
+) -> Response<Body> {
+    Csp::new().set_header(&mut response.headers, &state.config);
+    SecurityHeadersMiddleware::set_headers(&mut response.headers);
+
+    let id = request_log::request_id(headers);
+    response
+        .headers
+        .set_raw(request_log::HEADER, vec![id.clone().into_bytes()]);
+
+    let status = response.status.unwrap_or(status::NotFound).to_u16();
+    let elapsed = start.elapsed();
+    state
+        .metrics
+        .http_responses
+        .with_label_values(&[route, &status.to_string()])
+        .inc();
+    state
+        .metrics
+        .http_request_duration
+        .with_label_values(&[route])
+        .observe(duration_to_seconds(elapsed));
+    state.logger.log(&RequestLine {
+        request_id: &id,
+        method: req.method().to_string(),
+        path: req.uri().path().to_string(),
+        route: Some(route),
+        status: Some(status),
+        duration_ms: Some(elapsed.as_millis()),
+    });
+
+    into_hyper(response)
+}
+
+fn iron_headers(headers: &HeaderMap) -> iron::headers::Headers {
+    let mut converted = iron::headers::Headers::new();
+    for (name, value) in headers {
+        converted.append_raw(name.as_str().to_string(), value.as_bytes().to_vec());
+    }
+    converted
+}
+
+/// Like Iron, responses without a status are a `404 Not Found`
+fn into_hyper(response: iron::Response) -> Response<Body> {
+    let mut converted =
+        Response::builder().status(response.status.unwrap_or(status::NotFound).to_u16());
+    for header in response.headers.iter() {
+        converted = converted.header(header.name(), header.value_string());
+    }
+
+    let mut body = Vec::new();
+    if let Some(mut content) = response.body {
+        if let Err(err) = content.write_body(&mut body) {
+            log::error!("failed to write the body of the response: {}", err);
+        }
+    }
+    converted.body(body.into()).unwrap_or_else(|err| {
+        log::error!("invalid response: {}", err);
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use iron::headers::ContentType;
+
+    #[test]
+    fn convert_responses() {
+        let mut response = iron::Response::with((status::Ok, "alert(1)"));
+        response
+            .headers
+            .set(ContentType("text/javascript".parse().unwrap()));
+        response.headers.set_raw("X-Custom", vec![b"1".to_vec()]);
+
+        let converted = into_hyper(response);
+        assert_eq!(converted.status(), 200);
+        assert_eq!(converted.headers()["content-type"], "text/javascript");
+        assert_eq!(converted.headers()["x-custom"], "1");
+
+        let converted = into_hyper(iron::Response::new());
+        assert_eq!(converted.status(), 404);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("if-none-match", "\"abc\"".parse().unwrap());
+        assert_eq!(
+            iron_headers(&headers).get_raw("If-None-Match"),
+            Some(&[b"\"abc\"".to_vec()][..])
+        );
+    }
+
+    #[test]
+    fn shared_resources_are_served_by_hyper() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            env.storage()
+                .store_one("rustdoc-20200101.css", b"body {}".to_vec())?;
+
+            let web = env.frontend();
+            for path in &["/rustdoc-20200101.css", "/dummy/0.1.0/rustdoc-20200101.css"] {
+                let response = web.get(path).send()?;
+                assert_eq!(response.status(), 200, "{}", path);
+                let headers = response.headers();
+                assert_eq!(headers["content-type"], "text/css", "{}", path);
+                assert_eq!(headers["x-content-type-options"], "nosniff", "{}", path);
+                assert!(headers.contains_key("content-security-policy"), "{}", path);
+                assert!(headers.contains_key("x-request-id"), "{}", path);
+
+                let etag = headers["etag"].clone();
+                let response = web.get(path).header("If-None-Match", etag).send()?;
+                assert_eq!(response.status(), 304, "{}", path);
+            }
+
+            let metrics = env.metrics();
+            for status in &["200", "304"] {
+                let responses = metrics
+                    .http_responses
+                    .with_label_values(&["shared resource", status]);
+                assert_eq!(responses.get(), 2);
+            }
+
+            // the other files are still served by Iron
+            assert_eq!(web.get("/dummy/0.1.0/dummy/").send()?.status(), 200);
+            assert_eq!(web.get("/missing.css").send()?.status(), 404);
+
+            Ok(())
+        });
+    }
+}
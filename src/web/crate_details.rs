@@ -1,9 +1,28 @@
-use super::{match_version, redirect_base, render_markdown, MatchSemver, MetaData};
-use crate::{db::Pool, impl_webpage, repositories::RepositoryStatsUpdater, web::page::WebPage};
+use super::{
+    error::Nope, file::File, permanent_crate_redirect, redirect_base, renamed_crate_redirect,
+    render_markdown, MatchSemver, MetaData,
+};
+use crate::{
+    db::{
+        advisories::{self, Advisory},
+        page_views::{self, RecentViews},
+        queries::{self, PreparedStatements},
+        Pool,
+    },
+    impl_webpage,
+    repositories::RepositoryStatsUpdater,
+    utils::{
+        citation::Citation,
+        rustdoc_json,
+        storage_stats::{self, DocSizes},
+    },
+    web::page::WebPage,
+    Config, Storage, VersionCache,
+};
 use chrono::{DateTime, Utc};
+use iron::headers::{AccessControlAllowOrigin, ContentType};
 use iron::prelude::*;
-use iron::Url;
-use postgres::Client;
+use iron::{status, Url};
 use router::Router;
 use serde::{ser::Serializer, Serialize};
 use serde_json::Value;
@@ -22,9 +41,12 @@ pub struct CrateDetails {
     #[serde(serialize_with = "optional_markdown")]
     rustdoc: Option<String>, // this is description_long in database
     release_time: DateTime<Utc>,
-    build_status: bool,
-    last_successful_build: Option<String>,
-    rustdoc_status: bool,
+    /// When the documentation was last built successfully, `None` for old releases
+    pub(crate) last_build_time: Option<DateTime<Utc>>,
+    pub(crate) build_status: bool,
+    /// The latest version built successfully, only set when the build of this one failed
+    pub(crate) last_successful_build: Option<String>,
+    pub(crate) rustdoc_status: bool,
     repository_url: Option<String>,
     homepage_url: Option<String>,
     keywords: Option<Value>,
@@ -40,6 +62,14 @@ pub struct CrateDetails {
     documented_items: Option<f32>,
     total_items_needing_examples: Option<f32>,
     items_with_examples: Option<f32>,
+    /// The citation from the `CITATION.cff` file, formatted for humans
+    citation: Option<String>,
+    /// The size of the documentation by kind of file, `None` if it wasn't uploaded
+    doc_sizes: Option<DocSizes>,
+    /// The source pages generated by rustdoc aren't part of the documentation
+    pub(crate) no_source: bool,
+    /// The minimum supported Rust version declared in the manifest
+    rust_version: Option<String>,
     /// Database id for this crate
     pub(crate) crate_id: i32,
     /// Database id for this release
@@ -53,6 +83,10 @@ struct RepositoryMetadata {
     issues: i32,
     name: Option<String>,
     icon: &'static str,
+    has_stats: bool,
+    last_commit: Option<DateTime<Utc>>,
+    default_branch: Option<String>,
+    archived: bool,
 }
 
 fn optional_markdown<S>(markdown: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
@@ -75,58 +109,12 @@ pub struct Release {
 
 impl CrateDetails {
     pub fn new(
-        conn: &mut Client,
+        conn: &mut impl PreparedStatements,
         name: &str,
         version: &str,
         up: &RepositoryStatsUpdater,
     ) -> Option<CrateDetails> {
-        // get all stuff, I love you rustfmt
-        let query = "
-            SELECT
-                crates.id AS crate_id,
-                releases.id AS release_id,
-                crates.name,
-                releases.version,
-                releases.description,
-                releases.dependencies,
-                releases.readme,
-                releases.description_long,
-                releases.release_time,
-                releases.build_status,
-                releases.rustdoc_status,
-                releases.repository_url,
-                releases.homepage_url,
-                releases.keywords,
-                releases.have_examples,
-                releases.target_name,
-                repositories.host as repo_host,
-                repositories.stars as repo_stars,
-                repositories.forks as repo_forks,
-                repositories.issues as repo_issues,
-                repositories.name as repo_name,
-                releases.is_library,
-                releases.yanked,
-                releases.doc_targets,
-                releases.license,
-                releases.documentation_url,
-                releases.default_target,
-                doc_coverage.total_items,
-                doc_coverage.documented_items,
-                doc_coverage.total_items_needing_examples,
-                doc_coverage.items_with_examples
-            FROM releases
-            INNER JOIN crates ON releases.crate_id = crates.id
-            LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
-            LEFT JOIN repositories ON releases.repository_id = repositories.id
-            WHERE crates.name = $1 AND releases.version = $2;";
-
-        let rows = conn.query(query, &[&name, &version]).unwrap();
-
-        let krate = if rows.is_empty() {
-            return None;
-        } else {
-            &rows[0]
-        };
+        let krate = queries::release_details(conn, name, version).unwrap()?;
 
         let crate_id: i32 = krate.get("crate_id");
         let release_id: i32 = krate.get("release_id");
@@ -143,6 +131,10 @@ impl CrateDetails {
                     forks: krate.get("repo_forks"),
                     name: krate.get("repo_name"),
                     icon: up.get_icon_name(&host),
+                    has_stats: up.has_stats(&host),
+                    last_commit: krate.get("repo_last_commit"),
+                    default_branch: krate.get("repo_default_branch"),
+                    archived: krate.get("repo_archived"),
                 });
 
         let metadata = MetaData {
@@ -160,6 +152,20 @@ impl CrateDetails {
         let total_items: Option<i32> = krate.get("total_items");
         let total_items_needing_examples: Option<i32> = krate.get("total_items_needing_examples");
         let items_with_examples: Option<i32> = krate.get("items_with_examples");
+        let citation = krate
+            .get::<_, Option<Value>>("citation")
+            .and_then(|data| serde_json::from_value::<Citation>(data).ok())
+            .map(|citation| citation.formatted());
+
+        let doc_sizes = krate
+            .get::<_, Option<i64>>("doc_size_html")
+            .map(|html| DocSizes {
+                html,
+                src: krate.get("doc_size_src"),
+                implementors: krate.get("doc_size_implementors"),
+                search_index: krate.get("doc_size_search_index"),
+                other: krate.get("doc_size_other"),
+            });
 
         let mut crate_details = CrateDetails {
             name: krate.get("name"),
@@ -170,6 +176,7 @@ impl CrateDetails {
             readme: krate.get("readme"),
             rustdoc: krate.get("description_long"),
             release_time: krate.get("release_time"),
+            last_build_time: krate.get("last_build_time"),
             build_status: krate.get("build_status"),
             last_successful_build: None,
             rustdoc_status: krate.get("rustdoc_status"),
@@ -188,25 +195,15 @@ impl CrateDetails {
             total_items: total_items.map(|v| v as f32),
             total_items_needing_examples: total_items_needing_examples.map(|v| v as f32),
             items_with_examples: items_with_examples.map(|v| v as f32),
+            citation,
+            doc_sizes,
+            no_source: krate.get("no_source"),
+            rust_version: krate.get("rust_version"),
             crate_id,
             release_id,
         };
 
-        // get owners
-        let owners = conn
-            .query(
-                "SELECT login, avatar
-                 FROM owners
-                 INNER JOIN owner_rels ON owner_rels.oid = owners.id
-                 WHERE cid = $1",
-                &[&crate_id],
-            )
-            .unwrap();
-
-        crate_details.owners = owners
-            .into_iter()
-            .map(|row| (row.get("login"), row.get("avatar")))
-            .collect();
+        crate_details.owners = queries::crate_owners(conn, crate_id).unwrap();
 
         if !crate_details.build_status {
             crate_details.last_successful_build = crate_details
@@ -230,29 +227,17 @@ impl CrateDetails {
     }
 }
 
-fn releases_for_crate(conn: &mut Client, crate_id: i32) -> Vec<Release> {
-    let mut releases: Vec<Release> = conn
-        .query(
-            "SELECT 
-                version,
-                build_status,
-                yanked,
-                is_library
-             FROM releases
-             WHERE 
-                 releases.crate_id = $1",
-            &[&crate_id],
-        )
+fn releases_for_crate(conn: &mut impl PreparedStatements, crate_id: i32) -> Vec<Release> {
+    let mut releases: Vec<Release> = queries::crate_releases(conn, crate_id)
         .unwrap()
         .into_iter()
-        .filter_map(|row| {
-            let version: String = row.get("version");
-            semver::Version::parse(&version)
+        .filter_map(|release| {
+            semver::Version::parse(&release.version)
                 .map(|semversion| Release {
                     version: semversion,
-                    build_status: row.get("build_status"),
-                    yanked: row.get("yanked"),
-                    is_library: row.get("is_library"),
+                    build_status: release.build_status,
+                    yanked: release.yanked,
+                    is_library: release.is_library,
                 })
                 .ok()
         })
@@ -266,6 +251,14 @@ fn releases_for_crate(conn: &mut Client, crate_id: i32) -> Vec<Release> {
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct CrateDetailsPage {
     details: CrateDetails,
+    /// The security advisories affecting the release
+    advisories: Vec<Advisory>,
+    /// Introduces the advisories, see `advisories::label`
+    advisories_label: &'static str,
+    /// The views of the documentation of the crate, `None` when they aren't counted
+    views: Option<RecentViews>,
+    /// The details of the latest release
+    canonical_url: String,
 }
 
 impl_webpage! {
@@ -278,14 +271,39 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
     let name = cexpect!(req, router.find("name"));
     let req_version = router.find("version");
 
-    let mut conn = extension!(req, Pool).get()?;
+    let mut conn = extension!(req, Pool).read()?;
+    if let Some(redirect) = ctry!(req, renamed_crate_redirect(req, &mut conn, name)) {
+        return Ok(redirect);
+    }
 
-    match match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())? {
+    let matched = extension!(req, VersionCache).match_version(&mut conn, name, req_version)?;
+    if let Some(canonical_name) = matched.corrected_name {
+        return permanent_crate_redirect(req, name, &canonical_name);
+    }
+
+    match matched.version {
         MatchSemver::Exact((version, _)) => {
             let updater = extension!(req, RepositoryStatsUpdater);
             let details = cexpect!(req, CrateDetails::new(&mut conn, name, &version, updater));
-
-            CrateDetailsPage { details }.into_response(req)
+            let canonical_url = format!("{}/crate/{}/latest", redirect_base(req), name);
+            let advisories = ctry!(req, advisories::affecting(&mut conn, name, &version));
+            let views = if extension!(req, Config).page_view_stats {
+                Some(ctry!(
+                    req,
+                    page_views::recent_views(&mut conn, details.crate_id)
+                ))
+            } else {
+                None
+            };
+
+            CrateDetailsPage {
+                details,
+                advisories_label: advisories::label(&advisories),
+                advisories,
+                views,
+                canonical_url,
+            }
+            .into_response(req)
         }
 
         MatchSemver::Semver((version, _)) => {
@@ -304,11 +322,312 @@ pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+/// Serves the citation of a release as a BibTeX entry
+pub fn citation_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/citation.bib",
+                    redirect_base(req),
+                    name,
+                    version
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let rows = ctry!(
+        req,
+        conn.query(
+            "SELECT citations.data
+             FROM citations
+             INNER JOIN releases ON releases.id = citations.release_id
+             INNER JOIN crates ON releases.crate_id = crates.id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version]
+        )
+    );
+    let citation: Citation = match rows.first() {
+        Some(row) => ctry!(req, serde_json::from_value(row.get("data"))),
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let key = format!("{}_{}", name, version);
+    let mut resp = Response::with((status::Ok, citation.bibtex(&key)));
+    resp.headers.set(ContentType(
+        "application/x-bibtex; charset=utf-8".parse().unwrap(),
+    ));
+    Ok(resp)
+}
+
+/// The `Cargo.lock` used by the build of a release, either shipped with the crate or generated by
+/// cargo right before the build
+pub fn lockfile_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/Cargo.lock",
+                    redirect_base(req),
+                    name,
+                    version
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let path = format!("lockfiles/{}/{}/Cargo.lock", name, version);
+    let file = match File::from_path(storage, &path, config) {
+        Ok(file) => file,
+        Err(..) => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut resp = Response::with((status::Ok, file.0.content));
+    resp.headers
+        .set(ContentType("text/plain; charset=utf-8".parse().unwrap()));
+    Ok(resp)
+}
+
+/// The compressed output of `rustdoc --output-format json` for a target of a release, the default
+/// target when none is given
+pub fn rustdoc_json_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+    let target = router.find("target");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let (version, release_id) = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact(release) => release,
+
+        MatchSemver::Semver((version, _)) => {
+            let mut path = format!("{}/crate/{}/{}/json", redirect_base(req), name, version);
+            if let Some(target) = target {
+                path.push('/');
+                path.push_str(target);
+            }
+            let url = ctry!(req, Url::parse(&path));
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let target = match target {
+        Some(target) => target.to_string(),
+        None => {
+            let row = ctry!(
+                req,
+                conn.query_opt(
+                    "SELECT default_target FROM releases WHERE id = $1",
+                    &[&release_id]
+                )
+            );
+            match row.and_then(|row| row.get::<_, Option<String>>(0)) {
+                Some(target) => target,
+                None => return Err(Nope::ResourceNotFound.into()),
+            }
+        }
+    };
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let path = rustdoc_json::storage_path(name, &version, &target);
+    let download = match File::download(storage, &path, config) {
+        Ok(download) => download,
+        Err(..) => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut resp = download.serve(&req.headers);
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[derive(Debug, Serialize)]
+struct PageViewsJson {
+    name: String,
+    #[serde(flatten)]
+    views: RecentViews,
+}
+
+/// The views of the documentation of a crate in the last days, when they're counted
+pub fn page_views_handler(req: &mut Request) -> IronResult<Response> {
+    if !extension!(req, Config).page_view_stats {
+        return Err(Nope::ResourceNotFound.into());
+    }
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+
+    let mut conn = extension!(req, Pool).read()?;
+    let crate_id: i32 = match ctry!(
+        req,
+        conn.query_opt("SELECT id FROM crates WHERE name = $1", &[&name])
+    ) {
+        Some(row) => row.get(0),
+        None => return Err(Nope::CrateNotFound.into()),
+    };
+    let json = PageViewsJson {
+        name: name.to_string(),
+        views: ctry!(req, page_views::recent_views(&mut conn, crate_id)),
+    };
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[derive(Debug, Serialize)]
+struct DocSizeJson {
+    name: String,
+    version: String,
+    #[serde(flatten)]
+    sizes: DocSizes,
+    total: i64,
+}
+
+/// The size of the documentation of a release by kind of file, in bytes before compression
+pub fn doc_size_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/doc-size.json",
+                    redirect_base(req),
+                    name,
+                    version
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let sizes = match ctry!(req, storage_stats::doc_sizes(&mut conn, name, &version)) {
+        Some(sizes) => sizes,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+    let json = DocSizeJson {
+        name: name.to_owned(),
+        version,
+        sizes,
+        total: sizes.total(),
+    };
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[derive(Debug, Serialize)]
+struct StatusJson {
+    name: String,
+    version: String,
+    build_status: bool,
+    doc_status: bool,
+    /// The minimum supported Rust version declared in the manifest
+    rust_version: Option<String>,
+}
+
+/// The status of the build of a release, for tools checking whether its documentation exists
+pub fn status_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/status.json",
+                    redirect_base(req),
+                    name,
+                    version
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let row = match ctry!(req, queries::release_details(&mut conn, name, &version)) {
+        Some(row) => row,
+        None => return Err(Nope::VersionNotFound.into()),
+    };
+    let json = StatusJson {
+        name: row.get("name"),
+        version,
+        build_status: row.get("build_status"),
+        doc_status: row.get("rustdoc_status"),
+        rust_version: row.get("rust_version"),
+    };
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::index::api::CrateOwner;
-    use crate::test::{wrapper, TestDatabase};
+    use crate::test::{assert_redirect, fake_advisory, wrapper, TestDatabase};
     use failure::Error;
     use kuchiki::traits::TendrilSink;
     use std::collections::HashMap;
@@ -953,4 +1272,322 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn citation() {
+        wrapper(|env| {
+            let citation = Citation::parse(
+                "title: Dummy\nauthors:\n  - family-names: Doe\n    given-names: Jane\n\
+                 date-released: 2021-01-01",
+            )?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .citation(citation)
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(web.get("/crate/dummy/0.1.0").send()?.text()?);
+            assert_eq!(
+                page.select_first("p.citation")
+                    .expect("missing citation")
+                    .text_contents(),
+                "Doe, J. (2021). Dummy [Computer software]."
+            );
+            let page = kuchiki::parse_html().one(web.get("/crate/dummy/0.2.0").send()?.text()?);
+            assert!(page.select_first("p.citation").is_err());
+
+            let resp = web.get("/crate/dummy/0.1.0/citation.bib").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(
+                resp.headers()["Content-Type"],
+                "application/x-bibtex; charset=utf-8"
+            );
+            assert_eq!(
+                resp.text()?,
+                "@software{dummy_0_1_0,\n  author = {Doe, Jane},\n  title = {Dummy},\n  year = {2021}\n}\n"
+            );
+
+            let resp = web.get("/crate/dummy/0.2.0/citation.bib").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn repository_activity() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .github_stats("some/repo", 10, 10, 10)
+                .create()?;
+
+            let activity = |web: &crate::test::TestFrontend| -> Result<_, failure::Error> {
+                let page = kuchiki::parse_html().one(web.get("/crate/dummy/0.1.0").send()?.text()?);
+                Ok((
+                    page.select_first(".repository-activity")
+                        .map(|node| node.text_contents())
+                        .ok(),
+                    page.select_first(".repository-archived").is_ok(),
+                ))
+            };
+
+            let web = env.frontend();
+            let (last_commit, archived) = activity(web)?;
+            let last_commit = last_commit.expect("missing repository activity");
+            assert!(last_commit.contains("Last commit"), "{}", last_commit);
+            assert!(!last_commit.contains(" on "), "{}", last_commit);
+            assert!(!archived);
+
+            env.db().conn().execute(
+                "UPDATE repositories SET default_branch = 'main', archived = TRUE",
+                &[],
+            )?;
+            let (last_commit, archived) = activity(web)?;
+            assert!(last_commit.unwrap().contains("on main"));
+            assert!(archived);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn doc_size() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file("foo/index.html", &[b'a'; 1000])
+                .rustdoc_file("src/foo/lib.rs.html", &[b'a'; 2000])
+                .rustdoc_file("search-index-20210101.js", &[b'a'; 3000])
+                .build()?;
+            env.fake_release().name("bar").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0").send()?.text()?);
+            let panel = page.select_first(".doc-size").unwrap().text_contents();
+            assert!(panel.contains("5.86 KB"), "{}", panel);
+            assert!(panel.contains("1.95 KB of source pages"), "{}", panel);
+
+            let json: serde_json::Value =
+                web.get("/crate/foo/0.1.0/doc-size.json").send()?.json()?;
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "name": "foo",
+                    "version": "0.1.0",
+                    "html": 1000,
+                    "src": 2000,
+                    "implementors": 0,
+                    "search_index": 3000,
+                    "other": 0,
+                    "total": 6000,
+                })
+            );
+            assert_redirect(
+                "/crate/foo/0.1/doc-size.json",
+                "/crate/foo/0.1.0/doc-size.json",
+                web,
+            )?;
+
+            // releases created without going through the uploader have no sizes
+            let page = kuchiki::parse_html().one(web.get("/crate/bar/0.1.0").send()?.text()?);
+            assert!(page.select_first(".doc-size").is_err());
+            let res = web.get("/crate/bar/0.1.0/doc-size.json").send()?;
+            assert_eq!(res.status(), 404);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn advisories() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            env.fake_release().name("foo").version("0.2.0").create()?;
+            advisories::replace_all(
+                &mut env.db().conn(),
+                &[Advisory {
+                    informational: Some("unsound".into()),
+                    patched: vec![">= 0.2.0".into()],
+                    ..fake_advisory("RUSTSEC-2021-0001", "foo")
+                }],
+            )?;
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0").send()?.text()?);
+            let advisories = page
+                .select_first("#advisories")
+                .expect("missing the advisories");
+            let link = advisories.as_node().select_first("a").unwrap();
+            assert_eq!(
+                link.attributes.borrow().get("href"),
+                Some("https://rustsec.org/advisories/RUSTSEC-2021-0001.html")
+            );
+            let text = advisories.text_contents();
+            assert!(text.contains("is affected by\n"), "{}", text);
+            assert!(text.contains("an advisory:"), "{}", text);
+            assert!(text.contains("(unsound)"), "{}", text);
+            assert!(text.contains("Use after free"), "{}", text);
+            assert!(text.contains("2021-01-02"), "{}", text);
+
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.2.0").send()?.text()?);
+            assert!(page.select_first("#advisories").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rust_version() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .rust_version("1.56")
+                .create()?;
+            env.fake_release().name("bar").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0").send()?.text()?);
+            let msrv = page.select_first(".msrv").expect("missing the MSRV");
+            assert_eq!(msrv.text_contents(), "Rust 1.56");
+            let page = kuchiki::parse_html().one(web.get("/crate/bar/0.1.0").send()?.text()?);
+            assert!(page.select_first(".msrv").is_err());
+
+            let json: serde_json::Value = web.get("/crate/foo/0.1.0/status.json").send()?.json()?;
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "name": "foo",
+                    "version": "0.1.0",
+                    "build_status": true,
+                    "doc_status": true,
+                    "rust_version": "1.56",
+                })
+            );
+            let json: serde_json::Value = web.get("/crate/bar/0.1.0/status.json").send()?.json()?;
+            assert_eq!(json["rust_version"], serde_json::Value::Null);
+            assert_redirect(
+                "/crate/foo/0.1/status.json",
+                "/crate/foo/0.1.0/status.json",
+                web,
+            )?;
+            assert_eq!(
+                web.get("/crate/foo/0.2.0/status.json").send()?.status(),
+                404
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rustdoc_json() {
+        wrapper(|env| {
+            let json = br#"{"root":"0:0","format_version":9}"#;
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .add_target("i686-pc-windows-msvc")
+                .rustdoc_json("x86_64-unknown-linux-gnu", json)
+                .rustdoc_json("i686-pc-windows-msvc", json)
+                .build()?;
+            env.fake_builder().name("bar").version("0.1.0").build()?;
+
+            let web = env.frontend();
+            for path in &[
+                "/crate/foo/0.1.0/json",
+                "/crate/foo/0.1.0/json/x86_64-unknown-linux-gnu",
+                "/crate/foo/0.1.0/json/i686-pc-windows-msvc",
+            ] {
+                let resp = web.get(path).send()?;
+                assert!(resp.status().is_success(), "{}", path);
+                assert_eq!(resp.headers()["Content-Type"], "application/zstd");
+                assert_eq!(resp.headers()["Access-Control-Allow-Origin"], "*");
+                let content = crate::storage::decompress(
+                    resp.bytes()?.as_ref(),
+                    crate::storage::CompressionAlgorithm::Zstd,
+                    usize::MAX,
+                )?;
+                assert_eq!(content, &json[..], "{}", path);
+            }
+            assert_redirect("/crate/foo/0.1/json", "/crate/foo/0.1.0/json", web)?;
+            assert_redirect(
+                "/crate/foo/0.1/json/i686-pc-windows-msvc",
+                "/crate/foo/0.1.0/json/i686-pc-windows-msvc",
+                web,
+            )?;
+
+            for path in &[
+                "/crate/foo/0.1.0/json/x86_64-apple-darwin",
+                "/crate/bar/0.1.0/json",
+                "/crate/foo/0.2.0/json",
+            ] {
+                assert_eq!(web.get(path).send()?.status(), 404, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn page_views() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.page_view_stats = true;
+                config.page_view_flush_interval = 1;
+            });
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            let views = || -> Result<String, Error> {
+                let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0").send()?.text()?);
+                Ok(page
+                    .select_first(".page-views b")
+                    .expect("missing the page views")
+                    .text_contents())
+            };
+            assert_eq!(views()?, "0");
+
+            assert!(web.get("/foo/0.1.0/foo/").send()?.status().is_success());
+            assert!(web
+                .get("/foo/0.1.0/foo/index.html")
+                .send()?
+                .status()
+                .is_success());
+            // the requests that don't want to be tracked aren't counted
+            web.get("/foo/0.1.0/foo/").header("DNT", "1").send()?;
+            // the views are written in the background
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+            while views()? != "2" && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            assert_eq!(views()?, "2");
+
+            let json: serde_json::Value = web.get("/crate/foo/views.json").send()?.json()?;
+            assert_eq!(json["name"], "foo");
+            assert_eq!(json["total"], 2);
+            assert_eq!(json["days"].as_array().unwrap().len(), 1);
+            assert_eq!(web.get("/crate/bar/views.json").send()?.status(), 404);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn page_views_disabled() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let web = env.frontend();
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0").send()?.text()?);
+            assert!(page.select_first(".page-views").is_err());
+
+            Ok(())
+        });
+    }
 }
@@ -1,5 +1,7 @@
 use crate::config::Config;
-use iron::{AfterMiddleware, BeforeMiddleware, IronResult, Request, Response};
+use iron::{headers::Headers, AfterMiddleware, BeforeMiddleware, IronResult, Request, Response};
+
+const FRAME_ANCESTORS: &str = "frame-ancestors 'self'";
 
 pub(super) struct Csp {
     nonce: String,
@@ -7,7 +9,7 @@ pub(super) struct Csp {
 }
 
 impl Csp {
-    fn new() -> Self {
+    pub(super) fn new() -> Self {
         // Nonces need to be different for each single request in order to maintain security, so we
         // generate a new one with a cryptographically-secure generator for each request.
         let mut random = [0u8; 36];
@@ -27,15 +29,49 @@ impl Csp {
         &self.nonce
     }
 
+    /// Sets the policy matching the content type of the response
+    pub(super) fn set_header(&self, headers: &mut Headers, config: &Config) {
+        let content_type = headers
+            .get_raw("Content-Type")
+            .and_then(|headers| headers.get(0))
+            .map(|header| header.as_slice());
+
+        let preset = match content_type {
+            Some(b"text/html; charset=utf-8") => ContentType::Html,
+            Some(b"text/svg+xml") => ContentType::Svg,
+            _ => ContentType::Other,
+        };
+
+        if let Some(rendered) = self.render(preset) {
+            headers.set_raw(
+                // The Report-Only header tells the browser to just log CSP failures instead of
+                // actually enforcing them. This is useful to check if the CSP works without
+                // impacting production traffic.
+                if config.csp_report_only {
+                    "Content-Security-Policy-Report-Only"
+                } else {
+                    "Content-Security-Policy"
+                },
+                vec![rendered.as_bytes().to_vec()],
+            );
+        }
+    }
+
     fn render(&self, content_type: ContentType) -> Option<String> {
         if self.suppress {
-            return None;
+            // The framing of the page doesn't depend on its content, so it's still restricted.
+            return Some(FRAME_ANCESTORS.into());
         }
         let mut result = String::new();
 
         // Disable everything by default
         result.push_str("default-src 'none'");
 
+        // Only allow docs.rs itself to embed the page in a frame, to prevent clickjacking. This
+        // isn't covered by `default-src`.
+        result.push_str("; ");
+        result.push_str(FRAME_ANCESTORS);
+
         // Disable the <base> HTML tag to prevent injected HTML content from changing the base URL
         // of all relative links included in the website.
         result.push_str("; base-uri 'none'");
@@ -62,6 +98,9 @@ impl Csp {
         // Allow loading any font from the current origin.
         result.push_str("; font-src 'self'");
 
+        // Allow the scripts to fetch pages and events from the current origin.
+        result.push_str("; connect-src 'self'");
+
         // Only allow scripts with the random nonce attached to them.
         //
         // We can't just allow 'self' here, as users can upload arbitrary .js files as part of
@@ -103,33 +142,8 @@ impl AfterMiddleware for CspMiddleware {
             .get::<Config>()
             .expect("missing Config")
             .clone();
-        let csp = req.extensions.get_mut::<Csp>().expect("missing CSP");
-
-        let content_type = res
-            .headers
-            .get_raw("Content-Type")
-            .and_then(|headers| headers.get(0))
-            .map(|header| header.as_slice());
-
-        let preset = match content_type {
-            Some(b"text/html; charset=utf-8") => ContentType::Html,
-            Some(b"text/svg+xml") => ContentType::Svg,
-            _ => ContentType::Other,
-        };
-
-        if let Some(rendered) = csp.render(preset) {
-            res.headers.set_raw(
-                // The Report-Only header tells the browser to just log CSP failures instead of
-                // actually enforcing them. This is useful to check if the CSP works without
-                // impacting production traffic.
-                if config.csp_report_only {
-                    "Content-Security-Policy-Report-Only"
-                } else {
-                    "Content-Security-Policy"
-                },
-                vec![rendered.as_bytes().to_vec()],
-            );
-        }
+        let csp = req.extensions.get::<Csp>().expect("missing CSP");
+        csp.set_header(&mut res.headers, &config);
         Ok(res)
     }
 }
@@ -150,16 +164,23 @@ mod tests {
         let mut csp = Csp::new();
         csp.suppress(true);
 
-        assert!(csp.render(ContentType::Other).is_none());
-        assert!(csp.render(ContentType::Html).is_none());
-        assert!(csp.render(ContentType::Svg).is_none());
+        for content_type in [ContentType::Other, ContentType::Html, ContentType::Svg] {
+            assert_eq!(
+                Some("frame-ancestors 'self'".into()),
+                csp.render(content_type)
+            );
+        }
     }
 
     #[test]
     fn test_csp_other() {
         let csp = Csp::new();
         assert_eq!(
-            Some("default-src 'none'; base-uri 'none'; img-src 'self' https:".into()),
+            Some(
+                "default-src 'none'; frame-ancestors 'self'; base-uri 'none'; \
+                 img-src 'self' https:"
+                    .into()
+            ),
             csp.render(ContentType::Other)
         );
     }
@@ -169,7 +190,8 @@ mod tests {
         let csp = Csp::new();
         assert_eq!(
             Some(
-                "default-src 'none'; base-uri 'none'; img-src 'self' https:; \
+                "default-src 'none'; frame-ancestors 'self'; base-uri 'none'; \
+                 img-src 'self' https:; \
                  style-src 'self' 'unsafe-inline'"
                     .into()
             ),
@@ -182,8 +204,10 @@ mod tests {
         let csp = Csp::new();
         assert_eq!(
             Some(format!(
-                "default-src 'none'; base-uri 'none'; img-src 'self' https:; \
-                 style-src 'self'; font-src 'self'; script-src 'nonce-{}'",
+                "default-src 'none'; frame-ancestors 'self'; base-uri 'none'; \
+                 img-src 'self' https:; \
+                 style-src 'self'; font-src 'self'; connect-src 'self'; \
+                 script-src 'nonce-{}'",
                 csp.nonce()
             )),
             csp.render(ContentType::Html)
@@ -0,0 +1,522 @@
+//! Dependency tree of a release
+//!
+//! The dependencies recorded at build time only contain the version requirements, so each one is
+//! resolved against the releases known by docs.rs, picking the newest one matching the
+//! requirement like a fresh `cargo update` would. The normal and build dependencies of the
+//! resolved releases are then resolved too, one level of the tree at a time.
+
+use super::{redirect_base, MatchSemver};
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{page::WebPage, MetaData},
+    VersionCache,
+};
+use iron::{
+    headers::{AccessControlAllowOrigin, ContentType},
+    status, IronResult, Request, Response, Url,
+};
+use postgres::Client;
+use router::Router;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// The tree isn't expanded past this depth
+const MAX_DEPTH: usize = 10;
+/// Maximum number of crates resolved for a single tree, the other ones are left unresolved
+const MAX_CRATES: usize = 250;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Dependency {
+    name: String,
+    req: String,
+    /// `normal`, `dev` or `build`
+    kind: String,
+    /// The newest release on docs.rs matching the requirement
+    version: Option<String>,
+    /// Link to the documentation of the resolved release, or to its crate page if it has none
+    url: Option<String>,
+    /// The dependencies of this crate are already listed elsewhere in the tree
+    duplicate: bool,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Clone)]
+struct Release {
+    version: Version,
+    dependencies: Vec<(String, String, String)>,
+    rustdoc_status: bool,
+    target_name: Option<String>,
+}
+
+/// Parses the `dependencies` column, stored as `[name, req, kind]` triples. Old releases don't
+/// have the kind.
+fn parse_dependencies(value: Option<Value>) -> Vec<(String, String, String)> {
+    value
+        .as_ref()
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|dependency| {
+            let name = dependency.get(0)?.as_str()?;
+            let req = dependency.get(1)?.as_str()?;
+            let kind = dependency
+                .get(2)
+                .and_then(Value::as_str)
+                .unwrap_or("normal");
+            Some((name.to_owned(), req.to_owned(), kind.to_owned()))
+        })
+        .collect()
+}
+
+/// Caches the releases of the crates in the tree, fetched one level at a time
+#[derive(Default)]
+struct Resolver {
+    releases: HashMap<String, Vec<Release>>,
+}
+
+impl Resolver {
+    fn fetch(&mut self, conn: &mut Client, names: Vec<String>) -> Result<(), failure::Error> {
+        let names: Vec<String> = names
+            .into_iter()
+            .filter(|name| !self.releases.contains_key(name))
+            .take(MAX_CRATES.saturating_sub(self.releases.len()))
+            .collect();
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        for name in &names {
+            self.releases.insert(name.clone(), Vec::new());
+        }
+        let rows = conn.query(
+            "SELECT crates.name, releases.version, releases.dependencies,
+                    releases.rustdoc_status, releases.target_name
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = ANY($1) AND NOT releases.yanked",
+            &[&names],
+        )?;
+        for row in rows {
+            let version = match Version::parse(row.get("version")) {
+                Ok(version) => version,
+                Err(_) => continue,
+            };
+            let name: String = row.get("name");
+            self.releases.entry(name).or_default().push(Release {
+                version,
+                dependencies: parse_dependencies(row.get("dependencies")),
+                rustdoc_status: row.get("rustdoc_status"),
+                target_name: row.get("target_name"),
+            });
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, name: &str, req: &str) -> Option<&Release> {
+        let req = VersionReq::parse(req).ok()?;
+        self.releases
+            .get(name)?
+            .iter()
+            .filter(|release| req.matches(&release.version))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+}
+
+/// Resolves the dependency tree of a release
+pub(crate) fn dependency_tree(
+    conn: &mut Client,
+    dependencies: Vec<(String, String, String)>,
+) -> Result<Vec<Dependency>, failure::Error> {
+    /// The tree is built level by level, so the children are stored as indexes in `nodes`
+    struct Node {
+        dependency: Dependency,
+        children: Vec<usize>,
+    }
+
+    impl Node {
+        fn new((name, req, kind): (String, String, String)) -> Self {
+            Node {
+                dependency: Dependency {
+                    name,
+                    req,
+                    kind,
+                    version: None,
+                    url: None,
+                    duplicate: false,
+                    dependencies: Vec::new(),
+                },
+                children: Vec::new(),
+            }
+        }
+    }
+
+    let mut nodes: Vec<Node> = dependencies.into_iter().map(Node::new).collect();
+    let roots: Vec<usize> = (0..nodes.len()).collect();
+
+    let mut resolver = Resolver::default();
+    let mut expanded = HashSet::new();
+    let mut level = roots.clone();
+    for depth in 0..MAX_DEPTH {
+        let names = level
+            .iter()
+            .map(|&node| nodes[node].dependency.name.clone())
+            .collect();
+        resolver.fetch(conn, names)?;
+
+        let mut next_level = Vec::new();
+        for node in level {
+            let dependency = &mut nodes[node].dependency;
+            let release = match resolver.resolve(&dependency.name, &dependency.req) {
+                Some(release) => release,
+                None => continue,
+            };
+            let version = release.version.to_string();
+            dependency.url = Some(match (&release.target_name, release.rustdoc_status) {
+                (Some(target_name), true) => {
+                    format!("/{}/{}/{}/", dependency.name, version, target_name)
+                }
+                _ => format!("/crate/{}/{}", dependency.name, version),
+            });
+            dependency.version = Some(version.clone());
+
+            if depth + 1 == MAX_DEPTH {
+                continue;
+            }
+            if !expanded.insert((dependency.name.clone(), version)) {
+                dependency.duplicate = !release.dependencies.is_empty();
+                continue;
+            }
+            // the dev-dependencies of the dependencies are not needed to use them
+            let children: Vec<_> = release
+                .dependencies
+                .iter()
+                .filter(|(_, _, kind)| kind != "dev")
+                .cloned()
+                .collect();
+            for child in children {
+                let index = nodes.len();
+                nodes.push(Node::new(child));
+                nodes[node].children.push(index);
+                next_level.push(index);
+            }
+        }
+        if next_level.is_empty() {
+            break;
+        }
+        level = next_level;
+    }
+
+    fn into_tree(nodes: &mut [Option<Node>], node: usize) -> Dependency {
+        let Node {
+            mut dependency,
+            children,
+        } = nodes[node].take().unwrap();
+        dependency.dependencies = children
+            .into_iter()
+            .map(|child| into_tree(nodes, child))
+            .collect();
+        dependency
+    }
+    let mut nodes: Vec<_> = nodes.into_iter().map(Some).collect();
+    Ok(roots
+        .into_iter()
+        .map(|root| into_tree(&mut nodes, root))
+        .collect())
+}
+
+/// A line of the tree shown on the dependencies page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DependencyRow {
+    depth: usize,
+    name: String,
+    req: String,
+    kind: String,
+    version: Option<String>,
+    url: Option<String>,
+    duplicate: bool,
+}
+
+fn flatten(dependencies: Vec<Dependency>, depth: usize, rows: &mut Vec<DependencyRow>) {
+    for dependency in dependencies {
+        rows.push(DependencyRow {
+            depth,
+            name: dependency.name,
+            req: dependency.req,
+            kind: dependency.kind,
+            version: dependency.version,
+            url: dependency.url,
+            duplicate: dependency.duplicate,
+        });
+        flatten(dependency.dependencies, depth + 1, rows);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DependenciesPage {
+    metadata: MetaData,
+    dependencies: Vec<DependencyRow>,
+}
+
+impl_webpage! {
+    DependenciesPage = "crate/dependencies.html",
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DependenciesJson {
+    name: String,
+    version: String,
+    dependencies: Vec<Dependency>,
+}
+
+pub fn dependencies_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let req_version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let is_json = req
+        .url
+        .path()
+        .last()
+        .is_some_and(|segment| segment.ends_with(".json"));
+
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let ext = if is_json { ".json" } else { "" };
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/deps{}",
+                    redirect_base(req),
+                    name,
+                    version,
+                    ext,
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let rows = ctry!(
+        req,
+        conn.query(
+            "SELECT releases.dependencies FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&name, &version]
+        )
+    );
+    let row = cexpect!(req, rows.first());
+    let dependencies = ctry!(
+        req,
+        dependency_tree(&mut conn, parse_dependencies(row.get(0)))
+    );
+
+    if is_json {
+        let json = DependenciesJson {
+            name: name.to_owned(),
+            version,
+            dependencies,
+        };
+        let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+        resp.headers.set(ContentType::json());
+        resp.headers.set(AccessControlAllowOrigin::Any);
+
+        Ok(resp)
+    } else {
+        let mut rows = Vec::new();
+        flatten(dependencies, 0, &mut rows);
+        DependenciesPage {
+            metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, &version)),
+            dependencies: rows,
+        }
+        .into_response(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::*;
+    use kuchiki::traits::TendrilSink;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn tree() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[
+                    ("bar", "^1.0", "normal"),
+                    ("baz", "^0.2", "build"),
+                    ("qux", "^0.3", "dev"),
+                    ("missing", "^1.0", "normal"),
+                ])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("1.0.0")
+                .dependencies(&[("baz", "^0.2", "normal"), ("quux", "^1", "dev")])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("1.1.0")
+                .dependencies(&[("baz", "^0.2", "normal"), ("quux", "^1", "dev")])
+                .create()?;
+            // yanked releases are never picked
+            env.fake_release()
+                .name("bar")
+                .version("1.2.0")
+                .yanked(true)
+                .dependencies(&[])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("2.0.0")
+                .dependencies(&[])
+                .create()?;
+            env.fake_release()
+                .name("baz")
+                .version("0.2.3")
+                .dependencies(&[("bar", "^2", "normal")])
+                .create()?;
+            env.fake_release()
+                .name("qux")
+                .version("0.3.0")
+                .binary(true)
+                .dependencies(&[])
+                .create()?;
+
+            let web = env.frontend();
+            let json: Value = web.get("/crate/foo/0.1.0/deps.json").send()?.json()?;
+
+            let node = |name: &str,
+                        req: &str,
+                        kind: &str,
+                        version: Option<&str>,
+                        url: Option<&str>,
+                        duplicate: bool,
+                        deps: Value| {
+                json!({
+                    "name": name,
+                    "req": req,
+                    "kind": kind,
+                    "version": version,
+                    "url": url,
+                    "duplicate": duplicate,
+                    "dependencies": deps,
+                })
+            };
+            let bar2 = || {
+                node(
+                    "bar",
+                    "^2",
+                    "normal",
+                    Some("2.0.0"),
+                    Some("/bar/2.0.0/bar/"),
+                    false,
+                    json!([]),
+                )
+            };
+            // `baz` is only expanded where it's the closest to the root of the tree
+            assert_eq!(
+                json,
+                json!({
+                    "name": "foo",
+                    "version": "0.1.0",
+                    "dependencies": [
+                        node("bar", "^1.0", "normal", Some("1.1.0"), Some("/bar/1.1.0/bar/"), false, json!([
+                            node("baz", "^0.2", "normal", Some("0.2.3"), Some("/baz/0.2.3/baz/"), true, json!([])),
+                        ])),
+                        node("baz", "^0.2", "build", Some("0.2.3"), Some("/baz/0.2.3/baz/"), false, json!([bar2()])),
+                        node("qux", "^0.3", "dev", Some("0.3.0"), Some("/crate/qux/0.3.0"), false, json!([])),
+                        node("missing", "^1.0", "normal", None, None, false, json!([])),
+                    ],
+                })
+            );
+
+            let page = kuchiki::parse_html().one(web.get("/crate/foo/0.1.0/deps").send()?.text()?);
+            let links: Vec<_> = page
+                .select(".dependency-tree li a")
+                .unwrap()
+                .map(|a| a.attributes.borrow().get("href").unwrap().to_owned())
+                .collect();
+            assert_eq!(
+                links,
+                vec![
+                    "/bar/1.1.0/bar/",
+                    "/baz/0.2.3/baz/",
+                    "/baz/0.2.3/baz/",
+                    "/bar/2.0.0/bar/",
+                    "/crate/qux/0.3.0",
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn cycles() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[("bar", "^0.1", "normal")])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .dependencies(&[("foo", "^0.1", "normal")])
+                .create()?;
+
+            let json: Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/deps.json")
+                .send()?
+                .json()?;
+            let foo = &json["dependencies"][0]["dependencies"][0];
+            assert_eq!(foo["name"], "foo");
+            assert_eq!(foo["dependencies"][0]["name"], "bar");
+            assert_eq!(foo["dependencies"][0]["duplicate"], true);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn redirects_and_empty() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[])
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/crate/foo/latest/deps", "/crate/foo/0.1.0/deps", web)?;
+            assert_redirect(
+                "/crate/foo/latest/deps.json",
+                "/crate/foo/0.1.0/deps.json",
+                web,
+            )?;
+            let page = web.get("/crate/foo/0.1.0/deps").send()?.text()?;
+            assert!(
+                page.contains("doesn&#x27;t have any dependencies")
+                    || page.contains("doesn't have any dependencies")
+            );
+
+            Ok(())
+        })
+    }
+}
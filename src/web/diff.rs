@@ -0,0 +1,451 @@
+//! Changes of the documented items between two releases
+//!
+//! `/crate/:name/diff/:old..:new` lists the public items added, removed or whose kind changed
+//! between two releases, to help reviewing the upgrade of a dependency, and
+//! `/api/v1/diff?crate=:name&old=:old&new=:new` answers with the same lists as JSON.
+//! `/crate/:name/:version/diff`, linked from the navigation of the crate, redirects to the
+//! comparison of a release with the previous one that has documentation. The items are
+//! the ones of the indexes recorded by the builds (see `utils::item_index`), the releases built
+//! before they were recorded can't be compared.
+
+use super::error::Nope;
+use super::{redirect_base, ErrorPage, MatchSemver, MetaData};
+use crate::db::{
+    queries::{self, PreparedStatements},
+    Pool,
+};
+use crate::utils::item_index::{page_path, ItemIndex};
+use crate::{impl_webpage, web::page::WebPage, Config, Storage, VersionCache};
+use failure::Error;
+use iron::headers::{AccessControlAllowOrigin, ContentType};
+use iron::{status, IronResult, Request, Response, Url};
+use router::Router;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DiffItem {
+    path: String,
+    kind: String,
+    /// Set for the items whose kind changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_kind: Option<String>,
+    /// The page of the item, in the older release for the removed items
+    url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Diff {
+    #[serde(rename = "crate")]
+    krate: String,
+    old_version: String,
+    new_version: String,
+    added: Vec<DiffItem>,
+    removed: Vec<DiffItem>,
+    changed: Vec<DiffItem>,
+}
+
+/// The releases matching the requested versions
+struct Releases {
+    name: String,
+    old_version: String,
+    new_version: String,
+    /// Whether both versions were exact
+    exact: bool,
+}
+
+fn match_releases(
+    conn: &mut impl PreparedStatements,
+    version_cache: &VersionCache,
+    name: &str,
+    old: &str,
+    new: &str,
+) -> Result<Releases, Nope> {
+    let mut exact = true;
+    let mut versions = Vec::with_capacity(2);
+    for version in &[old, new] {
+        versions.push(
+            match version_cache
+                .match_version(conn, name, Some(version))
+                .and_then(|matched| matched.assume_exact())?
+            {
+                MatchSemver::Exact((version, _)) => version,
+                MatchSemver::Semver((version, _)) => {
+                    exact = false;
+                    version
+                }
+            },
+        );
+    }
+    let new_version = versions.pop().unwrap();
+    let old_version = versions.pop().unwrap();
+
+    Ok(Releases {
+        name: name.into(),
+        old_version,
+        new_version,
+        exact,
+    })
+}
+
+/// Compares the items of the releases, `None` if one of them has no index. The pages of the items
+/// are linked under `base`.
+fn compare(
+    storage: &Storage,
+    config: &Config,
+    base: &str,
+    releases: &Releases,
+) -> Result<Option<Diff>, Error> {
+    let load =
+        |version: &str| ItemIndex::load(storage, &releases.name, version, config.max_file_size);
+    let (old, new) = match (load(&releases.old_version)?, load(&releases.new_version)?) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Ok(None),
+    };
+
+    let item = |version: &str, path: &str, kind: &str, old_kind: Option<&str>| DiffItem {
+        path: path.into(),
+        kind: kind.into(),
+        old_kind: old_kind.map(String::from),
+        url: match page_path(path, kind) {
+            Some(page) => format!("{}/{}/{}/{}", base, releases.name, version, page),
+            None => String::new(),
+        },
+    };
+    let diff = old.diff(&new);
+    Ok(Some(Diff {
+        added: diff
+            .added
+            .iter()
+            .map(|(path, kind)| item(&releases.new_version, path, kind, None))
+            .collect(),
+        removed: diff
+            .removed
+            .iter()
+            .map(|(path, kind)| item(&releases.old_version, path, kind, None))
+            .collect(),
+        changed: diff
+            .changed
+            .iter()
+            .map(|(path, old_kind, kind)| item(&releases.new_version, path, kind, Some(old_kind)))
+            .collect(),
+        krate: releases.name.clone(),
+        old_version: releases.old_version.clone(),
+        new_version: releases.new_version.clone(),
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct DiffPage {
+    metadata: MetaData,
+    diff: Diff,
+}
+
+impl_webpage! {
+    DiffPage = "crate/diff.html",
+}
+
+/// `/crate/:name/diff/:old..:new`
+pub fn diff_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let (old, new) = match router
+        .find("versions")
+        .and_then(|versions| versions.split_once(".."))
+    {
+        Some(versions) => versions,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let releases = match_releases(&mut conn, extension!(req, VersionCache), name, old, new)?;
+    if !releases.exact {
+        let url = ctry!(
+            req,
+            Url::parse(&format!(
+                "{}/crate/{}/diff/{}..{}",
+                redirect_base(req),
+                releases.name,
+                releases.old_version,
+                releases.new_version
+            )),
+        );
+        return Ok(super::redirect(url));
+    }
+
+    let diff = match ctry!(
+        req,
+        compare(
+            extension!(req, Storage),
+            extension!(req, Config),
+            &redirect_base(req),
+            &releases
+        )
+    ) {
+        Some(diff) => diff,
+        None => {
+            return ErrorPage {
+                title: "The documented items of these releases aren't available",
+                message: Some(
+                    "the releases built before docs.rs recorded their items can't be compared"
+                        .into(),
+                ),
+                status: status::NotFound,
+            }
+            .into_response(req)
+        }
+    };
+
+    DiffPage {
+        metadata: cexpect!(
+            req,
+            MetaData::from_crate(&mut conn, &releases.name, &releases.new_version)
+        ),
+        diff,
+    }
+    .into_response(req)
+}
+
+/// The latest release with documentation published before the release `release_id`
+fn previous_release(
+    conn: &mut impl PreparedStatements,
+    release_id: i32,
+    version: &str,
+) -> Result<Option<String>, Error> {
+    let version = semver::Version::parse(version)?;
+    let crate_id: i32 = conn
+        .client()
+        .query_one(
+            "SELECT crate_id FROM releases WHERE id = $1",
+            &[&release_id],
+        )?
+        .get(0);
+
+    Ok(queries::crate_releases(conn, crate_id)?
+        .into_iter()
+        .filter(|release| release.build_status && release.is_library)
+        .filter_map(|release| semver::Version::parse(&release.version).ok())
+        .filter(|previous| *previous < version)
+        .max()
+        .map(|previous| previous.to_string()))
+}
+
+/// `/crate/:name/:version/diff`
+pub fn previous_diff_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let version = router.find("version");
+
+    let mut conn = extension!(req, Pool).get()?;
+    let matched = extension!(req, VersionCache).match_version(&mut conn, name, version)?;
+    if let Some(canonical_name) = matched.corrected_name {
+        return super::permanent_crate_redirect(req, name, &canonical_name);
+    }
+    let (version, release_id) = matched.version.into_parts();
+    let previous = match ctry!(req, previous_release(&mut conn, release_id, &version)) {
+        Some(previous) => previous,
+        None => {
+            return ErrorPage {
+                title: "There's no previous release to compare with",
+                message: Some(
+                    format!(
+                        "no release of {} older than {} has documentation",
+                        name, version
+                    )
+                    .into(),
+                ),
+                status: status::NotFound,
+            }
+            .into_response(req)
+        }
+    };
+
+    let url = ctry!(
+        req,
+        Url::parse(&format!(
+            "{}/crate/{}/diff/{}..{}",
+            redirect_base(req),
+            name,
+            previous,
+            version
+        )),
+    );
+    Ok(super::redirect(url))
+}
+
+/// `/api/v1/diff?crate=:name&old=:old&new=:new`
+pub fn diff_api_handler(req: &mut Request) -> IronResult<Response> {
+    let param = |name: &str| {
+        req.url
+            .as_ref()
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let (name, old, new) = match (param("crate"), param("old"), param("new")) {
+        (Some(name), Some(old), Some(new)) => (name, old, new),
+        _ => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let releases = match_releases(&mut conn, extension!(req, VersionCache), &name, &old, &new)?;
+    let diff = match ctry!(
+        req,
+        compare(
+            extension!(req, Storage),
+            extension!(req, Config),
+            &redirect_base(req),
+            &releases
+        )
+    ) {
+        Some(diff) => diff,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&diff).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{wrapper, TestEnvironment};
+    use kuchiki::traits::TendrilSink;
+    use reqwest::{header, Method};
+    use serde_json::json;
+
+    fn build_releases(env: &TestEnvironment) -> Result<(), failure::Error> {
+        env.fake_builder()
+            .name("foo")
+            .version("0.1.0")
+            .item("foo::Error", "struct")
+            .item("foo::parse", "function")
+            .item("foo::ser", "module")
+            .item("foo::ser::to_string", "function")
+            .build()?;
+        env.fake_builder()
+            .name("foo")
+            .version("0.2.0")
+            .item("foo::Error", "enum")
+            .item("foo::parse", "function")
+            .item("foo::ser", "module")
+            .item("foo::de", "module")
+            .build()?;
+        // built before the items were recorded
+        env.fake_builder().name("foo").version("0.3.0").build()?;
+        Ok(())
+    }
+
+    #[test]
+    fn diff_api() {
+        wrapper(|env| {
+            build_releases(env)?;
+            let web = env.frontend();
+            let base = format!("http://{}", web.server_addr());
+
+            let diff: serde_json::Value = web
+                .get("/api/v1/diff?crate=foo&old=0.1.0&new=0.2")
+                .send()?
+                .error_for_status()?
+                .json()?;
+            assert_eq!(
+                diff,
+                json!({
+                    "crate": "foo",
+                    "old_version": "0.1.0",
+                    "new_version": "0.2.0",
+                    "added": [{
+                        "path": "foo::de",
+                        "kind": "module",
+                        "url": format!("{}/foo/0.2.0/foo/de/index.html", base),
+                    }],
+                    "removed": [{
+                        "path": "foo::ser::to_string",
+                        "kind": "function",
+                        "url": format!("{}/foo/0.1.0/foo/ser/fn.to_string.html", base),
+                    }],
+                    "changed": [{
+                        "path": "foo::Error",
+                        "kind": "enum",
+                        "old_kind": "struct",
+                        "url": format!("{}/foo/0.2.0/foo/enum.Error.html", base),
+                    }],
+                })
+            );
+
+            for query in &[
+                "crate=foo&old=0.1.0&new=0.3.0",
+                "crate=foo&old=0.1.0&new=1.0.0",
+                "crate=bar&old=0.1.0&new=0.2.0",
+                "crate=foo&old=0.1.0",
+            ] {
+                let status = web.get(&format!("/api/v1/diff?{}", query)).send()?.status();
+                assert_eq!(status, 404, "{}", query);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn diff_page() {
+        wrapper(|env| {
+            build_releases(env)?;
+            let web = env.frontend();
+
+            let response = web
+                .request_without_redirects(Method::GET, "/crate/foo/diff/0.1..0.2")
+                .send()?;
+            assert_eq!(response.status(), 302);
+            assert!(response.headers()[header::LOCATION]
+                .to_str()?
+                .ends_with("/crate/foo/diff/0.1.0..0.2.0"));
+
+            let page =
+                kuchiki::parse_html().one(web.get("/crate/foo/diff/0.1.0..0.2.0").send()?.text()?);
+            let items = |list: &str| -> Vec<String> {
+                page.select(&format!("#{} li code", list))
+                    .expect("invalid selector")
+                    .map(|el| el.text_contents())
+                    .collect()
+            };
+            assert_eq!(items("added"), vec!["foo::de"]);
+            assert_eq!(items("removed"), vec!["foo::ser::to_string"]);
+            assert_eq!(items("changed"), vec!["foo::Error"]);
+            assert_eq!(
+                page.select_first("#removed li a")
+                    .unwrap()
+                    .attributes
+                    .borrow()
+                    .get("href")
+                    .map(|href| href.ends_with("/foo/0.1.0/foo/ser/fn.to_string.html")),
+                Some(true)
+            );
+
+            let nav = page
+                .select_first(".pure-menu-active")
+                .expect("missing the active tab");
+            assert_eq!(nav.text_contents().trim(), "Changes");
+
+            // the navigation of the crate links to the diff with the previous release
+            let response = web
+                .request_without_redirects(Method::GET, "/crate/foo/0.2.0/diff")
+                .send()?;
+            assert_eq!(response.status(), 302);
+            assert!(response.headers()[header::LOCATION]
+                .to_str()?
+                .ends_with("/crate/foo/diff/0.1.0..0.2.0"));
+
+            for path in &[
+                "/crate/foo/0.1.0/diff",
+                "/crate/foo/diff/0.1.0..0.3.0",
+                "/crate/foo/diff/0.1.0",
+                "/crate/bar/diff/0.1.0..0.2.0",
+            ] {
+                assert_eq!(web.get(path).send()?.status(), 404, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -1,10 +1,13 @@
 use crate::{
-    db::PoolError,
+    db::{Pool, PoolError},
+    impl_webpage,
     web::{page::WebPage, releases::Search, ErrorPage},
 };
 use failure::Fail;
 use iron::{status::Status, Handler, IronError, IronResult, Request, Response};
-use std::{error::Error, fmt};
+use router::Router;
+use serde::Serialize;
+use std::{borrow::Cow, error::Error, fmt};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Nope {
@@ -73,11 +76,13 @@ impl Handler for Nope {
             .into_response(req),
 
             Nope::CrateNotFound => {
-                // user tried to navigate to a crate that doesn't exist
-                // TODO: Display the attempted crate and a link to a search for said crate
-                ErrorPage {
+                // user tried to navigate to a crate that doesn't exist, suggest the ones with a
+                // similar name in case of a typo
+                let suggestions = crate_suggestions(req);
+                CrateNotFoundPage {
                     title: "The requested crate does not exist",
                     message: Some("no such crate".into()),
+                    suggestions,
                     status: Status::NotFound,
                 }
                 .into_response(req)
@@ -137,6 +142,65 @@ impl Handler for Nope {
     }
 }
 
+/// Number of crates suggested when the requested one doesn't exist
+const CRATE_SUGGESTIONS: i64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct CrateSuggestion {
+    name: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CrateNotFoundPage {
+    title: &'static str,
+    message: Option<Cow<'static, str>>,
+    suggestions: Vec<CrateSuggestion>,
+    #[serde(skip)]
+    status: Status,
+}
+
+impl_webpage! {
+    CrateNotFoundPage = "error.html",
+    status = |page| page.status,
+}
+
+/// Finds the crates with a name close to the one requested, linking to the same kind of page
+fn crate_suggestions(req: &Request) -> Vec<CrateSuggestion> {
+    let name = match req
+        .extensions
+        .get::<Router>()
+        .and_then(|params| params.find("name").or_else(|| params.find("crate")))
+    {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+    let mut conn = match req.extensions.get::<Pool>().map(Pool::get) {
+        Some(Ok(conn)) => conn,
+        _ => return Vec::new(),
+    };
+
+    let names = match super::similar_crate_names(&mut conn, name, CRATE_SUGGESTIONS) {
+        Ok(names) => names,
+        Err(err) => {
+            log::error!("failed to find crates similar to {}: {}", name, err);
+            return Vec::new();
+        }
+    };
+    let crate_page = req.url.path().first() == Some(&"crate");
+    names
+        .into_iter()
+        .map(|name| CrateSuggestion {
+            url: if crate_page {
+                format!("/crate/{}", name)
+            } else {
+                format!("/{}", name)
+            },
+            name,
+        })
+        .collect()
+}
+
 impl From<PoolError> for IronError {
     fn from(err: PoolError) -> IronError {
         IronError::new(err.compat(), Status::InternalServerError)
@@ -171,6 +235,39 @@ mod tests {
         });
     }
 
+    #[test]
+    fn crate_suggestions() {
+        wrapper(|env| {
+            env.fake_release().name("serde").create()?;
+            env.fake_release().name("serde_json").create()?;
+            env.fake_release().name("regex").create()?;
+            let web = env.frontend();
+
+            let suggestions = |path: &str| -> Result<Vec<String>, failure::Error> {
+                let response = web.get(path).send()?;
+                assert_eq!(response.status(), 404);
+                let page = kuchiki::parse_html().one(response.text()?);
+                Ok(page
+                    .select(".suggestions a")
+                    .unwrap()
+                    .map(|link| link.attributes.borrow().get("href").unwrap().to_owned())
+                    .collect())
+            };
+            assert_eq!(suggestions("/serd")?, vec!["/serde", "/serde_json"]);
+            assert_eq!(
+                suggestions("/serd/latest/serd/")?,
+                vec!["/serde", "/serde_json"]
+            );
+            assert_eq!(
+                suggestions("/crate/Serd")?,
+                vec!["/crate/serde", "/crate/serde_json"]
+            );
+            assert!(suggestions("/crate-which-doesnt-exist")?.is_empty());
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn check_404_page_content_resource() {
         wrapper(|env| {
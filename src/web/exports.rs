@@ -0,0 +1,141 @@
+//! The dumps of the public tables published by `utils::dataset_export`
+//!
+//! `/api/v1/exports` lists the available dumps with the URL of their files, which are downloaded
+//! from `/api/v1/exports/:date/:file`.
+
+use super::error::Nope;
+use super::file::File;
+use super::redirect_base;
+use crate::utils::dataset_export::{storage_path, Manifest};
+use crate::{Config, Storage};
+use chrono::NaiveDate;
+use iron::headers::{AccessControlAllowOrigin, ContentType};
+use iron::prelude::*;
+use iron::status;
+use router::Router;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct DumpJson {
+    date: NaiveDate,
+    files: Vec<DumpFileJson>,
+}
+
+#[derive(Debug, Serialize)]
+struct DumpFileJson {
+    name: String,
+    url: String,
+    rows: u64,
+    size: u64,
+}
+
+pub fn manifest_handler(req: &mut Request) -> IronResult<Response> {
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let manifest = ctry!(req, Manifest::load(storage, config.max_file_size)).unwrap_or_default();
+
+    let base = redirect_base(req);
+    let dumps: Vec<DumpJson> = manifest
+        .dumps
+        .into_iter()
+        .map(|dump| {
+            let date = dump.date;
+            DumpJson {
+                date,
+                files: dump
+                    .files
+                    .into_iter()
+                    .map(|file| DumpFileJson {
+                        url: format!("{}/api/v1/exports/{}/{}", base, date, file.name),
+                        name: file.name,
+                        rows: file.rows,
+                        size: file.size,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let mut resp = Response::with((
+        status::Ok,
+        serde_json::to_string(&serde_json::json!({ "dumps": dumps })).unwrap(),
+    ));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+pub fn download_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let date = cexpect!(req, router.find("date"));
+    let name = cexpect!(req, router.find("file"));
+    let date = match date.parse::<NaiveDate>() {
+        Ok(date) => date,
+        Err(_) => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    // only the files of the dumps that weren't deleted yet are served
+    match ctry!(req, Manifest::load(storage, config.max_file_size)) {
+        Some(manifest) if manifest.contains(date, name) => {}
+        _ => return Err(Nope::ResourceNotFound.into()),
+    }
+    let download = match File::download(storage, &storage_path(date, name), config) {
+        Ok(download) => download,
+        Err(..) => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut resp = download.serve(&req.headers);
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use crate::utils::dataset_export::export_datasets;
+
+    #[test]
+    fn exports() {
+        wrapper(|env| {
+            let web = env.frontend();
+            let json: serde_json::Value = web.get("/api/v1/exports").send()?.json()?;
+            assert_eq!(json, serde_json::json!({ "dumps": [] }));
+
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            let dump = export_datasets(&mut env.db().conn(), &env.storage(), 7)?;
+
+            let json: serde_json::Value = web.get("/api/v1/exports").send()?.json()?;
+            let dumps = json["dumps"].as_array().unwrap();
+            assert_eq!(dumps.len(), 1);
+            assert_eq!(dumps[0]["date"], dump.date.to_string());
+            let file = &dumps[0]["files"][0];
+            assert_eq!(file["name"], "releases.csv.gz");
+            assert_eq!(file["rows"], 1);
+            let url = format!(
+                "http://{}/api/v1/exports/{}/releases.csv.gz",
+                web.server_addr(),
+                dump.date
+            );
+            assert_eq!(file["url"], url);
+
+            let resp = web
+                .get(&format!("/api/v1/exports/{}/releases.csv.gz", dump.date))
+                .send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers()["Content-Type"], "application/gzip");
+            assert_eq!(resp.bytes()?.len() as u64, dump.files[0].size);
+
+            for path in &[
+                format!("/api/v1/exports/{}/missing.csv.gz", dump.date),
+                "/api/v1/exports/2021-01-01/releases.csv.gz".into(),
+                "/api/v1/exports/latest/releases.csv.gz".into(),
+            ] {
+                assert_eq!(web.get(path).send()?.status(), 404, "{}", path);
+            }
+
+            Ok(())
+        });
+    }
+}
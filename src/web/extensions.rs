@@ -1,10 +1,14 @@
+use crate::db::page_views::PageViews;
+use crate::utils::{asset_integrity::AssetIntegrity, pubsubhubbub::Hub};
 use crate::web::page::TemplateData;
 use crate::{
-    db::Pool, repositories::RepositoryStatsUpdater, BuildQueue, Config, Context, Metrics, Storage,
+    db::Pool, repositories::RepositoryStatsUpdater, BuildQueue, Config, Context, Metrics,
+    ReleasesCache, Storage, VersionCache,
 };
 use failure::Error;
 use iron::{BeforeMiddleware, IronResult, Request};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub(super) struct InjectExtensions {
@@ -15,6 +19,11 @@ pub(super) struct InjectExtensions {
     metrics: Arc<Metrics>,
     template_data: Arc<TemplateData>,
     repository_stats_updater: Arc<RepositoryStatsUpdater>,
+    version_cache: Arc<VersionCache>,
+    releases_cache: Arc<ReleasesCache>,
+    hub: Arc<Hub>,
+    asset_integrity: Arc<AssetIntegrity>,
+    page_views: Arc<PageViews>,
 }
 
 impl InjectExtensions {
@@ -29,6 +38,14 @@ impl InjectExtensions {
             storage: context.storage()?,
             metrics: context.metrics()?,
             repository_stats_updater: context.repository_stats_updater()?,
+            version_cache: context.version_cache()?,
+            releases_cache: context.releases_cache()?,
+            hub: Arc::new(Hub::new(&*context.config()?, context.metrics()?)?),
+            asset_integrity: Arc::new(AssetIntegrity::default()),
+            page_views: Arc::new(PageViews::new(
+                context.pool()?,
+                Duration::from_secs(context.config()?.page_view_flush_interval),
+            )),
             template_data,
         })
     }
@@ -46,6 +63,14 @@ impl BeforeMiddleware for InjectExtensions {
             .insert::<TemplateData>(self.template_data.clone());
         req.extensions
             .insert::<RepositoryStatsUpdater>(self.repository_stats_updater.clone());
+        req.extensions
+            .insert::<VersionCache>(self.version_cache.clone());
+        req.extensions
+            .insert::<ReleasesCache>(self.releases_cache.clone());
+        req.extensions.insert::<Hub>(self.hub.clone());
+        req.extensions
+            .insert::<AssetIntegrity>(self.asset_integrity.clone());
+        req.extensions.insert::<PageViews>(self.page_views.clone());
 
         Ok(())
     }
@@ -66,3 +91,8 @@ key!(Storage => Arc<Storage>);
 key!(Metrics => Arc<Metrics>);
 key!(TemplateData => Arc<TemplateData>);
 key!(RepositoryStatsUpdater => Arc<RepositoryStatsUpdater>);
+key!(VersionCache => Arc<VersionCache>);
+key!(ReleasesCache => Arc<ReleasesCache>);
+key!(Hub => Arc<Hub>);
+key!(AssetIntegrity => Arc<AssetIntegrity>);
+key!(PageViews => Arc<PageViews>);
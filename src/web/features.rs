@@ -1,9 +1,10 @@
-use super::{match_version, redirect_base, MatchSemver};
+use super::{redirect_base, MatchSemver};
 use crate::db::types::Feature;
 use crate::{
     db::Pool,
     impl_webpage,
     web::{page::WebPage, MetaData},
+    VersionCache,
 };
 use iron::{IronResult, Request, Response, Url};
 use router::Router;
@@ -29,24 +30,26 @@ pub fn build_features_handler(req: &mut Request) -> IronResult<Response> {
     let req_version = router.find("version");
 
     let mut conn = extension!(req, Pool).get()?;
-    let version =
-        match match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())? {
-            MatchSemver::Exact((version, _)) => version,
-
-            MatchSemver::Semver((version, _)) => {
-                let url = ctry!(
-                    req,
-                    Url::parse(&format!(
-                        "{}/crate/{}/{}/features",
-                        redirect_base(req),
-                        name,
-                        version
-                    )),
-                );
-
-                return Ok(super::redirect(url));
-            }
-        };
+    let version = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, req_version)
+        .and_then(|m| m.assume_exact())?
+    {
+        MatchSemver::Exact((version, _)) => version,
+
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/features",
+                    redirect_base(req),
+                    name,
+                    version
+                )),
+            );
+
+            return Ok(super::redirect(url));
+        }
+    };
     let rows = ctry!(
         req,
         conn.query(
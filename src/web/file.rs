@@ -1,12 +1,38 @@
 //! Database based file handler
 
-use crate::storage::{Blob, Storage};
+use crate::storage::{detect_mime, Blob, Storage};
 use crate::{error::Result, Config};
-use iron::{status, Response};
+use iron::{headers::Headers, modifiers::RedirectRaw, status, Response};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub(crate) struct File(pub(crate) Blob);
 
+/// A file, or a temporary url to download it directly from the storage
+#[derive(Debug)]
+pub(super) enum Download {
+    File(File),
+    Redirect(String),
+}
+
+impl Download {
+    pub(super) fn serve(self, request_headers: &Headers) -> Response {
+        use iron::headers::{CacheControl, CacheDirective};
+
+        match self {
+            Download::File(file) => file.serve(request_headers),
+            Download::Redirect(url) => {
+                let mut response = Response::with((status::Found, RedirectRaw(url)));
+                // the url expires, it must not outlive it in a cache
+                response
+                    .headers
+                    .set(CacheControl(vec![CacheDirective::NoStore]));
+                response
+            }
+        }
+    }
+}
+
 impl File {
     /// Gets file from database
     pub(super) fn from_path(storage: &Storage, path: &str, config: &Config) -> Result<File> {
@@ -19,19 +45,91 @@ impl File {
         Ok(File(storage.get(path, max_size)?))
     }
 
+    /// Like `from_path`, without blocking the thread while the file is downloaded
+    #[cfg(feature = "hyper-server")]
+    pub(super) async fn from_path_async(
+        storage: &Storage,
+        path: &str,
+        config: &Config,
+    ) -> Result<File> {
+        let max_size = if path.ends_with(".html") {
+            config.max_file_size_html
+        } else {
+            config.max_file_size
+        };
+
+        Ok(File(storage.get_async(path, max_size).await?))
+    }
+
+    /// Like `from_path`, but binary files larger than `DOCSRS_PRESIGNED_URL_THRESHOLD` are
+    /// redirected to a temporary storage url when the backend supports it, instead of going
+    /// through the web server
+    pub(super) fn download(storage: &Storage, path: &str, config: &Config) -> Result<Download> {
+        let threshold = match config.presigned_url_threshold {
+            Some(threshold)
+                if storage.supports_presigned_urls() && !detect_mime(path).starts_with("text/") =>
+            {
+                threshold
+            }
+            _ => return Ok(Download::File(File::from_path(storage, path, config)?)),
+        };
+
+        // a HEAD request tells the size of the file, only the small ones are downloaded
+        let expires_in = Duration::from_secs(config.presigned_url_expiration);
+        match storage.presigned_url(path, threshold, expires_in)? {
+            Some(url) => Ok(Download::Redirect(url)),
+            None => Ok(Download::File(File::from_path(storage, path, config)?)),
+        }
+    }
+
     /// Consumes File and creates a iron response
-    pub(super) fn serve(self) -> Response {
-        use iron::headers::{CacheControl, CacheDirective, ContentType, HttpDate, LastModified};
+    ///
+    /// Conditional requests are answered with a `304 Not Modified` when the client already has
+    /// the current version of the file, either matching its `ETag` or its modification date.
+    pub(super) fn serve(self, request_headers: &Headers) -> Response {
+        use iron::headers::{
+            CacheControl, CacheDirective, ContentType, ETag, EntityTag, HttpDate, IfModifiedSince,
+            IfNoneMatch, LastModified,
+        };
 
-        let mut response = Response::with((status::Ok, self.0.content));
+        let etag = self
+            .0
+            .content_hash
+            .as_ref()
+            .map(|hash| EntityTag::strong(hash.clone()));
+
+        // If-Modified-Since must be ignored when If-None-Match is present, see RFC 7232 § 3.3
+        let not_modified = match (request_headers.get::<IfNoneMatch>(), &etag) {
+            (Some(IfNoneMatch::Any), Some(_)) => true,
+            (Some(IfNoneMatch::Items(tags)), Some(etag)) => {
+                tags.iter().any(|tag| tag.weak_eq(etag))
+            }
+            (Some(_), None) => false,
+            (None, _) => match request_headers.get::<IfModifiedSince>() {
+                Some(IfModifiedSince(HttpDate(since))) => {
+                    self.0.date_updated.timestamp() <= since.to_timespec().sec
+                }
+                None => false,
+            },
+        };
+
+        let mut response = if not_modified {
+            Response::with(status::NotModified)
+        } else {
+            let mut response = Response::with((status::Ok, self.0.content));
+            response
+                .headers
+                .set(ContentType(self.0.mime.parse().unwrap()));
+            response
+        };
         let cache = vec![
             CacheDirective::Public,
             CacheDirective::MaxAge(super::STATIC_FILE_CACHE_DURATION as u32),
         ];
-        response
-            .headers
-            .set(ContentType(self.0.mime.parse().unwrap()));
         response.headers.set(CacheControl(cache));
+        if let Some(etag) = etag {
+            response.headers.set(ETag(etag));
+        }
         // FIXME: This is so horrible
         response.headers.set(LastModified(HttpDate(
             time::strptime(
@@ -70,7 +168,7 @@ mod tests {
             .unwrap();
             file.0.date_updated = now;
 
-            let resp = file.serve();
+            let resp = file.serve(&Headers::new());
             assert_eq!(
                 resp.headers.get_raw("Last-Modified").unwrap(),
                 [now.format("%a, %d %b %Y %T GMT").to_string().into_bytes()].as_ref(),
@@ -80,6 +178,102 @@ mod tests {
         });
     }
 
+    #[test]
+    fn conditional_requests() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with("dummy/script.js", b"alert(1)" as &[u8])
+                .create()?;
+
+            let web = env.frontend();
+            let path = "/dummy/0.1.0/dummy/script.js";
+
+            let resp = web.get(path).send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            let etag = resp.headers()["ETag"].clone();
+            let last_modified = resp.headers()["Last-Modified"].clone();
+            assert_eq!(resp.text()?, "alert(1)");
+
+            let resp = web.get(path).header("If-None-Match", &etag).send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_MODIFIED);
+            assert_eq!(resp.headers()["ETag"], etag);
+            assert_eq!(resp.text()?, "");
+
+            let resp = web
+                .get(path)
+                .header("If-None-Match", "\"something-else\"")
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+            let resp = web
+                .get(path)
+                .header("If-Modified-Since", &last_modified)
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+            // If-None-Match takes precedence over If-Modified-Since
+            let resp = web
+                .get(path)
+                .header("If-None-Match", "\"something-else\"")
+                .header("If-Modified-Since", &last_modified)
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+            let resp = web
+                .get(path)
+                .header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn download_without_presigned_urls() {
+        wrapper(|env| {
+            env.override_config(|config| config.presigned_url_threshold = Some(16));
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with("dummy/big.wasm", &[b'A'; 64] as &[u8])
+                .create()?;
+
+            // the database backend can't create urls, so the files are served as usual
+            match File::download(
+                &env.storage(),
+                "rustdoc/dummy/0.1.0/dummy/big.wasm",
+                &env.config(),
+            )? {
+                Download::File(file) => assert_eq!(file.0.content.len(), 64),
+                Download::Redirect(url) => panic!("unexpected redirect to {}", url),
+            }
+
+            let resp = env.frontend().get("/dummy/0.1.0/dummy/big.wasm").send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            assert_eq!(resp.bytes()?.len(), 64);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn redirect_to_storage() {
+        let resp = Download::Redirect("https://s3.example.com/file.bin?X-Amz-Signature=abc".into())
+            .serve(&Headers::new());
+        assert_eq!(resp.status, Some(status::Found));
+        assert_eq!(
+            resp.headers.get_raw("Location").unwrap(),
+            [b"https://s3.example.com/file.bin?X-Amz-Signature=abc".to_vec()].as_ref()
+        );
+        assert_eq!(
+            resp.headers.get_raw("Cache-Control").unwrap(),
+            [b"no-store".to_vec()].as_ref()
+        );
+    }
+
     #[test]
     fn test_max_size() {
         const MAX_SIZE: usize = 1024;
@@ -0,0 +1,184 @@
+//! hyper server in front of the Iron server
+//!
+//! Iron only speaks plain HTTP/1.1 and blocks a thread per request. The Iron server listens on the
+//! loopback interface when the frontend is used, and the frontend listens on the configured
+//! address instead, forwarding the requests to it. The frontend terminates TLS and negotiates
+//! HTTP/2 when a certificate is configured (see `tls`), and serves the routes ported to async
+//! itself when the `hyper-server` feature is enabled (see `compat`).
+
+use crate::Context;
+use failure::{Error, ResultExt};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderValue, HOST};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Client, Request, Response, StatusCode, Uri, Version};
+use log::{debug, error};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio_rustls::rustls::{ServerConfig, Session};
+use tokio_rustls::TlsAcceptor;
+
+/// The server forwarding the requests to the Iron server, it stops when it's dropped
+pub(super) struct Frontend {
+    addr: SocketAddr,
+    // the connections are served by the tasks of the runtime
+    _runtime: Runtime,
+}
+
+/// What the connections need to answer the requests
+#[derive(Clone)]
+struct Upstream {
+    client: Client<HttpConnector>,
+    addr: SocketAddr,
+    https: bool,
+    #[cfg(feature = "hyper-server")]
+    routes: Arc<super::compat::AsyncRouter>,
+    #[cfg(feature = "hyper-server")]
+    state: Arc<super::compat::AsyncState>,
+}
+
+impl Frontend {
+    pub(super) fn start(
+        addr: &str,
+        tls: Option<ServerConfig>,
+        upstream: SocketAddr,
+        context: &dyn Context,
+    ) -> Result<Self, Error> {
+        let runtime = Runtime::new()?;
+        let listener = runtime
+            .block_on(TcpListener::bind(addr))
+            .with_context(|_| format!("failed to bind to socket on {}", addr))?;
+        let addr = listener.local_addr()?;
+
+        let upstream = Upstream {
+            client: Client::new(),
+            addr: upstream,
+            https: tls.is_some(),
+            #[cfg(feature = "hyper-server")]
+            routes: Arc::new(super::routes::build_routes().async_router()),
+            #[cfg(feature = "hyper-server")]
+            state: Arc::new(super::compat::AsyncState::new(context)?),
+        };
+        #[cfg(not(feature = "hyper-server"))]
+        let _ = context;
+
+        let acceptor = tls.map(|config| TlsAcceptor::from(Arc::new(config)));
+        runtime.spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("failed to accept a connection: {}", err);
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let upstream = upstream.clone();
+                tokio::spawn(async move {
+                    let acceptor = match acceptor {
+                        Some(acceptor) => acceptor,
+                        None => return serve(stream, Http::new(), peer, upstream).await,
+                    };
+                    let stream = match acceptor.accept(stream).await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            debug!("TLS handshake with {} failed: {}", peer, err);
+                            return;
+                        }
+                    };
+
+                    // without ALPN, hyper detects the HTTP/2 connection preface by itself
+                    let mut http = Http::new();
+                    if stream.get_ref().1.get_alpn_protocol() == Some(b"h2") {
+                        http.http2_only(true);
+                    }
+                    serve(stream, http, peer, upstream).await;
+                });
+            }
+        });
+
+        Ok(Self {
+            addr,
+            _runtime: runtime,
+        })
+    }
+
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+async fn serve<S>(stream: S, http: Http, peer: SocketAddr, upstream: Upstream)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |req| handle(upstream.clone(), req));
+    if let Err(err) = http.serve_connection(stream, service).await {
+        debug!("error while serving {}: {}", peer, err);
+    }
+}
+
+async fn handle(upstream: Upstream, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    #[cfg(feature = "hyper-server")]
+    {
+        if let Some(response) = upstream.routes.handle(&upstream.state, &req).await {
+            return Ok(response);
+        }
+    }
+
+    forward(upstream, req).await
+}
+
+/// Forwards the request to the Iron server over HTTP/1.1
+async fn forward(upstream: Upstream, mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    // HTTP/2 requests carry the host in the `:authority` pseudo-header instead
+    if !req.headers().contains_key(HOST) {
+        let authority = req
+            .uri()
+            .authority()
+            .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok());
+        if let Some(authority) = authority {
+            req.headers_mut().insert(HOST, authority);
+        }
+    }
+    // the redirects built by the handlers keep the scheme of the request
+    if upstream.https {
+        req.headers_mut().insert(
+            "cloudfront-forwarded-proto",
+            HeaderValue::from_static("https"),
+        );
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    *req.uri_mut() = match format!("http://{}{}", upstream.addr, path).parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(err) => {
+            debug!("invalid request path {:?}: {}", path, err);
+            return Ok(status_response(StatusCode::BAD_REQUEST));
+        }
+    };
+    *req.version_mut() = Version::HTTP_11;
+
+    Ok(match upstream.client.request(req).await {
+        Ok(response) => response,
+        Err(err) => {
+            error!("failed to forward the request to the web server: {}", err);
+            status_response(StatusCode::BAD_GATEWAY)
+        }
+    })
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,682 @@
+//! Read-only GraphQL API over crates, releases, builds and documentation coverage
+//!
+//! Only compiled with the `graphql` feature. The endpoint lives at `/-/graphql` and accepts both
+//! `GET` requests (with `query`, `operationName`, `variables` and `id` query parameters) and
+//! `POST` requests with a JSON body containing the same fields.
+//!
+//! Every query is checked against `DOCSRS_GRAPHQL_MAX_DEPTH` (how deeply fields can be nested)
+//! and `DOCSRS_GRAPHQL_MAX_COMPLEXITY` (how many fields can be selected in total). When
+//! `DOCSRS_GRAPHQL_PERSISTED_QUERIES` points to a directory, arbitrary queries are rejected and
+//! clients have to pass the `id` of one of the `<id>.graphql` files in that directory instead.
+
+use crate::{db::Pool, db::PoolClient, Config};
+use chrono::{DateTime, Utc};
+use failure::Error;
+use iron::{
+    headers::{CacheControl, CacheDirective, ContentType},
+    method::Method,
+    status, IronResult, Request, Response,
+};
+use juniper::{
+    DefaultScalarValue, EmptyMutation, Executor, FieldError, FieldResult, InputValue,
+    LookAheadMethods, LookAheadSelection, RootNode,
+};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Read;
+
+/// Maximum size of a request body
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+/// Maximum number of releases returned by `Crate.releases`
+const MAX_RELEASES: i32 = 100;
+
+type Schema = RootNode<'static, Query, EmptyMutation<GraphQLContext>>;
+
+/// Per-request state shared by all the resolvers
+pub(crate) struct GraphQLContext {
+    conn: RefCell<PoolClient>,
+    max_depth: usize,
+    max_complexity: usize,
+    /// Fields selected so far by the top-level fields of the query
+    complexity: Cell<usize>,
+    // Resolvers memoize their results, so that the same data selected multiple times in a query
+    // (for example through aliases or fragments) is only loaded once
+    crates: RefCell<HashMap<String, Option<Crate>>>,
+    releases: RefCell<HashMap<i32, Vec<Release>>>,
+    builds: RefCell<HashMap<i32, Vec<Build>>>,
+    coverage: RefCell<HashMap<i32, Option<DocCoverage>>>,
+}
+
+impl juniper::Context for GraphQLContext {}
+
+impl GraphQLContext {
+    fn new(conn: PoolClient, config: &Config) -> Self {
+        Self {
+            conn: RefCell::new(conn),
+            max_depth: config.graphql_max_depth,
+            max_complexity: config.graphql_max_complexity,
+            complexity: Cell::new(0),
+            crates: RefCell::new(HashMap::new()),
+            releases: RefCell::new(HashMap::new()),
+            builds: RefCell::new(HashMap::new()),
+            coverage: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Checks the selection of a top-level field against the depth and complexity limits
+    fn check_limits(&self, executor: &Executor<Self>) -> FieldResult<()> {
+        let (depth, fields) = measure(&executor.look_ahead(), 1, self.max_depth);
+        if depth > self.max_depth {
+            return Err(FieldError::from(format!(
+                "query is nested too deeply (maximum depth is {})",
+                self.max_depth
+            )));
+        }
+
+        let complexity = self.complexity.get() + fields;
+        self.complexity.set(complexity);
+        if complexity > self.max_complexity {
+            return Err(FieldError::from(format!(
+                "query selects too many fields (maximum complexity is {})",
+                self.max_complexity
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn cached<K, V>(
+        &self,
+        cache: &RefCell<HashMap<K, V>>,
+        key: K,
+        load: impl FnOnce(&mut PoolClient) -> Result<V, Error>,
+    ) -> Result<V, Error>
+    where
+        K: Eq + Hash,
+        V: Clone,
+    {
+        if let Some(value) = cache.borrow().get(&key) {
+            return Ok(value.clone());
+        }
+
+        let value = load(&mut self.conn.borrow_mut())?;
+        cache.borrow_mut().insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+/// Returns the depth of the selection and the number of fields it contains. Fields deeper than
+/// `max_depth` are not visited.
+fn measure(
+    selection: &LookAheadSelection<DefaultScalarValue>,
+    depth: usize,
+    max_depth: usize,
+) -> (usize, usize) {
+    if depth > max_depth {
+        return (depth, 1);
+    }
+
+    let (mut max_child_depth, mut fields) = (depth, 1);
+    for name in selection.child_names() {
+        if let Some(child) = selection.select_child(name) {
+            let (child_depth, child_fields) = measure(child, depth + 1, max_depth);
+            max_child_depth = max_child_depth.max(child_depth);
+            fields += child_fields;
+        }
+    }
+
+    (max_child_depth, fields)
+}
+
+pub(crate) struct Query;
+
+#[juniper::object(Context = GraphQLContext)]
+impl Query {
+    /// Looks up a crate by its exact name
+    #[graphql(name = "crate")]
+    fn krate(
+        context: &GraphQLContext,
+        executor: &Executor,
+        name: String,
+    ) -> FieldResult<Option<Crate>> {
+        context.check_limits(executor)?;
+        Ok(Crate::load(context, name)?)
+    }
+
+    /// Looks up a single release of a crate
+    fn release(
+        context: &GraphQLContext,
+        executor: &Executor,
+        name: String,
+        version: String,
+    ) -> FieldResult<Option<Release>> {
+        context.check_limits(executor)?;
+        Ok(match Crate::load(context, name)? {
+            Some(krate) => krate
+                .load_releases(context)?
+                .into_iter()
+                .find(|release| release.version == version),
+            None => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Crate {
+    id: i32,
+    name: String,
+}
+
+impl Crate {
+    fn load(context: &GraphQLContext, name: String) -> Result<Option<Self>, Error> {
+        context.cached(&context.crates, name.clone(), |conn| {
+            Ok(conn
+                .query_opt("SELECT id, name FROM crates WHERE name = $1", &[&name])?
+                .map(|row| Crate {
+                    id: row.get("id"),
+                    name: row.get("name"),
+                }))
+        })
+    }
+
+    /// All the releases of the crate, newest first
+    fn load_releases(&self, context: &GraphQLContext) -> Result<Vec<Release>, Error> {
+        context.cached(&context.releases, self.id, |conn| {
+            Ok(conn
+                .query(
+                    "SELECT
+                         id,
+                         version,
+                         release_time,
+                         yanked,
+                         is_library,
+                         build_status,
+                         rustdoc_status,
+                         license,
+                         description,
+                         default_target,
+                         doc_targets
+                     FROM releases
+                     WHERE crate_id = $1
+                     ORDER BY release_time DESC",
+                    &[&self.id],
+                )?
+                .into_iter()
+                .map(|row| Release {
+                    id: row.get("id"),
+                    version: row.get("version"),
+                    release_time: row.get("release_time"),
+                    yanked: row.get::<_, Option<bool>>("yanked").unwrap_or(false),
+                    is_library: row.get("is_library"),
+                    build_status: row.get("build_status"),
+                    rustdoc_status: row.get("rustdoc_status"),
+                    license: row.get("license"),
+                    description: row.get("description"),
+                    default_target: row.get("default_target"),
+                    doc_targets: row
+                        .get::<_, Option<serde_json::Value>>("doc_targets")
+                        .and_then(|targets| serde_json::from_value(targets).ok())
+                        .unwrap_or_default(),
+                })
+                .collect())
+        })
+    }
+}
+
+#[juniper::object(Context = GraphQLContext)]
+impl Crate {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The releases of the crate, newest first
+    fn releases(&self, context: &GraphQLContext, limit: Option<i32>) -> FieldResult<Vec<Release>> {
+        let limit = limit.unwrap_or(MAX_RELEASES).clamp(0, MAX_RELEASES) as usize;
+        let mut releases = self.load_releases(context)?;
+        releases.truncate(limit);
+        Ok(releases)
+    }
+
+    /// The newest release that wasn't yanked
+    fn latest_release(&self, context: &GraphQLContext) -> FieldResult<Option<Release>> {
+        Ok(self
+            .load_releases(context)?
+            .into_iter()
+            .find(|release| !release.yanked))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Release {
+    id: i32,
+    version: String,
+    release_time: Option<DateTime<Utc>>,
+    yanked: bool,
+    is_library: bool,
+    build_status: bool,
+    rustdoc_status: bool,
+    license: Option<String>,
+    description: Option<String>,
+    default_target: Option<String>,
+    doc_targets: Vec<String>,
+}
+
+#[juniper::object(Context = GraphQLContext)]
+impl Release {
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// When the release was published, in RFC 3339 format
+    fn release_time(&self) -> Option<String> {
+        self.release_time.map(|time| time.to_rfc3339())
+    }
+
+    fn yanked(&self) -> bool {
+        self.yanked
+    }
+
+    fn is_library(&self) -> bool {
+        self.is_library
+    }
+
+    /// Whether the latest build of the release succeeded
+    fn build_status(&self) -> bool {
+        self.build_status
+    }
+
+    /// Whether the release has documentation
+    fn rustdoc_status(&self) -> bool {
+        self.rustdoc_status
+    }
+
+    fn license(&self) -> Option<&str> {
+        self.license.as_deref()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    fn default_target(&self) -> Option<&str> {
+        self.default_target.as_deref()
+    }
+
+    /// The targets the release was documented for
+    fn doc_targets(&self) -> &[String] {
+        &self.doc_targets
+    }
+
+    /// The builds of the release, newest first
+    fn builds(&self, context: &GraphQLContext) -> FieldResult<Vec<Build>> {
+        Ok(context.cached(&context.builds, self.id, |conn| {
+            Ok(conn
+                .query(
+                    "SELECT id, rustc_version, docsrs_version, build_status, build_time
+                     FROM builds
+                     WHERE rid = $1
+                     ORDER BY id DESC",
+                    &[&self.id],
+                )?
+                .into_iter()
+                .map(|row| Build {
+                    id: row.get("id"),
+                    rustc_version: row.get("rustc_version"),
+                    docsrs_version: row.get("docsrs_version"),
+                    build_status: row.get("build_status"),
+                    build_time: row.get("build_time"),
+                })
+                .collect())
+        })?)
+    }
+
+    fn doc_coverage(&self, context: &GraphQLContext) -> FieldResult<Option<DocCoverage>> {
+        Ok(context.cached(&context.coverage, self.id, |conn| {
+            Ok(conn
+                .query_opt(
+                    "SELECT
+                         total_items,
+                         documented_items,
+                         total_items_needing_examples,
+                         items_with_examples
+                     FROM doc_coverage
+                     WHERE release_id = $1",
+                    &[&self.id],
+                )?
+                .map(|row| DocCoverage {
+                    total_items: row.get("total_items"),
+                    documented_items: row.get("documented_items"),
+                    total_items_needing_examples: row.get("total_items_needing_examples"),
+                    items_with_examples: row.get("items_with_examples"),
+                }))
+        })?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Build {
+    id: i32,
+    rustc_version: String,
+    docsrs_version: String,
+    build_status: bool,
+    build_time: DateTime<Utc>,
+}
+
+#[juniper::object(Context = GraphQLContext)]
+impl Build {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn rustc_version(&self) -> &str {
+        &self.rustc_version
+    }
+
+    fn docsrs_version(&self) -> &str {
+        &self.docsrs_version
+    }
+
+    fn build_status(&self) -> bool {
+        self.build_status
+    }
+
+    /// When the build finished, in RFC 3339 format
+    fn build_time(&self) -> String {
+        self.build_time.to_rfc3339()
+    }
+}
+
+#[derive(Debug, Clone, juniper::GraphQLObject)]
+pub(crate) struct DocCoverage {
+    total_items: Option<i32>,
+    documented_items: Option<i32>,
+    total_items_needing_examples: Option<i32>,
+    items_with_examples: Option<i32>,
+}
+
+/// A GraphQL request, either parsed from the query string or from the JSON body
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQLRequest {
+    query: Option<String>,
+    /// The id of a persisted query
+    id: Option<String>,
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+impl GraphQLRequest {
+    fn from_request(req: &mut Request) -> Result<Self, String> {
+        if req.method == Method::Post {
+            let mut body = String::new();
+            (&mut req.body)
+                .take(MAX_BODY_SIZE)
+                .read_to_string(&mut body)
+                .map_err(|err| format!("failed to read the request body: {}", err))?;
+            return serde_json::from_str(&body)
+                .map_err(|err| format!("invalid request body: {}", err));
+        }
+
+        let mut request = GraphQLRequest::default();
+        for (key, value) in req.url.as_ref().query_pairs() {
+            match key.as_ref() {
+                "query" => request.query = Some(value.into_owned()),
+                "id" => request.id = Some(value.into_owned()),
+                "operationName" => request.operation_name = Some(value.into_owned()),
+                "variables" => {
+                    request.variables = Some(
+                        serde_json::from_str(&value)
+                            .map_err(|err| format!("invalid variables: {}", err))?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        Ok(request)
+    }
+
+    /// Returns the query to execute, enforcing the persisted queries allowlist if configured
+    fn document(&self, config: &Config) -> Result<String, String> {
+        match (&config.graphql_persisted_queries, &self.id, &self.query) {
+            (Some(dir), Some(id), _) => {
+                let valid_id = !id.is_empty()
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                let path = dir.join(format!("{}.graphql", id));
+                if !valid_id || !path.is_file() {
+                    return Err(format!("unknown persisted query: {}", id));
+                }
+                std::fs::read_to_string(&path)
+                    .map_err(|err| format!("failed to load persisted query {}: {}", id, err))
+            }
+            (Some(_), None, _) => Err("only persisted queries are allowed".into()),
+            (None, Some(_), _) => Err("persisted queries are not enabled".into()),
+            (None, None, Some(query)) => Ok(query.clone()),
+            (None, None, None) => Err("missing query".into()),
+        }
+    }
+}
+
+fn json_response(status: status::Status, body: String) -> Response {
+    let mut resp = Response::with((status, body));
+    resp.headers.set(ContentType::json());
+    resp.headers
+        .set(CacheControl(vec![CacheDirective::MaxAge(60)]));
+    resp
+}
+
+fn error_response(message: String) -> Response {
+    json_response(
+        status::BadRequest,
+        serde_json::json!({ "errors": [{ "message": message }] }).to_string(),
+    )
+}
+
+pub fn graphql_handler(req: &mut Request) -> IronResult<Response> {
+    let config = extension!(req, Config).clone();
+    let conn = extension!(req, Pool).get()?;
+
+    let request = match GraphQLRequest::from_request(req) {
+        Ok(request) => request,
+        Err(message) => return Ok(error_response(message)),
+    };
+    let document = match request.document(&config) {
+        Ok(document) => document,
+        Err(message) => return Ok(error_response(message)),
+    };
+    let schema = Schema::new(Query, EmptyMutation::new());
+    let context = GraphQLContext::new(conn, &config);
+    let request = juniper::http::GraphQLRequest::new(
+        document,
+        request.operation_name.clone(),
+        request.variables.clone(),
+    );
+    let response = request.execute(&schema, &context);
+
+    let status = if response.is_ok() {
+        status::Ok
+    } else {
+        status::BadRequest
+    };
+    Ok(json_response(
+        status,
+        ctry!(req, serde_json::to_string(&response)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docbuilder::DocCoverage;
+    use crate::test::{wrapper, FakeBuild, TestEnvironment};
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+    use std::fs;
+
+    fn query(env: &TestEnvironment, query: &str) -> Result<(StatusCode, Value), failure::Error> {
+        let resp = env
+            .frontend()
+            .post("/-/graphql")
+            .json(&json!({ "query": query }))
+            .send()?;
+        Ok((resp.status(), resp.json()?))
+    }
+
+    #[test]
+    fn crate_releases_builds() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default().rustc_version("rustc 1.0.0")])
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .doc_coverage(DocCoverage {
+                    total_items: 10,
+                    documented_items: 5,
+                    total_items_needing_examples: 0,
+                    items_with_examples: 0,
+                })
+                .create()?;
+
+            let (status, resp) = query(
+                env,
+                r#"{
+                    crate(name: "foo") {
+                        name
+                        latestRelease { version docCoverage { totalItems documentedItems } }
+                        releases { version builds { rustcVersion buildStatus } }
+                    }
+                }"#,
+            )?;
+            assert_eq!(status, StatusCode::OK, "{}", resp);
+            let krate = &resp["data"]["crate"];
+            assert_eq!(krate["name"], "foo");
+            assert_eq!(krate["latestRelease"]["version"], "0.2.0");
+            assert_eq!(
+                krate["latestRelease"]["docCoverage"],
+                json!({ "totalItems": 10, "documentedItems": 5 })
+            );
+            assert_eq!(krate["releases"][1]["version"], "0.1.0");
+            assert_eq!(
+                krate["releases"][1]["builds"],
+                json!([{ "rustcVersion": "rustc 1.0.0", "buildStatus": true }])
+            );
+
+            let (status, resp) = query(env, r#"{ crate(name: "bar") { name } }"#)?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(resp["data"]["crate"], Value::Null);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn get_request() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let resp = env
+                .frontend()
+                .get("/-/graphql")
+                .query(&[
+                    (
+                        "query",
+                        "query Release($version: String!) { \
+                             release(name: \"foo\", version: $version) { version rustdocStatus } \
+                         }",
+                    ),
+                    ("variables", r#"{"version": "0.1.0"}"#),
+                ])
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp: Value = resp.json()?;
+            assert_eq!(
+                resp["data"]["release"],
+                json!({ "version": "0.1.0", "rustdocStatus": true })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn limits() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.graphql_max_depth = 3;
+                config.graphql_max_complexity = 4;
+            });
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let (status, _) = query(env, r#"{ crate(name: "foo") { releases { version } } }"#)?;
+            assert_eq!(status, StatusCode::OK);
+
+            let (status, resp) = query(
+                env,
+                r#"{ crate(name: "foo") { releases { builds { id } } } }"#,
+            )?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(resp["data"]["crate"], Value::Null);
+            assert!(resp["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("nested too deeply"));
+
+            // the complexity is counted over all the top-level fields
+            let (_, resp) = query(
+                env,
+                r#"{
+                    a: crate(name: "foo") { name releases { version } }
+                    b: crate(name: "foo") { name }
+                }"#,
+            )?;
+            assert!(resp["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("too many fields"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn persisted_queries() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            fs::write(
+                dir.path().join("crate-name.graphql"),
+                r#"{ crate(name: "foo") { name } }"#,
+            )?;
+            let path = dir.path().to_owned();
+            env.override_config(|config| config.graphql_persisted_queries = Some(path));
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let (status, resp) = query(env, r#"{ crate(name: "foo") { name } }"#)?;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(
+                resp["errors"][0]["message"],
+                "only persisted queries are allowed"
+            );
+
+            let resp = env.frontend().get("/-/graphql?id=crate-name").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp: Value = resp.json()?;
+            assert_eq!(resp["data"]["crate"]["name"], "foo");
+
+            for id in &["missing", "..%2Fcrate-name"] {
+                let resp = env
+                    .frontend()
+                    .get(&format!("/-/graphql?id={}", id))
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            }
+
+            Ok(())
+        });
+    }
+}
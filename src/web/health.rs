@@ -0,0 +1,149 @@
+//! Health and readiness checks for load balancers and orchestrators
+//!
+//! `/-/health` only checks the state of the web server process itself, while `/-/ready` also
+//! checks that the database and the storage backend are reachable. Both return a JSON report and
+//! `503 Service Unavailable` if any check failed.
+
+use super::page::TemplateData;
+use crate::{db::Pool, Storage};
+use iron::{
+    headers::{CacheControl, CacheDirective, ContentType},
+    status, IronResult, Request, Response,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Path looked up in the storage to check it's reachable, it doesn't need to exist
+const STORAGE_PROBE_PATH: &str = "health-check";
+
+#[derive(Debug, Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<E: std::fmt::Display> From<Result<(), E>> for Check {
+    fn from(res: Result<(), E>) -> Self {
+        match res {
+            Ok(()) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(err) => Check {
+                ok: false,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    ok: bool,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+impl Report {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        Report {
+            ok: checks.values().all(|check| check.ok),
+            checks,
+        }
+    }
+
+    fn into_response(self) -> Response {
+        let status = if self.ok {
+            status::Ok
+        } else {
+            status::ServiceUnavailable
+        };
+
+        let mut resp = Response::with((status, serde_json::to_string(&self).unwrap()));
+        resp.headers.set(ContentType::json());
+        resp.headers.set(CacheControl(vec![
+            CacheDirective::NoCache,
+            CacheDirective::NoStore,
+        ]));
+        resp
+    }
+}
+
+fn check_templates(template_data: &TemplateData) -> Check {
+    // every page extends the base template
+    template_data
+        .templates
+        .load()
+        .get_template("base.html")
+        .map(drop)
+        .into()
+}
+
+fn check_database(pool: &Pool) -> Check {
+    pool.get()
+        .map_err(failure::Error::from)
+        .and_then(|mut conn| Ok(conn.execute("SELECT 1", &[]).map(drop)?))
+        .into()
+}
+
+fn check_storage(storage: &Storage) -> Check {
+    storage.exists(STORAGE_PROBE_PATH).map(drop).into()
+}
+
+pub fn health_handler(req: &mut Request) -> IronResult<Response> {
+    let mut checks = BTreeMap::new();
+    checks.insert("templates", check_templates(extension!(req, TemplateData)));
+
+    Ok(Report::new(checks).into_response())
+}
+
+pub fn ready_handler(req: &mut Request) -> IronResult<Response> {
+    let mut checks = BTreeMap::new();
+    checks.insert("templates", check_templates(extension!(req, TemplateData)));
+    checks.insert("database", check_database(extension!(req, Pool)));
+    checks.insert("storage", check_storage(extension!(req, Storage)));
+
+    Ok(Report::new(checks).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    #[test]
+    fn health() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/-/health").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get("Content-Type").unwrap(),
+                "application/json"
+            );
+
+            let report: Value = resp.json()?;
+            assert_eq!(report["ok"], true);
+            assert_eq!(report["checks"]["templates"]["ok"], true);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn ready() {
+        wrapper(|env| {
+            let resp = env.frontend().get("/-/ready").send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let report: Value = resp.json()?;
+            assert_eq!(report["ok"], true);
+            for check in &["templates", "database", "storage"] {
+                assert_eq!(report["checks"][check]["ok"], true, "{} failed", check);
+                assert!(report["checks"][check].get("error").is_none());
+            }
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,284 @@
+//! Server-side syntax highlighting of the source browser
+//!
+//! Source files are highlighted with the syntaxes bundled with syntect, producing `syntax-*`
+//! classes styled like rustdoc's own source pages in every theme. Highlighting a large file takes
+//! a while, so the HTML is stored under `highlighted/` the first time a file is viewed.
+//!
+//! In Rust files, the names of the items recorded by the build (see `utils::definitions`) are
+//! linked to their definitions, unless they appear in a comment or a string.
+
+use crate::utils::definitions::Definitions;
+use crate::Storage;
+use once_cell::sync::Lazy;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Prefix of the highlighted copies of the files stored under `sources/`
+pub(crate) const HIGHLIGHTED_PREFIX: &str = "highlighted";
+/// Larger files are escaped without being highlighted
+const MAX_HIGHLIGHTED_SIZE: usize = 512 * 1024;
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "syntax-" };
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+fn syntax_for(path: &str) -> &'static SyntaxReference {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let extension = match file_name.rsplit_once('.') {
+        Some((_, extension)) => extension,
+        // files without an extension, like `Makefile`
+        None => file_name,
+    };
+    SYNTAXES
+        .find_syntax_by_extension(extension)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text())
+}
+
+/// Highlights the content of a file, the language is guessed from its path. The returned HTML is
+/// escaped and can be embedded in a `<pre>` as-is.
+pub(crate) fn highlight(code: &str, path: &str) -> String {
+    let syntax = if code.len() > MAX_HIGHLIGHTED_SIZE {
+        SYNTAXES.find_syntax_plain_text()
+    } else {
+        syntax_for(path)
+    };
+
+    let mut html = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        html.parse_html_for_line_which_includes_newline(line);
+    }
+    html.finalize()
+}
+
+/// Links the names of the items in highlighted Rust code to the lines defining them
+fn link_definitions(html: &str, file: &str, definitions: &Definitions, base_url: &str) -> String {
+    let mut linked = String::with_capacity(html.len());
+    // whether each of the open spans is a comment or a string
+    let mut spans: Vec<bool> = Vec::new();
+    let mut line: u32 = 1;
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = &rest[..end];
+            if tag.starts_with("</") {
+                spans.pop();
+            } else {
+                let ignored = tag.contains("syntax-comment") || tag.contains("syntax-string");
+                spans.push(ignored || spans.last().copied().unwrap_or(false));
+            }
+            linked.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        rest = &rest[end..];
+        if spans.last().copied().unwrap_or(false) {
+            line += text.matches('\n').count() as u32;
+            linked.push_str(text);
+            continue;
+        }
+
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c == '&' {
+                // entities like `&lt;` are not identifiers
+                let mut entity_end = start + 1;
+                while let Some(&(i, c)) = chars.peek() {
+                    chars.next();
+                    entity_end = i + c.len_utf8();
+                    if c == ';' {
+                        break;
+                    }
+                }
+                linked.push_str(&text[start..entity_end]);
+            } else if c == '_' || c.is_alphabetic() {
+                let mut ident_end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c != '_' && !c.is_alphanumeric() {
+                        break;
+                    }
+                    chars.next();
+                    ident_end = i + c.len_utf8();
+                }
+                let ident = &text[start..ident_end];
+                match definitions.get(ident) {
+                    // the definition itself isn't linked
+                    Some(definition) if definition.file != file || definition.line != line => {
+                        linked.push_str(&format!(
+                            "<a class=\"definition\" href=\"{}{}#L{}\" title=\"{} {}\">{}</a>",
+                            base_url,
+                            definition.file,
+                            definition.line,
+                            definition.kind,
+                            ident,
+                            ident
+                        ));
+                    }
+                    _ => linked.push_str(ident),
+                }
+            } else {
+                if c == '\n' {
+                    line += 1;
+                }
+                linked.push(c);
+            }
+        }
+    }
+
+    linked
+}
+
+/// Highlights a file of the sources of a release, reusing the copy stored by a previous view
+pub(crate) fn highlight_source(
+    storage: &Storage,
+    name: &str,
+    version: &str,
+    path: &str,
+    code: &str,
+    max_size: usize,
+) -> String {
+    let highlighted_path = format!("{}/{}/{}/{}", HIGHLIGHTED_PREFIX, name, version, path);
+
+    if let Ok(blob) = storage.get(&highlighted_path, max_size) {
+        if let Ok(html) = String::from_utf8(blob.content) {
+            return html;
+        }
+    }
+
+    let mut html = highlight(code, path);
+    if html.contains("syntax-rust") {
+        match Definitions::load(storage, name, version, max_size) {
+            Ok(Some(definitions)) => {
+                let base_url = format!("/crate/{}/{}/source/", name, version);
+                html = link_definitions(&html, path, &definitions, &base_url);
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!(
+                "failed to load the definitions of {} {}: {}",
+                name,
+                version,
+                err
+            ),
+        }
+    }
+
+    // the highlighted copy is only a cache, the file can still be shown without it
+    if let Err(err) = storage.store_one(highlighted_path, html.clone()) {
+        log::warn!("failed to store the highlighted {}: {}", path, err);
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn languages() {
+        let rust = highlight("fn main() {}\n", "src/main.rs");
+        assert!(rust.contains(
+            r#"<span class="syntax-storage syntax-type syntax-function syntax-rust">fn</span>"#
+        ));
+
+        let markdown = highlight("# Title\n", "README.md");
+        assert!(markdown.contains("syntax-markdown"));
+
+        // unknown languages are only escaped
+        assert_eq!(
+            highlight("<script>alert(1)</script>\n", "LICENSE"),
+            "<span class=\"syntax-text syntax-plain\">&lt;script&gt;alert(1)&lt;/script&gt;\n</span>"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let html = highlight("let html = \"<b>&</b>\";\n", "src/lib.rs");
+        assert!(html.contains("&lt;b&gt;&amp;&lt;/b&gt;"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn large_files_are_not_highlighted() {
+        let code = "fn main() {}\n".repeat(MAX_HIGHLIGHTED_SIZE / 10);
+        assert!(!highlight(&code, "src/main.rs").contains("syntax-rust"));
+    }
+
+    #[test]
+    fn stored_in_storage() {
+        wrapper(|env| {
+            let storage = env.storage();
+            let html = highlight_source(
+                &storage,
+                "foo",
+                "0.1.0",
+                "src/lib.rs",
+                "fn a() {}\n",
+                64 * 1024,
+            );
+            assert!(html.contains("syntax-rust"));
+            assert_eq!(
+                storage
+                    .get("highlighted/foo/0.1.0/src/lib.rs", 64 * 1024)?
+                    .content,
+                html.as_bytes()
+            );
+
+            // the stored copy is used by the next views
+            storage.store_one("highlighted/foo/0.1.0/src/lib.rs", "cached")?;
+            assert_eq!(
+                highlight_source(
+                    &storage,
+                    "foo",
+                    "0.1.0",
+                    "src/lib.rs",
+                    "fn a() {}\n",
+                    64 * 1024
+                ),
+                "cached"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn definitions() {
+        let mut definitions = Definitions::default();
+        definitions.add("Foo", "src/lib.rs", 1, "struct");
+        definitions.add("bar", "src/bar.rs", 3, "function");
+        definitions.add("lt", "src/bar.rs", 4, "function");
+
+        let code =
+            "struct Foo;\n// Foo\nfn a(x: Foo) -> &'static str { bar(); \"Foo\"; lt(1 < 2) }\n";
+        let html = link_definitions(
+            &highlight(code, "src/lib.rs"),
+            "src/lib.rs",
+            &definitions,
+            "/s/",
+        );
+        let links: Vec<_> = html.match_indices("<a ").collect();
+        assert_eq!(links.len(), 3, "{}", html);
+        assert!(html.contains(
+            r#"<a class="definition" href="/s/src/lib.rs#L1" title="struct Foo">Foo</a>"#
+        ));
+        assert!(html.contains(r#"href="/s/src/bar.rs#L3""#));
+        assert!(html.contains(r#"href="/s/src/bar.rs#L4""#));
+        // the escaped `<` is left untouched
+        assert!(html.contains("&lt;"));
+        assert_eq!(kuchiki_text(&html), code,);
+    }
+
+    fn kuchiki_text(html: &str) -> String {
+        use kuchiki::traits::TendrilSink;
+        kuchiki::parse_html()
+            .one(format!("<pre>{}</pre>", html))
+            .select_first("pre")
+            .unwrap()
+            .text_contents()
+    }
+}
@@ -4,7 +4,9 @@ use crate::Metrics;
 use iron::headers::ContentType;
 use iron::prelude::*;
 use iron::status::Status;
+use iron::{AfterMiddleware, BeforeMiddleware};
 use prometheus::{Encoder, HistogramVec, TextEncoder};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 pub(super) fn metrics_handler(req: &mut Request) -> IronResult<Response> {
@@ -25,7 +27,7 @@ pub(super) fn metrics_handler(req: &mut Request) -> IronResult<Response> {
 
 /// Converts a `Duration` to seconds, used by prometheus internally
 #[inline]
-fn duration_to_seconds(d: Duration) -> f64 {
+pub(super) fn duration_to_seconds(d: Duration) -> f64 {
     let nanos = f64::from(d.subsec_nanos()) / 1e9;
     d.as_secs() as f64 + nanos
 }
@@ -46,6 +48,10 @@ impl RequestRecorder {
 
 impl iron::Handler for RequestRecorder {
     fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        request
+            .extensions
+            .insert::<RouteName>(self.route_name.clone());
+
         let start = Instant::now();
         let result = self.handler.handle(request);
         let resp_time = duration_to_seconds(start.elapsed());
@@ -64,6 +70,74 @@ impl iron::Handler for RequestRecorder {
     }
 }
 
+/// The name of the route that handled the request, as recorded by `RequestRecorder`
+pub(super) struct RouteName;
+
+impl iron::typemap::Key for RouteName {
+    type Value = String;
+}
+
+struct RequestStart;
+
+impl iron::typemap::Key for RequestStart {
+    type Value = Instant;
+}
+
+/// Middleware recording the duration and the status code of every response, labelled with the
+/// name of the route that handled it.
+///
+/// Unlike `RequestRecorder`, this wraps the whole request, including the error pages rendered for
+/// failed requests.
+#[derive(Clone)]
+pub(super) struct ResponseRecorder {
+    metrics: Arc<Metrics>,
+}
+
+impl ResponseRecorder {
+    pub(super) fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+
+    fn record(&self, req: &Request, status: Option<Status>) {
+        let route = req
+            .extensions
+            .get::<RouteName>()
+            .map(String::as_str)
+            .unwrap_or("unmatched");
+        let status = status.map_or_else(|| "unknown".into(), |s| s.to_u16().to_string());
+
+        self.metrics
+            .http_responses
+            .with_label_values(&[route, &status])
+            .inc();
+        if let Some(start) = req.extensions.get::<RequestStart>() {
+            self.metrics
+                .http_request_duration
+                .with_label_values(&[route])
+                .observe(duration_to_seconds(start.elapsed()));
+        }
+    }
+}
+
+impl BeforeMiddleware for ResponseRecorder {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions.insert::<RequestStart>(Instant::now());
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for ResponseRecorder {
+    fn after(&self, req: &mut Request, res: Response) -> IronResult<Response> {
+        self.record(req, res.status);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+        self.record(req, err.response.status);
+        Err(err)
+    }
+}
+
 struct RenderingTime {
     start: Instant,
     step: &'static str,
@@ -127,10 +201,10 @@ mod tests {
             ("/releases/queue", "/releases/queue"),
             ("/releases/recent-failures", "/releases/recent-failures"),
             (
-                "/releases/recent-failures/1",
-                "/releases/recent-failures/:page",
+                "/releases/recent-failures?after=1_1",
+                "/releases/recent-failures",
             ),
-            ("/releases/recent/1", "/releases/recent/:page"),
+            ("/releases/recent", "/releases/recent"),
             ("/-/static/robots.txt", "static resource"),
             ("/sitemap.xml", "static resource"),
             ("/-/static/style.css", "static resource"),
@@ -210,6 +284,47 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_response_status_codes_being_collected() {
+        wrapper(|env| {
+            env.fake_release().name("rcc").version("0.0.0").create()?;
+
+            let frontend = env.frontend();
+            for path in &[
+                "/crate/rcc/0.0.0",
+                "/crate/rcc/0.0.0/builds/12345",
+                "/rcc/0.0.0/rcc/",
+                "/rcc/0.0.0/rcc/",
+                "/rcc/0.0.0/rcc/not-found.html",
+                "/-/static/style.css",
+            ] {
+                frontend.get(path).send()?;
+            }
+
+            let metrics = env.metrics();
+            let responses = |route: &str, status: &str| {
+                metrics
+                    .http_responses
+                    .with_label_values(&[route, status])
+                    .get()
+            };
+            assert_eq!(responses("/crate/:name/:version", "200"), 1);
+            assert_eq!(responses("/crate/:name/:version/builds/:id", "404"), 1);
+            assert_eq!(responses("rustdoc page", "200"), 2);
+            assert_eq!(responses("rustdoc page", "404"), 1);
+            assert_eq!(responses("static resource", "200"), 1);
+            assert_eq!(
+                metrics
+                    .http_request_duration
+                    .with_label_values(&["rustdoc page"])
+                    .get_sample_count(),
+                3
+            );
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_metrics_page_success() {
         wrapper(|env| {
@@ -77,23 +77,52 @@ macro_rules! extension {
     }};
 }
 
+mod admin;
+pub(crate) mod admin_api;
+pub(crate) mod admin_login;
 mod build_details;
 mod builds;
+#[cfg(feature = "hyper-server")]
+mod compat;
 mod crate_details;
 mod csp;
+mod dependencies;
+mod diff;
 mod error;
+mod exports;
 mod extensions;
 mod features;
 mod file;
+mod frontend;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod health;
+mod highlight;
 pub(crate) mod metrics;
-mod releases;
+mod owners;
+pub(crate) mod releases;
+mod releases_cache;
+mod request_log;
+mod resolve;
+mod reverse_dependencies;
 mod routes;
 mod rustdoc;
+mod security_headers;
+mod settings;
 mod sitemap;
 mod source;
 mod statics;
-
-use crate::{impl_webpage, Context};
+mod tls;
+mod version_cache;
+
+use crate::{
+    cdn::{CdnKind, SurrogateKeys},
+    db::{
+        crate_redirects,
+        queries::{releases_matching_name, PreparedStatements},
+    },
+    impl_webpage, Context,
+};
 use chrono::{DateTime, Utc};
 use csp::CspMiddleware;
 use error::Nope;
@@ -109,16 +138,18 @@ use iron::{
 };
 use page::TemplateData;
 use postgres::Client;
+pub use releases_cache::ReleasesCache;
+pub use request_log::current_request_id;
 use router::{NoRoute, TrailingSlash};
+use security_headers::SecurityHeadersMiddleware;
 use semver::{Version, VersionReq};
 use serde::Serialize;
 use std::{borrow::Cow, fmt, net::SocketAddr, sync::Arc};
+pub use version_cache::VersionCache;
 
 /// Duration of static files for staticfile and DatabaseFileHandler (in seconds)
 const STATIC_FILE_CACHE_DURATION: u64 = 60 * 60 * 24 * 30 * 12; // 12 months
 
-const DEFAULT_BIND: &str = "0.0.0.0:3000";
-
 struct MainHandler {
     shared_resource_handler: Box<dyn Handler>,
     router_handler: Box<dyn Handler>,
@@ -132,6 +163,7 @@ impl MainHandler {
 
         chain.link_before(CspMiddleware);
         chain.link_after(CspMiddleware);
+        chain.link_after(SecurityHeadersMiddleware);
 
         chain
     }
@@ -243,6 +275,7 @@ impl Handler for MainHandler {
     }
 }
 
+#[derive(Debug, Clone)]
 struct MatchVersion {
     /// Represents the crate name that was found when attempting to load a crate release.
     ///
@@ -250,6 +283,15 @@ struct MatchVersion {
     /// dashes (`-`) replaced with underscores (`_`) and vice versa.
     pub corrected_name: Option<String>,
     pub version: MatchSemver,
+    /// Set when the matched release is yanked, only exact versions can match yanked releases
+    pub yanked: Option<Yanked>,
+}
+
+/// The release matched by `match_version` was yanked
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Yanked {
+    /// The latest release of the crate that isn't yanked, preferring the stable ones
+    pub latest_version: Option<String>,
 }
 
 impl MatchVersion {
@@ -295,7 +337,7 @@ impl MatchSemver {
 /// underscores (`_`) and vice-versa. The return value will indicate whether the crate name has
 /// been matched exactly, or if there has been a "correction" in the name that matched instead.
 fn match_version(
-    conn: &mut Client,
+    conn: &mut impl PreparedStatements,
     name: &str,
     version: Option<&str>,
 ) -> Result<MatchVersion, Nope> {
@@ -313,36 +355,36 @@ fn match_version(
         })
         .unwrap_or_else(|| "*".into());
 
-    let mut corrected_name = None;
-    let versions: Vec<(String, i32, bool)> = {
-        let query = "SELECT name, version, releases.id, releases.yanked
-            FROM releases INNER JOIN crates ON releases.crate_id = crates.id
-            WHERE normalize_crate_name(name) = normalize_crate_name($1)";
-
-        let rows = conn.query(query, &[&name]).unwrap();
-        let mut rows = rows.iter().peekable();
-
-        if let Some(row) = rows.peek() {
-            let db_name = row.get(0);
-
-            if db_name != name {
-                corrected_name = Some(db_name);
-            }
-        };
-
-        rows.map(|row| (row.get(1), row.get(2), row.get(3)))
-            .collect()
-    };
+    let releases = releases_matching_name(conn, name).map_err(|err| {
+        log::error!("failed to look up the releases of {}: {}", name, err);
+        Nope::InternalServerError
+    })?;
+    let corrected_name = releases
+        .first()
+        .filter(|release| release.name != name)
+        .map(|release| release.name.clone());
+    let versions: Vec<(String, i32, bool)> = releases
+        .into_iter()
+        .map(|release| (release.version, release.id, release.yanked))
+        .collect();
 
     if versions.is_empty() {
         return Err(Nope::CrateNotFound);
     }
 
     // first check for exact match, we can't expect users to use semver in query
-    if let Some((version, id, _)) = versions.iter().find(|(vers, _, _)| vers == &req_version) {
+    if let Some((version, id, yanked)) = versions.iter().find(|(vers, _, _)| vers == &req_version) {
+        let yanked = if *yanked {
+            Some(Yanked {
+                latest_version: latest_unyanked_version(&versions),
+            })
+        } else {
+            None
+        };
         return Ok(MatchVersion {
             corrected_name,
             version: MatchSemver::Exact((version.to_owned(), *id)),
+            yanked,
         });
     }
 
@@ -380,6 +422,7 @@ fn match_version(
         return Ok(MatchVersion {
             corrected_name,
             version: MatchSemver::Semver((version.to_string(), *id)),
+            yanked: None,
         });
     }
 
@@ -391,6 +434,7 @@ fn match_version(
             .map(|v| MatchVersion {
                 corrected_name,
                 version: MatchSemver::Semver((v.0.to_string(), v.1)),
+                yanked: None,
             })
             .ok_or(Nope::VersionNotFound);
     }
@@ -400,6 +444,38 @@ fn match_version(
     Err(Nope::VersionNotFound)
 }
 
+/// The version the yanked releases point to, the versions that aren't valid semver are skipped
+fn latest_unyanked_version(versions: &[(String, i32, bool)]) -> Option<String> {
+    versions
+        .iter()
+        .filter(|(_, _, yanked)| !yanked)
+        .filter_map(|(version, _, _)| Version::parse(version).ok())
+        .max_by_key(|version| (!version.is_prerelease(), version.clone()))
+        .map(|version| version.to_string())
+}
+
+/// Returns the names of the crates closest to the name of a crate that doesn't exist, to suggest
+/// them to users who made a typo. The names are compared by their trigrams, using the
+/// `crates_name_trgm_idx` index.
+fn similar_crate_names(
+    conn: &mut Client,
+    name: &str,
+    limit: i64,
+) -> Result<Vec<String>, failure::Error> {
+    Ok(conn
+        .query(
+            "SELECT name
+             FROM crates
+             WHERE name % $1
+             ORDER BY similarity(name, $1) DESC, name
+             LIMIT $2",
+            &[&name, &limit],
+        )?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
 /// Wrapper around the Markdown parser and renderer to render markdown
 fn render_markdown(text: &str) -> String {
     use comrak::{markdown_to_html, ComrakExtensionOptions, ComrakOptions};
@@ -421,7 +497,9 @@ fn render_markdown(text: &str) -> String {
 
 #[must_use = "`Server` blocks indefinitely when dropped"]
 pub struct Server {
+    // dropped first, the frontend can only stop once Iron did
     inner: Listening,
+    frontend: Option<frontend::Frontend>,
 }
 
 impl Server {
@@ -431,13 +509,47 @@ impl Server {
         context: &dyn Context,
     ) -> Result<Self, Error> {
         // Initialize templates
-        let template_data = Arc::new(TemplateData::new(&mut *context.pool()?.get()?)?);
+        let template_data = Arc::new(TemplateData::new(
+            &mut *context.pool()?.get()?,
+            &*context.config()?,
+        )?);
         if reload_templates {
+            #[cfg(feature = "template-reloading")]
             TemplateData::start_template_reloading(template_data.clone(), context.pool()?);
+            #[cfg(not(feature = "template-reloading"))]
+            failure::bail!("reloading the templates needs the `template-reloading` feature");
         }
 
-        let server = Self::start_inner(addr.unwrap_or(DEFAULT_BIND), template_data, context)?;
-        info!("Running docs.rs web server on http://{}", server.addr());
+        let addr = match addr {
+            Some(addr) => addr.to_owned(),
+            None => context.config()?.listen_address.to_string(),
+        };
+        let config = context.config()?;
+        let tls = match (&config.tls_certificate, &config.tls_private_key) {
+            (Some(certificate), Some(private_key)) => {
+                Some(tls::load_config(certificate, private_key)?)
+            }
+            _ => None,
+        };
+        let scheme = if tls.is_some() { "https" } else { "http" };
+
+        let server = if tls.is_some() || cfg!(feature = "hyper-server") {
+            let mut server = Self::start_inner("127.0.0.1:0", template_data, context)?;
+            server.frontend = Some(frontend::Frontend::start(
+                &addr,
+                tls,
+                server.inner.socket,
+                context,
+            )?);
+            server
+        } else {
+            Self::start_inner(&addr, template_data, context)?
+        };
+        info!(
+            "Running docs.rs web server on {}://{}",
+            scheme,
+            server.addr()
+        );
         Ok(server)
     }
 
@@ -446,7 +558,18 @@ impl Server {
         template_data: Arc<TemplateData>,
         context: &dyn Context,
     ) -> Result<Self, Error> {
-        let mut iron = Iron::new(MainHandler::new(template_data, context)?);
+        let recorder = metrics::ResponseRecorder::new(context.metrics()?);
+        let logger = request_log::RequestLogger::new(context.config()?.structured_request_logs);
+        let mut chain = Chain::new(MainHandler::new(template_data, context)?);
+        chain.link_before(logger.clone());
+        chain.link_before(recorder.clone());
+        if context.config()?.cdn_backend == CdnKind::Fastly {
+            chain.link_after(SurrogateKeys);
+        }
+        chain.link_after(recorder);
+        chain.link_after(logger);
+
+        let mut iron = Iron::new(chain);
         if cfg!(test) {
             iron.threads = 1;
         }
@@ -454,11 +577,17 @@ impl Server {
             .http(addr)
             .unwrap_or_else(|_| panic!("Failed to bind to socket on {}", addr));
 
-        Ok(Server { inner })
+        Ok(Server {
+            inner,
+            frontend: None,
+        })
     }
 
     pub(crate) fn addr(&self) -> SocketAddr {
-        self.inner.socket
+        match &self.frontend {
+            Some(frontend) => frontend.addr(),
+            None => self.inner.socket,
+        }
     }
 
     /// Iron is bugged, and it never closes the server even when the listener is dropped. To
@@ -470,7 +599,7 @@ impl Server {
     /// https://docs.rs/iron/0.5/iron/struct.Listening.html#method.close
     #[cfg(test)]
     pub(crate) fn leak(self) {
-        std::mem::forget(self.inner);
+        std::mem::forget(self);
     }
 }
 
@@ -511,6 +640,58 @@ fn redirect(url: Url) -> Response {
     resp
 }
 
+/// Permanently redirects a page of the crate `name` to the same page of the crate `new_name`, for
+/// renamed crates and for the spellings of a name that only differ from the one on crates.io by
+/// dashes and underscores. Serving both spellings would make search engines index the pages twice.
+fn permanent_crate_redirect(req: &Request, name: &str, new_name: &str) -> IronResult<Response> {
+    use iron::url::percent_encoding::percent_decode;
+
+    let mut path = req.url.path();
+    if let Some(segment) = path.iter_mut().find(|segment| {
+        percent_decode(segment.as_bytes())
+            .decode_utf8()
+            .ok()
+            .as_deref()
+            == Some(name)
+    }) {
+        *segment = new_name;
+    }
+    let mut url_str = format!("{}/{}", redirect_base(req), path.join("/"));
+    if let Some(query) = req.url.query() {
+        url_str.push('?');
+        url_str.push_str(query);
+    }
+    let url = ctry!(req, Url::parse(&url_str));
+
+    Ok(Response::with((status::MovedPermanently, Redirect(url))))
+}
+
+/// Permanently redirects the pages of a crate renamed by the admins (see `db::crate_redirects`),
+/// `None` if the crate wasn't renamed. The versions and the items of the new crate don't match the
+/// ones of the old crate, so the `/crate/` pages go to the page of the new crate and the
+/// documentation goes to the latest documentation of the new crate.
+fn renamed_crate_redirect(
+    req: &Request,
+    conn: &mut Client,
+    name: &str,
+) -> Result<Option<Response>, Error> {
+    let new_name = match crate_redirects::get(conn, name)? {
+        Some(new_name) => new_name,
+        None => return Ok(None),
+    };
+
+    let url = if req.url.path().first() == Some(&"crate") {
+        format!("{}/crate/{}", redirect_base(req), new_name)
+    } else {
+        format!("{}/{}", redirect_base(req), new_name)
+    };
+    let url = Url::parse(&url).map_err(failure::err_msg)?;
+    Ok(Some(Response::with((
+        status::MovedPermanently,
+        Redirect(url),
+    ))))
+}
+
 fn redirect_base(req: &Request) -> String {
     // Try to get the scheme from CloudFront first, and then from iron
     let scheme = req
@@ -881,6 +1062,48 @@ mod test {
         });
     }
 
+    #[test]
+    fn yanked_releases_point_to_the_latest_unyanked() {
+        wrapper(|env| {
+            let yanked = |v| {
+                match_version(&mut env.db().conn(), "foo", Some(v))
+                    .unwrap()
+                    .yanked
+            };
+
+            for v in &["0.1.0", "0.2.0", "0.3.0-beta.1", "0.3.0"] {
+                release(v, env);
+            }
+            env.db().conn().execute(
+                "UPDATE releases SET yanked = true WHERE version IN ('0.1.0', '0.3.0')",
+                &[],
+            )?;
+
+            assert_eq!(yanked("0.2.0"), None);
+            assert_eq!(
+                yanked("0.3.0"),
+                Some(Yanked {
+                    latest_version: Some("0.2.0".into())
+                })
+            );
+            // only the exact versions can match yanked releases
+            let matched = match_version(&mut env.db().conn(), "foo", Some("0.3.0-beta.1"))?;
+            assert_eq!(matched.yanked, None);
+
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET yanked = true", &[])?;
+            assert_eq!(
+                yanked("0.1.0"),
+                Some(Yanked {
+                    latest_version: None
+                })
+            );
+
+            Ok(())
+        });
+    }
+
     #[test]
     // vaguely related to https://github.com/rust-lang/docs.rs/issues/395
     fn metadata_has_no_effect() {
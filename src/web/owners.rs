@@ -0,0 +1,348 @@
+//! Pages listing the crates of a crates.io owner, either a user or a team
+
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{error::Nope, page::WebPage, redirect_base},
+};
+use chrono::{DateTime, Utc};
+use iron::{IronResult, Request, Response, Url};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+
+/// Number of crates shown per page
+const CRATES_PER_PAGE: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Owner {
+    id: i32,
+    login: String,
+    /// The name shown on the page, the login if the owner didn't set a name
+    name: String,
+    avatar: String,
+    /// Teams are named `github:org:team` by crates.io
+    is_team: bool,
+}
+
+impl Owner {
+    fn load(conn: &mut Client, login: &str) -> Result<Option<Self>, failure::Error> {
+        let row = match conn.query_opt(
+            "SELECT id, login, name, avatar FROM owners WHERE login = $1",
+            &[&login],
+        )? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let login: String = row.get("login");
+        let name: String = row.get("name");
+        Ok(Some(Owner {
+            id: row.get("id"),
+            name: if name.is_empty() { login.clone() } else { name },
+            avatar: row.get("avatar"),
+            is_team: login.contains(':'),
+            login,
+        }))
+    }
+}
+
+/// The latest release of a crate of the owner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct OwnedCrate {
+    name: String,
+    version: String,
+    description: Option<String>,
+    target_name: Option<String>,
+    release_time: DateTime<Utc>,
+    build_status: bool,
+    rustdoc_status: bool,
+    is_library: bool,
+    yanked: bool,
+}
+
+/// Build status of the latest releases of all the crates of an owner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+struct BuildSummary {
+    total: i64,
+    successful: i64,
+    failed: i64,
+    /// Binary crates have no documentation to build
+    not_library: i64,
+}
+
+fn get_build_summary(conn: &mut Client, owner_id: i32) -> Result<BuildSummary, failure::Error> {
+    let row = conn.query_one(
+        "SELECT COUNT(*) AS total,
+                COUNT(*) FILTER (
+                    WHERE releases.build_status AND releases.is_library
+                ) AS successful,
+                COUNT(*) FILTER (
+                    WHERE NOT releases.build_status AND releases.is_library
+                ) AS failed,
+                COUNT(*) FILTER (WHERE NOT releases.is_library) AS not_library
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         INNER JOIN owner_rels ON owner_rels.cid = crates.id
+         WHERE owner_rels.oid = $1",
+        &[&owner_id],
+    )?;
+
+    Ok(BuildSummary {
+        total: row.get("total"),
+        successful: row.get("successful"),
+        failed: row.get("failed"),
+        not_library: row.get("not_library"),
+    })
+}
+
+fn get_owned_crates(
+    conn: &mut Client,
+    owner_id: i32,
+    page: i64,
+    limit: i64,
+) -> Result<Vec<OwnedCrate>, failure::Error> {
+    let offset = (page - 1) * limit;
+
+    Ok(conn
+        .query(
+            "SELECT crates.name,
+                    releases.version,
+                    releases.description,
+                    releases.target_name,
+                    releases.release_time,
+                    releases.build_status,
+                    releases.rustdoc_status,
+                    releases.is_library,
+                    releases.yanked
+             FROM crates
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             INNER JOIN owner_rels ON owner_rels.cid = crates.id
+             WHERE owner_rels.oid = $1
+             ORDER BY crates.name
+             LIMIT $2 OFFSET $3",
+            &[&owner_id, &limit, &offset],
+        )?
+        .into_iter()
+        .map(|row| OwnedCrate {
+            name: row.get("name"),
+            version: row.get("version"),
+            description: row.get("description"),
+            target_name: row.get("target_name"),
+            release_time: row.get("release_time"),
+            build_status: row.get("build_status"),
+            rustdoc_status: row.get("rustdoc_status"),
+            is_library: row.get("is_library"),
+            yanked: row.get("yanked"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct OwnerPage {
+    owner: Owner,
+    crates: Vec<OwnedCrate>,
+    summary: BuildSummary,
+    page_number: i64,
+    show_next_page: bool,
+    show_previous_page: bool,
+}
+
+impl_webpage! {
+    OwnerPage = "releases/owner.html",
+}
+
+pub fn owner_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let login = cexpect!(req, router.find("login"));
+    let page_number: i64 = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "page")
+        .and_then(|(_, page)| page.parse().ok())
+        .filter(|&page| page >= 1)
+        .unwrap_or(1);
+
+    let mut conn = extension!(req, Pool).get()?;
+    let owner = match ctry!(req, Owner::load(&mut conn, login)) {
+        Some(owner) => owner,
+        None => return Err(Nope::OwnerNotFound.into()),
+    };
+    // owners are only recorded while their crates are added, so the ones without crates are
+    // the same as the unknown ones
+    let summary = ctry!(req, get_build_summary(&mut conn, owner.id));
+    if summary.total == 0 {
+        return Err(Nope::OwnerNotFound.into());
+    }
+    let crates = ctry!(
+        req,
+        get_owned_crates(&mut conn, owner.id, page_number, CRATES_PER_PAGE)
+    );
+    if crates.is_empty() {
+        return Err(Nope::ResourceNotFound.into());
+    }
+
+    OwnerPage {
+        owner,
+        crates,
+        summary,
+        page_number,
+        show_next_page: page_number * CRATES_PER_PAGE < summary.total,
+        show_previous_page: page_number != 1,
+    }
+    .into_response(req)
+}
+
+/// Redirects the `/releases/:owner` and `/releases/:owner/:page` pages, which used to list the
+/// crates of an owner, to the owner page
+pub fn releases_owner_redirect_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let owner = cexpect!(req, router.find("owner"));
+    let login = owner.strip_prefix('@').unwrap_or(owner);
+
+    let mut url = ctry!(
+        req,
+        Url::parse(&format!("{}/owners/{}", redirect_base(req), login)),
+    );
+    if let Some(page) = router.find("page").filter(|&page| page != "1") {
+        url.as_mut().set_query(Some(&format!("page={}", page)));
+    }
+
+    Ok(super::redirect(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CRATES_PER_PAGE;
+    use crate::index::api::CrateOwner;
+    use crate::test::*;
+    use kuchiki::traits::TendrilSink;
+
+    fn owner(login: &str, name: &str) -> CrateOwner {
+        CrateOwner {
+            login: login.into(),
+            avatar: format!("https://example.org/{}", login),
+            name: name.into(),
+            email: format!("{}@example.org", login),
+        }
+    }
+
+    #[test]
+    fn owner_page() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("built")
+                .add_owner(owner("foobar", "Foo Bar"))
+                .create()?;
+            env.fake_release()
+                .name("failed")
+                .build_result_failed()
+                .add_owner(owner("foobar", "Foo Bar"))
+                .create()?;
+            env.fake_release()
+                .name("binary")
+                .binary(true)
+                .add_owner(owner("foobar", "Foo Bar"))
+                .create()?;
+            env.fake_release()
+                .name("other")
+                .add_owner(owner("someone", ""))
+                .create()?;
+
+            let page =
+                kuchiki::parse_html().one(env.frontend().get("/owners/foobar").send()?.text()?);
+            assert_eq!(
+                page.select_first("#crate-title").unwrap().text_contents(),
+                "Foo Bar"
+            );
+            let crates: Vec<_> = page
+                .select(".recent-releases-container li .name")
+                .unwrap()
+                .map(|name| name.text_contents().trim().to_owned())
+                .collect();
+            assert_eq!(crates, vec!["binary-1.0.0", "built-1.0.0", "failed-1.0.0"]);
+            let summary = page.select_first(".owner-summary").unwrap().text_contents();
+            let summary: Vec<_> = summary.split_whitespace().collect();
+            assert_eq!(
+                summary.join(" "),
+                "3 crates: 1 built successfully, 1 failed to build, 1 not a library"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn teams_and_unknown_owners() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("some_random_crate")
+                .add_owner(owner("github:rust-lang:libs", "libs"))
+                .create()?;
+            let web = env.frontend();
+
+            let page =
+                kuchiki::parse_html().one(web.get("/owners/github:rust-lang:libs").send()?.text()?);
+            assert!(page
+                .select_first(".description")
+                .unwrap()
+                .text_contents()
+                .contains("team"));
+
+            let response = web.get("/owners/random-author").send()?;
+            assert_eq!(response.status(), 404);
+            let page = kuchiki::parse_html().one(response.text()?);
+            assert_eq!(
+                page.select_first("#crate-title").unwrap().text_contents(),
+                "The requested owner does not exist",
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn pagination() {
+        wrapper(|env| {
+            for i in 0..=CRATES_PER_PAGE {
+                env.fake_release()
+                    .name(&format!("some_random_crate_{:03}", i))
+                    .add_owner(owner("foobar", "Foo Bar"))
+                    .create()?;
+            }
+            let web = env.frontend();
+
+            let page = kuchiki::parse_html().one(web.get("/owners/foobar").send()?.text()?);
+            assert!(page.select_first("a[href='/owners/foobar?page=2']").is_ok());
+            let page = kuchiki::parse_html().one(web.get("/owners/foobar?page=2").send()?.text()?);
+            assert_eq!(
+                page.select(".recent-releases-container li")
+                    .unwrap()
+                    .count(),
+                1
+            );
+            assert_eq!(web.get("/owners/foobar?page=3").send()?.status(), 404);
+            assert_redirect("/releases/@foobar/2", "/owners/foobar?page=2", web)?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn old_pages_redirect() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("some_random_crate")
+                .add_owner(owner("foobar", "Foo Bar"))
+                .create()?;
+            let web = env.frontend();
+
+            assert_redirect("/releases/foobar", "/owners/foobar", web)?;
+            assert_redirect("/releases/@foobar", "/owners/foobar", web)?;
+            assert_redirect("/releases/@foobar/1", "/owners/foobar", web)?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,202 @@
+//! Links shown in the navigation bar and in the footer
+//!
+//! The default links point to the rust-lang websites, self-hosted instances can replace them with
+//! a TOML file (see `links.toml` for the format) set in `DOCSRS_SITE_LINKS`. The links are exposed
+//! to the templates by the `site_links()` function.
+
+use crate::error::Result;
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_LINKS: &str = include_str!("links.toml");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SiteLinks {
+    /// The dropdown menu at the right of the navigation bar, hidden when missing
+    #[serde(default)]
+    pub(crate) navbar: Option<Menu>,
+    #[serde(default)]
+    pub(crate) footer: Vec<Link>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Menu {
+    pub(crate) title: String,
+    pub(crate) href: String,
+    #[serde(default)]
+    pub(crate) new_tab: bool,
+    #[serde(default)]
+    pub(crate) links: Vec<Link>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Link {
+    pub(crate) text: String,
+    pub(crate) href: String,
+    #[serde(default)]
+    pub(crate) new_tab: bool,
+    #[serde(default)]
+    pub(crate) divided: bool,
+}
+
+impl SiteLinks {
+    /// Loads the links from `path`, or the default ones if no path is provided
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|_| format!("failed to read {}", path.display()))?;
+                Ok(Self::parse(&content)
+                    .with_context(|_| format!("invalid site links in {}", path.display()))?)
+            }
+            None => Self::parse(DEFAULT_LINKS),
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let links: SiteLinks = toml::from_str(content)?;
+
+        if let Some(navbar) = &links.navbar {
+            check_link(&navbar.title, &navbar.href)?;
+        }
+        for link in links.navbar.iter().flat_map(|navbar| &navbar.links) {
+            check_link(&link.text, &link.href)?;
+        }
+        for link in &links.footer {
+            check_link(&link.text, &link.href)?;
+        }
+
+        Ok(links)
+    }
+}
+
+/// Links must have a text and point either to a page of this instance or to a web page
+fn check_link(text: &str, href: &str) -> Result<()> {
+    if text.trim().is_empty() {
+        failure::bail!("the link to {:?} has no text", href);
+    }
+
+    let local = href.starts_with('/') && !href.starts_with("//");
+    let remote = url::Url::parse(href)
+        .map(|url| ["http", "https", "mailto"].contains(&url.scheme()))
+        .unwrap_or(false);
+    if !local && !remote {
+        failure::bail!(
+            "the link {:?} points to {:?}, which is neither a path nor a web url",
+            text,
+            href
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn default_links() {
+        let links = SiteLinks::load(None).unwrap();
+        assert_eq!(links.navbar.unwrap().links.len(), 6);
+        assert_eq!(links.footer[0].href, "/about");
+    }
+
+    #[test]
+    fn custom_links() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("links.toml");
+        fs::write(
+            &path,
+            r#"
+            [[footer]]
+            text = "Intranet"
+            href = "https://intranet.example.com"
+            new_tab = true
+            "#,
+        )?;
+
+        assert_eq!(
+            SiteLinks::load(Some(&path))?,
+            SiteLinks {
+                navbar: None,
+                footer: vec![Link {
+                    text: "Intranet".into(),
+                    href: "https://intranet.example.com".into(),
+                    new_tab: true,
+                    divided: false,
+                }],
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_links() {
+        for invalid in &[
+            "[[footer]]\ntext = ''\nhref = '/about'",
+            "[[footer]]\ntext = 'About'\nhref = 'javascript:alert(1)'",
+            "[[footer]]\ntext = 'About'\nhref = '//example.com'",
+            "[[footer]]\ntext = 'About'\nhref = 'about'",
+            "[[footer]]\ntext = 'About'\nhref = '/about'\ncolor = 'red'",
+            "[navbar]\ntitle = 'Company'\nhref = 'ftp://example.com'",
+        ] {
+            assert!(SiteLinks::parse(invalid).is_err(), "{:?}", invalid);
+        }
+        assert!(SiteLinks::load(Some(Path::new("/missing/links.toml"))).is_err());
+    }
+
+    #[test]
+    fn rendered_links() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let path = dir.path().join("links.toml");
+            fs::write(
+                &path,
+                r#"
+                [navbar]
+                title = "Company"
+                href = "https://example.com"
+
+                [[navbar.links]]
+                text = "Wiki"
+                href = "https://wiki.example.com"
+
+                [[footer]]
+                text = "Support"
+                href = "/support"
+                "#,
+            )?;
+            env.override_config(|config| config.site_links = Some(path));
+
+            let page = kuchiki::parse_html().one(env.frontend().get("/").send()?.text()?);
+            let footer: Vec<_> = page
+                .select(".docs-rs-footer a")
+                .expect("invalid selector")
+                .map(|link| link.text_contents())
+                .collect();
+            assert_eq!(footer, vec!["Support"]);
+
+            let navbar = page
+                .select_first(r#"a[href="https://example.com"]"#)
+                .expect("missing navbar menu");
+            assert_eq!(navbar.text_contents().trim(), "Company");
+            assert!(page
+                .select_first(r#"a[href="https://wiki.example.com"]"#)
+                .is_ok());
+            assert!(page
+                .select_first(r#"a[href="https://doc.rust-lang.org/book/"]"#)
+                .is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -1,3 +1,5 @@
+mod links;
+mod render_cache;
 mod templates;
 mod web_page;
 
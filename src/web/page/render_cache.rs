@@ -0,0 +1,178 @@
+//! In-process cache of the rendered pages that are expensive to render
+//!
+//! The pages opting in with `cached = true` in `impl_webpage!`, like the home page and the
+//! releases lists, are rendered once per template and data, and served from the cache for a
+//! minute (`DOCSRS_RENDER_CACHE_TTL`). The values that change on every request, the CSP nonce and
+//! the request ID, are rendered as random placeholders and filled in on every response.
+
+use crate::{Config, Metrics};
+use dashmap::DashMap;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Once there are this many pages, the expired ones are dropped, and the new ones aren't cached
+/// until there's room again. The search results of the releases lists have a page per query.
+const MAX_ENTRIES: usize = 1000;
+
+struct Entry {
+    inserted: Instant,
+    rendered: Arc<str>,
+}
+
+pub(crate) struct RenderCache {
+    /// Keyed on the name of the template and the hash of the data it's rendered with
+    entries: DashMap<(String, [u8; 32]), Entry>,
+    ttl: Duration,
+    /// Incremented by every invalidation, so that a page rendered with the previous templates
+    /// isn't stored
+    generation: AtomicU64,
+    nonce_placeholder: String,
+    request_id_placeholder: String,
+}
+
+impl RenderCache {
+    pub(super) fn new(config: &Config) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl: Duration::from_secs(config.render_cache_ttl),
+            generation: AtomicU64::new(0),
+            nonce_placeholder: placeholder(),
+            request_id_placeholder: placeholder(),
+        }
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.ttl != Duration::from_secs(0)
+    }
+
+    /// Rendered instead of the CSP nonce, replaced by `fill`
+    pub(super) fn nonce_placeholder(&self) -> &str {
+        &self.nonce_placeholder
+    }
+
+    /// Rendered instead of the request ID, replaced by `fill`
+    pub(super) fn request_id_placeholder(&self) -> &str {
+        &self.request_id_placeholder
+    }
+
+    /// The page rendered from `context` with `template`, calling `render` if it isn't cached
+    pub(super) fn render<T: Serialize>(
+        &self,
+        template: &str,
+        context: &T,
+        metrics: &Metrics,
+        render: impl FnOnce() -> tera::Result<String>,
+    ) -> tera::Result<Arc<str>> {
+        let data = serde_json::to_vec(context).expect("failed to serialize the page");
+        let key = (template.to_string(), Sha256::digest(&data).into());
+
+        if let Some(entry) = self.entries.get(&key) {
+            if entry.inserted.elapsed() < self.ttl {
+                metrics
+                    .render_cache_lookups
+                    .with_label_values(&[template, "hit"])
+                    .inc();
+                return Ok(entry.rendered.clone());
+            }
+        }
+        metrics
+            .render_cache_lookups
+            .with_label_values(&[template, "miss"])
+            .inc();
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let rendered: Arc<str> = render()?.into();
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries
+                .retain(|_, entry| entry.inserted.elapsed() < self.ttl);
+        }
+        if generation == self.generation.load(Ordering::SeqCst) && self.entries.len() < MAX_ENTRIES
+        {
+            self.entries.insert(
+                key,
+                Entry {
+                    inserted: Instant::now(),
+                    rendered: rendered.clone(),
+                },
+            );
+        }
+
+        Ok(rendered)
+    }
+
+    /// Replaces the placeholders of a cached page with the values of the request
+    pub(super) fn fill(&self, rendered: &str, nonce: &str, request_id: Option<&str>) -> String {
+        let filled = rendered.replace(&self.nonce_placeholder, nonce);
+        match request_id {
+            Some(request_id) => filled.replace(&self.request_id_placeholder, request_id),
+            None => filled,
+        }
+    }
+
+    /// Drops all the cached pages, called when the templates are reloaded
+    pub(super) fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.clear();
+    }
+}
+
+/// Random, so that it can't be part of the data of a page
+fn placeholder() -> String {
+    let mut random = [0u8; 16];
+    getrandom::getrandom(&mut random).expect("failed to generate a placeholder");
+    let random: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("docsrs-placeholder-{}", random)
+}
+
+impl std::fmt::Debug for RenderCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderCache")
+            .field("entries", &self.entries.len())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn render_once() {
+        wrapper(|env| {
+            let cache = RenderCache::new(&env.config());
+            let metrics = env.metrics();
+            let lookups = |result| {
+                metrics
+                    .render_cache_lookups
+                    .with_label_values(&["page.html", result])
+                    .get()
+            };
+
+            let mut renders = 0;
+            for data in &["foo", "foo", "bar"] {
+                let rendered = cache.render("page.html", data, &metrics, || {
+                    renders += 1;
+                    Ok(format!("{} {}", data, cache.nonce_placeholder()))
+                })?;
+                assert_eq!(
+                    cache.fill(&rendered, "nonce", None),
+                    format!("{} nonce", data)
+                );
+            }
+            assert_eq!(renders, 2);
+            assert_eq!(lookups("hit"), 1);
+            assert_eq!(lookups("miss"), 2);
+
+            cache.invalidate();
+            cache.render("page.html", &"foo", &metrics, || Ok(String::new()))?;
+            assert_eq!(lookups("miss"), 3);
+
+            Ok(())
+        });
+    }
+}
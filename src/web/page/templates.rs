@@ -1,18 +1,19 @@
-use crate::{db::Pool, error::Result};
+use super::links::SiteLinks;
+use super::render_cache::RenderCache;
+#[cfg(feature = "template-reloading")]
+use crate::db::Pool;
+use crate::{error::Result, Config};
 use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
 use failure::ResultExt;
-use notify::{watcher, RecursiveMode, Watcher};
 use path_slash::PathExt;
 use postgres::Client;
 use serde_json::Value;
 use std::{
     collections::HashMap,
     fmt,
-    path::PathBuf,
-    sync::{mpsc::channel, Arc},
-    thread,
-    time::Duration,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 use tera::{Result as TeraResult, Tera};
 use walkdir::WalkDir;
@@ -22,17 +23,31 @@ const TEMPLATES_DIRECTORY: &str = "templates";
 /// Holds all data relevant to templating
 #[derive(Debug)]
 pub(crate) struct TemplateData {
-    /// The actual templates, stored in an `ArcSwap` so that they're hot-swappable
-    // TODO: Conditional compilation so it's not always wrapped, the `ArcSwap` is unneeded overhead for prod
+    /// The actual templates, stored in an `ArcSwap` so that they can be reloaded without a lock
+    /// while the server is running, by the watcher in development or through the admin API
     pub templates: ArcSwap<Tera>,
+    /// The pages rendered recently, dropped when the templates are reloaded
+    pub(super) render_cache: RenderCache,
+    /// The file containing the navbar and footer links, if the default ones are not used
+    links_file: Option<PathBuf>,
+    /// Whether the views of the documentation are counted, to link to the rankings by views
+    page_view_stats: bool,
 }
 
 impl TemplateData {
-    pub(crate) fn new(conn: &mut Client) -> Result<Self> {
+    pub(crate) fn new(conn: &mut Client, config: &Config) -> Result<Self> {
         log::trace!("Loading templates");
 
+        let links_file = config.site_links.clone();
         let data = Self {
-            templates: ArcSwap::from_pointee(load_templates(conn)?),
+            templates: ArcSwap::from_pointee(load_templates(
+                conn,
+                links_file.as_deref(),
+                config.page_view_stats,
+            )?),
+            render_cache: RenderCache::new(config),
+            links_file,
+            page_view_stats: config.page_view_stats,
         };
 
         log::trace!("Finished loading templates");
@@ -40,7 +55,23 @@ impl TemplateData {
         Ok(data)
     }
 
+    /// Replaces the templates with the ones currently on disk
+    pub(crate) fn reload(&self, conn: &mut Client) -> Result<()> {
+        self.templates.store(Arc::new(load_templates(
+            conn,
+            self.links_file.as_deref(),
+            self.page_view_stats,
+        )?));
+        self.render_cache.invalidate();
+        Ok(())
+    }
+
+    /// Reloads the templates when they change, only used in development
+    #[cfg(feature = "template-reloading")]
     pub(crate) fn start_template_reloading(template_data: Arc<TemplateData>, pool: Pool) {
+        use notify::{watcher, RecursiveMode, Watcher};
+        use std::{sync::mpsc::channel, thread, time::Duration};
+
         let (tx, rx) = channel();
         // Set a 2 second event debounce for the watcher
         let mut watcher = watcher(tx, Duration::from_secs(2)).unwrap();
@@ -48,15 +79,15 @@ impl TemplateData {
         watcher
             .watch(TEMPLATES_DIRECTORY, RecursiveMode::Recursive)
             .unwrap();
+        if let Some(links_file) = &template_data.links_file {
+            watcher
+                .watch(links_file, RecursiveMode::NonRecursive)
+                .unwrap();
+        }
 
         thread::spawn(move || {
             fn reload(template_data: &TemplateData, pool: &Pool) -> Result<()> {
-                let mut conn = pool.get()?;
-                template_data
-                    .templates
-                    .swap(Arc::new(load_templates(&mut conn)?));
-
-                Ok(())
+                template_data.reload(&mut *pool.get()?)
             }
 
             // The watcher needs to be moved into the thread so that it's not dropped (when dropped,
@@ -93,7 +124,11 @@ fn load_rustc_resource_suffix(conn: &mut Client) -> Result<String> {
     failure::bail!("failed to parse the rustc version");
 }
 
-pub(super) fn load_templates(conn: &mut Client) -> Result<Tera> {
+pub(super) fn load_templates(
+    conn: &mut Client,
+    links_file: Option<&Path>,
+    page_view_stats: bool,
+) -> Result<Tera> {
     // This uses a custom function to find the templates in the filesystem instead of Tera's
     // builtin way (passing a glob expression to Tera::new), speeding up the startup of the
     // application and running the tests.
@@ -130,6 +165,14 @@ pub(super) fn load_templates(conn: &mut Client) -> Result<Tera> {
         "docsrs_version",
         Value::String(crate::BUILD_VERSION.into()),
     );
+    // This function will return the links shown in the navbar and in the footer.
+    ReturnValue::add_function_to(
+        &mut tera,
+        "site_links",
+        serde_json::to_value(SiteLinks::load(links_file)?)?,
+    );
+    // This function will return whether the views of the documentation are counted.
+    ReturnValue::add_function_to(&mut tera, "page_view_stats", Value::Bool(page_view_stats));
     // This function will return the resource suffix of the latest nightly used to build
     // documentation on docs.rs, or ??? if no resource suffix was found.
     ReturnValue::add_function_to(
@@ -363,7 +406,7 @@ mod tests {
         crate::test::wrapper(|env| {
             let db = env.db();
 
-            let tera = load_templates(&mut db.conn()).unwrap();
+            let tera = load_templates(&mut db.conn(), None, false).unwrap();
             tera.check_macro_files().unwrap();
 
             Ok(())
@@ -1,19 +1,25 @@
 use super::TemplateData;
 use crate::ctry;
 use crate::web::csp::Csp;
+use crate::web::request_log::RequestId;
+use crate::web::settings::Settings;
+use crate::Metrics;
 use iron::{headers::ContentType, response::Response, status::Status, IronResult, Request};
 use serde::Serialize;
 use std::borrow::Cow;
 use tera::Context;
 
 /// When making using a custom status, use a closure that coerces to a `fn(&Self) -> Status`
+///
+/// Pages that are expensive to render and the same for many requests can be cached with
+/// `cached = true`, see `render_cache`
 #[macro_export]
 macro_rules! impl_webpage {
-    ($page:ty = $template:literal $(, status = $status:expr)? $(, content_type = $content_type:expr)? $(,)?) => {
-        $crate::impl_webpage!($page = |_| ::std::borrow::Cow::Borrowed($template) $(, status = $status)? $(, content_type = $content_type)?);
+    ($page:ty = $template:literal $(, status = $status:expr)? $(, content_type = $content_type:expr)? $(, cached = $cached:expr)? $(,)?) => {
+        $crate::impl_webpage!($page = |_| ::std::borrow::Cow::Borrowed($template) $(, status = $status)? $(, content_type = $content_type)? $(, cached = $cached)?);
     };
 
-    ($page:ty = $template:expr $(, status = $status:expr)? $(, content_type = $content_type:expr)? $(,)?) => {
+    ($page:ty = $template:expr $(, status = $status:expr)? $(, content_type = $content_type:expr)? $(, cached = $cached:expr)? $(,)?) => {
         impl $crate::web::page::WebPage for $page {
             fn template(&self) -> ::std::borrow::Cow<'static, str> {
                 let template: fn(&Self) -> ::std::borrow::Cow<'static, str> = $template;
@@ -32,6 +38,12 @@ macro_rules! impl_webpage {
                     $content_type
                 }
             )?
+
+            $(
+                fn is_cached() -> bool {
+                    $cached
+                }
+            )?
         }
     };
 }
@@ -39,6 +51,8 @@ macro_rules! impl_webpage {
 #[derive(Serialize)]
 struct TemplateContext<'a, T> {
     csp_nonce: &'a str,
+    request_id: Option<&'a str>,
+    preferred_theme: Option<String>,
     #[serde(flatten)]
     page: &'a T,
 }
@@ -46,27 +60,56 @@ struct TemplateContext<'a, T> {
 /// The central trait that rendering pages revolves around, it handles selecting and rendering the template
 pub trait WebPage: Serialize + Sized {
     /// Turn the current instance into a `Response`, ready to be served
-    // TODO: We could cache similar pages using the `&Context`
     fn into_response(self, req: &Request) -> IronResult<Response> {
         let csp_nonce = req
             .extensions
             .get::<Csp>()
             .expect("missing CSP from the request extensions")
             .nonce();
-
-        let ctx = Context::from_serialize(&TemplateContext {
-            csp_nonce,
-            page: &self,
-        })
-        .unwrap();
-        let status = self.get_status();
-        let result = req
+        let request_id = req.extensions.get::<RequestId>().map(String::as_str);
+        let template_data = req
             .extensions
             .get::<TemplateData>()
-            .expect("missing TemplateData from the request extensions")
-            .templates
-            .load()
-            .render(&self.template(), &ctx);
+            .expect("missing TemplateData from the request extensions");
+        let cache = &template_data.render_cache;
+
+        let status = self.get_status();
+        let template = self.template();
+        let cached = Self::is_cached() && cache.is_enabled() && !status.is_server_error();
+        // the cached pages are rendered with placeholders instead of the values of the request
+        let context = if cached {
+            TemplateContext {
+                csp_nonce: cache.nonce_placeholder(),
+                request_id: request_id.map(|_| cache.request_id_placeholder()),
+                preferred_theme: Settings::from_request(req).theme,
+                page: &self,
+            }
+        } else {
+            TemplateContext {
+                csp_nonce,
+                request_id,
+                preferred_theme: Settings::from_request(req).theme,
+                page: &self,
+            }
+        };
+        let render = || {
+            template_data
+                .templates
+                .load()
+                .render(&template, &Context::from_serialize(&context).unwrap())
+        };
+
+        let result = if cached {
+            let metrics = req
+                .extensions
+                .get::<Metrics>()
+                .expect("missing Metrics from the request extensions");
+            cache
+                .render(&template, &context, metrics, render)
+                .map(|rendered| cache.fill(&rendered, csp_nonce, request_id))
+        } else {
+            render()
+        };
 
         let rendered = if status.is_server_error() {
             // avoid infinite loop if error.html somehow fails to load
@@ -93,4 +136,9 @@ pub trait WebPage: Serialize + Sized {
     fn content_type() -> ContentType {
         ContentType::html()
     }
+
+    /// Whether the rendered page is kept in the render cache, defaults to `false`
+    fn is_cached() -> bool {
+        false
+    }
 }
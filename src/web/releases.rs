@@ -2,14 +2,34 @@
 
 use crate::{
     build_queue::QueuedCrate,
-    db::{Pool, PoolClient},
+    db::{
+        page_views::ViewsRanking,
+        queries::{
+            latest_releases, LatestRelease, LatestReleasesCursor, LatestReleasesOrder,
+            LatestReleasesPage, PreparedStatements,
+        },
+        Pool, PoolClient,
+    },
     impl_webpage,
-    web::{error::Nope, match_version, page::WebPage, redirect_base},
-    BuildQueue, Config,
+    utils::{
+        consistency::{self, ConsistencyIssue},
+        pubsubhubbub::{Hub, SubscriptionError, SubscriptionRequest},
+        queue_events,
+        rebuild::{self, RebuildRun},
+        storage_stats::{self, ReleaseStorageStats, StorageTotals},
+    },
+    web::{
+        error::Nope,
+        page::{TemplateData, WebPage},
+        redirect_base,
+    },
+    BuildQueue, Config, ReleasesCache, VersionCache,
 };
 use chrono::{DateTime, NaiveDate, Utc};
 use iron::{
-    headers::{ContentType, Expires, HttpDate},
+    headers::{
+        AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType, Expires, HttpDate,
+    },
     mime::{Mime, SubLevel, TopLevel},
     modifiers::Redirect,
     status, IronResult, Request, Response, Url,
@@ -17,6 +37,7 @@ use iron::{
 use postgres::Client;
 use router::Router;
 use serde::Serialize;
+use std::io::Read;
 
 /// Number of release in home page
 const RELEASES_IN_HOME: i64 = 15;
@@ -24,6 +45,8 @@ const RELEASES_IN_HOME: i64 = 15;
 const RELEASES_IN_RELEASES: i64 = 30;
 /// Releases in recent releases feed
 const RELEASES_IN_FEED: i64 = 150;
+/// Subscription requests to the hub of the feed are small forms
+const MAX_HUB_REQUEST_SIZE: u64 = 8 * 1024;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Release {
@@ -50,7 +73,7 @@ impl Default for Release {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Order {
     ReleaseTime, // this is default order
     GithubStars,
@@ -64,112 +87,136 @@ impl Default for Order {
     }
 }
 
-pub(crate) fn get_releases(conn: &mut Client, page: i64, limit: i64, order: Order) -> Vec<Release> {
-    let offset = (page - 1) * limit;
+impl Order {
+    /// How the releases are sorted, and whether only the failed ones are kept
+    pub(super) fn sorting(self) -> (LatestReleasesOrder, bool) {
+        match self {
+            Order::ReleaseTime => (LatestReleasesOrder::ReleaseTime, false),
+            Order::GithubStars => (LatestReleasesOrder::Stars, false),
+            Order::RecentFailures => (LatestReleasesOrder::ReleaseTime, true),
+            Order::FailuresByGithubStars => (LatestReleasesOrder::Stars, true),
+        }
+    }
+}
 
-    // WARNING: it is _crucial_ that this always be hard-coded and NEVER be user input
-    let (ordering, filter_failed): (&'static str, _) = match order {
-        Order::ReleaseTime => ("releases.release_time", false),
-        Order::GithubStars => ("repositories.stars", false),
-        Order::RecentFailures => ("releases.release_time", true),
-        Order::FailuresByGithubStars => ("repositories.stars", true),
-    };
-    let query = format!(
-        "SELECT crates.name,
-            releases.version,
-            releases.description,
-            releases.target_name,
-            releases.release_time,
-            releases.rustdoc_status,
-            repositories.stars
-        FROM crates
-        INNER JOIN releases ON crates.latest_version_id = releases.id
-        LEFT JOIN repositories ON releases.repository_id = repositories.id
-        WHERE
-            ((NOT $3) OR (releases.build_status = FALSE AND releases.is_library = TRUE)) 
-            AND {0} IS NOT NULL
+impl From<LatestRelease> for Release {
+    fn from(release: LatestRelease) -> Self {
+        Release {
+            name: release.name,
+            version: release.version,
+            description: release.description,
+            target_name: release.target_name,
+            release_time: release.release_time,
+            rustdoc_status: release.rustdoc_status,
+            stars: release.stars,
+        }
+    }
+}
 
-        ORDER BY {0} DESC
-        LIMIT $1 OFFSET $2",
+/// The first `limit` releases in `order`
+pub(crate) fn get_releases(
+    conn: &mut impl PreparedStatements,
+    limit: i64,
+    order: Order,
+) -> Vec<Release> {
+    let (ordering, filter_failed) = order.sorting();
+
+    latest_releases(
+        conn,
         ordering,
-    );
+        filter_failed,
+        LatestReleasesPage::Offset(0),
+        limit,
+    )
+    .unwrap()
+    .into_iter()
+    .map(Release::from)
+    .collect()
+}
 
-    conn.query(query.as_str(), &[&limit, &offset, &filter_failed])
-        .unwrap()
-        .into_iter()
-        .map(|row| Release {
-            name: row.get(0),
-            version: row.get(1),
-            description: row.get(2),
-            target_name: row.get(3),
-            release_time: row.get(4),
-            rustdoc_status: row.get(5),
-            stars: row.get::<_, Option<i32>>(6).unwrap_or(0),
-        })
-        .collect()
+/// Build status of the releases returned by a search
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum BuildStatusFilter {
+    /// Releases with documentation, the default
+    #[default]
+    Success,
+    /// Libraries that failed to build
+    Failed,
+    Any,
 }
 
-fn get_releases_by_owner(
-    conn: &mut Client,
-    page: i64,
-    limit: i64,
-    owner: &str,
-) -> (String, Vec<Release>) {
-    let offset = (page - 1) * limit;
+impl BuildStatusFilter {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failed => "failed",
+            Self::Any => "any",
+        }
+    }
+}
 
-    let query = "SELECT crates.name,
-                        releases.version,
-                        releases.description,
-                        releases.target_name,
-                        releases.release_time,
-                        releases.rustdoc_status,
-                        repositories.stars,
-                        owners.name,
-                        owners.login
-                 FROM crates
-                 INNER JOIN releases ON releases.id = crates.latest_version_id
-                 INNER JOIN owner_rels ON owner_rels.cid = crates.id
-                 INNER JOIN owners ON owners.id = owner_rels.oid
-                 LEFT JOIN repositories ON releases.repository_id = repositories.id
-                 WHERE owners.login = $1
-                 ORDER BY repositories.stars DESC NULLS LAST
-                 LIMIT $2 OFFSET $3";
-    let query = conn.query(query, &[&owner, &limit, &offset]).unwrap();
-
-    let mut owner_name = None;
-    let packages = query
-        .into_iter()
-        .map(|row| {
-            if owner_name.is_none() {
-                owner_name = Some(if !row.get::<usize, String>(7).is_empty() {
-                    row.get(7)
-                } else {
-                    row.get(8)
-                });
-            }
+impl std::str::FromStr for BuildStatusFilter {
+    type Err = ();
 
-            Release {
-                name: row.get(0),
-                version: row.get(1),
-                description: row.get(2),
-                target_name: row.get(3),
-                release_time: row.get(4),
-                rustdoc_status: row.get(5),
-                stars: row.get::<_, Option<i32>>(6).unwrap_or(0),
-            }
-        })
-        .collect();
+    fn from_str(status: &str) -> Result<Self, ()> {
+        match status {
+            "success" => Ok(Self::Success),
+            "failed" => Ok(Self::Failed),
+            "any" => Ok(Self::Any),
+            _ => Err(()),
+        }
+    }
+}
 
-    (owner_name.unwrap_or_default(), packages)
+/// Filters applied to the results of a search, from the `status`, `target` and `license` query
+/// parameters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(super) struct SearchFilters {
+    pub(super) status: BuildStatusFilter,
+    /// Only releases documented for this target
+    pub(super) target: Option<String>,
+    /// Only releases with this SPDX license identifier in their license expression
+    pub(super) license: Option<String>,
+}
+
+impl SearchFilters {
+    fn from_query_pairs<'a>(
+        pairs: impl Iterator<Item = (std::borrow::Cow<'a, str>, std::borrow::Cow<'a, str>)>,
+    ) -> Self {
+        let mut filters = SearchFilters::default();
+        for (key, value) in pairs {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_ref() {
+                "status" => filters.status = value.parse().unwrap_or_default(),
+                "target" => filters.target = Some(value.to_owned()),
+                "license" => filters.license = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        filters
+    }
 }
 
 /// Get the search results for a crate search query
 ///
 /// Retrieves crates which names have a levenshtein distance of less than or equal to 3,
-/// crates who fit into or otherwise are made up of the query or crates whose descriptions
-/// match the search query.
+/// crates who fit into or otherwise are made up of the query, crates whose names share most of
+/// their trigrams with the query or crates whose descriptions match the search query.
+///
+/// The results are ranked by a weighted sum of three scores between 0 and 1, the weights being
+/// configured by `search_*_weight` in the `Config`:
+///
+/// * how close the name is to the query, with a bonus when the name starts with the query or
+///   contains it
+/// * the downloads of the release, on a logarithmic scale topping at a billion downloads
+/// * how recent the release is, halved after a year
 ///
 /// * `query`: The query string, unfiltered
+/// * `filters`: Restrictions on the build status, targets and license of the releases
 /// * `page`: The page of results to show (1-indexed)
 /// * `limit`: The number of results to return
 ///
@@ -177,7 +224,9 @@ fn get_releases_by_owner(
 ///
 fn get_search_results(
     conn: &mut Client,
+    config: &Config,
     mut query: &str,
+    filters: &SearchFilters,
     page: i64,
     limit: i64,
 ) -> Result<(i64, Vec<Release>), failure::Error> {
@@ -206,23 +255,59 @@ fn get_search_results(
                     releases.crate_id,
                     RANK() OVER (PARTITION BY crate_id ORDER BY release_time DESC) as rank
                 FROM releases
-                WHERE releases.rustdoc_status AND NOT releases.yanked
+                WHERE (releases.rustdoc_status OR $4 <> 'success') AND NOT releases.yanked
             ) AS releases
             WHERE releases.rank = 1
         ) AS latest_release ON latest_release.crate_id = crates.id
         INNER JOIN releases ON latest_release.id = releases.id
         LEFT JOIN repositories ON releases.repository_id = repositories.id
         WHERE
-            ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
-            OR crates.name ILIKE CONCAT('%', $1, '%')
+            (
+                ((char_length($1)::float - levenshtein(crates.name, $1)::float) / char_length($1)::float) >= 0.65
+                OR crates.name ILIKE CONCAT('%', $1, '%')
+                OR crates.name % $1
+            )
+            AND ($4 <> 'failed' OR (NOT releases.build_status AND releases.is_library))
+            AND (
+                $5::TEXT IS NULL
+                OR releases.default_target = $5
+                OR releases.doc_targets::jsonb ? $5
+            )
+            AND (
+                $6::TEXT IS NULL
+                OR LOWER($6) = ANY(regexp_split_to_array(LOWER(releases.license), '[\\s/()]+'))
+            )
         GROUP BY crates.id, releases.id, repositories.stars
         ORDER BY
-            levenshtein(crates.name, $1) ASC,
-            crates.name ILIKE CONCAT('%', $1, '%'),
-            releases.downloads DESC
+            $7 * (
+                1.0 - levenshtein(LOWER(crates.name), LOWER($1))::float
+                    / GREATEST(char_length(crates.name), char_length($1))::float
+                + CASE
+                    WHEN LEFT(LOWER(crates.name), char_length($1)) = LOWER($1) THEN 0.2
+                    WHEN crates.name ILIKE CONCAT('%', $1, '%') THEN 0.1
+                    ELSE 0.0
+                END
+            )
+            + $8 * LEAST(LOG(1 + GREATEST(releases.downloads, 0))::float / 9.0, 1.0)
+            + $9 / (1.0 + EXTRACT(EPOCH FROM NOW() - releases.release_time)::float / 31557600.0)
+            DESC,
+            crates.name
         LIMIT $2 OFFSET $3";
 
-    let rows = conn.query(statement, &[&query, &limit, &offset])?;
+    let rows = conn.query(
+        statement,
+        &[
+            &query,
+            &limit,
+            &offset,
+            &filters.status.as_str(),
+            &filters.target,
+            &filters.license,
+            &config.search_name_weight,
+            &config.search_downloads_weight,
+            &config.search_recency_weight,
+        ],
+    )?;
 
     // Each row contains the total number of possible/valid results, just get it once
     let total_results = rows
@@ -252,11 +337,16 @@ struct HomePage {
 
 impl_webpage! {
     HomePage = "core/home.html",
+    cached = true,
 }
 
 pub fn home_page(req: &mut Request) -> IronResult<Response> {
-    let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_HOME, Order::ReleaseTime);
+    let mut conn = extension!(req, Pool).read()?;
+    let recent_releases = ctry!(
+        req,
+        extension!(req, ReleasesCache).first_page(&mut conn, Order::ReleaseTime, RELEASES_IN_HOME)
+    );
+    let recent_releases = recent_releases.into_iter().map(Release::from).collect();
 
     HomePage { recent_releases }.into_response(req)
 }
@@ -269,28 +359,78 @@ struct ReleaseFeed {
 impl_webpage! {
     ReleaseFeed  = "releases/feed.xml",
     content_type = ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![])),
+    cached = true,
 }
 
 pub fn releases_feed_handler(req: &mut Request) -> IronResult<Response> {
     let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_FEED, Order::ReleaseTime);
+    let recent_releases = ctry!(
+        req,
+        extension!(req, ReleasesCache).first_page(&mut conn, Order::ReleaseTime, RELEASES_IN_FEED)
+    );
+    let recent_releases = recent_releases.into_iter().map(Release::from).collect();
 
     ReleaseFeed { recent_releases }.into_response(req)
 }
 
+/// Renders the feed outside of a request, to deliver it to the subscribers of the WebSub hub
+pub(crate) fn render_releases_feed(
+    conn: &mut Client,
+    templates: &TemplateData,
+) -> crate::error::Result<String> {
+    let recent_releases = get_releases(conn, RELEASES_IN_FEED, Order::ReleaseTime);
+    let page = ReleaseFeed { recent_releases };
+    Ok(templates
+        .templates
+        .load()
+        .render(&page.template(), &tera::Context::from_serialize(&page)?)?)
+}
+
+/// Subscriptions to the feed, see `utils::pubsubhubbub`
+pub fn releases_feed_hub_handler(req: &mut Request) -> IronResult<Response> {
+    let mut body = Vec::new();
+    ctry!(
+        req,
+        (&mut req.body)
+            .take(MAX_HUB_REQUEST_SIZE)
+            .read_to_end(&mut body)
+    );
+
+    let pool = extension!(req, Pool);
+    let hub = extension!(req, Hub);
+    let result = SubscriptionRequest::from_form(&body)
+        .map_err(failure::Error::from)
+        .and_then(|request| hub.handle(&mut *pool.get()?, &request));
+    // the request is verified later, by the daemon
+    let result = match result {
+        Ok(()) => Ok(Response::with(status::Accepted)),
+        Err(err) => err.downcast::<SubscriptionError>().map(|err| {
+            let status = match err {
+                SubscriptionError::TooManyHostSubscriptions(_)
+                | SubscriptionError::TooManyPendingRequests => status::TooManyRequests,
+                _ => status::BadRequest,
+            };
+            Response::with((status, err.to_string()))
+        }),
+    };
+
+    Ok(ctry!(req, result))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct ViewReleases {
     releases: Vec<Release>,
     description: String,
     release_type: ReleaseType,
-    show_next_page: bool,
-    show_previous_page: bool,
-    page_number: i64,
-    owner: Option<String>,
+    /// Cursor of the last release shown, if there are releases after it
+    next_page: Option<String>,
+    /// Cursor of the first release shown, if there are releases before it
+    previous_page: Option<String>,
 }
 
 impl_webpage! {
     ViewReleases = "releases/releases.html",
+    cached = true,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
@@ -300,16 +440,42 @@ pub(super) enum ReleaseType {
     Stars,
     RecentFailures,
     Failures,
-    Owner,
+    Popular,
+    Trending,
     Search,
 }
 
-fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<Response> {
-    let page_number: i64 = extension!(req, Router)
-        .find("page")
-        .and_then(|page_num| page_num.parse().ok())
-        .unwrap_or(1);
+impl ReleaseType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Recent => "recent",
+            Self::Stars => "stars",
+            Self::RecentFailures => "recent-failures",
+            Self::Failures => "failures",
+            Self::Popular => "popular",
+            Self::Trending => "trending",
+            Self::Search => "search",
+        }
+    }
+}
+
+/// The page of the releases to show, from the `after` or `before` query parameters
+fn requested_page(req: &Request, order: LatestReleasesOrder) -> LatestReleasesPage {
+    for (key, value) in req.url.as_ref().query_pairs() {
+        let page: fn(_) -> _ = match key.as_ref() {
+            "after" => LatestReleasesPage::After,
+            "before" => LatestReleasesPage::Before,
+            _ => continue,
+        };
+        if let Some(cursor) = LatestReleasesCursor::parse(order, &value) {
+            return page(cursor);
+        }
+    }
 
+    LatestReleasesPage::Offset(0)
+}
+
+fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<Response> {
     let (description, release_order) = match release_type {
         ReleaseType::Recent => ("Recently uploaded crates", Order::ReleaseTime),
         ReleaseType::Stars => ("Crates with most stars", Order::GithubStars),
@@ -319,30 +485,92 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
             Order::FailuresByGithubStars,
         ),
 
-        ReleaseType::Owner | ReleaseType::Search => panic!(
-            "The owners and search page have special requirements and cannot use this handler",
-        ),
+        ReleaseType::Popular | ReleaseType::Trending => {
+            panic!("The rankings by views have no pages and use `most_viewed_handler`")
+        }
+        ReleaseType::Search => {
+            panic!("The search page has special requirements and cannot use this handler",)
+        }
     };
+    let (ordering, filter_failed) = release_order.sorting();
+    let mut conn = extension!(req, Pool).read()?;
+
+    // the numbered pages of the old links redirect to the page after the last release of the
+    // previous one
+    if let Some(page_number) = extension!(req, Router).find("page") {
+        let page_number: i64 = page_number.parse().unwrap_or(1);
+        let mut url = format!("{}/releases/{}", redirect_base(req), release_type.as_str());
+
+        if page_number > 1 {
+            let offset = (page_number - 1) * RELEASES_IN_RELEASES - 1;
+            let previous = ctry!(
+                req,
+                latest_releases(
+                    &mut conn,
+                    ordering,
+                    filter_failed,
+                    LatestReleasesPage::Offset(offset),
+                    1,
+                )
+            );
+            if let Some(previous) = previous.first() {
+                url.push_str(&format!("?after={}", previous.cursor(ordering)));
+            }
+        }
 
-    let releases = {
-        let mut conn = extension!(req, Pool).get()?;
-        get_releases(&mut conn, page_number, RELEASES_IN_RELEASES, release_order)
-    };
+        let url = ctry!(req, Url::parse(&url));
+        return Ok(super::redirect(url));
+    }
 
-    // Show next and previous page buttons
-    let (show_next_page, show_previous_page) = (
-        releases.len() == RELEASES_IN_RELEASES as usize,
-        page_number != 1,
+    let page = requested_page(req, ordering);
+    // one more release than shown tells whether there's a page further in that direction
+    let mut releases = ctry!(
+        req,
+        match page {
+            LatestReleasesPage::Offset(0) => extension!(req, ReleasesCache).first_page(
+                &mut conn,
+                release_order,
+                RELEASES_IN_RELEASES + 1
+            ),
+            _ => latest_releases(
+                &mut conn,
+                ordering,
+                filter_failed,
+                page,
+                RELEASES_IN_RELEASES + 1,
+            ),
+        }
     );
+    let has_more = releases.len() > RELEASES_IN_RELEASES as usize;
+    if has_more {
+        match page {
+            LatestReleasesPage::Before(_) => {
+                releases.remove(0);
+            }
+            _ => releases.truncate(RELEASES_IN_RELEASES as usize),
+        }
+    }
+
+    let (show_next_page, show_previous_page) = match page {
+        LatestReleasesPage::Offset(_) => (has_more, false),
+        LatestReleasesPage::After(_) => (has_more, true),
+        LatestReleasesPage::Before(_) => (true, has_more),
+    };
+    let next_page = releases
+        .last()
+        .filter(|_| show_next_page)
+        .map(|release| release.cursor(ordering).to_string());
+    let previous_page = releases
+        .first()
+        .filter(|_| show_previous_page)
+        .map(|release| release.cursor(ordering).to_string());
 
     ViewReleases {
-        releases,
+        releases: releases.into_iter().map(Release::from).collect(),
         description: description.into(),
         release_type,
-        show_next_page,
-        show_previous_page,
-        page_number,
-        owner: None,
+        next_page,
+        previous_page,
     }
     .into_response(req)
 }
@@ -363,49 +591,47 @@ pub fn releases_failures_by_stars_handler(req: &mut Request) -> IronResult<Respo
     releases_handler(req, ReleaseType::Failures)
 }
 
-pub fn owner_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    // page number of releases
-    let page_number: i64 = router
-        .find("page")
-        .and_then(|page_num| page_num.parse().ok())
-        .unwrap_or(1);
-    let owner_route_value = router.find("owner").unwrap();
-
-    let (owner_name, releases) = {
-        let mut conn = extension!(req, Pool).get()?;
-
-        // We need to keep the owner_route_value unchanged, as we may render paginated links in the page.
-        // Changing the owner_route_value directly will cause the link to change, for example: @foobar -> foobar.
-        let mut owner = owner_route_value;
-        if owner.starts_with('@') {
-            owner = &owner[1..];
-        }
-        get_releases_by_owner(&mut conn, page_number, RELEASES_IN_RELEASES, owner)
+/// The crates ranked by the views of their documentation, only the first page of them
+fn most_viewed_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<Response> {
+    let (description, ranking) = match release_type {
+        ReleaseType::Popular => (
+            "Crates with the most viewed documentation",
+            ViewsRanking::Popular,
+        ),
+        ReleaseType::Trending => (
+            "Crates whose documentation is trending",
+            ViewsRanking::Trending,
+        ),
+        _ => panic!("only the rankings by views can use this handler"),
     };
-
-    if releases.is_empty() {
-        return Err(Nope::OwnerNotFound.into());
+    if !extension!(req, Config).page_view_stats {
+        return Err(Nope::ResourceNotFound.into());
     }
 
-    // Show next and previous page buttons
-    let (show_next_page, show_previous_page) = (
-        releases.len() == RELEASES_IN_RELEASES as usize,
-        page_number != 1,
+    let mut conn = extension!(req, Pool).read()?;
+    let releases = ctry!(
+        req,
+        extension!(req, ReleasesCache).most_viewed(&mut conn, ranking, RELEASES_IN_RELEASES)
     );
 
     ViewReleases {
-        releases,
-        description: format!("Crates from {}", owner_name),
-        release_type: ReleaseType::Owner,
-        show_next_page,
-        show_previous_page,
-        page_number,
-        owner: Some(owner_route_value.into()),
+        releases: releases.into_iter().map(Release::from).collect(),
+        description: description.into(),
+        release_type,
+        next_page: None,
+        previous_page: None,
     }
     .into_response(req)
 }
 
+pub fn popular_releases_handler(req: &mut Request) -> IronResult<Response> {
+    most_viewed_handler(req, ReleaseType::Popular)
+}
+
+pub fn trending_releases_handler(req: &mut Request) -> IronResult<Response> {
+    most_viewed_handler(req, ReleaseType::Trending)
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub(super) struct Search {
     pub(super) title: String,
@@ -417,10 +643,23 @@ pub(super) struct Search {
     pub(super) current_page: i64,
     /// This should always be `ReleaseType::Search`
     pub(super) release_type: ReleaseType,
+    pub(super) filters: SearchFilters,
+    /// Targets suggested by the target filter
+    pub(super) targets: &'static [&'static str],
     #[serde(skip)]
     pub(super) status: iron::status::Status,
 }
 
+/// Search results returned by `/releases/search.json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SearchJson {
+    query: String,
+    filters: SearchFilters,
+    /// Number of crates matching the search, only the first ones are returned
+    total: i64,
+    results: Vec<Release>,
+}
+
 impl Default for Search {
     fn default() -> Self {
         Self {
@@ -431,6 +670,8 @@ impl Default for Search {
             next_page_button: false,
             current_page: 0,
             release_type: ReleaseType::Search,
+            filters: SearchFilters::default(),
+            targets: docsrs_metadata::DEFAULT_TARGETS,
             status: iron::status::Ok,
         }
     }
@@ -506,7 +747,7 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     let url = req.url.as_ref();
     let mut params = url.query_pairs();
     let query = params.find(|(key, _)| key == "query");
-    let mut conn = extension!(req, Pool).get()?;
+    let mut conn = extension!(req, Pool).read()?;
 
     if let Some((_, query)) = query {
         // check if I am feeling lucky button pressed and redirect user to crate page
@@ -524,7 +765,9 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
             // since we never pass a version into `match_version` here, we'll never get
             // `MatchVersion::Exact`, so the distinction between `Exact` and `Semver` doesn't
             // matter
-            if let Ok(matchver) = match_version(&mut conn, &query, None) {
+            if let Ok(matchver) =
+                extension!(req, VersionCache).match_version(&mut conn, &query, None)
+            {
                 let (version, id) = matchver.version.into_parts();
                 let query = matchver.corrected_name.unwrap_or_else(|| query.to_string());
 
@@ -572,10 +815,36 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
             }
         }
 
-        let (_, results) = ctry!(
+        let filters = SearchFilters::from_query_pairs(url.query_pairs());
+        let (total, results) = ctry!(
             req,
-            get_search_results(&mut conn, &query, 1, RELEASES_IN_RELEASES)
+            get_search_results(
+                &mut conn,
+                extension!(req, Config),
+                &query,
+                &filters,
+                1,
+                RELEASES_IN_RELEASES
+            )
         );
+
+        let is_json = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .is_some_and(|segment| segment.ends_with(".json"));
+        if is_json {
+            let json = SearchJson {
+                query: query.into_owned(),
+                filters,
+                total,
+                results,
+            };
+            let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+            resp.headers.set(ContentType::json());
+            resp.headers.set(AccessControlAllowOrigin::Any);
+            return Ok(resp);
+        }
+
         let title = if results.is_empty() {
             format!("No results found for '{}'", query)
         } else {
@@ -587,6 +856,7 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
             title,
             results,
             search_query: Some(query.into_owned()),
+            filters,
             ..Default::default()
         }
         .into_response(req)
@@ -614,36 +884,20 @@ pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
         req,
         conn.query(
             "
-            WITH dates AS (
+            SELECT
+                dates.date_ AS date,
+                COALESCE(release_activity.releases, 0) AS counts,
+                COALESCE(release_activity.failures, 0) AS failures
+            FROM (
                 -- we need this series so that days in the statistic that don't have any releases are included
-                SELECT generate_series( 
+                SELECT generate_series(
                         CURRENT_DATE - INTERVAL '30 days',
                         CURRENT_DATE - INTERVAL '1 day',
                         '1 day'::interval
                     )::date AS date_
-            ), 
-            release_stats AS (
-                SELECT
-                    release_time::date AS date_,
-                    COUNT(*) AS counts,
-                    SUM(CAST((is_library = TRUE AND build_status = FALSE) AS INT)) AS failures
-                FROM
-                    releases
-                WHERE
-                    release_time >= CURRENT_DATE - INTERVAL '30 days' AND
-                    release_time < CURRENT_DATE
-                GROUP BY
-                    release_time::date
-            ) 
-            SELECT 
-                dates.date_ AS date,
-                COALESCE(rs.counts, 0) AS counts,
-                COALESCE(rs.failures, 0) AS failures 
-            FROM
-                dates 
-                LEFT OUTER JOIN Release_stats AS rs ON dates.date_ = rs.date_
-
-            ORDER BY 
+            ) AS dates
+            LEFT OUTER JOIN release_activity ON dates.date_ = release_activity.date
+            ORDER BY
                 dates.date_
             ",
             &[],
@@ -691,6 +945,95 @@ pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
     .into_response(req)
 }
 
+/// The events of the build queue after the `cursor` query parameter, see `utils::queue_events`
+pub fn queue_events_handler(req: &mut Request) -> IronResult<Response> {
+    let cursor = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "cursor")
+        .and_then(|(_, value)| value.parse().ok());
+    let mut conn = extension!(req, Pool).get()?;
+    let events = ctry!(req, queue_events::since(&mut conn, cursor));
+
+    let mut response = Response::with((status::Ok, serde_json::to_string(&events).unwrap()));
+    response.headers.set(ContentType::json());
+    response
+        .headers
+        .set(CacheControl(vec![CacheDirective::NoCache]));
+    Ok(response)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RebuildsPage {
+    description: &'static str,
+    runs: Vec<RebuildRun>,
+    /// Used to show how far along the release list each run got
+    max_release_id: i32,
+}
+
+impl_webpage! {
+    RebuildsPage = "releases/rebuilds.html",
+}
+
+pub fn rebuilds_handler(req: &mut Request) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let runs = ctry!(req, rebuild::rebuild_runs(&mut conn));
+    let max_release_id = ctry!(req, rebuild::max_release_id(&mut conn));
+
+    RebuildsPage {
+        description: "Progress of the bulk rebuilds of older documentation",
+        runs,
+        max_release_id,
+    }
+    .into_response(req)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ConsistencyPage {
+    description: &'static str,
+    issues: Vec<ConsistencyIssue>,
+}
+
+impl_webpage! {
+    ConsistencyPage = "releases/consistency.html",
+}
+
+pub fn consistency_handler(req: &mut Request) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let issues = ctry!(req, consistency::consistency_issues(&mut conn));
+
+    ConsistencyPage {
+        description: "Files of releases missing from the storage",
+        issues,
+    }
+    .into_response(req)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct StoragePage {
+    description: &'static str,
+    totals: StorageTotals,
+    releases: Vec<ReleaseStorageStats>,
+}
+
+impl_webpage! {
+    StoragePage = "releases/storage.html",
+}
+
+pub fn storage_handler(req: &mut Request) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+    let totals = ctry!(req, storage_stats::totals(&mut conn));
+    let releases = ctry!(req, storage_stats::top_consumers(&mut conn, 100));
+
+    StoragePage {
+        description: "Releases taking the most space in the storage",
+        totals,
+        releases,
+    }
+    .into_response(req)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -699,6 +1042,7 @@ mod tests {
     use chrono::{Duration, TimeZone};
     use failure::Error;
     use kuchiki::traits::TendrilSink;
+    use serde_json::{json, Value};
     use std::collections::HashSet;
 
     #[test]
@@ -719,7 +1063,7 @@ mod tests {
             // release without stars will not be shown
             env.fake_release().name("baz").version("1.0.0").create()?;
 
-            let releases = get_releases(&mut db.conn(), 1, 10, Order::GithubStars);
+            let releases = get_releases(&mut db.conn(), 10, Order::GithubStars);
             assert_eq!(
                 vec![
                     "bar", // 20 stars
@@ -764,12 +1108,20 @@ mod tests {
                 .version("0.0.0")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "foo", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "foo",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
 
-            let expected = ["foo", "fo0", "bar-foo", "foo-bar"];
+            // names starting with the query rank before the ones only containing it
+            let expected = ["foo", "fo0", "foo-bar", "bar-foo"];
             for expected in expected.iter() {
                 assert_eq!(expected, &results.next().unwrap().name);
             }
@@ -792,8 +1144,14 @@ mod tests {
             let near_matches = ["Regex", "rEgex", "reGex", "regEx", "regeX"];
 
             for name in near_matches.iter() {
-                let (num_results, mut results) =
-                    dbg!(get_search_results(&mut db.conn(), *name, 1, 100))?;
+                let (num_results, mut results) = dbg!(get_search_results(
+                    &mut db.conn(),
+                    &env.config(),
+                    *name,
+                    &SearchFilters::default(),
+                    1,
+                    100
+                ))?;
                 assert_eq!(num_results, 3);
 
                 for name in releases.iter() {
@@ -816,7 +1174,14 @@ mod tests {
                 .build_result_failed()
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "regex", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "regex",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 0);
 
             let results = results.into_iter();
@@ -836,7 +1201,14 @@ mod tests {
                 .yanked(true)
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "regex", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "regex",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 0);
 
             let results = results.into_iter();
@@ -852,7 +1224,14 @@ mod tests {
             let db = env.db();
             env.fake_release().name("regex").version("0.0.0").create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "redex", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "redex",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 1);
 
             let mut results = results.into_iter();
@@ -874,7 +1253,14 @@ mod tests {
     //             .create()?;
     //
     //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "supercalifragilisticexpialidocious", 1, 100)?;
+    //             get_search_results(
+    //                 &mut db.conn(),
+    //                 &env.config(),
+    //                 "supercalifragilisticexpialidocious",
+    //                 &SearchFilters::default(),
+    //                 1,
+    //                 100,
+    //             )?;
     //         assert_eq!(num_results, 1);
     //
     //         let mut results = results.into_iter();
@@ -900,7 +1286,14 @@ mod tests {
                 .name("something_completely_unrelated")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "something", 1, 2)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "something",
+                &SearchFilters::default(),
+                1,
+                2,
+            )?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
@@ -923,7 +1316,14 @@ mod tests {
                 .name("something_completely_unrelated")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "something", 2, 2)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "something",
+                &SearchFilters::default(),
+                2,
+                2,
+            )?;
             assert_eq!(num_results, 4);
 
             let mut results = results.into_iter();
@@ -938,6 +1338,69 @@ mod tests {
         })
     }
 
+    #[test]
+    fn search_filters() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("filter_documented")
+                .license("MIT OR Apache-2.0")
+                .create()?;
+            env.fake_release()
+                .name("filter_failed")
+                .license("GPL-3.0")
+                .build_result_failed()
+                .create()?;
+            env.fake_release()
+                .name("filter_wasm")
+                .license("Apache-2.0/MIT")
+                .add_target("wasm32-unknown-unknown")
+                .create()?;
+            let web = env.frontend();
+
+            let search = |params: &str| -> Result<Vec<String>, failure::Error> {
+                let json: serde_json::Value = web
+                    .get(&format!("/releases/search.json?query=filter_{}", params))
+                    .send()?
+                    .json()?;
+                Ok(json["results"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|release| release["name"].as_str().unwrap().to_owned())
+                    .collect())
+            };
+
+            assert_eq!(search("")?, vec!["filter_wasm", "filter_documented"]);
+            assert_eq!(search("&status=failed")?, vec!["filter_failed"]);
+            assert_eq!(search("&status=any")?.len(), 3);
+            assert_eq!(
+                search("&target=wasm32-unknown-unknown")?,
+                vec!["filter_wasm"]
+            );
+            assert_eq!(
+                search("&license=apache-2.0")?,
+                vec!["filter_wasm", "filter_documented"]
+            );
+            assert_eq!(search("&license=Apache")?, Vec::<String>::new());
+            assert_eq!(
+                search("&status=any&license=GPL-3.0")?,
+                vec!["filter_failed"]
+            );
+
+            let page = kuchiki::parse_html().one(
+                web.get("/releases/search?query=filter_&status=failed")
+                    .send()?
+                    .text()?,
+            );
+            let selected = page
+                .select_first("select[name=status] option[selected]")
+                .unwrap();
+            assert_eq!(selected.attributes.borrow().get("value"), Some("failed"));
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn release_dates() {
         wrapper(|env| {
@@ -967,7 +1430,14 @@ mod tests {
                 .version("0.0.0")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "somethang", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "somethang",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 1);
 
             let mut results = results.into_iter();
@@ -999,7 +1469,14 @@ mod tests {
     //             .create()?;
     //
     //         let (num_results, results) =
-    //             get_search_results(&mut db.conn(), "name_better_than_description", 1, 100)?;
+    //             get_search_results(
+    //                 &mut db.conn(),
+    //                 &env.config(),
+    //                 "name_better_than_description",
+    //                 &SearchFilters::default(),
+    //                 1,
+    //                 100,
+    //             )?;
     //         assert_eq!(num_results, 2);
     //
     //         let mut results = results.into_iter();
@@ -1032,7 +1509,14 @@ mod tests {
                 .name("i_am_useless_and_mean_nothing")
                 .create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "match", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "match",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 3);
 
             let mut results = results.into_iter();
@@ -1053,7 +1537,14 @@ mod tests {
             env.fake_release().name("matcb").downloads(10).create()?;
             env.fake_release().name("matcc").downloads(1).create()?;
 
-            let (num_results, results) = get_search_results(&mut db.conn(), "match", 1, 100)?;
+            let (num_results, results) = get_search_results(
+                &mut db.conn(),
+                &env.config(),
+                "match",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
             assert_eq!(num_results, 3);
 
             let mut results = results.into_iter();
@@ -1066,6 +1557,103 @@ mod tests {
         })
     }
 
+    #[test]
+    fn exact_match_before_popular_crates() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("serde")
+                .downloads(10)
+                .release_time(Utc::now() - chrono::Duration::days(3 * 365))
+                .create()?;
+            env.fake_release()
+                .name("serde_json")
+                .downloads(100_000_000)
+                .create()?;
+
+            let (_, results) = get_search_results(
+                &mut env.db().conn(),
+                &env.config(),
+                "Serde",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
+            let names: Vec<_> = results
+                .iter()
+                .map(|release| release.name.as_str())
+                .collect();
+            assert_eq!(names, vec!["serde", "serde_json"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn order_by_recency() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("matca")
+                .release_time(Utc::now() - chrono::Duration::days(2 * 365))
+                .create()?;
+            env.fake_release()
+                .name("matcb")
+                .release_time(Utc::now() - chrono::Duration::days(30))
+                .create()?;
+            env.fake_release()
+                .name("matcc")
+                .release_time(Utc::now() - chrono::Duration::days(365))
+                .create()?;
+
+            let (_, results) = get_search_results(
+                &mut env.db().conn(),
+                &env.config(),
+                "match",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
+            let names: Vec<_> = results
+                .iter()
+                .map(|release| release.name.as_str())
+                .collect();
+            assert_eq!(names, vec!["matcb", "matcc", "matca"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn configurable_ranking_weights() {
+        wrapper(|env| {
+            // only the downloads count
+            env.override_config(|config| {
+                config.search_name_weight = 0.0;
+                config.search_recency_weight = 0.0;
+            });
+            env.fake_release().name("regex").downloads(10).create()?;
+            env.fake_release()
+                .name("regex-syntax")
+                .downloads(1_000_000)
+                .create()?;
+
+            let (_, results) = get_search_results(
+                &mut env.db().conn(),
+                &env.config(),
+                "regex",
+                &SearchFilters::default(),
+                1,
+                100,
+            )?;
+            let names: Vec<_> = results
+                .iter()
+                .map(|release| release.name.as_str())
+                .collect();
+            assert_eq!(names, vec!["regex-syntax", "regex"]);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn im_feeling_lucky_with_stars() {
         wrapper(|env| {
@@ -1170,6 +1758,48 @@ mod tests {
         })
     }
 
+    #[test]
+    fn releases_by_views() {
+        wrapper(|env| {
+            let web = env.frontend();
+            assert_eq!(web.get("/releases/popular").send()?.status(), 404);
+            assert_eq!(web.get("/releases/trending").send()?.status(), 404);
+            Ok(())
+        });
+
+        wrapper(|env| {
+            env.override_config(|config| config.page_view_stats = true);
+            let old = env.fake_release().name("old").version("0.1.0").create()?;
+            let new = env.fake_release().name("new").version("0.1.0").create()?;
+            env.fake_release()
+                .name("unseen")
+                .version("0.1.0")
+                .create()?;
+            for (release_id, days_ago, views) in &[(old, 14, 3000), (old, 1, 1000), (new, 1, 200)] {
+                env.db().conn().execute(
+                    "INSERT INTO page_views (release_id, date, views)
+                     VALUES ($1, CURRENT_DATE - $2::INT, $3)",
+                    &[release_id, days_ago, views],
+                )?;
+            }
+
+            let web = env.frontend();
+            assert_eq!(
+                get_release_links("/releases/popular", web)?,
+                vec!["/old/0.1.0/old/", "/new/0.1.0/new/"]
+            );
+            assert_eq!(
+                get_release_links("/releases/trending", web)?,
+                vec!["/new/0.1.0/new/", "/old/0.1.0/old/"]
+            );
+
+            let page = kuchiki::parse_html().one(web.get("/releases").send()?.text()?);
+            assert!(page.select_first(r#"a[href="/releases/trending"]"#).is_ok());
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn failures_by_stars() {
         wrapper(|env| {
@@ -1243,6 +1873,74 @@ mod tests {
         })
     }
 
+    fn get_pagination_links(path: &str, web: &TestFrontend) -> Result<Vec<String>, Error> {
+        let response = web.get(path).send()?;
+        assert!(response.status().is_success());
+
+        let page = kuchiki::parse_html().one(response.text()?);
+
+        Ok(page
+            .select(".pagination a")
+            .expect("missing pagination")
+            .map(|el| el.attributes.borrow().get("href").unwrap().to_string())
+            .collect())
+    }
+
+    #[test]
+    fn releases_pagination() {
+        wrapper(|env| {
+            let web = env.frontend();
+            let mut ids = Vec::new();
+            for i in 0..=RELEASES_IN_RELEASES {
+                let release_time = Utc.ymd(2020, 4, 16).and_hms(4, 33, 50) + Duration::minutes(i);
+                ids.push(
+                    env.fake_release()
+                        .name(&format!("crate_{}", i))
+                        .release_time(release_time)
+                        .create()?,
+                );
+            }
+
+            let links = get_release_links("/releases/recent", web)?;
+            assert_eq!(links.len(), RELEASES_IN_RELEASES as usize);
+            assert_eq!(links[0], "/crate_30/1.0.0/crate_30/");
+            assert_eq!(links[29], "/crate_1/1.0.0/crate_1/");
+
+            // the next page starts after the last release shown
+            let next = format!(
+                "/releases/recent?after={}",
+                LatestReleasesCursor::ReleaseTime(Utc.ymd(2020, 4, 16).and_hms(4, 34, 50), ids[1])
+            );
+            assert_eq!(
+                get_pagination_links("/releases/recent", web)?,
+                vec![next.clone()]
+            );
+            assert_eq!(
+                get_release_links(&next, web)?,
+                vec!["/crate_0/1.0.0/crate_0/"]
+            );
+
+            let previous = format!(
+                "/releases/recent?before={}",
+                LatestReleasesCursor::ReleaseTime(Utc.ymd(2020, 4, 16).and_hms(4, 33, 50), ids[0])
+            );
+            assert_eq!(get_pagination_links(&next, web)?, vec![previous.clone()]);
+            assert_eq!(get_release_links(&previous, web)?, links);
+            assert_eq!(get_pagination_links(&previous, web)?, vec![next.clone()]);
+
+            // the numbered pages redirect to the cursors
+            assert_redirect("/releases/recent/2", &next, web)?;
+            assert_redirect("/releases/recent/1", "/releases/recent", web)?;
+            assert_redirect("/releases/recent/3", "/releases/recent", web)?;
+            assert_redirect("/releases/stars/1", "/releases/stars", web)?;
+
+            // a cursor that can't be parsed is the first page
+            assert_eq!(get_release_links("/releases/recent?after=foo", web)?, links);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn releases_homepage_and_recent() {
         wrapper(|env| {
@@ -1323,6 +2021,24 @@ mod tests {
             // failures only one
             assert!(text.contains(&format!("data: [{},1]", vec!["0"; 29].join(","))));
 
+            // a rebuild replaces the release in the counts
+            env.fake_release()
+                .name("some_random_crate_that_failed_yesterday")
+                .release_time(Utc::now() - Duration::days(1))
+                .create()?;
+            let text = web.get("/releases/activity/").send()?.text()?;
+            assert!(text.contains(&format!("data: [{},2]", vec!["0"; 29].join(","))));
+            assert_eq!(text.matches(&empty_data).count(), 1);
+
+            // deleted releases aren't counted anymore
+            crate::db::delete_crate(
+                &mut env.db().conn(),
+                &env.storage(),
+                "some_random_crate_yesterday",
+            )?;
+            let text = web.get("/releases/activity/").send()?.text()?;
+            assert!(text.contains(&format!("data: [{},1]", vec!["0"; 29].join(","))));
+
             Ok(())
         })
     }
@@ -1342,6 +2058,51 @@ mod tests {
         })
     }
 
+    #[test]
+    fn release_feed_hub() {
+        wrapper(|env| {
+            env.fake_release().name("some_random_crate").create()?;
+            let feed = render_releases_feed(
+                &mut env.db().conn(),
+                &TemplateData::new(&mut env.db().conn(), &env.config())?,
+            )?;
+            assert!(feed.contains(r#"<link href="https://docs.rs/releases/feed/hub" rel="hub" />"#));
+            assert!(feed.contains("<title>some_random_crate-1.0.0</title>"));
+
+            let response = env
+                .frontend()
+                .post("/releases/feed/hub")
+                .body("hub.mode=publish&hub.topic=https%3A%2F%2Fdocs.rs%2Freleases%2Ffeed")
+                .send()?;
+            assert_eq!(response.status(), 400);
+            assert_eq!(response.text()?, "invalid hub.mode: unknown mode");
+
+            // the requests are verified later, without requesting the local network
+            let subscribe = |callback: &str| {
+                env.frontend()
+                    .post("/releases/feed/hub")
+                    .form(&[
+                        ("hub.mode", "subscribe"),
+                        ("hub.topic", "https://docs.rs/releases/feed"),
+                        ("hub.callback", callback),
+                    ])
+                    .send()
+            };
+            assert_eq!(subscribe("http://127.0.0.1:3000/callback")?.status(), 400);
+            assert_eq!(subscribe("https://example.com/callback")?.status(), 202);
+            let queued: Vec<String> = env
+                .db()
+                .conn()
+                .query("SELECT callback FROM websub_verifications", &[])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(queued, vec!["https://example.com/callback"]);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_releases_queue() {
         wrapper(|env| {
@@ -1387,80 +2148,146 @@ mod tests {
     }
 
     #[test]
-    fn nonexistent_owner_page() {
+    fn test_releases_queue_events() {
         wrapper(|env| {
-            env.fake_release()
-                .name("some_random_crate")
-                .add_owner(CrateOwner {
-                    login: "foobar".into(),
-                    avatar: "https://example.org/foobar".into(),
-                    name: "Foo Bar".into(),
-                    email: "foobar@example.org".into(),
-                })
-                .create()?;
-            let page = kuchiki::parse_html().one(
-                env.frontend()
-                    .get("/releases/random-author")
-                    .send()?
-                    .text()?,
-            );
-
-            assert_eq!(page.select("#crate-title").unwrap().count(), 1);
+            let web = env.frontend();
+            let start: Value = web.get("/releases/queue/events").send()?.json()?;
+            assert_eq!(start["events"], json!([]));
+
+            env.build_queue().add_crate("foo", "1.0.0", 0, None)?;
+            let response = web
+                .get(&format!(
+                    "/releases/queue/events?cursor={}",
+                    start["cursor"]
+                ))
+                .send()?;
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()["Content-Type"], "application/json");
+            assert_eq!(response.headers()["Cache-Control"], "no-cache");
+            let events: Value = response.json()?;
             assert_eq!(
-                page.select("#crate-title")
-                    .unwrap()
-                    .next()
-                    .unwrap()
-                    .text_contents(),
-                "The requested owner does not exist",
+                events["events"],
+                json!([{"event": "queued", "name": "foo", "version": "1.0.0", "priority": 0}])
             );
+            assert_ne!(events["cursor"], start["cursor"]);
 
             Ok(())
         });
     }
 
     #[test]
-    fn owners_page() {
+    fn test_releases_rebuilds() {
         wrapper(|env| {
             let web = env.frontend();
-            env.fake_release()
-                .name("some_random_crate")
-                .add_owner(CrateOwner {
-                    login: "foobar".into(),
-                    avatar: "https://example.org/foobar".into(),
-                    name: "Foo Bar".into(),
-                    email: "foobar@example.org".into(),
-                })
-                .create()?;
-            // Request an owner without @ sign.
-            assert_success("/releases/foobar", web)?;
-            // Request an owner with @ sign.
-            assert_success("/releases/@foobar", web)
-        })
+
+            let empty = kuchiki::parse_html().one(web.get("/releases/rebuilds").send()?.text()?);
+            assert!(empty
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("No rebuilds")));
+
+            let release_id = env.fake_release().create()?;
+            env.db().conn().execute(
+                "INSERT INTO rebuild_runs (since, last_release_id, enqueued)
+                 VALUES ('rustc 1.0.0 (000 2021-01-01)', $1, 42)",
+                &[&release_id],
+            )?;
+
+            let full = kuchiki::parse_html().one(web.get("/releases/rebuilds").send()?.text()?);
+            let items = full
+                .select(".rebuild-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(items.len(), 1);
+            assert!(items[0].text_contents().contains("2021-01-01"));
+            assert!(items[0].text_contents().contains("42 releases queued"));
+            assert!(items[0].text_contents().contains("100% done"));
+
+            Ok(())
+        });
     }
 
     #[test]
-    fn owners_pagination() {
+    fn test_releases_consistency() {
         wrapper(|env| {
             let web = env.frontend();
-            for i in 0..RELEASES_IN_RELEASES {
-                env.fake_release()
-                    .name(&format!("some_random_crate_{}", i))
-                    .add_owner(CrateOwner {
-                        login: "foobar".into(),
-                        avatar: "https://example.org/foobar".into(),
-                        name: "Foo Bar".into(),
-                        email: "foobar@example.org".into(),
-                    })
-                    .create()?;
-            }
-            let page = kuchiki::parse_html().one(web.get("/releases/@foobar").send()?.text()?);
-            let button = page.select_first("a[href='/releases/@foobar/2']");
 
-            assert!(button.is_ok());
+            let empty = kuchiki::parse_html().one(web.get("/releases/consistency").send()?.text()?);
+            assert!(empty
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("No missing files")));
+
+            env.fake_builder().name("foo").version("0.1.0").build()?;
+            env.storage().delete_prefix("sources/foo/0.1.0/src/")?;
+            consistency::check_storage(&mut env.db().conn(), &env.storage(), 10)?;
+
+            let full = kuchiki::parse_html().one(web.get("/releases/consistency").send()?.text()?);
+            let items = full
+                .select(".consistency-list > li")
+                .expect("missing list items")
+                .collect::<Vec<_>>();
+            assert_eq!(items.len(), 1);
+            assert!(items[0].text_contents().contains("foo-0.1.0"));
+            assert!(items[0]
+                .text_contents()
+                .contains("sources/foo/0.1.0/src/lib.rs"));
 
             Ok(())
-        })
+        });
+    }
+
+    #[test]
+    fn test_releases_storage() {
+        wrapper(|env| {
+            let web = env.frontend();
+
+            let empty = kuchiki::parse_html().one(web.get("/releases/storage").send()?.text()?);
+            assert!(empty
+                .select(".release > strong")
+                .expect("missing heading")
+                .any(|el| el.text_contents().contains("No release was stored")));
+
+            // random data, so that it stays large once compressed
+            let mut state = 0x2545_f491_u32;
+            let large: Vec<u8> = (0..100_000)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            env.fake_builder().name("small").version("0.1.0").build()?;
+            env.fake_builder()
+                .name("large")
+                .version("0.1.0")
+                .rustdoc_file("large/index.html", &large)
+                .build()?;
+
+            let totals = storage_stats::totals(&mut env.db().conn())?;
+            assert_eq!(totals.releases, 2);
+            assert!(totals.compressed > 100_000);
+            assert!(
+                env.metrics()
+                    .stored_bytes
+                    .with_label_values(&["rustdoc"])
+                    .get()
+                    > 0
+            );
+
+            let full = kuchiki::parse_html().one(web.get("/releases/storage").send()?.text()?);
+            let items = full
+                .select(".storage-list > li")
+                .expect("missing list items")
+                .map(|item| item.text_contents())
+                .collect::<Vec<_>>();
+            assert_eq!(items.len(), 2);
+            assert!(items[0].contains("large-0.1.0"));
+            assert!(items[1].contains("small-0.1.0"));
+
+            Ok(())
+        });
     }
 
     #[test]
@@ -1569,10 +2396,57 @@ mod tests {
     fn test_empty_query() {
         wrapper(|env| {
             let mut conn = env.db().conn();
-            let (num_results, results) = get_search_results(&mut conn, "", 0, 0).unwrap();
+            let (num_results, results) = get_search_results(
+                &mut conn,
+                &env.config(),
+                "",
+                &SearchFilters::default(),
+                0,
+                0,
+            )
+            .unwrap();
             assert_eq!(num_results, 0);
             assert!(results.is_empty());
             Ok(())
         })
     }
+
+    #[test]
+    fn home_page_is_cached() {
+        wrapper(|env| {
+            env.fake_release().name("foo").create()?;
+            let web = env.frontend();
+            let render = || -> Result<(String, String), Error> {
+                let response = web.get("/").send()?;
+                let csp = response.headers()["content-security-policy"]
+                    .to_str()?
+                    .to_string();
+                Ok((csp, response.text()?))
+            };
+            let lookups = |result| {
+                env.metrics()
+                    .render_cache_lookups
+                    .with_label_values(&["core/home.html", result])
+                    .get()
+            };
+
+            let (first_csp, first) = render()?;
+            let (second_csp, second) = render()?;
+            assert_eq!((lookups("hit"), lookups("miss")), (1, 1));
+            assert!(second.contains("/foo/"));
+            // every response still gets its own nonce
+            assert_ne!(first_csp, second_csp);
+            for (csp, page) in &[(first_csp, first), (second_csp, second)] {
+                let nonce = csp.split("'nonce-").nth(1).unwrap().split('\'').next();
+                assert!(page.contains(&format!("nonce=\"{}\"", nonce.unwrap())));
+                assert!(!page.contains("docsrs-placeholder-"));
+            }
+
+            env.fake_release().name("bar").create()?;
+            assert!(render()?.1.contains("/bar/"));
+            assert_eq!(lookups("miss"), 2);
+
+            Ok(())
+        })
+    }
 }
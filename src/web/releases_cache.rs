@@ -0,0 +1,204 @@
+//! In-process cache of the first pages of the releases lists
+//!
+//! The home page, the feed and the first page of the releases lists run the same few queries
+//! over all the crates on every request. Their results are kept for a minute
+//! (`DOCSRS_RELEASES_CACHE_TTL`), and dropped as soon as this process adds a release. Other
+//! processes only list new releases once the entries expire. The rankings by views of the
+//! documentation are cached the same way.
+
+use super::releases::Order;
+use crate::{
+    db::{
+        page_views::{self, ViewsRanking},
+        queries::{latest_releases, LatestRelease, LatestReleasesPage, PreparedStatements},
+    },
+    Config, Metrics,
+};
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    inserted: Instant,
+    releases: Vec<LatestRelease>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Listing {
+    Latest(Order),
+    MostViewed(ViewsRanking),
+}
+
+pub struct ReleasesCache {
+    /// There's an entry for every list and number of releases requested, only a handful of them
+    /// in practice
+    entries: DashMap<(Listing, i64), Entry>,
+    ttl: Duration,
+    /// Incremented by every invalidation, so that a lookup racing with one doesn't store a result
+    /// that's already outdated
+    generation: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+impl ReleasesCache {
+    pub fn new(metrics: Arc<Metrics>, config: &Config) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl: Duration::from_secs(config.releases_cache_ttl),
+            generation: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    /// The first `limit` releases in `order`
+    pub(super) fn first_page(
+        &self,
+        conn: &mut impl PreparedStatements,
+        order: Order,
+        limit: i64,
+    ) -> Result<Vec<LatestRelease>, postgres::Error> {
+        let (ordering, filter_failed) = order.sorting();
+        self.cached(conn, Listing::Latest(order), limit, |conn| {
+            latest_releases(
+                conn,
+                ordering,
+                filter_failed,
+                LatestReleasesPage::Offset(0),
+                limit,
+            )
+        })
+    }
+
+    /// The latest releases of the first `limit` crates in `ranking`
+    pub(super) fn most_viewed(
+        &self,
+        conn: &mut impl PreparedStatements,
+        ranking: ViewsRanking,
+        limit: i64,
+    ) -> Result<Vec<LatestRelease>, postgres::Error> {
+        self.cached(conn, Listing::MostViewed(ranking), limit, |conn| {
+            page_views::most_viewed(conn, ranking, limit)
+        })
+    }
+
+    fn cached<C: PreparedStatements>(
+        &self,
+        conn: &mut C,
+        listing: Listing,
+        limit: i64,
+        query: impl FnOnce(&mut C) -> Result<Vec<LatestRelease>, postgres::Error>,
+    ) -> Result<Vec<LatestRelease>, postgres::Error> {
+        if self.ttl == Duration::from_secs(0) {
+            return query(conn);
+        }
+
+        if let Some(entry) = self.entries.get(&(listing, limit)) {
+            if entry.inserted.elapsed() < self.ttl {
+                self.metrics
+                    .releases_cache_lookups
+                    .with_label_values(&["hit"])
+                    .inc();
+                return Ok(entry.releases.clone());
+            }
+        }
+        self.metrics
+            .releases_cache_lookups
+            .with_label_values(&["miss"])
+            .inc();
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let releases = query(conn)?;
+        if generation == self.generation.load(Ordering::SeqCst) {
+            self.entries.insert(
+                (listing, limit),
+                Entry {
+                    inserted: Instant::now(),
+                    releases: releases.clone(),
+                },
+            );
+        }
+
+        Ok(releases)
+    }
+
+    /// Drops all the cached lists, called when a release is added
+    pub(crate) fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.clear();
+    }
+}
+
+impl std::fmt::Debug for ReleasesCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReleasesCache")
+            .field("entries", &self.entries.len())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+
+    fn names(env: &TestEnvironment, order: Order) -> Vec<String> {
+        env.releases_cache()
+            .first_page(&mut env.db().conn(), order, 10)
+            .unwrap()
+            .into_iter()
+            .map(|release| release.name)
+            .collect()
+    }
+
+    fn lookups(env: &TestEnvironment, result: &str) -> i64 {
+        env.metrics()
+            .releases_cache_lookups
+            .with_label_values(&[result])
+            .get()
+    }
+
+    #[test]
+    fn new_release_invalidates() {
+        wrapper(|env| {
+            env.fake_release().name("foo").create()?;
+            assert_eq!(names(env, Order::ReleaseTime), vec!["foo"]);
+            assert_eq!(names(env, Order::ReleaseTime), vec!["foo"]);
+            assert_eq!(lookups(env, "hit"), 1);
+            assert_eq!(lookups(env, "miss"), 1);
+
+            // the lists are cached, not the crates
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET description = 'changed'", &[])?;
+            let cached =
+                env.releases_cache()
+                    .first_page(&mut env.db().conn(), Order::ReleaseTime, 10)?;
+            assert_eq!(cached[0].description.as_deref(), Some("Fake package"));
+
+            env.fake_release().name("bar").create()?;
+            assert_eq!(names(env, Order::ReleaseTime), vec!["bar", "foo"]);
+            assert_eq!(lookups(env, "miss"), 2);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.releases_cache_ttl = 0);
+            env.fake_release().name("foo").create()?;
+            assert_eq!(names(env, Order::ReleaseTime), vec!["foo"]);
+
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET build_status = FALSE", &[])?;
+            assert_eq!(names(env, Order::RecentFailures), vec!["foo"]);
+            assert_eq!(lookups(env, "hit") + lookups(env, "miss"), 0);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,214 @@
+//! Request IDs and structured request logs
+//!
+//! Every request gets an ID, either taken from a valid incoming `X-Request-Id` header (set by the
+//! CDN or load balancer) or randomly generated. The ID is returned in the `X-Request-Id` response
+//! header, shown on error pages and attached to every log line emitted while handling the
+//! request, including the ones coming from the storage backends, so that a user report can be
+//! matched with the backend logs.
+//!
+//! When `DOCSRS_STRUCTURED_REQUEST_LOGS` is enabled, a JSON line with the method, path, status
+//! and duration of every request is also logged under the `docs_rs::requests` target.
+
+use super::metrics::RouteName;
+use iron::headers::Headers;
+use iron::prelude::*;
+use iron::status::Status;
+use iron::{AfterMiddleware, BeforeMiddleware};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::time::Instant;
+
+pub(super) const HEADER: &str = "X-Request-Id";
+/// Incoming IDs longer than this are replaced with a generated one
+const MAX_INCOMING_LEN: usize = 64;
+
+thread_local! {
+    // iron handles every request on a single thread, from the first middleware to the last one
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Returns the ID of the request being handled by the current thread, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// The ID of the request, stored in the request extensions
+pub(crate) struct RequestId;
+
+impl iron::typemap::Key for RequestId {
+    type Value = String;
+}
+
+struct RequestStart;
+
+impl iron::typemap::Key for RequestStart {
+    type Value = Instant;
+}
+
+/// The ID of a new request, taken from its `X-Request-Id` header when it's valid
+pub(super) fn request_id(headers: &Headers) -> String {
+    incoming_id(headers).unwrap_or_else(generate_id)
+}
+
+fn generate_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("failed to generate a request id");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn incoming_id(headers: &Headers) -> Option<String> {
+    let raw = headers.get_raw(HEADER)?.first()?;
+    let id = std::str::from_utf8(raw).ok()?;
+
+    let valid = !id.is_empty()
+        && id.len() <= MAX_INCOMING_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Some(id.to_owned())
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct RequestLine<'a> {
+    pub(super) request_id: &'a str,
+    pub(super) method: String,
+    pub(super) path: String,
+    pub(super) route: Option<&'a str>,
+    pub(super) status: Option<u16>,
+    pub(super) duration_ms: Option<u128>,
+}
+
+#[derive(Clone)]
+pub(super) struct RequestLogger {
+    structured: bool,
+}
+
+impl RequestLogger {
+    pub(super) fn new(structured: bool) -> Self {
+        Self { structured }
+    }
+
+    /// Logs the line of a request when the structured logs are enabled
+    pub(super) fn log(&self, line: &RequestLine<'_>) {
+        if self.structured {
+            match serde_json::to_string(line) {
+                Ok(line) => log::info!(target: "docs_rs::requests", "{}", line),
+                Err(err) => log::error!("failed to serialize the request log line: {}", err),
+            }
+        }
+    }
+
+    fn finish(&self, req: &Request, status: Option<Status>, headers: &mut Headers) {
+        let id = match req.extensions.get::<RequestId>() {
+            Some(id) => id,
+            None => return,
+        };
+        headers.set_raw(HEADER, vec![id.clone().into_bytes()]);
+
+        self.log(&RequestLine {
+            request_id: id,
+            method: req.method.to_string(),
+            path: format!("/{}", req.url.path().join("/")),
+            route: req.extensions.get::<RouteName>().map(String::as_str),
+            status: status.map(|status| status.to_u16()),
+            duration_ms: req
+                .extensions
+                .get::<RequestStart>()
+                .map(|start| start.elapsed().as_millis()),
+        });
+
+        CURRENT.with(|current| current.borrow_mut().take());
+    }
+}
+
+impl BeforeMiddleware for RequestLogger {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let id = request_id(&req.headers);
+        CURRENT.with(|current| *current.borrow_mut() = Some(id.clone()));
+        req.extensions.insert::<RequestId>(id);
+        req.extensions.insert::<RequestStart>(Instant::now());
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for RequestLogger {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.finish(req, res.status, &mut res.headers);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        let status = err.response.status;
+        self.finish(req, status, &mut err.response.headers);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn incoming_ids() {
+        let mut headers = Headers::new();
+        assert_eq!(incoming_id(&headers), None);
+
+        headers.set_raw(HEADER, vec![b"abc-123_x.y".to_vec()]);
+        assert_eq!(incoming_id(&headers).as_deref(), Some("abc-123_x.y"));
+
+        for invalid in &["", "with space", "<script>", &"a".repeat(65)] {
+            headers.set_raw(HEADER, vec![invalid.as_bytes().to_vec()]);
+            assert_eq!(incoming_id(&headers), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn generated_ids() {
+        let id = generate_id();
+        assert_eq!(id.len(), 32);
+        assert_ne!(id, generate_id());
+    }
+
+    #[test]
+    fn request_id_header() {
+        wrapper(|env| {
+            let web = env.frontend();
+
+            let resp = web.get("/").send()?;
+            let id = resp.headers().get(HEADER).unwrap().to_str()?;
+            assert_eq!(id.len(), 32);
+
+            let resp = web.get("/").header(HEADER, "from-the-cdn").send()?;
+            assert_eq!(resp.headers().get(HEADER).unwrap(), "from-the-cdn");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn request_id_on_error_pages() {
+        wrapper(|env| {
+            let resp = env
+                .frontend()
+                .get("/crate/not-a-crate")
+                .header(HEADER, "some-request")
+                .send()?;
+            assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+            assert_eq!(resp.headers().get(HEADER).unwrap(), "some-request");
+
+            let page = kuchiki::parse_html().one(resp.text()?);
+            let id = page
+                .select_first("#request-id")
+                .expect("missing request id");
+            assert_eq!(id.text_contents(), "some-request");
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,141 @@
+//! Resolution of item paths to the URL of their documentation
+//!
+//! `/api/v1/resolve?crate=serde&version=1.0&path=serde::Serialize` answers with the page of the
+//! item in the release matching the version, so that IDEs, compiler diagnostics and bots can link
+//! to the documentation without guessing the layout of rustdoc's output. The items are looked up
+//! in the index recorded by the build (see `utils::item_index`).
+
+use super::error::Nope;
+use super::{redirect_base, MatchSemver};
+use crate::db::Pool;
+use crate::utils::item_index::{page_path, ItemIndex};
+use crate::{Config, Storage, VersionCache};
+use iron::headers::{AccessControlAllowOrigin, ContentType};
+use iron::prelude::*;
+use iron::status;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct Resolved {
+    #[serde(rename = "crate")]
+    krate: String,
+    version: String,
+    /// The canonical path of the item, it differs from the requested one for re-exports
+    path: String,
+    kind: String,
+    url: String,
+}
+
+pub fn resolve_handler(req: &mut Request) -> IronResult<Response> {
+    let param = |name: &str| {
+        req.url
+            .as_ref()
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let (name, req_version, path) = match (param("crate"), param("version"), param("path")) {
+        (Some(name), version, Some(path)) => (name, version, path),
+        _ => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let mut conn = extension!(req, Pool).get()?;
+    let matched =
+        extension!(req, VersionCache).match_version(&mut conn, &name, req_version.as_deref())?;
+    // the name in the URLs is the one of the crate, not the one requested
+    let name = matched.corrected_name.unwrap_or(name);
+    let version = match matched.version {
+        MatchSemver::Exact((version, _)) | MatchSemver::Semver((version, _)) => version,
+    };
+
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let index = match ctry!(
+        req,
+        ItemIndex::load(storage, &name, &version, config.max_file_size)
+    ) {
+        Some(index) => index,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+    let (item_path, kind) = match index.resolve(&path) {
+        Some(item) => item,
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+    let page = cexpect!(req, page_path(item_path, kind));
+
+    let resolved = Resolved {
+        url: format!("{}/{}/{}/{}", redirect_base(req), name, version, page),
+        krate: name.clone(),
+        version: version.clone(),
+        path: item_path.to_string(),
+        kind: kind.to_string(),
+    };
+
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&resolved).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp.headers.set(AccessControlAllowOrigin::Any);
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use serde_json::json;
+
+    #[test]
+    fn resolve_paths() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("1.0.0")
+                .item("foo::ser", "module")
+                .item("foo::ser::Serialize", "trait")
+                .item("foo::ser::Error", "enum")
+                .item("foo::ser::Error::Custom", "variant")
+                .reexport("foo::Serialize", "foo::ser::Serialize")
+                .build()?;
+
+            let web = env.frontend();
+            let resolve = |query: &str| -> Result<serde_json::Value, failure::Error> {
+                Ok(web
+                    .get(&format!("/api/v1/resolve?{}", query))
+                    .send()?
+                    .error_for_status()?
+                    .json()?)
+            };
+            let base = format!("http://{}", web.server_addr());
+
+            assert_eq!(
+                resolve("crate=foo&version=1.0&path=foo::Serialize")?,
+                json!({
+                    "crate": "foo",
+                    "version": "1.0.0",
+                    "path": "foo::ser::Serialize",
+                    "kind": "trait",
+                    "url": format!("{}/foo/1.0.0/foo/ser/trait.Serialize.html", base),
+                })
+            );
+            assert_eq!(
+                resolve("crate=foo&path=foo::ser::Error::Custom")?["url"],
+                format!("{}/foo/1.0.0/foo/ser/enum.Error.html#variant.Custom", base)
+            );
+
+            for query in &[
+                "crate=foo&path=foo::Missing",
+                // `Error` isn't re-exported at the root
+                "crate=foo&path=foo::Error",
+                "crate=foo&version=2.0&path=foo::ser",
+                "crate=bar&path=bar::ser",
+                "crate=foo",
+            ] {
+                let status = web
+                    .get(&format!("/api/v1/resolve?{}", query))
+                    .send()?
+                    .status();
+                assert_eq!(status, 404, "{}", query);
+            }
+
+            Ok(())
+        });
+    }
+}
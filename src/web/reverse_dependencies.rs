@@ -0,0 +1,288 @@
+//! Crates depending on a given crate
+//!
+//! Only the latest release of each crate is considered, using the dependencies stored in the
+//! `release_dependencies` table when the releases were added.
+
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{error::Nope, page::WebPage, MetaData},
+    VersionCache,
+};
+use chrono::{DateTime, Utc};
+use iron::{
+    headers::{AccessControlAllowOrigin, ContentType},
+    status, IronResult, Request, Response,
+};
+use postgres::Client;
+use router::Router;
+use serde::Serialize;
+
+/// Number of dependents shown per page
+const DEPENDENTS_PER_PAGE: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Dependent {
+    name: String,
+    version: String,
+    description: Option<String>,
+    target_name: Option<String>,
+    rustdoc_status: bool,
+    release_time: DateTime<Utc>,
+    /// The requirements on the crate, a crate can depend on it more than once (e.g. as a normal
+    /// and as a dev-dependency)
+    reqs: Vec<String>,
+    /// `normal`, `dev` or `build`
+    kinds: Vec<String>,
+}
+
+/// Returns a page of the crates whose latest release depends on `name`, the most recently released
+/// first, and the total number of those crates
+fn get_dependents(
+    conn: &mut Client,
+    name: &str,
+    page: i64,
+    limit: i64,
+) -> Result<(Vec<Dependent>, i64), failure::Error> {
+    let offset = (page - 1) * limit;
+
+    let total: i64 = conn
+        .query_one(
+            "SELECT COUNT(DISTINCT release_dependencies.release_id)
+             FROM release_dependencies
+             INNER JOIN crates ON crates.latest_version_id = release_dependencies.release_id
+             WHERE release_dependencies.name = $1",
+            &[&name],
+        )?
+        .get(0);
+
+    let dependents = conn
+        .query(
+            "SELECT crates.name,
+                    releases.version,
+                    releases.description,
+                    releases.target_name,
+                    releases.rustdoc_status,
+                    releases.release_time,
+                    ARRAY_AGG(DISTINCT release_dependencies.req) AS reqs,
+                    ARRAY_AGG(DISTINCT release_dependencies.kind) AS kinds
+             FROM release_dependencies
+             INNER JOIN releases ON releases.id = release_dependencies.release_id
+             INNER JOIN crates ON crates.latest_version_id = releases.id
+             WHERE release_dependencies.name = $1
+             GROUP BY crates.name, releases.id
+             ORDER BY releases.release_time DESC, crates.name
+             LIMIT $2 OFFSET $3",
+            &[&name, &limit, &offset],
+        )?
+        .into_iter()
+        .map(|row| Dependent {
+            name: row.get("name"),
+            version: row.get("version"),
+            description: row.get("description"),
+            target_name: row.get("target_name"),
+            rustdoc_status: row.get("rustdoc_status"),
+            release_time: row.get("release_time"),
+            reqs: row.get("reqs"),
+            kinds: row.get("kinds"),
+        })
+        .collect();
+
+    Ok((dependents, total))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ReverseDependenciesPage {
+    metadata: MetaData,
+    dependents: Vec<Dependent>,
+    total: i64,
+    page_number: i64,
+    show_next_page: bool,
+    show_previous_page: bool,
+}
+
+impl_webpage! {
+    ReverseDependenciesPage = "crate/reverse_dependencies.html",
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ReverseDependenciesJson {
+    name: String,
+    total: i64,
+    page: i64,
+    per_page: i64,
+    dependents: Vec<Dependent>,
+}
+
+pub fn reverse_dependencies_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("name"));
+    let page_number: i64 = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "page")
+        .and_then(|(_, page)| page.parse().ok())
+        .filter(|&page| page >= 1)
+        .unwrap_or(1);
+
+    let mut conn = extension!(req, Pool).get()?;
+
+    let is_json = req
+        .url
+        .path()
+        .last()
+        .is_some_and(|segment| segment.ends_with(".json"));
+
+    let matched = extension!(req, VersionCache).match_version(&mut conn, name, None)?;
+    if let Some(canonical_name) = matched.corrected_name {
+        return super::permanent_crate_redirect(req, name, &canonical_name);
+    }
+    let (version, _) = matched.version.into_parts();
+
+    let (dependents, total) = ctry!(
+        req,
+        get_dependents(&mut conn, name, page_number, DEPENDENTS_PER_PAGE)
+    );
+    if dependents.is_empty() && page_number != 1 {
+        return Err(Nope::ResourceNotFound.into());
+    }
+
+    if is_json {
+        let json = ReverseDependenciesJson {
+            name: name.to_owned(),
+            total,
+            page: page_number,
+            per_page: DEPENDENTS_PER_PAGE,
+            dependents,
+        };
+        let mut resp = Response::with((status::Ok, serde_json::to_string(&json).unwrap()));
+        resp.headers.set(ContentType::json());
+        resp.headers.set(AccessControlAllowOrigin::Any);
+
+        Ok(resp)
+    } else {
+        ReverseDependenciesPage {
+            metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, &version)),
+            show_next_page: page_number * DEPENDENTS_PER_PAGE < total,
+            show_previous_page: page_number != 1,
+            dependents,
+            total,
+            page_number,
+        }
+        .into_response(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::*;
+    use kuchiki::traits::TendrilSink;
+    use serde_json::Value;
+
+    #[test]
+    fn dependents() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[])
+                .create()?;
+            // only the latest release of each crate counts
+            env.fake_release()
+                .name("old")
+                .version("0.1.0")
+                .dependencies(&[("foo", "^0.1", "normal")])
+                .create()?;
+            env.fake_release()
+                .name("old")
+                .version("0.2.0")
+                .dependencies(&[])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("1.0.0")
+                .release_time(chrono::Utc::now() - chrono::Duration::days(1))
+                .dependencies(&[("foo", "^0.1", "normal"), ("foo", "0.1.0", "dev")])
+                .create()?;
+            env.fake_release()
+                .name("baz")
+                .version("2.0.0")
+                .dependencies(&[("foo", "^0.1", "build"), ("other", "^1", "normal")])
+                .create()?;
+
+            let web = env.frontend();
+            let json: Value = web.get("/crate/foo/reverse-deps.json").send()?.json()?;
+            assert_eq!(json["name"], "foo");
+            assert_eq!(json["total"], 2);
+            let dependents = json["dependents"].as_array().unwrap();
+            assert_eq!(dependents.len(), 2);
+            // the most recent releases come first
+            assert_eq!(dependents[0]["name"], "baz");
+            assert_eq!(dependents[0]["kinds"], serde_json::json!(["build"]));
+            assert_eq!(dependents[1]["name"], "bar");
+            assert_eq!(dependents[1]["reqs"], serde_json::json!(["0.1.0", "^0.1"]));
+            assert_eq!(dependents[1]["kinds"], serde_json::json!(["dev", "normal"]));
+
+            let page =
+                kuchiki::parse_html().one(web.get("/crate/foo/reverse-deps").send()?.text()?);
+            let names: Vec<_> = page
+                .select(".recent-releases-container li .name")
+                .unwrap()
+                .map(|name| name.text_contents().trim().to_owned())
+                .collect();
+            assert_eq!(names, vec!["baz-2.0.0", "bar-1.0.0"]);
+
+            // the dependencies are stored with the name of the crate
+            assert_redirect(
+                "/crate/Foo/reverse-deps.json?page=1",
+                "/crate/foo/reverse-deps.json?page=1",
+                web,
+            )?;
+
+            // `other` isn't on docs.rs
+            assert_eq!(web.get("/crate/other/reverse-deps").send()?.status(), 404);
+            assert_eq!(
+                web.get("/crate/other/reverse-deps.json").send()?.status(),
+                404
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn pagination() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .dependencies(&[])
+                .create()?;
+            for i in 0..(super::DEPENDENTS_PER_PAGE + 5) {
+                env.fake_release()
+                    .name(&format!("dependent-{}", i))
+                    .version("1.0.0")
+                    .dependencies(&[("foo", "^0.1", "normal")])
+                    .create()?;
+            }
+
+            let web = env.frontend();
+            let json: Value = web
+                .get("/crate/foo/reverse-deps.json?page=2")
+                .send()?
+                .json()?;
+            assert_eq!(json["total"], super::DEPENDENTS_PER_PAGE + 5);
+            assert_eq!(json["dependents"].as_array().unwrap().len(), 5);
+
+            let page = web.get("/crate/foo/reverse-deps").send()?.text()?;
+            assert!(page.contains("href=\"/crate/foo/reverse-deps?page=2\""));
+            assert_eq!(
+                web.get("/crate/foo/reverse-deps?page=3").send()?.status(),
+                404
+            );
+
+            Ok(())
+        })
+    }
+}
@@ -1,5 +1,8 @@
+use super::admin_api::{AdminApi, AdminScope, ApiHandler};
+#[cfg(feature = "hyper-server")]
+use super::compat::{AsyncHandler, AsyncRouter};
 use super::metrics::RequestRecorder;
-use iron::middleware::Handler;
+use iron::{method::Method, middleware::Handler};
 use router::Router;
 use std::collections::HashSet;
 
@@ -29,8 +32,24 @@ pub(super) fn build_routes() -> Routes {
         PermanentRedirect("/-/static/opensearch.xml"),
     );
 
+    // Served by the hyper server when the `hyper-server` feature is enabled, Iron serves the shared
+    // resources before trying the router too
+    #[cfg(feature = "hyper-server")]
+    routes.async_resource(
+        "/*path",
+        "shared resource",
+        super::rustdoc::shared_resource_async,
+    );
+
     routes.static_resource("/-/static/:single", super::statics::static_handler);
     routes.static_resource("/-/static/*", super::statics::static_handler);
+    routes.static_resource("/-/health", super::health::health_handler);
+    routes.static_resource("/-/ready", super::health::ready_handler);
+    #[cfg(feature = "graphql")]
+    {
+        routes.static_resource("/-/graphql", super::graphql::graphql_handler);
+        routes.post_resource("/-/graphql", super::graphql::graphql_handler);
+    }
     routes.internal_page("/-/storage-change-detection.html", {
         #[derive(Debug, serde::Serialize)]
         struct StorageChangeDetection {}
@@ -46,15 +65,145 @@ pub(super) fn build_routes() -> Routes {
     routes.internal_page("/about", super::sitemap::about_handler);
     routes.internal_page("/about/metrics", super::metrics::metrics_handler);
     routes.internal_page("/about/builds", super::sitemap::about_builds_handler);
+    routes.static_resource("/about/builds.json", super::sitemap::about_builds_handler);
+    routes.internal_page("/about/jobs", super::sitemap::about_jobs_handler);
     routes.internal_page("/about/:subpage", super::sitemap::about_handler);
 
+    routes.internal_page("/settings", super::settings::settings_handler);
+    routes.post_resource("/settings", super::settings::save_settings_handler);
+
     routes.internal_page("/releases", super::releases::recent_releases_handler);
     routes.static_resource("/releases/feed", super::releases::releases_feed_handler);
-    routes.internal_page("/releases/:owner", super::releases::owner_handler);
-    routes.internal_page("/releases/:owner/:page", super::releases::owner_handler);
+    routes.post_resource(
+        "/releases/feed/hub",
+        super::releases::releases_feed_hub_handler,
+    );
+    routes.internal_page(
+        "/releases/:owner",
+        super::owners::releases_owner_redirect_handler,
+    );
+    routes.internal_page(
+        "/releases/:owner/:page",
+        super::owners::releases_owner_redirect_handler,
+    );
+    routes.internal_page("/owners/:login", super::owners::owner_handler);
     routes.internal_page("/releases/activity", super::releases::activity_handler);
     routes.internal_page("/releases/search", super::releases::search_handler);
+    routes.static_resource("/releases/search.json", super::releases::search_handler);
+    routes.static_resource("/api/v1/resolve", super::resolve::resolve_handler);
+    routes.static_resource("/api/v1/diff", super::diff::diff_api_handler);
+    routes.static_resource("/api/v1/exports", super::exports::manifest_handler);
+    routes.static_resource(
+        "/api/v1/exports/:date/:file",
+        super::exports::download_handler,
+    );
     routes.internal_page("/releases/queue", super::releases::build_queue_handler);
+    routes.static_resource(
+        "/releases/queue/events",
+        super::releases::queue_events_handler,
+    );
+    routes.internal_page("/releases/rebuilds", super::releases::rebuilds_handler);
+    routes.internal_page(
+        "/releases/consistency",
+        super::releases::consistency_handler,
+    );
+    routes.internal_page("/releases/storage", super::releases::storage_handler);
+    routes.internal_page("/admin/audit", super::admin::audit_handler);
+    routes.internal_page("/admin/limits", super::admin::limits_handler);
+    routes.post_resource("/admin/limits", super::admin::save_limits_handler);
+    routes.internal_page("/admin/crates", super::admin::crates_handler);
+    routes.post_resource("/admin/crates", super::admin::save_crates_handler);
+    routes.static_resource("/admin/login", super::admin_login::login_handler);
+    routes.static_resource(
+        "/admin/login/callback",
+        super::admin_login::login_callback_handler,
+    );
+    routes.static_resource("/admin/logout", super::admin_login::logout_handler);
+    routes.admin_api(
+        Method::Post,
+        "/admin/api/queue/:name/:version",
+        AdminScope::Queue,
+        super::admin_api::queue_handler,
+    );
+    routes.admin_api(
+        Method::Get,
+        "/admin/api/blacklist",
+        AdminScope::Blacklist,
+        super::admin_api::blacklist_handler,
+    );
+    routes.admin_api(
+        Method::Post,
+        "/admin/api/blacklist/:name",
+        AdminScope::Blacklist,
+        super::admin_api::blacklist_add_handler,
+    );
+    routes.admin_api(
+        Method::Delete,
+        "/admin/api/blacklist/:name",
+        AdminScope::Blacklist,
+        super::admin_api::blacklist_remove_handler,
+    );
+    routes.admin_api(
+        Method::Delete,
+        "/admin/api/crates/:name",
+        AdminScope::Delete,
+        super::admin_api::delete_handler,
+    );
+    routes.admin_api(
+        Method::Delete,
+        "/admin/api/crates/:name/:version",
+        AdminScope::Delete,
+        super::admin_api::delete_handler,
+    );
+    routes.admin_api(
+        Method::Get,
+        "/admin/api/limits",
+        AdminScope::Limits,
+        super::admin_api::limits_handler,
+    );
+    routes.admin_api(
+        Method::Get,
+        "/admin/api/limits/:name",
+        AdminScope::Limits,
+        super::admin_api::crate_limits_handler,
+    );
+    routes.admin_api(
+        Method::Post,
+        "/admin/api/limits/:name",
+        AdminScope::Limits,
+        super::admin_api::set_limits_handler,
+    );
+    routes.admin_api(
+        Method::Delete,
+        "/admin/api/limits/:name",
+        AdminScope::Limits,
+        super::admin_api::remove_limits_handler,
+    );
+    routes.admin_api(
+        Method::Get,
+        "/admin/api/redirects",
+        AdminScope::Redirects,
+        super::admin_api::redirects_handler,
+    );
+    routes.admin_api(
+        Method::Post,
+        "/admin/api/redirects/:name",
+        AdminScope::Redirects,
+        super::admin_api::set_redirect_handler,
+    );
+    routes.admin_api(
+        Method::Delete,
+        "/admin/api/redirects/:name",
+        AdminScope::Redirects,
+        super::admin_api::remove_redirect_handler,
+    );
+    routes.admin_api(
+        Method::Post,
+        "/admin/api/templates/reload",
+        AdminScope::Templates,
+        super::admin_api::reload_templates_handler,
+    );
+    routes.internal_page("/releases/recent", super::releases::recent_releases_handler);
     routes.internal_page(
         "/releases/recent/:page",
         super::releases::recent_releases_handler,
@@ -67,6 +216,14 @@ pub(super) fn build_routes() -> Routes {
         "/releases/stars/:page",
         super::releases::releases_by_stars_handler,
     );
+    routes.internal_page(
+        "/releases/popular",
+        super::releases::popular_releases_handler,
+    );
+    routes.internal_page(
+        "/releases/trending",
+        super::releases::trending_releases_handler,
+    );
     routes.internal_page(
         "/releases/recent-failures",
         super::releases::releases_recent_failures_handler,
@@ -97,14 +254,71 @@ pub(super) fn build_routes() -> Routes {
         "/crate/:name/:version/builds.json",
         super::builds::build_list_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/citation.bib",
+        super::crate_details::citation_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/Cargo.lock",
+        super::crate_details::lockfile_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/json",
+        super::crate_details::rustdoc_json_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/json/:target",
+        super::crate_details::rustdoc_json_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/doc-size.json",
+        super::crate_details::doc_size_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/status.json",
+        super::crate_details::status_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/builds/current/log",
+        super::builds::running_build_log_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/builds/:id",
         super::build_details::build_details_handler,
     );
+    routes.internal_page(
+        "/crate/:name/reverse-deps",
+        super::reverse_dependencies::reverse_dependencies_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/reverse-deps.json",
+        super::reverse_dependencies::reverse_dependencies_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/views.json",
+        super::crate_details::page_views_handler,
+    );
+    routes.internal_page("/crate/:name/diff/:versions", super::diff::diff_handler);
+    routes.internal_page(
+        "/crate/:name/:version/diff",
+        super::diff::previous_diff_handler,
+    );
+    routes.internal_page(
+        "/crate/:name/:version/deps",
+        super::dependencies::dependencies_handler,
+    );
+    routes.static_resource(
+        "/crate/:name/:version/deps.json",
+        super::dependencies::dependencies_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/features",
         super::features::build_features_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/source.tar.gz",
+        super::source::source_tarball_handler,
+    );
     routes.internal_page(
         "/crate/:name/:version/source",
         SimpleRedirect::new(|url| url.set_path(&format!("{}/", url.path()))),
@@ -141,6 +355,14 @@ pub(super) fn build_routes() -> Routes {
         "/:crate/:version/all.html",
         super::rustdoc::rustdoc_html_server_handler,
     );
+    routes.rustdoc_page(
+        "/:crate/:version/implementors/*",
+        super::rustdoc::rustdoc_asset_handler,
+    );
+    routes.rustdoc_page(
+        "/:crate/:version/:target/implementors/*",
+        super::rustdoc::rustdoc_asset_handler,
+    );
     routes.rustdoc_page(
         "/:crate/:version/:target",
         super::rustdoc::rustdoc_redirector_handler,
@@ -178,9 +400,17 @@ pub(super) struct Routes {
     /// GET routes serving rustdoc content. The BlockBlacklistedPrefixes middleware is added
     /// automatically to all of them.
     rustdoc_get: Vec<(String, Box<dyn Handler>)>,
+    /// Normal POST routes.
+    post: Vec<(String, Box<dyn Handler>)>,
+    /// DELETE routes, only used by the admin API.
+    delete: Vec<(String, Box<dyn Handler>)>,
     /// Prefixes of all the internal routes. This data is used to power the
     /// BlockBlacklistedPrefixes middleware.
     page_prefixes: HashSet<String>,
+    /// GET routes ported to async, served by the hyper server before forwarding the requests to
+    /// Iron.
+    #[cfg(feature = "hyper-server")]
+    async_get: Vec<(String, String, AsyncHandler)>,
 }
 
 impl Routes {
@@ -188,7 +418,11 @@ impl Routes {
         Self {
             get: Vec::new(),
             rustdoc_get: Vec::new(),
+            post: Vec::new(),
+            delete: Vec::new(),
             page_prefixes: HashSet::new(),
+            #[cfg(feature = "hyper-server")]
+            async_get: Vec::new(),
         }
     }
 
@@ -201,6 +435,20 @@ impl Routes {
         for (pattern, handler) in self.get.drain(..) {
             router.get(&pattern, handler, calculate_id(&pattern));
         }
+        for (pattern, handler) in self.post.drain(..) {
+            router.post(
+                &pattern,
+                handler,
+                format!("post:{}", calculate_id(&pattern)),
+            );
+        }
+        for (pattern, handler) in self.delete.drain(..) {
+            router.delete(
+                &pattern,
+                handler,
+                format!("delete:{}", calculate_id(&pattern)),
+            );
+        }
 
         // All rustdoc pages have the prefixes of other docs.rs pages blacklisted. This prevents,
         // for example, a crate named "about" from hijacking /about/0.1.0/index.html.
@@ -216,6 +464,24 @@ impl Routes {
         router
     }
 
+    #[cfg(feature = "hyper-server")]
+    pub(super) fn async_router(mut self) -> AsyncRouter {
+        let mut router = AsyncRouter::new();
+        for (pattern, route, handler) in self.async_get.drain(..) {
+            router.add(&pattern, route, handler);
+        }
+        router
+    }
+
+    /// Routes ported to async are only served by the hyper server, their Iron handler has to be
+    /// registered separately as long as Iron can be used without it. When the async handler
+    /// doesn't answer, the request is forwarded to Iron.
+    #[cfg(feature = "hyper-server")]
+    fn async_resource(&mut self, pattern: &str, route: &str, handler: AsyncHandler) {
+        self.async_get
+            .push((pattern.to_string(), route.to_string(), handler));
+    }
+
     /// A static resource is a normal page without any special behavior on the router side.
     fn static_resource(&mut self, pattern: &str, handler: impl Handler) {
         self.get.push((
@@ -224,6 +490,32 @@ impl Routes {
         ));
     }
 
+    /// Same as a static resource, but answering POST requests.
+    fn post_resource(&mut self, pattern: &str, handler: impl Handler) {
+        self.post.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(handler, "static resource")),
+        ));
+    }
+
+    /// Endpoints of the admin API answer with JSON, and only to the requests authenticated with
+    /// a token that has the `scope` of the endpoint.
+    fn admin_api(&mut self, method: Method, pattern: &str, scope: AdminScope, handler: ApiHandler) {
+        let routes = match method {
+            Method::Get => &mut self.get,
+            Method::Post => &mut self.post,
+            Method::Delete => &mut self.delete,
+            _ => panic!("unsupported method for the admin API: {}", method),
+        };
+        routes.push((
+            pattern.to_string(),
+            Box::new(RequestRecorder::new(
+                AdminApi::new(scope, handler),
+                "admin api",
+            )),
+        ));
+    }
+
     /// Internal pages are docs.rs's own pages, instead of the documentation of a crate uploaded by
     /// an user. The router adds these extra things when adding a new internal page:
     ///
@@ -1,25 +1,46 @@
 //! rustdoc handler
 
+#[cfg(feature = "hyper-server")]
+use crate::web::compat::{AsyncRequest, AsyncResponse, AsyncState};
 use crate::{
-    db::Pool,
+    db::{advisories, dependency_links, page_views::PageViews, Pool},
+    impl_webpage,
     repositories::RepositoryStatsUpdater,
-    utils,
+    utils::{
+        asset_integrity::{AssetHashes, AssetIntegrity},
+        retention, AdvisoriesBanner, CanonicalLink, DocsRsLayout, HtmlRewriter, StaleLayout,
+        SubresourceIntegrity, YankedBanner,
+    },
     web::{
-        crate_details::CrateDetails, csp::Csp, error::Nope, file::File, match_version,
-        metrics::RenderingTimesRecorder, redirect_base, MatchSemver, MetaData,
+        crate_details::CrateDetails,
+        csp::Csp,
+        error::Nope,
+        file::{Download, File},
+        metrics::{RenderingTimesRecorder, RouteName},
+        page::WebPage,
+        permanent_crate_redirect, redirect_base, renamed_crate_redirect,
+        settings::Settings,
+        MatchSemver, MetaData,
     },
-    Config, Metrics, Storage,
+    Config, Metrics, Storage, VersionCache,
 };
 use iron::url::percent_encoding::percent_decode;
 use iron::{
-    headers::{CacheControl, CacheDirective, Expires, HttpDate},
+    headers::{
+        AcceptEncoding, CacheControl, CacheDirective, ContentEncoding, Encoding, Expires, HttpDate,
+        LastModified, Quality, Referer,
+    },
     modifiers::Redirect,
     status, Handler, IronResult, Request, Response, Url,
 };
 use lol_html::errors::RewritingError;
+use postgres::Client;
 use router::Router;
+use semver::Version;
 use serde::Serialize;
 use std::path::Path;
+#[cfg(feature = "hyper-server")]
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct RustLangRedirector {
@@ -73,6 +94,8 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
         let url = ctry!(req, Url::parse(&url_str));
         let mut resp = Response::with((status::Found, Redirect(url)));
         resp.headers.set(Expires(HttpDate(time::now())));
+        // the target depends on the settings
+        resp.headers.set_raw("Vary", vec![b"Cookie".to_vec()]);
 
         Ok(resp)
     }
@@ -106,9 +129,9 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
         // javascript files should be handled by the file server instead of erroneously
         // redirecting to the crate root page
         if req.url.as_ref().path_segments().unwrap().count() > 2 {
-            // this URL is actually from a crate-internal path, serve it there instead
+            // this URL is actually from a crate-internal path, like the search index
             rendering_time.step("serve JS for crate");
-            return rustdoc_html_server_handler(req);
+            return rustdoc_asset_handler(req);
         } else {
             rendering_time.step("serve JS");
             let storage = extension!(req, Storage);
@@ -117,7 +140,7 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
             let path = req.url.path();
             let path = path.join("/");
             return match File::from_path(storage, &path, config) {
-                Ok(f) => Ok(f.serve()),
+                Ok(f) => Ok(f.serve(&req.headers)),
                 Err(..) => Err(Nope::ResourceNotFound.into()),
             };
         }
@@ -140,45 +163,64 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
 
     // this handler should never called without crate pattern
     let crate_name = cexpect!(req, router.find("crate"));
-    let mut crate_name = percent_decode(crate_name.as_bytes())
+    let crate_name = percent_decode(crate_name.as_bytes())
         .decode_utf8()
         .unwrap_or_else(|_| crate_name.into())
         .into_owned();
     let req_version = router.find("version");
     let mut target = router.find("target");
 
+    // the curated redirects of the renamed crates take precedence over the dash/underscore
+    // correction of `match_version`
+    rendering_time.step("check crate redirects");
+    if let Some(redirect) = ctry!(req, renamed_crate_redirect(req, &mut conn, &crate_name)) {
+        return Ok(redirect);
+    }
+
     // it doesn't matter if the version that was given was exact or not, since we're redirecting
     // anyway
     rendering_time.step("match version");
-    let v = match_version(&mut conn, &crate_name, req_version)?;
-    if let Some(new_name) = v.corrected_name {
-        // `match_version` checked against -/_ typos, so if we have a name here we should
-        // use that instead
-        crate_name = new_name;
+    let v = extension!(req, VersionCache).match_version(&mut conn, &crate_name, req_version)?;
+    if let Some(canonical_name) = v.corrected_name {
+        // `match_version` checked against -/_ typos, only the name on crates.io is served
+        return permanent_crate_redirect(req, &crate_name, &canonical_name);
     }
     let (version, id) = v.version.into_parts();
 
     // get target name and whether it has docs
     // FIXME: This is a bit inefficient but allowing us to use less code in general
     rendering_time.step("fetch release doc status");
-    let (target_name, has_docs): (String, bool) = {
+    let (target_name, has_docs, default_target, doc_targets): (String, bool, String, Vec<String>) = {
         let rows = ctry!(
             req,
             conn.query(
-                "SELECT target_name, rustdoc_status
+                "SELECT target_name, rustdoc_status, default_target, doc_targets
                  FROM releases
                  WHERE releases.id = $1",
                 &[&id]
             ),
         );
 
-        (rows[0].get(0), rows[0].get(1))
+        (
+            rows[0].get(0),
+            rows[0].get(1),
+            rows[0].get(2),
+            MetaData::parse_doc_targets(rows[0].get(3)),
+        )
     };
 
     if target == Some("index.html") || target == Some(&target_name) {
         target = None;
     }
 
+    // open the documentation of the target preferred in the settings when no target was asked
+    let settings = Settings::from_request(req);
+    if target.is_none() {
+        target = settings.default_target.as_deref().filter(|&preferred| {
+            preferred != default_target && doc_targets.iter().any(|target| target == preferred)
+        });
+    }
+
     if has_docs {
         rendering_time.step("redirect to doc");
         redirect_to_doc(req, &crate_name, &version, target, &target_name)
@@ -196,6 +238,21 @@ struct RustdocPage {
     inner_path: String,
     is_latest_version: bool,
     is_prerelease: bool,
+    /// Adds a `robots` meta tag keeping search engines from indexing the page
+    #[serde(skip)]
+    noindex: bool,
+    /// The same page in the latest release
+    #[serde(skip)]
+    canonical_url: String,
+    /// The hashes of the shared rustdoc assets, by file name
+    #[serde(skip)]
+    asset_hashes: AssetHashes,
+    /// Set when the release is yanked
+    #[serde(skip)]
+    yanked_banner: Option<YankedBanner>,
+    /// Set when security advisories affect the release
+    #[serde(skip)]
+    advisories_banner: Option<AdvisoriesBanner>,
     krate: CrateDetails,
     metadata: MetaData,
 }
@@ -220,11 +277,33 @@ impl RustdocPage {
             .get::<crate::Metrics>()
             .expect("missing Metrics from the request extensions");
 
-        // Build the page of documentation
-        let ctx = ctry!(req, tera::Context::from_serialize(self));
+        // Build the page of documentation, the scripts docs.rs adds to it are ready for a CSP
+        let mut ctx = ctry!(req, tera::Context::from_serialize(&self));
+        ctx.insert(
+            "csp_nonce",
+            req.extensions.get::<Csp>().expect("missing CSP").nonce(),
+        );
+        let layout = ctry!(req, DocsRsLayout::render(templates, &ctx));
+        let mut rewriter = HtmlRewriter::new(max_parse_memory)
+            .pass(StaleLayout)
+            .pass(layout);
+        if let Some(banner) = self.yanked_banner.clone() {
+            rewriter = rewriter.pass(banner);
+        }
+        if let Some(banner) = self.advisories_banner.clone() {
+            rewriter = rewriter.pass(banner);
+        }
+        let rewriter = rewriter
+            .pass(CanonicalLink {
+                url: &self.canonical_url,
+                noindex: self.noindex,
+            })
+            .pass(SubresourceIntegrity {
+                hashes: &self.asset_hashes,
+            });
         // Extract the head and body of the rustdoc file so that we can insert it into our own html
         // while logging OOM errors from html rewriting
-        let html = match utils::rewrite_lol(rustdoc_html, max_parse_memory, ctx, templates) {
+        let html = match rewriter.rewrite(rustdoc_html, metrics) {
             Err(RewritingError::MemoryLimitExceeded(..)) => {
                 metrics.html_rewrite_ooms.inc();
 
@@ -246,10 +325,121 @@ impl RustdocPage {
     }
 }
 
+/// Shown instead of a generic 404 when the release has no documentation, rendered by `error.html`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct MissingDocsPage {
+    title: String,
+    message: &'static str,
+    crate_name: String,
+    build_log_url: String,
+    last_successful_build: Option<String>,
+}
+
+impl_webpage! {
+    MissingDocsPage = "error.html",
+    status = |_| status::NotFound,
+}
+
+impl MissingDocsPage {
+    fn new(conn: &mut Client, krate: &CrateDetails, name: &str, version: &str) -> Self {
+        let (title, message) = if krate.build_status {
+            (
+                format!("{}-{} doesn't have any documentation", name, version),
+                "the build succeeded without generating documentation",
+            )
+        } else {
+            (
+                format!("docs.rs failed to build {}-{}", name, version),
+                "the documentation of this release couldn't be built",
+            )
+        };
+
+        // link to the log of the last build, or the list of builds if it can't be found
+        let last_build = conn.query_opt(
+            "SELECT id FROM builds WHERE rid = $1 ORDER BY id DESC LIMIT 1",
+            &[&krate.release_id],
+        );
+        let build_log_url = match last_build {
+            Ok(Some(row)) => format!(
+                "/crate/{}/{}/builds/{}",
+                name,
+                version,
+                row.get::<_, i32>(0)
+            ),
+            Ok(None) => format!("/crate/{}/{}/builds", name, version),
+            Err(err) => {
+                log::error!(
+                    "failed to look up the last build of {} {}: {}",
+                    name,
+                    version,
+                    err
+                );
+                format!("/crate/{}/{}/builds", name, version)
+            }
+        };
+
+        Self {
+            title,
+            message,
+            crate_name: name.to_string(),
+            build_log_url,
+            last_successful_build: krate.last_successful_build.clone(),
+        }
+    }
+}
+
+/// The release replacing `version` when it's a pre-release retired by the retention policy, see
+/// `utils::retention`
+fn retired_release_replacement(
+    conn: &mut Client,
+    name: &str,
+    version: Option<&str>,
+) -> Option<String> {
+    // only the exact versions of pre-releases can be retired, no need to look up the others
+    let version = version?;
+    if Version::parse(version).ok()?.pre.is_empty() {
+        return None;
+    }
+    match retention::superseded_by(conn, name, version) {
+        Ok(replacement) => replacement,
+        Err(err) => {
+            log::error!(
+                "failed to look up the retired release {} {}: {}",
+                name,
+                version,
+                err
+            );
+            None
+        }
+    }
+}
+
+/// The version with documentation the links to an unbuilt version of a dependency are redirected
+/// to, see `db::dependency_links`
+fn unbuilt_dependency_replacement(
+    conn: &mut Client,
+    name: &str,
+    version: Option<&str>,
+) -> Option<String> {
+    let version = version?;
+    match dependency_links::replacement(conn, name, version) {
+        Ok(replacement) => replacement,
+        Err(err) => {
+            log::error!(
+                "failed to look up the replacement of the dependency {} {}: {}",
+                name,
+                version,
+                err
+            );
+            None
+        }
+    }
+}
+
 /// Serves documentation generated by rustdoc.
 ///
-/// This includes all HTML files for an individual crate, as well as the `search-index.js`, which is
-/// also crate-specific.
+/// This includes all HTML files for an individual crate. The crate-specific scripts, like the
+/// `search-index.js`, are served by [`rustdoc_asset_handler`].
 pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     let metrics = extension!(req, Metrics).clone();
     let mut rendering_time = RenderingTimesRecorder::new(&metrics.rustdoc_rendering_times);
@@ -293,31 +483,46 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         Ok(super::redirect(url))
     };
 
+    if let Some(redirect) = ctry!(req, renamed_crate_redirect(req, &mut conn, &name)) {
+        return Ok(redirect);
+    }
+
     rendering_time.step("match version");
 
     // Check the database for releases with the requested version while doing the following:
     // * If no matching releases are found, return a 404 with the underlying error
     // Then:
     // * If both the name and the version are an exact match, return the version of the crate.
-    // * If there is an exact match, but the requested crate name was corrected (dashes vs. underscores), redirect to the corrected name.
+    // * If the requested crate name was corrected (dashes vs. underscores), permanently redirect to the corrected name.
     // * If there is a semver (but not exact) match, redirect to the exact version.
-    let release_found = match_version(&mut conn, &name, url_version)?;
-
-    let version = match release_found.version {
-        MatchSemver::Exact((version, _)) => {
-            // Redirect when the requested crate name isn't correct
-            if let Some(name) = release_found.corrected_name {
-                return redirect(&name, &version, &req_path);
+    let release_found =
+        match extension!(req, VersionCache).match_version(&mut conn, &name, url_version) {
+            Ok(release_found) => release_found,
+            Err(err) => {
+                let replacement = retired_release_replacement(&mut conn, &name, url_version)
+                    .or_else(|| unbuilt_dependency_replacement(&mut conn, &name, url_version));
+                return match replacement {
+                    Some(replacement) => redirect(&name, &replacement, &req_path),
+                    None => Err(err.into()),
+                };
             }
+        };
 
-            version
-        }
+    // Redirect when the requested crate name isn't correct
+    if let Some(canonical_name) = release_found.corrected_name {
+        return permanent_crate_redirect(req, &name, &canonical_name);
+    }
+
+    let yanked = release_found.yanked;
+    let version = match release_found.version {
+        MatchSemver::Exact((version, _)) => version,
 
         // Redirect when the requested version isn't correct
         MatchSemver::Semver((v, _)) => {
             // to prevent cloudfront caching the wrong artifacts on URLs with loose semver
             // versions, redirect the browser to the returned version instead of loading it
             // immediately
+            let v = retired_release_replacement(&mut conn, &name, url_version).unwrap_or(v);
             return redirect(&name, &v, &req_path);
         }
     };
@@ -330,12 +535,34 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     // NOTE: we know this crate must exist because we just checked it above (or else `match_version` is buggy)
     let krate = cexpect!(req, CrateDetails::new(&mut conn, &name, &version, updater));
 
+    if !krate.rustdoc_status {
+        // the documentation of the archived pre-releases is served by the release superseding them
+        if let Some(replacement) = retired_release_replacement(&mut conn, &name, Some(&version)) {
+            return redirect(&name, &replacement, &req_path);
+        }
+        // the links from the documentation of the crates depending on this release go to the
+        // nearest release with documentation, the visitors are told why there's none
+        if is_internal_referer(req) {
+            if let Some(replacement) =
+                unbuilt_dependency_replacement(&mut conn, &name, Some(&version))
+            {
+                return redirect(&name, &replacement, &req_path);
+            }
+        }
+    }
+
     // if visiting the full path to the default target, remove the target from the path
     // expects a req_path that looks like `[/:target]/.*`
     if req_path.get(0).copied() == Some(&krate.metadata.default_target) {
         return redirect(&name, &version, &req_path[1..]);
     }
 
+    // explain why there's no documentation instead of answering with a generic 404
+    if !krate.rustdoc_status {
+        rendering_time.step("serve missing docs page");
+        return MissingDocsPage::new(&mut conn, &krate, &name, &version).into_response(req);
+    }
+
     rendering_time.step("fetch from storage");
 
     // Add rustdoc prefix, name and version to the path for accessing the file stored in the database
@@ -353,8 +580,12 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     let mut path = ctry!(req, percent_decode(path.as_bytes()).decode_utf8());
 
     // Attempt to load the file from the database
-    let file = match File::from_path(storage, &path, config) {
-        Ok(file) => file,
+    let file = match File::download(storage, &path, config) {
+        Ok(Download::File(file)) => file,
+        Ok(download @ Download::Redirect(_)) => {
+            rendering_time.step("serve asset");
+            return Ok(download.serve(&req.headers));
+        }
         Err(err) => {
             log::debug!("got error serving {}: {}", path, err);
             // If it fails, we try again with /index.html at the end
@@ -363,6 +594,19 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
 
             return if ctry!(req, storage.exists(&path)) {
                 redirect(&name, &version, &req_path[3..])
+            } else if krate.no_source && req_path.get(3).copied() == Some("src") {
+                // the source pages were left out of the documentation, the sources are still in
+                // the source browser
+                let url = ctry!(
+                    req,
+                    Url::parse(&format!(
+                        "{}/crate/{}/{}/source/",
+                        redirect_base(req),
+                        name,
+                        version
+                    )),
+                );
+                Ok(super::redirect(url))
             } else if req_path.get(3).map_or(false, |p| p.contains('-')) {
                 // This is a target, not a module; it may not have been built.
                 // Redirect to the default target and show a search page instead of a hard 404.
@@ -381,7 +625,7 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     if !path.ends_with(".html") {
         rendering_time.step("serve asset");
 
-        return Ok(file.serve());
+        return Ok(file.serve(&req.headers));
     }
 
     rendering_time.step("find latest path");
@@ -449,9 +693,80 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         latest_path.push_str(query);
     }
 
+    // the banner of the yanked releases links to the same page in the latest release that isn't
+    let yanked_banner = yanked.map(|yanked| YankedBanner {
+        name: name.clone(),
+        version: version.clone(),
+        latest: yanked.latest_version.map(|latest| {
+            let target = if target.is_empty() {
+                &krate.metadata.default_target
+            } else {
+                target
+            };
+            let url = format!(
+                "/crate/{}/{}/target-redirect/{}/{}",
+                name, latest, target, inner_path
+            );
+            (latest, url)
+        }),
+    });
+
+    if !is_latest_version
+        && latest_release.build_status
+        && Settings::from_request(req).redirect_to_latest
+        && !is_internal_referer(req)
+    {
+        rendering_time.step("redirect to latest version");
+        let url = ctry!(
+            req,
+            Url::parse(&format!("{}{}", redirect_base(req), latest_path)),
+        );
+        let mut response = super::redirect(url);
+        response.headers.set_raw("Vary", vec![b"Cookie".to_vec()]);
+        return Ok(response);
+    }
+
+    // the documentation is still served when the advisories can't be loaded
+    let advisories = advisories::affecting(&mut conn, &name, &version).unwrap_or_else(|err| {
+        log::error!(
+            "failed to load the advisories of {} {}: {}",
+            name,
+            version,
+            err
+        );
+        Vec::new()
+    });
+    let advisories_banner = if advisories.is_empty() {
+        None
+    } else {
+        Some(AdvisoriesBanner {
+            name: name.clone(),
+            version: version.clone(),
+            advisories,
+        })
+    };
+
     metrics
         .recently_accessed_releases
         .record(krate.crate_id, krate.release_id, target);
+    if config.page_view_stats && !do_not_track(req) {
+        extension!(req, PageViews).record(krate.release_id);
+    }
+
+    let metadata_headers = if config.rustdoc_metadata_headers {
+        Some(MetadataHeaders {
+            krate: name.clone(),
+            version: version.clone(),
+            target: if target.is_empty() {
+                krate.metadata.default_target.clone()
+            } else {
+                target.to_owned()
+            },
+            latest_version: latest_version.clone(),
+        })
+    } else {
+        None
+    };
 
     let target = if target.is_empty() {
         String::new()
@@ -459,18 +774,184 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         format!("{}/", target)
     };
 
+    // only the latest release of a crate is indexed, the canonical url of the other ones points
+    // to the same page in it
+    let noindex = config.noindex_outdated_releases && (!is_latest_version || krate.metadata.yanked);
+    let canonical_url = format!(
+        "{}/{}/latest/{}{}",
+        redirect_base(req),
+        name,
+        target,
+        inner_path.strip_suffix("index.html").unwrap_or(&inner_path)
+    );
+
+    // rebuilt documentation must be recrawled, even if the release itself is old
+    let last_modified = krate
+        .last_build_time
+        .map(|time| HttpDate(time::at_utc(time::Timespec::new(time.timestamp(), 0))));
+
+    let asset_hashes = extension!(req, AssetIntegrity).hashes(&mut conn);
+
     rendering_time.step("rewrite html");
-    RustdocPage {
+    let mut response = RustdocPage {
         latest_path,
         latest_version,
         target,
         inner_path,
         is_latest_version,
         is_prerelease,
+        noindex,
+        canonical_url: canonical_url.clone(),
+        asset_hashes,
+        yanked_banner,
+        advisories_banner,
         metadata: krate.metadata.clone(),
         krate,
     }
-    .into_response(&file.0.content, config.max_parse_memory, req, &path)
+    .into_response(&file.0.content, config.max_parse_memory, req, &path)?;
+
+    if let Some(headers) = metadata_headers {
+        headers.apply(&mut response);
+    }
+    if let Some(last_modified) = last_modified {
+        response.headers.set(LastModified(last_modified));
+    }
+    response.headers.set_raw(
+        "Link",
+        vec![format!("<{}>; rel=\"canonical\"", canonical_url).into_bytes()],
+    );
+    // the outdated releases redirect to the latest one depending on the settings
+    response.headers.set_raw("Vary", vec![b"Cookie".to_vec()]);
+    Ok(response)
+}
+
+/// Serves the scripts generated by rustdoc for a release, like the search index or the lists of
+/// implementors. They are the most requested files of the documentation, so they are served
+/// without loading the details of the crate, and compressed for the clients accepting it.
+pub fn rustdoc_asset_handler(req: &mut Request) -> IronResult<Response> {
+    let metrics = extension!(req, Metrics).clone();
+    let mut rendering_time = RenderingTimesRecorder::new(&metrics.rustdoc_rendering_times);
+
+    let router = extension!(req, Router);
+    let name = cexpect!(req, router.find("crate")).to_string();
+    let url_version = router.find("version");
+    let mut req_path = req.url.path();
+    req_path.drain(..2).for_each(drop);
+
+    let mut conn = extension!(req, Pool).get()?;
+    if let Some(redirect) = ctry!(req, renamed_crate_redirect(req, &mut conn, &name)) {
+        return Ok(redirect);
+    }
+
+    rendering_time.step("match version");
+    let release_found =
+        extension!(req, VersionCache).match_version(&mut conn, &name, url_version)?;
+    if let Some(canonical_name) = release_found.corrected_name {
+        return permanent_crate_redirect(req, &name, &canonical_name);
+    }
+    let version = match release_found.version {
+        MatchSemver::Exact((version, _)) => version,
+        // the assets of a version can be cached for a long time, the ones of `latest` can't
+        MatchSemver::Semver((version, _)) => {
+            let url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/{}/{}/{}",
+                    redirect_base(req),
+                    name,
+                    version,
+                    req_path.join("/")
+                )),
+            );
+            return Ok(super::redirect(url));
+        }
+    };
+
+    rendering_time.step("fetch from storage");
+    let path = format!("rustdoc/{}/{}/{}", name, version, req_path.join("/"));
+    let path = ctry!(req, percent_decode(path.as_bytes()).decode_utf8());
+    let storage = extension!(req, Storage);
+    let config = extension!(req, Config);
+    let mut file = match File::from_path(storage, &path, config) {
+        Ok(file) => file,
+        Err(_) => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    rendering_time.step("serve asset");
+    let gzip = accepts_gzip(req);
+    if gzip {
+        file.0.content = ctry!(req, gzip_compress(&file.0.content));
+        // the compressed file is a different representation, with its own entity tag
+        if let Some(hash) = &mut file.0.content_hash {
+            hash.push_str("-gzip");
+        }
+    }
+    let mut response = file.serve(&req.headers);
+    if gzip {
+        response.headers.set(ContentEncoding(vec![Encoding::Gzip]));
+    }
+    response
+        .headers
+        .set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+    Ok(response)
+}
+
+fn accepts_gzip(req: &Request) -> bool {
+    match req.headers.get::<AcceptEncoding>() {
+        Some(encodings) => encodings
+            .iter()
+            .any(|encoding| encoding.item == Encoding::Gzip && encoding.quality > Quality(0)),
+        None => false,
+    }
+}
+
+fn gzip_compress(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+/// Whether the request asks not to be tracked, with the `DNT: 1` header
+fn do_not_track(req: &Request) -> bool {
+    matches!(req.headers.get_raw("DNT"), Some([value]) if value.as_slice() == b"1")
+}
+
+/// Whether the request comes from a link on docs.rs itself, e.g. from the list of versions, in which
+/// case the outdated documentation was opened on purpose
+fn is_internal_referer(req: &Request) -> bool {
+    req.headers
+        .get::<Referer>()
+        .and_then(|referer| iron::url::Url::parse(referer).ok())
+        .is_some_and(|referer| {
+            referer.host_str() == Some(&req.url.host().to_string())
+                && referer.port_or_known_default() == Some(req.url.port())
+        })
+}
+
+/// Describes which release a rustdoc page belongs to, so that browser extensions and crawlers
+/// don't need to parse the HTML to find out.
+struct MetadataHeaders {
+    krate: String,
+    version: String,
+    target: String,
+    latest_version: String,
+}
+
+impl MetadataHeaders {
+    fn apply(self, response: &mut Response) {
+        let headers = [
+            ("X-DocsRs-Crate", self.krate),
+            ("X-DocsRs-Version", self.version),
+            ("X-DocsRs-Target", self.target),
+            ("X-DocsRs-Latest-Version", self.latest_version),
+        ];
+        for (name, value) in headers {
+            response.headers.set_raw(name, vec![value.into_bytes()]);
+        }
+    }
 }
 
 /// Checks whether the given path exists.
@@ -601,55 +1082,57 @@ pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
     let name = cexpect!(req, extension!(req, Router).find("crate"));
     let mut conn = extension!(req, Pool).get()?;
 
-    let options =
-        match match_version(&mut conn, name, Some(&version)).and_then(|m| m.assume_exact()) {
-            Ok(MatchSemver::Exact((version, id))) => {
-                let rows = ctry!(
-                    req,
-                    conn.query(
-                        "SELECT rustdoc_status
+    let options = match extension!(req, VersionCache)
+        .match_version(&mut conn, name, Some(&version))
+        .and_then(|m| m.assume_exact())
+    {
+        Ok(MatchSemver::Exact((version, id))) => {
+            let rows = ctry!(
+                req,
+                conn.query(
+                    "SELECT rustdoc_status
                      FROM releases
                      WHERE releases.id = $1",
-                        &[&id]
-                    ),
-                );
-                if !rows.is_empty() && rows[0].get(0) {
-                    BadgeOptions {
-                        subject: "docs".to_owned(),
-                        status: version,
-                        color: "#4d76ae".to_owned(),
-                    }
-                } else {
-                    BadgeOptions {
-                        subject: "docs".to_owned(),
-                        status: version,
-                        color: "#e05d44".to_owned(),
-                    }
+                    &[&id]
+                ),
+            );
+            if !rows.is_empty() && rows[0].get(0) {
+                BadgeOptions {
+                    subject: "docs".to_owned(),
+                    status: version,
+                    color: "#4d76ae".to_owned(),
+                }
+            } else {
+                BadgeOptions {
+                    subject: "docs".to_owned(),
+                    status: version,
+                    color: "#e05d44".to_owned(),
                 }
             }
+        }
 
-            Ok(MatchSemver::Semver((version, _))) => {
-                let base_url = format!("{}/{}/badge.svg", redirect_base(req), name);
-                let url = ctry!(
-                    req,
-                    iron::url::Url::parse_with_params(&base_url, &[("version", version)]),
-                );
-                let iron_url = ctry!(req, Url::from_generic_url(url));
-                return Ok(super::redirect(iron_url));
-            }
-
-            Err(Nope::VersionNotFound) => BadgeOptions {
-                subject: "docs".to_owned(),
-                status: "version not found".to_owned(),
-                color: "#e05d44".to_owned(),
-            },
+        Ok(MatchSemver::Semver((version, _))) => {
+            let base_url = format!("{}/{}/badge.svg", redirect_base(req), name);
+            let url = ctry!(
+                req,
+                iron::url::Url::parse_with_params(&base_url, &[("version", version)]),
+            );
+            let iron_url = ctry!(req, Url::from_generic_url(url));
+            return Ok(super::redirect(iron_url));
+        }
 
-            Err(_) => BadgeOptions {
-                subject: "docs".to_owned(),
-                status: "no builds".to_owned(),
-                color: "#e05d44".to_owned(),
-            },
-        };
+        Err(Nope::VersionNotFound) => BadgeOptions {
+            subject: "docs".to_owned(),
+            status: "version not found".to_owned(),
+            color: "#e05d44".to_owned(),
+        },
+
+        Err(_) => BadgeOptions {
+            subject: "docs".to_owned(),
+            status: "no builds".to_owned(),
+            color: "#e05d44".to_owned(),
+        },
+    };
 
     let mut resp = Response::with((status::Ok, ctry!(req, Badge::new(options)).to_svg()));
     resp.headers
@@ -670,21 +1153,27 @@ pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
 /// deduplicate them and save space.
 pub struct SharedResourceHandler;
 
+/// The extensions of the shared files
+const SHARED_RESOURCE_EXTENSIONS: &[&str] = &["js", "css", "woff", "woff2", "svg", "png"];
+
+fn is_shared_resource(filename: &str) -> bool {
+    let extension = Path::new(filename).extension();
+    SHARED_RESOURCE_EXTENSIONS
+        .iter()
+        .any(|s| extension == Some(s.as_ref()))
+}
+
 impl Handler for SharedResourceHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
         let path = req.url.path();
         let filename = path.last().unwrap(); // unwrap is fine: vector is non-empty
-        if let Some(extension) = Path::new(filename).extension() {
-            if ["js", "css", "woff", "woff2", "svg", "png"]
-                .iter()
-                .any(|s| *s == extension)
-            {
-                let storage = extension!(req, Storage);
-                let config = extension!(req, Config);
+        if is_shared_resource(filename) {
+            let storage = extension!(req, Storage);
+            let config = extension!(req, Config);
 
-                if let Ok(file) = File::from_path(storage, filename, config) {
-                    return Ok(file.serve());
-                }
+            if let Ok(file) = File::from_path(storage, filename, config) {
+                req.extensions.insert::<RouteName>("shared resource".into());
+                return Ok(file.serve(&req.headers));
             }
         }
 
@@ -693,6 +1182,22 @@ impl Handler for SharedResourceHandler {
     }
 }
 
+/// `SharedResourceHandler` for the hyper server, the files are downloaded without blocking
+#[cfg(feature = "hyper-server")]
+pub(super) fn shared_resource_async(state: Arc<AsyncState>, req: AsyncRequest) -> AsyncResponse {
+    Box::pin(async move {
+        let filename = req.params.find("path")?.rsplit('/').next()?;
+        if !is_shared_resource(filename) {
+            return None;
+        }
+
+        let file = File::from_path_async(&state.storage, filename, &state.config)
+            .await
+            .ok()?;
+        Some(file.serve(&req.iron_headers()))
+    })
+}
+
 #[cfg(test)]
 mod test {
     use crate::test::*;
@@ -1037,6 +1542,147 @@ mod test {
         })
     }
 
+    #[test]
+    fn yanked_release_shows_banner() {
+        fn banner_link(path: &str, web: &TestFrontend) -> Result<Option<String>, failure::Error> {
+            let page = kuchiki::parse_html().one(web.get(path).send()?.text()?);
+            let banner = match page.select_first("#yanked-banner") {
+                Ok(banner) => banner,
+                Err(()) => return Ok(None),
+            };
+            assert!(banner.text_contents().contains("has been yanked"));
+            Ok(Some(
+                banner
+                    .as_node()
+                    .select_first("a")
+                    .map(|a| a.attributes.borrow().get("href").unwrap().to_string())
+                    .unwrap_or_default(),
+            ))
+        }
+
+        wrapper(|env| {
+            let web = env.frontend();
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .yanked(true)
+                .create()?;
+            assert_eq!(
+                banner_link("/dummy/0.1.0/dummy/", web)?,
+                Some(String::new())
+            );
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            assert_eq!(
+                banner_link("/dummy/0.1.0/dummy/", web)?,
+                Some(
+                    "/crate/dummy/0.2.0/target-redirect/x86_64-unknown-linux-gnu/dummy/index.html"
+                        .into()
+                )
+            );
+            assert_eq!(banner_link("/dummy/0.2.0/dummy/", web)?, None);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unbuilt_dependency_versions_redirect() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dep")
+                .version("1.0.0")
+                .build_result_failed()
+                .create()?;
+            env.fake_release()
+                .name("dep")
+                .version("1.1.0")
+                .rustdoc_file("dep/index.html")
+                .create()?;
+            let release_id = env.fake_release().name("foo").version("0.1.0").create()?;
+            crate::db::dependency_links::record(
+                &mut env.db().conn(),
+                release_id,
+                &[
+                    ("dep".into(), "1.0.0".into()),
+                    ("dep".into(), "1.0.1".into()),
+                ],
+            )?;
+
+            let web = env.frontend();
+            let location = |path: &str, referer: Option<String>| -> Result<_, failure::Error> {
+                let mut request = web.request_without_redirects(reqwest::Method::GET, path);
+                if let Some(referer) = referer {
+                    request = request.header(reqwest::header::REFERER, referer);
+                }
+                let response = request.send()?;
+                Ok(response
+                    .headers()
+                    .get(reqwest::header::LOCATION)
+                    .map(|location| location.to_str().unwrap().to_string()))
+            };
+            let referer = format!("http://{}/foo/0.1.0/foo/", web.server_addr());
+            let redirected = |location: Option<String>| {
+                matches!(location, Some(location)
+                    if location.ends_with("/dep/1.1.0/x86_64-unknown-linux-gnu/dep/struct.Dep.html"))
+            };
+
+            // never published
+            assert!(redirected(location(
+                "/dep/1.0.1/x86_64-unknown-linux-gnu/dep/struct.Dep.html",
+                None
+            )?));
+            // the build failed, only the links from the documentation are redirected
+            assert!(redirected(location(
+                "/dep/1.0.0/x86_64-unknown-linux-gnu/dep/struct.Dep.html",
+                Some(referer)
+            )?));
+            assert!(!redirected(location(
+                "/dep/1.0.0/x86_64-unknown-linux-gnu/dep/struct.Dep.html",
+                None
+            )?));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn vulnerable_release_shows_advisories() {
+        wrapper(|env| {
+            let web = env.frontend();
+            for version in &["0.1.0", "0.2.0"] {
+                env.fake_release()
+                    .name("dummy")
+                    .version(version)
+                    .rustdoc_file("dummy/index.html")
+                    .create()?;
+            }
+            crate::db::advisories::replace_all(
+                &mut env.db().conn(),
+                &[crate::db::advisories::Advisory {
+                    patched: vec![">= 0.2.0".into()],
+                    ..fake_advisory("RUSTSEC-2021-0001", "dummy")
+                }],
+            )?;
+
+            let page = kuchiki::parse_html().one(web.get("/dummy/0.1.0/dummy/").send()?.text()?);
+            let banner = page
+                .select_first("#advisories-banner")
+                .expect("missing the advisories banner");
+            assert!(banner.text_contents().contains("RUSTSEC-2021-0001"));
+
+            let page = kuchiki::parse_html().one(web.get("/dummy/0.2.0/dummy/").send()?.text()?);
+            assert!(page.select_first("#advisories-banner").is_err());
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn badges_are_urlencoded() {
         wrapper(|env| {
@@ -1132,12 +1778,13 @@ mod test {
     }
 
     #[test]
-    fn specific_pages_do_not_handle_mismatched_separators() {
+    fn specific_pages_redirect_mismatched_separators() {
         wrapper(|env| {
             env.fake_release()
                 .name("dummy-dash")
                 .version("0.1.0")
                 .rustdoc_file("dummy_dash/index.html")
+                .source_file("Cargo.toml", b"[package]")
                 .create()?;
 
             env.fake_release()
@@ -1157,10 +1804,42 @@ mod test {
                 web,
             )?;
 
-            assert_eq!(
-                web.get("/crate/dummy_mixed_separators").send()?.status(),
-                StatusCode::NOT_FOUND
+            assert_redirect(
+                "/crate/dummy_mixed_separators",
+                "/crate/dummy_mixed-separators/0.1.0",
+                web,
+            )?;
+            assert_redirect(
+                "/crate/dummy_dash/0.1.0/source/",
+                "/crate/dummy-dash/0.1.0/source/",
+                web,
+            )?;
+
+            // the other spellings are permanently redirected, the pages link to the canonical one
+            let resp = web
+                .request_without_redirects(
+                    reqwest::Method::GET,
+                    "/dummy_dash/0.1.0/dummy_dash/index.html",
+                )
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+            assert!(resp.headers()["location"]
+                .to_str()?
+                .ends_with("/dummy-dash/0.1.0/dummy_dash/index.html"));
+
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/dummy_mixed-separators/0.1.0")
+                    .send()?
+                    .text()?,
             );
+            assert!(page
+                .select_first("link[rel='canonical']")
+                .unwrap()
+                .attributes
+                .borrow()
+                .get("href")
+                .unwrap()
+                .ends_with("/crate/dummy_mixed-separators/latest"));
 
             Ok(())
         })
@@ -1498,6 +2177,60 @@ mod test {
         })
     }
 
+    #[test]
+    fn renamed_crate_redirects() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo_old")
+                .version("0.1.0")
+                .create()?;
+            env.fake_release()
+                .name("foo-new")
+                .version("1.0.0")
+                .rustdoc_file("foo_new/index.html")
+                .create()?;
+            crate::db::crate_redirects::set(
+                &mut env.db().conn(),
+                &crate::db::crate_redirects::CrateRedirect {
+                    old_name: "foo_old".into(),
+                    new_name: "foo-new".into(),
+                },
+            )?;
+
+            let web = env.frontend();
+            // the versions and the items of the old crate don't exist in the new one
+            for (path, target) in &[
+                ("/foo_old", "/foo-new"),
+                ("/foo_old/0.1.0?search=x", "/foo-new"),
+                ("/foo_old/0.1.0/foo_old/struct.Foo.html", "/foo-new"),
+                ("/foo_old/0.1.0/foo_old/all.html", "/foo-new"),
+                (
+                    "/foo_old/0.1.0/implementors/foo_old/trait.Foo.js",
+                    "/foo-new",
+                ),
+                ("/crate/foo_old", "/crate/foo-new"),
+                ("/crate/foo_old/0.1.0", "/crate/foo-new"),
+            ] {
+                let resp = web
+                    .request_without_redirects(reqwest::Method::GET, path)
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY, "{}", path);
+                assert!(
+                    resp.headers()["location"].to_str()?.ends_with(target),
+                    "{}",
+                    path
+                );
+            }
+
+            assert_redirect("/foo_old/0.1.0/foo_old/", "/foo-new/1.0.0/foo_new/", web)?;
+            // the redirect comes before the dash/underscore correction
+            assert_redirect("/foo-old", "/foo-new/1.0.0/foo_new/", web)?;
+            assert_redirect("/crate/foo-old/0.1.0", "/crate/foo-new/1.0.0", web)?;
+
+            Ok(())
+        })
+    }
+
     #[test]
     // regression test for https://github.com/rust-lang/docs.rs/issues/856
     fn test_no_trailing_target_slash() {
@@ -1812,6 +2545,34 @@ mod test {
         })
     }
 
+    #[test]
+    fn missing_source_pages_redirect_to_the_source_browser() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file("foo/index.html")
+                .source_file("Cargo.toml", b"[package]")
+                .builds(vec![FakeBuild::default().no_source()])
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .rustdoc_file("bar/index.html")
+                .create()?;
+
+            let web = env.frontend();
+            assert_redirect(
+                "/foo/0.1.0/src/foo/lib.rs.html",
+                "/crate/foo/0.1.0/source/",
+                web,
+            )?;
+            assert_not_found("/bar/0.1.0/src/bar/lib.rs.html", web)?;
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_redirect_source_not_rust() {
         wrapper(|env| {
@@ -1836,4 +2597,350 @@ mod test {
             Ok(())
         })
     }
+
+    #[test]
+    fn metadata_headers() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .add_platform("x86_64-pc-windows-msvc")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+
+            let web = env.frontend();
+            let check = |path: &str, version: &str, target: &str| -> Result<(), failure::Error> {
+                // the request is redirected to the exact version before the headers are set
+                let resp = web.get(path).send()?;
+                assert!(resp.status().is_success());
+                let headers = resp.headers();
+                assert_eq!(headers["X-DocsRs-Crate"], "dummy");
+                assert_eq!(headers["X-DocsRs-Version"], version);
+                assert_eq!(headers["X-DocsRs-Target"], target);
+                assert_eq!(headers["X-DocsRs-Latest-Version"], "0.2.0");
+                Ok(())
+            };
+
+            check("/dummy/0.1.0/dummy/", "0.1.0", "x86_64-unknown-linux-gnu")?;
+            check("/dummy/0.1/dummy/", "0.1.0", "x86_64-unknown-linux-gnu")?;
+            check("/dummy/latest/dummy/", "0.2.0", "x86_64-unknown-linux-gnu")?;
+            check(
+                "/dummy/0.2/x86_64-pc-windows-msvc/dummy/",
+                "0.2.0",
+                "x86_64-pc-windows-msvc",
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn metadata_headers_disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.rustdoc_metadata_headers = false);
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+
+            let resp = env.frontend().get("/dummy/0.1.0/dummy/").send()?;
+            assert!(resp.status().is_success());
+            assert!(resp.headers().get("X-DocsRs-Crate").is_none());
+            assert!(resp.headers().get("X-DocsRs-Version").is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn old_releases_get_the_current_layout() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with(
+                    "dummy/index.html",
+                    b"<html><head></head><body>\
+                      <div class=\"nav-container\"><a href=\"/\">old docs.rs</a></div>\
+                      <section id=\"main\">dummy</section></body></html>",
+                )
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let page = kuchiki::parse_html()
+                .one(env.frontend().get("/dummy/0.1.0/dummy/").send()?.text()?);
+            assert_eq!(page.select(".nav-container").unwrap().count(), 1);
+            assert!(!page.text_contents().contains("old docs.rs"));
+            assert_eq!(page.select_first("#main").unwrap().text_contents(), "dummy");
+            let versions: Vec<_> = page
+                .select(".nav-container a[data-fragment='retain']")
+                .unwrap()
+                .map(|link| link.attributes.borrow().get("href").unwrap().to_owned())
+                .filter(|href| href.contains("/target-redirect/"))
+                .collect();
+            assert!(versions
+                .iter()
+                .any(|href| href.starts_with("/crate/dummy/0.2.0/")));
+            assert!(versions
+                .iter()
+                .any(|href| href.starts_with("/crate/dummy/0.1.0/")));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn noindex_outdated_releases() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .add_platform("x86_64-pc-windows-msvc")
+                .rustdoc_file("dummy/struct.Foo.html")
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.3.0")
+                .yanked(true)
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+
+            let web = env.frontend();
+            let check =
+                |path: &str, noindex: bool, canonical: &str| -> Result<(), failure::Error> {
+                    let resp = web.get(path).send()?;
+                    assert!(resp.status().is_success());
+                    let base = format!("http://{}", web.server_addr());
+                    assert_eq!(
+                        resp.headers()["Link"],
+                        format!("<{}{}>; rel=\"canonical\"", base, canonical).as_str()
+                    );
+
+                    let page = kuchiki::parse_html().one(resp.text()?);
+                    assert_eq!(
+                        page.select_first("link[rel='canonical']")
+                            .unwrap()
+                            .attributes
+                            .borrow()
+                            .get("href"),
+                        Some(format!("{}{}", base, canonical).as_str())
+                    );
+                    assert_eq!(
+                        page.select_first("meta[name='robots'][content='noindex']")
+                            .is_ok(),
+                        noindex,
+                        "{}",
+                        path
+                    );
+                    Ok(())
+                };
+
+            check("/dummy/0.1.0/dummy/", true, "/dummy/latest/dummy/")?;
+            check("/dummy/0.2.0/dummy/", false, "/dummy/latest/dummy/")?;
+            check(
+                "/dummy/0.2.0/x86_64-pc-windows-msvc/dummy/struct.Foo.html",
+                false,
+                "/dummy/latest/x86_64-pc-windows-msvc/dummy/struct.Foo.html",
+            )?;
+            check("/dummy/0.3.0/dummy/", true, "/dummy/latest/dummy/")?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn noindex_disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.noindex_outdated_releases = false);
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let page = kuchiki::parse_html()
+                .one(env.frontend().get("/dummy/0.1.0/dummy/").send()?.text()?);
+            assert!(page.select_first("meta[name='robots']").is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn last_modified_is_the_build_time() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            env.db().conn().execute(
+                "UPDATE releases SET last_build_time = '2021-08-20T12:00:00Z'",
+                &[],
+            )?;
+
+            let resp = env.frontend().get("/dummy/0.1.0/dummy/").send()?;
+            assert!(resp.status().is_success());
+            assert_eq!(
+                resp.headers()["Last-Modified"],
+                "Fri, 20 Aug 2021 12:00:00 GMT"
+            );
+
+            // releases built before the build times were recorded don't have the header
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET last_build_time = NULL", &[])?;
+            let resp = env.frontend().get("/dummy/0.1.0/dummy/").send()?;
+            assert!(resp.headers().get("Last-Modified").is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn shared_assets_have_subresource_integrity() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with(
+                    "dummy/index.html",
+                    b"<html><head></head><body><script src=\"../main-1.55.0.js\"></script>\
+                      <script src=\"../search-index-1.55.0.js\"></script></body></html>",
+                )
+                .create()?;
+            env.db().conn().execute(
+                "INSERT INTO shared_asset_integrity (path, integrity)
+                 VALUES ('main-1.55.0.js', 'sha384-main')",
+                &[],
+            )?;
+
+            let page = kuchiki::parse_html()
+                .one(env.frontend().get("/dummy/0.1.0/dummy/").send()?.text()?);
+            let main = page
+                .select_first("script[src='../main-1.55.0.js']")
+                .unwrap();
+            assert_eq!(
+                main.attributes.borrow().get("integrity"),
+                Some("sha384-main")
+            );
+            let search_index = page
+                .select_first("script[src='../search-index-1.55.0.js']")
+                .unwrap();
+            assert_eq!(search_index.attributes.borrow().get("integrity"), None);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn rustdoc_assets() {
+        wrapper(|env| {
+            let search_index = b"var searchIndex = JSON.parse('{}');";
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file_with("search-index-20210820.js", search_index)
+                .rustdoc_file("implementors/core/clone/trait.Clone.js")
+                .rustdoc_file("x86_64-pc-windows-msvc/implementors/core/clone/trait.Clone.js")
+                .create()?;
+            let web = env.frontend();
+
+            let resp = web.get("/dummy/0.1.0/search-index-20210820.js").send()?;
+            assert!(resp.status().is_success());
+            assert!(resp.headers().get("Content-Encoding").is_none());
+            assert!(resp.headers()["Cache-Control"]
+                .to_str()?
+                .contains("max-age=31104000"));
+            assert_eq!(resp.bytes()?.as_ref(), &search_index[..]);
+
+            let resp = web
+                .get("/dummy/0.1.0/search-index-20210820.js")
+                .header("Accept-Encoding", "gzip, deflate")
+                .send()?;
+            assert_eq!(resp.headers()["Content-Encoding"], "gzip");
+            assert_eq!(resp.headers()["Vary"], "Accept-Encoding");
+            let mut content = Vec::new();
+            std::io::Read::read_to_end(
+                &mut flate2::read::GzDecoder::new(resp.bytes()?.as_ref()),
+                &mut content,
+            )?;
+            assert_eq!(content, &search_index[..]);
+
+            assert_success("/dummy/0.1.0/implementors/core/clone/trait.Clone.js", web)?;
+            assert_success(
+                "/dummy/0.1.0/x86_64-pc-windows-msvc/implementors/core/clone/trait.Clone.js",
+                web,
+            )?;
+            assert_redirect(
+                "/dummy/latest/implementors/core/clone/trait.Clone.js",
+                "/dummy/0.1.0/implementors/core/clone/trait.Clone.js",
+                web,
+            )?;
+            assert_eq!(
+                web.get("/dummy/0.1.0/implementors/core/marker/trait.Copy.js")
+                    .send()?
+                    .status(),
+                404
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn missing_docs_explain_the_build_failure() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            let release_id = env
+                .fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .build_result_failed()
+                .create()?;
+            let build_id: i32 = env
+                .db()
+                .conn()
+                .query_one("SELECT id FROM builds WHERE rid = $1", &[&release_id])?
+                .get(0);
+
+            let response = env.frontend().get("/dummy/0.2.0/dummy/").send()?;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let page = kuchiki::parse_html().one(response.text()?);
+            assert_eq!(
+                page.select_first("#crate-title").unwrap().text_contents(),
+                "docs.rs failed to build dummy-0.2.0"
+            );
+            let links: Vec<_> = page
+                .select(".description a")
+                .unwrap()
+                .map(|a| a.attributes.borrow().get("href").unwrap().to_string())
+                .collect();
+            assert_eq!(
+                links,
+                vec![
+                    format!("/crate/dummy/0.2.0/builds/{}", build_id),
+                    "/dummy/0.1.0".to_string(),
+                ]
+            );
+
+            Ok(())
+        })
+    }
 }
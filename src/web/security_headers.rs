@@ -0,0 +1,88 @@
+use iron::{headers::Headers, AfterMiddleware, IronError, IronResult, Request, Response};
+
+/// Sets the headers protecting every response, the Content Security Policy is set by
+/// `CspMiddleware`
+pub(super) struct SecurityHeadersMiddleware;
+
+impl SecurityHeadersMiddleware {
+    pub(super) fn set_headers(headers: &mut Headers) {
+        // Prevent browsers from guessing the type of the files, which would allow a crate to
+        // serve a text file of its documentation as a script or a page.
+        headers.set_raw("X-Content-Type-Options", vec![b"nosniff".to_vec()]);
+
+        // Don't send the full URL of the page to other websites, only the origin.
+        headers.set_raw(
+            "Referrer-Policy",
+            vec![b"strict-origin-when-cross-origin".to_vec()],
+        );
+
+        // The pages of rustdoc are served without a Content Security Policy, and some browsers
+        // don't support `frame-ancestors` anyway.
+        headers.set_raw("X-Frame-Options", vec![b"SAMEORIGIN".to_vec()]);
+    }
+
+    /// The admin pages can show the anti-CSRF token of an admin session (see `admin_login`). The
+    /// scripts of the documentation are of the same origin, so they must neither frame these
+    /// pages nor keep a handle on their window after opening them.
+    fn set_admin_headers(req: &Request, headers: &mut Headers) {
+        if req.url.path().first() == Some(&"admin") {
+            headers.set_raw("X-Frame-Options", vec![b"DENY".to_vec()]);
+            headers.set_raw("Cross-Origin-Opener-Policy", vec![b"same-origin".to_vec()]);
+        }
+    }
+}
+
+impl AfterMiddleware for SecurityHeadersMiddleware {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        Self::set_headers(&mut res.headers);
+        Self::set_admin_headers(req, &mut res.headers);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        Self::set_headers(&mut err.response.headers);
+        Self::set_admin_headers(req, &mut err.response.headers);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+
+    #[test]
+    fn security_headers() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            let web = env.frontend();
+
+            for path in &["/", "/crate/foo/0.1.0", "/foo/0.1.0/foo/", "/not-a-crate"] {
+                let resp = web.get(path).send()?;
+                let headers = resp.headers();
+                assert_eq!(headers["x-content-type-options"], "nosniff", "{}", path);
+                assert_eq!(
+                    headers["referrer-policy"], "strict-origin-when-cross-origin",
+                    "{}",
+                    path
+                );
+                assert_eq!(headers["x-frame-options"], "SAMEORIGIN", "{}", path);
+                let csp = headers
+                    .get("content-security-policy")
+                    .or_else(|| headers.get("content-security-policy-report-only"))
+                    .expect("missing content security policy");
+                assert!(csp.to_str()?.contains("frame-ancestors 'self'"), "{}", path);
+                assert!(
+                    headers.get("cross-origin-opener-policy").is_none(),
+                    "{}",
+                    path
+                );
+            }
+
+            let resp = web.get("/admin/limits").send()?;
+            assert_eq!(resp.headers()["x-frame-options"], "DENY");
+            assert_eq!(resp.headers()["cross-origin-opener-policy"], "same-origin");
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,401 @@
+//! Preferences of the visitors, stored in a cookie signed with `DOCSRS_COOKIE_SECRET`
+//!
+//! Nothing is stored on the server: the cookie contains the preferences encoded as JSON, followed
+//! by an HMAC-SHA256 of them so that they can't be forged to inject content in the pages.
+//! Cookies with an invalid signature are ignored.
+
+use crate::{impl_webpage, web::page::WebPage, Config};
+use hmac::{Hmac, Mac, NewMac};
+use iron::{
+    headers::{Cookie, SetCookie},
+    IronResult, Request, Response, Url,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Read;
+
+const COOKIE_NAME: &str = "docsrs_settings";
+/// The preferences are kept for a year after they were last saved
+const COOKIE_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+/// Maximum size of the submitted settings form
+const MAX_FORM_SIZE: u64 = 4 * 1024;
+/// The themes shipped with rustdoc
+const THEMES: &[&str] = &["light", "dark", "ayu"];
+
+/// Used when no secret is configured, the cookies are then invalidated by every restart
+static RANDOM_SECRET: Lazy<[u8; 32]> = Lazy::new(|| {
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).expect("failed to generate the cookie secret");
+    secret
+});
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// Target whose documentation is opened by the links without a target, when the release has
+    /// documentation for it
+    pub(crate) default_target: Option<String>,
+    /// One of the rustdoc themes, used when the browser has no theme stored by rustdoc
+    pub(crate) theme: Option<String>,
+    /// Go to the latest release when arriving on the documentation of an outdated one from
+    /// another website
+    pub(crate) redirect_to_latest: bool,
+}
+
+impl Settings {
+    /// Reads the settings from the request cookie, the defaults are used if there's none or if its
+    /// signature is invalid
+    pub(crate) fn from_request(req: &Request) -> Self {
+        let config = match req.extensions.get::<Config>() {
+            Some(config) => config,
+            None => return Self::default(),
+        };
+
+        req.headers
+            .get::<Cookie>()
+            .into_iter()
+            .flat_map(|cookies| cookies.iter())
+            .filter_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+            .find_map(|value| Self::decode(config, value))
+            .unwrap_or_default()
+    }
+
+    fn decode(config: &Config, value: &str) -> Option<Self> {
+        let (payload, signature) = value.split_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        let mut mac = mac(config);
+        mac.update(payload.as_bytes());
+        mac.verify(&signature).ok()?;
+
+        let json = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn encode(&self, config: &Config) -> String {
+        let json = serde_json::to_vec(self).expect("failed to serialize the settings");
+        let payload = base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        let mut mac = mac(config);
+        mac.update(payload.as_bytes());
+        let signature = mac.finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            payload,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Parses the `application/x-www-form-urlencoded` body of the settings form, invalid values
+    /// are dropped
+    fn from_form(body: &[u8]) -> Self {
+        let mut settings = Self::default();
+        for (key, value) in url::form_urlencoded::parse(body) {
+            let value = value.trim();
+            match &*key {
+                "default_target" if is_valid_target(value) => {
+                    settings.default_target = Some(value.to_owned());
+                }
+                "theme" if THEMES.contains(&value) => settings.theme = Some(value.to_owned()),
+                "redirect_to_latest" => settings.redirect_to_latest = true,
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+pub(super) fn mac(config: &Config) -> Hmac<Sha256> {
+    let secret = match &config.cookie_secret {
+        Some(secret) => secret.as_bytes(),
+        None => &RANDOM_SECRET[..],
+    };
+    Hmac::new_varkey(secret).expect("HMAC accepts keys of any size")
+}
+
+/// Target triples only contain ASCII letters, digits, dashes, underscores and dots
+fn is_valid_target(target: &str) -> bool {
+    !target.is_empty()
+        && target.len() <= 64
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct SettingsPage {
+    settings: Settings,
+    targets: &'static [&'static str],
+    themes: &'static [&'static str],
+    /// Set when the page is shown right after saving the settings
+    saved: bool,
+}
+
+impl_webpage! {
+    SettingsPage = "core/settings.html",
+}
+
+pub fn settings_handler(req: &mut Request) -> IronResult<Response> {
+    let saved = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .any(|(key, _)| key == "saved");
+
+    SettingsPage {
+        settings: Settings::from_request(req),
+        targets: docsrs_metadata::DEFAULT_TARGETS,
+        themes: THEMES,
+        saved,
+    }
+    .into_response(req)
+}
+
+pub fn save_settings_handler(req: &mut Request) -> IronResult<Response> {
+    let mut body = Vec::new();
+    ctry!(
+        req,
+        (&mut req.body).take(MAX_FORM_SIZE).read_to_end(&mut body)
+    );
+    let settings = Settings::from_form(&body);
+    let config = extension!(req, Config);
+
+    let url = ctry!(
+        req,
+        Url::parse(&format!("{}/settings?saved", super::redirect_base(req))),
+    );
+    let mut resp = super::redirect(url);
+    resp.headers.set(SetCookie(vec![format!(
+        "{}={}; Path=/; Max-Age={}; SameSite=Lax; HttpOnly",
+        COOKIE_NAME,
+        settings.encode(config),
+        COOKIE_MAX_AGE,
+    )]));
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::*;
+    use kuchiki::traits::TendrilSink;
+    use reqwest::{header, Method, StatusCode};
+
+    /// The `Cookie` header storing `settings`
+    fn settings_cookie(env: &TestEnvironment, settings: &Settings) -> String {
+        format!("{}={}", COOKIE_NAME, settings.encode(&env.config()))
+    }
+
+    /// The location the request is redirected to, or `None` if it isn't
+    fn redirect_location(
+        env: &TestEnvironment,
+        path: &str,
+        settings: Option<&Settings>,
+        referer: Option<&str>,
+    ) -> Result<Option<String>, failure::Error> {
+        let mut request = env.frontend().request_without_redirects(Method::GET, path);
+        if let Some(settings) = settings {
+            request = request.header(header::COOKIE, settings_cookie(env, settings));
+        }
+        if let Some(referer) = referer {
+            request = request.header(header::REFERER, referer);
+        }
+        let response = request.send()?;
+        // the responses depending on the settings mustn't be shared between visitors by caches
+        assert_eq!(response.headers()[header::VARY], "Cookie");
+        if !response.status().is_redirection() {
+            assert!(response.status().is_success(), "{}", response.status());
+            return Ok(None);
+        }
+        let location = reqwest::Url::parse(response.headers()[header::LOCATION].to_str()?)?;
+        Ok(Some(location.path().to_owned()))
+    }
+
+    #[test]
+    fn signed_cookie() {
+        wrapper(|env| {
+            let config = env.config();
+            let settings = Settings {
+                default_target: Some("x86_64-pc-windows-msvc".into()),
+                theme: Some("dark".into()),
+                redirect_to_latest: true,
+            };
+            let value = settings.encode(&config);
+            assert_eq!(Settings::decode(&config, &value), Some(settings));
+
+            // the preferences can't be changed without the secret
+            let (_, signature) = value.split_once('.').unwrap();
+            let forged =
+                base64::encode_config(r#"{"theme":"\"><script>"}"#, base64::URL_SAFE_NO_PAD);
+            assert_eq!(
+                Settings::decode(&config, &format!("{}.{}", forged, signature)),
+                None
+            );
+            assert_eq!(Settings::decode(&config, "garbage"), None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_form_values_are_dropped() {
+        assert_eq!(
+            Settings::from_form(
+                b"default_target=%3Cscript%3E&theme=neon&redirect_to_latest=on&other=1"
+            ),
+            Settings {
+                default_target: None,
+                theme: None,
+                redirect_to_latest: true,
+            }
+        );
+        assert_eq!(
+            Settings::from_form(b"default_target=wasm32-unknown-unknown&theme=ayu"),
+            Settings {
+                default_target: Some("wasm32-unknown-unknown".into()),
+                theme: Some("ayu".into()),
+                redirect_to_latest: false,
+            }
+        );
+    }
+
+    #[test]
+    fn save_settings() {
+        wrapper(|env| {
+            let web = env.frontend();
+            let response = web
+                .request_without_redirects(Method::POST, "/settings")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body("default_target=i686-pc-windows-msvc&theme=dark")
+                .send()?;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            assert!(response.headers()[header::LOCATION]
+                .to_str()?
+                .ends_with("/settings?saved"));
+            let cookie = response.headers()[header::SET_COOKIE].to_str()?;
+            let cookie = cookie.split(';').next().unwrap().to_owned();
+
+            let page = kuchiki::parse_html().one(
+                web.get("/settings?saved")
+                    .header(header::COOKIE, &cookie)
+                    .send()?
+                    .text()?,
+            );
+            assert_eq!(
+                page.select_first("input[name='default_target']")
+                    .unwrap()
+                    .attributes
+                    .borrow()
+                    .get("value"),
+                Some("i686-pc-windows-msvc")
+            );
+            assert!(page.select_first("option[value='dark'][selected]").is_ok());
+            assert!(page
+                .select_first("input[name='redirect_to_latest'][checked]")
+                .is_err());
+            // the theme is applied to all the docs.rs pages
+            let page = kuchiki::parse_html().one(
+                web.get("/")
+                    .header(header::COOKIE, &cookie)
+                    .send()?
+                    .text()?,
+            );
+            assert_eq!(
+                page.select_first("html")
+                    .unwrap()
+                    .attributes
+                    .borrow()
+                    .get("data-preferred-theme"),
+                Some("dark")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn preferred_target() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .add_platform("x86_64-pc-windows-msvc")
+                .create()?;
+
+            let windows = Settings {
+                default_target: Some("x86_64-pc-windows-msvc".into()),
+                ..Settings::default()
+            };
+            let wasm = Settings {
+                default_target: Some("wasm32-unknown-unknown".into()),
+                ..Settings::default()
+            };
+            assert_eq!(
+                redirect_location(env, "/dummy", Some(&windows), None)?.as_deref(),
+                Some("/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/")
+            );
+            assert_eq!(
+                redirect_location(env, "/dummy/0.1", Some(&windows), None)?.as_deref(),
+                Some("/dummy/0.1.0/x86_64-pc-windows-msvc/dummy/")
+            );
+            // the release wasn't built for the preferred target
+            assert_eq!(
+                redirect_location(env, "/dummy", Some(&wasm), None)?.as_deref(),
+                Some("/dummy/0.1.0/dummy/")
+            );
+            assert_eq!(
+                redirect_location(env, "/dummy", None, None)?.as_deref(),
+                Some("/dummy/0.1.0/dummy/")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn redirect_to_latest() {
+        wrapper(|env| {
+            env.fake_release().name("dummy").version("0.1.0").create()?;
+            env.fake_release().name("dummy").version("0.2.0").create()?;
+
+            let settings = Settings {
+                redirect_to_latest: true,
+                ..Settings::default()
+            };
+            let latest = Some(
+                "/crate/dummy/0.2.0/target-redirect/x86_64-unknown-linux-gnu/dummy/index.html"
+                    .to_owned(),
+            );
+            assert_eq!(
+                redirect_location(env, "/dummy/0.1.0/dummy/", Some(&settings), None)?,
+                latest
+            );
+            assert_eq!(
+                redirect_location(
+                    env,
+                    "/dummy/0.1.0/dummy/",
+                    Some(&settings),
+                    Some("https://www.example.com/")
+                )?,
+                latest
+            );
+            // the outdated release was picked on docs.rs
+            let referer = format!("http://{}/crate/dummy/0.2.0", env.frontend().server_addr());
+            assert_eq!(
+                redirect_location(env, "/dummy/0.1.0/dummy/", Some(&settings), Some(&referer))?,
+                None
+            );
+            assert_eq!(
+                redirect_location(env, "/dummy/0.1.0/dummy/", None, None)?,
+                None
+            );
+            assert_eq!(
+                redirect_location(env, "/dummy/0.2.0/dummy/", Some(&settings), None)?,
+                None
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -1,10 +1,15 @@
-use crate::{db::Pool, docbuilder::Limits, impl_webpage, web::error::Nope, web::page::WebPage};
+use crate::{
+    db::Pool, docbuilder::Limits, impl_webpage, utils::scheduler::Schedule, web::error::Nope,
+    web::page::WebPage,
+};
 use chrono::{DateTime, Utc};
+use docsrs_metadata::{DEFAULT_TARGETS, HOST_TARGET};
 use iron::{
-    headers::ContentType,
+    headers::{AccessControlAllowOrigin, ContentType},
     mime::{Mime, SubLevel, TopLevel},
-    IronResult, Request, Response,
+    status, IronResult, Request, Response,
 };
+use postgres::Client;
 use router::Router;
 use serde::Serialize;
 use serde_json::Value;
@@ -29,7 +34,8 @@ pub fn sitemapindex_handler(req: &mut Request) -> IronResult<Response> {
 /// The sitemap
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct SitemapXml {
-    /// The release's names and RFC 3339 timestamp to be displayed on the sitemap
+    /// The crate names and the RFC 3339 timestamp of their last build (or release, for the releases
+    /// built before build times were recorded)
     releases: Vec<(String, String)>,
 }
 
@@ -54,7 +60,7 @@ pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
     let query = conn
         .query(
             "SELECT crates.name,
-                    MAX(releases.release_time) as release_time
+                    MAX(COALESCE(releases.last_build_time, releases.release_time)) as last_modified
              FROM crates
              INNER JOIN releases ON releases.crate_id = crates.id
              WHERE 
@@ -82,33 +88,166 @@ pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
 struct AboutBuilds {
     /// The current version of rustc that docs.rs is using to build crates
     rustc_version: Option<String>,
+    /// The version of rustdoc shipped with that rustc
+    rustdoc_version: Option<String>,
     /// The default crate build limits
     limits: Limits,
+    /// The targets built when a crate doesn't pick its own
+    default_targets: &'static [&'static str],
+    /// The target of the build machine, all the other ones are cross-compiled
+    host_target: &'static str,
     /// Just for the template, since this isn't shared with AboutPage
     active_tab: &'static str,
 }
 
 impl_webpage!(AboutBuilds = "core/about/builds.html");
 
+/// The build environment as returned by `/about/builds.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct BuildEnvironment {
+    rustc_version: Option<String>,
+    rustdoc_version: Option<String>,
+    limits: BuildLimits,
+    default_targets: &'static [&'static str],
+    host_target: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct BuildLimits {
+    /// In bytes
+    memory: usize,
+    /// In bytes
+    disk_space: usize,
+    /// In seconds
+    timeout: u64,
+    targets: usize,
+    networking: bool,
+    /// In bytes
+    max_log_size: usize,
+}
+
+impl From<&Limits> for BuildLimits {
+    fn from(limits: &Limits) -> Self {
+        BuildLimits {
+            memory: limits.memory(),
+            disk_space: limits.disk_space(),
+            timeout: limits.timeout().as_secs(),
+            targets: limits.targets(),
+            networking: limits.networking(),
+            max_log_size: limits.max_log_size(),
+        }
+    }
+}
+
+/// Reads a string stored in the `config` table by the builder
+fn get_config_string(conn: &mut Client, name: &str) -> Result<Option<String>, failure::Error> {
+    let row = conn.query_opt("SELECT value FROM config WHERE name = $1", &[&name])?;
+
+    Ok(row.and_then(|row| match row.try_get(0) {
+        Ok(Some(Value::String(value))) => Some(value),
+        _ => None,
+    }))
+}
+
 pub fn about_builds_handler(req: &mut Request) -> IronResult<Response> {
+    let is_json = req
+        .url
+        .path()
+        .last()
+        .is_some_and(|segment| segment.ends_with(".json"));
+
     let mut conn = extension!(req, Pool).get()?;
-    let res = ctry!(
-        req,
-        conn.query("SELECT value FROM config WHERE name = 'rustc_version'", &[]),
-    );
-
-    let rustc_version = res.get(0).and_then(|row| {
-        if let Ok(Some(Value::String(version))) = row.try_get(0) {
-            Some(version)
-        } else {
-            None
+    let rustc_version = ctry!(req, get_config_string(&mut conn, "rustc_version"));
+    let rustdoc_version = ctry!(req, get_config_string(&mut conn, "rustdoc_version"));
+    let limits = Limits::default();
+
+    if is_json {
+        let environment = BuildEnvironment {
+            rustc_version,
+            rustdoc_version,
+            limits: BuildLimits::from(&limits),
+            default_targets: DEFAULT_TARGETS,
+            host_target: HOST_TARGET,
+        };
+
+        let mut resp =
+            Response::with((status::Ok, ctry!(req, serde_json::to_string(&environment))));
+        resp.headers.set(ContentType::json());
+        resp.headers.set(AccessControlAllowOrigin::Any);
+
+        Ok(resp)
+    } else {
+        AboutBuilds {
+            rustc_version,
+            rustdoc_version,
+            limits,
+            default_targets: DEFAULT_TARGETS,
+            host_target: HOST_TARGET,
+            active_tab: "builds",
         }
-    });
+        .into_response(req)
+    }
+}
 
-    AboutBuilds {
-        rustc_version,
-        limits: Limits::default(),
-        active_tab: "builds",
+/// A job run periodically by the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ScheduledJob {
+    name: String,
+    schedule: String,
+    last_started: Option<DateTime<Utc>>,
+    last_finished: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    running: bool,
+    next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct AboutJobs {
+    jobs: Vec<ScheduledJob>,
+    active_tab: &'static str,
+}
+
+impl_webpage!(AboutJobs = "core/about/jobs.html");
+
+fn get_scheduled_jobs(conn: &mut Client) -> Result<Vec<ScheduledJob>, failure::Error> {
+    Ok(conn
+        .query(
+            "SELECT name, schedule, last_started, last_finished, last_success, last_error
+             FROM scheduled_jobs
+             ORDER BY name",
+            &[],
+        )?
+        .into_iter()
+        .map(|row| {
+            let schedule: String = row.get("schedule");
+            let last_started: Option<DateTime<Utc>> = row.get("last_started");
+            let last_finished: Option<DateTime<Utc>> = row.get("last_finished");
+            let next_run = schedule
+                .parse::<Schedule>()
+                .ok()
+                .and_then(|schedule| schedule.next_after(last_started.unwrap_or_else(Utc::now)));
+
+            ScheduledJob {
+                name: row.get("name"),
+                running: last_started.is_some() && last_finished < last_started,
+                last_success: row.get("last_success"),
+                last_error: row.get("last_error"),
+                schedule,
+                last_started,
+                last_finished,
+                next_run,
+            }
+        })
+        .collect())
+}
+
+pub fn about_jobs_handler(req: &mut Request) -> IronResult<Response> {
+    let mut conn = extension!(req, Pool).get()?;
+
+    AboutJobs {
+        jobs: ctry!(req, get_scheduled_jobs(&mut conn)),
+        active_tab: "jobs",
     }
     .into_response(req)
 }
@@ -150,7 +289,11 @@ pub fn about_handler(req: &mut Request) -> IronResult<Response> {
 
 #[cfg(test)]
 mod tests {
+    use crate::docbuilder::Limits;
     use crate::test::{assert_success, wrapper};
+    use chrono::{TimeZone, Utc};
+    use docsrs_metadata::{DEFAULT_TARGETS, HOST_TARGET};
+    use kuchiki::traits::TendrilSink;
     use reqwest::StatusCode;
 
     #[test]
@@ -218,6 +361,49 @@ mod tests {
         })
     }
 
+    #[test]
+    fn sitemap_lastmod() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("some_random_crate")
+                .release_time(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0))
+                .create()?;
+            env.fake_release()
+                .name("some_old_crate")
+                .release_time(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0))
+                .create()?;
+            env.db().conn().execute(
+                "UPDATE releases SET last_build_time = '2021-08-20T12:00:00Z'
+                 FROM crates
+                 WHERE crates.id = releases.crate_id AND crates.name = 'some_random_crate'",
+                &[],
+            )?;
+            // built before the build times were recorded
+            env.db().conn().execute(
+                "UPDATE releases SET last_build_time = NULL
+                 FROM crates
+                 WHERE crates.id = releases.crate_id AND crates.name = 'some_old_crate'",
+                &[],
+            )?;
+
+            let content = env
+                .frontend()
+                .get("/-/sitemap/s/sitemap.xml")
+                .send()?
+                .text()?;
+            assert!(content.contains(
+                "<loc>https://docs.rs/some_random_crate</loc>\n            \
+                 <lastmod>2021-08-20T12:00:00+00:00</lastmod>"
+            ));
+            assert!(content.contains(
+                "<loc>https://docs.rs/some_old_crate</loc>\n            \
+                 <lastmod>2019-01-01T00:00:00+00:00</lastmod>"
+            ));
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn about_page() {
         wrapper(|env| {
@@ -239,6 +425,97 @@ mod tests {
         })
     }
 
+    #[test]
+    fn about_builds() {
+        wrapper(|env| {
+            env.db().conn().execute(
+                "INSERT INTO config (name, value) VALUES
+                    ('rustc_version', '\"rustc 1.55.0-nightly (2021-07-01)\"'),
+                    ('rustdoc_version', '\"rustdoc 1.55.0-nightly (2021-07-01)\"')",
+                &[],
+            )?;
+            let web = env.frontend();
+
+            let page = web.get("/about/builds").send()?.text()?;
+            assert!(page.contains("<code>rustc 1.55.0-nightly (2021-07-01)</code>"));
+            assert!(page.contains("<code>rustdoc 1.55.0-nightly (2021-07-01)</code>"));
+            for target in DEFAULT_TARGETS {
+                assert!(page.contains(&format!("<li><code>{}</code></li>", target)));
+            }
+
+            let response = web.get("/about/builds.json").send()?;
+            assert!(response.status().is_success());
+            let json: serde_json::Value = response.json()?;
+            assert_eq!(
+                json["rustdoc_version"],
+                "rustdoc 1.55.0-nightly (2021-07-01)"
+            );
+            assert_eq!(json["host_target"], HOST_TARGET);
+            assert_eq!(
+                json["limits"]["timeout"],
+                Limits::default().timeout().as_secs()
+            );
+            assert_eq!(
+                json["default_targets"].as_array().unwrap().len(),
+                DEFAULT_TARGETS.len()
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn about_builds_without_versions() {
+        wrapper(|env| {
+            let web = env.frontend();
+            assert_success("/about/builds", web)?;
+
+            let json: serde_json::Value = web.get("/about/builds.json").send()?.json()?;
+            assert!(json["rustc_version"].is_null());
+            assert!(json["rustdoc_version"].is_null());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn about_jobs() {
+        wrapper(|env| {
+            env.db().conn().execute(
+                "INSERT INTO scheduled_jobs
+                    (name, schedule, last_started, last_finished, last_success, last_error)
+                 VALUES
+                    ('never run', '0 * * * *', NULL, NULL, NULL, NULL),
+                    ('failing', '*/5 * * * *', NOW(), NOW(), NOW() - INTERVAL '1 day', 'oops')",
+                &[],
+            )?;
+
+            let page = kuchiki::parse_html().one(env.frontend().get("/about/jobs").send()?.text()?);
+            let rows: Vec<Vec<String>> = page
+                .select(".scheduled-jobs tbody tr")
+                .unwrap()
+                .map(|row| {
+                    row.as_node()
+                        .select("td")
+                        .unwrap()
+                        .map(|cell| cell.text_contents().trim().to_owned())
+                        .collect()
+                })
+                .collect();
+
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0][0], "failing");
+            assert!(rows[0][2].ends_with(", failed"));
+            assert_eq!(rows[0][3], "one day ago");
+            assert!(rows[0][4].ends_with(" UTC"));
+            assert_eq!(rows[1][0], "never run");
+            assert_eq!(rows[1][2], "never");
+            assert_eq!(rows[1][3], "never");
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn robots_txt() {
         wrapper(|env| {
@@ -4,17 +4,28 @@ use crate::{
     db::Pool,
     impl_webpage,
     web::{
-        error::Nope, file::File as DbFile, match_version, page::WebPage, redirect_base,
-        MatchSemver, MetaData, Url,
+        error::Nope,
+        file::{Download, File as DbFile},
+        highlight,
+        page::WebPage,
+        permanent_crate_redirect, redirect_base, render_markdown, MatchSemver, MetaData, Url,
     },
-    Config, Storage,
+    Config, Storage, VersionCache,
 };
-use iron::{IronResult, Request, Response};
+use flate2::{write::GzEncoder, Compression};
+use iron::headers::{
+    CacheControl, CacheDirective, ContentDisposition, ContentType, DispositionParam,
+    DispositionType,
+};
+use iron::response::WriteBody;
+use iron::{status, IronResult, Request, Response};
 use postgres::Client;
 use router::Router;
 use serde::Serialize;
 use serde_json::Value;
 use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::sync::Arc;
 
 /// A source file's name and mime type
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize)]
@@ -152,9 +163,23 @@ impl FileList {
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct SourcePage {
     file_list: FileList,
+    /// The same file in the latest release
+    canonical_url: String,
     show_parent_link: bool,
+    /// The directory being browsed, relative to the root of the crate
+    directory: String,
+    /// The highlighted HTML of the file, or the rendered Markdown
     file_content: Option<String>,
-    is_rust_source: bool,
+    /// Number of lines of the file, each one gets a `#L{n}` anchor
+    line_count: usize,
+    /// Markdown files can be shown rendered, with `?rendered=1`
+    is_markdown: bool,
+    rendered: bool,
+}
+
+fn is_markdown(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".md") || path.ends_with(".markdown")
 }
 
 impl_webpage! {
@@ -163,7 +188,7 @@ impl_webpage! {
 
 pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     let router = extension!(req, Router);
-    let mut crate_name = cexpect!(req, router.find("name"));
+    let crate_name = cexpect!(req, router.find("name"));
     let req_version = cexpect!(req, router.find("version"));
     let pool = extension!(req, Pool);
     let mut conn = pool.get()?;
@@ -172,16 +197,16 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     // remove first elements from path which is /crate/:name/:version/source
     req_path.drain(0..4);
 
-    let v = match_version(&mut conn, crate_name, Some(req_version))?;
-    if let Some(new_name) = &v.corrected_name {
-        // `match_version` checked against -/_ typos, so if we have a name here we should
-        // use that instead
-        crate_name = new_name;
+    let v =
+        extension!(req, VersionCache).match_version(&mut conn, crate_name, Some(req_version))?;
+    if let Some(canonical_name) = &v.corrected_name {
+        // `match_version` checked against -/_ typos, only the name on crates.io is served
+        return permanent_crate_redirect(req, crate_name, canonical_name);
     }
     let version = match v.version {
         MatchSemver::Exact((version, _)) => version,
         MatchSemver::Semver((version, _)) => {
-            let url = ctry!(
+            let mut url = ctry!(
                 req,
                 Url::parse(&format!(
                     "{}/crate/{}/{}/source/{}",
@@ -191,6 +216,8 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
                     req_path.join("/"),
                 )),
             );
+            // keeps `?rendered=1`
+            url.as_mut().set_query(req.url.query());
 
             return Ok(super::redirect(url));
         }
@@ -222,42 +249,232 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     // try to get actual file first
     // skip if request is a directory
     let file = if !file_path.ends_with('/') {
-        DbFile::from_path(storage, &file_path, config).ok()
+        match DbFile::download(storage, &file_path, config) {
+            Ok(Download::File(file)) => Some(file),
+            Ok(download @ Download::Redirect(_)) => return Ok(download.serve(&req.headers)),
+            Err(_) => None,
+        }
     } else {
         None
     };
 
-    let (file_content, is_rust_source) = if let Some(file) = file {
+    let is_markdown = is_markdown(&file_path);
+    let rendered = is_markdown
+        && req
+            .url
+            .as_ref()
+            .query_pairs()
+            .any(|(key, value)| key == "rendered" && value == "1");
+
+    let mut line_count = 0;
+    let file_content = if let Some(file) = file {
         // serve the file with DatabaseFileHandler if file isn't text and not empty
         if !file.0.mime.starts_with("text") && !file.is_empty() {
-            return Ok(file.serve());
+            return Ok(file.serve(&req.headers));
         } else if file.0.mime.starts_with("text") && !file.is_empty() {
-            (
-                String::from_utf8(file.0.content).ok(),
-                file.0.path.ends_with(".rs"),
-            )
+            String::from_utf8(file.0.content).ok().map(|code| {
+                if rendered {
+                    render_markdown(&code)
+                } else {
+                    line_count = code.lines().count();
+                    let prefix = format!("sources/{}/{}/", crate_name, version);
+                    let path = file_path.strip_prefix(&prefix).unwrap_or(&file_path);
+                    highlight::highlight_source(
+                        storage,
+                        crate_name,
+                        &version,
+                        path,
+                        &code,
+                        config.max_file_size_html,
+                    )
+                }
+            })
         } else {
-            (None, false)
+            None
         }
     } else {
-        (None, false)
+        None
     };
 
     let file_list = FileList::from_path(&mut conn, crate_name, &version, &req_path)
         .ok_or(Nope::ResourceNotFound)?;
 
+    let canonical_url = format!(
+        "{}/crate/{}/latest/source/{}",
+        redirect_base(req),
+        crate_name,
+        req_path
+    );
+
     SourcePage {
         file_list,
+        canonical_url,
         show_parent_link: !req_path.is_empty(),
+        directory: req_path,
         file_content,
-        is_rust_source,
+        line_count,
+        is_markdown,
+        rendered,
     }
     .into_response(req)
 }
 
+/// Body of the source tarballs, the files are fetched from the storage while it's being sent
+struct SourceTarball {
+    storage: Arc<Storage>,
+    max_file_size: usize,
+    /// Directory the files are placed in, like `cargo package` does
+    root: String,
+    name: String,
+    version: String,
+    files: Vec<String>,
+}
+
+impl WriteBody for SourceTarball {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut tarball = tar::Builder::new(GzEncoder::new(res, Compression::default()));
+        for path in &self.files {
+            let blob = self
+                .storage
+                .get(
+                    &format!("sources/{}/{}/{}", self.name, self.version, path),
+                    self.max_file_size,
+                )
+                .map_err(|err| io::Error::other(err.compat()))?;
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(blob.content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(blob.date_updated.timestamp().max(0) as u64);
+            header.set_cksum();
+            tarball.append_data(
+                &mut header,
+                format!("{}/{}", self.root, path),
+                blob.content.as_slice(),
+            )?;
+        }
+        tarball.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Serves the source files of a release as a `.tar.gz`, limited to a directory with `?path=`
+pub fn source_tarball_handler(req: &mut Request) -> IronResult<Response> {
+    let router = extension!(req, Router);
+    let crate_name = cexpect!(req, router.find("name"));
+    let req_version = cexpect!(req, router.find("version"));
+    let pool = extension!(req, Pool);
+    let mut conn = pool.get()?;
+
+    let v =
+        extension!(req, VersionCache).match_version(&mut conn, crate_name, Some(req_version))?;
+    if let Some(canonical_name) = &v.corrected_name {
+        return permanent_crate_redirect(req, crate_name, canonical_name);
+    }
+    let version = match v.version {
+        MatchSemver::Exact((version, _)) => version,
+        MatchSemver::Semver((version, _)) => {
+            let mut url = ctry!(
+                req,
+                Url::parse(&format!(
+                    "{}/crate/{}/{}/source.tar.gz",
+                    redirect_base(req),
+                    crate_name,
+                    version,
+                )),
+            );
+            url.as_mut().set_query(req.url.query());
+
+            return Ok(super::redirect(url));
+        }
+    };
+
+    let directory = req
+        .url
+        .as_ref()
+        .query_pairs()
+        .find(|(key, _)| key == "path")
+        .map(|(_, path)| path.trim_matches('/').to_owned())
+        .unwrap_or_default();
+    if directory.split('/').any(|component| component == "..") {
+        return Err(Nope::ResourceNotFound.into());
+    }
+
+    let rows = ctry!(
+        req,
+        conn.query(
+            "SELECT releases.files
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE crates.name = $1 AND releases.version = $2",
+            &[&crate_name, &version],
+        ),
+    );
+    let files: Option<Value> = match rows.first() {
+        Some(row) => row.get(0),
+        None => return Err(Nope::ResourceNotFound.into()),
+    };
+
+    let prefix = if directory.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", directory)
+    };
+    let files: Vec<String> = files
+        .as_ref()
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        // every file is stored as a `[mime, path]` pair
+        .filter_map(|file| file.get(1)?.as_str())
+        .filter(|path| path.starts_with(&prefix) && *path != ".cargo-ok")
+        .map(String::from)
+        .collect();
+    if files.is_empty() {
+        return Err(Nope::ResourceNotFound.into());
+    }
+
+    let root = format!("{}-{}", crate_name, version);
+    let file_name = if directory.is_empty() {
+        format!("{}.tar.gz", root)
+    } else {
+        format!("{}-{}.tar.gz", root, directory.replace('/', "-"))
+    };
+
+    let mut response = Response::with((
+        status::Ok,
+        Box::new(SourceTarball {
+            storage: extension!(req, Storage).clone(),
+            max_file_size: extension!(req, Config).max_file_size,
+            root,
+            name: crate_name.to_owned(),
+            version,
+            files,
+        }) as Box<dyn WriteBody>,
+    ));
+    response
+        .headers
+        .set(ContentType("application/gzip".parse().unwrap()));
+    response.headers.set(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(
+            iron::headers::Charset::Ext("UTF-8".into()),
+            None,
+            file_name.into_bytes(),
+        )],
+    });
+    // the sources of a release never change
+    response.headers.set(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(365 * 24 * 60 * 60),
+    ]));
+    Ok(response)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test::*;
+    use kuchiki::traits::TendrilSink;
 
     #[test]
     fn cargo_ok_not_skipped() {
@@ -319,4 +536,235 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn highlighted() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"pub fn lt(a: u8) -> bool { a < 1 }\n")
+                .build()?;
+            let web = env.frontend();
+
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/src/lib.rs")
+                    .send()?
+                    .text()?,
+            );
+            let code = page.select_first("code.syntax-highlighted").unwrap();
+            assert!(code.as_node().select_first("span.syntax-rust").is_ok());
+            assert_eq!(code.text_contents(), "pub fn lt(a: u8) -> bool { a < 1 }\n");
+            // highlight.js isn't needed anymore
+            assert!(page.select_first("script[src*='highlight']").is_err());
+
+            assert!(env.storage().exists("highlighted/foo/0.1.0/src/lib.rs")?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn line_anchors() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"fn a() {}\n\nfn b() {}\n")
+                .build()?;
+            let web = env.frontend();
+
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/src/lib.rs")
+                    .send()?
+                    .text()?,
+            );
+            let anchors: Vec<_> = page
+                .select("pre.line-numbers a")
+                .unwrap()
+                .map(|a| a.attributes.borrow().get("href").unwrap().to_owned())
+                .collect();
+            assert_eq!(anchors, vec!["#L1", "#L2", "#L3"]);
+            assert!(page.select_first("#L3").is_ok());
+            assert!(page.select_first("#copy-permalink").is_ok());
+            assert!(page.select_first("script[src*='source.js']").is_ok());
+
+            // the permalinks point to an exact version, the other ones are redirected
+            assert_redirect(
+                "/crate/foo/latest/source/src/lib.rs",
+                "/crate/foo/0.1.0/source/src/lib.rs",
+                web,
+            )?;
+
+            // directories don't have line numbers
+            let page =
+                kuchiki::parse_html().one(web.get("/crate/foo/0.1.0/source/").send()?.text()?);
+            assert!(page.select_first("pre.line-numbers").is_err());
+            assert!(page.select_first("script[src*='source.js']").is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn rendered_markdown() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file(
+                    "README.md",
+                    b"# Foo\n\n<script>alert(1)</script>\n\n[link](javascript:alert(1))\n",
+                )
+                .build()?;
+            let web = env.frontend();
+
+            // the source is shown by default
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/README.md")
+                    .send()?
+                    .text()?,
+            );
+            assert!(page.select_first("code.syntax-highlighted").is_ok());
+            assert!(page.select_first("a[href='?rendered=1']").is_ok());
+
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/README.md?rendered=1")
+                    .send()?
+                    .text()?,
+            );
+            let rendered = page.select_first("div.rendered-markdown").unwrap();
+            let rendered = rendered.as_node();
+            assert_eq!(rendered.select_first("h1").unwrap().text_contents(), "Foo");
+            // raw HTML and unsafe links are removed, like in the READMEs of the crate pages
+            assert!(rendered.select_first("script").is_err());
+            assert_eq!(
+                rendered
+                    .select_first("a")
+                    .unwrap()
+                    .attributes
+                    .borrow()
+                    .get("href"),
+                Some("")
+            );
+            assert!(page.select_first("pre.line-numbers").is_err());
+
+            // the option is kept by the redirects
+            assert_redirect(
+                "/crate/foo/latest/source/README.md?rendered=1",
+                "/crate/foo/0.1.0/source/README.md?rendered=1",
+                web,
+            )?;
+
+            // other files are never rendered
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/src/lib.rs?rendered=1")
+                    .send()?
+                    .text()?,
+            );
+            assert!(page.select_first("div.rendered-markdown").is_err());
+            assert!(page.select_first("a[href='?rendered=1']").is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn tarball() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("Cargo.toml", b"[package]\nname = \"foo\"\n")
+                .source_file("src/lib.rs", b"mod bar;\n")
+                .source_file("src/bar/mod.rs", b"// bar\n")
+                .build()?;
+            let web = env.frontend();
+
+            let entries = |url: &str| -> Result<Vec<(String, String)>, failure::Error> {
+                let resp = web.get(url).send()?;
+                assert!(resp.status().is_success());
+                assert_eq!(resp.headers()["Content-Type"], "application/gzip");
+                let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(resp));
+                let mut entries = Vec::new();
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    let mut content = String::new();
+                    std::io::Read::read_to_string(&mut entry, &mut content)?;
+                    entries.push((entry.path()?.to_string_lossy().into_owned(), content));
+                }
+                entries.sort();
+                Ok(entries)
+            };
+
+            let all = entries("/crate/foo/0.1.0/source.tar.gz")?;
+            let paths: Vec<_> = all.iter().map(|(path, _)| path.as_str()).collect();
+            assert!(paths.contains(&"foo-0.1.0/Cargo.toml"));
+            assert!(paths.contains(&"foo-0.1.0/src/lib.rs"));
+            assert!(!paths.contains(&"foo-0.1.0/.cargo-ok"));
+
+            assert_eq!(
+                entries("/crate/foo/0.1.0/source.tar.gz?path=src/bar/")?,
+                vec![("foo-0.1.0/src/bar/mod.rs".into(), "// bar\n".into())]
+            );
+            let resp = web.get("/crate/foo/0.1.0/source.tar.gz?path=src").send()?;
+            assert_eq!(
+                resp.headers()["Content-Disposition"],
+                "attachment; filename=\"foo-0.1.0-src.tar.gz\""
+            );
+
+            assert_redirect(
+                "/crate/foo/latest/source.tar.gz?path=src",
+                "/crate/foo/0.1.0/source.tar.gz?path=src",
+                web,
+            )?;
+            assert_not_found("/crate/foo/0.1.0/source.tar.gz?path=missing", web)?;
+            assert_not_found("/crate/foo/0.1.0/source.tar.gz?path=src/../..", web)?;
+
+            // the source browser links to the tarball of the current directory
+            let page =
+                kuchiki::parse_html().one(web.get("/crate/foo/0.1.0/source/src/").send()?.text()?);
+            assert!(page
+                .select_first("a[href='/crate/foo/0.1.0/source.tar.gz?path=src/']")
+                .is_ok());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn definition_links() {
+        wrapper(|env| {
+            env.fake_builder()
+                .name("foo")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"mod bar;\npub use bar::Bar;\n")
+                .source_file("src/bar.rs", b"\npub struct Bar;\n")
+                .definition("bar", "src/lib.rs", 1, "module")
+                .definition("Bar", "src/bar.rs", 2, "struct")
+                .build()?;
+            let web = env.frontend();
+
+            let page = kuchiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/source/src/lib.rs")
+                    .send()?
+                    .text()?,
+            );
+            let links: Vec<_> = page
+                .select("code a.definition")
+                .unwrap()
+                .map(|a| a.attributes.borrow().get("href").unwrap().to_owned())
+                .collect();
+            // the `bar` of line 1 is the definition itself
+            assert_eq!(
+                links,
+                vec![
+                    "/crate/foo/0.1.0/source/src/lib.rs#L1",
+                    "/crate/foo/0.1.0/source/src/bar.rs#L2",
+                ]
+            );
+            assert_success("/crate/foo/0.1.0/source/src/bar.rs", web)?;
+
+            Ok(())
+        })
+    }
 }
@@ -0,0 +1,109 @@
+//! TLS termination and HTTP/2 in the bundled web server
+//!
+//! Iron only speaks plain HTTP/1.1. When `DOCSRS_TLS_CERTIFICATE` and `DOCSRS_TLS_PRIVATE_KEY` are
+//! set, the frontend terminates TLS and negotiates HTTP/2 with ALPN before forwarding the requests
+//! to Iron. Small deployments don't need a proxy in front of the web server this way.
+
+use failure::{format_err, Error, ResultExt};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+
+/// Loads the PEM encoded certificate chain and private key, advertising HTTP/2 and HTTP/1.1
+pub(crate) fn load_config(certificate: &Path, private_key: &Path) -> Result<ServerConfig, Error> {
+    let open = |path: &Path| -> Result<BufReader<File>, Error> {
+        Ok(BufReader::new(File::open(path).with_context(|_| {
+            format!("failed to open {}", path.display())
+        })?))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(certificate)?)?;
+    if certs.is_empty() {
+        return Err(format_err!("no certificate in {}", certificate.display()));
+    }
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut open(private_key)?)?;
+    keys.extend(rustls_pemfile::rsa_private_keys(&mut open(private_key)?)?);
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| format_err!("no private key in {}", private_key.display()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(
+        certs.into_iter().map(Certificate).collect(),
+        PrivateKey(key),
+    )?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use crate::web::Server;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name)
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(load_config(&fixture("cert.pem"), &fixture("key.pem")).is_ok());
+        // the certificate isn't a private key
+        assert!(load_config(&fixture("cert.pem"), &fixture("cert.pem")).is_err());
+        assert!(load_config(&fixture("key.pem"), &fixture("key.pem")).is_err());
+        assert!(load_config(&fixture("missing.pem"), &fixture("key.pem")).is_err());
+    }
+
+    #[test]
+    fn https_and_http2() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.tls_certificate = Some(fixture("cert.pem"));
+                config.tls_private_key = Some(fixture("key.pem"));
+            });
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let server = Server::start(Some("127.0.0.1:0"), false, env)?;
+            // rustls refuses the IP addresses sent as server names
+            let base = format!("https://localhost:{}", server.addr().port());
+            let client = |http2: bool| {
+                let builder = reqwest::blocking::Client::builder()
+                    .danger_accept_invalid_certs(true)
+                    .redirect(reqwest::redirect::Policy::none());
+                if http2 {
+                    builder.http2_prior_knowledge()
+                } else {
+                    builder
+                }
+                .build()
+            };
+
+            for &http2 in &[false, true] {
+                let response = client(http2)?.get(format!("{}/crate/foo", base)).send()?;
+                assert_eq!(
+                    response.version(),
+                    if http2 {
+                        reqwest::Version::HTTP_2
+                    } else {
+                        reqwest::Version::HTTP_11
+                    }
+                );
+                assert_eq!(response.status(), 302);
+                // the redirect keeps the scheme and the host of the request
+                assert_eq!(
+                    response.headers()["location"],
+                    format!("{}/crate/foo/0.1.0", base).as_str()
+                );
+            }
+
+            server.leak();
+            Ok(())
+        });
+    }
+}
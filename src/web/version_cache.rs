@@ -0,0 +1,245 @@
+//! In-process cache of the `match_version` results
+//!
+//! The redirector resolves the requested crate and version on every request, so popular crates
+//! hit the database over and over for the same answer. The results are kept for a few seconds
+//! (`DOCSRS_VERSION_CACHE_TTL`), and the entries of a crate are dropped as soon as this process
+//! adds or yanks one of its releases. Other processes only see new releases once the entries
+//! expire.
+
+use super::{error::Nope, match_version, MatchVersion};
+use crate::{db::queries::PreparedStatements, Config, Metrics};
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// When the cache grows past this size the expired entries are removed, and if that's not
+/// enough the whole cache is cleared
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    /// Used to find all the entries of a crate, regardless of the `-`/`_` variant requested
+    normalized_name: String,
+    name: String,
+    version: Option<String>,
+}
+
+struct Entry {
+    inserted: Instant,
+    result: Result<MatchVersion, Nope>,
+}
+
+pub struct VersionCache {
+    entries: DashMap<Key, Entry>,
+    ttl: Duration,
+    /// Incremented by every invalidation, so that a lookup racing with one doesn't store a result
+    /// that's already outdated
+    generation: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+impl VersionCache {
+    pub fn new(metrics: Arc<Metrics>, config: &Config) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl: Duration::from_secs(config.version_cache_ttl),
+            generation: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    /// Cached version of `match_version`
+    pub(super) fn match_version(
+        &self,
+        conn: &mut impl PreparedStatements,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<MatchVersion, Nope> {
+        if self.ttl == Duration::from_secs(0) {
+            return match_version(conn, name, version);
+        }
+
+        let key = Key {
+            normalized_name: normalize_name(name),
+            name: name.into(),
+            version: version.map(String::from),
+        };
+        if let Some(entry) = self.entries.get(&key) {
+            if entry.inserted.elapsed() < self.ttl {
+                self.metrics
+                    .version_cache_lookups
+                    .with_label_values(&["hit"])
+                    .inc();
+                return entry.result.clone();
+            }
+        }
+        self.metrics
+            .version_cache_lookups
+            .with_label_values(&["miss"])
+            .inc();
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let result = match_version(conn, name, version);
+        // database errors are not cached
+        let cacheable = !matches!(result, Err(Nope::InternalServerError));
+        if cacheable && generation == self.generation.load(Ordering::SeqCst) {
+            self.make_room();
+            self.entries.insert(
+                key,
+                Entry {
+                    inserted: Instant::now(),
+                    result: result.clone(),
+                },
+            );
+        }
+
+        result
+    }
+
+    /// Drops the cached results of a crate, called when its releases change
+    pub(crate) fn invalidate(&self, name: &str) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let normalized_name = normalize_name(name);
+        self.entries
+            .retain(|key, _| key.normalized_name != normalized_name);
+    }
+
+    fn make_room(&self) {
+        if self.entries.len() < MAX_ENTRIES {
+            return;
+        }
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, entry| entry.inserted.elapsed() < ttl);
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.clear();
+        }
+    }
+}
+
+impl std::fmt::Debug for VersionCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VersionCache")
+            .field("entries", &self.entries.len())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// Same as the `normalize_crate_name` SQL function
+fn normalize_name(name: &str) -> String {
+    name.replace('_', "-").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{wrapper, TestEnvironment};
+
+    fn latest(env: &TestEnvironment, name: &str) -> Option<String> {
+        env.version_cache()
+            .match_version(&mut env.db().conn(), name, None)
+            .ok()
+            .map(|matched| matched.version.into_parts().0)
+    }
+
+    fn lookups(env: &TestEnvironment, result: &str) -> i64 {
+        env.metrics()
+            .version_cache_lookups
+            .with_label_values(&[result])
+            .get()
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            assert_eq!(latest(env, "foo"), Some("0.1.0".into()));
+            assert_eq!(latest(env, "foo"), Some("0.1.0".into()));
+            assert_eq!(latest(env, "bar"), None);
+            assert_eq!(latest(env, "bar"), None);
+            assert_eq!(lookups(env, "hit"), 2);
+            assert_eq!(lookups(env, "miss"), 2);
+
+            // the corrected name is cached too
+            let matched = env
+                .version_cache()
+                .match_version(&mut env.db().conn(), "FOO", None)?;
+            assert_eq!(matched.corrected_name.as_deref(), Some("foo"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn new_release_invalidates() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo-bar")
+                .version("0.1.0")
+                .create()?;
+            assert_eq!(latest(env, "foo-bar"), Some("0.1.0".into()));
+            assert_eq!(latest(env, "foo_bar"), Some("0.1.0".into()));
+
+            env.fake_release()
+                .name("foo-bar")
+                .version("0.2.0")
+                .create()?;
+            assert_eq!(latest(env, "foo-bar"), Some("0.2.0".into()));
+            assert_eq!(latest(env, "foo_bar"), Some("0.2.0".into()));
+            assert_eq!(lookups(env, "hit"), 0);
+
+            // a crate that didn't exist yet
+            assert_eq!(latest(env, "baz"), None);
+            env.fake_release().name("baz").version("1.0.0").create()?;
+            assert_eq!(latest(env, "baz"), Some("1.0.0".into()));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn yank_invalidates() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            env.fake_release().name("foo").version("0.2.0").create()?;
+            assert_eq!(latest(env, "foo"), Some("0.2.0".into()));
+
+            env.db().conn().execute(
+                "UPDATE releases SET yanked = TRUE WHERE version = '0.2.0'",
+                &[],
+            )?;
+            // not invalidated yet
+            assert_eq!(latest(env, "foo"), Some("0.2.0".into()));
+
+            env.version_cache().invalidate("foo");
+            assert_eq!(latest(env, "foo"), Some("0.1.0".into()));
+
+            // the yanked version can still be requested explicitly
+            let matched =
+                env.version_cache()
+                    .match_version(&mut env.db().conn(), "foo", Some("0.2.0"))?;
+            assert_eq!(matched.version.into_parts().0, "0.2.0");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn disabled() {
+        wrapper(|env| {
+            env.override_config(|config| config.version_cache_ttl = 0);
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            assert_eq!(latest(env, "foo"), Some("0.1.0".into()));
+
+            env.db()
+                .conn()
+                .execute("UPDATE releases SET yanked = TRUE", &[])?;
+            assert_eq!(latest(env, "foo"), None);
+            assert_eq!(lookups(env, "hit") + lookups(env, "miss"), 0);
+
+            Ok(())
+        });
+    }
+}
@@ -44,4 +44,8 @@ function formatCrateName(crateTitleAndVersion) {
     for (const e of document.querySelectorAll('a[data-fragment="retain"]')) {
         e.addEventListener('mouseover', () => e.hash = document.location.hash);
     }
+
+    for (const banner of document.querySelectorAll(".warning-banner")) {
+        banner.querySelector("button").addEventListener("click", () => banner.remove());
+    }
 })();
@@ -0,0 +1,100 @@
+// Update the build queue and the builds of a release while they change, without reloading the
+// page, by polling the events of the queue. The element with the id `queue-events-target` is
+// replaced with the one of a fresh copy of the page when its list changes, and its items of the
+// crates being built are marked as running.
+// When it has `data-name` and `data-version`, only the events of this release are followed.
+(function() {
+    var target = document.getElementById("queue-events-target");
+    if (target === null || typeof fetch === "undefined") {
+        return;
+    }
+    var name = target.getAttribute("data-name");
+    var version = target.getAttribute("data-version");
+    var running = {};
+
+    function key(event) {
+        return event.name + " " + event.version;
+    }
+
+    function markRunning() {
+        var items = target.querySelectorAll("li[data-name]");
+        for (var i = 0; i < items.length; i += 1) {
+            var item = items[i];
+            var isRunning = running[key({
+                name: item.getAttribute("data-name"),
+                version: item.getAttribute("data-version"),
+            })];
+            // the builds started before the page was loaded are already marked
+            if (typeof isRunning === "boolean") {
+                item.classList.toggle("running", isRunning);
+            }
+        }
+    }
+
+    // several events often come at once, the page is only fetched once for them
+    var refreshing = null;
+    function refresh() {
+        if (refreshing !== null) {
+            return;
+        }
+        refreshing = setTimeout(function() {
+            fetch(window.location.href, { cache: "no-store" })
+                .then(function(response) {
+                    return response.text();
+                })
+                .then(function(html) {
+                    var page = new DOMParser().parseFromString(html, "text/html");
+                    var fresh = page.getElementById("queue-events-target");
+                    if (fresh !== null) {
+                        target.parentNode.replaceChild(fresh, target);
+                        target = fresh;
+                        markRunning();
+                    }
+                })
+                .catch(function() {})
+                .then(function() {
+                    refreshing = null;
+                });
+        }, 500);
+    }
+
+    var handlers = {
+        queued: refresh,
+        started: function(event) {
+            running[key(event)] = true;
+            markRunning();
+        },
+        finished: function(event) {
+            running[key(event)] = false;
+            markRunning();
+            refresh();
+        },
+    };
+
+    // the first poll only returns the cursor of the next events
+    var cursor = null;
+    function poll() {
+        var url = "/releases/queue/events";
+        if (cursor !== null) {
+            url += "?cursor=" + cursor;
+        }
+        fetch(url, { cache: "no-store" })
+            .then(function(response) {
+                return response.json();
+            })
+            .then(function(page) {
+                cursor = page.cursor;
+                page.events.forEach(function(event) {
+                    if (name !== null && (event.name !== name || event.version !== version)) {
+                        return;
+                    }
+                    handlers[event.event](event);
+                });
+            })
+            .catch(function() {})
+            .then(function() {
+                setTimeout(poll, 5000);
+            });
+    }
+    poll();
+})();
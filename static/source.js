@@ -0,0 +1,96 @@
+(function() {
+    const lineNumbers = document.querySelector("pre.line-numbers");
+    if (!lineNumbers) {
+        return;
+    }
+    const lines = lineNumbers.getElementsByTagName("a");
+
+    // Accepts `#L10`, `#L10-L25`, and the `#10`/`#10-25` anchors of rustdoc's source pages
+    function parseRange(hash) {
+        const match = /^#L?(\d+)(?:-L?(\d+))?$/.exec(hash);
+        if (!match) {
+            return null;
+        }
+        let start = parseInt(match[1], 10);
+        let end = match[2] ? parseInt(match[2], 10) : start;
+        if (start > end) {
+            [start, end] = [end, start];
+        }
+        if (start < 1 || start > lines.length) {
+            return null;
+        }
+        return [start, Math.min(end, lines.length)];
+    }
+
+    function formatRange(range) {
+        return range[0] === range[1] ? "#L" + range[0] : "#L" + range[0] + "-L" + range[1];
+    }
+
+    let selected = null;
+    function highlight(range, scroll) {
+        for (const line of lineNumbers.querySelectorAll(".line-highlighted")) {
+            line.classList.remove("line-highlighted");
+        }
+        selected = range;
+        if (range === null) {
+            return;
+        }
+        for (let line = range[0]; line <= range[1]; ++line) {
+            lines[line - 1].classList.add("line-highlighted");
+        }
+        if (scroll) {
+            lines[range[0] - 1].scrollIntoView();
+        }
+    }
+
+    function highlightFromHash(scroll) {
+        const range = parseRange(document.location.hash);
+        if (range !== null && formatRange(range) !== document.location.hash) {
+            // rewrite the old anchors, so that the copied links use the new ones
+            history.replaceState(null, "", formatRange(range));
+        }
+        highlight(range, scroll);
+    }
+
+    lineNumbers.addEventListener("click", function(ev) {
+        const line = ev.target.closest("a");
+        if (!line) {
+            return;
+        }
+        ev.preventDefault();
+        const number = parseInt(line.id.substring(1), 10);
+        let range = [number, number];
+        if (ev.shiftKey && selected !== null) {
+            range = [Math.min(selected[0], number), Math.max(selected[0], number)];
+        }
+        history.replaceState(null, "", formatRange(range));
+        highlight(range, false);
+    });
+    window.addEventListener("hashchange", () => highlightFromHash(true));
+    highlightFromHash(true);
+
+    const copyPermalink = document.getElementById("copy-permalink");
+    let resetPermalinkTimeout = null;
+    const resetPermalinkLabel = copyPermalink.innerHTML;
+    copyPermalink.addEventListener("click", function() {
+        // the version of the page is always an exact one, as the other ones are redirected
+        const temporaryInput = document.createElement("input");
+        temporaryInput.type = "text";
+        temporaryInput.value = document.location.origin + document.location.pathname +
+            (selected !== null ? formatRange(selected) : "");
+
+        document.body.append(temporaryInput);
+        temporaryInput.select();
+        document.execCommand("copy");
+        temporaryInput.remove();
+
+        copyPermalink.textContent = "✓ Copied";
+        if (resetPermalinkTimeout !== null) {
+            clearTimeout(resetPermalinkTimeout);
+        }
+        resetPermalinkTimeout = setTimeout(function() {
+            resetPermalinkTimeout = null;
+            copyPermalink.innerHTML = resetPermalinkLabel;
+        }, 1000);
+    });
+})();
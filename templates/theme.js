@@ -15,4 +15,8 @@ window.addEventListener('message', function (ev) {
   }
 });
 
-applyTheme(window.localStorage.getItem('rustdoc-theme'));
+// the theme picked on the settings page is only used when rustdoc has none stored
+applyTheme(
+  window.localStorage.getItem('rustdoc-theme') ||
+    document.documentElement.dataset.preferredTheme
+);